@@ -1,15 +1,25 @@
 use lazy_static;
 use std::collections::HashMap;
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use graph::prelude::*;
+use graph::util::env::env_var;
 use web3::types::*;
 
+use crate::health::IngestorHealth;
+
 lazy_static! {
     static ref CLEANUP_BLOCKS: bool = std::env::var("GRAPH_ETHEREUM_CLEANUP_BLOCKS")
         .ok()
         .map(|s| s.eq_ignore_ascii_case("true"))
         .unwrap_or(false);
+
+    /// How many blocks the chain head may be ahead of the slowest synced
+    /// deployment of the chain; the block ingestor waits for that
+    /// deployment to catch up before it ingests new blocks. This is off
+    /// unless it is set
+    static ref MAX_LEAD: Option<u64> = env_var("GRAPH_ETHEREUM_INGESTOR_MAX_LEAD");
 }
 
 pub struct BlockIngestorMetrics {
@@ -44,9 +54,12 @@ where
     chain_store: Arc<S>,
     eth_adapter: Arc<dyn EthereumAdapter>,
     ancestor_count: u64,
-    _network_name: String,
+    network_name: String,
     logger: Logger,
     polling_interval: Duration,
+    last_poll: Arc<Mutex<Option<Instant>>>,
+    /// 1 while the ingestor waits for slow deployments, 0 otherwise
+    waiting: Box<Gauge>,
 }
 
 impl<S> BlockIngestor<S>
@@ -60,6 +73,7 @@ where
         network_name: String,
         logger_factory: &LoggerFactory,
         polling_interval: Duration,
+        registry: Arc<dyn MetricsRegistry>,
     ) -> Result<BlockIngestor<S>, Error> {
         let logger = logger_factory.component_logger(
             "BlockIngestor",
@@ -72,16 +86,41 @@ where
 
         let logger = logger.new(o!("network_name" => network_name.clone()));
 
+        let mut labels = HashMap::new();
+        labels.insert(String::from("network"), network_name.clone());
+        let waiting = registry
+            .new_gauge(
+                String::from("ethereum_block_ingestor_waiting"),
+                String::from(
+                    "1 if the block ingestor waits for the slowest synced deployment to catch up, \
+                 0 otherwise",
+                ),
+                labels,
+            )
+            .expect("failed to create `ethereum_block_ingestor_waiting` gauge");
+
         Ok(BlockIngestor {
             chain_store,
             eth_adapter,
             ancestor_count,
-            _network_name: network_name,
+            network_name,
             logger,
             polling_interval,
+            last_poll: Arc::new(Mutex::new(None)),
+            waiting,
         })
     }
 
+    /// A handle that tells whether this ingestor is still polling
+    /// successfully after it has been spawned
+    pub fn health(&self) -> IngestorHealth {
+        IngestorHealth {
+            network_name: self.network_name.clone(),
+            polling_interval: self.polling_interval,
+            last_poll: self.last_poll.clone(),
+        }
+    }
+
     pub async fn into_polling_stream(self) {
         loop {
             match self.do_poll().await {
@@ -99,7 +138,7 @@ where
                         "Trying again after block polling failed: {}", inner_err
                     );
                 }
-                Ok(()) => (),
+                Ok(()) => *self.last_poll.lock().unwrap() = Some(Instant::now()),
             }
 
             if *CLEANUP_BLOCKS {
@@ -150,6 +189,25 @@ where
             return Ok(());
         }
 
+        // Rather than moving the chain head further ahead of deployments
+        // that can not keep up, wait for them
+        if let (Some(max_lead), Some(head_block_ptr)) = (*MAX_LEAD, &head_block_ptr_opt) {
+            let slowest = self.chain_store.slowest_synced_block()?;
+            let waiting = slowest.map_or(false, |slowest| {
+                head_block_ptr.number >= slowest.max(0) as u64 + max_lead
+            });
+            self.waiting.set(if waiting { 1.0 } else { 0.0 });
+            if waiting {
+                debug!(
+                    self.logger,
+                    "Waiting for the slowest synced deployment to catch up";
+                    "current_block_head" => head_block_ptr.number,
+                    "slowest_deployment_block" => slowest,
+                );
+                return Ok(());
+            }
+        }
+
         // Ask for latest block again, but now with full transactions
         let latest_block = self.eth_adapter.latest_block(&self.logger).compat().await?;
 
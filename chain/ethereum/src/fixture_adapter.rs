@@ -0,0 +1,555 @@
+//! An `EthereumAdapter` that serves a fixed chain from JSON files instead
+//! of talking to an Ethereum node, so that indexing can be tested end to
+//! end without an RPC endpoint. A fixture directory contains
+//!
+//! - `blocks/*.json`: one block per file in the format of the block cache,
+//!   i.e., an object with the `block` including its transactions and the
+//!   `transaction_receipts`, plus optional Parity `traces` for call
+//!   handlers. The blocks must form a chain without gaps
+//! - `calls.json` (optional): the results of `eth_call`, a list of objects
+//!   with the contract `address`, the ABI-encoded input `data`, the
+//!   `result`, and optionally the `block` number at which the result
+//!   applies
+//! - `network.json` (optional): the `net_version`, which defaults to `1`,
+//!   and the `genesis_block_hash`, which defaults to the hash of the first
+//!   block of the fixture
+//!
+//! The highest block of the fixture is the chain head.
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use graph::prelude::ethabi::Token;
+use graph::prelude::web3::types::{Address, Block, Bytes, Log, Trace, H256};
+use graph::prelude::*;
+
+/// A block of the fixture
+#[derive(Deserialize)]
+struct FixtureBlock {
+    #[serde(flatten)]
+    block: EthereumBlock,
+    #[serde(default)]
+    traces: Vec<Trace>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixtureNetwork {
+    net_version: Option<String>,
+    genesis_block_hash: Option<H256>,
+}
+
+/// The result of calling a contract with `data`, at `block` or at any
+/// block if `block` is not set
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixtureCall {
+    address: Address,
+    block: Option<u64>,
+    data: Bytes,
+    result: Bytes,
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    let data = fs::read(path).map_err(|e| format_err!("failed to read {:?}: {}", path, e))?;
+    serde_json::from_slice(&data).map_err(|e| format_err!("invalid fixture {:?}: {}", path, e))
+}
+
+pub struct FixtureEthereumAdapter {
+    net_version: String,
+    genesis_block_hash: H256,
+    blocks: HashMap<H256, FixtureBlock>,
+    /// The hashes of the blocks by number
+    hashes: BTreeMap<u64, H256>,
+    calls: Vec<FixtureCall>,
+}
+
+impl FixtureEthereumAdapter {
+    /// Load the fixture in the directory `dir`
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+
+        let mut paths = fs::read_dir(dir.join("blocks"))
+            .map_err(|e| format_err!("failed to read the blocks of fixture {:?}: {}", dir, e))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.retain(|path| path.extension().map_or(false, |ext| ext == "json"));
+        paths.sort();
+
+        let mut blocks = HashMap::new();
+        let mut hashes = BTreeMap::new();
+        for path in paths {
+            let block: FixtureBlock = read_json(&path)?;
+            let (number, hash) = match (block.block.block.number, block.block.block.hash) {
+                (Some(number), Some(hash)) => (number.as_u64(), hash),
+                _ => return Err(format_err!("block {:?} has no number or hash", path)),
+            };
+            if hashes.insert(number, hash).is_some() {
+                return Err(format_err!(
+                    "fixture {:?} has more than one block with number {}",
+                    dir,
+                    number
+                ));
+            }
+            blocks.insert(hash, block);
+        }
+
+        let mut parent: Option<(u64, H256)> = None;
+        for (number, hash) in &hashes {
+            if let Some((parent_number, parent_hash)) = parent {
+                let block = &blocks[hash].block.block;
+                if *number != parent_number + 1 || block.parent_hash != parent_hash {
+                    return Err(format_err!(
+                        "block {} of fixture {:?} is not the child of block {}",
+                        number,
+                        dir,
+                        parent_number
+                    ));
+                }
+            }
+            parent = Some((*number, *hash));
+        }
+        let first = *hashes
+            .values()
+            .next()
+            .ok_or_else(|| format_err!("fixture {:?} has no blocks", dir))?;
+
+        let network_path = dir.join("network.json");
+        let network: FixtureNetwork = if network_path.exists() {
+            read_json(&network_path)?
+        } else {
+            FixtureNetwork::default()
+        };
+        let calls_path = dir.join("calls.json");
+        let calls = if calls_path.exists() {
+            read_json(&calls_path)?
+        } else {
+            vec![]
+        };
+
+        Ok(FixtureEthereumAdapter {
+            net_version: network.net_version.unwrap_or_else(|| "1".to_owned()),
+            genesis_block_hash: network.genesis_block_hash.unwrap_or(first),
+            blocks,
+            hashes,
+            calls,
+        })
+    }
+
+    fn block(&self, hash: H256) -> Option<&FixtureBlock> {
+        self.blocks.get(&hash)
+    }
+
+    fn block_at(&self, number: u64) -> Option<&FixtureBlock> {
+        self.hashes.get(&number).and_then(|hash| self.block(*hash))
+    }
+
+    fn blocks_in_range(&self, from: u64, to: u64) -> impl Iterator<Item = &FixtureBlock> {
+        self.hashes
+            .range(from..=to)
+            .filter_map(move |(_, hash)| self.block(*hash))
+    }
+
+    fn head(&self) -> &LightEthereumBlock {
+        // Loading the fixture ensures that there is at least one block
+        let hash = self.hashes.values().next_back().unwrap();
+        &self.blocks[hash].block.block
+    }
+
+    fn missing_block(number: u64) -> Error {
+        format_err!("the fixture has no block with number {}", number)
+    }
+
+    /// The result for a call of `address` with `data` at block `number`.
+    /// Results for that block take precedence over results for any block
+    fn call_result(&self, address: Address, data: &[u8], number: u64) -> Option<&Bytes> {
+        let matching = |call: &&FixtureCall| call.address == address && call.data.0 == data;
+        self.calls
+            .iter()
+            .filter(matching)
+            .find(|call| call.block == Some(number))
+            .or_else(|| {
+                self.calls
+                    .iter()
+                    .filter(matching)
+                    .find(|call| call.block.is_none())
+            })
+            .map(|call| &call.result)
+    }
+}
+
+/// The header of `block`, with only the hashes of its transactions
+fn block_header(block: &LightEthereumBlock) -> Result<Block<H256>, Error> {
+    let hashes: Vec<H256> = block.transactions.iter().map(|tx| tx.hash).collect();
+    let mut value = serde_json::to_value(block)?;
+    value["transactions"] = serde_json::to_value(hashes)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+impl EthereumAdapter for FixtureEthereumAdapter {
+    fn net_identifiers(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = EthereumNetworkIdentifier, Error = Error> + Send> {
+        Box::new(future::ok(EthereumNetworkIdentifier {
+            net_version: self.net_version.clone(),
+            genesis_block_hash: self.genesis_block_hash,
+        }))
+    }
+
+    fn latest_block(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = EthereumAdapterError> + Send + Unpin>
+    {
+        Box::new(future::ok(self.head().clone()))
+    }
+
+    fn latest_block_header(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = Block<H256>, Error = EthereumAdapterError> + Send> {
+        Box::new(future::result(
+            block_header(self.head()).map_err(EthereumAdapterError::from),
+        ))
+    }
+
+    fn load_block(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = Error> + Send> {
+        Box::new(future::result(
+            self.block(block_hash)
+                .map(|block| block.block.block.clone())
+                .ok_or_else(|| format_err!("the fixture has no block with hash {:?}", block_hash)),
+        ))
+    }
+
+    fn load_blocks(
+        &self,
+        _: Logger,
+        _: Arc<dyn ChainStore>,
+        block_hashes: HashSet<H256>,
+    ) -> Box<dyn Stream<Item = LightEthereumBlock, Error = Error> + Send> {
+        let mut blocks = block_hashes
+            .into_iter()
+            .map(|hash| {
+                self.block(hash)
+                    .map(|block| block.block.block.clone())
+                    .ok_or_else(|| format_err!("the fixture has no block with hash {:?}", hash))
+            })
+            .collect::<Result<Vec<_>, _>>();
+        if let Ok(blocks) = &mut blocks {
+            blocks.sort_by_key(|block| block.number);
+        }
+        Box::new(
+            future::result(blocks)
+                .map(stream::iter_ok::<_, Error>)
+                .flatten_stream(),
+        )
+    }
+
+    fn block_range_to_ptrs(
+        &self,
+        _: Logger,
+        from: u64,
+        to: u64,
+    ) -> Box<dyn Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        let ptrs: Result<Vec<_>, Error> = (from..=to)
+            .map(|number| {
+                self.block_at(number)
+                    .map(|block| EthereumBlockPointer::from(&block.block.block))
+                    .ok_or_else(|| Self::missing_block(number))
+            })
+            .collect();
+        Box::new(future::result(ptrs))
+    }
+
+    fn block_by_hash(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        Box::new(future::ok(
+            self.block(block_hash)
+                .map(|block| block.block.block.clone()),
+        ))
+    }
+
+    fn block_by_number(
+        &self,
+        _: &Logger,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        Box::new(future::ok(
+            self.block_at(block_number)
+                .map(|block| block.block.block.clone()),
+        ))
+    }
+
+    fn load_full_block(
+        &self,
+        _: &Logger,
+        block: LightEthereumBlock,
+    ) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+        let hash = block.hash.expect("block is missing block hash");
+        Box::new(future::result(
+            self.block(hash)
+                .map(|block| block.block.clone())
+                .ok_or(EthereumAdapterError::BlockUnavailable(hash)),
+        ))
+    }
+
+    fn block_pointer_from_number(
+        &self,
+        _: &Logger,
+        _: Arc<dyn ChainStore>,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = EthereumBlockPointer, Error = EthereumAdapterError> + Send> {
+        Box::new(future::result(
+            self.block_at(block_number)
+                .map(|block| EthereumBlockPointer::from(&block.block.block))
+                .ok_or_else(|| EthereumAdapterError::from(Self::missing_block(block_number))),
+        ))
+    }
+
+    fn block_hash_by_block_number(
+        &self,
+        _: &Logger,
+        _: Arc<dyn ChainStore>,
+        block_number: u64,
+        _: bool,
+    ) -> Box<dyn Future<Item = Option<H256>, Error = Error> + Send> {
+        Box::new(future::ok(self.hashes.get(&block_number).cloned()))
+    }
+
+    fn uncles(
+        &self,
+        _: &Logger,
+        block: &LightEthereumBlock,
+    ) -> Box<dyn Future<Item = Vec<Option<Block<H256>>>, Error = Error> + Send> {
+        // The fixture only contains the blocks of the main chain
+        Box::new(future::ok(vec![None; block.uncles.len()]))
+    }
+
+    fn is_on_main_chain(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        _: Arc<dyn ChainStore>,
+        block_ptr: EthereumBlockPointer,
+    ) -> Box<dyn Future<Item = bool, Error = Error> + Send> {
+        Box::new(future::ok(
+            self.hashes.get(&block_ptr.number) == Some(&block_ptr.hash),
+        ))
+    }
+
+    fn calls_in_block(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        block_number: u64,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        Box::new(future::result(
+            self.block(block_hash)
+                .map(|block| {
+                    block
+                        .traces
+                        .iter()
+                        .filter_map(EthereumCall::try_from_trace)
+                        .collect::<Vec<_>>()
+                })
+                .ok_or_else(|| Self::missing_block(block_number)),
+        ))
+    }
+
+    fn logs_in_block_range(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        log_filter: EthereumLogFilter,
+    ) -> DynTryFuture<'static, Vec<Log>, Error> {
+        let logs: Vec<Log> = self
+            .blocks_in_range(from, to)
+            .flat_map(|block| block.block.transaction_receipts.iter())
+            .flat_map(|receipt| receipt.logs.iter())
+            .filter(|log| log_filter.matches(log))
+            .cloned()
+            .collect();
+        Box::pin(futures03::future::ok(logs))
+    }
+
+    fn calls_in_block_range(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        call_filter: EthereumCallFilter,
+    ) -> Box<dyn Stream<Item = EthereumCall, Error = Error> + Send> {
+        let calls: Vec<_> = self
+            .blocks_in_range(from, to)
+            .flat_map(|block| block.traces.iter())
+            .filter_map(EthereumCall::try_from_trace)
+            .filter(|call| call_filter.matches(call))
+            .collect();
+        Box::new(stream::iter_ok(calls))
+    }
+
+    fn contract_call(
+        &self,
+        _: &Logger,
+        call: EthereumContractCall,
+        _: Arc<dyn EthereumCallCache>,
+    ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
+        for (token, kind) in call
+            .args
+            .iter()
+            .zip(call.function.inputs.iter().map(|p| &p.kind))
+        {
+            if !token.type_check(kind) {
+                return Box::new(future::err(EthereumContractCallError::TypeError(
+                    token.clone(),
+                    kind.clone(),
+                )));
+            }
+        }
+
+        let call_data = call.function.encode_input(&call.args).unwrap();
+        let output = match self.call_result(call.address, &call_data, call.block_ptr.number) {
+            Some(output) => output,
+            None => {
+                return Box::new(future::err(EthereumContractCallError::Revert(format!(
+                    "the fixture has no result for the call of `{}` on {:?} at block {}",
+                    call.function.name, call.address, call.block_ptr.number
+                ))))
+            }
+        };
+
+        // Decode the output the same way as for calls to an Ethereum node
+        Box::new(future::result(if output.0.is_empty() {
+            Err(EthereumContractCallError::Revert("empty response".into()))
+        } else {
+            call.function.decode_output(&output.0).map_err(|e| {
+                EthereumContractCallError::Revert(format!("failed to decode output: {}", e))
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::serde_json::json;
+
+    fn hash(n: u64) -> String {
+        format!("0x{:064x}", n)
+    }
+
+    fn block(number: u64, parent: u64) -> serde_json::Value {
+        json!({
+            "block": {
+                "hash": hash(number + 100),
+                "parentHash": hash(parent + 100),
+                "sha3Uncles": hash(0),
+                "miner": "0x0000000000000000000000000000000000000000",
+                "stateRoot": hash(0),
+                "transactionsRoot": hash(0),
+                "receiptsRoot": hash(0),
+                "number": format!("0x{:x}", number),
+                "gasUsed": "0x0",
+                "gasLimit": "0x0",
+                "extraData": "0x",
+                "logsBloom": format!("0x{}", "0".repeat(512)),
+                "timestamp": "0x0",
+                "difficulty": "0x0",
+                "totalDifficulty": "0x0",
+                "sealFields": [],
+                "uncles": [],
+                "transactions": [],
+                "size": "0x0",
+                "mixHash": hash(0),
+                "nonce": "0x0000000000000000"
+            },
+            "transaction_receipts": []
+        })
+    }
+
+    fn write_fixture(name: &str, blocks: &[(u64, u64)]) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("fixture-adapter-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("blocks")).unwrap();
+        for (number, parent) in blocks {
+            fs::write(
+                dir.join("blocks").join(format!("{:08}.json", number)),
+                block(*number, *parent).to_string(),
+            )
+            .unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn loads_chain() {
+        let dir = write_fixture("chain", &[(5, 4), (6, 5), (7, 6)]);
+        fs::write(dir.join("network.json"), r#"{ "net_version": "4" }"#).unwrap();
+        let adapter = FixtureEthereumAdapter::load(&dir).unwrap();
+
+        assert_eq!("4", adapter.net_version);
+        assert_eq!(H256::from_low_u64_be(105), adapter.genesis_block_hash);
+        assert_eq!(Some(7), adapter.head().number.map(|n| n.as_u64()));
+        assert!(adapter.block_at(6).is_some());
+        assert!(adapter.block_at(8).is_none());
+        let header = block_header(adapter.head()).unwrap();
+        assert_eq!(adapter.head().hash, header.hash);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_broken_chains() {
+        // A gap between blocks 5 and 7
+        let dir = write_fixture("gap", &[(5, 4), (7, 6)]);
+        assert!(FixtureEthereumAdapter::load(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Block 6 is not the child of block 5
+        let dir = write_fixture("parent", &[(5, 4), (6, 3)]);
+        assert!(FixtureEthereumAdapter::load(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let dir = write_fixture("empty", &[]);
+        assert!(FixtureEthereumAdapter::load(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prefers_call_results_for_the_block() {
+        let dir = write_fixture("calls", &[(1, 0)]);
+        let address = "0x0000000000000000000000000000000000000001";
+        let calls = json!([
+            { "address": address, "data": "0x01", "result": "0x02" },
+            { "address": address, "data": "0x01", "block": 3, "result": "0x03" },
+        ]);
+        fs::write(dir.join("calls.json"), calls.to_string()).unwrap();
+        let adapter = FixtureEthereumAdapter::load(&dir).unwrap();
+
+        let address = Address::from_low_u64_be(1);
+        let result = |number| {
+            adapter
+                .call_result(address, &[1], number)
+                .map(|r| r.0.clone())
+        };
+        assert_eq!(Some(vec![2]), result(2));
+        assert_eq!(Some(vec![3]), result(3));
+        assert_eq!(None, adapter.call_result(address, &[2], 3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use graph::prelude::*;
+
+/// How long a block ingestor can go without a successful poll, on top of
+/// ten polling intervals, before it counts as stalled
+const STALL_GRACE: Duration = Duration::from_secs(60);
+
+/// Tells whether a spawned `BlockIngestor` is still polling successfully
+#[derive(Clone)]
+pub struct IngestorHealth {
+    pub(crate) network_name: String,
+    pub(crate) polling_interval: Duration,
+    pub(crate) last_poll: Arc<Mutex<Option<Instant>>>,
+}
+
+impl IngestorHealth {
+    pub fn network_name(&self) -> &str {
+        &self.network_name
+    }
+
+    /// Whether the ingestor polled successfully recently. Ingestors that
+    /// have not finished their first poll are not running yet
+    pub fn is_running(&self) -> bool {
+        let threshold = self.polling_interval * 10 + STALL_GRACE;
+        self.last_poll
+            .lock()
+            .unwrap()
+            .map_or(false, |last_poll| last_poll.elapsed() < threshold)
+    }
+}
+
+/// Healthy as long as at least one of the block ingestors of the node is
+/// running
+pub struct BlockIngestorsCheck {
+    ingestors: Vec<IngestorHealth>,
+}
+
+impl BlockIngestorsCheck {
+    pub fn new(ingestors: Vec<IngestorHealth>) -> Self {
+        BlockIngestorsCheck { ingestors }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for BlockIngestorsCheck {
+    fn component(&self) -> String {
+        "block_ingestors".to_owned()
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        if self.ingestors.iter().any(IngestorHealth::is_running) {
+            return Ok(());
+        }
+        let networks: Vec<_> = self
+            .ingestors
+            .iter()
+            .map(IngestorHealth::network_name)
+            .collect();
+        Err(format_err!(
+            "no block ingestor is running; networks: {}",
+            networks.join(", ")
+        ))
+    }
+}
+
+/// Healthy if the Ethereum provider for a network returns its latest
+/// block header
+pub struct ProviderCheck {
+    logger: Logger,
+    network_name: String,
+    eth_adapter: Arc<dyn EthereumAdapter>,
+}
+
+impl ProviderCheck {
+    pub fn new(
+        logger: &Logger,
+        network_name: String,
+        eth_adapter: Arc<dyn EthereumAdapter>,
+    ) -> Self {
+        ProviderCheck {
+            logger: logger
+                .new(o!("component" => "ProviderCheck", "network" => network_name.clone())),
+            network_name,
+            eth_adapter,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for ProviderCheck {
+    fn component(&self) -> String {
+        format!("provider/{}", self.network_name)
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        self.eth_adapter
+            .latest_block_header(&self.logger)
+            .compat()
+            .await
+            .map(|_| ())
+            .map_err(|e| format_err!("failed to get the latest block: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ingestor(last_poll: Option<Instant>) -> IngestorHealth {
+        IngestorHealth {
+            network_name: "mainnet".to_owned(),
+            polling_interval: Duration::from_millis(500),
+            last_poll: Arc::new(Mutex::new(last_poll)),
+        }
+    }
+
+    #[test]
+    fn ingestors_run_once_they_polled() {
+        assert!(!ingestor(None).is_running());
+        assert!(ingestor(Some(Instant::now())).is_running());
+        let check = BlockIngestorsCheck::new(vec![ingestor(None), ingestor(Some(Instant::now()))]);
+        assert!(futures03::executor::block_on(check.check()).is_ok());
+        let check = BlockIngestorsCheck::new(vec![ingestor(None)]);
+        assert!(futures03::executor::block_on(check.check()).is_err());
+    }
+}
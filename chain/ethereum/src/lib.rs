@@ -4,10 +4,14 @@ extern crate lazy_static;
 mod block_ingestor;
 mod block_stream;
 mod ethereum_adapter;
+mod fixture_adapter;
+mod health;
 pub mod network_indexer;
 mod transport;
 
 pub use self::block_ingestor::{BlockIngestor, BlockIngestorMetrics};
 pub use self::block_stream::{BlockStream, BlockStreamBuilder};
 pub use self::ethereum_adapter::EthereumAdapter;
-pub use self::transport::{EventLoopHandle, Transport};
+pub use self::fixture_adapter::FixtureEthereumAdapter;
+pub use self::health::{BlockIngestorsCheck, IngestorHealth, ProviderCheck};
+pub use self::transport::{EventLoopHandle, ReloadableTransport, Transport};
@@ -1,4 +1,5 @@
 use futures::future::FutureResult;
+use std::collections::BTreeSet;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use super::*;
@@ -57,10 +58,15 @@ fn create_subgraph(
     // version and deployment to make clear they belong together
     let version_entity_id = subgraph_id.to_string();
     ops.extend(
-        SubgraphVersionEntity::new(subgraph_entity_id.clone(), subgraph_id.clone(), created_at)
-            .write_operations(&version_entity_id)
-            .into_iter()
-            .map(|op| op.into()),
+        SubgraphVersionEntity::new(
+            subgraph_entity_id.clone(),
+            subgraph_id.clone(),
+            created_at,
+            None,
+        )
+        .write_operations(&version_entity_id)
+        .into_iter()
+        .map(|op| op.into()),
     );
 
     // Immediately make this version the current one
@@ -92,6 +98,8 @@ fn create_subgraph(
             .expect("valid Ethereum network subgraph schema"),
         data_sources: vec![],
         graft: None,
+        features: BTreeSet::new(),
+        api_version: ApiVersion::default(),
         templates: vec![],
     };
 
@@ -2,6 +2,8 @@ use graph::prelude::*;
 use jsonrpc_core::types::Call;
 use serde_json::Value;
 use std::env;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
 
 use web3::transports::{http, ipc, ws};
 use web3::RequestId;
@@ -83,3 +85,135 @@ impl web3::BatchTransport for Transport {
         }
     }
 }
+
+/// Spaces requests out so that no more than a given number are sent per
+/// second
+#[derive(Debug)]
+struct Throttle {
+    /// The time between two requests, or `None` if requests are not
+    /// rate limited
+    interval: Option<Duration>,
+    /// When the next request may be sent
+    next: Instant,
+}
+
+impl Throttle {
+    /// How long to wait before sending a request now, if at all
+    fn wait(&mut self) -> Option<Duration> {
+        let interval = self.interval?;
+        let now = Instant::now();
+        let slot = self.next.max(now);
+        self.next = slot + interval;
+        Some(slot - now).filter(|wait| *wait > Duration::from_millis(0))
+    }
+}
+
+/// A transport whose underlying connection can be replaced while it is in
+/// use, for example, when the URL of a provider is changed in the
+/// configuration file. Requests that were started before the replacement
+/// finish on the old connection. Its rate limit can be changed in the same
+/// way.
+#[derive(Clone, Debug)]
+pub struct ReloadableTransport {
+    inner: Arc<RwLock<Transport>>,
+    throttle: Arc<Mutex<Throttle>>,
+}
+
+impl ReloadableTransport {
+    pub fn new(transport: Transport) -> Self {
+        ReloadableTransport {
+            inner: Arc::new(RwLock::new(transport)),
+            throttle: Arc::new(Mutex::new(Throttle {
+                interval: None,
+                next: Instant::now(),
+            })),
+        }
+    }
+
+    /// Send all future requests through `transport`
+    pub fn replace(&self, transport: Transport) {
+        *self.inner.write().unwrap() = transport;
+    }
+
+    /// Send at most `rate_limit` requests per second from now on, or as
+    /// many as callers make if it is `None`
+    pub fn set_rate_limit(&self, rate_limit: Option<u32>) {
+        self.throttle.lock().unwrap().interval = rate_limit
+            .filter(|rate| *rate > 0)
+            .map(|rate| Duration::from_secs(1) / rate);
+    }
+
+    fn current(&self) -> Transport {
+        self.inner.read().unwrap().clone()
+    }
+
+    fn wait(&self) -> Option<Duration> {
+        self.throttle.lock().unwrap().wait()
+    }
+}
+
+impl web3::Transport for ReloadableTransport {
+    type Out = <Transport as web3::Transport>::Out;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.current().prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let transport = self.current();
+        match self.wait() {
+            None => transport.send(id, request),
+            Some(wait) => Box::new(
+                tokio::time::delay_for(wait)
+                    .map(Ok)
+                    .compat()
+                    .and_then(move |()| transport.send(id, request)),
+            ),
+        }
+    }
+}
+
+impl web3::BatchTransport for ReloadableTransport {
+    type Batch = <Transport as web3::BatchTransport>::Batch;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let transport = self.current();
+        // A batch counts as one request against the rate limit
+        match self.wait() {
+            None => transport.send_batch(requests),
+            Some(wait) => {
+                let requests: Vec<_> = requests.into_iter().collect();
+                Box::new(
+                    tokio::time::delay_for(wait)
+                        .map(Ok)
+                        .compat()
+                        .and_then(move |()| transport.send_batch(requests)),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttle_spaces_out_requests() {
+        let mut throttle = Throttle {
+            interval: None,
+            next: Instant::now(),
+        };
+        assert_eq!(None, throttle.wait());
+
+        throttle.interval = Some(Duration::from_secs(10));
+        assert_eq!(None, throttle.wait());
+        let wait = throttle.wait().expect("the second request waits");
+        assert!(wait > Duration::from_secs(9) && wait <= Duration::from_secs(10));
+        let wait = throttle.wait().expect("the third request waits");
+        assert!(wait > Duration::from_secs(19) && wait <= Duration::from_secs(20));
+    }
+}
@@ -151,6 +151,13 @@ impl EthereumCallCache for FakeEthereumCallCache {
     ) -> Result<(), Error> {
         unimplemented!()
     }
+
+    fn get_calls_in_block(
+        &self,
+        _: EthereumBlockPointer,
+    ) -> Result<Vec<CachedEthereumCall>, Error> {
+        unimplemented!()
+    }
 }
 
 #[test]
@@ -3,8 +3,9 @@ mod metrics;
 mod subgraph;
 pub mod three_box;
 
-pub use crate::link_resolver::LinkResolver;
+pub use crate::link_resolver::{IpfsHealthCheck, LinkResolver};
 pub use crate::metrics::MetricsRegistry;
 pub use crate::subgraph::{
-    DataSourceLoader, SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar,
+    replay, DataSourceLoader, SubgraphAssignmentProvider, SubgraphInstance,
+    SubgraphInstanceManager, SubgraphRegistrar,
 };
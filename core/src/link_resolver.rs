@@ -1,5 +1,6 @@
 use std::env;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -12,7 +13,9 @@ use lazy_static::lazy_static;
 use lru_time_cache::LruCache;
 use serde_json::Value;
 
-use graph::prelude::{LinkResolver as LinkResolverTrait, *};
+use graph::prelude::{
+    DeploymentFiles, HealthCheck, IpfsCache, LinkResolver as LinkResolverTrait, *,
+};
 
 /// Environment variable for limiting the `ipfs.map` file size limit.
 const MAX_IPFS_MAP_FILE_SIZE_VAR: &'static str = "GRAPH_MAX_IPFS_MAP_FILE_SIZE";
@@ -36,8 +39,20 @@ lazy_static! {
     static ref IPFS_TIMEOUT: Duration = Duration::from_secs(
         read_u64_from_env("GRAPH_IPFS_TIMEOUT").unwrap_or(60)
     );
+
+    /// How often to check whether the IPFS gateways are reachable, in seconds
+    static ref IPFS_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(
+        read_u64_from_env("GRAPH_IPFS_HEALTH_CHECK_INTERVAL").unwrap_or(30)
+    );
 }
 
+/// After this many requests in a row failed, a gateway is unhealthy and only
+/// used when no healthy gateway is left
+const MAX_GATEWAY_FAILURES: u32 = 3;
+
+/// How long a gateway may take to answer a health check
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
 fn read_u64_from_env(name: &str) -> Option<u64> {
     env::var(name).ok().map(|s| {
         u64::from_str(&s).unwrap_or_else(|_| {
@@ -49,6 +64,38 @@ fn read_u64_from_env(name: &str) -> Option<u64> {
     })
 }
 
+/// One of the IPFS nodes that files are fetched from
+struct Gateway {
+    client: IpfsClient,
+    /// The number of requests to this gateway that failed in a row
+    failures: AtomicU32,
+}
+
+impl Gateway {
+    fn new(client: IpfsClient) -> Self {
+        Gateway {
+            client,
+            failures: AtomicU32::new(0),
+        }
+    }
+
+    fn healthy(&self) -> bool {
+        self.failures.load(Ordering::Relaxed) < MAX_GATEWAY_FAILURES
+    }
+
+    fn succeeded(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    fn failed(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn unreachable(&self) {
+        self.failures.store(MAX_GATEWAY_FAILURES, Ordering::Relaxed);
+    }
+}
+
 /// The IPFS APIs don't have a quick "do you have the file" function. Instead, we
 /// just rely on whether an API times out. That makes sense for IPFS, but not for
 /// our application. We want to be able to quickly select from a potential list
@@ -61,19 +108,34 @@ fn read_u64_from_env(name: &str) -> Option<u64> {
 /// the file from each client, which would be wasteful of bandwidth and memory in
 /// the case multiple clients respond in a timely manner. In addition, we may
 /// make good use of the stat returned.
+///
+/// Only healthy gateways are asked, unless none of them is healthy. The
+/// gateways in `exclude` are never asked. Returns the index of the gateway
+/// that answered first.
 async fn select_fastest_client_with_stat<'a>(
-    clients: &'a [IpfsClient],
+    gateways: &'a [Gateway],
     logger: &'a Logger,
     path: &'_ str,
     timeout: Duration,
     do_retry: bool,
-) -> Result<(ObjectStatResponse, &'a IpfsClient), failure::Error> {
+    exclude: &[usize],
+) -> Result<(ObjectStatResponse, usize), failure::Error> {
     let mut err: Option<failure::Error> = None;
 
-    let mut stats: FuturesUnordered<_> = clients
+    let usable: Vec<_> = (0..gateways.len())
+        .filter(|i| !exclude.contains(i))
+        .collect();
+    let healthy: Vec<_> = usable
         .iter()
-        .enumerate()
-        .map(|(i, c)| {
+        .cloned()
+        .filter(|i| gateways[*i].healthy())
+        .collect();
+    let candidates = if healthy.is_empty() { usable } else { healthy };
+
+    let mut stats: FuturesUnordered<_> = candidates
+        .into_iter()
+        .map(|i| {
+            let c = &gateways[i].client;
             let retry_fut = if do_retry {
                 retry("object.stat", logger).no_limit()
             } else {
@@ -90,7 +152,7 @@ async fn select_fastest_client_with_stat<'a>(
     while let Some(result) = stats.next().await {
         match result {
             Ok((stat, index)) => {
-                return Ok((stat, &clients[index]));
+                return Ok((stat, index));
             }
             Err(e) => err = Some(e.into()),
         }
@@ -125,8 +187,15 @@ fn restrict_file_size(
 
 #[derive(Clone)]
 pub struct LinkResolver {
-    clients: Arc<Vec<IpfsClient>>,
+    gateways: Arc<Vec<Gateway>>,
     cache: Arc<Mutex<LruCache<String, Vec<u8>>>>,
+    /// Where files are kept beyond the in-memory cache, usually the database
+    persistent_cache: Option<Arc<dyn IpfsCache>>,
+    /// The files of subgraphs that were deployed without IPFS
+    deployment_files: Option<Arc<dyn DeploymentFiles>>,
+    /// Whether files that are in neither cache are an error instead of
+    /// being fetched from IPFS
+    cache_only: bool,
     timeout: Duration,
     retry: bool,
 }
@@ -140,49 +209,153 @@ impl From<IpfsClient> for LinkResolver {
 impl From<Vec<IpfsClient>> for LinkResolver {
     fn from(clients: Vec<IpfsClient>) -> Self {
         Self {
-            clients: Arc::new(clients),
+            gateways: Arc::new(clients.into_iter().map(Gateway::new).collect()),
             cache: Arc::new(Mutex::new(LruCache::with_capacity(
                 *MAX_IPFS_CACHE_SIZE as usize,
             ))),
+            persistent_cache: None,
+            deployment_files: None,
+            cache_only: false,
             timeout: *IPFS_TIMEOUT,
             retry: false,
         }
     }
 }
 
-#[async_trait]
-impl LinkResolverTrait for LinkResolver {
-    fn with_timeout(mut self, timeout: Duration) -> Self {
-        self.timeout = timeout;
-        self
+impl LinkResolver {
+    /// Also keep the files that fit into the in-memory cache in `cache`, and
+    /// look for files there before fetching them from IPFS
+    pub fn with_persistent_cache(self, cache: Arc<dyn IpfsCache>) -> Self {
+        LinkResolver {
+            persistent_cache: Some(cache),
+            ..self
+        }
     }
 
-    fn with_retries(mut self) -> Self {
-        self.retry = true;
-        self
+    /// Read the files of subgraphs that were deployed from a local
+    /// directory or an HTTP URL from `files` instead of IPFS
+    pub fn with_deployment_files(self, files: Arc<dyn DeploymentFiles>) -> Self {
+        LinkResolver {
+            deployment_files: Some(files),
+            ..self
+        }
     }
 
-    /// Supports links of the form `/ipfs/ipfs_hash` or just `ipfs_hash`.
-    async fn cat(&self, logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
-        // Discard the `/ipfs/` prefix (if present) to get the hash.
-        let path = link.link.trim_start_matches("/ipfs/").to_owned();
+    /// Only read files from the caches and the deployment files, and fail
+    /// for files that are not there instead of fetching them from IPFS.
+    /// Replaying blocks uses this so that handlers see exactly the files
+    /// they saw when the blocks were first processed
+    pub fn cache_only(self) -> Self {
+        LinkResolver {
+            cache_only: true,
+            ..self
+        }
+    }
 
-        if let Some(data) = self.cache.lock().unwrap().get(&path) {
-            trace!(logger, "IPFS cache hit"; "hash" => &path);
-            return Ok(data.clone());
+    /// Check periodically whether the gateways are reachable. Unreachable
+    /// gateways are only used when no other gateway is left, until they
+    /// are reachable again. Panics if no gateway is reachable when the
+    /// gateways are checked for the first time. Must be called from within
+    /// a Tokio runtime
+    pub fn start_health_checks(&self, logger: &Logger) {
+        let logger = logger.new(o!("component" => "IpfsHealthCheck"));
+        let gateways = self.gateways.clone();
+        graph::spawn(async move {
+            let mut interval = tokio::time::interval(*IPFS_HEALTH_CHECK_INTERVAL);
+            let mut first = true;
+            loop {
+                interval.tick().await;
+                let mut reachable = 0;
+                for (index, gateway) in gateways.iter().enumerate() {
+                    let version =
+                        tokio::time::timeout(HEALTH_CHECK_TIMEOUT, gateway.client.version()).await;
+                    match version {
+                        Ok(Ok(_)) => {
+                            if first {
+                                info!(logger, "Successfully connected to IPFS node";
+                                      "gateway" => index);
+                            } else if !gateway.healthy() {
+                                info!(logger, "IPFS node is reachable again";
+                                      "gateway" => index);
+                            }
+                            gateway.succeeded();
+                            reachable += 1;
+                        }
+                        Ok(Err(e)) => {
+                            warn!(logger, "IPFS node is not reachable";
+                                  "gateway" => index, "error" => e.to_string());
+                            gateway.unreachable();
+                        }
+                        Err(_) => {
+                            warn!(logger, "IPFS node did not answer in time";
+                                  "gateway" => index);
+                            gateway.unreachable();
+                        }
+                    }
+                }
+                if first && reachable == 0 {
+                    error!(
+                        logger,
+                        "Is there an IPFS node running at any of the addresses?"
+                    );
+                    panic!("Failed to connect to IPFS");
+                }
+                first = false;
+            }
+        });
+    }
+
+    /// A check for the health server that fails when no gateway is healthy
+    pub fn health(&self) -> IpfsHealthCheck {
+        IpfsHealthCheck {
+            gateways: self.gateways.clone(),
         }
-        trace!(logger, "IPFS cache miss"; "hash" => &path);
+    }
 
-        let (stat, client) =
-            select_fastest_client_with_stat(&self.clients, logger, &path, self.timeout, self.retry)
-                .await?;
+    async fn deployment_file(&self, id: &str) -> Result<Vec<u8>, Error> {
+        let files = self
+            .deployment_files
+            .clone()
+            .ok_or_else(|| format_err!("subgraph file {} is not stored on this node", id))?;
+        let id = id.to_owned();
+        graph::spawn_blocking_async_allow_panic(move || {
+            files
+                .get(&id)?
+                .ok_or_else(|| format_err!("subgraph file {} does not exist", id))
+        })
+        .await
+    }
 
-        // FIXME: Having an env variable here is a problem for consensus.
-        // Index Nodes should not disagree on whether the file should be read.
-        let max_file_size: Option<u64> = read_u64_from_env(MAX_IPFS_FILE_SIZE_VAR);
-        restrict_file_size(&path, &stat, &max_file_size)?;
+    async fn persistent_get(&self, logger: &Logger, path: &str) -> Option<Vec<u8>> {
+        let cache = self.persistent_cache.clone()?;
+        let path = path.to_owned();
+        match graph::spawn_blocking_async_allow_panic(move || cache.get(&path)).await {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(logger, "Failed to read IPFS file from the cache"; "error" => e.to_string());
+                None
+            }
+        }
+    }
 
-        let path = path.clone();
+    async fn persistent_insert(&self, logger: &Logger, path: &str, data: Vec<u8>) {
+        if let Some(cache) = self.persistent_cache.clone() {
+            let path = path.to_owned();
+            let result =
+                graph::spawn_blocking_async_allow_panic(move || cache.insert(&path, &data)).await;
+            if let Err(e) = result {
+                warn!(logger, "Failed to write IPFS file to the cache"; "error" => e.to_string());
+            }
+        }
+    }
+
+    async fn fetch(
+        &self,
+        logger: &Logger,
+        client: &IpfsClient,
+        path: &str,
+    ) -> Result<Vec<u8>, Error> {
+        let path = path.to_owned();
         let retry_fut = if self.retry {
             retry("ipfs.cat", &logger).no_limit()
         } else {
@@ -200,14 +373,6 @@ impl LinkResolverTrait for LinkResolver {
                         .try_concat()
                         .await?
                         .to_vec();
-
-                    // Only cache files if they are not too large
-                    if data.len() <= *MAX_IPFS_CACHE_FILE_SIZE as usize {
-                        let mut cache = self.cache.lock().unwrap();
-                        if !cache.contains_key(&path) {
-                            cache.insert(path.to_owned(), data.clone());
-                        }
-                    }
                     Result::<Vec<u8>, Error>::Ok(data)
                 }
                 .boxed()
@@ -218,20 +383,148 @@ impl LinkResolverTrait for LinkResolver {
 
         Ok(data)
     }
+}
+
+pub struct IpfsHealthCheck {
+    gateways: Arc<Vec<Gateway>>,
+}
+
+#[async_trait]
+impl HealthCheck for IpfsHealthCheck {
+    fn component(&self) -> String {
+        "ipfs".to_owned()
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        if self.gateways.iter().any(|gateway| gateway.healthy()) {
+            Ok(())
+        } else {
+            Err(format_err!("none of the IPFS nodes is healthy"))
+        }
+    }
+}
+
+#[async_trait]
+impl LinkResolverTrait for LinkResolver {
+    fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn with_retries(mut self) -> Self {
+        self.retry = true;
+        self
+    }
+
+    /// Supports links of the form `/ipfs/ipfs_hash` or just `ipfs_hash`.
+    async fn cat(&self, logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        // Discard the `/ipfs/` prefix (if present) to get the hash.
+        let path = link.link.trim_start_matches("/ipfs/").to_owned();
+
+        if is_local_file(&path) {
+            return self.deployment_file(&path).await;
+        }
+        if let Some(data) = self.cache.lock().unwrap().get(&path) {
+            trace!(logger, "IPFS cache hit"; "hash" => &path);
+            return Ok(data.clone());
+        }
+        if let Some(data) = self.persistent_get(logger, &path).await {
+            trace!(logger, "IPFS persistent cache hit"; "hash" => &path);
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(path.clone(), data.clone());
+            return Ok(data);
+        }
+        trace!(logger, "IPFS cache miss"; "hash" => &path);
+        if self.cache_only {
+            return Err(format_err!("IPFS file {} is not cached", path));
+        }
+
+        // FIXME: Having an env variable here is a problem for consensus.
+        // Index Nodes should not disagree on whether the file should be read.
+        let max_file_size: Option<u64> = read_u64_from_env(MAX_IPFS_FILE_SIZE_VAR);
+
+        // Fail over to the other gateways that have the file if fetching it
+        // from the fastest one fails
+        let mut failed = vec![];
+        let data = loop {
+            let (stat, index) = select_fastest_client_with_stat(
+                &self.gateways,
+                logger,
+                &path,
+                self.timeout,
+                self.retry,
+                &failed,
+            )
+            .await?;
+            restrict_file_size(&path, &stat, &max_file_size)?;
+
+            let gateway = &self.gateways[index];
+            match self.fetch(logger, &gateway.client, &path).await {
+                Ok(data) => {
+                    gateway.succeeded();
+                    break data;
+                }
+                Err(e) => {
+                    gateway.failed();
+                    failed.push(index);
+                    if failed.len() == self.gateways.len() {
+                        return Err(e);
+                    }
+                    warn!(logger, "Failed to fetch file from IPFS node, trying another one";
+                          "hash" => &path, "gateway" => index, "error" => e.to_string());
+                }
+            }
+        };
+
+        // Only cache files if they are not too large
+        if data.len() <= *MAX_IPFS_CACHE_FILE_SIZE as usize {
+            {
+                let mut cache = self.cache.lock().unwrap();
+                if !cache.contains_key(&path) {
+                    cache.insert(path.clone(), data.clone());
+                }
+            }
+            self.persistent_insert(logger, &path, data.clone()).await;
+        }
+
+        Ok(data)
+    }
 
     async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
         // Discard the `/ipfs/` prefix (if present) to get the hash.
         let path = link.link.trim_start_matches("/ipfs/");
 
-        let (stat, client) =
-            select_fastest_client_with_stat(&self.clients, logger, path, self.timeout, self.retry)
+        let chunks: Box<dyn futures01::Stream<Item = Vec<u8>, Error = Error> + Send> =
+            if self.cache_only {
+                let data = self.cat(logger, link).await?;
+                Box::new(futures01::stream::once(Ok(data)))
+            } else {
+                let (stat, index) = select_fastest_client_with_stat(
+                    &self.gateways,
+                    logger,
+                    path,
+                    self.timeout,
+                    self.retry,
+                    &[],
+                )
                 .await?;
+                let client = &self.gateways[index].client;
 
-        let max_file_size =
-            read_u64_from_env(MAX_IPFS_MAP_FILE_SIZE_VAR).or(Some(DEFAULT_MAX_IPFS_MAP_FILE_SIZE));
-        restrict_file_size(path, &stat, &max_file_size)?;
+                let max_file_size = read_u64_from_env(MAX_IPFS_MAP_FILE_SIZE_VAR)
+                    .or(Some(DEFAULT_MAX_IPFS_MAP_FILE_SIZE));
+                restrict_file_size(path, &stat, &max_file_size)?;
 
-        let mut stream = client.cat(&path).compat().fuse();
+                Box::new(
+                    client
+                        .cat(&path)
+                        .compat()
+                        .map(|chunk| chunk.to_vec())
+                        .map_err(Error::from),
+                )
+            };
+        let mut stream = chunks.fuse();
 
         let mut buf = BytesMut::with_capacity(1024);
 
@@ -1,22 +1,12 @@
 use async_trait::async_trait;
 use futures01::sync::mpsc::Sender;
-use lazy_static::lazy_static;
 
 use std::collections::HashMap;
-use std::env;
-use std::str::FromStr;
 
 use graph::components::subgraph::SharedProofOfIndexing;
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
 use web3::types::Log;
 
-lazy_static! {
-    static ref MAX_DATA_SOURCES: Option<usize> = env::var("GRAPH_SUBGRAPH_MAX_DATA_SOURCES")
-        .ok()
-        .map(|s| usize::from_str(&s)
-            .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_SUBGRAPH_MAX_DATA_SOURCES")));
-}
-
 pub struct SubgraphInstance<T: RuntimeHostBuilder> {
     subgraph_id: SubgraphDeploymentId,
     network: String,
@@ -37,7 +27,8 @@ impl<T> SubgraphInstance<T>
 where
     T: RuntimeHostBuilder,
 {
-    pub(crate) fn from_manifest(
+    /// Create a runtime host for each data source of `manifest`
+    pub fn from_manifest(
         logger: &Logger,
         manifest: SubgraphManifest,
         host_builder: T,
@@ -86,6 +77,12 @@ where
         Ok(this)
     }
 
+    /// How many data sources the instance has, including the ones that
+    /// templates created
+    pub fn data_source_count(&self) -> usize {
+        self.hosts.len()
+    }
+
     fn new_host(
         &mut self,
         logger: Logger,
@@ -226,6 +223,37 @@ where
         Ok(state)
     }
 
+    async fn process_entity_change(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        source: &SubgraphDeploymentId,
+        change: &EntityOperation,
+        mut state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, anyhow::Error> {
+        let entity_type = match change {
+            EntityOperation::Set { key, .. } | EntityOperation::Remove { key } => &key.entity_type,
+        };
+        let block_number = block.number.unwrap().as_u64();
+        let matching_hosts = self
+            .hosts
+            .iter()
+            .filter(|host| host.matches_entity_change(source, entity_type, block_number));
+        for host in matching_hosts {
+            state = host
+                .process_entity_change(
+                    logger,
+                    block,
+                    change,
+                    state,
+                    proof_of_indexing.cheap_clone(),
+                )
+                .await?;
+        }
+        Ok(state)
+    }
+
     fn add_dynamic_data_source(
         &mut self,
         logger: &Logger,
@@ -233,16 +261,6 @@ where
         top_level_templates: Arc<Vec<DataSourceTemplate>>,
         metrics: Arc<HostMetrics>,
     ) -> Result<Arc<T::Host>, anyhow::Error> {
-        // Protect against creating more than the allowed maximum number of data sources
-        if let Some(max_data_sources) = *MAX_DATA_SOURCES {
-            if self.hosts.len() >= max_data_sources {
-                anyhow::bail!(
-                    "Limit of {} data sources per subgraph exceeded",
-                    max_data_sources,
-                );
-            }
-        }
-
         let host = Arc::new(self.new_host(
             logger.clone(),
             data_source,
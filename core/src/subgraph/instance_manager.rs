@@ -2,19 +2,28 @@ use atomic_refcell::AtomicRefCell;
 use futures01::sync::mpsc::{channel, Receiver, Sender};
 use lazy_static::lazy_static;
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Instant;
 
 use graph::components::ethereum::triggers_in_block;
 use graph::components::store::ModificationsAndCache;
-use graph::components::subgraph::{ProofOfIndexing, SharedProofOfIndexing};
+use graph::components::subgraph::{
+    write_audit, MappingError, ProofOfIndexing, SharedProofOfIndexing,
+};
 use graph::data::store::scalar::Bytes;
 use graph::data::subgraph::schema::{
     queries::LazyMetadata, DynamicEthereumContractDataSourceEntity, SubgraphError, SubgraphHealth,
     POI_OBJECT,
 };
+use graph::log::error_reporting::{self, ErrorReport};
+use graph::prelude::web3::types::H256;
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
+use graph::trace::{Span, SpanKind};
+use graph::util::env::env_var;
 use graph::util::lfu_cache::LfuCache;
+use graph::util::memory::{self, MemoryUsage, Subsystem};
+use graph::util::shutdown::Shutdown;
 
 use super::SubgraphInstance;
 
@@ -26,8 +35,29 @@ lazy_static! {
             .unwrap_or("10000".into())
             .parse::<u64>()
             .expect("invalid GRAPH_ENTITY_CACHE_SIZE");
+
+    /// A deployment that is behind the chain head and has not processed a
+    /// block for this many seconds is reported as stalled
+    static ref SUBGRAPH_STALLED_THRESHOLD: Duration =
+        Duration::from_secs(env_var("GRAPH_SUBGRAPH_STALLED_THRESHOLD").unwrap_or(600));
 }
 
+/// How often the indexing lag of a deployment is updated
+const LAG_UPDATE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often a deployment checks whether it was put into maintenance, and,
+/// while it is in maintenance, whether it was taken out of it again
+const MAINTENANCE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a deployment with data sources of kind `subgraph` checks
+/// whether their source deployment has caught up with the block it is
+/// about to process
+const SOURCE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the block that a deployment has processed is recorded, so that
+/// the index node can estimate how long syncing will take
+const SYNC_SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
 type SharedInstanceKeepAliveMap = Arc<RwLock<HashMap<SubgraphDeploymentId, CancelGuard>>>;
 
 struct IndexingInputs<B, S> {
@@ -39,6 +69,18 @@ struct IndexingInputs<B, S> {
     stream_builder: B,
     templates_use_calls: bool,
     top_level_templates: Arc<Vec<DataSourceTemplate>>,
+    entity_sources: Vec<EntitySource>,
+    shutdown: Shutdown,
+}
+
+/// A deployment whose entity changes are handled by data sources of kind
+/// `subgraph`
+struct EntitySource {
+    deployment: SubgraphDeploymentId,
+    /// The entity types whose changes are handled
+    entity_types: Vec<String>,
+    /// The first block for which changes are handled
+    start_block: u64,
 }
 
 struct IndexingState<T: RuntimeHostBuilder> {
@@ -50,6 +92,7 @@ struct IndexingState<T: RuntimeHostBuilder> {
     block_filter: EthereumBlockFilter,
     restarts: u64,
     entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+    entity_cache_usage: MemoryUsage,
 }
 
 struct IndexingContext<B, T: RuntimeHostBuilder, S> {
@@ -95,6 +138,23 @@ impl SubgraphInstanceManagerMetrics {
     }
 }
 
+/// A trigger whose handler failed; reads like the error it wraps
+#[derive(Debug)]
+struct HandlerFailure {
+    data_source: String,
+    handler: String,
+    deterministic: bool,
+    error: Error,
+}
+
+impl fmt::Display for HandlerFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl Fail for HandlerFailure {}
+
 enum TriggerType {
     Event,
     Call,
@@ -115,8 +175,13 @@ struct SubgraphInstanceMetrics {
     pub block_trigger_count: Box<Histogram>,
     pub block_processing_duration: Box<Histogram>,
     pub block_ops_transaction_duration: Box<Histogram>,
+    pub seconds_since_last_block: Box<Gauge>,
+    pub stalled: Box<Gauge>,
+    pub data_source_count: Box<Gauge>,
+    pub data_sources_created: Box<Counter>,
 
     trigger_processing_duration: Box<HistogramVec>,
+    last_block_processed: Mutex<Instant>,
 }
 
 impl SubgraphInstanceMetrics {
@@ -156,15 +221,61 @@ impl SubgraphInstanceMetrics {
                 vec![0.01, 0.05, 0.1, 0.3, 0.7, 2.0],
             )
             .expect("failed to create `subgraph_transact_block_operations_duration_{}");
+        let seconds_since_last_block = registry
+            .new_gauge(
+                format!("subgraph_seconds_since_last_block_{}", subgraph_hash),
+                String::from("Seconds since a subgraph deployment last processed a block"),
+                HashMap::new(),
+            )
+            .expect("failed to create `subgraph_seconds_since_last_block` gauge");
+        let stalled = registry
+            .new_gauge(
+                format!("subgraph_stalled_{}", subgraph_hash),
+                String::from("1 if a subgraph deployment is behind the chain head and has not processed a block for longer than GRAPH_SUBGRAPH_STALLED_THRESHOLD, 0 otherwise"),
+                HashMap::new(),
+            )
+            .expect("failed to create `subgraph_stalled` gauge");
+        let data_source_count = registry
+            .new_gauge(
+                format!("subgraph_data_source_count_{}", subgraph_hash),
+                String::from("The number of data sources of a subgraph deployment, including those created from templates"),
+                HashMap::new(),
+            )
+            .expect("failed to create `subgraph_data_source_count` gauge");
+        let data_sources_created = registry
+            .new_counter(
+                format!("subgraph_data_sources_created_{}", subgraph_hash),
+                String::from("Counts the data sources that the handlers of a subgraph deployment created from templates"),
+                HashMap::new(),
+            )
+            .expect("failed to create `subgraph_data_sources_created` counter");
 
         Self {
             block_trigger_count,
             block_processing_duration,
             trigger_processing_duration,
             block_ops_transaction_duration,
+            seconds_since_last_block,
+            stalled,
+            data_source_count,
+            data_sources_created,
+            last_block_processed: Mutex::new(Instant::now()),
         }
     }
 
+    pub fn observe_block_processed(&self) {
+        *self.last_block_processed.lock().unwrap() = Instant::now();
+    }
+
+    /// Update how far the deployment lags behind, given how many blocks it
+    /// is behind the chain head
+    pub fn update_lag(&self, blocks_behind: f64) {
+        let idle = self.last_block_processed.lock().unwrap().elapsed();
+        self.seconds_since_last_block.set(idle.as_secs_f64());
+        let stalled = blocks_behind > 0.0 && idle > *SUBGRAPH_STALLED_THRESHOLD;
+        self.stalled.set(if stalled { 1.0 } else { 0.0 });
+    }
+
     pub fn observe_trigger_processing_duration(&self, duration: f64, trigger: TriggerType) {
         self.trigger_processing_duration
             .with_label_values(vec![trigger.label_value()].as_slice())
@@ -176,6 +287,8 @@ impl SubgraphInstanceMetrics {
         registry.unregister(self.block_trigger_count.clone());
         registry.unregister(self.trigger_processing_duration.clone());
         registry.unregister(self.block_ops_transaction_duration.clone());
+        registry.unregister(self.seconds_since_last_block.clone());
+        registry.unregister(self.stalled.clone());
     }
 }
 
@@ -189,6 +302,7 @@ impl SubgraphInstanceManager {
         block_stream_builder: B,
         metrics_registry: Arc<M>,
         graphql_runner: Arc<impl GraphQlRunner>,
+        shutdown: Shutdown,
     ) -> Self
     where
         S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
@@ -211,6 +325,7 @@ impl SubgraphInstanceManager {
             block_stream_builder,
             metrics_registry.clone(),
             graphql_runner,
+            shutdown,
         );
 
         SubgraphInstanceManager {
@@ -229,6 +344,7 @@ impl SubgraphInstanceManager {
         block_stream_builder: B,
         metrics_registry: Arc<M>,
         graphql_runner: Arc<impl GraphQlRunner>,
+        shutdown: Shutdown,
     ) where
         S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
         B: BlockStreamBuilder,
@@ -280,6 +396,7 @@ impl SubgraphInstanceManager {
                             manifest,
                             metrics_registry_for_subgraph.clone(),
                             graphql_runner.clone(),
+                            shutdown.clone(),
                         )
                         .await
                         {
@@ -314,6 +431,7 @@ impl SubgraphInstanceManager {
         manifest: SubgraphManifest,
         registry: Arc<M>,
         graphql_runner: Arc<impl GraphQlRunner>,
+        shutdown: Shutdown,
     ) -> Result<(), Error>
     where
         B: BlockStreamBuilder,
@@ -341,6 +459,141 @@ impl SubgraphInstanceManager {
         };
         store.start_subgraph_deployment(&logger, &manifest.id, status_ops)?;
 
+        let ctx = Self::indexing_context(
+            logger,
+            instances,
+            host_builder,
+            stream_builder,
+            store,
+            eth_adapter,
+            manifest,
+            registry.clone(),
+            shutdown,
+        )?;
+        let subgraph_metrics_unregister = ctx.subgraph_metrics.clone();
+
+        // Update the lag of the deployment until it stops, i.e., until the
+        // indexing task below drops the metrics
+        let lag_metrics = Arc::downgrade(&ctx.subgraph_metrics);
+        let lag_block_stream_metrics = ctx.block_stream_metrics.cheap_clone();
+        graph::spawn(async move {
+            let mut interval = tokio::time::interval(LAG_UPDATE_INTERVAL);
+            loop {
+                interval.tick().await;
+                match lag_metrics.upgrade() {
+                    Some(metrics) => {
+                        metrics.update_lag(lag_block_stream_metrics.blocks_behind.get())
+                    }
+                    None => break,
+                }
+            }
+        });
+
+        // Keep restarting the subgraph until it terminates. The subgraph
+        // will usually only run once, but is restarted whenever a block
+        // creates dynamic data sources. This allows us to recreate the
+        // block stream and include events for the new data sources going
+        // forward; this is easier than updating the existing block stream.
+        //
+        // This task has many calls to the store, so mark it as `blocking`.
+        graph::spawn_blocking(async move {
+            let res = run_subgraph(ctx).await;
+            subgraph_metrics_unregister.unregister(registry);
+            res
+        });
+
+        Ok(())
+    }
+
+    /// Process `blocks` for the deployment of `manifest` the same way that
+    /// indexing does, but without a block stream, and return once the last
+    /// of them has been processed. The deployment must exist and have
+    /// processed the parent of the first block. Triggers only come from the
+    /// receipts of the blocks, so that deployments with call handlers or
+    /// block handlers with a call filter can not be replayed
+    pub async fn replay_blocks<B, S, M>(
+        logger: Logger,
+        host_builder: impl RuntimeHostBuilder,
+        stream_builder: B,
+        store: Arc<S>,
+        eth_adapter: Arc<dyn EthereumAdapter>,
+        manifest: SubgraphManifest,
+        registry: Arc<M>,
+        blocks: Vec<EthereumBlock>,
+    ) -> Result<(), Error>
+    where
+        B: BlockStreamBuilder,
+        S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
+        M: MetricsRegistry,
+    {
+        let mut ctx = Self::indexing_context(
+            logger.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
+            host_builder,
+            stream_builder,
+            store,
+            eth_adapter,
+            manifest,
+            registry.clone(),
+            Shutdown::new(),
+        )?;
+        if ctx.inputs.templates_use_calls
+            || !ctx.state.call_filter.is_empty()
+            || !ctx.state.block_filter.contract_addresses.is_empty()
+        {
+            return Err(format_err!(
+                "can not replay blocks for a subgraph with call handlers or block handlers \
+                 with a call filter since call traces are not cached"
+            ));
+        }
+
+        let canceler = CancelGuard::new();
+        for block in blocks {
+            let block = BlockFinality::NonFinal(EthereumBlockWithCalls {
+                ethereum_block: block,
+                calls: None,
+            });
+            let block = triggers_in_block(
+                ctx.inputs.eth_adapter.cheap_clone(),
+                logger.cheap_clone(),
+                ctx.inputs.store.clone(),
+                ctx.ethrpc_metrics.clone(),
+                ctx.state.log_filter.clone(),
+                ctx.state.call_filter.clone(),
+                ctx.state.block_filter.clone(),
+                block,
+            )
+            .await?;
+            let eth_adapter = ctx.inputs.eth_adapter.cheap_clone();
+            ctx = match process_block(&logger, eth_adapter, ctx, canceler.handle(), block).await {
+                Ok((ctx, _)) => ctx,
+                Err(CancelableError::Cancel) => return Err(format_err!("replay was canceled")),
+                Err(CancelableError::Error(e)) => return Err(e),
+            };
+        }
+        ctx.subgraph_metrics.unregister(registry);
+        Ok(())
+    }
+
+    /// Set up everything that is needed to index the deployment of
+    /// `manifest`
+    fn indexing_context<B, S, M, T>(
+        logger: Logger,
+        instances: SharedInstanceKeepAliveMap,
+        host_builder: T,
+        stream_builder: B,
+        store: Arc<S>,
+        eth_adapter: Arc<dyn EthereumAdapter>,
+        manifest: SubgraphManifest,
+        registry: Arc<M>,
+        shutdown: Shutdown,
+    ) -> Result<IndexingContext<B, T, S>, Error>
+    where
+        B: BlockStreamBuilder,
+        S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
+        M: MetricsRegistry,
+        T: RuntimeHostBuilder,
+    {
         let mut templates: Vec<DataSourceTemplate> = vec![];
         for data_source in manifest.data_sources.iter() {
             for template in data_source.templates.iter() {
@@ -368,6 +621,34 @@ impl SubgraphInstanceManager {
 
         let top_level_templates = Arc::new(manifest.templates.clone());
 
+        let mut entity_sources: Vec<EntitySource> = vec![];
+        for data_source in manifest.data_sources.iter() {
+            let deployment = match data_source.source_subgraph() {
+                Some(deployment) => deployment,
+                None => continue,
+            };
+            let entity_types = data_source
+                .mapping
+                .entity_handlers
+                .iter()
+                .map(|handler| handler.entity.clone());
+            let start_block = data_source.source.start_block;
+            match entity_sources
+                .iter_mut()
+                .find(|source| &source.deployment == deployment)
+            {
+                Some(source) => {
+                    source.entity_types.extend(entity_types);
+                    source.start_block = source.start_block.min(start_block);
+                }
+                None => entity_sources.push(EntitySource {
+                    deployment: deployment.clone(),
+                    entity_types: entity_types.collect(),
+                    start_block,
+                }),
+            }
+        }
+
         // Create a subgraph instance from the manifest; this moves
         // ownership of the manifest and host builder into the new instance
         let stopwatch_metrics =
@@ -376,7 +657,6 @@ impl SubgraphInstanceManager {
             registry.clone(),
             deployment_id.clone().to_string(),
         ));
-        let subgraph_metrics_unregister = subgraph_metrics.clone();
         let host_metrics = Arc::new(HostMetrics::new(
             registry.clone(),
             deployment_id.clone().to_string(),
@@ -394,9 +674,12 @@ impl SubgraphInstanceManager {
         ));
         let instance =
             SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
+        subgraph_metrics
+            .data_source_count
+            .set(instance.data_source_count() as f64);
 
         // The subgraph state tracks the state of the subgraph instance over time
-        let ctx = IndexingContext {
+        Ok(IndexingContext {
             inputs: IndexingInputs {
                 deployment_id: deployment_id.clone(),
                 network_name,
@@ -406,6 +689,8 @@ impl SubgraphInstanceManager {
                 stream_builder,
                 templates_use_calls,
                 top_level_templates,
+                entity_sources,
+                shutdown,
             },
             state: IndexingState {
                 logger,
@@ -416,33 +701,20 @@ impl SubgraphInstanceManager {
                 block_filter,
                 restarts: 0,
                 entity_lfu_cache: LfuCache::new(),
+                entity_cache_usage: MemoryUsage::new(Subsystem::EntityCache),
             },
             subgraph_metrics,
             host_metrics,
             ethrpc_metrics,
             block_stream_metrics,
-        };
-
-        // Keep restarting the subgraph until it terminates. The subgraph
-        // will usually only run once, but is restarted whenever a block
-        // creates dynamic data sources. This allows us to recreate the
-        // block stream and include events for the new data sources going
-        // forward; this is easier than updating the existing block stream.
-        //
-        // This task has many calls to the store, so mark it as `blocking`.
-        graph::spawn_blocking(async move {
-            let res = run_subgraph(ctx).await;
-            subgraph_metrics_unregister.unregister(registry);
-            res
-        });
-
-        Ok(())
+        })
     }
 
     fn stop_subgraph(instances: SharedInstanceKeepAliveMap, id: SubgraphDeploymentId) {
         // Drop the cancel guard to shut down the subgraph now
         let mut instances = instances.write().unwrap();
         instances.remove(&id);
+        write_audit::forget(&id);
     }
 }
 
@@ -469,6 +741,7 @@ where
     let store_for_err = ctx.inputs.store.cheap_clone();
     let logger = ctx.state.logger.cheap_clone();
     let id_for_err = ctx.inputs.deployment_id.clone();
+    let mut last_sync_sample: Option<Instant> = None;
 
     loop {
         debug!(logger, "Starting or restarting subgraph");
@@ -503,13 +776,25 @@ where
 
         debug!(logger, "Starting block stream");
 
+        let mut last_maintenance_check: Option<Instant> = None;
+
         // Process events from the stream as long as no restart is needed
         loop {
+            // Do not write anything while the deployment is in maintenance;
+            // queries keep being answered from the blocks processed so far
+            if last_maintenance_check.map_or(true, |t| t.elapsed() >= MAINTENANCE_CHECK_INTERVAL) {
+                if !wait_for_maintenance(&logger, &ctx.inputs, &block_stream_cancel_handle).await {
+                    return Err(());
+                }
+                last_maintenance_check = Some(Instant::now());
+            }
+
             let block = match block_stream.next().await {
                 Some(Ok(BlockStreamEvent::Block(block))) => block,
                 Some(Ok(BlockStreamEvent::Revert)) => {
                     // On revert, clear the entity cache.
                     ctx.state.entity_lfu_cache = LfuCache::new();
+                    ctx.state.entity_cache_usage.set(0);
                     continue;
                 }
                 // Log and drop the errors from the block_stream
@@ -525,6 +810,19 @@ where
                 None => unreachable!("The block stream stopped producing blocks"),
             };
 
+            // Once the node is shutting down, stop before starting on a new
+            // block; blocks that are already being processed get finished
+            let _work = match ctx.inputs.shutdown.start_work() {
+                Some(work) => work,
+                None => {
+                    info!(
+                        &logger,
+                        "Stopping subgraph because the node is shutting down"
+                    );
+                    return Err(());
+                }
+            };
+
             let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
 
             if block.triggers.len() > 0 {
@@ -546,10 +844,22 @@ where
 
             let elapsed = start.elapsed().as_secs_f64();
             subgraph_metrics.block_processing_duration.observe(elapsed);
+            subgraph_metrics.observe_block_processed();
 
             match res {
                 Ok((c, needs_restart)) => {
                     ctx = c;
+
+                    if last_sync_sample.map_or(true, |t| t.elapsed() >= SYNC_SAMPLE_INTERVAL) {
+                        if let Err(e) = ctx
+                            .inputs
+                            .store
+                            .record_sync_sample(&ctx.inputs.deployment_id, block_ptr.number)
+                        {
+                            warn!(logger, "Failed to record sync sample"; "error" => e.to_string());
+                        }
+                        last_sync_sample = Some(Instant::now());
+                    }
                     if needs_restart {
                         // Increase the restart counter
                         ctx.state.restarts += 1;
@@ -582,11 +892,27 @@ where
                         "code" => LogCode::SubgraphSyncingFailure
                     );
 
+                    let failure = e.downcast_ref::<HandlerFailure>();
+                    let handler = failure.map(|failure| failure.handler.clone());
+                    error_reporting::report(
+                        ErrorReport::deployment_failed(&id_for_err, e.to_string())
+                            .with_block(Some(block_ptr))
+                            .with_handler(handler.clone()),
+                    );
+
                     let error = SubgraphError {
                         subgraph_id: id_for_err.clone(),
                         message: e.to_string(),
                         block_ptr: Some(block_ptr),
-                        handler: None,
+                        handler,
+                        data_source: failure.map(|failure| failure.data_source.clone()),
+                        deterministic: failure.map_or(false, |failure| failure.deterministic),
+                        proof_of_indexing: last_proof_of_indexing(
+                            &logger,
+                            &*store_for_err,
+                            &id_for_err,
+                        )
+                        .await,
                     };
 
                     // Set subgraph status to Failed
@@ -606,6 +932,77 @@ where
     }
 }
 
+/// The proof of indexing of `deployment` for the last block that it
+/// processed, if there is one
+async fn last_proof_of_indexing(
+    logger: &Logger,
+    store: &impl Store,
+    deployment: &SubgraphDeploymentId,
+) -> Option<H256> {
+    let result = match store.block_ptr(deployment.clone()) {
+        Ok(Some(block_ptr)) => {
+            store
+                .get_proof_of_indexing(deployment, &None, block_ptr.hash)
+                .await
+        }
+        Ok(None) => Ok(None),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(proof_of_indexing) => proof_of_indexing.map(H256::from),
+        Err(e) => {
+            warn!(logger, "Failed to look up the proof of indexing of a failed subgraph";
+                  "error" => e.to_string());
+            None
+        }
+    }
+}
+
+/// Wait until the deployment is not in maintenance. Returns `false` if the
+/// subgraph should stop instead because the node is shutting down or the
+/// deployment was unassigned in the meantime
+async fn wait_for_maintenance<B, S>(
+    logger: &Logger,
+    inputs: &IndexingInputs<B, S>,
+    cancel_handle: &CancelHandle,
+) -> bool
+where
+    S: Store,
+{
+    let mut paused = false;
+    loop {
+        match inputs
+            .store
+            .is_deployment_in_maintenance(inputs.deployment_id.clone())
+        {
+            Ok(false) => break,
+            Ok(true) => {
+                if !paused {
+                    info!(
+                        logger,
+                        "Pausing indexing while the deployment is in maintenance"
+                    );
+                    paused = true;
+                }
+            }
+            Err(e) => {
+                // Keep indexing rather than stall because of a failed check
+                warn!(logger, "Failed to check whether the deployment is in maintenance";
+                      "error" => e.to_string());
+                break;
+            }
+        }
+        if inputs.shutdown.is_triggered() || cancel_handle.is_canceled() {
+            return false;
+        }
+        tokio::time::delay_for(MAINTENANCE_CHECK_INTERVAL).await;
+    }
+    if paused {
+        info!(logger, "Resuming indexing after maintenance");
+    }
+    true
+}
+
 /// Processes a block and returns the updated context and a boolean flag indicating
 /// whether new dynamic data sources have been added to the subgraph.
 async fn process_block<B: BlockStreamBuilder, T: RuntimeHostBuilder, S>(
@@ -627,6 +1024,13 @@ where
         "block_hash" => format!("{:?}", block_ptr.hash)
     ));
 
+    // The handlers and the final transaction of this block are children
+    // of its span
+    let mut span = Span::root("subgraph.block", SpanKind::Internal, None);
+    span.set_attribute("deployment", &ctx.inputs.deployment_id);
+    span.set_attribute("block_number", block_ptr.number);
+    span.set_attribute("triggers", triggers.len());
+
     if triggers.len() == 1 {
         info!(&logger, "1 trigger found in this block for this subgraph");
     } else if triggers.len() > 1 {
@@ -659,18 +1063,30 @@ where
 
     // Process events one after the other, passing in entity operations
     // collected previously to every new event being processed
-    let (mut ctx, mut block_state) = process_triggers(
+    let mut block_state = BlockState::new(
+        ctx.inputs.store.clone(),
+        std::mem::take(&mut ctx.state.entity_lfu_cache),
+    );
+    block_state.trace = span.context();
+    block_state.data_source_count = ctx.state.instance.data_source_count();
+    let (mut ctx, block_state) = process_triggers(
         &logger,
-        BlockState::new(
-            ctx.inputs.store.clone(),
-            std::mem::take(&mut ctx.state.entity_lfu_cache),
-        ),
+        block_state,
         proof_of_indexing.cheap_clone(),
         ctx,
         &light_block,
         triggers,
     )
     .await?;
+    let mut block_state = process_entity_changes(
+        &logger,
+        block_state,
+        proof_of_indexing.cheap_clone(),
+        &ctx,
+        &light_block,
+        &block_stream_cancel_handle,
+    )
+    .await?;
 
     // If new data sources have been created, restart the subgraph after this block.
     // This is necessary to re-create the block stream.
@@ -749,6 +1165,13 @@ where
         }
     }
 
+    ctx.subgraph_metrics
+        .data_sources_created
+        .inc_by(block_state.data_sources_created as f64);
+    ctx.subgraph_metrics
+        .data_source_count
+        .set(ctx.state.instance.data_source_count() as f64);
+
     // Apply entity operations and advance the stream
 
     // Avoid writing to store if block stream has been canceled
@@ -767,6 +1190,8 @@ where
         .await?;
     }
 
+    let audit = std::mem::take(&mut block_state.write_audit);
+
     let section = ctx.host_metrics.stopwatch.start_section("as_modifications");
     let ModificationsAndCache {
         modifications: mods,
@@ -786,7 +1211,14 @@ where
         .host_metrics
         .stopwatch
         .start_section("entity_cache_evict");
-    cache.evict(*ENTITY_CACHE_SIZE);
+    // Under memory pressure, keep only a fraction of the usual cache
+    let max_weight = if memory::under_pressure() {
+        *ENTITY_CACHE_SIZE / 4
+    } else {
+        *ENTITY_CACHE_SIZE
+    };
+    cache.evict(max_weight);
+    ctx.state.entity_cache_usage.set(cache.weight());
     section.end();
 
     // Put the cache back in the ctx, asserting that the placeholder cache was not used.
@@ -803,6 +1235,7 @@ where
     let subgraph_id = ctx.inputs.deployment_id.clone();
     let stopwatch = ctx.host_metrics.stopwatch.clone();
     let start = Instant::now();
+    let _entered = span.enter();
 
     match ctx
         .inputs
@@ -812,6 +1245,7 @@ where
         Ok(should_migrate) => {
             let elapsed = start.elapsed().as_secs_f64();
             metrics.block_ops_transaction_duration.observe(elapsed);
+            write_audit::record(&ctx.inputs.deployment_id, block_ptr_after, audit);
             if should_migrate {
                 ctx.inputs.store.migrate_subgraph_deployment(
                     &logger,
@@ -822,6 +1256,7 @@ where
             Ok((ctx, needs_restart))
         }
         Err(e) => {
+            span.set_error(&e);
             Err(format_err!("Error while processing block stream for a subgraph: {}", e).into())
         }
     }
@@ -907,14 +1342,17 @@ async fn process_triggers<B: BlockStreamBuilder, T: RuntimeHostBuilder, S: Send
                 proof_of_indexing.cheap_clone(),
             )
             .await
-            .map_err(move |e| match transaction_id {
-                Some(tx_hash) => format_err!(
-                    "Failed to process trigger in block {}, transaction {:x}: {:#}",
-                    block_ptr,
-                    tx_hash,
-                    e
-                ),
-                None => format_err!("Failed to process trigger: {:#}", e),
+            .map_err(move |e| {
+                let error = match transaction_id {
+                    Some(tx_hash) => format_err!(
+                        "Failed to process trigger in block {}, transaction {:x}: {:#}",
+                        block_ptr,
+                        tx_hash,
+                        e
+                    ),
+                    None => format_err!("Failed to process trigger: {:#}", e),
+                };
+                handler_failure(&e, error)
             })?;
         let elapsed = start.elapsed().as_secs_f64();
         subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger_type);
@@ -922,6 +1360,170 @@ async fn process_triggers<B: BlockStreamBuilder, T: RuntimeHostBuilder, S: Send
     Ok((ctx, block_state))
 }
 
+/// Process the changes that the source deployments of data sources of kind
+/// `subgraph` made to their entities in `block`, after waiting for each
+/// source to process `block`
+async fn process_entity_changes<B, T: RuntimeHostBuilder, S>(
+    logger: &Logger,
+    mut block_state: BlockState,
+    proof_of_indexing: SharedProofOfIndexing,
+    ctx: &IndexingContext<B, T, S>,
+    block: &Arc<LightEthereumBlock>,
+    cancel_handle: &CancelHandle,
+) -> Result<BlockState, CancelableError<Error>>
+where
+    S: ChainStore + Store,
+{
+    let block_ptr = EthereumBlockPointer::from(block.as_ref());
+    let block_number = block_ptr.number;
+    for source in ctx.inputs.entity_sources.iter() {
+        if block_number < source.start_block {
+            continue;
+        }
+        if !wait_for_source(logger, &ctx.inputs, cancel_handle, source, block_ptr).await? {
+            return Err(CancelableError::Cancel);
+        }
+
+        let changes = ctx
+            .inputs
+            .store
+            .changes_in_block(
+                &source.deployment,
+                &source.entity_types,
+                block_number as BlockNumber,
+            )
+            .map_err(|e| {
+                format_err!(
+                    "Failed to load the entity changes of `{}` in block {}: {}",
+                    source.deployment,
+                    block_number,
+                    e
+                )
+            })?;
+        for change in changes.iter() {
+            block_state = ctx
+                .state
+                .instance
+                .process_entity_change(
+                    logger,
+                    block,
+                    &source.deployment,
+                    change,
+                    block_state,
+                    proof_of_indexing.cheap_clone(),
+                )
+                .await
+                .map_err(|e| {
+                    let error = format_err!(
+                        "Failed to process the entity changes of `{}` in block {}: {:#}",
+                        source.deployment,
+                        block_number,
+                        e
+                    );
+                    handler_failure(&e, error)
+                })?;
+        }
+    }
+    Ok(block_state)
+}
+
+/// Wait until the deployment `source` has processed `block`. Returns
+/// `false` if the subgraph should stop instead because the node is
+/// shutting down or the deployment was unassigned in the meantime. Fails
+/// if the source does not exist, has failed, or has moved past `block` on
+/// a different chain, since the subgraph could then wait forever
+async fn wait_for_source<B, S>(
+    logger: &Logger,
+    inputs: &IndexingInputs<B, S>,
+    cancel_handle: &CancelHandle,
+    source: &EntitySource,
+    block: EthereumBlockPointer,
+) -> Result<bool, Error>
+where
+    S: ChainStore + Store,
+{
+    let mut waiting = false;
+    loop {
+        let deployment = inputs
+            .store
+            .get(SubgraphDeploymentEntity::key(source.deployment.clone()))?
+            .ok_or_else(|| {
+                format_err!(
+                    "The source deployment `{}` does not exist",
+                    source.deployment
+                )
+            })?;
+        if let Some(Value::Bool(true)) = deployment.get("failed") {
+            return Err(format_err!(
+                "The source deployment `{}` has failed",
+                source.deployment
+            ));
+        }
+
+        if let Some(source_ptr) = inputs.store.block_ptr(source.deployment.clone())? {
+            if source_ptr.number == block.number && source_ptr.hash == block.hash {
+                return Ok(true);
+            }
+            // A source at the same block number, but on another block, may
+            // still revert to the chain of `block`
+            if source_ptr.number > block.number {
+                if processed_on_chain(&*inputs.store, source_ptr, block)? {
+                    return Ok(true);
+                }
+                return Err(format_err!(
+                    "The source deployment `{}` is at block {} on a chain without block {}",
+                    source.deployment,
+                    source_ptr,
+                    block
+                ));
+            }
+        }
+        if !waiting {
+            info!(logger, "Waiting for the source deployment to process the block";
+                  "source" => source.deployment.as_str());
+            waiting = true;
+        }
+        if inputs.shutdown.is_triggered() || cancel_handle.is_canceled() {
+            return Ok(false);
+        }
+        tokio::time::delay_for(SOURCE_POLL_INTERVAL).await;
+    }
+}
+
+/// Whether `block` is an ancestor of `source_ptr`, which has to be a later
+/// block. Blocks that the chain store no longer has are old enough to be
+/// final, and count as ancestors
+fn processed_on_chain<S: ChainStore>(
+    store: &S,
+    source_ptr: EthereumBlockPointer,
+    block: EthereumBlockPointer,
+) -> Result<bool, Error> {
+    // Only walking the ancestors of `source_ptr` is expensive, and it is
+    // only needed if there is more than one block with that number
+    let hashes = store.block_hashes_by_block_number(block.number)?;
+    if hashes.iter().all(|hash| hash == &block.hash) {
+        return Ok(true);
+    }
+    let ancestor = store.ancestor_block(source_ptr, source_ptr.number - block.number)?;
+    Ok(ancestor.map_or(true, |ancestor| ancestor.block.hash == Some(block.hash)))
+}
+
+/// Turn `error`, which was caused by `e`, into a `HandlerFailure` if `e`
+/// comes from a failed handler, so that the failure of the subgraph
+/// records which handler failed
+fn handler_failure(e: &anyhow::Error, error: Error) -> Error {
+    match e.downcast_ref::<MappingError>() {
+        Some(mapping_error) => HandlerFailure {
+            data_source: mapping_error.data_source().to_owned(),
+            handler: mapping_error.handler().to_owned(),
+            deterministic: mapping_error.is_deterministic(),
+            error,
+        }
+        .into(),
+        None => error,
+    }
+}
+
 fn create_dynamic_data_sources<B, T: RuntimeHostBuilder, S>(
     logger: Logger,
     ctx: &mut IndexingContext<B, T, S>,
@@ -3,6 +3,7 @@ mod instance_manager;
 mod loader;
 mod provider;
 mod registrar;
+pub mod replay;
 
 pub use self::instance::SubgraphInstance;
 pub use self::instance_manager::SubgraphInstanceManager;
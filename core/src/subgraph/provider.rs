@@ -6,6 +6,7 @@ use async_trait::async_trait;
 use futures01::sync::mpsc::{channel, Receiver, Sender};
 
 use graph::data::subgraph::schema::{attribute_index_definitions, SubgraphError};
+use graph::log::error_reporting::{self, ErrorReport};
 use graph::prelude::{
     DataSourceLoader as _, GraphQlRunner,
     SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait, *,
@@ -168,11 +169,16 @@ where
                 "error" => format!("{}", e)
             );
 
+            error_reporting::report(ErrorReport::deployment_failed(&subgraph_id, e.to_string()));
+
             let error = SubgraphError {
                 subgraph_id: subgraph_id.clone(),
                 message: e.to_string(),
                 block_ptr: None,
                 handler: None,
+                data_source: None,
+                deterministic: false,
+                proof_of_indexing: None,
             };
 
             let _ignore_error = store.apply_metadata_operations(
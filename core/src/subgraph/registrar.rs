@@ -34,6 +34,8 @@ pub struct SubgraphRegistrar<L, P, S, CS> {
     ethereum_adapters: HashMap<String, Arc<dyn EthereumAdapter>>,
     node_id: NodeId,
     version_switching_mode: SubgraphVersionSwitchingMode,
+    placer: Option<Arc<dyn DeploymentPlacer>>,
+    deployment_files: Option<Arc<dyn DeploymentFiles>>,
     assignment_event_stream_cancel_guard: CancelGuard, // cancels on drop
 }
 
@@ -73,10 +75,68 @@ where
             ethereum_adapters,
             node_id,
             version_switching_mode,
+            placer: None,
+            deployment_files: None,
             assignment_event_stream_cancel_guard: CancelGuard::new(),
         }
     }
 
+    /// Use `placer` to decide which node new deployments are assigned to
+    /// when the deploy request does not name a node
+    pub fn with_placer(self, placer: Arc<dyn DeploymentPlacer>) -> Self {
+        SubgraphRegistrar {
+            placer: Some(placer),
+            ..self
+        }
+    }
+
+    /// Allow deploying subgraphs from local directories and HTTP URLs by
+    /// copying their files into `files`
+    pub fn with_deployment_files(self, files: Arc<dyn DeploymentFiles>) -> Self {
+        SubgraphRegistrar {
+            deployment_files: Some(files),
+            ..self
+        }
+    }
+
+    /// Find the node that a new deployment of `name` on `network` should
+    /// be assigned to. If the placement rules allow several nodes, pick
+    /// the one with the fewest assignments
+    fn place(&self, name: &SubgraphName, network: &str) -> Result<NodeId, SubgraphRegistrarError> {
+        let nodes = match &self.placer {
+            Some(placer) => placer
+                .place(name.as_str(), network)
+                .map_err(SubgraphRegistrarError::PlacementError)?,
+            None => None,
+        };
+        let nodes = match nodes {
+            Some(nodes) if nodes.len() > 1 => nodes,
+            Some(mut nodes) if nodes.len() == 1 => return Ok(nodes.pop().unwrap()),
+            _ => return Ok(self.node_id.clone()),
+        };
+
+        let assignments = self
+            .store
+            .find(
+                SubgraphDeploymentAssignmentEntity::query().filter(EntityFilter::In(
+                    "nodeId".to_owned(),
+                    nodes.iter().map(|node| node.to_string().into()).collect(),
+                )),
+            )?;
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for assignment in assignments {
+            if let Some(Value::String(node)) = assignment.get("nodeId") {
+                *counts.entry(node.clone()).or_insert(0) += 1;
+            }
+        }
+        // `min_by_key` returns the first of several minimal elements, so
+        // ties go to the node listed first in the rule
+        Ok(nodes
+            .into_iter()
+            .min_by_key(|node| counts.get(node.as_str()).cloned().unwrap_or(0))
+            .unwrap())
+    }
+
     pub fn start(&self) -> impl Future<Item = (), Error = Error> {
         let logger_clone1 = self.logger.clone();
         let logger_clone2 = self.logger.clone();
@@ -297,8 +357,16 @@ where
         &self,
         name: SubgraphName,
         hash: SubgraphDeploymentId,
-        node_id: NodeId,
-    ) -> Result<(), SubgraphRegistrarError> {
+        node_id: Option<NodeId>,
+        version_label: Option<String>,
+        ignore_graft_depth: bool,
+    ) -> Result<Vec<SubgraphManifestValidationWarning>, SubgraphRegistrarError> {
+        if let Some(label) = &version_label {
+            if !SubgraphVersionSelector::is_valid_label(label) {
+                return Err(SubgraphRegistrarError::InvalidVersionLabel(label.clone()));
+            }
+        }
+
         let logger = self.logger_factory.subgraph_logger(&hash);
 
         let unvalidated = UnvalidatedSubgraphManifest::resolve(
@@ -310,7 +378,7 @@ where
         .await?;
 
         let (manifest, validation_warnings) = unvalidated
-            .validate(self.store.clone())
+            .validate(self.store.clone(), ignore_graft_depth)
             .map_err(SubgraphRegistrarError::ManifestValidationError)?;
 
         let network_name = manifest.network_name();
@@ -322,6 +390,11 @@ where
             SubgraphRegistrarError::NetworkNotSupported(network_name.clone()),
         )?;
 
+        let node_id = match node_id {
+            Some(node_id) => node_id,
+            None => self.place(&name, &network_name)?,
+        };
+
         let manifest_id = manifest.id.clone();
         create_subgraph_version(
             &logger,
@@ -331,6 +404,7 @@ where
             name.clone(),
             manifest,
             node_id,
+            version_label,
             self.version_switching_mode,
         )
         .compat()
@@ -344,7 +418,21 @@ where
             "validation_warnings" => format!("{:?}", validation_warnings),
         );
 
-        Ok(())
+        Ok(validation_warnings)
+    }
+
+    async fn import_subgraph(
+        &self,
+        location: &str,
+    ) -> Result<SubgraphDeploymentId, SubgraphRegistrarError> {
+        let files = self.deployment_files.as_ref().ok_or_else(|| {
+            SubgraphRegistrarError::ImportError(format_err!(
+                "this node can only deploy subgraphs from IPFS"
+            ))
+        })?;
+        import_subgraph(&self.logger, files.as_ref(), location)
+            .await
+            .map_err(SubgraphRegistrarError::ImportError)
     }
 
     async fn remove_subgraph(&self, name: SubgraphName) -> Result<(), SubgraphRegistrarError> {
@@ -358,6 +446,65 @@ where
     ) -> Result<(), SubgraphRegistrarError> {
         reassign_subgraph(self.store.clone(), hash, node_id)
     }
+
+    async fn set_maintenance(
+        &self,
+        hash: SubgraphDeploymentId,
+        maintenance: bool,
+    ) -> Result<(), SubgraphRegistrarError> {
+        if !self.store.is_deployed(&hash)? {
+            return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+        }
+        if maintenance {
+            warn!(self.logger, "Pausing indexing for maintenance";
+                  "subgraph_id" => hash.to_string());
+        } else {
+            info!(self.logger, "Resuming indexing after maintenance";
+                  "subgraph_id" => hash.to_string());
+        }
+        self.store
+            .apply_metadata_operations(SubgraphDeploymentEntity::maintenance_operations(
+                &hash,
+                maintenance,
+            ))?;
+        Ok(())
+    }
+
+    async fn set_publication(
+        &self,
+        hash: SubgraphDeploymentId,
+        publish: bool,
+        replica_identity: Option<ReplicaIdentity>,
+    ) -> Result<Option<String>, SubgraphRegistrarError> {
+        if !self.store.is_deployed(&hash)? {
+            return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+        }
+        let publication = self
+            .store
+            .set_publication(&hash, publish, replica_identity)?;
+        info!(self.logger, "Changed publication of subgraph";
+              "subgraph_id" => hash.to_string(),
+              "publication" => publication.as_deref().unwrap_or(""),
+              "replica_identity" => replica_identity
+                  .map(|identity| identity.as_sql())
+                  .unwrap_or(""));
+        Ok(publication)
+    }
+
+    async fn rewind_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        block_ptr: EthereumBlockPointer,
+    ) -> Result<(), SubgraphRegistrarError> {
+        if !self.store.is_deployed(&hash)? {
+            return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+        }
+        warn!(self.logger, "Rewinding subgraph";
+              "subgraph_id" => hash.to_string(),
+              "block" => block_ptr.to_string());
+        self.store.rewind(&hash, block_ptr)?;
+        Ok(())
+    }
 }
 
 async fn handle_assignment_event(
@@ -731,6 +878,7 @@ fn create_subgraph_version(
     name: SubgraphName,
     manifest: SubgraphManifest,
     node_id: NodeId,
+    version_label: Option<String>,
     version_switching_mode: SubgraphVersionSwitchingMode,
 ) -> Box<dyn Future<Item = (), Error = SubgraphRegistrarError> + Send> {
     let logger = logger.clone();
@@ -778,6 +926,7 @@ fn create_subgraph_version(
                     subgraph_version_data.subgraph_entity_id.clone(),
                     manifest_id.clone(),
                     created_at,
+                    version_label,
                 )
                 .write_operations(&subgraph_version_data.version_entity_id)
                 .into_iter()
@@ -0,0 +1,471 @@
+//! Replaying blocks that a deployment has already processed, for
+//! debugging. The handlers of the deployment run again for a range of
+//! blocks and write into a scratch deployment, but only get to use inputs
+//! that were cached when the blocks were first processed: blocks and their
+//! receipts from the block cache, the results of `ethereum.call` from the
+//! call cache and files from the IPFS cache. Any input that is not cached
+//! makes the replay fail. Comparing the entity changes that each block made
+//! in the scratch deployment with those of the original deployment shows
+//! where handlers are not deterministic.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use graph::prelude::ethabi::Token;
+use graph::prelude::web3::types::{Block, Log, H256};
+use graph::prelude::*;
+
+/// An `EthereumAdapter` that answers requests from the block and call
+/// caches and never talks to an Ethereum node. Requests that the caches
+/// can not answer, like those for call traces, fail
+pub struct CachedEthereumAdapter {
+    chain_store: Arc<dyn ChainStore>,
+}
+
+impl CachedEthereumAdapter {
+    pub fn new(chain_store: Arc<dyn ChainStore>) -> Self {
+        CachedEthereumAdapter { chain_store }
+    }
+
+    fn cached_block(&self, hash: H256) -> Result<Option<LightEthereumBlock>, Error> {
+        Ok(self.chain_store.blocks(vec![hash])?.pop())
+    }
+
+    fn cached_block_by_number(&self, number: u64) -> Result<Option<LightEthereumBlock>, Error> {
+        let hashes = self.chain_store.block_hashes_by_block_number(number)?;
+        match hashes.as_slice() {
+            [] => Ok(None),
+            [hash] => self.cached_block(*hash),
+            _ => Err(format_err!(
+                "the block cache has {} blocks with number {}",
+                hashes.len(),
+                number
+            )),
+        }
+    }
+}
+
+fn not_cached(what: &str) -> Error {
+    format_err!(
+        "{} can not be read from the cache when replaying blocks",
+        what
+    )
+}
+
+impl EthereumAdapter for CachedEthereumAdapter {
+    fn net_identifiers(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = EthereumNetworkIdentifier, Error = Error> + Send> {
+        Box::new(future::err(not_cached("the network identifier")))
+    }
+
+    fn latest_block(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = EthereumAdapterError> + Send + Unpin>
+    {
+        Box::new(future::err(not_cached("the latest block").into()))
+    }
+
+    fn latest_block_header(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = Block<H256>, Error = EthereumAdapterError> + Send> {
+        Box::new(future::err(not_cached("the latest block").into()))
+    }
+
+    fn load_block(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = Error> + Send> {
+        Box::new(future::result(self.cached_block(block_hash).and_then(
+            |block| block.ok_or_else(|| format_err!("block {:?} is not cached", block_hash)),
+        )))
+    }
+
+    fn load_blocks(
+        &self,
+        _: Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_hashes: HashSet<H256>,
+    ) -> Box<dyn Stream<Item = LightEthereumBlock, Error = Error> + Send> {
+        let blocks = chain_store
+            .blocks(block_hashes.iter().cloned().collect())
+            .and_then(|blocks| {
+                if blocks.len() < block_hashes.len() {
+                    Err(format_err!(
+                        "{} of {} blocks are not cached",
+                        block_hashes.len() - blocks.len(),
+                        block_hashes.len()
+                    ))
+                } else {
+                    Ok(blocks)
+                }
+            });
+        Box::new(
+            future::result(blocks)
+                .map(stream::iter_ok::<_, Error>)
+                .flatten_stream(),
+        )
+    }
+
+    fn block_range_to_ptrs(
+        &self,
+        _: Logger,
+        _: u64,
+        _: u64,
+    ) -> Box<dyn Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        Box::new(future::err(not_cached("ranges of blocks")))
+    }
+
+    fn block_by_hash(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        Box::new(future::result(self.cached_block(block_hash)))
+    }
+
+    fn block_by_number(
+        &self,
+        _: &Logger,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        Box::new(future::result(self.cached_block_by_number(block_number)))
+    }
+
+    fn load_full_block(
+        &self,
+        _: &Logger,
+        block: LightEthereumBlock,
+    ) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+        let ptr = EthereumBlockPointer::from(&block);
+        let full_block = self
+            .chain_store
+            .ancestor_block(ptr, 0)
+            .and_then(|full_block| {
+                full_block
+                    .filter(has_receipts)
+                    .ok_or_else(|| format_err!("the receipts of block {} are not cached", ptr))
+            });
+        Box::new(future::result(
+            full_block.map_err(EthereumAdapterError::from),
+        ))
+    }
+
+    fn block_pointer_from_number(
+        &self,
+        _: &Logger,
+        _: Arc<dyn ChainStore>,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = EthereumBlockPointer, Error = EthereumAdapterError> + Send> {
+        let ptr = self.cached_block_by_number(block_number).and_then(|block| {
+            block
+                .map(|block| EthereumBlockPointer::from(&block))
+                .ok_or_else(|| format_err!("block {} is not cached", block_number))
+        });
+        Box::new(future::result(ptr.map_err(EthereumAdapterError::from)))
+    }
+
+    fn block_hash_by_block_number(
+        &self,
+        _: &Logger,
+        _: Arc<dyn ChainStore>,
+        block_number: u64,
+        _: bool,
+    ) -> Box<dyn Future<Item = Option<H256>, Error = Error> + Send> {
+        let hash = self
+            .cached_block_by_number(block_number)
+            .map(|block| block.and_then(|block| block.hash));
+        Box::new(future::result(hash))
+    }
+
+    fn uncles(
+        &self,
+        _: &Logger,
+        _: &LightEthereumBlock,
+    ) -> Box<dyn Future<Item = Vec<Option<Block<H256>>>, Error = Error> + Send> {
+        Box::new(future::err(not_cached("uncles")))
+    }
+
+    fn is_on_main_chain(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        _: Arc<dyn ChainStore>,
+        _: EthereumBlockPointer,
+    ) -> Box<dyn Future<Item = bool, Error = Error> + Send> {
+        Box::new(future::err(not_cached("whether a block is final")))
+    }
+
+    fn calls_in_block(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        _: u64,
+        _: H256,
+    ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        Box::new(future::err(not_cached("call traces")))
+    }
+
+    fn logs_in_block_range(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        _: u64,
+        _: u64,
+        _: EthereumLogFilter,
+    ) -> DynTryFuture<'static, Vec<Log>, Error> {
+        Box::pin(futures03::future::err(not_cached(
+            "logs outside of cached receipts",
+        )))
+    }
+
+    fn calls_in_block_range(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        _: u64,
+        _: u64,
+        _: EthereumCallFilter,
+    ) -> Box<dyn Stream<Item = EthereumCall, Error = Error> + Send> {
+        Box::new(stream::once(Err(not_cached("call traces"))))
+    }
+
+    fn contract_call(
+        &self,
+        logger: &Logger,
+        call: EthereumContractCall,
+        cache: Arc<dyn EthereumCallCache>,
+    ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
+        for (token, kind) in call
+            .args
+            .iter()
+            .zip(call.function.inputs.iter().map(|p| &p.kind))
+        {
+            if !token.type_check(kind) {
+                return Box::new(future::err(EthereumContractCallError::TypeError(
+                    token.clone(),
+                    kind.clone(),
+                )));
+            }
+        }
+
+        let call_data = call.function.encode_input(&call.args).unwrap();
+        let output = match cache.get_call(call.address, &call_data, call.block_ptr) {
+            Ok(Some(output)) => output,
+            Ok(None) => {
+                return Box::new(future::err(EthereumContractCallError::NotCached(
+                    call.address,
+                    call.block_ptr.number,
+                )))
+            }
+            Err(e) => {
+                error!(logger, "call cache get error"; "error" => e.to_string());
+                return Box::new(future::err(EthereumContractCallError::NotCached(
+                    call.address,
+                    call.block_ptr.number,
+                )));
+            }
+        };
+
+        // Decode the output the same way as for calls to an Ethereum node
+        Box::new(future::result(if output.is_empty() {
+            Err(EthereumContractCallError::Revert("empty response".into()))
+        } else {
+            call.function.decode_output(&output).map_err(|e| {
+                EthereumContractCallError::Revert(format!("failed to decode output: {}", e))
+            })
+        }))
+    }
+}
+
+/// Whether the block cache has the receipts of all transactions in
+/// `block`; blocks that were only needed for their header are cached
+/// without receipts
+fn has_receipts(block: &EthereumBlock) -> bool {
+    block.transaction_receipts.len() == block.block.transactions.len()
+}
+
+/// The blocks `from..=to` of the chain that ends in `head`, together with
+/// their receipts, from the block cache
+pub fn cached_blocks(
+    chain_store: &dyn ChainStore,
+    head: EthereumBlockPointer,
+    from: u64,
+    to: u64,
+) -> Result<Vec<EthereumBlock>, Error> {
+    if from > to || to > head.number {
+        return Err(format_err!(
+            "can not replay blocks {} to {} of a deployment that has processed block {}",
+            from,
+            to,
+            head.number
+        ));
+    }
+
+    let missing = |number: u64| format_err!("block {} is not cached", number);
+    let mut blocks = vec![];
+    let mut block = chain_store
+        .ancestor_block(head, head.number - to)?
+        .ok_or_else(|| missing(to))?;
+    loop {
+        let ptr = EthereumBlockPointer::from(&block);
+        if !has_receipts(&block) {
+            return Err(format_err!("the receipts of block {} are not cached", ptr));
+        }
+        blocks.push(block);
+        if ptr.number == from {
+            break;
+        }
+        block = chain_store
+            .ancestor_block(ptr, 1)?
+            .ok_or_else(|| missing(ptr.number - 1))?;
+    }
+    blocks.reverse();
+    Ok(blocks)
+}
+
+/// How the change that a replayed block made to an entity differs from
+/// the change that the block made when it was first processed
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeDiff {
+    /// Only the original run changed the entity
+    Missing(EntityOperation),
+    /// Only the replay changed the entity
+    Extra(EntityOperation),
+    /// Both changed the entity, but not in the same way
+    Different {
+        original: EntityOperation,
+        replayed: EntityOperation,
+    },
+}
+
+impl fmt::Display for ChangeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChangeDiff::Missing(op) => write!(f, "only in the original: {}", describe(op)),
+            ChangeDiff::Extra(op) => write!(f, "only in the replay: {}", describe(op)),
+            ChangeDiff::Different { original, replayed } => write!(
+                f,
+                "different:\n    original: {}\n    replay:   {}",
+                describe(original),
+                describe(replayed)
+            ),
+        }
+    }
+}
+
+fn entity_key(op: &EntityOperation) -> &EntityKey {
+    match op {
+        EntityOperation::Set { key, .. } | EntityOperation::Remove { key } => key,
+    }
+}
+
+fn describe(op: &EntityOperation) -> String {
+    let key = entity_key(op);
+    match op {
+        EntityOperation::Set { data, .. } => {
+            let data: BTreeMap<_, _> = data.iter().collect();
+            format!("set {}[{}] {:?}", key.entity_type, key.entity_id, data)
+        }
+        EntityOperation::Remove { .. } => format!("remove {}[{}]", key.entity_type, key.entity_id),
+    }
+}
+
+/// Compare the changes that a block made to the entities of the original
+/// deployment with those it made to the entities of the scratch deployment.
+/// Changes are matched by entity type and id since the two deployments
+/// have different ids
+pub fn diff_changes(
+    original: Vec<EntityOperation>,
+    replayed: Vec<EntityOperation>,
+) -> Vec<ChangeDiff> {
+    fn by_entity(ops: Vec<EntityOperation>) -> BTreeMap<(String, String), EntityOperation> {
+        ops.into_iter()
+            .map(|op| {
+                let key = entity_key(&op);
+                ((key.entity_type.clone(), key.entity_id.clone()), op)
+            })
+            .collect()
+    }
+
+    let original = by_entity(original);
+    let mut replayed = by_entity(replayed);
+    let mut diffs: BTreeMap<(String, String), ChangeDiff> = BTreeMap::new();
+    for (key, original) in original {
+        let diff = match replayed.remove(&key) {
+            None => ChangeDiff::Missing(original),
+            Some(replayed) => match (&original, &replayed) {
+                (
+                    EntityOperation::Set { data: old, .. },
+                    EntityOperation::Set { data: new, .. },
+                ) if old == new => continue,
+                (EntityOperation::Remove { .. }, EntityOperation::Remove { .. }) => continue,
+                _ => ChangeDiff::Different { original, replayed },
+            },
+        };
+        diffs.insert(key, diff);
+    }
+    for (key, replayed) in replayed {
+        diffs.insert(key, ChangeDiff::Extra(replayed));
+    }
+    diffs.into_iter().map(|(_, diff)| diff).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(subgraph: &str, id: &str) -> EntityKey {
+        EntityKey {
+            subgraph_id: SubgraphDeploymentId::new(subgraph).unwrap(),
+            entity_type: "Thing".to_owned(),
+            entity_id: id.to_owned(),
+        }
+    }
+
+    fn set(subgraph: &str, id: &str, name: &str) -> EntityOperation {
+        EntityOperation::Set {
+            key: key(subgraph, id),
+            data: entity! { id: id, name: name },
+        }
+    }
+
+    fn remove(subgraph: &str, id: &str) -> EntityOperation {
+        EntityOperation::Remove {
+            key: key(subgraph, id),
+        }
+    }
+
+    #[test]
+    fn diffs_changes_by_entity() {
+        let original = vec![
+            set("original", "1", "one"),
+            set("original", "2", "two"),
+            remove("original", "3"),
+            remove("original", "4"),
+        ];
+        let replayed = vec![
+            remove("scratch", "4"),
+            set("scratch", "2", "deux"),
+            set("scratch", "1", "one"),
+            set("scratch", "5", "five"),
+        ];
+
+        assert_eq!(
+            vec![
+                ChangeDiff::Different {
+                    original: set("original", "2", "two"),
+                    replayed: set("scratch", "2", "deux"),
+                },
+                ChangeDiff::Missing(remove("original", "3")),
+                ChangeDiff::Extra(set("scratch", "5", "five")),
+            ],
+            diff_changes(original, replayed)
+        );
+        assert!(diff_changes(vec![], vec![]).is_empty());
+    }
+}
@@ -2,11 +2,12 @@ use ethabi::{Bytes, Error as ABIError, Function, ParamType, Token};
 use failure::SyncFailure;
 use futures::Future;
 use futures03::future::TryFutureExt;
+use lazy_static::lazy_static;
 use mockall::predicate::*;
 use mockall::*;
 use petgraph::graphmap::GraphMap;
 use std::cmp;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::marker::Unpin;
 use tiny_keccak::keccak256;
@@ -15,10 +16,25 @@ use web3::types::*;
 use super::types::*;
 use crate::components::metrics::{CounterVec, GaugeVec, HistogramVec};
 use crate::prelude::*;
+use crate::util::env::env_var;
+
+lazy_static! {
+    /// The largest block range for which we look at the bloom filters of
+    /// cached blocks before asking the Ethereum node for logs; reading the
+    /// blooms of too many blocks is slower than asking for the logs
+    static ref MAX_BLOOM_RANGE: u64 = env_var("GRAPH_ETHEREUM_MAX_BLOOM_RANGE").unwrap_or(1000);
+
+    /// The largest number of contract addresses in a single `eth_getLogs`
+    /// call; filters for more contracts are split into several calls
+    static ref MAX_GET_LOGS_CONTRACTS: usize = env_var("GRAPH_ETHEREUM_GET_LOGS_MAX_CONTRACTS")
+        .unwrap_or(2000)
+        .max(1);
+}
 
 pub type EventSignature = H256;
 
 /// A collection of attributes that (kind of) uniquely identify an Ethereum blockchain.
+#[derive(Clone, Debug, PartialEq)]
 pub struct EthereumNetworkIdentifier {
     pub net_version: String,
     pub genesis_block_hash: H256,
@@ -63,6 +79,10 @@ pub enum EthereumContractCallError {
     Revert(String),
     #[fail(display = "ethereum node took too long to perform call")]
     Timeout,
+    /// The result of the call is not in the call cache, and the adapter
+    /// can only answer calls from the cache
+    #[fail(display = "call to {:?} at block {} is not cached", _0, _1)]
+    NotCached(Address, u64),
 }
 
 impl From<ABIError> for EthereumContractCallError {
@@ -122,11 +142,29 @@ impl fmt::Display for EthGetLogsFilter {
                 self.contracts.len()
             )
         } else {
-            write!(f, "unreachable")
+            write!(
+                f,
+                "{} contracts, {} events",
+                self.contracts.len(),
+                self.event_signatures.len()
+            )
         }
     }
 }
 
+/// Whether the bloom filter `bloom` of a block or receipt might contain
+/// `input`, an address or a topic, following the yellow paper: `input` is
+/// in the bloom if the three bits that the first six bytes of its hash
+/// select are set
+fn bloom_contains(bloom: &H2048, input: &[u8]) -> bool {
+    let hash = keccak256(input);
+    let bloom = bloom.as_bytes();
+    (0..3).all(|i| {
+        let bit = (((hash[2 * i] as usize) << 8) | hash[2 * i + 1] as usize) & 2047;
+        bloom[255 - bit / 8] & (1 << (bit % 8)) != 0
+    })
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct EthereumLogFilter {
     /// Log filters can be represented as a bipartite graph between contracts and events. An edge
@@ -142,9 +180,37 @@ impl EthereumLogFilter {
     /// Check if log bloom filter indicates a possible match for this log filter.
     /// Returns `true` to indicate that a matching `Log` _might_ be contained.
     /// Returns `false` to indicate that a matching `Log` _is not_ contained.
-    pub fn check_bloom(&self, _bloom: H2048) -> bool {
-        // TODO issue #352: implement bloom filter check
-        true // not even wrong
+    pub fn check_bloom(&self, bloom: H2048) -> bool {
+        self.contracts_and_events_graph
+            .all_edges()
+            .any(|edge| match edge {
+                (LogFilterNode::Contract(contract), LogFilterNode::Event(event), ())
+                | (LogFilterNode::Event(event), LogFilterNode::Contract(contract), ()) => {
+                    bloom_contains(&bloom, contract.as_bytes())
+                        && bloom_contains(&bloom, event.as_bytes())
+                }
+                _ => false,
+            })
+            || self
+                .wildcard_events
+                .iter()
+                .any(|event| bloom_contains(&bloom, event.as_bytes()))
+    }
+
+    /// The smallest range within `from..=to` that contains all blocks that
+    /// might have logs matching this filter, according to `blooms`, the
+    /// bloom filters of blocks by number. Blocks without a bloom filter
+    /// might match. Returns `None` if no block in the range can match
+    pub fn narrow_range(&self, blooms: &[(u64, H2048)], from: u64, to: u64) -> Option<(u64, u64)> {
+        let blooms: HashMap<u64, H2048> = blooms.iter().cloned().collect();
+        let mut candidates = (from..=to).filter(|number| {
+            blooms
+                .get(number)
+                .map_or(true, |bloom| self.check_bloom(*bloom))
+        });
+        let first = candidates.next()?;
+        let last = candidates.last().unwrap_or(first);
+        Some((first, last))
     }
 
     /// Check if this filter matches the specified `Log`.
@@ -227,20 +293,47 @@ impl EthereumLogFilter {
             })
         }
 
-        // The current algorithm is to repeatedly find the maximum cardinality vertex and turn all
-        // of its edges into a filter. This is nice because it is neutral between filtering by
-        // contract or by events, if there are many events that appear on only one data source
-        // we'll filter by many events on a single contract, but if there is an event that appears
-        // on a lot of data sources we'll filter by many contracts with a single event.
+        // Contracts that have exactly the same events, usually data sources created from the same
+        // template, are covered by a single filter for all of them and all of their events. None
+        // of the logs that filter returns are false positives.
+        let mut g = self.contracts_and_events_graph;
+        let mut contracts_by_events: BTreeMap<Vec<EventSignature>, Vec<Address>> = BTreeMap::new();
+        for node in g.nodes() {
+            if let LogFilterNode::Contract(address) = node {
+                let mut events: Vec<_> = g
+                    .neighbors(node)
+                    .filter_map(|neighbor| match neighbor {
+                        LogFilterNode::Event(event_sig) => Some(event_sig),
+                        LogFilterNode::Contract(_) => None,
+                    })
+                    .collect();
+                events.sort();
+                contracts_by_events.entry(events).or_default().push(address);
+            }
+        }
+        for (event_signatures, mut contracts) in contracts_by_events {
+            if contracts.len() < 2 || event_signatures.is_empty() {
+                continue;
+            }
+            contracts.sort();
+            for address in &contracts {
+                g.remove_node(LogFilterNode::Contract(*address));
+            }
+            filters.push(EthGetLogsFilter {
+                contracts,
+                event_signatures,
+            });
+        }
+
+        // For the remaining contracts, the current algorithm is to repeatedly find the maximum
+        // cardinality vertex and turn all of its edges into a filter. This is nice because it is
+        // neutral between filtering by contract or by events, if there are many events that
+        // appear on only one data source we'll filter by many events on a single contract, but if
+        // there is an event that appears on a lot of data sources we'll filter by many contracts
+        // with a single event.
         //
         // From a theoretical standpoint we're finding a vertex cover, and this is not the optimal
         // algorithm to find a minimum vertex cover, but should be fine as an approximation.
-        //
-        // One optimization we're not doing is to merge nodes that have the same neighbors into a
-        // single node. For example if a subgraph has two data sources, each with the same two
-        // events, we could cover that with a single filter and no false positives. However that
-        // might cause the filter to become too broad, so at the moment it seems excessive.
-        let mut g = self.contracts_and_events_graph;
         while g.edge_count() > 0 {
             // If there are edges, there are vertexes.
             let max_vertex = g.nodes().max_by_key(|&n| g.neighbors(n).count()).unwrap();
@@ -269,7 +362,22 @@ impl EthereumLogFilter {
             filters.push(filter);
             g.remove_node(max_vertex);
         }
-        filters.into_iter()
+
+        // Ethereum nodes limit the size of requests, so split filters for many contracts.
+        let max_contracts = *MAX_GET_LOGS_CONTRACTS;
+        filters
+            .into_iter()
+            .flat_map(move |filter| match filter.contracts.len() > max_contracts {
+                false => vec![filter],
+                true => filter
+                    .contracts
+                    .chunks(max_contracts)
+                    .map(|contracts| EthGetLogsFilter {
+                        contracts: contracts.to_vec(),
+                        event_signatures: filter.event_signatures.clone(),
+                    })
+                    .collect(),
+            })
     }
 }
 
@@ -414,9 +522,13 @@ pub struct EthereumBlockFilter {
 }
 
 impl EthereumBlockFilter {
+    /// Data sources of kind `subgraph` need every block, since they handle
+    /// the entity changes of their source deployment in each block
     pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
         iter.into_iter()
-            .filter(|data_source| data_source.source.address.is_some())
+            .filter(|data_source| {
+                data_source.source.address.is_some() || data_source.source_subgraph().is_some()
+            })
             .fold(Self::default(), |mut filter_opt, data_source| {
                 let has_block_handler_with_call_filter = data_source
                     .mapping
@@ -436,7 +548,8 @@ impl EthereumBlockFilter {
                     .any(|block_handler| block_handler.filter.is_none());
 
                 filter_opt.extend(Self {
-                    trigger_every_block: has_block_handler_without_filter,
+                    trigger_every_block: has_block_handler_without_filter
+                        || data_source.source_subgraph().is_some(),
                     contract_addresses: if has_block_handler_with_call_filter {
                         vec![(
                             data_source.source.start_block,
@@ -770,6 +883,13 @@ fn parse_log_triggers(
     log_filter: EthereumLogFilter,
     block: &EthereumBlock,
 ) -> Vec<EthereumTrigger> {
+    if block
+        .block
+        .logs_bloom
+        .map_or(false, |bloom| !log_filter.check_bloom(bloom))
+    {
+        return vec![];
+    }
     block
         .transaction_receipts
         .iter()
@@ -834,6 +954,19 @@ pub async fn triggers_in_block(
 ) -> Result<EthereumBlockWithTriggers, Error> {
     match &ethereum_block {
         BlockFinality::Final(block) => {
+            // When only logs can trigger handlers and the bloom filter of
+            // the block rules out all of them, there is nothing to fetch
+            let only_logs = call_filter.is_empty()
+                && !block_filter.trigger_every_block
+                && block_filter.contract_addresses.is_empty();
+            if only_logs
+                && block
+                    .logs_bloom
+                    .map_or(false, |bloom| !log_filter.check_bloom(bloom))
+            {
+                return Ok(EthereumBlockWithTriggers::new(vec![], ethereum_block));
+            }
+
             let mut blocks = blocks_with_triggers(
                 adapter,
                 logger,
@@ -866,6 +999,29 @@ pub async fn triggers_in_block(
     }
 }
 
+/// The part of `from..=to` that we need to ask the Ethereum node for logs
+/// matching `log_filter`, or `None` if the bloom filters of the cached
+/// blocks rule out matching logs for the whole range
+fn log_range(
+    logger: &Logger,
+    chain_store: &dyn ChainStore,
+    log_filter: &EthereumLogFilter,
+    from: u64,
+    to: u64,
+) -> Option<(u64, u64)> {
+    if to < from || to - from >= *MAX_BLOOM_RANGE {
+        return Some((from, to));
+    }
+    match chain_store.logs_blooms(from, to) {
+        Ok(blooms) => log_filter.narrow_range(&blooms, from, to),
+        Err(e) => {
+            debug!(logger, "Failed to get bloom filters of cached blocks";
+                   "error" => e.to_string());
+            Some((from, to))
+        }
+    }
+}
+
 /// Returns blocks with triggers, corresponding to the specified range and filters.
 /// If a block contains no triggers, there may be no corresponding item in the stream.
 /// However the `to` block will always be present, even if triggers are empty.
@@ -898,13 +1054,26 @@ pub fn blocks_with_triggers(
         Box<dyn Future<Item = Vec<EthereumTrigger>, Error = Error> + Send>,
     > = futures::stream::FuturesUnordered::new();
 
-    // Scan the block range from triggers to find relevant blocks
+    // Scan the block range from triggers to find relevant blocks. The
+    // bloom filters of the blocks we have cached tell us which parts of the
+    // range can not have matching logs, and we only ask the Ethereum node
+    // for the logs of the rest
     if !log_filter.is_empty() {
-        trigger_futs.push(Box::new(
-            eth.logs_in_block_range(&logger, subgraph_metrics.clone(), from, to, log_filter)
+        match log_range(&logger, chain_store.as_ref(), &log_filter, from, to) {
+            Some((log_from, log_to)) => trigger_futs.push(Box::new(
+                eth.logs_in_block_range(
+                    &logger,
+                    subgraph_metrics.clone(),
+                    log_from,
+                    log_to,
+                    log_filter,
+                )
                 .map_ok(|logs: Vec<Log>| logs.into_iter().map(EthereumTrigger::Log).collect())
                 .compat(),
-        ))
+            )),
+            None => debug!(logger, "Bloom filters rule out logs in block range";
+                           "from" => from, "to" => to),
+        }
     }
 
     if !call_filter.is_empty() {
@@ -991,9 +1160,10 @@ pub fn blocks_with_triggers(
 
 #[cfg(test)]
 mod tests {
-    use super::EthereumCallFilter;
+    use super::{EthereumCallFilter, EthereumLogFilter, LogFilterNode};
 
-    use web3::types::Address;
+    use tiny_keccak::keccak256;
+    use web3::types::{Address, H2048, H256};
 
     use std::collections::{HashMap, HashSet};
     use std::iter::FromIterator;
@@ -1042,4 +1212,113 @@ mod tests {
             Some(&(1, HashSet::from_iter(vec![[1u8; 4]])))
         );
     }
+
+    fn bloom(inputs: &[&[u8]]) -> H2048 {
+        let mut bloom = [0u8; 256];
+        for input in inputs {
+            let hash = keccak256(input);
+            for i in 0..3 {
+                let bit = (((hash[2 * i] as usize) << 8) | hash[2 * i + 1] as usize) & 2047;
+                bloom[255 - bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        H2048::from(bloom)
+    }
+
+    #[test]
+    fn checking_log_bloom() {
+        let contract = Address::from_low_u64_be(1);
+        let other_contract = Address::from_low_u64_be(2);
+        let event = H256::from_low_u64_be(10);
+        let other_event = H256::from_low_u64_be(11);
+
+        let mut filter = EthereumLogFilter::default();
+        filter.contracts_and_events_graph.add_edge(
+            LogFilterNode::Contract(contract),
+            LogFilterNode::Event(event),
+            (),
+        );
+
+        assert!(filter.check_bloom(bloom(&[contract.as_bytes(), event.as_bytes()])));
+        assert!(!filter.check_bloom(H2048::zero()));
+        assert!(!filter.check_bloom(bloom(&[contract.as_bytes(), other_event.as_bytes()])));
+        assert!(!filter.check_bloom(bloom(&[other_contract.as_bytes(), event.as_bytes()])));
+
+        filter.wildcard_events.insert(other_event);
+        assert!(filter.check_bloom(bloom(&[other_contract.as_bytes(), other_event.as_bytes()])));
+
+        let matching = bloom(&[contract.as_bytes(), event.as_bytes()]);
+        let blooms = vec![
+            (1, H2048::zero()),
+            (2, matching),
+            (3, H2048::zero()),
+            (4, matching),
+            (5, H2048::zero()),
+        ];
+        assert_eq!(Some((2, 4)), filter.narrow_range(&blooms, 1, 5));
+        assert_eq!(Some((2, 6)), filter.narrow_range(&blooms, 1, 6));
+        assert_eq!(None, filter.narrow_range(&blooms[..1], 1, 1));
+    }
+
+    #[test]
+    fn merging_contracts_with_the_same_events() {
+        let event = H256::from_low_u64_be(10);
+        let other_event = H256::from_low_u64_be(11);
+        let single_event = H256::from_low_u64_be(12);
+
+        let mut filter = EthereumLogFilter::default();
+        let mut add = |contract: u64, event: H256| {
+            filter.contracts_and_events_graph.add_edge(
+                LogFilterNode::Contract(Address::from_low_u64_be(contract)),
+                LogFilterNode::Event(event),
+                (),
+            );
+        };
+        // Contracts created from the same template
+        for contract in 1..=2 {
+            add(contract, event);
+            add(contract, other_event);
+        }
+        // A contract with events of its own
+        add(3, single_event);
+
+        let mut filters: Vec<_> = filter
+            .eth_get_logs_filters()
+            .map(|filter| (filter.contracts, filter.event_signatures))
+            .collect();
+        filters.sort();
+        assert_eq!(
+            vec![
+                (
+                    vec![Address::from_low_u64_be(1), Address::from_low_u64_be(2)],
+                    vec![event, other_event]
+                ),
+                (vec![Address::from_low_u64_be(3)], vec![single_event]),
+            ],
+            filters
+        );
+    }
+
+    #[test]
+    fn splitting_filters_with_many_contracts() {
+        let event = H256::from_low_u64_be(10);
+        let max_contracts = *super::MAX_GET_LOGS_CONTRACTS as u64;
+
+        let mut filter = EthereumLogFilter::default();
+        for contract in 0..max_contracts + 1 {
+            filter.contracts_and_events_graph.add_edge(
+                LogFilterNode::Contract(Address::from_low_u64_be(contract)),
+                LogFilterNode::Event(event),
+                (),
+            );
+        }
+
+        let filters: Vec<_> = filter.eth_get_logs_filters().collect();
+        assert_eq!(2, filters.len());
+        assert_eq!(max_contracts as usize, filters[0].contracts.len());
+        assert_eq!(1, filters[1].contracts.len());
+        assert!(filters
+            .iter()
+            .all(|filter| filter.event_signatures == vec![event]));
+    }
 }
@@ -42,3 +42,25 @@ pub trait LinkResolver: Send + Sync + 'static {
     /// separately.
     async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error>;
 }
+
+/// A size-capped cache of the contents of IPFS files that outlives the
+/// node and is shared by all nodes that use the same database
+pub trait IpfsCache: Send + Sync + 'static {
+    /// The contents of the file at `path`, if they are cached
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Cache `data` as the contents of the file at `path`, evicting the
+    /// files that were used least recently if the cache grows too big
+    fn insert(&self, path: &str, data: &[u8]) -> Result<(), Error>;
+}
+
+/// The files of subgraphs that were deployed from a local directory or an
+/// HTTP URL instead of IPFS, stored with the metadata of deployments. Each
+/// file is identified by the hash of its contents, like files in IPFS
+pub trait DeploymentFiles: Send + Sync + 'static {
+    /// The contents of the file `id`, if it is stored
+    fn get(&self, id: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Store `data` as the contents of the file `id`
+    fn insert(&self, id: &str, data: &[u8]) -> Result<(), Error>;
+}
@@ -0,0 +1,35 @@
+use lazy_static::lazy_static;
+use std::sync::{Arc, RwLock};
+
+use crate::prelude::{Error, Serialize};
+
+lazy_static! {
+    static ref INSTALLED: RwLock<Option<Arc<dyn MigrationStatusSource>>> = RwLock::new(None);
+}
+
+/// How far the schema of the database of one store shard is migrated
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MigrationStatus {
+    pub shard: String,
+    /// The version of the last migration that was applied, or `None` if
+    /// the database has not been migrated at all
+    pub version: Option<String>,
+    /// The versions of the migrations that this node has but that have not
+    /// been applied to the database yet, oldest first
+    pub pending: Vec<String>,
+}
+
+/// Reports the migration status of every store shard of the node
+pub trait MigrationStatusSource: Send + Sync {
+    fn migration_status(&self) -> Result<Vec<MigrationStatus>, Error>;
+}
+
+/// Make `source` available to the admin server
+pub fn install(source: Arc<dyn MigrationStatusSource>) {
+    *INSTALLED.write().unwrap() = Some(source);
+}
+
+/// The source of migration statuses of this node, if one was installed
+pub fn installed() -> Option<Arc<dyn MigrationStatusSource>> {
+    INSTALLED.read().unwrap().clone()
+}
@@ -54,6 +54,9 @@ pub mod server;
 /// Components dealing with storing entities.
 pub mod store;
 
+/// The state of the schema migrations of the databases of a node.
+pub mod migration;
+
 pub mod link_resolver;
 
 /// Components dealing with collecting metrics
@@ -1,8 +1,91 @@
 use std::io;
 use std::sync::Arc;
 
-use crate::prelude::Logger;
-use crate::prelude::NodeId;
+use crate::prelude::{Deserialize, Logger};
+use crate::util::security::constant_time_eq;
+use crate::util::tls::TlsConfig;
+
+/// The kinds of operations that a token for the admin server can allow
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminScope {
+    /// Create subgraph names and deploy new versions
+    Deploy,
+    /// Move deployments between index nodes
+    Assign,
+    /// Remove subgraph names
+    Remove,
+    /// Rewind deployments to an earlier block. The admin server does not
+    /// have a method for that yet; deployments can only be rewound with
+    /// `graphman rewind`, and tokens with this scope can not do anything
+    /// that needs it
+    Rewind,
+    /// Block and unblock the shapes of GraphQL queries
+    Queries,
+    /// Manage the monthly query quotas of API tokens
+    Quotas,
+    /// Inspect the databases of the node, e.g., their migrations
+    Store,
+}
+
+impl AdminScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminScope::Deploy => "deploy",
+            AdminScope::Assign => "assign",
+            AdminScope::Remove => "remove",
+            AdminScope::Rewind => "rewind",
+            AdminScope::Queries => "queries",
+            AdminScope::Quotas => "quotas",
+            AdminScope::Store => "store",
+        }
+    }
+}
+
+/// The bearer tokens that the admin server accepts, and what each of them
+/// allows. Without any tokens, the admin server does not require
+/// authentication
+#[derive(Clone, Debug, Default)]
+pub struct AdminAuth {
+    tokens: Vec<(String, Vec<AdminScope>)>,
+}
+
+impl AdminAuth {
+    pub fn with_token(mut self, token: String, scopes: Vec<AdminScope>) -> Self {
+        self.tokens.push((token, scopes));
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Check that a request with the bearer token `token` may perform an
+    /// operation that needs `scope`
+    pub fn check(&self, token: Option<&str>, scope: AdminScope) -> Result<(), String> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let token = token.ok_or_else(|| "a bearer token is required".to_owned())?;
+        // Look at every token so that the time this takes does not reveal
+        // how much of a token was right
+        let scopes = self
+            .tokens
+            .iter()
+            .filter(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            .map(|(_, scopes)| scopes)
+            .last()
+            .ok_or_else(|| "the bearer token is not valid".to_owned())?;
+        if scopes.contains(&scope) {
+            Ok(())
+        } else {
+            Err(format!(
+                "the bearer token does not allow `{}`",
+                scope.as_str()
+            ))
+        }
+    }
+}
 
 /// Common trait for JSON-RPC admin server implementations.
 pub trait JsonRpcServer<P> {
@@ -13,7 +96,32 @@ pub trait JsonRpcServer<P> {
         http_port: u16,
         ws_port: u16,
         provider: Arc<P>,
-        node_id: NodeId,
+        auth: AdminAuth,
+        tls: Option<Arc<TlsConfig>>,
         logger: Logger,
     ) -> Result<Self::Server, io::Error>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_scopes_of_tokens() {
+        let auth = AdminAuth::default();
+        assert!(auth.check(None, AdminScope::Remove).is_ok());
+
+        let auth = auth
+            .with_token("deployer".to_owned(), vec![AdminScope::Deploy])
+            .with_token(
+                "operator".to_owned(),
+                vec![AdminScope::Assign, AdminScope::Remove],
+            );
+        assert!(auth.check(None, AdminScope::Deploy).is_err());
+        assert!(auth.check(Some("deployer"), AdminScope::Deploy).is_ok());
+        assert!(auth.check(Some("deployer"), AdminScope::Remove).is_err());
+        assert!(auth.check(Some("operator"), AdminScope::Remove).is_ok());
+        assert!(auth.check(Some("operator"), AdminScope::Rewind).is_err());
+        assert!(auth.check(Some("deploye"), AdminScope::Deploy).is_err());
+    }
+}
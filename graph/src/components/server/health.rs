@@ -0,0 +1,36 @@
+use async_trait::async_trait;
+use failure::Error;
+use futures::prelude::*;
+use serde_derive::Serialize;
+
+/// A part of the node, like the store or an Ethereum provider, whose state
+/// decides whether the node is ready to do its work.
+#[async_trait]
+pub trait HealthCheck: Send + Sync + 'static {
+    /// The name of the component in health reports, like `store`.
+    fn component(&self) -> String;
+
+    /// Returns an error that says what is wrong if the component is not
+    /// healthy.
+    async fn check(&self) -> Result<(), Error>;
+}
+
+/// The result of checking one component.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ComponentHealth {
+    pub component: String,
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Common trait for health server implementations.
+pub trait HealthServer {
+    type ServeError;
+
+    /// Creates a new Tokio task that, when spawned, brings up the health server.
+    fn serve(
+        &mut self,
+        port: u16,
+    ) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>, Self::ServeError>;
+}
@@ -12,3 +12,6 @@ pub mod index_node;
 
 /// Components for the Prometheus metrics server.
 pub mod metrics;
+
+/// Components for the liveness and readiness probes.
+pub mod health;
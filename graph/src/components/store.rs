@@ -13,11 +13,12 @@ use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use web3::types::{Address, H256};
+use web3::types::{Address, H2048, H256};
 
 use crate::data::store::*;
 use crate::data::subgraph::schema::*;
 use crate::prelude::*;
+use crate::util::env::env_var;
 use crate::util::lfu_cache::LfuCache;
 
 lazy_static! {
@@ -29,6 +30,11 @@ lazy_static! {
             )))
             .map(Duration::from_millis)
             .unwrap_or_else(|| Duration::from_millis(1000));
+
+    /// The shortest time between two executions of a subscription once the
+    /// subgraph is synced; clients can ask for a longer one
+    pub static ref SUBSCRIPTION_MIN_INTERVAL: Duration =
+        Duration::from_millis(env_var("GRAPH_SUBSCRIPTION_MIN_INTERVAL").unwrap_or(0));
 }
 
 // Note: Do not modify fields without making a backward compatible change to
@@ -76,6 +82,9 @@ pub enum EntityFilter {
     NotStartsWith(Attribute, Value),
     EndsWith(Attribute, Value),
     NotEndsWith(Attribute, Value),
+    /// The `GeoPoint` attribute is at most the given number of meters away
+    /// from the point in the value
+    Within(Attribute, Value, f64),
 }
 
 // Define some convenience methods
@@ -275,6 +284,11 @@ pub struct EntityQuery {
     /// How to order the entities
     pub order: EntityOrder,
 
+    /// Only return the first entity for each value of the attribute the
+    /// entities are ordered by. For windows, this is done separately for
+    /// each parent
+    pub distinct: bool,
+
     /// A range to limit the size of the result.
     pub range: EntityRange,
 
@@ -296,6 +310,7 @@ impl EntityQuery {
             collection,
             filter: None,
             order: EntityOrder::Default,
+            distinct: false,
             range: EntityRange::first(100),
             logger: None,
             _force_use_of_new: (),
@@ -312,6 +327,11 @@ impl EntityQuery {
         self
     }
 
+    pub fn distinct(mut self, distinct: bool) -> Self {
+        self.distinct = distinct;
+        self
+    }
+
     pub fn range(mut self, range: EntityRange) -> Self {
         self.range = range;
         self
@@ -355,6 +375,175 @@ impl EntityQuery {
     }
 }
 
+/// An aggregate that an `EntityGroupQuery` computes for each group
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntityAggregate {
+    /// The number of entities in the group
+    Count,
+    /// The number of distinct values of the attribute in the group
+    CountDistinct(Attribute),
+    Sum(Attribute),
+    Min(Attribute),
+    Max(Attribute),
+    Avg(Attribute),
+}
+
+impl EntityAggregate {
+    /// The name under which the aggregate appears in the result of the
+    /// query, e.g., `count` or `amount_sum`
+    pub fn name(&self) -> String {
+        match self {
+            EntityAggregate::Count => "count".to_owned(),
+            EntityAggregate::CountDistinct(attr) => format!("{}_countDistinct", attr),
+            EntityAggregate::Sum(attr) => format!("{}_sum", attr),
+            EntityAggregate::Min(attr) => format!("{}_min", attr),
+            EntityAggregate::Max(attr) => format!("{}_max", attr),
+            EntityAggregate::Avg(attr) => format!("{}_avg", attr),
+        }
+    }
+
+    /// The attribute that the aggregate is computed over
+    pub fn attribute(&self) -> Option<&Attribute> {
+        match self {
+            EntityAggregate::Count => None,
+            EntityAggregate::CountDistinct(attr)
+            | EntityAggregate::Sum(attr)
+            | EntityAggregate::Min(attr)
+            | EntityAggregate::Max(attr)
+            | EntityAggregate::Avg(attr) => Some(attr),
+        }
+    }
+
+    /// The aggregate whose `name` is `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name == "count" {
+            return Some(EntityAggregate::Count);
+        }
+        let pos = name.rfind('_')?;
+        let attr = name[..pos].to_owned();
+        match &name[pos + 1..] {
+            "countDistinct" => Some(EntityAggregate::CountDistinct(attr)),
+            "sum" => Some(EntityAggregate::Sum(attr)),
+            "min" => Some(EntityAggregate::Min(attr)),
+            "max" => Some(EntityAggregate::Max(attr)),
+            "avg" => Some(EntityAggregate::Avg(attr)),
+            _ => None,
+        }
+    }
+}
+
+/// A filter on the aggregates of a group, i.e., a `having` clause
+#[derive(Clone, Debug, PartialEq)]
+pub enum AggregateFilter {
+    And(Vec<AggregateFilter>),
+    Equal(EntityAggregate, Value),
+    GreaterThan(EntityAggregate, Value),
+    LessThan(EntityAggregate, Value),
+    GreaterOrEqual(EntityAggregate, Value),
+    LessOrEqual(EntityAggregate, Value),
+}
+
+/// What to sort the groups of an `EntityGroupQuery` by
+#[derive(Clone, Debug, PartialEq)]
+pub enum GroupSortKey {
+    /// One of the attributes that the entities are grouped by
+    Attribute(Attribute),
+    Aggregate(EntityAggregate),
+}
+
+/// The order in which groups should be returned
+#[derive(Clone, Debug, PartialEq)]
+pub enum GroupOrder {
+    Ascending(GroupSortKey),
+    Descending(GroupSortKey),
+    /// Order by the attributes that the entities are grouped by
+    Default,
+}
+
+/// A query that groups the entities of one type by the values of some of
+/// their attributes and computes aggregates for each group. The result has
+/// one map per group with the attributes of `group_by` and the aggregates,
+/// by their `name`.
+#[derive(Clone, Debug)]
+pub struct EntityGroupQuery {
+    /// ID of the subgraph.
+    pub subgraph_id: SubgraphDeploymentId,
+
+    /// The block height at which to execute the query, like for
+    /// `EntityQuery`
+    pub block: BlockNumber,
+
+    /// The entity type whose entities are grouped
+    pub entity_type: String,
+
+    /// Filter to filter entities by before they are grouped
+    pub filter: Option<EntityFilter>,
+
+    /// The attributes to group by; with none, all entities form one group
+    pub group_by: Vec<Attribute>,
+
+    /// The aggregates to compute for each group
+    pub aggregates: Vec<EntityAggregate>,
+
+    /// Filter to filter groups by their aggregates
+    pub having: Option<AggregateFilter>,
+
+    /// How to order the groups
+    pub order: GroupOrder,
+
+    /// A range to limit the number of groups
+    pub range: EntityRange,
+
+    _force_use_of_new: (),
+}
+
+impl EntityGroupQuery {
+    pub fn new(subgraph_id: SubgraphDeploymentId, block: BlockNumber, entity_type: String) -> Self {
+        EntityGroupQuery {
+            subgraph_id,
+            block,
+            entity_type,
+            filter: None,
+            group_by: vec![],
+            aggregates: vec![EntityAggregate::Count],
+            having: None,
+            order: GroupOrder::Default,
+            range: EntityRange::first(100),
+            _force_use_of_new: (),
+        }
+    }
+
+    pub fn filter(mut self, filter: EntityFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn group_by(mut self, group_by: Vec<Attribute>) -> Self {
+        self.group_by = group_by;
+        self
+    }
+
+    pub fn aggregates(mut self, aggregates: Vec<EntityAggregate>) -> Self {
+        self.aggregates = aggregates;
+        self
+    }
+
+    pub fn having(mut self, having: AggregateFilter) -> Self {
+        self.having = Some(having);
+        self
+    }
+
+    pub fn order(mut self, order: GroupOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn range(mut self, range: EntityRange) -> Self {
+        self.range = range;
+        self
+    }
+}
+
 /// Operation types that lead to entity changes.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
@@ -559,6 +748,23 @@ where
         store: Arc<impl Store>,
         deployment: SubgraphDeploymentId,
         interval: Duration,
+    ) -> StoreEventStreamBox {
+        self.throttle(logger, store, deployment, interval, Duration::from_secs(0))
+    }
+
+    /// Like `throttle_while_syncing`, but once `deployment` is synced,
+    /// events are still combined and reported at most every
+    /// `synced_interval`, so that bursts of changes, e.g., on chains with
+    /// a short block time, only lead to one event. While the deployment is
+    /// syncing, events are reported at most every `syncing_interval` or
+    /// `synced_interval`, whichever is longer
+    pub fn throttle(
+        self,
+        logger: &Logger,
+        store: Arc<impl Store>,
+        deployment: SubgraphDeploymentId,
+        syncing_interval: Duration,
+        synced_interval: Duration,
     ) -> StoreEventStreamBox {
         // We refresh the synced flag every SYNC_REFRESH_FREQ*interval to
         // avoid hitting the database too often to see if the subgraph has
@@ -577,13 +783,22 @@ where
                     .unwrap_or(false)
         };
         let mut synced = check_synced(&*store, &deployment);
-        let synced_check_interval = interval.checked_mul(SYNC_REFRESH_FREQ).unwrap();
+        let interval = |synced: bool| {
+            if synced {
+                synced_interval
+            } else {
+                syncing_interval.max(synced_interval)
+            }
+        };
+        let synced_check_interval = syncing_interval.checked_mul(SYNC_REFRESH_FREQ).unwrap();
         let mut synced_last_refreshed = Instant::now();
 
         let mut pending_event: Option<StoreEvent> = None;
         let mut source = self.source.fuse();
         let mut had_err = false;
-        let mut delay = tokio::time::delay_for(interval).unit_error().compat();
+        let mut delay = tokio::time::delay_for(interval(synced))
+            .unit_error()
+            .compat();
         let logger = logger.clone();
 
         let source = Box::new(poll_fn(move || -> Poll<Option<Arc<StoreEvent>>, ()> {
@@ -599,7 +814,7 @@ where
                 synced_last_refreshed = Instant::now();
             }
 
-            if synced {
+            if synced && synced_interval == Duration::from_secs(0) {
                 return source.poll();
             }
 
@@ -610,7 +825,9 @@ where
                 // Timer errors are harmless. Treat them as if the timer had
                 // become ready.
                 Ok(Async::Ready(())) | Err(_) => {
-                    delay = tokio::time::delay_for(interval).unit_error().compat();
+                    delay = tokio::time::delay_for(interval(synced))
+                        .unit_error()
+                        .compat();
                     true
                 }
             };
@@ -708,6 +925,106 @@ impl MetadataOperation {
     }
 }
 
+/// Which version of a subgraph a query by name should go to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubgraphVersionSelector {
+    /// The version that queries go to by default
+    Current,
+    /// The version that was deployed most recently, but that will only
+    /// become the current version once it has synced
+    Pending,
+    /// The most recently deployed version with this label
+    Label(String),
+}
+
+impl SubgraphVersionSelector {
+    pub fn parse(selector: &str) -> Option<Self> {
+        match selector {
+            "current" => Some(SubgraphVersionSelector::Current),
+            "pending" => Some(SubgraphVersionSelector::Pending),
+            _ => None,
+        }
+    }
+
+    /// Determine the version from the `version` parameter in the query
+    /// string of a URL. Queries go to the current version unless they ask
+    /// for `version=pending`
+    pub fn from_url_query(query: Option<&str>) -> Result<Self, Error> {
+        let value = query
+            .unwrap_or("")
+            .split('&')
+            .filter_map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some("version"), Some(value)) => Some(value),
+                    _ => None,
+                }
+            })
+            .last();
+        match value {
+            None => Ok(SubgraphVersionSelector::Current),
+            Some(value) => Self::parse(value).ok_or_else(|| {
+                format_err!(
+                    "Invalid subgraph version {:?}, must be `current` or `pending`",
+                    value
+                )
+            }),
+        }
+    }
+
+    /// Split the segments of a path after `/subgraphs/name/` into the name
+    /// of a subgraph and the version that a `pending` or `version/<label>`
+    /// suffix selects. Returns `None` if there is no such suffix. Since
+    /// names have at most two parts, the suffix can always be told apart
+    /// from the name, except for two-part names ending in `pending`, which
+    /// are read as a one-part name with a suffix
+    pub fn from_path(segments: &[&str]) -> Option<(String, Self)> {
+        let (last, init) = segments.split_last()?;
+        if *last == "pending" && (1..=2).contains(&init.len()) {
+            return Some((init.join("/"), SubgraphVersionSelector::Pending));
+        }
+        let (version, name) = init.split_last()?;
+        if *version == "version" && (1..=2).contains(&name.len()) {
+            return Some((
+                name.join("/"),
+                SubgraphVersionSelector::Label(last.to_string()),
+            ));
+        }
+        None
+    }
+
+    /// Whether `label` can be used as the label of a subgraph version.
+    /// Labels appear in URLs and may only contain letters, digits, `.`,
+    /// `-` and `_`
+    pub fn is_valid_label(label: &str) -> bool {
+        !label.is_empty()
+            && label.len() <= 64
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+    }
+}
+
+/// Which columns Postgres writes to the WAL for the old version of an
+/// updated or deleted row, i.e., what logical replication sees of it
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicaIdentity {
+    /// Only the primary key
+    Default,
+    /// All columns
+    Full,
+}
+
+impl ReplicaIdentity {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            ReplicaIdentity::Default => "default",
+            ReplicaIdentity::Full => "full",
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct AttributeIndexDefinition {
     pub subgraph_id: SubgraphDeploymentId,
@@ -787,6 +1104,19 @@ pub enum TransactionAbortError {
     Other(String),
 }
 
+/// The deployment that a `SubgraphVersion` entity points to
+fn version_deployment(version: &Entity) -> Result<Option<SubgraphDeploymentId>, Error> {
+    let subgraph_id_str = version
+        .get("deployment")
+        .ok_or_else(|| format_err!("SubgraphVersion entity without `deployment`"))?
+        .to_owned()
+        .as_string()
+        .ok_or_else(|| format_err!("SubgraphVersion entity has wrong type in `deployment`"))?;
+    SubgraphDeploymentId::new(subgraph_id_str)
+        .map_err(|()| format_err!("SubgraphVersion entity has invalid subgraph ID in `deployment`"))
+        .map(Some)
+}
+
 /// Common trait for store implementations.
 pub trait Store: Send + Sync + 'static {
     /// Get a pointer to the most recently processed block in the subgraph.
@@ -834,6 +1164,24 @@ pub trait Store: Send + Sync + 'static {
     /// Queries the store for a single entity matching the store query.
     fn find_one(&self, query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError>;
 
+    /// Groups entities and computes aggregates for each group, see
+    /// `EntityGroupQuery`
+    fn find_groups(
+        &self,
+        query: EntityGroupQuery,
+    ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError>;
+
+    /// The changes that `block` made to entities of `entity_types` in
+    /// `subgraph_id`, ordered by entity type and id. Creating or updating
+    /// an entity shows up as an `EntityOperation::Set` with the entity as
+    /// of `block`, removing one as an `EntityOperation::Remove`
+    fn changes_in_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError>;
+
     /// Find the reverse of keccak256 for `hash` through looking it up in the
     /// rainbow table.
     fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError>;
@@ -866,6 +1214,27 @@ pub trait Store: Send + Sync + 'static {
         indexes: Vec<AttributeIndexDefinition>,
     ) -> Result<(), SubgraphAssignmentProviderError>;
 
+    /// Create a Postgres publication for all entity tables of `subgraph`
+    /// so that logical replication can follow them, or drop it if `publish`
+    /// is `false`. With a `replica_identity`, the replica identity of the
+    /// tables is changed, too. Returns the name of the publication if one
+    /// was created
+    fn set_publication(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+        publish: bool,
+        replica_identity: Option<ReplicaIdentity>,
+    ) -> Result<Option<String>, StoreError>;
+
+    /// Rewind `subgraph` so that its latest block is `block_ptr`, deleting
+    /// all entity versions that were created after it. The deployment must
+    /// not be assigned to any index node while it is rewound
+    fn rewind(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+        block_ptr: EthereumBlockPointer,
+    ) -> Result<(), StoreError>;
+
     /// Revert the entity changes from a single block atomically in the store, and update the
     /// subgraph block pointer from `block_ptr_from` to `block_ptr_to`.
     ///
@@ -886,6 +1255,17 @@ pub trait Store: Send + Sync + 'static {
     fn resolve_subgraph_name_to_id(
         &self,
         name: SubgraphName,
+    ) -> Result<Option<SubgraphDeploymentId>, Error> {
+        self.resolve_subgraph_version(name, SubgraphVersionSelector::Current)
+    }
+
+    /// Find the deployment of the version of subgraph `name` that
+    /// `selector` picks. Returns `None` if there is no such subgraph, or if
+    /// it does not have that version, e.g., because nothing is pending
+    fn resolve_subgraph_version(
+        &self,
+        name: SubgraphName,
+        selector: SubgraphVersionSelector,
     ) -> Result<Option<SubgraphDeploymentId>, Error> {
         // Find subgraph entity by name
         let subgraph_entities = self
@@ -906,45 +1286,60 @@ pub trait Store: Send + Sync + 'static {
             )),
         }?;
 
-        // Get current active subgraph version ID
-        let current_version_id = match subgraph_entity.get("currentVersion").ok_or_else(|| {
-            format_err!(
-                "Subgraph entity has no `currentVersion`. \
-                 The subgraph may have been created but not deployed yet. Make sure \
-                 to run `graph deploy` to deploy the subgraph and have it start \
-                 indexing."
-            )
-        })? {
-            Value::String(s) => s.to_owned(),
-            Value::Null => return Ok(None),
-            _ => {
+        // Get the ID of the selected subgraph version
+        let attribute = match &selector {
+            SubgraphVersionSelector::Current => "currentVersion",
+            SubgraphVersionSelector::Pending => "pendingVersion",
+            SubgraphVersionSelector::Label(label) => {
+                let versions = self
+                    .find(
+                        SubgraphVersionEntity::query()
+                            .filter(EntityFilter::And(vec![
+                                EntityFilter::new_equal("subgraph", subgraph_entity.id()?),
+                                EntityFilter::new_equal("label", label.as_str()),
+                            ]))
+                            .order(EntityOrder::Descending(
+                                "createdAt".to_owned(),
+                                ValueType::BigInt,
+                            ))
+                            .first(1),
+                    )
+                    .map_err(QueryError::from)?;
+                return match versions.into_iter().next() {
+                    Some(version) => version_deployment(&version),
+                    None => Ok(None),
+                };
+            }
+        };
+        let version_id = match subgraph_entity.get(attribute) {
+            Some(Value::String(s)) => s.to_owned(),
+            Some(Value::Null) => return Ok(None),
+            None if selector == SubgraphVersionSelector::Pending => return Ok(None),
+            None => {
                 return Err(format_err!(
-                    "Subgraph entity has wrong type in `currentVersion`"
+                    "Subgraph entity has no `currentVersion`. \
+                     The subgraph may have been created but not deployed yet. Make sure \
+                     to run `graph deploy` to deploy the subgraph and have it start \
+                     indexing."
+                ))
+            }
+            Some(_) => {
+                return Err(format_err!(
+                    "Subgraph entity has wrong type in `{}`",
+                    attribute
                 ));
             }
         };
 
         // Read subgraph version entity
         let version_entity_opt = self
-            .get(SubgraphVersionEntity::key(current_version_id))
+            .get(SubgraphVersionEntity::key(version_id))
             .map_err(QueryError::from)?;
         if version_entity_opt == None {
             return Ok(None);
         }
         let version_entity = version_entity_opt.unwrap();
-
-        // Parse subgraph ID
-        let subgraph_id_str = version_entity
-            .get("deployment")
-            .ok_or_else(|| format_err!("SubgraphVersion entity without `deployment`"))?
-            .to_owned()
-            .as_string()
-            .ok_or_else(|| format_err!("SubgraphVersion entity has wrong type in `deployment`"))?;
-        SubgraphDeploymentId::new(subgraph_id_str)
-            .map_err(|()| {
-                format_err!("SubgraphVersion entity has invalid subgraph ID in `deployment`")
-            })
-            .map(Some)
+        version_deployment(&version_entity)
     }
 
     /// Read all version entities pointing to the specified deployment IDs and
@@ -1160,6 +1555,125 @@ pub trait Store: Send + Sync + 'static {
             .unwrap_or(Ok(false))
     }
 
+    /// Return true if the deployment with the given id is in maintenance,
+    /// in which case it should not be indexed. Errors from the store are
+    /// passed back up
+    fn is_deployment_in_maintenance(&self, id: SubgraphDeploymentId) -> Result<bool, Error> {
+        let entity = self.get(SubgraphDeploymentEntity::key(id))?;
+        Ok(
+            match entity.as_ref().and_then(|entity| entity.get("maintenance")) {
+                Some(Value::Bool(maintenance)) => *maintenance,
+                _ => false,
+            },
+        )
+    }
+
+    /// Return the version of the GraphQL API semantics that the deployment
+    /// with the given id declares in its manifest. Deployments that do not
+    /// exist or do not declare one use the default version. Errors from the
+    /// store are passed back up
+    fn api_version(&self, id: &SubgraphDeploymentId) -> Result<ApiVersion, Error> {
+        let manifest = self.get(SubgraphManifestEntity::key(SubgraphManifestEntity::id(id)))?;
+        match manifest
+            .as_ref()
+            .and_then(|manifest| manifest.get("apiVersion"))
+        {
+            Some(Value::String(version)) => version.parse(),
+            _ => Ok(ApiVersion::default()),
+        }
+    }
+
+    /// Return the earliest block for which the deployment with the given id
+    /// retains the history of its entities; queries for blocks before it
+    /// can not be answered. Deployments that do not exist retain all
+    /// blocks. Errors from the store are passed back up
+    fn earliest_retained_block(&self, id: &SubgraphDeploymentId) -> Result<BlockNumber, Error> {
+        let entity = self.get(SubgraphDeploymentEntity::key(id.clone()))?;
+        Ok(
+            match entity
+                .as_ref()
+                .and_then(|entity| entity.get("earliestEthereumBlockNumber"))
+            {
+                Some(Value::BigInt(number)) => number.to_u64() as BlockNumber + 1,
+                Some(Value::Int(number)) => number + 1,
+                _ => 0,
+            },
+        )
+    }
+
+    /// Return the blocks where the data of the deployment with the given id
+    /// begins and up to which it is free of errors. Deployments that do
+    /// not exist get the default blocks. Errors from the store are passed
+    /// back up
+    fn deployment_blocks(&self, id: &SubgraphDeploymentId) -> Result<DeploymentBlocks, Error> {
+        fn block_number(entity: &Entity, attr: &str) -> Option<u64> {
+            match entity.get(attr) {
+                Some(Value::BigInt(number)) => Some(number.to_u64()),
+                Some(Value::Int(number)) => Some(*number as u64),
+                _ => None,
+            }
+        }
+
+        fn ids(entity: &Entity, attr: &str) -> Vec<String> {
+            match entity.get(attr) {
+                Some(Value::String(id)) => vec![id.clone()],
+                Some(Value::List(ids)) => {
+                    ids.iter().cloned().filter_map(Value::as_string).collect()
+                }
+                _ => vec![],
+            }
+        }
+
+        let deployment = match self.get(SubgraphDeploymentEntity::key(id.clone()))? {
+            Some(deployment) => deployment,
+            None => return Ok(DeploymentBlocks::default()),
+        };
+
+        let mut start_block = None;
+        let manifest = self.get(SubgraphManifestEntity::key(SubgraphManifestEntity::id(id)))?;
+        for data_source_id in manifest
+            .iter()
+            .flat_map(|manifest| ids(manifest, "dataSources"))
+        {
+            let data_source = self.get(EthereumContractDataSourceEntity::key(data_source_id))?;
+            for source_id in data_source.iter().flat_map(|ds| ids(ds, "source")) {
+                let source = self.get(EthereumContractSourceEntity::key(source_id))?;
+                let source_start = source
+                    .as_ref()
+                    .and_then(|source| block_number(source, "startBlock"))
+                    .unwrap_or(0);
+                start_block =
+                    Some(start_block.map_or(source_start, |start: u64| start.min(source_start)));
+            }
+        }
+
+        // Non-fatal errors are sorted by block number, so the first one
+        // and the fatal error are the only candidates for the first error
+        let mut first_error_block: Option<u64> = None;
+        let error_ids = ids(&deployment, "fatalError")
+            .into_iter()
+            .chain(ids(&deployment, "nonFatalErrors").into_iter().take(1));
+        for error_id in error_ids {
+            if let Some(error) = self.get(SubgraphError::key(error_id))? {
+                if let Some(number) = block_number(&error, "blockNumber") {
+                    first_error_block =
+                        Some(first_error_block.map_or(number, |first| first.min(number)));
+                }
+            }
+        }
+
+        let latest_block = block_number(&deployment, "latestEthereumBlockNumber");
+        Ok(DeploymentBlocks {
+            start_block: start_block.unwrap_or(0),
+            earliest_block: block_number(&deployment, "earliestEthereumBlockNumber")
+                .map_or(0, |number| number + 1),
+            last_healthy_block: match first_error_block {
+                Some(number) => number.checked_sub(1),
+                None => latest_block,
+            },
+        })
+    }
+
     /// Create a new subgraph deployment. The deployment must not exist yet. `ops`
     /// needs to contain all the operations on subgraphs and subgraph deployments to
     /// create the deployment, including any assignments as a current or pending
@@ -1202,6 +1716,20 @@ pub trait Store: Send + Sync + 'static {
         subgraph_id: &SubgraphDeploymentId,
         block_hash: H256,
     ) -> Result<Option<BlockNumber>, StoreError>;
+
+    /// Record that the subgraph has processed the block with number
+    /// `block_number` by now
+    fn record_sync_sample(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_number: u64,
+    ) -> Result<(), StoreError>;
+
+    /// Return the recent sync samples of the subgraph, oldest first
+    fn sync_samples(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<SyncSample>, StoreError>;
 }
 
 mock! {
@@ -1266,6 +1794,22 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn find_groups(
+        &self,
+        _: EntityGroupQuery,
+    ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    fn changes_in_block(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _entity_types: &[String],
+        _block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        unimplemented!()
+    }
+
     fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError> {
         unimplemented!()
     }
@@ -1295,6 +1839,23 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn set_publication(
+        &self,
+        _subgraph: &SubgraphDeploymentId,
+        _publish: bool,
+        _replica_identity: Option<ReplicaIdentity>,
+    ) -> Result<Option<String>, StoreError> {
+        unimplemented!()
+    }
+
+    fn rewind(
+        &self,
+        _subgraph: &SubgraphDeploymentId,
+        _block_ptr: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
     fn revert_block_operations(
         &self,
         _subgraph_id: SubgraphDeploymentId,
@@ -1341,6 +1902,21 @@ impl Store for MockStore {
     ) -> Result<Option<BlockNumber>, StoreError> {
         unimplemented!()
     }
+
+    fn record_sync_sample(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block_number: u64,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn sync_samples(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<SyncSample>, StoreError> {
+        unimplemented!()
+    }
 }
 
 #[automock]
@@ -1361,6 +1937,21 @@ pub trait SubgraphDeploymentStore: Send + Sync + 'static {
     /// Return the name of the network that the subgraph is indexing from. The
     /// names returned are things like `mainnet` or `ropsten`
     fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error>;
+
+    /// Return the number of the chain head block of the network that the
+    /// subgraph is indexing from, or `None` if that is not known
+    fn network_head_block_number(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<u64>, Error>;
+
+    /// Return the hash of the block with the given number on the chain that
+    /// the subgraph has processed, or `None` if that block is not known
+    fn block_hash(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        number: BlockNumber,
+    ) -> Result<Option<H256>, Error>;
 }
 
 /// Common trait for blockchain store implementations.
@@ -1415,6 +2006,11 @@ pub trait ChainStore: Send + Sync + 'static {
     /// Returns the blocks present in the store.
     fn blocks(&self, hashes: Vec<H256>) -> Result<Vec<LightEthereumBlock>, Error>;
 
+    /// The data of the block with `hash` exactly as it is stored, i.e., as
+    /// it was received from the Ethereum node, or `None` if the block is
+    /// not in the store
+    fn block_data(&self, hash: H256) -> Result<Option<serde_json::Value>, Error>;
+
     /// Get the `offset`th ancestor of `block_hash`, where offset=0 means the block matching
     /// `block_hash` and offset=1 means its parent. Returns None if unable to complete due to
     /// missing blocks in the chain store.
@@ -1439,6 +2035,16 @@ pub trait ChainStore: Send + Sync + 'static {
     /// Confirm that block number `number` has hash `hash` and that the store
     /// may purge any other blocks with that number
     fn confirm_block_hash(&self, number: u64, hash: &H256) -> Result<usize, Error>;
+
+    /// The number of the block that the slowest deployment of this chain
+    /// that is synced, assigned, and has not failed has processed, or
+    /// `None` if there is no such deployment
+    fn slowest_synced_block(&self) -> Result<Option<BlockNumber>, Error>;
+
+    /// The logs bloom filters of the cached blocks with numbers from `from`
+    /// to `to`, inclusive, by block number. Numbers that the store has no
+    /// block or more than one block for are left out
+    fn logs_blooms(&self, from: u64, to: u64) -> Result<Vec<(u64, H2048)>, Error>;
 }
 
 pub trait EthereumCallCache: Send + Sync + 'static {
@@ -1458,6 +2064,47 @@ pub trait EthereumCallCache: Send + Sync + 'static {
         block: EthereumBlockPointer,
         return_value: &[u8],
     ) -> Result<(), Error>;
+
+    /// The calls cached for blocks with the number of `block`. Since the
+    /// cache does not record block hashes, this includes calls for other
+    /// blocks with the same number that were later reorged away
+    fn get_calls_in_block(
+        &self,
+        block: EthereumBlockPointer,
+    ) -> Result<Vec<CachedEthereumCall>, Error>;
+}
+
+/// The blocks that mark where the data of a deployment begins and up to
+/// where it is free of errors, as reported in `_meta`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DeploymentBlocks {
+    /// The lowest start block of the data sources in the manifest
+    pub start_block: u64,
+    /// The first block the deployment processed, according to the earliest
+    /// block in its metadata, which is the block before the start block
+    pub earliest_block: u64,
+    /// The latest block the deployment processed without errors, or `None`
+    /// if it has not processed any block without errors
+    pub last_healthy_block: Option<u64>,
+}
+
+/// The block that a deployment had processed at some point in time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SyncSample {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub block_number: u64,
+}
+
+/// A contract call whose result is in the `EthereumCallCache`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedEthereumCall {
+    /// The hash of the encoded call, the contract address and the block
+    /// hash, which identifies the call in the cache
+    pub blake3_id: Vec<u8>,
+    pub block_ptr: EthereumBlockPointer,
+    pub contract_address: Address,
+    pub return_value: Vec<u8>,
 }
 
 /// An entity operation that can be transacted into the store; as opposed to
@@ -11,6 +11,67 @@ use crate::components::subgraph::SharedProofOfIndexing;
 use crate::prelude::*;
 use web3::types::{Log, Transaction};
 
+/// The error of a failed handler; it reads exactly like the error that
+/// caused it, but makes it possible to find out which handler failed
+#[derive(Debug)]
+pub struct MappingError {
+    data_source: String,
+    handler: String,
+    deterministic: bool,
+    error: anyhow::Error,
+}
+
+impl MappingError {
+    pub fn new(data_source: &str, handler: &str, error: anyhow::Error) -> Self {
+        MappingError {
+            data_source: data_source.to_owned(),
+            handler: handler.to_owned(),
+            deterministic: error.downcast_ref::<NonDeterministicError>().is_none(),
+            error,
+        }
+    }
+
+    pub fn data_source(&self) -> &str {
+        &self.data_source
+    }
+
+    pub fn handler(&self) -> &str {
+        &self.handler
+    }
+
+    /// Whether running the handler again would fail in the same way,
+    /// i.e., whether the failure is caused by the mapping and the data it
+    /// got rather than by something like an Ethereum node that did not
+    /// answer a call
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+}
+
+impl fmt::Display for MappingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Include the whole chain so that wrapping the error does not hide
+        // any of it
+        write!(f, "{:#}", self.error)
+    }
+}
+
+impl std::error::Error for MappingError {}
+
+/// Marks a handler failure whose cause lies outside of the mapping, like
+/// a failed Ethereum call, a store error or a timeout, so that running the
+/// handler again might succeed. Reads exactly like the error it wraps
+#[derive(Debug)]
+pub struct NonDeterministicError(pub anyhow::Error);
+
+impl fmt::Display for NonDeterministicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+impl std::error::Error for NonDeterministicError {}
+
 /// Common trait for runtime host implementations.
 #[async_trait]
 pub trait RuntimeHost: Send + Sync + Debug + 'static {
@@ -54,6 +115,26 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, anyhow::Error>;
+
+    /// Returns true if the RuntimeHost has a handler for changes to
+    /// entities of `entity_type` in the deployment `source`.
+    fn matches_entity_change(
+        &self,
+        source: &SubgraphDeploymentId,
+        entity_type: &str,
+        block_number: u64,
+    ) -> bool;
+
+    /// Process a change that another deployment made to one of its
+    /// entities in `block`
+    async fn process_entity_change(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        change: &EntityOperation,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, anyhow::Error>;
 }
 
 pub struct HostMetrics {
@@ -0,0 +1,248 @@
+//! Deploying subgraphs from a local directory or an HTTP URL instead of
+//! IPFS. The manifest and the files it references are copied into the
+//! `DeploymentFiles` of the store, and the links in the manifest are
+//! replaced with the ids of the copies, so that the deployment is resolved
+//! like one from IPFS afterwards.
+
+use failure::{format_err, Error};
+use serde_yaml::{Mapping, Value};
+use slog::{info, Logger};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use tiny_keccak::keccak256;
+use url::Url;
+
+use crate::components::link_resolver::DeploymentFiles;
+use crate::data::subgraph::SubgraphDeploymentId;
+
+/// The ids of imported files start with this, which IPFS hashes never do
+const LOCAL_FILE_PREFIX: &str = "local_";
+
+/// The manifest of a subgraph deployed from a directory
+const MANIFEST_FILE: &str = "subgraph.yaml";
+
+/// Whether `path` is the id of an imported file rather than an IPFS hash
+pub fn is_local_file(path: &str) -> bool {
+    path.starts_with(LOCAL_FILE_PREFIX)
+}
+
+/// The id of the file with the contents `data`. It is short enough to also
+/// be the id of a deployment
+fn local_file_id(data: &[u8]) -> String {
+    format!(
+        "{}{}",
+        LOCAL_FILE_PREFIX,
+        hex::encode(&keccak256(data)[..20])
+    )
+}
+
+/// Where a file is read from
+#[derive(Clone, Debug, PartialEq)]
+enum Location {
+    File(PathBuf),
+    Url(Url),
+}
+
+impl Location {
+    fn parse(location: &str) -> Result<Self, Error> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            return Ok(Location::Url(Url::parse(location)?));
+        }
+        let path = PathBuf::from(location);
+        if path.is_dir() {
+            Ok(Location::File(path.join(MANIFEST_FILE)))
+        } else {
+            Ok(Location::File(path))
+        }
+    }
+
+    /// The location of `file`, relative to this location
+    fn join(&self, file: &str) -> Result<Self, Error> {
+        match self {
+            Location::File(path) => Ok(Location::File(match path.parent() {
+                Some(dir) => dir.join(file),
+                None => PathBuf::from(file),
+            })),
+            Location::Url(url) => Ok(Location::Url(url.join(file)?)),
+        }
+    }
+
+    async fn read(&self) -> Result<Vec<u8>, Error> {
+        let data = match self {
+            Location::File(path) => {
+                let path = path.clone();
+                crate::spawn_blocking_async_allow_panic(move || std::fs::read(path))
+                    .await
+                    .map_err(Error::from)
+            }
+            Location::Url(url) => {
+                async {
+                    let response = reqwest::get(url.clone()).await?.error_for_status()?;
+                    Ok::<_, Error>(response.bytes().await?.to_vec())
+                }
+                .await
+            }
+        };
+        data.map_err(|e| format_err!("failed to read `{}`: {}", self, e))
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::File(path) => write!(f, "{}", path.display()),
+            Location::Url(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// Collect the values of all `file` keys in `value` that are paths or
+/// URLs; links to IPFS are mappings instead
+fn relative_files(value: &Value, files: &mut Vec<String>) {
+    match value {
+        Value::Mapping(map) => {
+            for (key, value) in map {
+                match (key.as_str(), value) {
+                    (Some("file"), Value::String(file)) => files.push(file.clone()),
+                    (_, value) => relative_files(value, files),
+                }
+            }
+        }
+        Value::Sequence(values) => {
+            for value in values {
+                relative_files(value, files)
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replace the paths and URLs in `file` keys with links to the ids of the
+/// copies of the files in `ids`
+fn replace_files(value: Value, ids: &HashMap<String, String>) -> Value {
+    match value {
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let value = match (key.as_str(), value) {
+                        (Some("file"), Value::String(file)) => {
+                            let mut link = Mapping::new();
+                            link.insert(
+                                Value::String("/".to_owned()),
+                                Value::String(ids[&file].clone()),
+                            );
+                            Value::Mapping(link)
+                        }
+                        (_, value) => replace_files(value, ids),
+                    };
+                    (key, value)
+                })
+                .collect(),
+        ),
+        Value::Sequence(values) => Value::Sequence(
+            values
+                .into_iter()
+                .map(|value| replace_files(value, ids))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Copy the subgraph at `location` into `files`. The manifest is read from
+/// the path or HTTP(S) URL `location`, or from `subgraph.yaml` if
+/// `location` is a directory; the files it references are read relative to
+/// it. Returns the id of the deployment of the copied subgraph
+pub async fn import_subgraph(
+    logger: &Logger,
+    files: &dyn DeploymentFiles,
+    location: &str,
+) -> Result<SubgraphDeploymentId, Error> {
+    let location = Location::parse(location)?;
+    info!(logger, "Import subgraph"; "location" => location.to_string());
+
+    let manifest: Value = serde_yaml::from_slice(&location.read().await?)
+        .map_err(|e| format_err!("failed to parse manifest `{}`: {}", location, e))?;
+
+    let mut paths = vec![];
+    relative_files(&manifest, &mut paths);
+    let mut ids = HashMap::new();
+    for path in paths {
+        if ids.contains_key(&path) {
+            continue;
+        }
+        let data = location.join(&path)?.read().await?;
+        let id = local_file_id(&data);
+        files.insert(&id, &data)?;
+        ids.insert(path, id);
+    }
+
+    let manifest = serde_yaml::to_string(&replace_files(manifest, &ids))?;
+    let id = local_file_id(manifest.as_bytes());
+    files.insert(&id, manifest.as_bytes())?;
+    SubgraphDeploymentId::new(id.clone())
+        .map_err(|()| format_err!("invalid deployment id `{}`", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Files(Mutex<HashMap<String, Vec<u8>>>);
+
+    impl DeploymentFiles for Files {
+        fn get(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+            Ok(self.0.lock().unwrap().get(id).cloned())
+        }
+
+        fn insert(&self, id: &str, data: &[u8]) -> Result<(), Error> {
+            self.0.lock().unwrap().insert(id.to_owned(), data.to_vec());
+            Ok(())
+        }
+    }
+
+    const MANIFEST: &str = "
+specVersion: 0.0.1
+schema:
+  file: ./schema.graphql
+dataSources:
+  - kind: ethereum/contract
+    name: Token
+    mapping:
+      file:
+        /: /ipfs/QmWasm
+      abis:
+        - name: Token
+          file: ./abis/Token.json
+";
+
+    #[tokio::test]
+    async fn imports_subgraph_from_directory() {
+        let dir = std::env::temp_dir().join(format!("graph-import-{}", std::process::id()));
+        fs::create_dir_all(dir.join("abis")).unwrap();
+        fs::write(dir.join(MANIFEST_FILE), MANIFEST).unwrap();
+        fs::write(dir.join("schema.graphql"), "type Thing @entity { id: ID! }").unwrap();
+        fs::write(dir.join("abis").join("Token.json"), "[]").unwrap();
+
+        let files = Files::default();
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let result = import_subgraph(&logger, &files, dir.to_str().unwrap()).await;
+        fs::remove_dir_all(&dir).unwrap();
+        let id = result.unwrap().to_string();
+        assert!(is_local_file(&id));
+
+        let files = files.0.into_inner().unwrap();
+        let manifest: Value = serde_yaml::from_slice(&files[&id]).unwrap();
+        let schema = manifest["schema"]["file"]["/"].as_str().unwrap();
+        assert!(is_local_file(schema));
+        assert_eq!(&b"type Thing @entity { id: ID! }"[..], &files[schema][..]);
+        let mapping = &manifest["dataSources"][0]["mapping"];
+        let abi = mapping["abis"][0]["file"]["/"].as_str().unwrap();
+        assert_eq!(&b"[]"[..], &files[abi][..]);
+        assert_eq!(Some("/ipfs/QmWasm"), mapping["file"]["/"].as_str());
+    }
+}
@@ -1,8 +1,9 @@
 use async_trait::async_trait;
 use web3::types::Log;
 
-use crate::components::subgraph::SharedProofOfIndexing;
+use crate::components::subgraph::{SharedProofOfIndexing, WriteAudit};
 use crate::prelude::*;
+use crate::trace::TraceContext;
 use crate::util::lfu_cache::LfuCache;
 
 #[derive(Clone, Debug)]
@@ -17,6 +18,15 @@ pub struct DataSourceTemplateInfo {
 pub struct BlockState {
     pub entity_cache: EntityCache,
     pub created_data_sources: Vec<DataSourceTemplateInfo>,
+    /// The span of the block or handler that is being processed
+    pub trace: Option<TraceContext>,
+    /// What the handlers did in this block
+    pub write_audit: WriteAudit,
+    /// How many data sources the deployment had before this block, and
+    /// how many handlers created in it; used to enforce the limits on
+    /// creating data sources
+    pub data_source_count: usize,
+    pub data_sources_created: usize,
 }
 
 impl BlockState {
@@ -24,6 +34,10 @@ impl BlockState {
         BlockState {
             entity_cache: EntityCache::with_current(store, lfu_cache),
             created_data_sources: Vec::new(),
+            trace: None,
+            write_audit: WriteAudit::default(),
+            data_source_count: 0,
+            data_sources_created: 0,
         }
     }
 }
@@ -54,6 +68,18 @@ pub trait SubgraphInstance<H: RuntimeHost> {
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, anyhow::Error>;
 
+    /// Process the change that the deployment `source` made to one of its
+    /// entities in `block` in the hosts that handle it.
+    async fn process_entity_change(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        source: &SubgraphDeploymentId,
+        change: &EntityOperation,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, anyhow::Error>;
+
     /// Adds dynamic data sources to the subgraph.
     fn add_dynamic_data_source(
         &mut self,
@@ -1,14 +1,19 @@
 mod host;
+mod import;
 mod instance;
 mod instance_manager;
 mod loader;
 mod proof_of_indexing;
 mod provider;
 mod registrar;
+pub mod write_audit;
 
 pub use crate::prelude::Entity;
 
-pub use self::host::{HostMetrics, RuntimeHost, RuntimeHostBuilder};
+pub use self::host::{
+    HostMetrics, MappingError, NonDeterministicError, RuntimeHost, RuntimeHostBuilder,
+};
+pub use self::import::{import_subgraph, is_local_file};
 pub use self::instance::{BlockState, DataSourceTemplateInfo, SubgraphInstance};
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::loader::DataSourceLoader;
@@ -17,4 +22,5 @@ pub use self::proof_of_indexing::{
     SharedProofOfIndexing,
 };
 pub use self::provider::SubgraphAssignmentProvider;
-pub use self::registrar::{SubgraphRegistrar, SubgraphVersionSwitchingMode};
+pub use self::registrar::{DeploymentPlacer, SubgraphRegistrar, SubgraphVersionSwitchingMode};
+pub use self::write_audit::WriteAudit;
@@ -18,6 +18,15 @@ impl SubgraphVersionSwitchingMode {
     }
 }
 
+/// Decides where new deployments go. Consulted by the registrar when a
+/// deployment is created without an explicit node assignment.
+pub trait DeploymentPlacer: Send + Sync + 'static {
+    /// Return the index nodes that may index a deployment of the subgraph
+    /// `name` on `network`, or `None` if no placement rule applies, in
+    /// which case the registrar's default node is used
+    fn place(&self, name: &str, network: &str) -> Result<Option<Vec<NodeId>>, String>;
+}
+
 /// Common trait for subgraph registrars.
 #[async_trait]
 pub trait SubgraphRegistrar: Send + Sync + 'static {
@@ -26,12 +35,27 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         name: SubgraphName,
     ) -> Result<CreateSubgraphResult, SubgraphRegistrarError>;
 
+    /// Deploy `hash` as a new version of the subgraph `name`. Returns the
+    /// problems that validating the manifest found but that did not prevent
+    /// the deployment. With `ignore_graft_depth`, the deployment may be
+    /// grafted onto a longer chain of grafts than `GRAPH_MAX_GRAFT_DEPTH`
     async fn create_subgraph_version(
         &self,
         name: SubgraphName,
         hash: SubgraphDeploymentId,
-        assignment_node_id: NodeId,
-    ) -> Result<(), SubgraphRegistrarError>;
+        assignment_node_id: Option<NodeId>,
+        version_label: Option<String>,
+        ignore_graft_depth: bool,
+    ) -> Result<Vec<SubgraphManifestValidationWarning>, SubgraphRegistrarError>;
+
+    /// Copy the subgraph with the manifest at `location`, a path, a
+    /// directory or an HTTP(S) URL, and the files the manifest references
+    /// into the store, so that it can be deployed without IPFS. Returns the
+    /// id to deploy it with
+    async fn import_subgraph(
+        &self,
+        location: &str,
+    ) -> Result<SubgraphDeploymentId, SubgraphRegistrarError>;
 
     async fn remove_subgraph(&self, name: SubgraphName) -> Result<(), SubgraphRegistrarError>;
 
@@ -40,4 +64,30 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         hash: SubgraphDeploymentId,
         node_id: NodeId,
     ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Pause indexing `hash` for maintenance, or resume it. Queries keep
+    /// being answered at the latest block that was indexed
+    async fn set_maintenance(
+        &self,
+        hash: SubgraphDeploymentId,
+        maintenance: bool,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Create a Postgres publication for the tables of `hash`, for tools
+    /// that follow changes through logical replication, or drop it if
+    /// `publish` is `false`. Returns the name of the publication
+    async fn set_publication(
+        &self,
+        hash: SubgraphDeploymentId,
+        publish: bool,
+        replica_identity: Option<ReplicaIdentity>,
+    ) -> Result<Option<String>, SubgraphRegistrarError>;
+
+    /// Rewind `hash` so that its latest block is `block_ptr`. The
+    /// deployment must not be assigned to any index node
+    async fn rewind_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        block_ptr: EthereumBlockPointer,
+    ) -> Result<(), SubgraphRegistrarError>;
 }
@@ -0,0 +1,197 @@
+//! Counts of the entity writes and `eth_call`s of each handler in the blocks
+//! that deployments processed most recently on this node. When two indexers
+//! compute different proofs of indexing, comparing these counts shows which
+//! handler in which block diverged without indexing the deployment again.
+
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+
+use crate::data::subgraph::SubgraphDeploymentId;
+use crate::prelude::EthereumBlockPointer;
+use crate::util::env::env_var;
+
+lazy_static! {
+    /// How many blocks to keep counts for per deployment; 0 turns the
+    /// counting off
+    static ref MAX_BLOCKS: usize = env_var("GRAPH_WRITE_AUDIT_BLOCKS").unwrap_or(100);
+
+    static ref AUDITS: RwLock<HashMap<SubgraphDeploymentId, VecDeque<BlockAudit>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// What one handler did in one block, summed over all the times it ran
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HandlerAudit {
+    pub data_source: String,
+    pub handler: String,
+    pub invocations: u64,
+    pub entity_sets: u64,
+    pub entity_removes: u64,
+    pub eth_calls: u64,
+}
+
+/// The counts of all handlers that ran in a block, in the order in which
+/// they first ran
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockAudit {
+    pub block: EthereumBlockPointer,
+    pub handlers: Vec<HandlerAudit>,
+}
+
+/// Collects the counts while a block is being processed
+#[derive(Clone, Debug, Default)]
+pub struct WriteAudit {
+    handlers: Vec<HandlerAudit>,
+    /// The index of the handler that is running in `handlers`
+    current: Option<usize>,
+}
+
+impl WriteAudit {
+    /// Count what happens from now on for `handler` of `data_source`
+    pub fn enter(&mut self, data_source: &str, handler: &str) {
+        let index = match self
+            .handlers
+            .iter()
+            .position(|audit| audit.data_source == data_source && audit.handler == handler)
+        {
+            Some(index) => index,
+            None => {
+                self.handlers.push(HandlerAudit {
+                    data_source: data_source.to_owned(),
+                    handler: handler.to_owned(),
+                    ..Default::default()
+                });
+                self.handlers.len() - 1
+            }
+        };
+        self.handlers[index].invocations += 1;
+        self.current = Some(index);
+    }
+
+    fn current(&mut self) -> Option<&mut HandlerAudit> {
+        let handlers = &mut self.handlers;
+        self.current.and_then(move |index| handlers.get_mut(index))
+    }
+
+    pub fn entity_set(&mut self) {
+        if let Some(audit) = self.current() {
+            audit.entity_sets += 1;
+        }
+    }
+
+    pub fn entity_remove(&mut self) {
+        if let Some(audit) = self.current() {
+            audit.entity_removes += 1;
+        }
+    }
+
+    pub fn eth_call(&mut self) {
+        if let Some(audit) = self.current() {
+            audit.eth_calls += 1;
+        }
+    }
+}
+
+/// Keep the counts of `audit` for `block` of `deployment`, forgetting the
+/// counts of the oldest block if there are too many. Counts for blocks at
+/// or after `block` were reverted and are dropped
+pub fn record(deployment: &SubgraphDeploymentId, block: EthereumBlockPointer, audit: WriteAudit) {
+    if *MAX_BLOCKS == 0 {
+        return;
+    }
+    let mut audits = AUDITS.write().unwrap();
+    let blocks = audits.entry(deployment.clone()).or_default();
+    while blocks
+        .back()
+        .map_or(false, |last| last.block.number >= block.number)
+    {
+        blocks.pop_back();
+    }
+    blocks.push_back(BlockAudit {
+        block,
+        handlers: audit.handlers,
+    });
+    while blocks.len() > *MAX_BLOCKS {
+        blocks.pop_front();
+    }
+}
+
+/// The counts for the blocks of `deployment` that are still kept, oldest
+/// first
+pub fn blocks(deployment: &SubgraphDeploymentId) -> Vec<BlockAudit> {
+    AUDITS
+        .read()
+        .unwrap()
+        .get(deployment)
+        .map(|blocks| blocks.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Forget the counts of `deployment`, e.g., because it was unassigned
+pub fn forget(deployment: &SubgraphDeploymentId) {
+    AUDITS.write().unwrap().remove(deployment);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::H256;
+
+    fn block(number: u64) -> EthereumBlockPointer {
+        EthereumBlockPointer {
+            hash: H256::from_low_u64_be(number),
+            number,
+        }
+    }
+
+    #[test]
+    fn counts_per_handler_and_drops_reverted_blocks() {
+        let deployment = SubgraphDeploymentId::new("QmWriteAudit").unwrap();
+
+        let mut audit = WriteAudit::default();
+        audit.entity_set();
+        audit.enter("Token", "handleTransfer");
+        audit.entity_set();
+        audit.eth_call();
+        audit.enter("Token", "handleApproval");
+        audit.entity_remove();
+        audit.enter("Token", "handleTransfer");
+        audit.entity_set();
+        record(&deployment, block(1), audit);
+        record(&deployment, block(2), WriteAudit::default());
+
+        let audits = blocks(&deployment);
+        assert_eq!(2, audits.len());
+        assert_eq!(
+            vec![
+                HandlerAudit {
+                    data_source: "Token".to_owned(),
+                    handler: "handleTransfer".to_owned(),
+                    invocations: 2,
+                    entity_sets: 2,
+                    entity_removes: 0,
+                    eth_calls: 1,
+                },
+                HandlerAudit {
+                    data_source: "Token".to_owned(),
+                    handler: "handleApproval".to_owned(),
+                    invocations: 1,
+                    entity_sets: 0,
+                    entity_removes: 1,
+                    eth_calls: 0,
+                },
+            ],
+            audits[0].handlers
+        );
+
+        record(&deployment, block(2), WriteAudit::default());
+        record(&deployment, block(1), WriteAudit::default());
+        let audits = blocks(&deployment);
+        assert_eq!(1, audits.len());
+        assert!(audits[0].handlers.is_empty());
+
+        forget(&deployment);
+        assert!(blocks(&deployment).is_empty());
+    }
+}
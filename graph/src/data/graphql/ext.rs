@@ -1,7 +1,7 @@
 use crate::data::schema::SCHEMA_TYPE_NAME;
 use graphql_parser::schema::{
-    Definition, Directive, Document, EnumType, Field, InterfaceType, Name, ObjectType, Type,
-    TypeDefinition, Value,
+    Definition, Directive, Document, EnumType, EnumValue, Field, InterfaceType, Name, ObjectType,
+    Type, TypeDefinition, Value,
 };
 
 use std::collections::{BTreeMap, HashMap};
@@ -184,3 +184,36 @@ impl DirectiveFinder for Vec<Directive> {
         self.iter().find(|directive| directive.name.eq(&name))
     }
 }
+
+/// The reason that introspection reports for `@deprecated` directives
+/// without a `reason`
+pub const DEFAULT_DEPRECATION_REASON: &str = "No longer supported";
+
+pub trait DeprecationExt {
+    /// If this is marked `@deprecated`, the reason why
+    fn deprecation_reason(&self) -> Option<String>;
+}
+
+fn deprecation_reason(directives: &Vec<Directive>) -> Option<String> {
+    directives
+        .find_directive(String::from("deprecated"))
+        .map(|directive| {
+            directive
+                .argument("reason")
+                .and_then(Value::as_string)
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_DEPRECATION_REASON.to_owned())
+        })
+}
+
+impl DeprecationExt for Field {
+    fn deprecation_reason(&self) -> Option<String> {
+        deprecation_reason(&self.directives)
+    }
+}
+
+impl DeprecationExt for EnumValue {
+    fn deprecation_reason(&self) -> Option<String> {
+        deprecation_reason(&self.directives)
+    }
+}
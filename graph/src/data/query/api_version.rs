@@ -0,0 +1,135 @@
+//! Versions of the semantics of the GraphQL API of subgraphs. Changes to
+//! how queries are answered that would break existing clients are only
+//! made in a new version, and each deployment keeps the semantics of the
+//! version it was deployed with, unless a query asks for another one.
+
+use failure::{format_err, Error};
+use serde::{de, ser};
+use std::fmt;
+use std::str::FromStr;
+
+/// The version of the GraphQL API that a query is executed with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ApiVersion {
+    /// The semantics of all deployments that do not declare an
+    /// `apiVersion` in their manifest
+    V0_0_1,
+    /// Strict handling of `null`: a `null` in a `where` filter is only
+    /// allowed for equality and `_not`, and is an invalid argument for all
+    /// other comparisons instead of failing when the store runs the query
+    V0_0_2,
+}
+
+impl ApiVersion {
+    /// All versions that this node supports, from oldest to newest
+    pub const ALL: &'static [ApiVersion] = &[ApiVersion::V0_0_1, ApiVersion::V0_0_2];
+
+    pub fn latest() -> Self {
+        *Self::ALL.last().unwrap()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::V0_0_1 => "0.0.1",
+            ApiVersion::V0_0_2 => "0.0.2",
+        }
+    }
+
+    /// Whether `null` is checked where older versions pass it on unchecked
+    pub fn strict_nulls(&self) -> bool {
+        *self >= ApiVersion::V0_0_2
+    }
+
+    /// Determine the version from the `api-version` parameter in the query
+    /// string of a URL. Returns `None` if there is no such parameter, in
+    /// which case the version of the deployment should be used
+    pub fn from_url_query(query: Option<&str>) -> Result<Option<Self>, Error> {
+        query
+            .unwrap_or("")
+            .split('&')
+            .filter_map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some("api-version"), Some(value)) => Some(value),
+                    _ => None,
+                }
+            })
+            .last()
+            .map(str::parse)
+            .transpose()
+    }
+}
+
+impl Default for ApiVersion {
+    fn default() -> Self {
+        ApiVersion::V0_0_1
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Self::ALL
+            .iter()
+            .find(|version| version.as_str() == s)
+            .cloned()
+            .ok_or_else(|| {
+                format_err!(
+                    "Invalid API version {:?}, must be one of {}",
+                    s,
+                    Self::ALL
+                        .iter()
+                        .map(ApiVersion::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ser::Serialize for ApiVersion {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> de::Deserialize<'de> for ApiVersion {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s: String = de::Deserialize::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| de::Error::invalid_value(de::Unexpected::Str(&s), &"valid API version"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_api_versions() {
+        assert_eq!(ApiVersion::V0_0_2, "0.0.2".parse().unwrap());
+        assert!("0.0.3".parse::<ApiVersion>().is_err());
+        assert_eq!(ApiVersion::V0_0_1, ApiVersion::default());
+        assert_eq!(ApiVersion::V0_0_2, ApiVersion::latest());
+
+        assert_eq!(None, ApiVersion::from_url_query(None).unwrap());
+        assert_eq!(
+            Some(ApiVersion::V0_0_2),
+            ApiVersion::from_url_query(Some("version=pending&api-version=0.0.2")).unwrap()
+        );
+        assert!(ApiVersion::from_url_query(Some("api-version=1")).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//! The shapes of queries that operators have blocked at runtime through
+//! the admin server. Queries are identified by the hash of their shape,
+//! which is the `query_hash` in the query audit log, so that queries that
+//! only differ in the values of their arguments are blocked together.
+
+use failure::{format_err, Error};
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+lazy_static! {
+    /// Maps the hash of each blocked shape to the reason it was blocked
+    static ref BLOCKED: RwLock<BTreeMap<u64, Option<String>>> = RwLock::new(BTreeMap::new());
+}
+
+/// Parse a query hash as it appears in the audit log, i.e., as 16
+/// hexadecimal digits
+pub fn parse_hash(hash: &str) -> Result<u64, Error> {
+    if hash.len() != 16 {
+        return Err(format_err!(
+            "the query hash `{}` does not have 16 hexadecimal digits",
+            hash
+        ));
+    }
+    u64::from_str_radix(hash, 16)
+        .map_err(|_| format_err!("the query hash `{}` is not hexadecimal", hash))
+}
+
+pub fn format_hash(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+/// Reject queries with the shape `hash` until it is unblocked. Returns
+/// whether the shape was already blocked
+pub fn block(hash: u64, reason: Option<String>) -> bool {
+    BLOCKED.write().unwrap().insert(hash, reason).is_some()
+}
+
+/// Accept queries with the shape `hash` again. Returns whether the shape
+/// was blocked
+pub fn unblock(hash: u64) -> bool {
+    BLOCKED.write().unwrap().remove(&hash).is_some()
+}
+
+/// If the shape `hash` is blocked, the reason why
+pub fn check(hash: u64) -> Option<Option<String>> {
+    let blocked = BLOCKED.read().unwrap();
+    if blocked.is_empty() {
+        return None;
+    }
+    blocked.get(&hash).cloned()
+}
+
+/// All blocked shapes and the reasons they were blocked
+pub fn list() -> Vec<(u64, Option<String>)> {
+    BLOCKED
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(hash, reason)| (*hash, reason.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_and_unblocks_shapes() {
+        let hash = parse_hash("00000000deadbeef").unwrap();
+        assert_eq!("00000000deadbeef", format_hash(hash));
+        assert!(parse_hash("deadbeef").is_err());
+        assert!(parse_hash("00000000deadbeeg").is_err());
+
+        assert_eq!(None, check(hash));
+        assert!(!block(hash, Some("too slow".to_owned())));
+        assert_eq!(Some(Some("too slow".to_owned())), check(hash));
+        assert!(list().contains(&(hash, Some("too slow".to_owned()))));
+        assert!(block(hash, None));
+        assert_eq!(Some(None), check(hash));
+        assert!(unblock(hash));
+        assert!(!unblock(hash));
+        assert_eq!(None, check(hash));
+    }
+}
@@ -9,8 +9,9 @@ use std::fmt;
 use std::string::FromUtf8Error;
 use std::sync::Arc;
 
-use crate::components::store::StoreError;
+use crate::components::store::{BlockNumber, StoreError};
 use crate::data::graphql::SerializableValue;
+use crate::data::store::scalar;
 use crate::data::subgraph::*;
 
 #[derive(Debug)]
@@ -28,6 +29,10 @@ impl From<failure::Error> for CloneableFailureError {
     }
 }
 
+/// The `code` in the `extensions` of the error for queries that use too
+/// many aliases, so that clients can tell it apart from other errors
+pub const TOO_MANY_ALIASES_CODE: &str = "TOO_MANY_ALIASES";
+
 /// Error caused while executing a [Query](struct.Query.html).
 #[derive(Debug, Clone)]
 pub enum QueryExecutionError {
@@ -48,6 +53,7 @@ pub enum QueryExecutionError {
     ResolveEntitiesError(String),
     OrderByNotSupportedError(String, String),
     OrderByNotSupportedForType(String),
+    DistinctOnRequiresOrderBy(String, String), // (distinct_on, order_by)
     FilterNotSupportedError(String, String),
     UnknownField(Pos, String, String),
     EmptyQuery,
@@ -68,9 +74,14 @@ pub enum QueryExecutionError {
     Unimplemented(String),
     EnumCoercionError(Pos, String, q::Value, String, Vec<String>),
     ScalarCoercionError(Pos, String, q::Value, String),
-    TooComplex(u64, u64), // (complexity, max_complexity)
-    TooDeep(u8),          // max_depth
+    TooComplex(u64, u64),                  // (complexity, max_complexity)
+    TooDeep(u8),                           // max_depth
+    TooManyAliases(Option<String>, usize), // (field, max_aliases)
     TooExpensive,
+    LimitExceeded(String),
+    Blocked(String, Option<String>), // (query_hash, reason)
+    // The block is before the earliest block the deployment retains
+    HistoryNotAvailable { earliest: BlockNumber },
     UndefinedFragment(String),
     // Using slow and prefetch query resolution yield different results
     IncorrectPrefetchResult { slow: q::Value, prefetch: q::Value },
@@ -141,6 +152,11 @@ impl fmt::Display for QueryExecutionError {
             OrderByNotSupportedForType(field_type) => {
                 write!(f, "Ordering by `{}` fields is not supported", field_type)
             }
+            DistinctOnRequiresOrderBy(distinct_on, order_by) => write!(
+                f,
+                "`distinctOn: {}` requires ordering by `{}`, but the query is ordered by `{}`",
+                distinct_on, distinct_on, order_by
+            ),
             FilterNotSupportedError(value, filter) => {
                 write!(f, "Filter not supported by value `{}`: `{}`", value, filter)
             }
@@ -216,6 +232,8 @@ impl fmt::Display for QueryExecutionError {
                            return smaller collections", complexity, max_complexity)
             }
             TooDeep(max_depth) => write!(f, "query has a depth that exceeds the limit of `{}`", max_depth),
+            TooManyAliases(Some(field), max_aliases) => write!(f, "query aliases the field `{}` more than `{}` times", field, max_aliases),
+            TooManyAliases(None, max_aliases) => write!(f, "query has a selection set with more than `{}` aliased fields", max_aliases),
             UndefinedFragment(frag_name) => write!(f, "fragment `{}` is not defined", frag_name),
             IncorrectPrefetchResult{ .. } => write!(f, "Running query with prefetch \
                            and slow query resolution yielded different results. \
@@ -224,7 +242,18 @@ impl fmt::Display for QueryExecutionError {
             Panic(msg) => write!(f, "panic processing query: {}", msg),
             EventStreamError => write!(f, "error in the subscription event stream"),
             FulltextQueryRequiresFilter => write!(f, "fulltext search queries can only use EntityFilter::Equal"),
-            TooExpensive => write!(f, "query is too expensive")
+            TooExpensive => write!(f, "query is too expensive"),
+            LimitExceeded(msg) => write!(f, "{}", msg),
+            Blocked(hash, reason) => {
+                write!(f, "queries with the shape of this query (query hash `{}`) have been blocked by the operator of this node", hash)?;
+                match reason {
+                    Some(reason) => write!(f, ": {}", reason),
+                    None => Ok(()),
+                }
+            }
+            HistoryNotAvailable { earliest } => write!(f, "the history of this subgraph is only \
+                           available from block `{}` on, and queries for earlier blocks can not \
+                           be answered", earliest),
         }
     }
 }
@@ -253,6 +282,12 @@ impl From<bigdecimal::ParseBigDecimalError> for QueryExecutionError {
     }
 }
 
+impl From<scalar::GeoPointParseError> for QueryExecutionError {
+    fn from(e: scalar::GeoPointParseError) -> Self {
+        QueryExecutionError::ValueParseError("GeoPoint".to_string(), e.to_string())
+    }
+}
+
 impl From<StoreError> for QueryExecutionError {
     fn from(e: StoreError) -> Self {
         QueryExecutionError::StoreError(CloneableFailureError(Arc::new(e.into())))
@@ -316,15 +351,12 @@ impl Serialize for QueryError {
     {
         use self::QueryExecutionError::*;
 
-        let entry_count =
-            if let QueryError::ExecutionError(QueryExecutionError::IncorrectPrefetchResult {
-                ..
-            }) = self
-            {
-                3
-            } else {
-                1
-            };
+        let entry_count = match self {
+            QueryError::ExecutionError(IncorrectPrefetchResult { .. }) => 3,
+            QueryError::ExecutionError(HistoryNotAvailable { .. })
+            | QueryError::ExecutionError(TooManyAliases(_, _)) => 2,
+            _ => 1,
+        };
         let mut map = serializer.serialize_map(Some(entry_count))?;
 
         let msg = match self {
@@ -387,6 +419,18 @@ impl Serialize for QueryError {
                 map.serialize_entry("prefetch", &SerializableValue(&prefetch))?;
                 format!("{}", self)
             }
+            QueryError::ExecutionError(HistoryNotAvailable { earliest }) => {
+                let mut extensions = HashMap::new();
+                extensions.insert("earliestBlock", *earliest);
+                map.serialize_entry("extensions", &extensions)?;
+                format!("{}", self)
+            }
+            QueryError::ExecutionError(TooManyAliases(_, _)) => {
+                let mut extensions = HashMap::new();
+                extensions.insert("code", TOO_MANY_ALIASES_CODE);
+                map.serialize_entry("extensions", &extensions)?;
+                format!("{}", self)
+            }
             _ => format!("{}", self),
         };
 
@@ -0,0 +1,127 @@
+//! How often the fields of the schema of each deployment are executed, so
+//! that subgraph authors can see which fields clients still use before
+//! they remove them. This is off unless
+//! `GRAPH_QUERY_FIELD_USAGE_SAMPLE_RATE` is set; query execution then
+//! records that fraction of field executions. Counts are kept in memory
+//! since the node started and are scaled up by the sample rate when they
+//! are read, so that they estimate how often each field was executed.
+
+use lazy_static::lazy_static;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::util::env::env_var;
+
+lazy_static! {
+    /// The fraction of field executions that are recorded
+    static ref SAMPLE_RATE: f64 = env_var("GRAPH_QUERY_FIELD_USAGE_SAMPLE_RATE").unwrap_or(0.0);
+
+    static ref USAGE: RwLock<HashMap<String, DeploymentUsage>> = RwLock::new(HashMap::new());
+}
+
+/// The sampled executions of one field
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Counter {
+    sampled: u64,
+    /// When the field was last sampled, in seconds since the epoch
+    last_used: u64,
+}
+
+/// The sampled executions of the fields of one deployment, by type and
+/// field name
+#[derive(Default)]
+struct DeploymentUsage {
+    fields: BTreeMap<(String, String), Counter>,
+}
+
+/// How often a field was executed
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldUsage {
+    pub type_name: String,
+    pub field: String,
+    /// The estimated number of times the field was executed
+    pub count: u64,
+    /// When the field was last seen in a sample, in seconds since the epoch
+    pub last_used: u64,
+}
+
+/// Whether field usage is recorded at all
+pub fn is_enabled() -> bool {
+    *SAMPLE_RATE > 0.0
+}
+
+/// Whether to record the execution of a field that is about to happen
+pub fn should_sample() -> bool {
+    is_enabled() && rand::thread_rng().gen::<f64>() < *SAMPLE_RATE
+}
+
+/// Record that `field` of `type_name` was executed for a query against
+/// `deployment`. Callers decide with `should_sample` which executions to
+/// record
+pub fn record(deployment: &str, type_name: &str, field: &str) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let mut usage = USAGE.write().unwrap();
+    let counter = usage
+        .entry(deployment.to_owned())
+        .or_default()
+        .fields
+        .entry((type_name.to_owned(), field.to_owned()))
+        .or_default();
+    counter.sampled += 1;
+    counter.last_used = now;
+}
+
+/// The usage of the fields of `deployment` that were executed since the
+/// node started, ordered by type and field name. Fields that were never
+/// sampled are not included
+pub fn usage(deployment: &str) -> Vec<FieldUsage> {
+    scaled_usage(deployment, *SAMPLE_RATE)
+}
+
+fn scaled_usage(deployment: &str, sample_rate: f64) -> Vec<FieldUsage> {
+    let usage = USAGE.read().unwrap();
+    let fields = match usage.get(deployment) {
+        Some(deployment) => &deployment.fields,
+        None => return vec![],
+    };
+    fields
+        .iter()
+        .map(|((type_name, field), counter)| FieldUsage {
+            type_name: type_name.clone(),
+            field: field.clone(),
+            count: if sample_rate > 0.0 && sample_rate < 1.0 {
+                (counter.sampled as f64 / sample_rate).round() as u64
+            } else {
+                counter.sampled
+            },
+            last_used: counter.last_used,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_usage_per_deployment() {
+        record("QmUsage", "Thing", "name");
+        record("QmUsage", "Thing", "name");
+        record("QmUsage", "Query", "things");
+        record("QmOther", "Thing", "name");
+
+        let usage = scaled_usage("QmUsage", 0.5);
+        let counts: Vec<_> = usage
+            .iter()
+            .map(|usage| (usage.type_name.as_str(), usage.field.as_str(), usage.count))
+            .collect();
+        assert_eq!(vec![("Query", "things", 2), ("Thing", "name", 4)], counts);
+        assert!(usage.iter().all(|usage| usage.last_used > 0));
+        assert!(scaled_usage("QmUnknown", 0.5).is_empty());
+    }
+}
@@ -1,7 +1,13 @@
+mod api_version;
+pub mod blocklist;
 mod error;
+pub mod field_usage;
+pub mod plans;
 mod query;
+pub mod quota;
 mod result;
 
-pub use self::error::{QueryError, QueryExecutionError};
+pub use self::api_version::ApiVersion;
+pub use self::error::{QueryError, QueryExecutionError, TOO_MANY_ALIASES_CODE};
 pub use self::query::{Query, QueryVariables};
 pub use self::result::QueryResult;
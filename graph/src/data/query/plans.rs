@@ -0,0 +1,204 @@
+//! The query plans of the SQL that the slowest GraphQL queries run. This
+//! is off unless `GRAPH_SQL_EXPLAIN_THRESHOLD` is set; the store then runs
+//! a sample of the SQL statements that take longer than that again with
+//! `EXPLAIN (ANALYZE, BUFFERS)` and records the plan under the shape of the
+//! GraphQL query, i.e., its `query_hash` in the query audit log. For each
+//! shape, the plan of the slowest statement is kept.
+//!
+//! Independently of that, `explain` runs a GraphQL query with the store
+//! explaining its SQL statements instead of running them, so that
+//! developers can see what SQL their queries lead to.
+
+use lazy_static::lazy_static;
+use rand::Rng;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::util::env::env_var;
+
+/// How many shapes to keep plans for; the plans of the fastest statements
+/// are dropped first
+const MAX_PLANS: usize = 100;
+
+lazy_static! {
+    static ref THRESHOLD: Option<Duration> =
+        env_var("GRAPH_SQL_EXPLAIN_THRESHOLD").map(Duration::from_millis);
+
+    /// The fraction of slow statements that are explained
+    static ref SAMPLE_RATE: f64 = env_var("GRAPH_SQL_EXPLAIN_SAMPLE_RATE").unwrap_or(0.1);
+
+    static ref PLANS: RwLock<BTreeMap<u64, QueryPlan>> = RwLock::new(BTreeMap::new());
+}
+
+thread_local! {
+    /// While `explain` runs on this thread, the deployment whose SQL
+    /// statements are explained, and the statements explained so far
+    static EXPLAINING: RefCell<Option<(String, Vec<SqlExplanation>)>> = RefCell::new(None);
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryPlan {
+    /// The deployment that the statement queried
+    pub subgraph: String,
+    /// The statement, with its bind variables
+    pub sql: String,
+    /// The output of `EXPLAIN (ANALYZE, BUFFERS)`
+    pub plan: String,
+    /// How long the statement took when it was first run
+    pub duration: Duration,
+}
+
+/// A SQL statement that the store would run for a GraphQL query
+#[derive(Clone, Debug, PartialEq)]
+pub struct SqlExplanation {
+    /// The statement, with its bind variables
+    pub sql: String,
+    /// The output of `EXPLAIN`, without running the statement
+    pub plan: String,
+}
+
+/// Restores what was explained before `explain` when dropped, also if the
+/// closure passed to `explain` panics
+struct Explaining {
+    previous: Option<(String, Vec<SqlExplanation>)>,
+}
+
+impl Drop for Explaining {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        EXPLAINING.with(|explaining| explaining.replace(previous));
+    }
+}
+
+/// Run `f` with the store explaining the SQL statements that query the
+/// entities of `deployment` instead of running them. These statements
+/// return no entities, and so nested fields whose statements need the
+/// entities of their parent field are not explained. Only statements that
+/// run on this thread are explained. Returns what `f` returns together
+/// with the explained statements, in the order in which they would run
+pub fn explain<T>(deployment: &str, f: impl FnOnce() -> T) -> (T, Vec<SqlExplanation>) {
+    let _explaining = Explaining {
+        previous: EXPLAINING
+            .with(|explaining| explaining.replace(Some((deployment.to_owned(), Vec::new())))),
+    };
+    let result = f();
+    let explained = EXPLAINING.with(|explaining| {
+        explaining
+            .borrow_mut()
+            .as_mut()
+            .map(|(_, explained)| std::mem::replace(explained, Vec::new()))
+            .unwrap_or_default()
+    });
+    (result, explained)
+}
+
+/// Whether the SQL statements for the entities of `deployment` should be
+/// explained rather than run
+pub fn is_explaining(deployment: &str) -> bool {
+    EXPLAINING.with(|explaining| match &*explaining.borrow() {
+        Some((explained, _)) => explained == deployment,
+        None => false,
+    })
+}
+
+/// Add `explanation` to the statements that `explain` returns
+pub fn add_explanation(explanation: SqlExplanation) {
+    EXPLAINING.with(|explaining| {
+        if let Some((_, explained)) = explaining.borrow_mut().as_mut() {
+            explained.push(explanation);
+        }
+    })
+}
+
+/// Whether to explain a statement that took `duration`
+pub fn should_explain(duration: Duration) -> bool {
+    match *THRESHOLD {
+        Some(threshold) if duration >= threshold => rand::thread_rng().gen::<f64>() < *SAMPLE_RATE,
+        _ => false,
+    }
+}
+
+/// Record `plan` for the query shape `hash`, unless a slower statement of
+/// that shape was recorded before
+pub fn record(hash: u64, plan: QueryPlan) {
+    let mut plans = PLANS.write().unwrap();
+    match plans.get(&hash) {
+        Some(previous) if previous.duration >= plan.duration => return,
+        _ => {}
+    }
+    plans.insert(hash, plan);
+    if plans.len() > MAX_PLANS {
+        let fastest = plans
+            .iter()
+            .min_by_key(|(_, plan)| plan.duration)
+            .map(|(hash, _)| *hash);
+        if let Some(fastest) = fastest {
+            plans.remove(&fastest);
+        }
+    }
+}
+
+/// The plan recorded for the query shape `hash`
+pub fn get(hash: u64) -> Option<QueryPlan> {
+    PLANS.read().unwrap().get(&hash).cloned()
+}
+
+/// All recorded plans, by query shape
+pub fn list() -> Vec<(u64, QueryPlan)> {
+    PLANS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(hash, plan)| (*hash, plan.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(millis: u64) -> QueryPlan {
+        QueryPlan {
+            subgraph: "QmSubgraph".to_owned(),
+            sql: "select 1".to_owned(),
+            plan: format!("Result (actual time={}ms)", millis),
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn keeps_slowest_plans() {
+        record(1, plan(20));
+        record(1, plan(10));
+        assert_eq!(Some(plan(20)), get(1));
+        record(1, plan(30));
+        assert_eq!(Some(plan(30)), get(1));
+
+        for hash in 2..(MAX_PLANS as u64 + 2) {
+            record(hash, plan(100 + hash));
+        }
+        assert_eq!(MAX_PLANS, list().len());
+        assert_eq!(None, get(1));
+        assert_eq!(Some(plan(102)), get(2));
+    }
+
+    #[test]
+    fn explains_only_within_explain() {
+        let explanation = |sql: &str| SqlExplanation {
+            sql: sql.to_owned(),
+            plan: "Result".to_owned(),
+        };
+
+        assert!(!is_explaining("QmSubgraph"));
+        add_explanation(explanation("select 0"));
+        let (result, explained) = explain("QmSubgraph", || {
+            add_explanation(explanation("select 1"));
+            (is_explaining("QmSubgraph"), is_explaining("QmOther"))
+        });
+        assert_eq!((true, false), result);
+        assert_eq!(vec![explanation("select 1")], explained);
+        assert!(!is_explaining("QmSubgraph"));
+    }
+}
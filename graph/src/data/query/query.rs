@@ -5,6 +5,7 @@ use std::collections::{BTreeMap, HashMap};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 
+use crate::data::query::ApiVersion;
 use crate::data::schema::Schema;
 
 fn deserialize_number<'de, D>(deserializer: D) -> Result<q::Number, D::Error>
@@ -109,6 +110,14 @@ pub struct Query {
     pub schema: Arc<Schema>,
     pub document: q::Document,
     pub variables: Option<QueryVariables>,
+    /// Who sent the query, for the query audit log
+    pub client: Option<String>,
+    /// The operation to execute if the document contains more than one
+    pub operation_name: Option<String>,
+    /// Identifies the query in logs, traces and the SQL it causes
+    pub query_id: String,
+    /// The version of the API semantics to execute the query with
+    pub api_version: ApiVersion,
     _force_use_of_new: (),
 }
 
@@ -122,7 +131,32 @@ impl Query {
             schema,
             document,
             variables,
+            client: None,
+            operation_name: None,
+            query_id: uuid::Uuid::new_v4().to_string(),
+            api_version: ApiVersion::default(),
             _force_use_of_new: (),
         }
     }
+
+    pub fn with_client(self, client: String) -> Self {
+        Query {
+            client: Some(client),
+            ..self
+        }
+    }
+
+    pub fn with_operation_name(self, operation_name: Option<String>) -> Self {
+        Query {
+            operation_name,
+            ..self
+        }
+    }
+
+    pub fn with_api_version(self, api_version: ApiVersion) -> Self {
+        Query {
+            api_version,
+            ..self
+        }
+    }
 }
@@ -0,0 +1,394 @@
+//! Monthly query quotas for API tokens. Operators give tokens a quota
+//! through the admin server; the quotas and how many queries each token
+//! made in a month are kept in the store, so that they are shared by all
+//! query nodes and survive restarts. Each node counts the queries it
+//! accepts in memory and adds them to the store periodically, which means
+//! that tokens that query several nodes can go over their quota by what
+//! the other nodes counted since they last synced.
+//!
+//! Tokens are identified by their hash as `client_identity` reports it, so
+//! that the store never sees the tokens themselves. Tokens without a quota,
+//! and requests without a token, are not limited.
+
+use chrono::prelude::{DateTime, Datelike, TimeZone, Utc};
+use failure::Error;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::log::audit::client_identity;
+use crate::util::env::env_var;
+use crate::util::security::constant_time_eq;
+
+/// By default, tokens can go 10% over their quota before their queries are
+/// refused
+const DEFAULT_GRACE_PERCENT: u64 = 10;
+
+lazy_static! {
+    static ref INSTALLED: RwLock<Option<Arc<Quotas>>> = RwLock::new(None);
+}
+
+/// The quota of one API token
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenQuota {
+    /// The hash of the token, e.g. `token:0123456789abcdef`
+    pub token: String,
+    /// A name for the token that is unique among all tokens with a quota
+    pub name: String,
+    /// How many queries the token can make in a calendar month (UTC)
+    pub monthly_limit: u64,
+}
+
+impl TokenQuota {
+    /// The quota for the bearer token `token`
+    pub fn new(token: &str, name: String, monthly_limit: u64) -> Self {
+        TokenQuota {
+            token: client_identity(Some(token), None),
+            name,
+            monthly_limit,
+        }
+    }
+}
+
+/// How many queries a token made in a month
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenUsage {
+    pub quota: TokenQuota,
+    /// The month, as `YYYY-MM`
+    pub month: String,
+    pub used: u64,
+}
+
+/// Where quotas and the usage of tokens are kept
+pub trait QuotaStore: Send + Sync + 'static {
+    /// All tokens that have a quota
+    fn quotas(&self) -> Result<Vec<TokenQuota>, Error>;
+
+    /// Give `quota.token` its quota, replacing any quota it had
+    fn set_quota(&self, quota: &TokenQuota) -> Result<(), Error>;
+
+    /// Remove the quota of the token called `name`. Returns whether there
+    /// was such a token
+    fn remove_quota(&self, name: &str) -> Result<bool, Error>;
+
+    /// Add `counts`, the number of queries per token, to the usage of the
+    /// tokens in `month`
+    fn add_usage(&self, month: &str, counts: &[(String, u64)]) -> Result<(), Error>;
+
+    /// How many queries each token made in `month`
+    fn usage(&self, month: &str) -> Result<HashMap<String, u64>, Error>;
+}
+
+/// Whether a query of a token may run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuotaCheck {
+    /// The token has no quota
+    Unlimited,
+    /// The token is within its quota
+    Within,
+    /// The token used up its quota, but is still within the grace
+    /// threshold; the query runs
+    Grace { limit: u64, used: u64 },
+    /// The token is beyond the grace threshold; the query is refused and
+    /// not counted
+    Exceeded { limit: u64, used: u64 },
+}
+
+struct State {
+    /// The month that `used` is for
+    month: String,
+    /// The quotas by token
+    quotas: HashMap<String, TokenQuota>,
+    /// The usage of tokens in `month` as of the last sync, from all nodes
+    used: HashMap<String, u64>,
+    /// The queries this node accepted since the last sync, by month and
+    /// token
+    pending: HashMap<(String, String), u64>,
+}
+
+impl State {
+    /// The quota of `client`. Looks at every quota so that the time this
+    /// takes does not reveal how much of a token hash was right
+    fn quota(&self, client: &str) -> Option<&TokenQuota> {
+        self.quotas
+            .iter()
+            .filter(|(token, _)| constant_time_eq(token.as_bytes(), client.as_bytes()))
+            .map(|(_, quota)| quota)
+            .last()
+    }
+}
+
+/// Enforces the quotas of tokens and counts their queries
+pub struct Quotas {
+    store: Arc<dyn QuotaStore>,
+    /// How far, as a fraction of their quota, tokens can go over it
+    grace: f64,
+    state: Mutex<State>,
+}
+
+/// The current month as `YYYY-MM`
+fn current_month() -> String {
+    Utc::now().format("%Y-%m").to_string()
+}
+
+/// How long it is from `now` until the next month starts
+fn until_next_month(now: DateTime<Utc>) -> Duration {
+    let (year, month) = match now.month() {
+        12 => (now.year() + 1, 1),
+        month => (now.year(), month + 1),
+    };
+    let start = Utc.ymd(year, month, 1).and_hms(0, 0, 0);
+    (start - now).to_std().unwrap_or_default()
+}
+
+impl Quotas {
+    pub fn new(store: Arc<dyn QuotaStore>, grace: f64) -> Self {
+        Quotas {
+            store,
+            grace,
+            state: Mutex::new(State {
+                month: current_month(),
+                quotas: HashMap::new(),
+                used: HashMap::new(),
+                pending: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Quotas with the grace threshold from `GRAPH_QUERY_QUOTA_GRACE`, in
+    /// percent of the quota
+    pub fn from_env(store: Arc<dyn QuotaStore>) -> Self {
+        let grace: u64 = env_var("GRAPH_QUERY_QUOTA_GRACE").unwrap_or(DEFAULT_GRACE_PERCENT);
+        Quotas::new(store, grace as f64 / 100.0)
+    }
+
+    /// Whether `client`, as identified by `client_identity`, is a token
+    /// with a quota
+    pub fn has_quota(&self, client: &str) -> bool {
+        self.state.lock().unwrap().quota(client).is_some()
+    }
+
+    /// Count a query of `client`, as identified by `client_identity`, if it
+    /// may run
+    pub fn acquire(&self, client: &str) -> QuotaCheck {
+        let mut state = self.state.lock().unwrap();
+        let limit = match state.quota(client) {
+            Some(quota) => quota.monthly_limit,
+            None => return QuotaCheck::Unlimited,
+        };
+
+        let month = current_month();
+        if month != state.month {
+            state.month = month;
+            state.used.clear();
+        }
+        let key = (state.month.clone(), client.to_owned());
+        let used = state.used.get(client).cloned().unwrap_or(0)
+            + state.pending.get(&key).cloned().unwrap_or(0);
+
+        let check = if used < limit {
+            QuotaCheck::Within
+        } else if (used as f64) < (limit as f64) * (1.0 + self.grace) {
+            QuotaCheck::Grace { limit, used }
+        } else {
+            return QuotaCheck::Exceeded { limit, used };
+        };
+        *state.pending.entry(key).or_insert(0) += 1;
+        check
+    }
+
+    /// How long a token that exceeded its quota has to wait until it gets
+    /// a new one
+    pub fn retry_after(&self) -> Duration {
+        until_next_month(Utc::now())
+    }
+
+    /// Add the queries counted since the last sync to the store, and load
+    /// the quotas and the usage of all tokens from it
+    pub fn sync(&self) -> Result<(), Error> {
+        let pending = std::mem::take(&mut self.state.lock().unwrap().pending);
+        let added = {
+            let mut by_month: HashMap<&str, Vec<(String, u64)>> = HashMap::new();
+            for ((month, token), count) in &pending {
+                by_month
+                    .entry(month.as_str())
+                    .or_default()
+                    .push((token.clone(), *count));
+            }
+            by_month
+                .iter()
+                .map(|(month, counts)| self.store.add_usage(month, counts))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        if let Err(e) = added {
+            // Keep the counts for the next sync; months that were added
+            // before the error will be counted again
+            let mut state = self.state.lock().unwrap();
+            for (key, count) in pending {
+                *state.pending.entry(key).or_insert(0) += count;
+            }
+            return Err(e);
+        }
+
+        let month = current_month();
+        let quotas = self.store.quotas()?;
+        let used = self.store.usage(&month)?;
+
+        let mut state = self.state.lock().unwrap();
+        state.month = month;
+        state.quotas = quotas
+            .into_iter()
+            .map(|quota| (quota.token.clone(), quota))
+            .collect();
+        state.used = used;
+        Ok(())
+    }
+
+    /// Give a token a quota; the other nodes enforce it after their next
+    /// sync
+    pub fn set_quota(&self, quota: TokenQuota) -> Result<(), Error> {
+        self.store.set_quota(&quota)?;
+        let mut state = self.state.lock().unwrap();
+        state
+            .quotas
+            .retain(|token, known| known.name != quota.name || token == &quota.token);
+        state.quotas.insert(quota.token.clone(), quota);
+        Ok(())
+    }
+
+    /// Remove the quota of the token called `name`. Returns whether there
+    /// was such a token
+    pub fn remove_quota(&self, name: &str) -> Result<bool, Error> {
+        let removed = self.store.remove_quota(name)?;
+        self.state
+            .lock()
+            .unwrap()
+            .quotas
+            .retain(|_, quota| quota.name != name);
+        Ok(removed)
+    }
+
+    /// The usage of all tokens with a quota in `month`, or in the current
+    /// month. Queries that this node has not synced yet are included
+    pub fn usage(&self, month: Option<&str>) -> Result<Vec<TokenUsage>, Error> {
+        self.sync()?;
+        let month = month.map(str::to_owned).unwrap_or_else(current_month);
+        let used = self.store.usage(&month)?;
+        let mut usage: Vec<_> = self
+            .store
+            .quotas()?
+            .into_iter()
+            .map(|quota| TokenUsage {
+                used: used.get(&quota.token).cloned().unwrap_or(0),
+                quota,
+                month: month.clone(),
+            })
+            .collect();
+        usage.sort_by(|a, b| a.quota.name.cmp(&b.quota.name));
+        Ok(usage)
+    }
+}
+
+/// Use `quotas` for all queries of this node and in the admin server
+pub fn install(quotas: Arc<Quotas>) {
+    *INSTALLED.write().unwrap() = Some(quotas);
+}
+
+/// The quotas of this node, if they were installed
+pub fn installed() -> Option<Arc<Quotas>> {
+    INSTALLED.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        quotas: Mutex<Vec<TokenQuota>>,
+        usage: Mutex<HashMap<(String, String), u64>>,
+    }
+
+    impl QuotaStore for MemoryStore {
+        fn quotas(&self) -> Result<Vec<TokenQuota>, Error> {
+            Ok(self.quotas.lock().unwrap().clone())
+        }
+
+        fn set_quota(&self, quota: &TokenQuota) -> Result<(), Error> {
+            let mut quotas = self.quotas.lock().unwrap();
+            quotas.retain(|known| known.token != quota.token && known.name != quota.name);
+            quotas.push(quota.clone());
+            Ok(())
+        }
+
+        fn remove_quota(&self, name: &str) -> Result<bool, Error> {
+            let mut quotas = self.quotas.lock().unwrap();
+            let count = quotas.len();
+            quotas.retain(|quota| quota.name != name);
+            Ok(quotas.len() < count)
+        }
+
+        fn add_usage(&self, month: &str, counts: &[(String, u64)]) -> Result<(), Error> {
+            let mut usage = self.usage.lock().unwrap();
+            for (token, count) in counts {
+                *usage.entry((month.to_owned(), token.clone())).or_insert(0) += count;
+            }
+            Ok(())
+        }
+
+        fn usage(&self, month: &str) -> Result<HashMap<String, u64>, Error> {
+            Ok(self
+                .usage
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|((m, _), _)| m == month)
+                .map(|((_, token), count)| (token.clone(), *count))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn enforces_quotas_with_grace() {
+        let store = Arc::new(MemoryStore::default());
+        let quotas = Quotas::new(store.clone(), 0.5);
+        let quota = TokenQuota::new("secret", "dashboard".to_owned(), 2);
+        let client = quota.token.clone();
+        quotas.set_quota(quota).unwrap();
+
+        assert_eq!(QuotaCheck::Unlimited, quotas.acquire("token:other"));
+        assert_eq!(QuotaCheck::Within, quotas.acquire(&client));
+        assert_eq!(QuotaCheck::Within, quotas.acquire(&client));
+        assert_eq!(
+            QuotaCheck::Grace { limit: 2, used: 2 },
+            quotas.acquire(&client)
+        );
+        assert_eq!(
+            QuotaCheck::Exceeded { limit: 2, used: 3 },
+            quotas.acquire(&client)
+        );
+
+        // Counts survive in the store, and other nodes see them
+        quotas.sync().unwrap();
+        let other = Quotas::new(store, 0.5);
+        other.sync().unwrap();
+        assert_eq!(
+            QuotaCheck::Exceeded { limit: 2, used: 3 },
+            other.acquire(&client)
+        );
+        let usage = other.usage(None).unwrap();
+        assert_eq!(1, usage.len());
+        assert_eq!("dashboard", usage[0].quota.name);
+        assert_eq!(3, usage[0].used);
+
+        assert!(other.remove_quota("dashboard").unwrap());
+        assert_eq!(QuotaCheck::Unlimited, other.acquire(&client));
+    }
+
+    #[test]
+    fn waits_until_next_month() {
+        let now = Utc.ymd(2020, 12, 31).and_hms(23, 59, 0);
+        assert_eq!(Duration::from_secs(60), until_next_month(now));
+    }
+}
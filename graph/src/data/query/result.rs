@@ -1,5 +1,6 @@
 use super::error::{QueryError, QueryExecutionError};
 use crate::data::graphql::SerializableValue;
+use crate::util::lfu_cache::CacheWeight;
 use graphql_parser::query as q;
 use serde::ser::*;
 use serde::Serialize;
@@ -27,6 +28,10 @@ pub struct QueryResult {
         serialize_with = "serialize_data"
     )]
     pub extensions: Option<q::Value>,
+    /// Whether the result came from the query cache. This is not sent to
+    /// clients
+    #[serde(skip_serializing)]
+    pub cached: bool,
 }
 
 impl QueryResult {
@@ -35,6 +40,7 @@ impl QueryResult {
             data,
             errors: None,
             extensions: None,
+            cached: false,
         }
     }
     pub fn with_extensions(mut self, extensions: BTreeMap<q::Name, q::Value>) -> Self {
@@ -43,6 +49,29 @@ impl QueryResult {
     }
 }
 
+impl CacheWeight for q::Value {
+    /// The approximate amount of bytes occupied by the value
+    fn weight(&self) -> u64 {
+        use std::mem::size_of_val;
+        size_of_val(self) as u64
+            + match self {
+                q::Value::String(s) | q::Value::Enum(s) | q::Value::Variable(s) => s.len() as u64,
+                q::Value::List(values) => values.iter().map(|value| value.weight()).sum(),
+                q::Value::Object(map) => map
+                    .iter()
+                    .map(|(key, value)| size_of_val(key) as u64 + key.len() as u64 + value.weight())
+                    .sum(),
+                q::Value::Int(_) | q::Value::Float(_) | q::Value::Boolean(_) | q::Value::Null => 0,
+            }
+    }
+}
+
+impl CacheWeight for QueryResult {
+    fn weight(&self) -> u64 {
+        self.data.weight() + self.extensions.weight()
+    }
+}
+
 impl From<QueryExecutionError> for QueryResult {
     fn from(e: QueryExecutionError) -> Self {
         let mut result = Self::new(None);
@@ -57,6 +86,7 @@ impl From<Vec<QueryExecutionError>> for QueryResult {
             data: None,
             errors: Some(e.into_iter().map(QueryError::from).collect()),
             extensions: None,
+            cached: false,
         }
     }
 }
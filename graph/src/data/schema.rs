@@ -1,6 +1,6 @@
 use crate::components::store::{Store, SubgraphDeploymentStore};
 use crate::data::graphql::ext::{DirectiveExt, DirectiveFinder, DocumentExt, TypeExt, ValueExt};
-use crate::data::store::ValueType;
+use crate::data::store::{ValueType, GEO_POINT_SCALAR};
 use crate::data::subgraph::{SubgraphDeploymentId, SubgraphName};
 use crate::prelude::Fail;
 
@@ -108,6 +108,51 @@ pub enum SchemaValidationError {
     FulltextIncludedFieldMissingRequiredProperty,
     #[fail(display = "Fulltext entity field, {}, not found or not a string", _0)]
     FulltextIncludedFieldInvalid(String),
+    #[fail(
+        display = "Type name `{}` is reserved for the types that are generated for the GraphQL API",
+        _0
+    )]
+    TypeNameReserved(String),
+    #[fail(
+        display = "Field `{}` in type `{}` has the same name as a filter for field `{}`; rename it",
+        _1, _0, _2
+    )]
+    FilterFieldCollision(String, String, String), // (type, field, filtered_field)
+    #[fail(
+        display = "Field `{}` in type `{}` is a list of lists, which can not be stored",
+        _1, _0
+    )]
+    NestedListField(String, String), // (type, field)
+    #[fail(
+        display = "Entity type `{}` must have an `id` field of type `ID!`, `String!` or `Bytes!`",
+        _0
+    )]
+    IdFieldInvalid(String),
+    #[fail(
+        display = "The name `{}` is longer than {} characters once it is converted to the \
+                   database name `{}`; shorten it",
+        _0, _1, _2
+    )]
+    NameTooLong(String, usize, String), // (name, limit, database_name)
+    #[fail(
+        display = "Field `{}` in type `{}` has an invalid @deprecated; it only takes a \
+                   `reason` that is a string",
+        _1, _0
+    )]
+    InvalidDeprecation(String, String), // (type, field)
+}
+
+/// Problems in a schema that do not prevent deploying it, but that most
+/// likely are not what the author intended
+#[derive(Debug, Fail, PartialEq, Eq)]
+pub enum SchemaWarning {
+    #[fail(
+        display = "Field `{}` in type `{}` stores a list of ids that `{}.{}` already points \
+                   back to; consider `@derivedFrom(field: \"{}\")` so that the list does not \
+                   have to be kept up to date by the mappings",
+        _1, _0, _2, _3, _3
+    )]
+    DerivableListField(String, String, String, String), // (type, field, target_type, target_field)
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -303,7 +348,7 @@ impl ImportedType {
             }),
             Value::Object(type_name_as) => {
                 match (type_name_as.get("name"), type_name_as.get("as")) {
-                    (Some(name), Some(az)) => Some(ImportedType {
+                    (Some(Value::String(name)), Some(Value::String(az))) => Some(ImportedType {
                         name: name.to_string(),
                         alias: az.to_string(),
                         explicit: true,
@@ -316,38 +361,66 @@ impl ImportedType {
     }
 }
 
+/// The subgraph that an `@import` directive imports types from, given
+/// either by name, which means whatever deployment is the current version
+/// of that subgraph, or by deployment id
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub struct SchemaReference {
-    subgraph: SubgraphDeploymentId,
+pub enum SchemaReference {
+    ByName(SubgraphName),
+    ById(SubgraphDeploymentId),
 }
 
 impl fmt::Display for SchemaReference {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{}", self.subgraph)
+        match self {
+            SchemaReference::ByName(name) => write!(f, "{}", name),
+            SchemaReference::ById(id) => write!(f, "{}", id),
+        }
     }
 }
 
 impl SchemaReference {
     fn new(subgraph: SubgraphDeploymentId) -> Self {
-        SchemaReference { subgraph }
+        SchemaReference::ById(subgraph)
+    }
+
+    /// The deployment that this reference currently points to
+    pub fn deployment<S: Store>(
+        &self,
+        store: &S,
+    ) -> Result<SubgraphDeploymentId, SchemaImportError> {
+        match self {
+            SchemaReference::ById(id) => Ok(id.clone()),
+            SchemaReference::ByName(name) => {
+                match store.resolve_subgraph_name_to_id(name.clone()) {
+                    Ok(Some(id)) => Ok(id),
+                    Ok(None) | Err(_) => {
+                        Err(SchemaImportError::ImportedSubgraphNotFound(self.clone()))
+                    }
+                }
+            }
+        }
     }
 
     pub fn resolve<S: Store + SubgraphDeploymentStore>(
         &self,
         store: Arc<S>,
     ) -> Result<Arc<Schema>, SchemaImportError> {
+        let id = self.deployment(store.as_ref())?;
         store
-            .input_schema(&self.subgraph)
+            .input_schema(&id)
             .map_err(|_| SchemaImportError::ImportedSchemaNotFound(self.clone()))
     }
 
     fn parse(value: &Value) -> Option<Self> {
         match value {
-            Value::Object(map) => match map.get("id") {
-                Some(Value::String(id)) => match SubgraphDeploymentId::new(id) {
-                    Ok(id) => Some(SchemaReference::new(id)),
-                    _ => None,
-                },
+            Value::Object(map) => match (map.get("id"), map.get("name")) {
+                (Some(Value::String(id)), None) => SubgraphDeploymentId::new(id.as_str())
+                    .ok()
+                    .map(SchemaReference::ById),
+                (None, Some(Value::String(name))) => SubgraphName::new(name.as_str())
+                    .ok()
+                    .map(SchemaReference::ByName),
                 _ => None,
             },
             _ => None,
@@ -537,6 +610,94 @@ impl Schema {
         })
     }
 
+    /// The names under which this schema imports types from other
+    /// subgraphs. Fields of these types hold the ids of the imported
+    /// entities
+    pub fn imported_type_names(&self) -> HashSet<String> {
+        self.imported_types()
+            .into_iter()
+            .map(|(imported_type, _)| imported_type.alias)
+            .collect()
+    }
+
+    /// The document of this schema with a copy of each object type that it
+    /// imports from one of `schemas`. A copy keeps the `@subgraphId` of the
+    /// subgraph that defines the type, which makes queries read its
+    /// entities from that subgraph; fields of the copy that refer to other
+    /// types are only kept if those types are imported from the same
+    /// subgraph, too. Types that are imported with `as`, or that can not be
+    /// found, are not copied, and fields that refer to them are removed
+    pub fn document_with_imports(
+        &self,
+        schemas: &HashMap<SchemaReference, Arc<Schema>>,
+    ) -> Document {
+        let imported_types = self.imported_types();
+        let mut imported: Vec<(ObjectType, &SchemaReference)> = Vec::new();
+        for (imported_type, schema_ref) in imported_types.iter() {
+            if imported_type.explicit {
+                continue;
+            }
+            let object_type = schemas.get(schema_ref).and_then(|schema| {
+                schema
+                    .document
+                    .get_object_type_definitions()
+                    .into_iter()
+                    .find(|object_type| object_type.name == imported_type.name)
+            });
+            if let Some(object_type) = object_type {
+                imported.push((object_type.clone(), schema_ref));
+            }
+        }
+        imported.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+        let mut names: HashMap<&SchemaReference, HashSet<String>> = HashMap::new();
+        for (object_type, schema_ref) in &imported {
+            names
+                .entry(*schema_ref)
+                .or_default()
+                .insert(object_type.name.clone());
+        }
+        for (object_type, schema_ref) in imported.iter_mut() {
+            let siblings = &names[*schema_ref];
+            object_type.implements_interfaces.clear();
+            object_type.fields.retain(|field| {
+                let base = field.field_type.get_base_type();
+                ValueType::is_scalar(base) || siblings.contains(base)
+            });
+        }
+
+        let mut document = self.document.clone();
+        document
+            .definitions
+            .extend(imported.into_iter().map(|(object_type, _)| {
+                Definition::TypeDefinition(TypeDefinition::Object(object_type))
+            }));
+
+        let known: HashSet<_> = document
+            .definitions
+            .iter()
+            .filter_map(|definition| match definition {
+                Definition::TypeDefinition(TypeDefinition::Object(t)) => Some(t.name.clone()),
+                Definition::TypeDefinition(TypeDefinition::Interface(t)) => Some(t.name.clone()),
+                Definition::TypeDefinition(TypeDefinition::Enum(t)) => Some(t.name.clone()),
+                _ => None,
+            })
+            .collect();
+        let is_known = |field: &Field| {
+            let base = field.field_type.get_base_type();
+            ValueType::is_scalar(base) || known.contains(base)
+        };
+        for definition in document.definitions.iter_mut() {
+            match definition {
+                Definition::TypeDefinition(TypeDefinition::Object(t)) => t.fields.retain(is_known),
+                Definition::TypeDefinition(TypeDefinition::Interface(t)) => {
+                    t.fields.retain(is_known)
+                }
+                _ => (),
+            }
+        }
+        document
+    }
+
     pub fn name_argument_value_from_directive(directive: &Directive) -> Value {
         directive
             .argument("name")
@@ -600,6 +761,28 @@ impl Schema {
         }
     }
 
+    /// Make this the schema of the deployment `id` instead of the one it
+    /// was parsed for, replacing the `@subgraphId` directives
+    pub fn set_subgraph_id(&mut self, id: SubgraphDeploymentId) {
+        for definition in self.document.definitions.iter_mut() {
+            if let Definition::TypeDefinition(ref mut type_definition) = definition {
+                let directives = match type_definition {
+                    TypeDefinition::Object(object_type) => &mut object_type.directives,
+                    TypeDefinition::Interface(interface_type) => &mut interface_type.directives,
+                    TypeDefinition::Enum(enum_type) => &mut enum_type.directives,
+                    TypeDefinition::Scalar(scalar_type) => &mut scalar_type.directives,
+                    TypeDefinition::InputObject(input_object_type) => {
+                        &mut input_object_type.directives
+                    }
+                    TypeDefinition::Union(union_type) => &mut union_type.directives,
+                };
+                directives.retain(|directive| directive.name != "subgraphId");
+            }
+        }
+        self.id = id.clone();
+        self.add_subgraph_id_directives(id);
+    }
+
     pub fn validate(
         &self,
         schemas: &HashMap<SchemaReference, Arc<Schema>>,
@@ -617,6 +800,9 @@ impl Schema {
         errors.append(&mut self.validate_import_directives());
         errors.append(&mut self.validate_fulltext_directives());
         errors.append(&mut self.validate_imported_types(schemas));
+        errors.append(&mut self.validate_type_names());
+        errors.append(&mut self.validate_entity_fields());
+        errors.append(&mut self.validate_deprecations());
         if errors.is_empty() {
             Ok(())
         } else {
@@ -624,6 +810,171 @@ impl Schema {
         }
     }
 
+    /// Problems in the schema that are worth pointing out to its author,
+    /// but that do not make it invalid
+    pub fn warnings(&self) -> Vec<SchemaWarning> {
+        let object_types = self.document.get_object_type_definitions();
+        let mut warnings = vec![];
+        for object_type in object_types.iter() {
+            for field in object_type.fields.iter() {
+                if !is_list_type(&field.field_type)
+                    || field.find_directive(String::from("derivedFrom")).is_some()
+                {
+                    continue;
+                }
+                let target_type = match object_types
+                    .iter()
+                    .find(|t| &t.name == field.field_type.get_base_type())
+                {
+                    Some(target_type) => target_type,
+                    None => continue,
+                };
+                // A single field in the target type that points back is
+                // what `@derivedFrom` needs; with several, it is not clear
+                // which one the list mirrors
+                let mut back_references = target_type.fields.iter().filter(|target_field| {
+                    !is_list_type(&target_field.field_type)
+                        && target_field.field_type.get_base_type() == &object_type.name
+                        && target_field
+                            .find_directive(String::from("derivedFrom"))
+                            .is_none()
+                });
+                if let (Some(target_field), None) = (back_references.next(), back_references.next())
+                {
+                    warnings.push(SchemaWarning::DerivableListField(
+                        object_type.name.clone(),
+                        field.name.clone(),
+                        target_type.name.clone(),
+                        target_field.name.clone(),
+                    ));
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Check that no type uses the name of a type that the GraphQL API
+    /// generates, and that the database names of types and fields fit
+    /// into Postgres identifiers
+    fn validate_type_names(&self) -> Vec<SchemaValidationError> {
+        let mut errors = vec![];
+        for typedef in self.document.definitions.iter().filter_map(|d| match d {
+            Definition::TypeDefinition(typedef) => Some(typedef),
+            _ => None,
+        }) {
+            let (name, fields) = match typedef {
+                TypeDefinition::Object(t) => (&t.name, Some(&t.fields)),
+                TypeDefinition::Interface(t) => (&t.name, Some(&t.fields)),
+                TypeDefinition::Enum(t) => (&t.name, None),
+                TypeDefinition::Scalar(t) => (&t.name, None),
+                TypeDefinition::Union(t) => (&t.name, None),
+                TypeDefinition::InputObject(t) => (&t.name, None),
+            };
+            if is_reserved_type_name(name) {
+                errors.push(SchemaValidationError::TypeNameReserved(name.clone()));
+            }
+            if name == SCHEMA_TYPE_NAME {
+                continue;
+            }
+            let mut names = vec![name];
+            names.extend(fields.into_iter().flatten().map(|field| &field.name));
+            for name in names {
+                let db_name = name.to_snake_case();
+                if db_name.len() > MAX_DB_NAME_LENGTH {
+                    errors.push(SchemaValidationError::NameTooLong(
+                        name.clone(),
+                        MAX_DB_NAME_LENGTH,
+                        db_name,
+                    ));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Check that entity types can be stored and queried: they need a
+    /// usable `id`, must not nest lists, and their fields must not clash
+    /// with the filters that the GraphQL API generates for other fields
+    fn validate_entity_fields(&self) -> Vec<SchemaValidationError> {
+        let enums = self
+            .document
+            .get_enum_definitions()
+            .iter()
+            .map(|enu| enu.name.clone())
+            .collect::<HashSet<_>>();
+        let mut errors = vec![];
+        for object_type in self
+            .document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter(|t| t.find_directive(String::from("entity")).is_some())
+        {
+            let id_type_valid = object_type
+                .fields
+                .iter()
+                .find(|field| field.name == "id")
+                .map_or(false, |field| match &field.field_type {
+                    Type::NonNullType(inner) => match inner.as_ref() {
+                        Type::NamedType(name) => {
+                            name == "ID" || name == "String" || name == "Bytes"
+                        }
+                        _ => false,
+                    },
+                    _ => false,
+                });
+            if !id_type_valid {
+                errors.push(SchemaValidationError::IdFieldInvalid(
+                    object_type.name.clone(),
+                ));
+            }
+
+            for field in object_type.fields.iter() {
+                if is_nested_list_type(&field.field_type) {
+                    errors.push(SchemaValidationError::NestedListField(
+                        object_type.name.clone(),
+                        field.name.clone(),
+                    ));
+                }
+                for suffix in filter_suffixes(field, &enums) {
+                    let filter = format!("{}_{}", field.name, suffix);
+                    if let Some(other) = object_type.fields.iter().find(|f| f.name == filter) {
+                        errors.push(SchemaValidationError::FilterFieldCollision(
+                            object_type.name.clone(),
+                            other.name.clone(),
+                            field.name.clone(),
+                        ));
+                    }
+                }
+            }
+        }
+        errors
+    }
+
+    /// Check that `@deprecated` directives on fields at most give a
+    /// `reason`, which is what introspection reports for them
+    fn validate_deprecations(&self) -> Vec<SchemaValidationError> {
+        let mut errors = vec![];
+        for (type_name, fields) in self.document.get_object_and_interface_type_fields() {
+            for field in fields {
+                let directive = match field.find_directive(String::from("deprecated")) {
+                    Some(directive) => directive,
+                    None => continue,
+                };
+                let valid = directive
+                    .arguments
+                    .iter()
+                    .all(|(name, value)| name == "reason" && value.as_string().is_some());
+                if !valid {
+                    errors.push(SchemaValidationError::InvalidDeprecation(
+                        type_name.clone(),
+                        field.name.clone(),
+                    ));
+                }
+            }
+        }
+        errors
+    }
+
     fn validate_schema_type_has_no_fields(&self) -> Result<(), SchemaValidationError> {
         match self
             .subgraph_schema_object_type()
@@ -968,7 +1319,7 @@ impl Schema {
                         // the respective schema or is itself imported
                         // If the imported type is itself imported, do not
                         // recursively check the schema
-                        let schema_handle = schema_ref.subgraph.to_string();
+                        let schema_handle = schema_ref.to_string();
                         let name = imported_type.name.as_str();
 
                         let is_local = local_types.iter().any(|object| object.name == name);
@@ -1252,6 +1603,89 @@ impl Schema {
     }
 }
 
+/// Postgres truncates identifiers that are longer than this
+const MAX_DB_NAME_LENGTH: usize = 63;
+
+/// Whether `name` clashes with the types that the GraphQL API adds to a
+/// subgraph schema, or is reserved by GraphQL itself
+fn is_reserved_type_name(name: &str) -> bool {
+    name == "Query"
+        || name == "Subscription"
+        || name == "OrderDirection"
+        || name == "Block_height"
+        || name == "GeoPoint_within"
+        || name == "_Meta_"
+        || name.starts_with("__")
+        || name.ends_with("_filter")
+        || name.ends_with("_orderBy")
+        || name.ends_with("_groupBy")
+        || name.ends_with("_groupOrderBy")
+        || name.ends_with("_having")
+        || name.ends_with("_group")
+}
+
+fn is_list_type(field_type: &Type) -> bool {
+    match field_type {
+        Type::NamedType(_) => false,
+        Type::ListType(_) => true,
+        Type::NonNullType(inner) => is_list_type(inner),
+    }
+}
+
+fn is_nested_list_type(field_type: &Type) -> bool {
+    match field_type {
+        Type::NamedType(_) => false,
+        Type::ListType(inner) => is_list_type(inner),
+        Type::NonNullType(inner) => is_nested_list_type(inner),
+    }
+}
+
+/// The suffixes of the filters that the GraphQL API generates for `field`,
+/// other than the filter that has the name of the field itself. This must
+/// be kept in sync with `field_filter_input_values` in `graphql::schema::api`
+fn filter_suffixes(field: &Field, enums: &HashSet<Name>) -> &'static [&'static str] {
+    const ORDERED: &[&str] = &["not", "gt", "lt", "gte", "lte", "in", "not_in"];
+    const STRING: &[&str] = &[
+        "not",
+        "gt",
+        "lt",
+        "gte",
+        "lte",
+        "in",
+        "not_in",
+        "contains",
+        "not_contains",
+        "starts_with",
+        "not_starts_with",
+        "ends_with",
+        "not_ends_with",
+    ];
+
+    let derived = field.find_directive(String::from("derivedFrom")).is_some();
+    let base = field.field_type.get_base_type();
+    if is_list_type(&field.field_type) {
+        if derived || base == GEO_POINT_SCALAR {
+            &[]
+        } else {
+            &["not", "contains", "not_contains"]
+        }
+    } else if enums.contains(base) {
+        &["not"]
+    } else {
+        match base.as_str() {
+            "BigInt" | "BigDecimal" | "ID" | "Int" => ORDERED,
+            "Boolean" => &["not", "in", "not_in"],
+            "Bytes" => &["not", "in", "not_in", "contains", "not_contains"],
+            GEO_POINT_SCALAR => &["not", "within"],
+            "String" => STRING,
+            // References to other entities are filtered by their id, and
+            // derived references can not be filtered
+            _ if derived => &[],
+            _ => STRING,
+        }
+    }
+}
+
 #[test]
 fn non_existing_interface() {
     let schema = "type Foo implements Bar @entity { foo: Int }";
@@ -1492,6 +1926,77 @@ type T @entity { id: ID! }
     }
 }
 
+#[test]
+fn test_document_with_imports() {
+    const ROOT_SCHEMA: &str = r#"
+type _Schema_
+  @import(types: ["Token", "Pair", { name: "Factory", as: "F" }], from: { name: "org/sub" })
+
+type Swap @entity {
+  id: ID!
+  token: Token!
+  pair: Pair
+  factory: F
+}"#;
+    const IMPORTED_SCHEMA: &str = r#"
+type Token @entity {
+  id: ID!
+  symbol: String!
+  pairs: [Pair!]! @derivedFrom(field: "token")
+  factory: Factory!
+}
+
+type Pair @entity { id: ID! token: Token! }
+
+type Factory @entity { id: ID! }"#;
+
+    let root_schema = Schema::parse(ROOT_SCHEMA, SubgraphDeploymentId::new("root").unwrap())
+        .expect("Failed to parse root schema");
+    let imported_schema = Schema::parse(
+        IMPORTED_SCHEMA,
+        SubgraphDeploymentId::new("imported").unwrap(),
+    )
+    .expect("Failed to parse imported schema");
+    let mut schemas = HashMap::new();
+    schemas.insert(
+        SchemaReference::ByName(SubgraphName::new("org/sub").unwrap()),
+        Arc::new(imported_schema),
+    );
+
+    let document = root_schema.document_with_imports(&schemas);
+    let object_type = |name: &str| {
+        document
+            .get_object_type_definitions()
+            .into_iter()
+            .find(|object_type| object_type.name == name)
+            .expect("object type exists")
+    };
+    let field_names = |object_type: &ObjectType| {
+        object_type
+            .fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        vec!["id", "token", "pair"],
+        field_names(object_type("Swap"))
+    );
+    let token = object_type("Token");
+    assert_eq!(vec!["id", "symbol", "pairs"], field_names(token));
+    assert_eq!(
+        Some(&Value::String("imported".to_owned())),
+        token
+            .find_directive("subgraphId".to_owned())
+            .and_then(|directive| directive.argument("id"))
+    );
+    assert!(document
+        .get_object_type_definitions()
+        .iter()
+        .all(|object_type| object_type.name != "Factory" && object_type.name != "F"));
+}
+
 #[test]
 fn test_fulltext_directive_validation() {
     const SCHEMA: &str = r#"
@@ -1521,3 +2026,99 @@ type Gravatar @entity {
 
     assert_eq!(schema.validate_fulltext_directives(), vec![]);
 }
+
+#[test]
+fn test_deploy_time_lints() {
+    const SCHEMA: &str = r#"
+type Query @entity { id: ID! }
+type Token @entity {
+  id: ID!
+  name: String!
+  name_not: String
+  owner: Account!
+  balances: [[Int!]!]!
+}
+type NoId @entity { name: String! }
+type NullableId @entity { id: ID }
+type Account @entity {
+  id: Bytes!
+  tokens: [Token!]!
+  aVeryLongFieldNameThatWillNotFitIntoAPostgresIdentifierOnceItIsConvertedToSnakeCase: Int
+}
+"#;
+
+    let document = graphql_parser::parse_schema(SCHEMA).expect("Failed to parse schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id1").unwrap(), document);
+    let errors = schema.validate(&HashMap::new()).unwrap_err();
+
+    assert!(errors.contains(&SchemaValidationError::TypeNameReserved("Query".to_owned())));
+    assert!(
+        errors.contains(&SchemaValidationError::FilterFieldCollision(
+            "Token".to_owned(),
+            "name_not".to_owned(),
+            "name".to_owned()
+        ))
+    );
+    assert!(errors.contains(&SchemaValidationError::NestedListField(
+        "Token".to_owned(),
+        "balances".to_owned()
+    )));
+    assert!(errors.contains(&SchemaValidationError::IdFieldInvalid("NoId".to_owned())));
+    assert!(errors.contains(&SchemaValidationError::IdFieldInvalid(
+        "NullableId".to_owned()
+    )));
+    assert!(!errors.contains(&SchemaValidationError::IdFieldInvalid("Account".to_owned())));
+    assert!(errors.iter().any(|error| match error {
+        SchemaValidationError::NameTooLong(name, 63, _) => name.starts_with("aVeryLong"),
+        _ => false,
+    }));
+    assert_eq!(6, errors.len());
+
+    assert_eq!(
+        vec![SchemaWarning::DerivableListField(
+            "Account".to_owned(),
+            "tokens".to_owned(),
+            "Token".to_owned(),
+            "owner".to_owned()
+        )],
+        schema.warnings()
+    );
+}
+
+#[test]
+fn test_set_subgraph_id() {
+    const SCHEMA: &str = "type Thing @entity { id: ID! } enum Color { red, green }";
+
+    let mut schema = Schema::parse(SCHEMA, SubgraphDeploymentId::new("original").unwrap())
+        .expect("Failed to parse schema");
+    schema.set_subgraph_id(SubgraphDeploymentId::new("scratch").unwrap());
+
+    assert_eq!("scratch", schema.id.as_str());
+    let text = schema.document.to_string();
+    assert_eq!(2, text.matches("@subgraphId(id: \"scratch\")").count());
+    assert!(!text.contains("original"));
+}
+
+#[test]
+fn test_deprecation_validation() {
+    const SCHEMA: &str = r#"
+type Token @entity {
+  id: ID!
+  name: String! @deprecated(reason: "Use `symbol`")
+  symbol: String! @deprecated
+  decimals: Int! @deprecated(reason: 18)
+  supply: BigInt! @deprecated(since: "0.0.2")
+}
+"#;
+
+    let document = graphql_parser::parse_schema(SCHEMA).expect("Failed to parse schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id1").unwrap(), document);
+
+    assert_eq!(
+        vec![
+            SchemaValidationError::InvalidDeprecation("Token".to_owned(), "decimals".to_owned()),
+            SchemaValidationError::InvalidDeprecation("Token".to_owned(), "supply".to_owned()),
+        ],
+        schema.validate_deprecations()
+    );
+}
@@ -55,6 +55,10 @@ impl NodeId {
 
         Ok(NodeId(s))
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 impl fmt::Display for NodeId {
@@ -103,6 +107,7 @@ pub const ID: &str = "ID";
 pub const BYTES_SCALAR: &str = "Bytes";
 pub const BIG_INT_SCALAR: &str = "BigInt";
 pub const BIG_DECIMAL_SCALAR: &str = "BigDecimal";
+pub const GEO_POINT_SCALAR: &str = "GeoPoint";
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum ValueType {
@@ -112,6 +117,7 @@ pub enum ValueType {
     BigDecimal,
     Int,
     String,
+    GeoPoint,
     List,
 }
 
@@ -126,6 +132,7 @@ impl FromStr for ValueType {
             "BigDecimal" => Ok(ValueType::BigDecimal),
             "Int" => Ok(ValueType::Int),
             "String" | "ID" => Ok(ValueType::String),
+            "GeoPoint" => Ok(ValueType::GeoPoint),
             "List" => Ok(ValueType::List),
             s => Err(format_err!("Type not available in this context: {}", s)),
         }
@@ -143,7 +150,8 @@ impl ValueType {
                 | ValueType::BigInt
                 | ValueType::Bytes
                 | ValueType::Int
-                | ValueType::String => true,
+                | ValueType::String
+                | ValueType::GeoPoint => true,
             })
             .unwrap_or(false)
     }
@@ -223,6 +231,7 @@ impl Value {
                     BYTES_SCALAR => Value::Bytes(scalar::Bytes::from_str(s)?),
                     BIG_INT_SCALAR => Value::BigInt(scalar::BigInt::from_str(s)?),
                     BIG_DECIMAL_SCALAR => Value::BigDecimal(scalar::BigDecimal::from_str(s)?),
+                    GEO_POINT_SCALAR => Value::String(scalar::GeoPoint::from_str(s)?.to_string()),
                     _ => Value::String(s.clone()),
                 }
             }
@@ -510,9 +510,61 @@ impl<'de> Deserialize<'de> for Bytes {
     }
 }
 
+/// A location on earth, given in degrees of latitude and longitude. Its
+/// string form, which is also how it is passed around in GraphQL and in
+/// entities, is `"<lat>,<lng>"`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lng: f64,
+}
+
+#[derive(Fail, Debug)]
+pub enum GeoPointParseError {
+    #[fail(display = "expected `<lat>,<lng>` but got `{}`", _0)]
+    Format(String),
+    #[fail(display = "latitude {} is not between -90 and 90", _0)]
+    Latitude(f64),
+    #[fail(display = "longitude {} is not between -180 and 180", _0)]
+    Longitude(f64),
+}
+
+impl GeoPoint {
+    pub fn new(lat: f64, lng: f64) -> Result<Self, GeoPointParseError> {
+        if !(-90.0..=90.0).contains(&lat) {
+            return Err(GeoPointParseError::Latitude(lat));
+        }
+        if !(-180.0..=180.0).contains(&lng) {
+            return Err(GeoPointParseError::Longitude(lng));
+        }
+        Ok(GeoPoint { lat, lng })
+    }
+}
+
+impl Display for GeoPoint {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{},{}", self.lat, self.lng)
+    }
+}
+
+impl FromStr for GeoPoint {
+    type Err = GeoPointParseError;
+
+    fn from_str(s: &str) -> Result<GeoPoint, Self::Err> {
+        let coordinate = |part: Option<&str>| {
+            part.and_then(|part| f64::from_str(part.trim()).ok())
+                .ok_or_else(|| GeoPointParseError::Format(s.to_owned()))
+        };
+        let mut parts = s.splitn(2, ',');
+        let lat = coordinate(parts.next())?;
+        let lng = coordinate(parts.next())?;
+        GeoPoint::new(lat, lng)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{BigDecimal, BigInt};
+    use super::{BigDecimal, BigInt, GeoPoint};
     use stable_hash::crypto::SetHasher;
     use stable_hash::prelude::*;
     use stable_hash::utils::stable_hash;
@@ -612,4 +664,15 @@ mod test {
             assert_eq!(normalized.to_string(), string);
         }
     }
+
+    #[test]
+    fn geo_point_from_str() {
+        let point = GeoPoint::from_str(" 52.52, 13.405").unwrap();
+        assert_eq!(GeoPoint::new(52.52, 13.405).unwrap(), point);
+        assert_eq!("52.52,13.405", point.to_string());
+        assert!(GeoPoint::from_str("52.52").is_err());
+        assert!(GeoPoint::from_str("north,east").is_err());
+        assert!(GeoPoint::from_str("91,0").is_err());
+        assert!(GeoPoint::from_str("0,-181").is_err());
+    }
 }
@@ -17,23 +17,29 @@ use web3::types::{Address, H256};
 use crate::components::link_resolver::LinkResolver;
 use crate::components::store::{Store, StoreError, SubgraphDeploymentStore};
 use crate::components::subgraph::DataSourceTemplateInfo;
+use crate::data::graphql::ext::DocumentExt;
 use crate::data::graphql::{TryFromValue, ValueMap};
-use crate::data::query::QueryExecutionError;
-use crate::data::schema::{Schema, SchemaImportError, SchemaValidationError};
-use crate::data::store::Entity;
+use crate::data::query::{ApiVersion, QueryExecutionError};
+use crate::data::schema::{Schema, SchemaImportError, SchemaValidationError, SchemaWarning};
+use crate::data::store::{Entity, Value};
 use crate::data::subgraph::schema::{
     EthereumBlockHandlerEntity, EthereumCallHandlerEntity, EthereumContractAbiEntity,
     EthereumContractDataSourceTemplateEntity, EthereumContractDataSourceTemplateSourceEntity,
     EthereumContractEventHandlerEntity, EthereumContractMappingEntity,
-    EthereumContractSourceEntity, SUBGRAPHS_ID,
+    EthereumContractSourceEntity, SubgraphDeploymentEntity, SubgraphError, TypedEntity,
+    SUBGRAPHS_ID,
 };
 use crate::prelude::{
     anyhow::{self, Context},
     format_err, impl_slog_value, BlockNumber, Deserialize, Fail, Serialize,
 };
-use crate::util::ethereum::string_to_h256;
+use crate::util::env::env_var;
+use crate::util::ethereum::{
+    contract_event_with_signature, contract_function_with_signature, string_to_h256,
+};
 use graphql_parser::query as q;
 
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
@@ -45,6 +51,9 @@ lazy_static! {
         .ok()
         .map(|s| s.eq_ignore_ascii_case("true"))
         .unwrap_or(false);
+
+    /// How many grafts deep a new deployment may be, counting its own
+    static ref MAX_GRAFT_DEPTH: u32 = env_var("GRAPH_MAX_GRAFT_DEPTH").unwrap_or(5);
 }
 
 /// Rust representation of the GraphQL schema for a `SubgraphManifest`.
@@ -196,6 +205,10 @@ impl SubgraphName {
 
         Ok(SubgraphName(s))
     }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
 }
 
 impl fmt::Display for SubgraphName {
@@ -283,6 +296,15 @@ pub enum SubgraphRegistrarError {
     ManifestValidationError(Vec<SubgraphManifestValidationError>),
     #[fail(display = "subgraph deployment error: {}", _0)]
     SubgraphDeploymentError(StoreError),
+    #[fail(display = "subgraph placement error: {}", _0)]
+    PlacementError(String),
+    #[fail(
+        display = "invalid version label {:?}: labels may only contain letters, digits, `.`, `-` and `_`, and have at most 64 characters",
+        _0
+    )]
+    InvalidVersionLabel(String),
+    #[fail(display = "subgraph import error: {}", _0)]
+    ImportError(failure::Error),
     #[fail(display = "subgraph registrar error: {}", _0)]
     Unknown(failure::Error),
 }
@@ -359,6 +381,13 @@ pub enum SubgraphAssignmentProviderEvent {
 pub enum SubgraphManifestValidationWarning {
     #[fail(display = "schema validation produced warnings: {:?}", _0)]
     SchemaValidationWarning(SchemaImportError),
+    #[fail(display = "{}", _0)]
+    SchemaWarning(SchemaWarning),
+    #[fail(
+        display = "data source `{}` has no event, call, block or entity handlers and will not do anything",
+        _0
+    )]
+    DataSourceWithoutHandlers(String),
 }
 
 #[derive(Fail, Debug)]
@@ -381,6 +410,31 @@ pub enum SubgraphManifestValidationError {
     SchemaValidationError(Vec<SchemaValidationError>),
     #[fail(display = "the graft base is invalid: {}", _0)]
     GraftBaseInvalid(String),
+    #[fail(
+        display = "the subgraph uses features that are not declared in the `features` of its manifest: {}",
+        _0
+    )]
+    UndeclaredFeatures(String),
+    #[fail(display = "data source `{}` does not match its ABI: {}", _0, _1)]
+    DataSourceAbiMismatch(String, String), // (data_source, reason)
+    #[fail(display = "data source `{}` can not handle entity changes: {}", _0, _1)]
+    SubgraphDataSourceInvalid(String, String), // (data_source, reason)
+}
+
+impl SubgraphManifestValidationError {
+    /// The individual problems that this error stands for, so that they
+    /// can be shown to the deployer one by one
+    pub fn messages(&self) -> Vec<String> {
+        match self {
+            SubgraphManifestValidationError::SchemaImportError(errors) => {
+                errors.iter().map(|e| e.to_string()).collect()
+            }
+            SubgraphManifestValidationError::SchemaValidationError(errors) => {
+                errors.iter().map(|e| e.to_string()).collect()
+            }
+            e => vec![e.to_string()],
+        }
+    }
 }
 
 #[derive(Fail, Debug)]
@@ -440,9 +494,14 @@ impl UnresolvedSchema {
 pub struct Source {
     #[serde(default, deserialize_with = "deserialize_address")]
     pub address: Option<Address>,
+    #[serde(default)]
     pub abi: String,
     #[serde(rename = "startBlock", default)]
     pub start_block: u64,
+    /// The deployment whose entity changes the data source handles, for
+    /// data sources of kind `subgraph`
+    #[serde(default)]
+    pub subgraph: Option<SubgraphDeploymentId>,
 }
 
 impl From<EthereumContractSourceEntity> for Source {
@@ -451,6 +510,7 @@ impl From<EthereumContractSourceEntity> for Source {
             address: entity.address,
             abi: entity.abi,
             start_block: entity.start_block,
+            subgraph: None,
         }
     }
 }
@@ -563,6 +623,18 @@ impl MappingEventHandler {
     }
 }
 
+/// A handler for the changes to entities of one type in the source
+/// deployment of a data source of kind `subgraph`. The `handler` is called
+/// with the entity whenever it is created or updated, and the
+/// `remove_handler`, if there is one, with its id when it is removed
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MappingEntityHandler {
+    pub entity: String,
+    pub handler: String,
+    pub remove_handler: Option<String>,
+}
+
 impl From<EthereumContractEventHandlerEntity> for MappingEventHandler {
     fn from(entity: EthereumContractEventHandlerEntity) -> Self {
         Self {
@@ -580,6 +652,7 @@ pub struct UnresolvedMapping {
     pub api_version: String,
     pub language: String,
     pub entities: Vec<String>,
+    #[serde(default)]
     pub abis: Vec<UnresolvedMappingABI>,
     #[serde(default)]
     pub block_handlers: Vec<MappingBlockHandler>,
@@ -587,6 +660,8 @@ pub struct UnresolvedMapping {
     pub call_handlers: Vec<MappingCallHandler>,
     #[serde(default)]
     pub event_handlers: Vec<MappingEventHandler>,
+    #[serde(default)]
+    pub entity_handlers: Vec<MappingEntityHandler>,
     pub file: Link,
 }
 
@@ -600,6 +675,7 @@ pub struct Mapping {
     pub block_handlers: Vec<MappingBlockHandler>,
     pub call_handlers: Vec<MappingCallHandler>,
     pub event_handlers: Vec<MappingEventHandler>,
+    pub entity_handlers: Vec<MappingEntityHandler>,
     pub runtime: Arc<Vec<u8>>,
     pub link: Link,
 }
@@ -619,6 +695,7 @@ impl UnresolvedMapping {
             block_handlers,
             call_handlers,
             event_handlers,
+            entity_handlers,
             file: link,
         } = self;
 
@@ -646,6 +723,7 @@ impl UnresolvedMapping {
             block_handlers: block_handlers.clone(),
             call_handlers: call_handlers.clone(),
             event_handlers: event_handlers.clone(),
+            entity_handlers,
             runtime,
             link,
         })
@@ -663,6 +741,7 @@ impl From<EthereumContractMappingEntity> for UnresolvedMapping {
             event_handlers: entity.event_handlers.into_iter().map(Into::into).collect(),
             call_handlers: entity.call_handlers.into_iter().map(Into::into).collect(),
             block_handlers: entity.block_handlers.into_iter().map(Into::into).collect(),
+            entity_handlers: vec![],
             file: entity.file.into(),
         }
     }
@@ -681,6 +760,10 @@ pub struct BaseDataSource<M, T> {
     pub templates: Vec<T>, // Deprecated in manifest spec version 0.0.2
 }
 
+/// The kind of data sources whose handlers are triggered by the entity
+/// changes of another deployment instead of by Ethereum data
+pub const SUBGRAPH_DATA_SOURCE_KIND: &str = "subgraph";
+
 pub type UnresolvedDataSource = BaseDataSource<UnresolvedMapping, UnresolvedDataSourceTemplate>;
 pub type DataSource = BaseDataSource<Mapping, DataSourceTemplate>;
 
@@ -724,6 +807,83 @@ impl UnresolvedDataSource {
     }
 }
 
+impl DataSource {
+    /// The deployment whose entity changes this data source handles, if it
+    /// is of kind `subgraph`
+    pub fn source_subgraph(&self) -> Option<&SubgraphDeploymentId> {
+        match self.kind.as_str() {
+            SUBGRAPH_DATA_SOURCE_KIND => self.source.subgraph.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Check that only data sources of kind `subgraph` have entity handlers,
+    /// and that those handle existing entity types of an existing
+    /// deployment other than `id`, and nothing else
+    fn validate_entity_handlers<S: SubgraphDeploymentStore>(
+        &self,
+        store: &S,
+        id: &SubgraphDeploymentId,
+    ) -> Vec<SubgraphManifestValidationError> {
+        let invalid = |reason: String| {
+            vec![SubgraphManifestValidationError::SubgraphDataSourceInvalid(
+                self.name.clone(),
+                reason,
+            )]
+        };
+
+        let mapping = &self.mapping;
+        if self.kind != SUBGRAPH_DATA_SOURCE_KIND {
+            if !mapping.entity_handlers.is_empty() {
+                return invalid(format!(
+                    "only data sources of kind `{}` can have entity handlers",
+                    SUBGRAPH_DATA_SOURCE_KIND
+                ));
+            }
+            return vec![];
+        }
+
+        let source = match &self.source.subgraph {
+            Some(source) => source,
+            None => return invalid("the deployment in `source.subgraph` is missing".to_owned()),
+        };
+        if source == id {
+            return invalid("a subgraph can not handle its own entity changes".to_owned());
+        }
+        if mapping.entity_handlers.is_empty() {
+            return invalid("it has no entity handlers".to_owned());
+        }
+        if !mapping.event_handlers.is_empty()
+            || !mapping.call_handlers.is_empty()
+            || !mapping.block_handlers.is_empty()
+        {
+            return invalid("it can only have entity handlers".to_owned());
+        }
+
+        let schema = match store.input_schema(source) {
+            Ok(schema) => schema,
+            Err(_) => return invalid(format!("the source deployment `{}` does not exist", source)),
+        };
+        mapping
+            .entity_handlers
+            .iter()
+            .filter(|handler| {
+                schema
+                    .document
+                    .get_object_type_definition(&handler.entity)
+                    .is_none()
+            })
+            .flat_map(|handler| {
+                invalid(format!(
+                    "the entity type `{}` of handler `{}` is not defined by the source \
+                     deployment `{}`",
+                    handler.entity, handler.handler, source
+                ))
+            })
+            .collect()
+    }
+}
+
 impl TryFrom<DataSourceTemplateInfo> for DataSource {
     type Error = anyhow::Error;
 
@@ -761,6 +921,7 @@ impl TryFrom<DataSourceTemplateInfo> for DataSource {
                 address: Some(address),
                 abi: template.source.abi,
                 start_block: 0,
+                subgraph: None,
             },
             mapping: template.mapping,
             context,
@@ -871,31 +1032,158 @@ pub struct Graft {
 }
 
 impl Graft {
+    /// Check that the base exists, has processed the graft block and had
+    /// no errors up to it, and, unless `ignore_depth` is set, that the
+    /// chain of grafts underneath the new deployment is not longer than
+    /// `GRAPH_MAX_GRAFT_DEPTH`
     fn validate<S: Store + SubgraphDeploymentStore>(
         &self,
         store: Arc<S>,
+        ignore_depth: bool,
     ) -> Vec<SubgraphManifestValidationError> {
         fn gbi(msg: String) -> Vec<SubgraphManifestValidationError> {
             vec![SubgraphManifestValidationError::GraftBaseInvalid(msg)]
         }
 
+        let base = match store.get(SubgraphDeploymentEntity::key(self.base.clone())) {
+            Err(e) => return gbi(e.to_string()),
+            Ok(None) => {
+                return gbi(format!(
+                    "failed to graft onto `{}` since it does not exist",
+                    self.base
+                ))
+            }
+            Ok(Some(base)) => base,
+        };
+
         match store.block_ptr(self.base.clone()) {
-            Err(e) => gbi(e.to_string()),
-            Ok(None) => gbi(format!(
-                "failed to graft onto `{}` since it has not processed any blocks",
-                self.base
-            )),
+            Err(e) => return gbi(e.to_string()),
+            Ok(None) => {
+                return gbi(format!(
+                    "failed to graft onto `{}` since it has not processed any blocks",
+                    self.base
+                ))
+            }
             Ok(Some(ptr)) => {
                 if ptr.number < self.block as u64 {
-                    gbi(format!(
+                    return gbi(format!(
                         "failed to graft onto `{}` at block {} since it has only processed block {}",
                         self.base, self.block, ptr.number
-                    ))
-                } else {
-                    vec![]
+                    ));
+                }
+            }
+        }
+
+        // A fatal error stops the base before the block that caused it, so
+        // only non-fatal errors can affect the data up to the graft block
+        let mut errors = vec![];
+        match Self::error_blocks(store.as_ref(), &base) {
+            Err(e) => errors.extend(gbi(e.to_string())),
+            Ok(blocks) => {
+                if let Some(block) = blocks.into_iter().filter(|b| *b <= self.block).min() {
+                    errors.extend(gbi(format!(
+                        "failed to graft onto `{}` at block {} since it is unhealthy from \
+                         block {} on",
+                        self.base, self.block, block
+                    )));
+                }
+            }
+        }
+
+        if !ignore_depth {
+            match Self::depth(store.as_ref(), base) {
+                Err(e) => errors.extend(gbi(e.to_string())),
+                Ok(depth) if depth > *MAX_GRAFT_DEPTH => errors.extend(gbi(format!(
+                    "failed to graft onto `{}` since the deployment would be grafted {} levels \
+                     deep, but at most {} are allowed; deploy with `ignore_graft_depth` to \
+                     allow it anyway",
+                    self.base, depth, *MAX_GRAFT_DEPTH
+                ))),
+                Ok(_) => (),
+            }
+        }
+        errors
+    }
+
+    /// The blocks at which the deployment `base` had non-fatal errors
+    fn error_blocks<S: Store>(store: &S, base: &Entity) -> Result<Vec<u64>, Error> {
+        let ids = match base.get("nonFatalErrors") {
+            Some(Value::List(ids)) => ids.clone(),
+            _ => vec![],
+        };
+        let mut blocks = vec![];
+        for id in ids {
+            let id = match id {
+                Value::String(id) => id,
+                _ => continue,
+            };
+            if let Some(error) = store.get(SubgraphError::key(id))? {
+                if let Some(Value::BigInt(number)) = error.get("blockNumber") {
+                    blocks.push(number.to_u64());
                 }
             }
         }
+        Ok(blocks)
+    }
+
+    /// The number of grafts in the chain that ends with a deployment that
+    /// is grafted onto `base`
+    fn depth<S: Store>(store: &S, base: Entity) -> Result<u32, Error> {
+        let mut depth = 1;
+        let mut current = base;
+        // Give up once the limit is exceeded, which also protects against
+        // cycles in the metadata
+        while depth <= *MAX_GRAFT_DEPTH {
+            let id = match current.get("graftBase") {
+                Some(Value::String(id)) => SubgraphDeploymentId::new(id.clone())
+                    .map_err(|()| format_err!("invalid graft base `{}`", id))?,
+                _ => break,
+            };
+            depth += 1;
+            current = match store.get(SubgraphDeploymentEntity::key(id))? {
+                Some(entity) => entity,
+                None => break,
+            };
+        }
+        Ok(depth)
+    }
+}
+
+/// Features of the node that subgraphs may depend on. Subgraphs must
+/// declare the features they use in the `features` list of their manifest,
+/// so that nodes that do not support a feature reject the subgraph when it
+/// is deployed instead of failing later
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SubgraphFeature {
+    /// `@fulltext` directives in the schema
+    FullTextSearch,
+    /// A `graft` in the manifest
+    Grafting,
+    /// Data sources of kind `subgraph`
+    SubgraphDataSources,
+}
+
+impl SubgraphFeature {
+    /// All features that this node supports
+    pub const ALL: &'static [SubgraphFeature] = &[
+        SubgraphFeature::FullTextSearch,
+        SubgraphFeature::Grafting,
+        SubgraphFeature::SubgraphDataSources,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubgraphFeature::FullTextSearch => "fullTextSearch",
+            SubgraphFeature::Grafting => "grafting",
+            SubgraphFeature::SubgraphDataSources => "subgraphDataSources",
+        }
+    }
+}
+
+impl fmt::Display for SubgraphFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
@@ -912,6 +1200,13 @@ pub struct BaseSubgraphManifest<S, D, T> {
     pub graft: Option<Graft>,
     #[serde(default)]
     pub templates: Vec<T>,
+    /// The features of the node that the subgraph uses
+    #[serde(default)]
+    pub features: BTreeSet<SubgraphFeature>,
+    /// The version of the GraphQL API semantics that queries against the
+    /// subgraph use unless they ask for a different one
+    #[serde(default)]
+    pub api_version: ApiVersion,
 }
 
 /// Consider two subgraphs to be equal if they come from the same IPLD link.
@@ -945,18 +1240,29 @@ impl UnvalidatedSubgraphManifest {
         ))
     }
 
+    /// Check that the manifest can be deployed. With `ignore_graft_depth`,
+    /// the manifest may be grafted onto a chain of grafts that is longer
+    /// than `GRAPH_MAX_GRAFT_DEPTH`
     pub fn validate<S: Store + SubgraphDeploymentStore>(
         self,
         store: Arc<S>,
+        ignore_graft_depth: bool,
     ) -> Result<
         (SubgraphManifest, Vec<SubgraphManifestValidationWarning>),
         Vec<SubgraphManifestValidationError>,
     > {
         let (schemas, import_errors) = self.0.schema.resolve_schema_references(store.clone());
-        let validation_warnings = import_errors
+        let mut validation_warnings: Vec<_> = import_errors
             .into_iter()
             .map(SubgraphManifestValidationWarning::SchemaValidationWarning)
             .collect();
+        validation_warnings.extend(
+            self.0
+                .schema
+                .warnings()
+                .into_iter()
+                .map(SubgraphManifestValidationWarning::SchemaWarning),
+        );
 
         let mut errors: Vec<SubgraphManifestValidationError> = vec![];
 
@@ -1008,7 +1314,7 @@ impl UnvalidatedSubgraphManifest {
             .data_sources
             .iter()
             .cloned()
-            .filter(|d| d.kind.eq("ethereum/contract"))
+            .filter(|d| d.kind.eq("ethereum/contract") || d.kind.eq(SUBGRAPH_DATA_SOURCE_KIND))
             .filter_map(|d| d.network)
             .collect::<Vec<String>>();
         networks.sort();
@@ -1030,13 +1336,88 @@ impl UnvalidatedSubgraphManifest {
                 ));
             });
 
+        for data_source in self.0.data_sources.iter() {
+            errors.extend(data_source.validate_entity_handlers(store.as_ref(), &self.0.id));
+        }
+
         if let Some(graft) = &self.0.graft {
             if *DISABLE_GRAFTS {
                 errors.push(SubgraphManifestValidationError::GraftBaseInvalid(
                     "Grafting of subgraphs is currently disabled".to_owned(),
                 ));
             }
-            errors.extend(graft.validate(store));
+            errors.extend(graft.validate(store, ignore_graft_depth));
+        }
+
+        // Validate that the handlers of data sources and templates refer to
+        // events and functions that their ABI has, since the mappings would
+        // otherwise fail once the first block is processed
+        let mappings = self
+            .0
+            .data_sources
+            .iter()
+            .filter(|ds| ds.kind != SUBGRAPH_DATA_SOURCE_KIND)
+            .map(|ds| (&ds.name, &ds.source.abi, &ds.mapping))
+            .chain(
+                self.0
+                    .templates
+                    .iter()
+                    .map(|t| (&t.name, &t.source.abi, &t.mapping)),
+            );
+        for (name, abi_name, mapping) in mappings {
+            if mapping.event_handlers.is_empty()
+                && mapping.call_handlers.is_empty()
+                && mapping.block_handlers.is_empty()
+                && mapping.entity_handlers.is_empty()
+            {
+                validation_warnings.push(
+                    SubgraphManifestValidationWarning::DataSourceWithoutHandlers(name.clone()),
+                );
+            }
+            let mismatch = |reason: String| {
+                SubgraphManifestValidationError::DataSourceAbiMismatch(name.clone(), reason)
+            };
+            let abi = match mapping.abis.iter().find(|abi| &abi.name == abi_name) {
+                Some(abi) => abi,
+                None => {
+                    errors.push(mismatch(format!(
+                        "the ABI `{}` of its source is not listed in `mapping.abis`",
+                        abi_name
+                    )));
+                    continue;
+                }
+            };
+            for handler in mapping.event_handlers.iter() {
+                if contract_event_with_signature(&abi.contract, &handler.event).is_none() {
+                    errors.push(mismatch(format!(
+                        "the event `{}` of handler `{}` is not in the ABI `{}`",
+                        handler.event, handler.handler, abi.name
+                    )));
+                }
+            }
+            for handler in mapping.call_handlers.iter() {
+                if contract_function_with_signature(&abi.contract, &handler.function).is_none() {
+                    errors.push(mismatch(format!(
+                        "the function `{}` of handler `{}` is not a non-constant function \
+                         in the ABI `{}`",
+                        handler.function, handler.handler, abi.name
+                    )));
+                }
+            }
+        }
+
+        // Validate that the manifest declares every feature that it uses
+        let undeclared: Vec<_> = self
+            .0
+            .used_features()
+            .into_iter()
+            .filter(|feature| !self.0.features.contains(feature))
+            .map(|feature| feature.as_str())
+            .collect();
+        if !undeclared.is_empty() {
+            errors.push(SubgraphManifestValidationError::UndeclaredFeatures(
+                undeclared.join(", "),
+            ));
         }
 
         match errors.is_empty() {
@@ -1098,12 +1479,32 @@ impl SubgraphManifest {
         self.data_sources
             .iter()
             .cloned()
-            .filter(|d| &d.kind == "ethereum/contract")
+            .filter(|d| &d.kind == "ethereum/contract" || &d.kind == SUBGRAPH_DATA_SOURCE_KIND)
             .filter_map(|d| d.network)
             .next()
             .expect("Validated manifest does not have a network defined on any datasource")
     }
 
+    /// The features of the node that the subgraph uses, whether it
+    /// declares them or not
+    pub fn used_features(&self) -> BTreeSet<SubgraphFeature> {
+        let mut features = BTreeSet::new();
+        if !self.schema.document.get_fulltext_directives().is_empty() {
+            features.insert(SubgraphFeature::FullTextSearch);
+        }
+        if self.graft.is_some() {
+            features.insert(SubgraphFeature::Grafting);
+        }
+        if self
+            .data_sources
+            .iter()
+            .any(|data_source| data_source.kind == SUBGRAPH_DATA_SOURCE_KIND)
+        {
+            features.insert(SubgraphFeature::SubgraphDataSources);
+        }
+        features
+    }
+
     pub fn start_blocks(&self) -> Vec<u64> {
         self.data_sources
             .iter()
@@ -1128,6 +1529,8 @@ impl UnresolvedSubgraphManifest {
             data_sources,
             graft,
             templates,
+            features,
+            api_version,
         } = self;
 
         match semver::Version::parse(&spec_version) {
@@ -1172,6 +1575,8 @@ impl UnresolvedSubgraphManifest {
             data_sources,
             graft,
             templates,
+            features,
+            api_version,
         })
     }
 }
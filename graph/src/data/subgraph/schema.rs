@@ -194,6 +194,7 @@ pub struct SubgraphVersionEntity {
     subgraph_id: String,
     deployment_id: SubgraphDeploymentId,
     created_at: u64,
+    label: Option<String>,
 }
 
 impl TypedEntity for SubgraphVersionEntity {
@@ -202,11 +203,17 @@ impl TypedEntity for SubgraphVersionEntity {
 }
 
 impl SubgraphVersionEntity {
-    pub fn new(subgraph_id: String, deployment_id: SubgraphDeploymentId, created_at: u64) -> Self {
+    pub fn new(
+        subgraph_id: String,
+        deployment_id: SubgraphDeploymentId,
+        created_at: u64,
+        label: Option<String>,
+    ) -> Self {
         Self {
             subgraph_id,
             deployment_id,
             created_at,
+            label,
         }
     }
 
@@ -216,6 +223,7 @@ impl SubgraphVersionEntity {
             subgraph: self.subgraph_id,
             deployment: self.deployment_id.to_string(),
             createdAt: self.created_at,
+            label: self.label,
         };
         vec![set_metadata_operation(Self::TYPENAME, id, entity)]
     }
@@ -389,6 +397,7 @@ impl SubgraphDeploymentEntity {
             failed: failed,
             health: health,
             synced: synced,
+            maintenance: false,
             nonFatalErrors: non_fatal_errors,
             earliestEthereumBlockHash: earliest_ethereum_block_hash,
             earliestEthereumBlockNumber: earliest_ethereum_block_number,
@@ -425,6 +434,23 @@ impl SubgraphDeploymentEntity {
         )]
     }
 
+    /// Put the deployment into maintenance, which pauses indexing it, or
+    /// take it out of maintenance
+    pub fn maintenance_operations(
+        id: &SubgraphDeploymentId,
+        maintenance: bool,
+    ) -> Vec<MetadataOperation> {
+        let entity = entity! {
+            maintenance: maintenance,
+        };
+
+        vec![update_metadata_operation(
+            Self::TYPENAME,
+            id.to_string(),
+            entity,
+        )]
+    }
+
     /// When starting the subgraph, we try to "unfail" it.
     pub fn unfail_operations(
         id: &SubgraphDeploymentId,
@@ -510,6 +536,8 @@ pub struct SubgraphManifestEntity {
     schema: String,
     data_sources: Vec<EthereumContractDataSourceEntity>,
     templates: Vec<EthereumContractDataSourceTemplateEntity>,
+    features: Vec<String>,
+    api_version: String,
 }
 
 impl TypedEntity for SubgraphManifestEntity {
@@ -551,6 +579,8 @@ impl SubgraphManifestEntity {
             schema: self.schema,
             dataSources: data_source_ids,
             templates: template_ids,
+            features: self.features.into_iter().map(Value::from).collect::<Vec<Value>>(),
+            apiVersion: self.api_version,
         };
 
         ops.push(set_metadata_operation(Self::TYPENAME, id, entity));
@@ -572,6 +602,12 @@ impl<'a> From<&'a super::SubgraphManifest> for SubgraphManifestEntity {
                 .iter()
                 .map(EthereumContractDataSourceTemplateEntity::from)
                 .collect(),
+            features: manifest
+                .features
+                .iter()
+                .map(|feature| feature.to_string())
+                .collect(),
+            api_version: manifest.api_version.to_string(),
         }
     }
 }
@@ -1340,6 +1376,15 @@ pub struct SubgraphError {
     pub message: String,
     pub block_ptr: Option<EthereumBlockPointer>,
     pub handler: Option<String>,
+    /// The data source of `handler`
+    pub data_source: Option<String>,
+    /// Whether the error would happen again if the block was processed
+    /// again, because it was caused by the mapping rather than by
+    /// something like an unreachable Ethereum node
+    pub deterministic: bool,
+    /// The proof of indexing of the deployment for the last block that it
+    /// processed before the error
+    pub proof_of_indexing: Option<H256>,
 }
 
 impl TypedEntity for SubgraphError {
@@ -1362,6 +1407,9 @@ impl From<SubgraphError> for Entity {
             message,
             block_ptr,
             handler,
+            data_source,
+            deterministic,
+            proof_of_indexing,
         } = subgraph_error;
 
         let mut entity = Entity::new();
@@ -1370,6 +1418,9 @@ impl From<SubgraphError> for Entity {
         entity.set("blockNumber", block_ptr.map(|x| x.number));
         entity.set("blockHash", block_ptr.map(|x| x.hash));
         entity.set("handler", handler);
+        entity.set("dataSource", data_source);
+        entity.set("deterministic", deterministic);
+        entity.set("proofOfIndexing", proof_of_indexing);
         entity
     }
 }
@@ -1389,6 +1440,9 @@ impl TryFromValue for SubgraphError {
             message: value.get_required("message")?,
             block_ptr,
             handler: value.get_optional("handler")?,
+            data_source: value.get_optional("dataSource")?,
+            deterministic: value.get_optional("deterministic")?.unwrap_or(false),
+            proof_of_indexing: value.get_optional("proofOfIndexing")?,
         })
     }
 }
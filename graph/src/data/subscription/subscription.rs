@@ -1,11 +1,14 @@
+use std::time::Duration;
+
 use crate::prelude::Query;
 
 /// A GraphQL subscription made by a client.
-///
-/// At the moment, this only contains the GraphQL query submitted as the
-/// subscription payload.
 #[derive(Clone, Debug)]
 pub struct Subscription {
     /// The GraphQL subscription query.
     pub query: Query,
+
+    /// The shortest time between two results that the client asked for;
+    /// changes that happen in between are reported together
+    pub interval: Option<Duration>,
 }
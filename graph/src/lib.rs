@@ -13,6 +13,9 @@ pub mod ext;
 /// Logging utilities
 pub mod log;
 
+/// Distributed tracing
+pub mod trace;
+
 /// `CheapClone` trait.
 pub mod cheap_clone;
 
@@ -86,36 +89,42 @@ pub mod prelude {
     pub use crate::components::graphql::{
         GraphQlRunner, QueryResultFuture, SubscriptionResultFuture,
     };
-    pub use crate::components::link_resolver::{JsonStreamValue, JsonValueStream, LinkResolver};
+    pub use crate::components::link_resolver::{
+        DeploymentFiles, IpfsCache, JsonStreamValue, JsonValueStream, LinkResolver,
+    };
     pub use crate::components::metrics::{
         aggregate::Aggregate, stopwatch::StopwatchMetrics, Collector, Counter, CounterVec, Gauge,
         GaugeVec, Histogram, HistogramOpts, HistogramVec, MetricsRegistry, Opts, PrometheusError,
         Registry,
     };
-    pub use crate::components::server::admin::JsonRpcServer;
+    pub use crate::components::server::admin::{AdminAuth, AdminScope, JsonRpcServer};
+    pub use crate::components::server::health::{ComponentHealth, HealthCheck, HealthServer};
     pub use crate::components::server::index_node::IndexNodeServer;
     pub use crate::components::server::metrics::MetricsServer;
     pub use crate::components::server::query::GraphQLServer;
     pub use crate::components::server::subscription::SubscriptionServer;
     pub use crate::components::store::{
-        AttributeIndexDefinition, BlockNumber, ChainStore, ChildMultiplicity, EntityCache,
-        EntityChange, EntityChangeOperation, EntityCollection, EntityFilter, EntityKey, EntityLink,
-        EntityModification, EntityOperation, EntityOrder, EntityQuery, EntityRange, EntityWindow,
-        EthereumCallCache, MetadataOperation, ParentLink, Store, StoreError, StoreEvent,
-        StoreEventStream, StoreEventStreamBox, SubgraphDeploymentStore, TransactionAbortError,
-        WindowAttribute, BLOCK_NUMBER_MAX, SUBSCRIPTION_THROTTLE_INTERVAL,
+        AggregateFilter, AttributeIndexDefinition, BlockNumber, CachedEthereumCall, ChainStore,
+        ChildMultiplicity, DeploymentBlocks, EntityAggregate, EntityCache, EntityChange,
+        EntityChangeOperation, EntityCollection, EntityFilter, EntityGroupQuery, EntityKey,
+        EntityLink, EntityModification, EntityOperation, EntityOrder, EntityQuery, EntityRange,
+        EntityWindow, EthereumCallCache, GroupOrder, GroupSortKey, MetadataOperation, ParentLink,
+        ReplicaIdentity, Store, StoreError, StoreEvent, StoreEventStream, StoreEventStreamBox,
+        SubgraphDeploymentStore, SubgraphVersionSelector, SyncSample, TransactionAbortError,
+        WindowAttribute, BLOCK_NUMBER_MAX, SUBSCRIPTION_MIN_INTERVAL,
+        SUBSCRIPTION_THROTTLE_INTERVAL,
     };
     pub use crate::components::subgraph::{
-        BlockState, DataSourceLoader, DataSourceTemplateInfo, HostMetrics, RuntimeHost,
-        RuntimeHostBuilder, SubgraphAssignmentProvider, SubgraphInstance, SubgraphInstanceManager,
-        SubgraphRegistrar, SubgraphVersionSwitchingMode,
+        import_subgraph, is_local_file, BlockState, DataSourceLoader, DataSourceTemplateInfo,
+        DeploymentPlacer, HostMetrics, RuntimeHost, RuntimeHostBuilder, SubgraphAssignmentProvider,
+        SubgraphInstance, SubgraphInstanceManager, SubgraphRegistrar, SubgraphVersionSwitchingMode,
     };
     pub use crate::components::{EventConsumer, EventProducer};
 
     pub use crate::cheap_clone::CheapClone;
     pub use crate::data::graphql::{SerializableValue, TryFromValue, ValueMap};
     pub use crate::data::query::{
-        Query, QueryError, QueryExecutionError, QueryResult, QueryVariables,
+        ApiVersion, Query, QueryError, QueryExecutionError, QueryResult, QueryVariables,
     };
     pub use crate::data::schema::Schema;
     pub use crate::data::store::ethereum::*;
@@ -128,9 +137,10 @@ pub mod prelude {
     pub use crate::data::subgraph::{
         BlockHandlerFilter, CreateSubgraphResult, DataSource, DataSourceContext,
         DataSourceTemplate, Link, MappingABI, MappingBlockHandler, MappingCallHandler,
-        MappingEventHandler, SubgraphAssignmentProviderError, SubgraphAssignmentProviderEvent,
-        SubgraphDeploymentId, SubgraphManifest, SubgraphManifestResolveError,
-        SubgraphManifestValidationError, SubgraphName, SubgraphRegistrarError,
+        MappingEntityHandler, MappingEventHandler, SubgraphAssignmentProviderError,
+        SubgraphAssignmentProviderEvent, SubgraphDeploymentId, SubgraphFeature, SubgraphManifest,
+        SubgraphManifestResolveError, SubgraphManifestValidationError,
+        SubgraphManifestValidationWarning, SubgraphName, SubgraphRegistrarError,
         UnvalidatedSubgraphManifest,
     };
     pub use crate::data::subscription::{
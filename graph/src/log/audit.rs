@@ -0,0 +1,361 @@
+//! A record of every query that the node runs, for compliance and capacity
+//! planning. The query runner hands a `QueryAuditRecord` for each query to
+//! a `QueryAuditLog`, which passes them on to its sinks from a background
+//! task so that queries never wait for the sinks.
+
+use async_trait::async_trait;
+use chrono::prelude::{SecondsFormat, Utc};
+use failure::{format_err, Error};
+use futures03::channel::mpsc;
+use futures03::stream::StreamExt;
+use serde::Serialize;
+use slog::{error, info, warn, Logger};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tiny_keccak::keccak256;
+
+use crate::data::query::{Query, QueryResult};
+use crate::util::env::env_var;
+
+/// How many records may wait for the sinks; records beyond that are
+/// dropped and counted
+const QUEUE_SIZE: usize = 10_000;
+
+/// The most records that are handed to a sink at once
+const MAX_BATCH_SIZE: usize = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheStatus {
+    /// The whole result came from the query cache
+    Hit,
+    /// At least part of the result had to be computed
+    Miss,
+}
+
+/// What the audit log records about one query
+#[derive(Clone, Debug, Serialize)]
+pub struct QueryAuditRecord {
+    /// When the query started, in RFC 3339 format
+    pub timestamp: String,
+    /// The id of the query in other logs and in traces
+    pub query_id: String,
+    pub deployment: String,
+    /// The hash of the shape of the query, which is the same for queries
+    /// that only differ in their arguments and variables
+    pub query_hash: String,
+    /// The query, as the node formats it
+    pub query: String,
+    /// The size of the variables, serialized as JSON, in bytes
+    pub variables_size: usize,
+    pub duration_ms: u64,
+    pub cache: CacheStatus,
+    /// The size of the result, serialized as JSON, in bytes
+    pub result_size: usize,
+    pub errors: usize,
+    /// Who sent the query, see `client_identity`
+    pub client: Option<String>,
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+impl QueryAuditRecord {
+    /// Start recording `query`. `shape_hash` identifies the shape of the
+    /// query
+    pub fn start(query: &Query, shape_hash: u64) -> Self {
+        QueryAuditRecord {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            query_id: query.query_id.clone(),
+            deployment: query.schema.id.to_string(),
+            query_hash: format!("{:016x}", shape_hash),
+            query: query.document.to_string(),
+            variables_size: query
+                .variables
+                .as_ref()
+                .and_then(|variables| serde_json::to_vec(variables).ok())
+                .map_or(0, |json| json.len()),
+            duration_ms: 0,
+            cache: CacheStatus::Miss,
+            result_size: 0,
+            errors: 0,
+            client: query.client.clone(),
+            started: Some(Instant::now()),
+        }
+    }
+
+    /// Record how the query went
+    pub fn finish(self, result: &QueryResult, cache: CacheStatus) -> Self {
+        QueryAuditRecord {
+            duration_ms: self
+                .started
+                .map_or(0, |started| started.elapsed().as_millis() as u64),
+            cache,
+            result_size: serde_json::to_vec(result).map_or(0, |json| json.len()),
+            errors: result.errors.as_ref().map_or(0, |errors| errors.len()),
+            ..self
+        }
+    }
+}
+
+/// How clients appear in the audit log: by a hash of their bearer token if
+/// they sent one, so that the log does not reveal tokens, and otherwise by
+/// their address
+pub fn client_identity(bearer_token: Option<&str>, remote_addr: Option<IpAddr>) -> String {
+    match (bearer_token, remote_addr) {
+        (Some(token), _) => format!("token:{}", hex::encode(&keccak256(token.as_bytes())[..8])),
+        (None, Some(addr)) => format!("ip:{}", addr),
+        (None, None) => "unknown".to_owned(),
+    }
+}
+
+/// A destination for audit records
+#[async_trait]
+pub trait QueryAuditSink: Send {
+    fn name(&self) -> &'static str;
+
+    async fn write(&mut self, records: &[QueryAuditRecord]) -> Result<(), Error>;
+}
+
+/// Writes records as JSON lines to a file. When the file would grow
+/// beyond `max_size` bytes, it is renamed to `<path>.1`, older files move
+/// to `<path>.2` and so on, and the oldest beyond `keep` files is removed
+pub struct RotatingFileSink {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: Option<File>,
+    size: u64,
+}
+
+impl RotatingFileSink {
+    pub fn new(path: PathBuf, max_size: u64, keep: usize) -> Self {
+        RotatingFileSink {
+            path,
+            max_size,
+            keep,
+            file: None,
+            size: 0,
+        }
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> Result<(), Error> {
+        self.file = None;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        let oldest = self.rotated(self.keep);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for n in (1..self.keep).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(from, self.rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> Result<(), Error> {
+        if self.file.is_some() && self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        self.file.as_mut().unwrap().write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl QueryAuditSink for RotatingFileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn write(&mut self, records: &[QueryAuditRecord]) -> Result<(), Error> {
+        for record in records {
+            let mut line = serde_json::to_vec(record)?;
+            line.push(b'\n');
+            self.write_line(&line)?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Produces records as JSON messages to a Kafka topic through the
+/// Confluent REST Proxy (API v2)
+pub struct KafkaSink {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl KafkaSink {
+    pub fn new(rest_proxy_url: &str, topic: &str) -> Self {
+        KafkaSink {
+            url: format!("{}/topics/{}", rest_proxy_url.trim_end_matches('/'), topic),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl QueryAuditSink for KafkaSink {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn write(&mut self, records: &[QueryAuditRecord]) -> Result<(), Error> {
+        let body = serde_json::json!({
+            "records": records
+                .iter()
+                .map(|record| serde_json::json!({ "value": record }))
+                .collect::<Vec<_>>(),
+        });
+        self.client
+            .post(&self.url)
+            .header("Content-Type", "application/vnd.kafka.json.v2+json")
+            .body(body.to_string())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map(|_| ())
+            .map_err(|e| format_err!("{}", e))
+    }
+}
+
+/// Hands audit records to sinks in the background
+pub struct QueryAuditLog {
+    sender: Mutex<mpsc::Sender<QueryAuditRecord>>,
+    dropped: AtomicUsize,
+    logger: Logger,
+}
+
+impl QueryAuditLog {
+    /// Start passing records to `sinks`. Must be called from within a
+    /// Tokio runtime
+    pub fn new(logger: Logger, mut sinks: Vec<Box<dyn QueryAuditSink>>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<QueryAuditRecord>(QUEUE_SIZE);
+        let sink_logger = logger.clone();
+        // The file sink does blocking IO
+        crate::task_spawn::spawn_blocking(async move {
+            while let Some(record) = receiver.next().await {
+                let mut records = vec![record];
+                while records.len() < MAX_BATCH_SIZE {
+                    match receiver.try_next() {
+                        Ok(Some(record)) => records.push(record),
+                        _ => break,
+                    }
+                }
+                for sink in sinks.iter_mut() {
+                    if let Err(e) = sink.write(&records).await {
+                        error!(sink_logger, "Failed to write query audit records";
+                               "sink" => sink.name(),
+                               "records" => records.len(),
+                               "error" => e.to_string());
+                    }
+                }
+            }
+        });
+        QueryAuditLog {
+            sender: Mutex::new(sender),
+            dropped: AtomicUsize::new(0),
+            logger,
+        }
+    }
+
+    /// Configure the audit log from the environment; returns `None` if no
+    /// sink is configured
+    pub fn from_env(logger: &Logger) -> Option<Self> {
+        let logger = logger.new(slog::o!("component" => "QueryAuditLog"));
+        let mut sinks: Vec<Box<dyn QueryAuditSink>> = vec![];
+        if let Ok(path) = env::var("GRAPH_QUERY_AUDIT_FILE") {
+            let max_size = env_var("GRAPH_QUERY_AUDIT_FILE_MAX_SIZE").unwrap_or(100 * 1024 * 1024);
+            let keep = env_var("GRAPH_QUERY_AUDIT_FILE_KEEP").unwrap_or(10);
+            info!(logger, "Writing query audit log to file";
+                  "path" => &path, "max_size" => max_size, "keep" => keep);
+            sinks.push(Box::new(RotatingFileSink::new(path.into(), max_size, keep)));
+        }
+        if let Ok(url) = env::var("GRAPH_QUERY_AUDIT_KAFKA_URL") {
+            let topic = env::var("GRAPH_QUERY_AUDIT_KAFKA_TOPIC")
+                .unwrap_or_else(|_| "graph-node-queries".to_owned());
+            info!(logger, "Writing query audit log to Kafka";
+                  "url" => &url, "topic" => &topic);
+            sinks.push(Box::new(KafkaSink::new(&url, &topic)));
+        }
+        if sinks.is_empty() {
+            None
+        } else {
+            Some(QueryAuditLog::new(logger, sinks))
+        }
+    }
+
+    /// Queue `record` for the sinks. If the sinks can not keep up, the
+    /// record is dropped
+    pub fn record(&self, record: QueryAuditRecord) {
+        if self.sender.lock().unwrap().try_send(record).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped % 1000 == 1 {
+                warn!(self.logger, "Dropping query audit records because the sinks are too slow";
+                      "dropped" => dropped);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_files() {
+        let dir = std::env::temp_dir().join(format!("audit-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("queries.log");
+        let mut sink = RotatingFileSink::new(path.clone(), 10, 2);
+        for line in &["first\n", "second\n", "third\n", "fourth\n"] {
+            sink.write_line(line.as_bytes()).unwrap();
+        }
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!("fourth\n", read(path.clone()));
+        assert_eq!("third\n", read(sink.rotated(1)));
+        assert_eq!("second\n", read(sink.rotated(2)));
+        assert!(!sink.rotated(3).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hides_tokens() {
+        let client = client_identity(Some("secret"), None);
+        assert!(client.starts_with("token:"));
+        assert!(!client.contains("secret"));
+        assert_eq!(
+            "ip:127.0.0.1",
+            client_identity(None, "127.0.0.1".parse().ok())
+        );
+    }
+}
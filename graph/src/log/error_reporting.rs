@@ -0,0 +1,297 @@
+//! Reporting of errors that need the attention of an operator to Sentry or
+//! to a generic webhook: panics, deployments that failed, and poisoned
+//! locks in the store. Reports are sent from a background task; until
+//! `init` has been called, they are dropped.
+
+use chrono::prelude::{SecondsFormat, Utc};
+use failure::{format_err, Error};
+use futures03::channel::mpsc;
+use futures03::stream::StreamExt;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::{json, Value};
+use slog::{error, info, Logger};
+use std::env;
+use std::panic;
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use url::Url;
+
+use crate::prelude::{EthereumBlockPointer, SubgraphDeploymentId};
+
+/// Reports beyond this many that wait to be sent are dropped
+const QUEUE_SIZE: usize = 1000;
+
+lazy_static! {
+    static ref REPORTER: RwLock<Option<Reporter>> = RwLock::new(None);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Panic,
+    DeploymentFailed,
+    StorePoisoned,
+}
+
+/// What is reported about one error
+#[derive(Clone, Debug, Serialize)]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub deployment: Option<String>,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<String>,
+    pub handler: Option<String>,
+    /// Where in the code a panic happened
+    pub location: Option<String>,
+    pub thread: Option<String>,
+}
+
+impl ErrorReport {
+    fn new(kind: ErrorKind, message: String) -> Self {
+        ErrorReport {
+            kind,
+            message,
+            deployment: None,
+            block_number: None,
+            block_hash: None,
+            handler: None,
+            location: None,
+            thread: thread::current().name().map(|name| name.to_owned()),
+        }
+    }
+
+    /// A deployment failed and stopped indexing
+    pub fn deployment_failed(deployment: &SubgraphDeploymentId, message: String) -> Self {
+        ErrorReport {
+            deployment: Some(deployment.to_string()),
+            ..ErrorReport::new(ErrorKind::DeploymentFailed, message)
+        }
+    }
+
+    /// A lock in the store was poisoned by a thread that panicked while
+    /// holding it
+    pub fn store_poisoned(what: &str) -> Self {
+        ErrorReport::new(
+            ErrorKind::StorePoisoned,
+            format!("the lock for the {} is poisoned", what),
+        )
+    }
+
+    pub fn with_block(self, block: Option<EthereumBlockPointer>) -> Self {
+        ErrorReport {
+            block_number: block.map(|block| block.number),
+            block_hash: block.map(|block| format!("{:x}", block.hash)),
+            ..self
+        }
+    }
+
+    pub fn with_handler(self, handler: Option<String>) -> Self {
+        ErrorReport { handler, ..self }
+    }
+
+    fn level(&self) -> &'static str {
+        match self.kind {
+            ErrorKind::Panic | ErrorKind::StorePoisoned => "fatal",
+            ErrorKind::DeploymentFailed => "error",
+        }
+    }
+}
+
+/// Where reports are sent
+#[derive(Clone, Debug, PartialEq)]
+enum Destination {
+    /// The store endpoint of a Sentry project
+    Sentry { url: String, auth: String },
+    /// Any URL that accepts a JSON `ErrorReport` with an additional `node`
+    /// field in a POST request
+    Webhook(String),
+}
+
+impl Destination {
+    /// Parse a Sentry DSN like `https://<key>@sentry.example.com/<project>`
+    fn sentry(dsn: &str) -> Result<Self, Error> {
+        let dsn = Url::parse(dsn)?;
+        let key = dsn.username();
+        if key.is_empty() {
+            return Err(format_err!("the DSN does not contain a key"));
+        }
+        let path = dsn.path().trim_end_matches('/');
+        let (prefix, project) = match path.rfind('/') {
+            Some(pos) if pos + 1 < path.len() => (&path[..pos], &path[pos + 1..]),
+            _ => return Err(format_err!("the DSN does not contain a project")),
+        };
+        let host = match (dsn.host_str(), dsn.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => return Err(format_err!("the DSN does not contain a host")),
+        };
+        Ok(Destination::Sentry {
+            url: format!(
+                "{}://{}{}/api/{}/store/",
+                dsn.scheme(),
+                host,
+                prefix,
+                project
+            ),
+            auth: format!(
+                "Sentry sentry_version=7, sentry_client=graph-node/{}, sentry_key={}",
+                env!("CARGO_PKG_VERSION"),
+                key
+            ),
+        })
+    }
+
+    fn url(&self) -> &str {
+        match self {
+            Destination::Sentry { url, .. } => url,
+            Destination::Webhook(url) => url,
+        }
+    }
+}
+
+/// A Sentry event for `report`
+fn sentry_event(report: &ErrorReport, node_id: &str) -> Value {
+    let mut tags = json!({ "kind": report.kind, "node": node_id });
+    if let Some(deployment) = &report.deployment {
+        tags["deployment"] = Value::from(deployment.as_str());
+    }
+    if let Some(handler) = &report.handler {
+        tags["handler"] = Value::from(handler.as_str());
+    }
+    if let Some(block_number) = report.block_number {
+        tags["block_number"] = Value::from(block_number.to_string());
+    }
+    json!({
+        "event_id": uuid::Uuid::new_v4().to_simple().to_string(),
+        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": report.level(),
+        "platform": "other",
+        "logger": "graph-node",
+        "server_name": node_id,
+        "release": format!("graph-node@{}", env!("CARGO_PKG_VERSION")),
+        "message": { "formatted": report.message },
+        "tags": tags,
+        "extra": {
+            "block_hash": report.block_hash,
+            "location": report.location,
+            "thread": report.thread,
+        },
+    })
+}
+
+struct Reporter {
+    sender: Mutex<mpsc::Sender<ErrorReport>>,
+}
+
+/// Start sending reports to the Sentry project with the DSN in
+/// `GRAPH_SENTRY_DSN`, or to the URL in `GRAPH_ERROR_REPORTING_URL`, and
+/// report panics. Reports are labeled with `node_id`. Nothing is reported
+/// if neither is set. Must be called from within a Tokio runtime
+pub fn init(logger: &Logger, node_id: &str) {
+    let logger = logger.new(slog::o!("component" => "ErrorReporting"));
+    let destination = match (
+        env::var("GRAPH_SENTRY_DSN"),
+        env::var("GRAPH_ERROR_REPORTING_URL"),
+    ) {
+        (Ok(dsn), _) => Destination::sentry(&dsn).expect("invalid value for GRAPH_SENTRY_DSN"),
+        (Err(_), Ok(url)) => Destination::Webhook(url),
+        (Err(_), Err(_)) => return,
+    };
+    info!(logger, "Reporting errors"; "url" => destination.url());
+
+    let (sender, mut receiver) = mpsc::channel::<ErrorReport>(QUEUE_SIZE);
+    let node_id = node_id.to_owned();
+    let client = reqwest::Client::new();
+    crate::task_spawn::spawn(async move {
+        while let Some(report) = receiver.next().await {
+            let request = match &destination {
+                Destination::Sentry { url, auth } => client
+                    .post(url)
+                    .header("X-Sentry-Auth", auth.as_str())
+                    .body(sentry_event(&report, &node_id).to_string()),
+                Destination::Webhook(url) => {
+                    let mut body = serde_json::to_value(&report).unwrap();
+                    body["node"] = Value::from(node_id.as_str());
+                    client.post(url).body(body.to_string())
+                }
+            };
+            let result = request
+                .header("Content-Type", "application/json")
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                error!(logger, "Failed to report error: {}", e;
+                       "kind" => format!("{:?}", report.kind),
+                       "message" => &report.message);
+            }
+        }
+    });
+    *REPORTER.write().unwrap() = Some(Reporter {
+        sender: Mutex::new(sender),
+    });
+
+    // Panics are still handled as before once they have been reported
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => "panic with a non-string payload".to_owned(),
+            },
+        };
+        report(ErrorReport {
+            location: info.location().map(|location| location.to_string()),
+            ..ErrorReport::new(ErrorKind::Panic, message)
+        });
+        default_hook(info)
+    }));
+}
+
+/// Send `report` if error reporting is configured. Never blocks; if too
+/// many reports are waiting to be sent, `report` is dropped
+pub fn report(report: ErrorReport) {
+    // A panic while the lock is held must not cause another panic
+    if let Ok(reporter) = REPORTER.try_read() {
+        if let Some(reporter) = reporter.as_ref() {
+            if let Ok(mut sender) = reporter.sender.try_lock() {
+                let _ = sender.try_send(report);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sentry_dsn() {
+        match Destination::sentry("https://abc123@sentry.example.com:9000/prefix/42").unwrap() {
+            Destination::Sentry { url, auth } => {
+                assert_eq!("https://sentry.example.com:9000/prefix/api/42/store/", url);
+                assert!(auth.ends_with("sentry_key=abc123"));
+            }
+            destination => panic!("unexpected destination {:?}", destination),
+        }
+        assert!(Destination::sentry("https://sentry.example.com/42").is_err());
+        assert!(Destination::sentry("https://abc123@sentry.example.com/").is_err());
+    }
+
+    #[test]
+    fn labels_sentry_events() {
+        let deployment = SubgraphDeploymentId::new("QmTest").unwrap();
+        let report = ErrorReport::deployment_failed(&deployment, "boom".to_owned())
+            .with_handler(Some("handleTransfer".to_owned()));
+        let event = sentry_event(&report, "index_node_0");
+        assert_eq!("error", event["level"]);
+        assert_eq!("boom", event["message"]["formatted"]);
+        assert_eq!("QmTest", event["tags"]["deployment"]);
+        assert_eq!("index_node_0", event["tags"]["node"]);
+        assert_eq!("handleTransfer", event["tags"]["handler"]);
+        assert_eq!("deployment_failed", event["tags"]["kind"]);
+    }
+}
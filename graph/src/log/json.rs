@@ -0,0 +1,242 @@
+use chrono::prelude::{SecondsFormat, Utc};
+use serde_json::{Map, Number, Value};
+use slog::*;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// Keys that subsystems use for the same thing under different names, and
+/// the name under which they appear in JSON logs
+const FIELD_NAMES: &[(&str, &str)] = &[
+    ("subgraph_id", "deployment"),
+    ("subgraph_deployment", "deployment"),
+    ("block_number", "block"),
+    ("time_ms", "duration_ms"),
+    ("query_time_ms", "duration_ms"),
+    ("total_ms", "duration_ms"),
+];
+
+fn field_name(key: &str) -> &str {
+    FIELD_NAMES
+        .iter()
+        .find(|(alias, _)| *alias == key)
+        .map_or(key, |(_, name)| name)
+}
+
+/// A drain that writes each record as one JSON object per line. Besides
+/// `timestamp`, `level` and `msg`, an object has a key for each key-value
+/// pair of the record and its logger; the keys that identify what a
+/// message is about, i.e., `component`, `deployment`, `block`, `query_id`
+/// and `duration_ms`, are named the same across all subsystems. Nested
+/// components are joined with ` > `, as in the text format
+pub struct JsonFormat<W: Write> {
+    out: Mutex<W>,
+}
+
+impl<W: Write> JsonFormat<W> {
+    pub fn new(out: W) -> Self {
+        JsonFormat {
+            out: Mutex::new(out),
+        }
+    }
+}
+
+impl<W: Write> Drain for JsonFormat<W> {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        let mut serializer = JsonSerializer::default();
+        // The record comes first so that its values take precedence over
+        // the ones of its logger
+        record.kv().serialize(record, &mut serializer)?;
+        values.serialize(record, &mut serializer)?;
+        let JsonSerializer {
+            mut fields,
+            mut components,
+        } = serializer;
+
+        let mut object = Map::new();
+        object.insert(
+            "timestamp".to_owned(),
+            Value::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+        );
+        object.insert("level".to_owned(), Value::from(level_name(record.level())));
+        object.insert("msg".to_owned(), Value::from(record.msg().to_string()));
+        if !components.is_empty() {
+            // Loggers list their components from the innermost outwards
+            components.reverse();
+            object.insert("component".to_owned(), Value::from(components.join(" > ")));
+        }
+        object.append(&mut fields);
+
+        let mut out = self.out.lock().unwrap();
+        serde_json::to_writer(&mut *out, &object)?;
+        out.write_all(b"\n")?;
+        out.flush()
+    }
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Critical => "critical",
+        Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+#[derive(Default)]
+struct JsonSerializer {
+    fields: Map<String, Value>,
+    components: Vec<String>,
+}
+
+impl JsonSerializer {
+    fn emit(&mut self, key: Key, value: Value) -> slog::Result {
+        if key == "component" {
+            self.components.push(match value {
+                Value::String(s) => s,
+                value => value.to_string(),
+            });
+        } else {
+            self.fields
+                .entry(field_name(key).to_owned())
+                .or_insert(value);
+        }
+        Ok(())
+    }
+}
+
+impl ser::Serializer for JsonSerializer {
+    fn emit_none(&mut self, key: Key) -> slog::Result {
+        self.emit(key, Value::Null)
+    }
+
+    fn emit_unit(&mut self, key: Key) -> slog::Result {
+        self.emit(key, Value::Null)
+    }
+
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_char(&mut self, key: Key, val: char) -> slog::Result {
+        self.emit(key, Value::from(val.to_string()))
+    }
+
+    fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_u8(&mut self, key: Key, val: u8) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_i8(&mut self, key: Key, val: i8) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_u16(&mut self, key: Key, val: u16) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_i16(&mut self, key: Key, val: i16) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_f32(&mut self, key: Key, val: f32) -> slog::Result {
+        self.emit_f64(key, val as f64)
+    }
+
+    fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_u128(&mut self, key: Key, val: u128) -> slog::Result {
+        // Durations in milliseconds are `u128`
+        match u64::try_from(val) {
+            Ok(val) => self.emit(key, Value::from(val)),
+            Err(_) => self.emit(key, Value::from(val.to_string())),
+        }
+    }
+
+    fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
+        // NaN and infinity can not be represented in JSON
+        self.emit(
+            key,
+            Number::from_f64(val).map_or_else(|| Value::from(val.to_string()), Value::Number),
+        )
+    }
+
+    fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
+        self.emit(key, Value::from(val))
+    }
+
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.emit(key, Value::from(val.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn uses_stable_field_names() {
+        let buffer = Buffer::default();
+        let logger = Logger::root(JsonFormat::new(buffer.clone()).fuse(), o!())
+            .new(o!("component" => "GraphQlRunner"))
+            .new(o!("component" => "Query", "subgraph_id" => "Qm1"));
+        info!(logger, "Query timing"; "query_time_ms" => 12u64, "block" => 7);
+        warn!(logger, "Second line");
+
+        let out = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!("info", lines[0]["level"]);
+        assert_eq!("Query timing", lines[0]["msg"]);
+        assert_eq!("GraphQlRunner > Query", lines[0]["component"]);
+        assert_eq!("Qm1", lines[0]["deployment"]);
+        assert_eq!(12, lines[0]["duration_ms"]);
+        assert_eq!(7, lines[0]["block"]);
+        assert!(lines[0].get("subgraph_id").is_none());
+        assert_eq!("warning", lines[1]["level"]);
+    }
+}
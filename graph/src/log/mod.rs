@@ -23,17 +23,59 @@ use slog::*;
 use slog_async;
 use slog_envlogger;
 use slog_term::*;
+use std::str::FromStr;
 use std::{env, fmt, io, result};
 
+pub mod audit;
 pub mod codes;
 pub mod elastic;
+pub mod error_reporting;
 pub mod factory;
+pub mod json;
 pub mod split;
 
+/// How the node writes its logs to stdout
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines, colored if stdout is a terminal
+    Text,
+    /// One JSON object per line, see `json::JsonFormat`
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format `{}`, use `text` or `json`", s)),
+        }
+    }
+}
+
 pub fn logger(show_debug: bool) -> Logger {
-    let use_color = isatty::stdout_isatty();
-    let decorator = slog_term::TermDecorator::new().build();
-    let drain = CustomFormat::new(decorator, use_color).fuse();
+    logger_with_format(show_debug, LogFormat::Text)
+}
+
+pub fn logger_with_format(show_debug: bool, format: LogFormat) -> Logger {
+    match format {
+        LogFormat::Text => {
+            let use_color = isatty::stdout_isatty();
+            let decorator = slog_term::TermDecorator::new().build();
+            root_logger(CustomFormat::new(decorator, use_color).fuse(), show_debug)
+        }
+        LogFormat::Json => root_logger(json::JsonFormat::new(io::stdout()).fuse(), show_debug),
+    }
+}
+
+/// Filter what goes to `drain` by `GRAPH_LOG` and write to it from a
+/// background thread
+fn root_logger<D>(drain: D, show_debug: bool) -> Logger
+where
+    D: Drain<Ok = (), Err = Never> + Send + 'static,
+{
     let drain = slog_envlogger::LogBuilder::new(drain)
         .filter(
             None,
@@ -0,0 +1,352 @@
+//! Distributed tracing. Spans are exported with OTLP to the collector at
+//! `GRAPH_OTLP_ENDPOINT` once `init` has been called; without that, all
+//! spans are disabled and cost next to nothing.
+//!
+//! The span that code on a thread works for is tracked with `enter`; spans
+//! started with `Span::child` become its children. Async code that moves
+//! between threads passes a `TraceContext` around explicitly instead.
+//!
+//! Similarly, the id of the GraphQL query that code on a thread works for
+//! is tracked with `enter_query`, so that the store can mark the SQL it
+//! generates for the query with it, together with the hash of the shape of
+//! the query.
+
+mod otlp;
+
+use rand::Rng;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+pub use self::otlp::init;
+
+/// The name of the W3C Trace Context header
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static CURRENT: Cell<Option<TraceContext>> = Cell::new(None);
+    static QUERY_ID: RefCell<Option<String>> = RefCell::new(None);
+    static QUERY_HASH: Cell<Option<u64>> = Cell::new(None);
+}
+
+/// Identifies a span and the trace it belongs to, as propagated in the
+/// `traceparent` header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parse a `traceparent` header, e.g.
+    /// `00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01`
+    pub fn from_traceparent(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+        // Later versions may add fields, but must keep these
+        if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+
+        let mut context = TraceContext {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            sampled: u8::from_str_radix(flags, 16).ok()? & 1 == 1,
+        };
+        hex::decode_to_slice(trace_id, &mut context.trace_id).ok()?;
+        hex::decode_to_slice(span_id, &mut context.span_id).ok()?;
+        if context.trace_id == [0; 16] || context.span_id == [0; 8] {
+            return None;
+        }
+        Some(context)
+    }
+
+    /// The `traceparent` header for requests made on behalf of this span
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex::encode(self.trace_id),
+            hex::encode(self.span_id),
+            self.sampled as u8
+        )
+    }
+
+    fn child(&self) -> Self {
+        TraceContext {
+            trace_id: self.trace_id,
+            span_id: rand::thread_rng().gen(),
+            sampled: self.sampled,
+        }
+    }
+}
+
+/// The span that code on this thread currently works for
+pub fn current() -> Option<TraceContext> {
+    CURRENT.with(|current| current.get())
+}
+
+/// Make `context` the current span of this thread until the returned
+/// guard is dropped
+pub fn enter(context: Option<TraceContext>) -> Entered {
+    Entered {
+        previous: CURRENT.with(|current| current.replace(context)),
+    }
+}
+
+/// Restores the previous current span when dropped
+pub struct Entered {
+    previous: Option<TraceContext>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// The id of the GraphQL query that code on this thread currently works for
+pub fn query_id() -> Option<String> {
+    QUERY_ID.with(|query_id| query_id.borrow().clone())
+}
+
+/// The hash of the shape of the GraphQL query that code on this thread
+/// currently works for
+pub fn query_hash() -> Option<u64> {
+    QUERY_HASH.with(|query_hash| query_hash.get())
+}
+
+/// Make `query_id` the id, and `query_hash` the hash of the shape, of the
+/// current query of this thread until the returned guard is dropped
+pub fn enter_query(query_id: &str, query_hash: u64) -> QueryEntered {
+    QueryEntered {
+        previous: QUERY_ID.with(|current| current.replace(Some(query_id.to_owned()))),
+        previous_hash: QUERY_HASH.with(|current| current.replace(Some(query_hash))),
+    }
+}
+
+/// Restores the previous current query when dropped
+pub struct QueryEntered {
+    previous: Option<String>,
+    previous_hash: Option<u64>,
+}
+
+impl Drop for QueryEntered {
+    fn drop(&mut self) {
+        QUERY_ID.with(|current| *current.borrow_mut() = self.previous.take());
+        QUERY_HASH.with(|current| current.set(self.previous_hash));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpanKind {
+    Internal,
+    /// Handling a request from a client
+    Server,
+    /// A request to another service, e.g., the database
+    Client,
+}
+
+/// A finished span, waiting to be exported
+#[derive(Debug)]
+struct SpanData {
+    name: &'static str,
+    kind: SpanKind,
+    context: TraceContext,
+    parent_span_id: Option<[u8; 8]>,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+    error: Option<String>,
+}
+
+/// A timed operation. The span ends and is exported when it is dropped.
+/// Spans that are not sampled, or that are started while tracing is off,
+/// record nothing
+pub struct Span {
+    data: Option<Box<SpanData>>,
+}
+
+impl Span {
+    /// A span that records nothing
+    pub fn disabled() -> Self {
+        Span { data: None }
+    }
+
+    /// Start a span for a request that comes in from elsewhere. It
+    /// continues the trace of `remote`, e.g., from a `traceparent` header,
+    /// and honors its sampling decision; without it, a new trace is
+    /// started
+    pub fn root(name: &'static str, kind: SpanKind, remote: Option<TraceContext>) -> Self {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return Span::disabled();
+        }
+        match remote {
+            Some(remote) => Span::start(name, kind, remote.child(), Some(remote.span_id)),
+            None => {
+                let mut rng = rand::thread_rng();
+                let context = TraceContext {
+                    trace_id: rng.gen(),
+                    span_id: rng.gen(),
+                    sampled: rng.gen::<f64>() < otlp::sample_ratio(),
+                };
+                Span::start(name, kind, context, None)
+            }
+        }
+    }
+
+    /// Start a child of the current span of this thread, if there is one
+    pub fn child(name: &'static str) -> Self {
+        Span::child_of(current().as_ref(), name)
+    }
+
+    /// Start a child of `parent`, if there is one
+    pub fn child_of(parent: Option<&TraceContext>, name: &'static str) -> Self {
+        Span::child_of_kind(parent, name, SpanKind::Internal)
+    }
+
+    pub fn child_of_kind(
+        parent: Option<&TraceContext>,
+        name: &'static str,
+        kind: SpanKind,
+    ) -> Self {
+        match parent {
+            Some(parent) if ENABLED.load(Ordering::Relaxed) => {
+                Span::start(name, kind, parent.child(), Some(parent.span_id))
+            }
+            _ => Span::disabled(),
+        }
+    }
+
+    fn start(
+        name: &'static str,
+        kind: SpanKind,
+        context: TraceContext,
+        parent_span_id: Option<[u8; 8]>,
+    ) -> Self {
+        // Unsampled spans are still propagated, so that the services we
+        // call make the same decision, but are never exported
+        let now = SystemTime::now();
+        Span {
+            data: Some(Box::new(SpanData {
+                name,
+                kind,
+                context,
+                parent_span_id,
+                start: now,
+                end: now,
+                attributes: vec![],
+                error: None,
+            })),
+        }
+    }
+
+    /// The context to pass to children of this span, or to other services
+    pub fn context(&self) -> Option<TraceContext> {
+        self.data.as_ref().map(|data| data.context)
+    }
+
+    /// Whether attributes of this span are exported. Check this before
+    /// computing attributes that are expensive to produce
+    pub fn is_recording(&self) -> bool {
+        self.data
+            .as_ref()
+            .map_or(false, |data| data.context.sampled)
+    }
+
+    pub fn set_attribute(&mut self, key: &'static str, value: impl fmt::Display) {
+        if let Some(data) = self.data.as_mut().filter(|data| data.context.sampled) {
+            data.attributes.push((key, value.to_string()));
+        }
+    }
+
+    /// Mark the operation as failed
+    pub fn set_error(&mut self, error: impl fmt::Display) {
+        if let Some(data) = self.data.as_mut().filter(|data| data.context.sampled) {
+            data.error = Some(error.to_string());
+        }
+    }
+
+    /// Make this span the current span of this thread until the returned
+    /// guard is dropped
+    pub fn enter(&self) -> Entered {
+        enter(self.context())
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(mut data) = self.data.take() {
+            if data.context.sampled {
+                data.end = SystemTime::now();
+                otlp::export(data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_traceparent() {
+        let header = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let context = TraceContext::from_traceparent(header).unwrap();
+        assert!(context.sampled);
+        assert_eq!(0xb7, context.span_id[0]);
+        assert_eq!(header, context.traceparent());
+
+        let unsampled = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00";
+        assert!(!TraceContext::from_traceparent(unsampled).unwrap().sampled);
+
+        assert_eq!(
+            None,
+            TraceContext::from_traceparent("00-xyz-b7ad6b7169203331-01")
+        );
+        assert_eq!(
+            None,
+            TraceContext::from_traceparent(
+                "00-00000000000000000000000000000000-b7ad6b7169203331-01"
+            )
+        );
+        // Future versions may append fields
+        assert!(TraceContext::from_traceparent(
+            "01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra"
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn entering_restores_the_previous_span() {
+        let outer = TraceContext::from_traceparent(
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        );
+        let _outer = enter(outer);
+        {
+            let inner = outer.map(|context| context.child());
+            let _inner = enter(inner);
+            assert_eq!(inner, current());
+        }
+        assert_eq!(outer, current());
+    }
+
+    #[test]
+    fn entering_a_query_restores_the_previous_one() {
+        assert_eq!(None, query_id());
+        let _outer = enter_query("outer", 1);
+        {
+            let _inner = enter_query("inner", 2);
+            assert_eq!(Some("inner".to_owned()), query_id());
+            assert_eq!(Some(2), query_hash());
+        }
+        assert_eq!(Some("outer".to_owned()), query_id());
+        assert_eq!(Some(1), query_hash());
+    }
+}
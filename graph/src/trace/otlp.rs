@@ -0,0 +1,139 @@
+use futures03::TryFutureExt;
+use lazy_static::lazy_static;
+use reqwest::Client;
+use serde_json::{json, Value};
+use slog::{debug, error, info, Logger};
+use std::env;
+use std::mem;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use super::{SpanData, SpanKind, ENABLED};
+use crate::util::env::env_var;
+
+/// Spans that are finished while this many are waiting to be exported are
+/// dropped, so that an unreachable collector can not use up memory
+const MAX_QUEUED_SPANS: usize = 10_000;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref SAMPLE_RATIO: f64 = env_var("GRAPH_OTLP_SAMPLE_RATIO").unwrap_or(1.0);
+    static ref QUEUE: Mutex<Vec<Box<SpanData>>> = Mutex::new(Vec::new());
+}
+
+pub(super) fn sample_ratio() -> f64 {
+    *SAMPLE_RATIO
+}
+
+pub(super) fn export(span: Box<SpanData>) {
+    let mut queue = QUEUE.lock().unwrap();
+    if queue.len() < MAX_QUEUED_SPANS {
+        queue.push(span);
+    }
+}
+
+/// Start exporting spans to the OTLP/HTTP collector configured with
+/// `GRAPH_OTLP_ENDPOINT`, or the standard `OTEL_EXPORTER_OTLP_ENDPOINT`.
+/// Tracing stays off if neither is set. Must be called from within a Tokio
+/// runtime
+pub fn init(logger: &Logger) {
+    let endpoint = match env::var("GRAPH_OTLP_ENDPOINT")
+        .or_else(|_| env::var("OTEL_EXPORTER_OTLP_ENDPOINT"))
+    {
+        Ok(endpoint) => endpoint,
+        Err(_) => return,
+    };
+    let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+    info!(logger, "Exporting traces"; "url" => &url, "sample_ratio" => sample_ratio());
+
+    ENABLED.store(true, Ordering::SeqCst);
+    periodically_flush_spans(logger.clone(), url);
+}
+
+fn periodically_flush_spans(logger: Logger, url: String) {
+    use futures03::stream::StreamExt;
+
+    let client = Client::new();
+    crate::task_spawn::spawn(tokio::time::interval(FLUSH_INTERVAL).for_each(move |_| {
+        let spans = mem::replace(&mut *QUEUE.lock().unwrap(), Vec::new());
+        let request = if spans.is_empty() {
+            None
+        } else {
+            debug!(logger, "Exporting {} spans", spans.len());
+            Some(
+                client
+                    .post(&url)
+                    .header("Content-Type", "application/json")
+                    .body(request_body(&spans).to_string())
+                    .send(),
+            )
+        };
+        let logger = logger.clone();
+        async move {
+            if let Some(request) = request {
+                request
+                    .and_then(|response| async { response.error_for_status() })
+                    .map_ok(|_| ())
+                    .unwrap_or_else(move |e| error!(logger, "Failed to export spans: {}", e))
+                    .await
+            }
+        }
+    }));
+}
+
+/// An `ExportTraceServiceRequest` in the JSON encoding of OTLP
+fn request_body(spans: &[Box<SpanData>]) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", "graph-node")],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "graph-node", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans.iter().map(|span| span_json(span)).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+fn span_json(span: &SpanData) -> Value {
+    let mut value = json!({
+        "traceId": hex::encode(span.context.trace_id),
+        "spanId": hex::encode(span.context.span_id),
+        "name": span.name,
+        "kind": match span.kind {
+            SpanKind::Internal => 1,
+            SpanKind::Server => 2,
+            SpanKind::Client => 3,
+        },
+        "startTimeUnixNano": unix_nanos(span.start),
+        "endTimeUnixNano": unix_nanos(span.end),
+        "attributes": span
+            .attributes
+            .iter()
+            .map(|(key, value)| attribute(key, value))
+            .collect::<Vec<_>>(),
+        "status": match &span.error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({}),
+        },
+    });
+    if let Some(parent_span_id) = span.parent_span_id {
+        value["parentSpanId"] = Value::from(hex::encode(parent_span_id));
+    }
+    value
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// OTLP encodes 64 bit integers as strings in JSON
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
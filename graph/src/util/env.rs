@@ -0,0 +1,12 @@
+use std::env;
+use std::str::FromStr;
+
+/// The value of the environment variable `name`, or `None` if it is not
+/// set. Panics if the variable is set but can not be parsed, since the
+/// node should not start with a setting that it does not understand
+pub fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().map(|s| {
+        s.parse()
+            .unwrap_or_else(|_| panic!("failed to parse env var {}", name))
+    })
+}
@@ -0,0 +1,335 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::{delay_until, Delay};
+
+use crate::util::env::env_var;
+
+/// Only this much of the headers of a request is kept to look for the
+/// length of its body
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// What clients of an HTTP server may send, and how slowly. Without these,
+/// a client can send arbitrarily large requests or hold on to connections
+/// by trickling bytes
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpLimits {
+    /// The largest request body, in bytes
+    pub max_body_size: usize,
+    /// How long a client may take to send the headers of a request,
+    /// counted from its first byte
+    pub header_timeout: Duration,
+    /// How long a client may take to send the body of a request
+    pub read_timeout: Duration,
+    /// How long a connection may stay open without a request
+    pub idle_timeout: Duration,
+}
+
+impl Default for HttpLimits {
+    fn default() -> Self {
+        HttpLimits {
+            max_body_size: 1024 * 1024,
+            header_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl HttpLimits {
+    /// Read the limits from `GRAPH_HTTP_MAX_BODY_SIZE` in bytes and from
+    /// `GRAPH_HTTP_HEADER_TIMEOUT`, `GRAPH_HTTP_READ_TIMEOUT` and
+    /// `GRAPH_HTTP_IDLE_TIMEOUT` in seconds
+    pub fn from_env() -> Self {
+        let default = HttpLimits::default();
+        let secs = |name| env_var(name).map(Duration::from_secs);
+        HttpLimits {
+            max_body_size: env_var("GRAPH_HTTP_MAX_BODY_SIZE").unwrap_or(default.max_body_size),
+            header_timeout: secs("GRAPH_HTTP_HEADER_TIMEOUT").unwrap_or(default.header_timeout),
+            read_timeout: secs("GRAPH_HTTP_READ_TIMEOUT").unwrap_or(default.read_timeout),
+            idle_timeout: secs("GRAPH_HTTP_IDLE_TIMEOUT").unwrap_or(default.idle_timeout),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Phase {
+    /// Waiting for the next request
+    Idle,
+    /// Receiving the headers of a request
+    Headers { head: Vec<u8>, line_len: usize },
+    /// Receiving the body of a request; `None` if its length is not known
+    Body { remaining: Option<u64> },
+    /// The whole request has been received and the server is working on
+    /// the response
+    Responding,
+}
+
+/// Follows the requests and responses on a connection, as far as needed
+/// to know which timeout applies
+#[derive(Debug)]
+struct Framer {
+    phase: Phase,
+    since: Instant,
+}
+
+impl Framer {
+    fn new(now: Instant) -> Self {
+        Framer {
+            phase: Phase::Idle,
+            since: now,
+        }
+    }
+
+    /// Account for `data` received from the client
+    fn received(&mut self, mut data: &[u8], now: Instant) {
+        while !data.is_empty() {
+            match &mut self.phase {
+                Phase::Idle | Phase::Responding => {
+                    // Clients may send empty lines between requests
+                    let start = data
+                        .iter()
+                        .position(|b| *b != b'\r' && *b != b'\n')
+                        .unwrap_or(data.len());
+                    data = &data[start..];
+                    if !data.is_empty() {
+                        self.phase = Phase::Headers {
+                            head: Vec::new(),
+                            line_len: 0,
+                        };
+                        self.since = now;
+                    }
+                }
+                Phase::Headers { head, line_len } => {
+                    let mut end = None;
+                    for (i, b) in data.iter().enumerate() {
+                        if head.len() < MAX_HEAD_SIZE {
+                            head.push(*b);
+                        }
+                        match b {
+                            b'\n' if *line_len == 0 => {
+                                end = Some(i + 1);
+                                break;
+                            }
+                            b'\n' => *line_len = 0,
+                            b'\r' => (),
+                            _ => *line_len += 1,
+                        }
+                    }
+                    match end {
+                        Some(end) => {
+                            self.phase = match body_length(head) {
+                                Some(0) => Phase::Responding,
+                                remaining => Phase::Body { remaining },
+                            };
+                            self.since = now;
+                            data = &data[end..];
+                        }
+                        None => data = &[],
+                    }
+                }
+                Phase::Body {
+                    remaining: Some(remaining),
+                } => {
+                    let len = (*remaining).min(data.len() as u64);
+                    *remaining -= len;
+                    if *remaining == 0 {
+                        self.phase = Phase::Responding;
+                    }
+                    data = &data[len as usize..];
+                }
+                Phase::Body { remaining: None } => data = &[],
+            }
+        }
+    }
+
+    /// Account for the server sending something to the client
+    fn sent(&mut self, now: Instant) {
+        match self.phase {
+            // A body of unknown length ends at the latest once the server
+            // starts to respond
+            Phase::Responding | Phase::Body { remaining: None } => {
+                self.phase = Phase::Idle;
+                self.since = now;
+            }
+            // E.g., a `100 Continue`
+            Phase::Idle | Phase::Body { .. } => self.since = now,
+            Phase::Headers { .. } => (),
+        }
+    }
+
+    fn deadline(&self, limits: &HttpLimits) -> Option<Instant> {
+        match self.phase {
+            Phase::Idle => Some(self.since + limits.idle_timeout),
+            Phase::Headers { .. } => Some(self.since + limits.header_timeout),
+            Phase::Body { .. } => Some(self.since + limits.read_timeout),
+            Phase::Responding => None,
+        }
+    }
+}
+
+/// The length of the body of the request with headers `head`, or `None`
+/// if it is sent in chunks
+fn body_length(head: &[u8]) -> Option<u64> {
+    let head = String::from_utf8_lossy(head);
+    let mut length = Some(0);
+    for line in head.lines().skip(1) {
+        let mut parts = line.splitn(2, ':');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name.trim(), value.trim()),
+            _ => continue,
+        };
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            return None;
+        }
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value.parse().ok();
+        }
+    }
+    length
+}
+
+/// A connection of an HTTP server that is closed if the client takes
+/// longer than the `HttpLimits` allow to send a request, or stays idle for
+/// too long. The server is never limited in how long it takes to respond
+pub struct LimitedStream<S> {
+    inner: S,
+    limits: HttpLimits,
+    framer: Framer,
+    timer: Option<(Instant, Delay)>,
+}
+
+impl<S> LimitedStream<S> {
+    pub fn new(inner: S, limits: HttpLimits) -> Self {
+        LimitedStream {
+            inner,
+            limits,
+            framer: Framer::new(Instant::now()),
+            timer: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Fail once the deadline for what the client is currently doing
+    /// has passed
+    fn poll_deadline(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let deadline = match self.framer.deadline(&self.limits) {
+            Some(deadline) => deadline,
+            None => {
+                self.timer = None;
+                return Poll::Pending;
+            }
+        };
+        if self.timer.as_ref().map(|(at, _)| *at) != Some(deadline) {
+            let timer = delay_until(tokio::time::Instant::from_std(deadline));
+            self.timer = Some((deadline, timer));
+        }
+        let (_, timer) = self.timer.as_mut().unwrap();
+        match Pin::new(timer).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the client was too slow",
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for LimitedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(n)) => {
+                this.framer.received(&buf[..n], Instant::now());
+                Poll::Ready(Ok(n))
+            }
+            Poll::Pending => this.poll_deadline(cx),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for LimitedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            if n > 0 {
+                this.framer.sent(Instant::now());
+            }
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_requests_with_and_without_body() {
+        let limits = HttpLimits::default();
+        let start = Instant::now();
+        let mut framer = Framer::new(start);
+        assert_eq!(Some(start + limits.idle_timeout), framer.deadline(&limits));
+
+        let later = start + Duration::from_secs(1);
+        framer.received(b"POST / HTTP/1.1\r\nContent-Len", later);
+        assert_eq!(
+            Some(later + limits.header_timeout),
+            framer.deadline(&limits)
+        );
+        framer.received(b"gth: 4\r\n\r\n{}", later);
+        assert_eq!(Phase::Body { remaining: Some(2) }, framer.phase);
+        framer.received(b"{}GET / HTTP/1.1\r\n\r\n", later);
+        assert_eq!(Phase::Responding, framer.phase);
+        assert_eq!(None, framer.deadline(&limits));
+
+        framer.sent(later);
+        assert_eq!(Phase::Idle, framer.phase);
+
+        framer.received(
+            b"\r\nPOST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2",
+            later,
+        );
+        assert_eq!(Phase::Body { remaining: None }, framer.phase);
+        assert_eq!(Some(later + limits.read_timeout), framer.deadline(&limits));
+        framer.sent(later);
+        assert_eq!(Phase::Idle, framer.phase);
+    }
+
+    #[test]
+    fn finds_body_length() {
+        assert_eq!(Some(0), body_length(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert_eq!(
+            Some(12),
+            body_length(b"POST / HTTP/1.1\r\ncontent-length: 12\r\n\r\n")
+        );
+        assert_eq!(
+            None,
+            body_length(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n")
+        );
+    }
+}
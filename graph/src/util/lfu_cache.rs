@@ -157,6 +157,11 @@ impl<K: Clone + Ord + Eq + Hash + Debug, V: CacheWeight + Default> LfuCache<K, V
         self.queue.len()
     }
 
+    /// The total weight of all entries
+    pub fn weight(&self) -> u64 {
+        self.total_weight
+    }
+
     pub fn evict(&mut self, max_weight: u64) {
         if self.total_weight <= max_weight {
             return;
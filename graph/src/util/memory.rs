@@ -0,0 +1,201 @@
+//! Approximate accounting of the memory that the big consumers in the node
+//! hold, so that it is visible which of them grows. Each consumer keeps a
+//! `MemoryUsage` up to date; the totals are exported as the
+//! `memory_usage_bytes` gauge once `init` has been called.
+//!
+//! If `GRAPH_MEMORY_SOFT_LIMIT` is set and the node uses more memory than
+//! that, the node is under memory pressure, and caches check
+//! `under_pressure` to shrink themselves until it is over.
+
+use lazy_static::lazy_static;
+use slog::{info, warn, Logger};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::components::metrics::MetricsRegistry;
+use crate::util::env::env_var;
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The parts of the node whose memory is accounted for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subsystem {
+    /// The results in the GraphQL query cache
+    QueryCache,
+    /// The entity caches of the subgraphs that are being indexed
+    EntityCache,
+    /// The cache of compressed GraphQL responses
+    ResponseCache,
+    /// The memories of the WASM instances that run mappings
+    WasmInstances,
+    /// The results of GraphQL queries that are being sent to clients
+    InFlightQueries,
+}
+
+const SUBSYSTEMS: [Subsystem; 5] = [
+    Subsystem::QueryCache,
+    Subsystem::EntityCache,
+    Subsystem::ResponseCache,
+    Subsystem::WasmInstances,
+    Subsystem::InFlightQueries,
+];
+
+impl Subsystem {
+    fn label_value(&self) -> &'static str {
+        match self {
+            Subsystem::QueryCache => "query_cache",
+            Subsystem::EntityCache => "entity_cache",
+            Subsystem::ResponseCache => "response_cache",
+            Subsystem::WasmInstances => "wasm_instances",
+            Subsystem::InFlightQueries => "in_flight_queries",
+        }
+    }
+
+    fn index(&self) -> usize {
+        SUBSYSTEMS.iter().position(|s| s == self).unwrap()
+    }
+}
+
+lazy_static! {
+    static ref USAGE: Vec<AtomicU64> = SUBSYSTEMS.iter().map(|_| AtomicU64::new(0)).collect();
+
+    /// The soft limit, in bytes
+    static ref SOFT_LIMIT: Option<u64> =
+        env_var::<u64>("GRAPH_MEMORY_SOFT_LIMIT").map(|mb| mb * 1024 * 1024);
+}
+
+static UNDER_PRESSURE: AtomicBool = AtomicBool::new(false);
+
+/// The number of bytes that `subsystem` currently holds
+pub fn usage(subsystem: Subsystem) -> u64 {
+    USAGE[subsystem.index()].load(Ordering::Relaxed)
+}
+
+/// Whether the node uses more memory than the soft limit. Caches should
+/// then shrink, and not grow
+pub fn under_pressure() -> bool {
+    UNDER_PRESSURE.load(Ordering::Relaxed)
+}
+
+/// Accounts for memory that one consumer holds in a subsystem. The memory
+/// is no longer accounted for once this is dropped
+#[derive(Debug)]
+pub struct MemoryUsage {
+    subsystem: Subsystem,
+    bytes: u64,
+}
+
+impl MemoryUsage {
+    pub fn new(subsystem: Subsystem) -> Self {
+        MemoryUsage {
+            subsystem,
+            bytes: 0,
+        }
+    }
+
+    pub fn with_bytes(subsystem: Subsystem, bytes: u64) -> Self {
+        let mut usage = MemoryUsage::new(subsystem);
+        usage.set(bytes);
+        usage
+    }
+
+    /// The consumer now holds `bytes` bytes
+    pub fn set(&mut self, bytes: u64) {
+        let total = &USAGE[self.subsystem.index()];
+        if bytes > self.bytes {
+            total.fetch_add(bytes - self.bytes, Ordering::Relaxed);
+        } else {
+            total.fetch_sub(self.bytes - bytes, Ordering::Relaxed);
+        }
+        self.bytes = bytes;
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Drop for MemoryUsage {
+    fn drop(&mut self) {
+        self.set(0)
+    }
+}
+
+/// The resident set size of the process, on Linux
+fn resident_bytes() -> Option<u64> {
+    const PAGE_SIZE: u64 = 4096;
+
+    let statm = fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * PAGE_SIZE)
+}
+
+/// Start exporting the memory usage and checking it against the soft
+/// limit. Must be called from within a Tokio runtime
+pub fn init(logger: &Logger, registry: Arc<impl MetricsRegistry>) {
+    let logger = logger.new(slog::o!("component" => "MemoryAccounting"));
+    let gauges = registry
+        .new_gauge_vec(
+            String::from("memory_usage_bytes"),
+            String::from("Approximate memory held by the subsystems of the node, in bytes"),
+            HashMap::new(),
+            vec![String::from("subsystem")],
+        )
+        .expect("failed to create `memory_usage_bytes` gauge");
+    if let Some(limit) = *SOFT_LIMIT {
+        info!(logger, "Shrinking caches when memory use exceeds the soft limit";
+              "soft_limit_mb" => limit / 1024 / 1024);
+    }
+
+    crate::task_spawn::spawn(async move {
+        let mut interval = tokio::time::interval(UPDATE_INTERVAL);
+        loop {
+            interval.tick().await;
+            for subsystem in SUBSYSTEMS.iter() {
+                gauges
+                    .with_label_values(&[subsystem.label_value()])
+                    .set(usage(*subsystem) as f64);
+            }
+
+            if let Some(limit) = *SOFT_LIMIT {
+                // Fall back to what we track ourselves where we can not
+                // find out how much memory the process uses
+                let used =
+                    resident_bytes().unwrap_or_else(|| SUBSYSTEMS.iter().map(|s| usage(*s)).sum());
+                let pressure = used > limit;
+                if pressure != UNDER_PRESSURE.swap(pressure, Ordering::Relaxed) {
+                    if pressure {
+                        warn!(logger, "Memory use exceeds the soft limit, shrinking caches";
+                              "used_mb" => used / 1024 / 1024,
+                              "soft_limit_mb" => limit / 1024 / 1024);
+                    } else {
+                        info!(logger, "Memory use is below the soft limit again";
+                              "used_mb" => used / 1024 / 1024);
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_usage() {
+        let before = usage(Subsystem::InFlightQueries);
+        let mut first = MemoryUsage::with_bytes(Subsystem::InFlightQueries, 100);
+        let second = MemoryUsage::with_bytes(Subsystem::InFlightQueries, 50);
+        assert_eq!(before + 150, usage(Subsystem::InFlightQueries));
+        first.set(20);
+        assert_eq!(before + 70, usage(Subsystem::InFlightQueries));
+        drop(second);
+        assert_eq!(before + 20, usage(Subsystem::InFlightQueries));
+        drop(first);
+        assert_eq!(before, usage(Subsystem::InFlightQueries));
+    }
+}
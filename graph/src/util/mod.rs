@@ -9,4 +9,19 @@ pub mod security;
 
 pub mod lfu_cache;
 
+/// Approximate accounting of memory use.
+pub mod memory;
+
+/// Coordination of a graceful shutdown.
+pub mod shutdown;
+
+/// TLS for the servers of the node.
+pub mod tls;
+
+/// Limits on what clients of the HTTP servers may send.
+pub mod http_limits;
+
+/// Settings from environment variables.
+pub mod env;
+
 pub mod error;
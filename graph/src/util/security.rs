@@ -38,3 +38,33 @@ impl<T: fmt::Display> slog::Value for SafeDisplay<T> {
         serializer.emit_str(key, format!("{}", self).as_str())
     }
 }
+
+/// The token in the value `authorization` of an `Authorization: Bearer
+/// <token>` header
+pub fn bearer_token(authorization: &str) -> Option<&str> {
+    let mut parts = authorization.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => Some(token.trim()),
+        _ => None,
+    }
+}
+
+/// Compare `a` and `b` in a time that does not depend on how long their
+/// common prefix is, so that comparing secrets does not reveal how much
+/// of a guess was right
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bearer_tokens() {
+        assert_eq!(Some("secret"), bearer_token("Bearer secret"));
+        assert_eq!(Some("secret"), bearer_token("bearer  secret "));
+        assert_eq!(None, bearer_token("Basic c2VjcmV0"));
+        assert_eq!(None, bearer_token("Bearer"));
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// How often `Shutdown::wait_idle` checks whether all work has finished
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Inner {
+    triggered: AtomicBool,
+    /// The number of `Work` guards that are currently alive
+    busy: AtomicUsize,
+    sender: watch::Sender<bool>,
+    receiver: watch::Receiver<bool>,
+}
+
+/// Coordinates a graceful shutdown of the node. Components that do work
+/// that should not be interrupted, like running a query or processing a
+/// block, wrap each unit of work in a `Work` guard obtained from
+/// `start_work`. Once shutdown has been triggered, no new work can start,
+/// and `wait_idle` waits for the work that is already running to finish.
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+/// A unit of work that should finish before the node shuts down. The work
+/// is considered done when the guard is dropped
+pub struct Work {
+    inner: Arc<Inner>,
+}
+
+impl Drop for Work {
+    fn drop(&mut self) {
+        self.inner.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Shutdown {
+            inner: Arc::new(Inner {
+                triggered: AtomicBool::new(false),
+                busy: AtomicUsize::new(0),
+                sender,
+                receiver,
+            }),
+        }
+    }
+
+    /// Start shutting down. After this, `start_work` will refuse to start
+    /// new work and the futures returned by `triggered` resolve
+    pub fn trigger(&self) {
+        self.inner.triggered.store(true, Ordering::SeqCst);
+        // Nobody listening is fine; the flag is what `start_work` checks
+        let _ = self.inner.sender.broadcast(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.inner.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Return a future that resolves once shutdown has been triggered
+    pub fn triggered(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let mut receiver = self.inner.receiver.clone();
+        async move {
+            while let Some(triggered) = receiver.recv().await {
+                if triggered {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Register the start of a unit of work. Returns `None` if shutdown has
+    /// already been triggered, in which case the work should not be started
+    pub fn start_work(&self) -> Option<Work> {
+        self.inner.busy.fetch_add(1, Ordering::SeqCst);
+        let work = Work {
+            inner: self.inner.clone(),
+        };
+        if self.is_triggered() {
+            // Dropping `work` undoes the increment
+            return None;
+        }
+        Some(work)
+    }
+
+    /// Wait for all running work to finish, but at most for `timeout`.
+    /// Returns `true` if all work finished in time
+    pub async fn wait_idle(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.inner.busy.load(Ordering::SeqCst) > 0 {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            tokio::time::delay_for(IDLE_POLL_INTERVAL).await;
+        }
+        true
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_work_starts_after_trigger() {
+        let shutdown = Shutdown::new();
+        let work = shutdown.start_work();
+        assert!(work.is_some());
+        assert_eq!(1, shutdown.inner.busy.load(Ordering::SeqCst));
+
+        shutdown.trigger();
+        assert!(shutdown.start_work().is_none());
+        assert_eq!(1, shutdown.inner.busy.load(Ordering::SeqCst));
+
+        drop(work);
+        assert_eq!(0, shutdown.inner.busy.load(Ordering::SeqCst));
+    }
+}
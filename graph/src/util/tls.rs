@@ -0,0 +1,256 @@
+use failure::{format_err, Error};
+use futures03::stream::{Stream, StreamExt};
+use slog::{debug, info, warn, Logger};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+use crate::util::http_limits::{HttpLimits, LimitedStream};
+
+/// How many connections can have finished their TLS handshake without
+/// having been picked up by the server yet
+const HANDSHAKE_BUFFER: usize = 100;
+
+/// How long a client has to finish its TLS handshake before its connection
+/// is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait before accepting connections again after that failed,
+/// e.g., because the process ran out of file descriptors
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The certificate and key that servers use for TLS. The files are read
+/// when the configuration is created and whenever `reload` is called;
+/// connections that are already open keep using the certificate they
+/// were opened with
+pub struct TlsConfig {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<ServerConfig>>,
+}
+
+impl TlsConfig {
+    pub fn load(
+        cert_path: impl Into<PathBuf>,
+        key_path: impl Into<PathBuf>,
+    ) -> Result<Self, Error> {
+        let cert_path = cert_path.into();
+        let key_path = key_path.into();
+        let current = RwLock::new(Arc::new(read_config(&cert_path, &key_path)?));
+        Ok(TlsConfig {
+            cert_path,
+            key_path,
+            current,
+        })
+    }
+
+    /// Read the certificate and key again, e.g., after they were renewed.
+    /// If that fails, the old certificate stays in use
+    pub fn reload(&self) -> Result<(), Error> {
+        let config = read_config(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+}
+
+fn read_config(cert_path: &PathBuf, key_path: &PathBuf) -> Result<ServerConfig, Error> {
+    let open = |path: &PathBuf| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format_err!("failed to open `{}`: {}", path.display(), e))
+    };
+
+    let chain = certs(&mut open(cert_path)?)
+        .map_err(|()| format_err!("invalid certificate in `{}`", cert_path.display()))?;
+    if chain.is_empty() {
+        return Err(format_err!("no certificate in `{}`", cert_path.display()));
+    }
+
+    let invalid_key = |()| format_err!("invalid private key in `{}`", key_path.display());
+    let mut keys = pkcs8_private_keys(&mut open(key_path)?).map_err(invalid_key)?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(key_path)?).map_err(invalid_key)?;
+    }
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| format_err!("no private key in `{}`", key_path.display()))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(chain, key)
+        .map_err(|e| format_err!("invalid certificate or key: {}", e))?;
+    Ok(config)
+}
+
+/// A connection that a server accepted, with or without TLS
+pub enum MaybeTlsStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl MaybeTlsStream {
+    pub fn remote_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            MaybeTlsStream::Plain(stream) => stream.peer_addr(),
+            MaybeTlsStream::Tls(stream) => stream.get_ref().0.peer_addr(),
+        }
+    }
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Bind a listener for `incoming` to `addr`. This needs to be called from
+/// within the runtime
+pub fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+/// Accept connections on `listener`. With `tls`, the TLS handshake of each
+/// connection happens in its own task so that slow clients do not hold up
+/// others, and connections whose handshake fails or takes longer than
+/// `HANDSHAKE_TIMEOUT` are dropped
+pub fn incoming(
+    logger: Logger,
+    mut listener: TcpListener,
+    tls: Option<Arc<TlsConfig>>,
+) -> impl Stream<Item = Result<MaybeTlsStream, io::Error>> + Send + Unpin + 'static {
+    let (mut sender, receiver) = mpsc::channel(HANDSHAKE_BUFFER);
+    crate::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    // Errors like running out of file descriptors persist
+                    // for a while; retrying right away would only spin
+                    warn!(logger, "Failed to accept connection"; "error" => e.to_string());
+                    tokio::time::delay_for(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+            match &tls {
+                None => {
+                    if sender.send(MaybeTlsStream::Plain(stream)).await.is_err() {
+                        return;
+                    }
+                }
+                Some(tls) => {
+                    let acceptor = tls.acceptor();
+                    let mut sender = sender.clone();
+                    let logger = logger.clone();
+                    crate::spawn(async move {
+                        let handshake = acceptor.accept(stream);
+                        match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+                            Ok(Ok(stream)) => {
+                                let _ = sender.send(MaybeTlsStream::Tls(Box::new(stream))).await;
+                            }
+                            Ok(Err(e)) => {
+                                debug!(logger, "TLS handshake failed"; "error" => e.to_string())
+                            }
+                            Err(_) => debug!(logger, "TLS handshake timed out"),
+                        }
+                    });
+                }
+            }
+        }
+    });
+    receiver.map(Ok)
+}
+
+/// Accept connections on `listener` and pass what is sent over them on to
+/// `backend` in plain text, decrypting it with `tls` if that is given and
+/// closing connections of clients that are slower than `limits` allow.
+/// This is for servers that can do neither themselves; `backend` should
+/// only listen on the loopback interface
+pub async fn proxy(
+    logger: Logger,
+    listener: TcpListener,
+    tls: Option<Arc<TlsConfig>>,
+    limits: HttpLimits,
+    backend: SocketAddr,
+) {
+    info!(logger, "Forwarding connections"; "backend" => backend.to_string());
+    let mut connections = incoming(logger.clone(), listener, tls);
+    while let Some(Ok(client)) = connections.next().await {
+        let logger = logger.clone();
+        let client = LimitedStream::new(client, limits.clone());
+        crate::spawn(async move {
+            let server = match TcpStream::connect(backend).await {
+                Ok(server) => server,
+                Err(e) => {
+                    debug!(logger, "Failed to connect to backend"; "error" => e.to_string());
+                    return;
+                }
+            };
+            let (mut client_read, mut client_write) = tokio::io::split(client);
+            let (mut server_read, mut server_write) = tokio::io::split(server);
+            // Stop forwarding responses if the client was too slow; a
+            // client that only closes its side still gets its response
+            let upstream = async {
+                if tokio::io::copy(&mut client_read, &mut server_write)
+                    .await
+                    .is_ok()
+                {
+                    futures03::future::pending::<()>().await;
+                }
+            };
+            let downstream = tokio::io::copy(&mut server_read, &mut client_write);
+            futures03::future::select(Box::pin(upstream), Box::pin(downstream)).await;
+        });
+    }
+}
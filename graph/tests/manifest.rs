@@ -6,8 +6,8 @@ use std::time::Duration;
 
 use graph::components::link_resolver::{JsonValueStream, LinkResolver as LinkResolverTrait};
 use graph::prelude::{
-    Entity, Link, SubgraphDeploymentId, SubgraphManifest, SubgraphManifestValidationError,
-    UnvalidatedSubgraphManifest,
+    Entity, Link, SubgraphDeploymentId, SubgraphFeature, SubgraphManifest,
+    SubgraphManifestValidationError, UnvalidatedSubgraphManifest,
 };
 
 use test_store::LOGGER;
@@ -57,6 +57,7 @@ async fn resolve_manifest(text: &str) -> SubgraphManifest {
 
     resolver.add(link.link.as_str(), text);
     resolver.add("/ipfs/Qmschema", GQL_SCHEMA);
+    resolver.add("/ipfs/Qmmapping", "");
 
     SubgraphManifest::resolve(link, &resolver, &LOGGER)
         .await
@@ -69,6 +70,7 @@ async fn resolve_unvalidated(text: &str) -> UnvalidatedSubgraphManifest {
 
     resolver.add(link.link.as_str(), text);
     resolver.add("/ipfs/Qmschema", GQL_SCHEMA);
+    resolver.add("/ipfs/Qmmapping", "");
 
     UnvalidatedSubgraphManifest::resolve(link, Arc::new(resolver), &LOGGER)
         .await
@@ -112,6 +114,29 @@ specVersion: 0.0.1
     assert_eq!(12345, graft.block);
 }
 
+#[tokio::test]
+async fn features_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+graft:
+  base: Qmbase
+  block: 12345
+features:
+  - grafting
+specVersion: 0.0.1
+";
+
+    let manifest = resolve_manifest(YAML).await;
+
+    let declared: Vec<_> = manifest.features.iter().cloned().collect();
+    assert_eq!(vec![SubgraphFeature::Grafting], declared);
+    let used: Vec<_> = manifest.used_features().into_iter().collect();
+    assert_eq!(vec![SubgraphFeature::Grafting], used);
+}
+
 #[test]
 fn graft_invalid_manifest() {
     const YAML: &str = "
@@ -140,7 +165,7 @@ specVersion: 0.0.1
         // would be a bit more work; we just want to make sure that
         // graft-related checks work
         let msg = unvalidated
-            .validate(store.clone())
+            .validate(store.clone(), false)
             .expect_err("Validation must fail")
             .into_iter()
             .find(|e| matches!(e, SubgraphManifestValidationError::GraftBaseInvalid(_)))
@@ -159,7 +184,7 @@ specVersion: 0.0.1
         // Validation against subgraph that has not reached the graft point fails
         let unvalidated = resolve_unvalidated(YAML).await;
         let msg = unvalidated
-            .validate(store)
+            .validate(store, false)
             .expect_err("Validation must fail")
             .into_iter()
             .find(|e| matches!(e, SubgraphManifestValidationError::GraftBaseInvalid(_)))
@@ -172,3 +197,110 @@ specVersion: 0.0.1
         );
     })
 }
+
+#[test]
+fn graft_base_missing_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+graft:
+  base: Qmmissing
+  block: 1
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msg = unvalidated
+            .validate(store, false)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .find(|e| matches!(e, SubgraphManifestValidationError::GraftBaseInvalid(_)))
+            .expect("There must be a GraftBaseInvalid error")
+            .to_string();
+        assert_eq!(
+            "the graft base is invalid: failed to graft onto `Qmmissing` \
+            since it does not exist",
+            msg
+        );
+    })
+}
+
+#[test]
+fn subgraph_data_source_manifest() {
+    const YAML: &str = "
+dataSources:
+  - kind: subgraph
+    name: Derived
+    network: mainnet
+    source:
+      subgraph: QmSubgraphSource
+      startBlock: 10
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      entityHandlers:
+        - entity: Thing
+          handler: handleThing
+          removeHandler: handleThingRemoved
+        - entity: Missing
+          handler: handleMissing
+      file:
+        /: /ipfs/Qmmapping
+schema:
+  file:
+    /: /ipfs/Qmschema
+features:
+  - subgraphDataSources
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let manifest = resolve_manifest(YAML).await;
+        let data_source = &manifest.data_sources[0];
+        assert_eq!(
+            Some("QmSubgraphSource"),
+            data_source.source_subgraph().map(|id| id.as_str())
+        );
+        let handlers = &data_source.mapping.entity_handlers;
+        assert_eq!(
+            Some("handleThingRemoved"),
+            handlers[0].remove_handler.as_deref()
+        );
+        assert_eq!(None, handlers[1].remove_handler);
+        let used: Vec<_> = manifest.used_features().into_iter().collect();
+        assert_eq!(vec![SubgraphFeature::SubgraphDataSources], used);
+
+        test_store::create_test_subgraph("QmSubgraphSource", GQL_SCHEMA);
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let errors: Vec<_> = unvalidated
+            .validate(store, false)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .filter(|e| {
+                matches!(
+                    e,
+                    SubgraphManifestValidationError::SubgraphDataSourceInvalid(..)
+                )
+            })
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "data source `Derived` can not handle entity changes: the entity type \
+                 `Missing` of handler `handleMissing` is not defined by the source \
+                 deployment `QmSubgraphSource`"
+            ],
+            errors
+        );
+    })
+}
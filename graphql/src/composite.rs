@@ -0,0 +1,280 @@
+//! Queries against composite deployments, which serve several deployments
+//! under one endpoint. Each toplevel field of a query names a namespace,
+//! which stands for one of the deployments, and its selections are a query
+//! against that deployment:
+//!
+//! ```graphql
+//! {
+//!   v1 { exchanges(first: 5) { id } }
+//!   v2 { pairs(first: 5) { id } }
+//! }
+//! ```
+//!
+//! We split such a query into one query per namespace, run them like any
+//! other query, and put their results under the response keys of the
+//! toplevel fields. The extensions of the response say which deployment
+//! and block each namespace was answered from.
+
+use graphql_parser::query as q;
+use std::collections::{BTreeMap, HashMap};
+
+use graph::prelude::{
+    EthereumBlockPointer, QueryError, QueryExecutionError, QueryResult, QueryVariables,
+    SubgraphDeploymentId,
+};
+
+use crate::prelude::object;
+use crate::query::ast as qast;
+
+/// The query for one toplevel field of a query against a composite
+/// deployment
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompositePart {
+    /// The response key under which the result of the query goes
+    pub response_key: String,
+    pub namespace: String,
+    /// The query to run against the deployment of `namespace`
+    pub document: q::Document,
+}
+
+/// Split the operation `operation_name` of `document` into one query per
+/// toplevel field. Every toplevel field must be one of `namespaces` and can
+/// not have arguments; fields with the same response key are merged
+pub fn split_composite_query(
+    document: &q::Document,
+    operation_name: Option<&str>,
+    variables: Option<&QueryVariables>,
+    namespaces: &[&str],
+) -> Result<Vec<CompositePart>, QueryExecutionError> {
+    let no_variables = HashMap::new();
+    let variables = variables.map_or(&no_variables, |variables| &**variables);
+
+    let operation = qast::get_operation(document, operation_name)?;
+    let selection_set = match operation {
+        q::OperationDefinition::Query(query) => &query.selection_set,
+        q::OperationDefinition::SelectionSet(selection_set) => selection_set,
+        q::OperationDefinition::Subscription(_) | q::OperationDefinition::Mutation(_) => {
+            return Err(QueryExecutionError::NotSupported(
+                "Only queries are supported for composite deployments".to_owned(),
+            ))
+        }
+    };
+    let fragments: Vec<_> = document
+        .definitions
+        .iter()
+        .filter(|definition| match definition {
+            q::Definition::Fragment(_) => true,
+            q::Definition::Operation(_) => false,
+        })
+        .cloned()
+        .collect();
+
+    let mut selections: Vec<(&q::Name, &q::Name, q::SelectionSet)> = Vec::new();
+    for selection in selection_set.items.iter().filter(|selection| {
+        !qast::skip_selection(selection, variables) && qast::include_selection(selection, variables)
+    }) {
+        let field = match selection {
+            q::Selection::Field(field) => field,
+            q::Selection::FragmentSpread(_) | q::Selection::InlineFragment(_) => {
+                return Err(QueryExecutionError::NotSupported(
+                    "Fragments are not supported at the top level of queries \
+                     against composite deployments"
+                        .to_owned(),
+                ))
+            }
+        };
+        if !namespaces.contains(&field.name.as_str()) {
+            return Err(QueryExecutionError::UnknownField(
+                field.position,
+                "Query".to_owned(),
+                field.name.clone(),
+            ));
+        }
+        if !field.arguments.is_empty() {
+            return Err(QueryExecutionError::NotSupported(format!(
+                "The namespace `{}` does not take arguments",
+                field.name
+            )));
+        }
+
+        let response_key = qast::get_response_key(field);
+        match selections
+            .iter_mut()
+            .find(|(key, _, _)| *key == response_key)
+        {
+            Some((_, namespace, selection_set)) if *namespace == &field.name => selection_set
+                .items
+                .extend(field.selection_set.items.iter().cloned()),
+            Some(_) => {
+                return Err(QueryExecutionError::NotSupported(format!(
+                    "The response key `{}` is used for more than one namespace",
+                    response_key
+                )))
+            }
+            None => selections.push((response_key, &field.name, field.selection_set.clone())),
+        }
+    }
+
+    Ok(selections
+        .into_iter()
+        .map(|(response_key, namespace, selection_set)| {
+            let operation = match operation {
+                q::OperationDefinition::Query(query) => q::OperationDefinition::Query(q::Query {
+                    selection_set,
+                    ..query.clone()
+                }),
+                _ => q::OperationDefinition::SelectionSet(selection_set),
+            };
+            let mut definitions = vec![q::Definition::Operation(operation)];
+            definitions.extend(fragments.iter().cloned());
+            CompositePart {
+                response_key: response_key.clone(),
+                namespace: namespace.clone(),
+                document: q::Document { definitions },
+            }
+        })
+        .collect())
+}
+
+/// The result of running one `CompositePart`
+pub struct CompositeResult {
+    pub part: CompositePart,
+    pub deployment: SubgraphDeploymentId,
+    /// The latest block that the deployment had processed when the query
+    /// ran
+    pub block: Option<EthereumBlockPointer>,
+    pub result: Result<QueryResult, QueryError>,
+}
+
+/// Combine the results of the parts of a query into one response. The
+/// data of each part goes under its response key, and the errors of all
+/// parts are combined. The extensions list the deployment and block for
+/// each namespace
+pub fn merge_composite_results(results: Vec<CompositeResult>) -> QueryResult {
+    let mut data = BTreeMap::new();
+    let mut errors = Vec::new();
+    let mut sources = BTreeMap::new();
+    for result in results {
+        let value = match result.result {
+            Ok(result) => {
+                errors.extend(result.errors.unwrap_or_default());
+                result.data.unwrap_or(q::Value::Null)
+            }
+            Err(e) => {
+                errors.push(e);
+                q::Value::Null
+            }
+        };
+        data.insert(result.part.response_key, value);
+        sources.insert(
+            result.part.namespace,
+            object! {
+                deployment: result.deployment.to_string(),
+                block: result.block.map(|block| object! {
+                    hash: format!("0x{}", block.hash_hex()),
+                    number: q::Number::from(block.number as i32),
+                }),
+            },
+        );
+    }
+
+    let mut result = QueryResult::new(Some(q::Value::Object(data)));
+    if !errors.is_empty() {
+        result.errors = Some(errors);
+    }
+    let mut extensions = BTreeMap::new();
+    extensions.insert("sources".to_owned(), q::Value::Object(sources));
+    result.with_extensions(extensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(query: &str) -> Result<Vec<CompositePart>, QueryExecutionError> {
+        let document = graphql_parser::parse_query(query).unwrap();
+        split_composite_query(&document, None, None, &["v1", "v2"])
+    }
+
+    #[test]
+    fn splits_queries_by_namespace() {
+        let parts = split(
+            "query q($first: Int) {
+               v1 { exchanges(first: $first) { ...ex } }
+               other: v2 { pairs { id } }
+               v1 { tokens { id } }
+             }
+             fragment ex on Exchange { id }",
+        )
+        .unwrap();
+        assert_eq!(2, parts.len());
+        assert_eq!(
+            ("v1", "v1"),
+            (&*parts[0].response_key, &*parts[0].namespace)
+        );
+        assert_eq!(
+            graphql_parser::parse_query(
+                "query q($first: Int) { exchanges(first: $first) { ...ex } tokens { id } }
+                 fragment ex on Exchange { id }"
+            )
+            .unwrap(),
+            parts[0].document
+        );
+        assert_eq!(
+            ("other", "v2"),
+            (&*parts[1].response_key, &*parts[1].namespace)
+        );
+
+        assert!(split("{ v3 { things { id } } }").is_err());
+        assert!(split("{ v1(block: { number: 1 }) { things { id } } }").is_err());
+        assert!(split("{ ... on Query { v1 { things { id } } } }").is_err());
+        assert!(split("{ v1 { things { id } } v1: v2 { pairs { id } } }").is_err());
+        assert!(split("subscription { v1 { things { id } } }").is_err());
+    }
+
+    #[test]
+    fn merges_results() {
+        let part = |key: &str| CompositePart {
+            response_key: key.to_owned(),
+            namespace: key.to_owned(),
+            document: q::Document {
+                definitions: vec![],
+            },
+        };
+        let result = merge_composite_results(vec![
+            CompositeResult {
+                part: part("v1"),
+                deployment: SubgraphDeploymentId::new("QmV1").unwrap(),
+                block: Some(EthereumBlockPointer::from((Default::default(), 10u64))),
+                result: Ok(QueryResult::new(Some(object! { things: vec![1] }))),
+            },
+            CompositeResult {
+                part: part("v2"),
+                deployment: SubgraphDeploymentId::new("QmV2").unwrap(),
+                block: None,
+                result: Ok(QueryResult::from(QueryExecutionError::Timeout)),
+            },
+        ]);
+
+        assert_eq!(
+            Some(object! { v1: object! { things: vec![1] }, v2: q::Value::Null }),
+            result.data
+        );
+        assert_eq!(1, result.errors.map_or(0, |errors| errors.len()));
+        assert_eq!(
+            Some(object! {
+                sources: object! {
+                    v1: object! {
+                        deployment: "QmV1",
+                        block: object! {
+                            hash: format!("0x{}", "00".repeat(32)),
+                            number: 10,
+                        },
+                    },
+                    v2: object! { deployment: "QmV2", block: q::Value::Null },
+                },
+            }),
+            result.extensions
+        );
+    }
+}
@@ -6,6 +6,7 @@ use std::collections::hash_map::Entry;
 use std::collections::{HashMap, VecDeque};
 use std::ops::Deref;
 use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 type Hash = <SetHasher as StableHasher>::Out;
 
@@ -71,6 +72,8 @@ impl<R> CheapClone for CachedResponse<R> {}
 struct CacheEntryInner<R> {
     cleanup: CleanupQueue,
     hash: Hash,
+    /// When the query that produces the result started running
+    started: Instant,
     // Considered using once_cell::sync::Lazy,
     // but that quickly becomes a mess of generics
     // or runs into the issue that Box<dyn FnOnce> can't be
@@ -87,6 +90,7 @@ impl<R> CacheEntryInner<R> {
         Arc::new(Self {
             cleanup: cleanup.cheap_clone(),
             hash,
+            started: Instant::now(),
             result: OnceCell::new(),
             condvar: Condvar::new(),
             lock: Mutex::new(false),
@@ -112,6 +116,25 @@ impl<R> CacheEntryInner<R> {
         self.set_inner(None);
     }
 
+    /// Wait until the result is available, but not past `deadline`.
+    /// Returns whether the result is available
+    fn wait_until(&self, deadline: Option<Instant>) -> bool {
+        let mut is_set = self.lock.lock().unwrap();
+        while !*is_set {
+            match deadline {
+                None => is_set = self.condvar.wait(is_set).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    is_set = self.condvar.wait_timeout(is_set, deadline - now).unwrap().0;
+                }
+            }
+        }
+        true
+    }
+
     fn wait(&self) -> &R {
         // Happy path - already cached.
         if let Some(r) = self.result.get() {
@@ -165,6 +188,19 @@ impl<R> PanicHelper<R> {
     }
 }
 
+/// How a call to `QueryCache::cached_query` got its result
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Herd {
+    /// The call ran the query
+    Leader,
+    /// An identical query was already running, and the call waited this
+    /// long for its result
+    Shared(Duration),
+    /// An identical query was already running but did not finish in time;
+    /// the call waited this long before running the query itself
+    TimedOut(Duration),
+}
+
 /// Cache that keeps a result around as long as it is still in use somewhere.
 /// The cache ensures that the query is not re-entrant, so multiple consumers
 /// of identical queries will not execute them in parallel.
@@ -183,9 +219,28 @@ impl<R> QueryCache<R> {
             cache: Arc::new(Mutex::new(HashMap::new())),
         }
     }
+    /// A handle for a `result` that was produced without running a query,
+    /// like a response read from a snapshot of the query cache. The handle
+    /// is not shared with queries that run later
+    pub fn standalone(&self, hash: Hash, result: R) -> CachedResponse<R> {
+        let inner = CacheEntryInner::new(hash, &self.cleanup);
+        inner.set(result);
+        CachedResponse { inner }
+    }
+
     /// Assumption: Whatever F is passed in consistently returns the same
     /// value for any input - for all values of F used with this Cache.
-    pub fn cached_query<F: FnOnce() -> R>(&self, hash: Hash, f: F) -> CachedResponse<R> {
+    ///
+    /// If an identical query is already running, wait for its result, but
+    /// only until it has been running for `max_wait`; after that, run `f`
+    /// without sharing its result so that one stuck query does not hold
+    /// up all the others
+    pub fn cached_query<F: FnOnce() -> R>(
+        &self,
+        hash: Hash,
+        max_wait: Option<Duration>,
+        f: F,
+    ) -> (CachedResponse<R>, Herd) {
         // This holds it's own lock so make sure that this happens outside of
         // holding any other lock.
         let cleanup = self.cleanup.pop();
@@ -209,35 +264,92 @@ impl<R> QueryCache<R> {
         // Try to pull the item out of the cache and return it.
         // If we get past this expr, it means this thread will do
         // the work and fullfil that 'promise' in this work variable.
-        let work = match cache.entry(hash) {
+        let (work, running) = match cache.entry(hash) {
             Entry::Occupied(mut entry) => {
                 // Cache hit!
                 if let Some(cached) = entry.get().upgrade() {
-                    return CachedResponse { inner: cached };
+                    (cached, true)
+                } else {
+                    // Need to re-add to cache
+                    let uncached = CacheEntryInner::new(hash, &self.cleanup);
+                    *entry.get_mut() = Arc::downgrade(&uncached);
+                    (uncached, false)
                 }
-                // Need to re-add to cache
-                let uncached = CacheEntryInner::new(hash, &self.cleanup);
-                *entry.get_mut() = Arc::downgrade(&uncached);
-                uncached
             }
             Entry::Vacant(entry) => {
                 let uncached = CacheEntryInner::new(hash, &self.cleanup);
                 entry.insert(Arc::downgrade(&uncached));
-                uncached
+                (uncached, false)
             }
         };
 
         // Don't hold the lock.
         drop(cache);
 
+        let (work, herd) = if running {
+            let start = Instant::now();
+            if work.wait_until(max_wait.map(|max_wait| work.started + max_wait)) {
+                return (
+                    CachedResponse { inner: work },
+                    Herd::Shared(start.elapsed()),
+                );
+            }
+            // The entry for this result is not in the cache, and it is
+            // therefore not shared with anybody
+            (
+                CacheEntryInner::new(hash, &self.cleanup),
+                Herd::TimedOut(start.elapsed()),
+            )
+        } else {
+            (work, Herd::Leader)
+        };
+
         // Now that we have taken on the responsibility, propagate panics to
         // make sure that no threads wait forever on a result that will never
         // come.
         let work = PanicHelper::new(work);
 
         // After all that ceremony, this part is easy enough.
-        CachedResponse {
+        let response = CachedResponse {
             inner: work.set(f()),
-        }
+        };
+        (response, herd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stable_hash::utils::stable_hash;
+    use std::thread;
+
+    #[test]
+    fn waiters_give_up_on_slow_queries() {
+        let cache = Arc::new(QueryCache::new());
+        let hash = stable_hash::<SetHasher, _>(&"query");
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let leader = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                let (response, herd) = cache.cached_query(hash, None, || {
+                    tx.send(()).unwrap();
+                    thread::sleep(Duration::from_millis(500));
+                    1
+                });
+                assert_eq!(Herd::Leader, herd);
+                *response
+            })
+        };
+        rx.recv().unwrap();
+
+        let (response, herd) = cache.cached_query(hash, Some(Duration::from_millis(10)), || 2);
+        assert_eq!(2, *response);
+        assert!(matches!(herd, Herd::TimedOut(_)));
+
+        let (response, herd) = cache.cached_query(hash, None, || 3);
+        assert_eq!(1, *response);
+        assert!(matches!(herd, Herd::Shared(_)));
+        assert_eq!(1, leader.join().unwrap());
     }
 }
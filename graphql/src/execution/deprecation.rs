@@ -0,0 +1,61 @@
+//! Counting queries for deprecated fields, so that subgraph authors can see
+//! whether clients still use a field before they remove it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use graph::data::graphql::ext::{
+    DeprecationExt, DirectiveExt, DirectiveFinder, ObjectTypeExt, ValueExt,
+};
+use graph::prelude::{CounterVec, MetricsRegistry};
+
+use super::hooks::{ExecutionHook, HookField};
+
+/// An `ExecutionHook` that counts how often fields marked `@deprecated`
+/// are queried, by deployment, type and field
+pub struct DeprecatedFieldMetrics {
+    usage: Box<CounterVec>,
+}
+
+impl DeprecatedFieldMetrics {
+    pub fn new(registry: Arc<impl MetricsRegistry>) -> Self {
+        let usage = registry
+            .new_counter_vec(
+                String::from("query_deprecated_field_usage"),
+                String::from("How often queries asked for fields that are marked @deprecated"),
+                HashMap::new(),
+                vec![
+                    String::from("deployment"),
+                    String::from("type"),
+                    String::from("field"),
+                ],
+            )
+            .expect("failed to create `query_deprecated_field_usage` counter");
+        DeprecatedFieldMetrics { usage }
+    }
+}
+
+impl ExecutionHook for DeprecatedFieldMetrics {
+    fn before_field(&self, hook_field: &HookField) {
+        let object_type = hook_field.object_type;
+        let deprecated = object_type
+            .field(&hook_field.field.name)
+            .map_or(false, |field| field.deprecation_reason().is_some());
+        if !deprecated {
+            return;
+        }
+
+        let deployment = object_type
+            .find_directive(String::from("subgraphId"))
+            .and_then(|directive| directive.argument("id"))
+            .and_then(ValueExt::as_string)
+            .map_or("", String::as_str);
+        self.usage
+            .with_label_values(&[
+                deployment,
+                object_type.name.as_str(),
+                hook_field.field.name.as_str(),
+            ])
+            .inc();
+    }
+}
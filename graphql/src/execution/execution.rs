@@ -1,20 +1,30 @@
-use super::cache::{CachedResponse, QueryCache};
+use super::cache::{CachedResponse, Herd, QueryCache};
 use graph::prelude::CheapClone;
 use graphql_parser::query as q;
 use graphql_parser::schema as s;
 use indexmap::IndexMap;
 use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
 use stable_hash::crypto::SetHasher;
 use stable_hash::prelude::*;
 use stable_hash::utils::stable_hash;
-use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs;
 use std::iter;
 use std::ops::Deref;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
-use std::sync::RwLock;
-use std::time::Instant;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
+use graph::data::query::plans;
+use graph::prelude::web3::types::H256;
 use graph::prelude::*;
+use graph::trace::Span;
+use graph::util::env::env_var;
+use graph::util::lfu_cache::CacheWeight;
+use graph::util::memory::{self, MemoryUsage, Subsystem};
 
 use crate::introspection::{
     is_introspection_field, INTROSPECTION_DOCUMENT, INTROSPECTION_QUERY_TYPE,
@@ -28,40 +38,536 @@ type QueryHash = <SetHasher as StableHasher>::Out;
 
 type QueryResponse = Result<BTreeMap<String, q::Value>, Vec<QueryExecutionError>>;
 
+/// How many different introspection responses we keep per deployment
+const MAX_INTROSPECTION_RESPONSES: usize = 32;
+
+/// A cached response, together with what is needed to invalidate it when
+/// the block it is for is reverted
+#[derive(Debug)]
+struct CacheEntry {
+    response: CachedResponse<QueryResponse>,
+    deployment: SubgraphDeploymentId,
+    /// The entity types that the query might have read
+    entity_types: BTreeSet<String>,
+    /// When the response stops being served; only set for responses at
+    /// the head of a deployment on a network with a TTL
+    expires: Option<Instant>,
+    weight: u64,
+}
+
+impl CacheEntry {
+    fn is_expired(&self) -> bool {
+        self.expires
+            .map_or(false, |expires| expires <= Instant::now())
+    }
+}
+
 #[derive(Debug)]
 struct CacheByBlock {
     block: EthereumBlockPointer,
-    cache: BTreeMap<QueryHash, CachedResponse<QueryResponse>>,
+    cache: BTreeMap<QueryHash, CacheEntry>,
+    usage: MemoryUsage,
 }
 
-lazy_static! {
-    // Comma separated subgraph ids to cache queries for.
-    // If `*` is present in the list, queries are cached for all subgraphs.
-    static ref CACHED_SUBGRAPH_IDS: Vec<String> = {
-        std::env::var("GRAPH_CACHED_SUBGRAPH_IDS")
-        .unwrap_or_default()
-        .split(',')
-        .map(|s| s.to_owned())
+impl CacheByBlock {
+    fn new(block: EthereumBlockPointer) -> Self {
+        CacheByBlock {
+            block,
+            cache: BTreeMap::new(),
+            usage: MemoryUsage::new(Subsystem::QueryCache),
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: QueryHash,
+        response: CachedResponse<QueryResponse>,
+        deployment: &SubgraphDeploymentId,
+        entity_types: &BTreeSet<String>,
+        expires: Option<Instant>,
+    ) {
+        let weight = match response.deref() {
+            Ok(map) => map
+                .iter()
+                .map(|(key, value)| key.len() as u64 + value.weight())
+                .sum(),
+            Err(_) => 0,
+        };
+        let entry = CacheEntry {
+            response,
+            deployment: deployment.clone(),
+            entity_types: entity_types.clone(),
+            expires,
+            weight,
+        };
+        // An expired response is replaced by a fresh one for the same query
+        let replaced = self
+            .cache
+            .insert(key, entry)
+            .map_or(0, |entry| entry.weight);
+        self.usage
+            .set((self.usage.bytes() + weight).saturating_sub(replaced));
+    }
+
+    /// Remove the responses for `deployment` that might have read any of
+    /// `entity_types` and return how many were removed
+    fn invalidate(
+        &mut self,
+        deployment: &SubgraphDeploymentId,
+        entity_types: &HashSet<String>,
+    ) -> usize {
+        let stale: Vec<_> = self
+            .cache
+            .iter()
+            .filter(|(_, entry)| {
+                &entry.deployment == deployment
+                    && entry
+                        .entity_types
+                        .iter()
+                        .any(|entity_type| entity_types.contains(entity_type))
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &stale {
+            if let Some(entry) = self.cache.remove(key) {
+                self.usage
+                    .set(self.usage.bytes().saturating_sub(entry.weight));
+            }
+        }
+        stale.len()
+    }
+}
+
+/// Settings for the query cache. They are initialized from the
+/// environment and can be changed while the node is running with
+/// `set_query_cache_settings`
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryCacheSettings {
+    /// How many blocks should be kept in the query cache. When the limit is
+    /// reached, older blocks are evicted. This should be kept small since a
+    /// lookup to the cache is O(n) on this value, and the cache memory usage
+    /// also increases with larger number. Set to 0 to disable the cache,
+    /// defaults to disabled.
+    pub blocks: usize,
+    /// Subgraph ids to cache queries for. If `*` is present in the list,
+    /// queries are cached for all subgraphs.
+    pub subgraph_ids: Vec<String>,
+    /// How long responses for the latest block of a deployment are served
+    /// from the cache, by network. Without this, they are served until the
+    /// block falls out of the cache, which can take long when a chain or
+    /// its provider stalls. A TTL for `*` applies to all other networks.
+    pub head_ttls: HashMap<String, Duration>,
+}
+
+impl QueryCacheSettings {
+    pub fn from_env() -> Self {
+        let blocks = std::env::var("GRAPH_QUERY_CACHE_BLOCKS")
+            .unwrap_or("0".to_string())
+            .parse::<usize>()
+            .expect("Invalid value for GRAPH_QUERY_CACHE_BLOCKS environment variable");
+        // Comma separated subgraph ids to cache queries for.
+        let subgraph_ids = std::env::var("GRAPH_CACHED_SUBGRAPH_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.to_owned())
+            .collect();
+        // Comma separated `network=seconds` pairs
+        let head_ttls = std::env::var("GRAPH_QUERY_CACHE_HEAD_TTL")
+            .map(|s| parse_head_ttls(&s).unwrap_or_else(|e| panic!("{}", e)))
+            .unwrap_or_default();
+        QueryCacheSettings {
+            blocks,
+            subgraph_ids,
+            head_ttls,
+        }
+    }
+
+    fn head_ttl(&self, network: &str) -> Option<Duration> {
+        self.head_ttls
+            .get(network)
+            .or_else(|| self.head_ttls.get("*"))
+            .cloned()
+    }
+
+    fn caches(&self, subgraph_id: &str) -> bool {
+        self.subgraph_ids
+            .iter()
+            .any(|id| id == "*" || id == subgraph_id)
+    }
+}
+
+fn parse_head_ttls(s: &str) -> Result<HashMap<String, Duration>, String> {
+    s.split(',')
+        .filter(|ttl| !ttl.trim().is_empty())
+        .map(|ttl| {
+            let mut parts = ttl.splitn(2, '=').map(str::trim);
+            match (parts.next(), parts.next().map(str::parse::<u64>)) {
+                (Some(network), Some(Ok(secs))) if !network.is_empty() => {
+                    Ok((network.to_owned(), Duration::from_secs(secs)))
+                }
+                _ => Err(format!(
+                    "invalid TTL `{}` in env var GRAPH_QUERY_CACHE_HEAD_TTL, \
+                     it must have the form `network=seconds`",
+                    ttl
+                )),
+            }
+        })
         .collect()
-    };
+}
+
+/// Whether queries against `deployment` are put into the query cache
+pub fn caches_deployment(deployment: &SubgraphDeploymentId) -> bool {
+    let settings = QUERY_CACHE_SETTINGS.read().unwrap();
+    settings.blocks > 0 && settings.caches(deployment)
+}
+
+/// Remove the cached responses for `deployment` at blocks after `block`
+/// that might have read any of `entity_types`. This is called when the
+/// deployment reverted blocks and changed entities of these types in doing
+/// so; the responses for other entity types stay valid. Returns how many
+/// responses were removed
+pub fn invalidate_query_cache(
+    deployment: &SubgraphDeploymentId,
+    block: u64,
+    entity_types: &HashSet<String>,
+) -> usize {
+    let mut cache = QUERY_CACHE.write().unwrap();
+    let removed = cache
+        .iter_mut()
+        .filter(|cache_by_block| cache_by_block.block.number > block)
+        .map(|cache_by_block| cache_by_block.invalidate(deployment, entity_types))
+        .sum();
+    // Blocks that have nothing cached anymore would otherwise keep
+    // queries for the blocks that replace them from being cached
+    cache.retain(|cache_by_block| !cache_by_block.cache.is_empty());
+    removed
+}
+
+/// Add the entity types that the fields in `selection_set` of `parent` and
+/// their subselections return to `types`. For interfaces, all the types
+/// that implement them are added. This overestimates the entity types that
+/// the query reads, which is fine for invalidating cached responses
+fn collect_entity_types<'a>(
+    ctx: &ExecutionContext<impl Resolver>,
+    selection_set: &q::SelectionSet,
+    parent: ObjectOrInterface<'a>,
+    types: &mut BTreeSet<String>,
+) {
+    let schema = &ctx.query.schema;
+    for selection in &selection_set.items {
+        match selection {
+            q::Selection::Field(field) => {
+                let field_def = match parent.field(&field.name) {
+                    Some(field_def) => field_def,
+                    None => continue,
+                };
+                let type_name = sast::get_field_name(&field_def.field_type);
+                match sast::get_named_type(&schema.document, &type_name) {
+                    Some(s::TypeDefinition::Object(object)) => {
+                        types.insert(object.name.clone());
+                        collect_entity_types(ctx, &field.selection_set, object.into(), types);
+                    }
+                    Some(s::TypeDefinition::Interface(interface)) => {
+                        if let Some(objects) = schema.types_for_interface().get(&interface.name) {
+                            types.extend(objects.iter().map(|object| object.name.clone()));
+                        }
+                        collect_entity_types(ctx, &field.selection_set, interface.into(), types);
+                    }
+                    _ => {}
+                }
+            }
+            q::Selection::FragmentSpread(spread) => {
+                if let Some(fragment) = ctx.query.fragments.get(&spread.fragment_name) {
+                    let q::TypeCondition::On(name) = &fragment.type_condition;
+                    if let Some(fragment_type) = fragment_type(ctx, name, parent) {
+                        collect_entity_types(ctx, &fragment.selection_set, fragment_type, types);
+                    }
+                }
+            }
+            q::Selection::InlineFragment(fragment) => {
+                let fragment_type = match &fragment.type_condition {
+                    Some(q::TypeCondition::On(name)) => fragment_type(ctx, name, parent),
+                    None => Some(parent),
+                };
+                if let Some(fragment_type) = fragment_type {
+                    collect_entity_types(ctx, &fragment.selection_set, fragment_type, types);
+                }
+            }
+        }
+    }
+}
+
+fn fragment_type<'a>(
+    ctx: &'a ExecutionContext<impl Resolver>,
+    name: &q::Name,
+    parent: ObjectOrInterface<'a>,
+) -> Option<ObjectOrInterface<'a>> {
+    if name == parent.name() {
+        return Some(parent);
+    }
+    match sast::get_named_type(&ctx.query.schema.document, name) {
+        Some(s::TypeDefinition::Object(object)) => Some(object.into()),
+        Some(s::TypeDefinition::Interface(interface)) => Some(interface.into()),
+        _ => None,
+    }
+}
 
-    static ref CACHE_ALL: bool = CACHED_SUBGRAPH_IDS.contains(&"*".to_string());
-
-    // How many blocks should be kept in the query cache. When the limit is reached, older blocks
-    // are evicted. This should be kept small since a lookup to the cache is O(n) on this value, and
-    // the cache memory usage also increases with larger number. Set to 0 to disable the cache,
-    // defaults to disabled.
-    static ref QUERY_CACHE_BLOCKS: usize = {
-        std::env::var("GRAPH_QUERY_CACHE_BLOCKS")
-        .unwrap_or("0".to_string())
-        .parse::<usize>()
-        .expect("Invalid value for GRAPH_QUERY_CACHE_BLOCKS environment variable")
+/// The version of the format of query cache snapshots. It must be changed
+/// whenever the format changes, or when the way cache keys are computed
+/// changes since the keys in older snapshots would then be meaningless
+const SNAPSHOT_VERSION: u64 = 1;
+
+/// Write the responses in the query cache to `path` so that the node can
+/// start with them after a restart. Responses that expire after a TTL are
+/// not written. Returns how many responses were written
+pub fn save_query_cache(path: &Path) -> Result<usize, Error> {
+    let mut count = 0;
+    let blocks: Vec<_> = {
+        let cache = QUERY_CACHE.read().unwrap();
+        cache
+            .iter()
+            .map(|cache_by_block| {
+                let entries: Vec<_> = cache_by_block
+                    .cache
+                    .iter()
+                    .filter(|(_, entry)| entry.expires.is_none())
+                    .filter_map(|(key, entry)| {
+                        let map = entry.response.deref().as_ref().ok()?;
+                        let response: BTreeMap<_, _> = map
+                            .iter()
+                            .map(|(name, value)| (name, SerializableValue(value)))
+                            .collect();
+                        Some(serde_json::json!({
+                            "key": hex::encode(key),
+                            "deployment": entry.deployment.as_str(),
+                            "entity_types": entry.entity_types,
+                            "response": response,
+                        }))
+                    })
+                    .collect();
+                count += entries.len();
+                serde_json::json!({
+                    "hash": cache_by_block.block.hash_hex(),
+                    "number": cache_by_block.block.number,
+                    "entries": entries,
+                })
+            })
+            .collect()
     };
+    let snapshot = serde_json::json!({
+        "version": SNAPSHOT_VERSION,
+        "graph_node_version": env!("CARGO_PKG_VERSION"),
+        "blocks": blocks,
+    });
+    fs::write(path, serde_json::to_vec(&snapshot)?)
+        .map_err(|e| format_err!("failed to write {}: {}", path.display(), e))?;
+    Ok(count)
+}
+
+/// Fill the query cache with the responses that `save_query_cache` wrote
+/// to `path`. Snapshots from another version of graph-node are ignored.
+/// Responses are only loaded for deployments whose queries are cached and
+/// for which `keep` returns `true` for the deployment and the block of the
+/// response; `keep` should check that the deployment is still on a block
+/// that is at or not too far past that block. Returns how many responses
+/// were loaded
+pub fn load_query_cache<F>(path: &Path, keep: F) -> Result<usize, Error>
+where
+    F: Fn(&SubgraphDeploymentId, &EthereumBlockPointer) -> bool,
+{
+    let text =
+        fs::read(path).map_err(|e| format_err!("failed to read {}: {}", path.display(), e))?;
+    let snapshot: serde_json::Value = serde_json::from_slice(&text)?;
+    if snapshot["version"].as_u64() != Some(SNAPSHOT_VERSION)
+        || snapshot["graph_node_version"].as_str() != Some(env!("CARGO_PKG_VERSION"))
+    {
+        return Err(format_err!(
+            "the query cache snapshot in {} was written by a different version of graph-node",
+            path.display()
+        ));
+    }
+
+    let settings = QUERY_CACHE_SETTINGS.read().unwrap();
+    let mut kept = HashMap::new();
+    let mut restored = VecDeque::new();
+    let mut count = 0;
+    for block in snapshot["blocks"].as_array().into_iter().flatten() {
+        if restored.len() >= settings.blocks {
+            break;
+        }
+        let block_ptr = match (
+            block["hash"]
+                .as_str()
+                .and_then(|hash| H256::from_str(hash).ok()),
+            block["number"].as_u64(),
+        ) {
+            (Some(hash), Some(number)) => EthereumBlockPointer { hash, number },
+            _ => return Err(format_err!("invalid block in query cache snapshot")),
+        };
+        let mut cache_by_block = CacheByBlock::new(block_ptr);
+        for entry in block["entries"].as_array().into_iter().flatten() {
+            let deployment = match entry["deployment"]
+                .as_str()
+                .and_then(|id| SubgraphDeploymentId::new(id).ok())
+            {
+                Some(deployment) => deployment,
+                None => continue,
+            };
+            if !settings.caches(&deployment)
+                || !*kept
+                    .entry(deployment.clone())
+                    .or_insert_with(|| keep(&deployment, &block_ptr))
+            {
+                continue;
+            }
+            let key = match entry["key"].as_str().and_then(|key| hex::decode(key).ok()) {
+                Some(ref bytes) if bytes.len() == 32 => {
+                    let mut key = QueryHash::default();
+                    key.copy_from_slice(bytes);
+                    key
+                }
+                _ => continue,
+            };
+            let response = match value_from_json(entry["response"].clone()) {
+                Some(q::Value::Object(map)) => map,
+                _ => continue,
+            };
+            let entity_types = entry["entity_types"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|entity_type| entity_type.as_str().map(str::to_owned))
+                .collect();
+            let response = QUERY_HERD_CACHE.standalone(key, Ok(response));
+            cache_by_block.insert(key, response, &deployment, &entity_types, None);
+            count += 1;
+        }
+        // Whether a deployment is kept depends on the block
+        kept.clear();
+        if !cache_by_block.cache.is_empty() {
+            restored.push_back(cache_by_block);
+        }
+    }
+    drop(settings);
+
+    let mut cache = QUERY_CACHE.write().unwrap();
+    if !cache.is_empty() {
+        return Err(format_err!(
+            "the query cache must be loaded before queries are run"
+        ));
+    }
+    *cache = restored;
+    Ok(count)
+}
+
+/// The GraphQL value for JSON that `SerializableValue` produced. Enums
+/// become strings, which are serialized the same way. Returns `None` for
+/// numbers that do not fit into a GraphQL value
+fn value_from_json(value: serde_json::Value) -> Option<q::Value> {
+    Some(match value {
+        serde_json::Value::Null => q::Value::Null,
+        serde_json::Value::Bool(b) => q::Value::Boolean(b),
+        serde_json::Value::Number(number) => {
+            if number.is_f64() {
+                q::Value::Float(number.as_f64()?)
+            } else {
+                let int = number.as_i64()?;
+                if int < i32::min_value() as i64 || int > i32::max_value() as i64 {
+                    return None;
+                }
+                q::Value::Int((int as i32).into())
+            }
+        }
+        serde_json::Value::String(s) => q::Value::String(s),
+        serde_json::Value::Array(values) => q::Value::List(
+            values
+                .into_iter()
+                .map(value_from_json)
+                .collect::<Option<_>>()?,
+        ),
+        serde_json::Value::Object(map) => q::Value::Object(
+            map.into_iter()
+                .map(|(name, value)| value_from_json(value).map(|value| (name, value)))
+                .collect::<Option<_>>()?,
+        ),
+    })
+}
+
+/// The settings that the query cache currently uses
+pub fn query_cache_settings() -> QueryCacheSettings {
+    QUERY_CACHE_SETTINGS.read().unwrap().clone()
+}
+
+/// Change the settings for the query cache. If the number of blocks is
+/// reduced, the oldest blocks are evicted from the cache right away
+pub fn set_query_cache_settings(settings: QueryCacheSettings) {
+    let mut cache = QUERY_CACHE.write().unwrap();
+    cache.truncate(settings.blocks);
+    *QUERY_CACHE_SETTINGS.write().unwrap() = settings;
+}
+
+lazy_static! {
+    static ref QUERY_CACHE_SETTINGS: RwLock<QueryCacheSettings> =
+        RwLock::new(QueryCacheSettings::from_env());
 
     // New blocks go on the front, so the oldest block will be at the back.
-    // This `VecDeque` works as a ring buffer with a capacity of `QUERY_CACHE_BLOCKS`.
+    // This `VecDeque` works as a ring buffer with a capacity of `QueryCacheSettings::blocks`.
     static ref QUERY_CACHE: RwLock<VecDeque<CacheByBlock>> = RwLock::new(VecDeque::new());
     static ref QUERY_HERD_CACHE: QueryCache<QueryResponse> = QueryCache::new();
+
+    // Introspection responses only depend on the schema of a deployment,
+    // and not on the block, so they are cached separately
+    static ref INTROSPECTION_CACHE: RwLock<HashMap<SubgraphDeploymentId, IntrospectionCache>> =
+        RwLock::new(HashMap::new());
+
+    /// How long a query waits at most for an identical query that is
+    /// already running before it runs itself
+    static ref QUERY_HERD_MAX_WAIT: Option<Duration> =
+        env_var("GRAPH_QUERY_HERD_MAX_WAIT").map(Duration::from_millis);
+}
+
+static HERD_METRICS: OnceCell<HerdMetrics> = OnceCell::new();
+
+/// Metrics for queries that wait for identical queries in the herd cache
+struct HerdMetrics {
+    waits: Box<CounterVec>,
+    wait_time: Box<Histogram>,
+}
+
+impl HerdMetrics {
+    fn observe(&self, herd: Herd) {
+        let (outcome, waited) = match herd {
+            Herd::Leader => return,
+            Herd::Shared(waited) => ("shared", waited),
+            Herd::TimedOut(waited) => ("timeout", waited),
+        };
+        self.waits.with_label_values(&[outcome]).inc();
+        self.wait_time.observe(waited.as_secs_f64());
+    }
+}
+
+/// Export metrics for queries that wait for identical queries that are
+/// already running: `query_herd_waits` counts them by whether they got the
+/// result of the other query or gave up waiting, and
+/// `query_herd_wait_seconds` shows how long they waited
+pub fn export_herd_metrics(registry: Arc<impl MetricsRegistry>) {
+    let waits = registry
+        .new_counter_vec(
+            String::from("query_herd_waits"),
+            String::from("Queries that waited for an identical query that was already running"),
+            HashMap::new(),
+            vec![String::from("outcome")],
+        )
+        .expect("failed to create `query_herd_waits` counter");
+    let wait_time = registry
+        .new_histogram(
+            String::from("query_herd_wait_seconds"),
+            String::from("How long queries waited for an identical query that was already running"),
+            HashMap::new(),
+            vec![0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 10.0, 60.0],
+        )
+        .expect("failed to create `query_herd_wait_seconds` histogram");
+    let _ = HERD_METRICS.set(HerdMetrics { waits, wait_time });
 }
 
 pub enum MaybeCached<T> {
@@ -82,9 +588,13 @@ impl<T: Clone> MaybeCached<T> {
 struct HashableQuery<'a> {
     query_schema_id: &'a SubgraphDeploymentId,
     query_variables: &'a HashMap<q::Name, q::Value>,
+    /// Only these variables are hashed since the others can not change the
+    /// response
+    used_variables: &'a BTreeSet<q::Name>,
     query_fragments: &'a HashMap<String, q::FragmentDefinition>,
     selection_set: &'a q::SelectionSet,
     block_ptr: &'a EthereumBlockPointer,
+    api_version: ApiVersion,
 }
 
 /// Note that the use of StableHash here is a little bit loose. In particular,
@@ -111,6 +621,7 @@ impl StableHash for HashableQuery<'_> {
         // Not stable! Uses to_string()
         self.query_variables
             .iter()
+            .filter(|(k, _)| self.used_variables.contains(*k))
             .map(|(k, v)| (k, v.to_string()))
             .collect::<HashMap<_, _>>()
             .stable_hash(sequence_number.next_child(), state);
@@ -129,10 +640,55 @@ impl StableHash for HashableQuery<'_> {
 
         self.block_ptr
             .stable_hash(sequence_number.next_child(), state);
+
+        self.api_version
+            .as_str()
+            .stable_hash(sequence_number.next_child(), state);
+    }
+}
+
+/// The introspection responses for a deployment. They are only valid for
+/// `schema`; when the deployment is redeployed and its schema is loaded
+/// again, they are thrown away
+struct IntrospectionCache {
+    schema: Arc<Schema>,
+    responses: HashMap<QueryHash, BTreeMap<String, q::Value>>,
+}
+
+impl IntrospectionCache {
+    fn new(schema: &Arc<Schema>) -> Self {
+        IntrospectionCache {
+            schema: schema.clone(),
+            responses: HashMap::new(),
+        }
     }
 }
 
-// The key is: subgraph id + selection set + variables + fragment definitions
+// The key is: selection set + variables + fragment definitions; the
+// deployment is covered by `IntrospectionCache`
+fn introspection_cache_key(
+    ctx: &ExecutionContext<impl Resolver>,
+    selection_set: &q::SelectionSet,
+) -> QueryHash {
+    let variables: BTreeMap<_, _> = ctx
+        .query
+        .variables
+        .iter()
+        .filter(|(name, _)| ctx.query.used_variables.contains(*name))
+        .map(|(name, value)| (name, value.to_string()))
+        .collect();
+    let fragments: BTreeMap<_, _> = ctx
+        .query
+        .fragments
+        .iter()
+        .map(|(name, fragment)| (name, fragment.to_string()))
+        .collect();
+    let text = format!("{}\n{:?}\n{:?}", selection_set, variables, fragments);
+    stable_hash::<SetHasher, _>(&text)
+}
+
+// The key is: subgraph id + selection set + variables + fragment
+// definitions + API version
 fn cache_key(
     ctx: &ExecutionContext<impl Resolver>,
     selection_set: &q::SelectionSet,
@@ -143,9 +699,11 @@ fn cache_key(
     let query = HashableQuery {
         query_schema_id: &ctx.query.schema.id,
         query_variables: &ctx.query.variables,
+        used_variables: &ctx.query.used_variables,
         query_fragments: &ctx.query.fragments,
         selection_set,
         block_ptr,
+        api_version: ctx.query.api_version,
     };
     stable_hash::<SetHasher, _>(&query)
 }
@@ -170,6 +728,9 @@ where
     /// Max value for `first`.
     pub max_first: u32,
 
+    /// The hooks to run around each field.
+    pub hooks: Arc<ExecutionHooks>,
+
     /// Will be `true` if the response was pulled from cache. The mechanism by
     /// which this is set is actually to start at `true` and then be set to
     /// `false` if the query is executed.
@@ -204,28 +765,25 @@ where
     R: Resolver,
 {
     pub fn as_introspection_context(&self) -> ExecutionContext<IntrospectionResolver> {
-        let introspection_resolver = IntrospectionResolver::new(&self.logger, &self.query.schema);
-
         ExecutionContext {
             logger: self.logger.cheap_clone(),
-            resolver: Arc::new(introspection_resolver),
+            resolver: IntrospectionResolver::cached(&self.logger, &self.query.schema),
             query: self.query.as_introspection_query(),
             deadline: self.deadline,
             max_first: std::u32::MAX,
+            hooks: self.hooks.clone(),
             cached: AtomicBool::new(true),
         }
     }
 }
 
-pub fn execute_root_selection_set_uncached(
+/// Split the top-level fields of `selection_set` into regular data fields
+/// and introspection fields
+fn split_root_selection_set(
     ctx: &ExecutionContext<impl Resolver>,
     selection_set: &q::SelectionSet,
     root_type: &s::ObjectType,
-) -> QueryResponse {
-    ctx.cached.store(false, std::sync::atomic::Ordering::SeqCst);
-
-    // Split the top-level fields into introspection fields and
-    // regular data fields
+) -> (q::SelectionSet, q::SelectionSet) {
     let mut data_set = q::SelectionSet {
         span: selection_set.span.clone(),
         items: Vec::new(),
@@ -247,6 +805,17 @@ pub fn execute_root_selection_set_uncached(
             data_set.items.extend(selections)
         }
     }
+    (data_set, intro_set)
+}
+
+pub fn execute_root_selection_set_uncached(
+    ctx: &ExecutionContext<impl Resolver>,
+    selection_set: &q::SelectionSet,
+    root_type: &s::ObjectType,
+) -> QueryResponse {
+    ctx.cached.store(false, std::sync::atomic::Ordering::SeqCst);
+
+    let (data_set, intro_set) = split_root_selection_set(ctx, selection_set, root_type);
 
     // If we are getting regular data, prefetch it from the database
     let mut values = if data_set.items.is_empty() {
@@ -258,36 +827,119 @@ pub fn execute_root_selection_set_uncached(
 
     // Resolve introspection fields, if there are any
     if !intro_set.items.is_empty() {
-        let ictx = ctx.as_introspection_context();
-
-        values.extend(execute_selection_set_to_map(
-            &ictx,
-            iter::once(&intro_set),
-            &*INTROSPECTION_QUERY_TYPE,
-            None,
-        )?);
+        values.extend(execute_introspection(ctx, &intro_set)?);
     }
 
     Ok(values)
 }
 
-/// Executes the root selection set of a query.
+/// Resolve the introspection fields in `intro_set`, or return the response
+/// of an earlier identical introspection query against the same schema
+fn execute_introspection(
+    ctx: &ExecutionContext<impl Resolver>,
+    intro_set: &q::SelectionSet,
+) -> QueryResponse {
+    let schema = &ctx.query.schema;
+    let key = introspection_cache_key(ctx, intro_set);
+    if let Some(cache) = INTROSPECTION_CACHE.read().unwrap().get(&schema.id) {
+        if Arc::ptr_eq(&cache.schema, schema) {
+            if let Some(response) = cache.responses.get(&key) {
+                return Ok(response.clone());
+            }
+        }
+    }
+
+    ctx.cached.store(false, std::sync::atomic::Ordering::SeqCst);
+    let ictx = ctx.as_introspection_context();
+    let response = execute_selection_set_to_map(
+        &ictx,
+        iter::once(intro_set),
+        &*INTROSPECTION_QUERY_TYPE,
+        None,
+    )?;
+
+    let mut cache = INTROSPECTION_CACHE.write().unwrap();
+    let cache = cache
+        .entry(schema.id.clone())
+        .or_insert_with(|| IntrospectionCache::new(schema));
+    if !Arc::ptr_eq(&cache.schema, schema) {
+        *cache = IntrospectionCache::new(schema);
+    }
+    if cache.responses.len() >= MAX_INTROSPECTION_RESPONSES {
+        cache.responses.clear();
+    }
+    cache.responses.insert(key, response.clone());
+    Ok(response)
+}
+
+/// Executes the root selection set of a query. If `block_ptr` is the latest
+/// block of the deployment, `head_network` is the network of the deployment
+/// so that the response is only cached for the TTL of that network.
+///
+/// The data fields and the introspection fields of the query are cached
+/// separately: the response for the data fields is cached by block, and the
+/// response for the introspection fields by schema, since it stays the same
+/// for all blocks. The two responses are then merged.
 pub fn execute_root_selection_set(
     ctx: &ExecutionContext<impl Resolver>,
     selection_set: &q::SelectionSet,
     root_type: &s::ObjectType,
     block_ptr: Option<EthereumBlockPointer>,
+    head_network: Option<&str>,
+) -> MaybeCached<QueryResponse> {
+    let (data_set, intro_set) = split_root_selection_set(ctx, selection_set, root_type);
+    if intro_set.items.is_empty() {
+        return execute_data_selection_set(ctx, &data_set, root_type, block_ptr, head_network);
+    }
+
+    let mut values = if data_set.items.is_empty() {
+        BTreeMap::default()
+    } else {
+        match execute_data_selection_set(ctx, &data_set, root_type, block_ptr, head_network)
+            .to_inner()
+        {
+            Ok(values) => values,
+            Err(errors) => return MaybeCached::NotCached(Err(errors)),
+        }
+    };
+    match execute_introspection(ctx, &intro_set) {
+        Ok(intro_values) => values.extend(intro_values),
+        Err(errors) => return MaybeCached::NotCached(Err(errors)),
+    }
+    MaybeCached::NotCached(Ok(values))
+}
+
+/// Executes the data fields of the root selection set of a query, using
+/// the query cache for `block_ptr`
+fn execute_data_selection_set(
+    ctx: &ExecutionContext<impl Resolver>,
+    selection_set: &q::SelectionSet,
+    root_type: &s::ObjectType,
+    block_ptr: Option<EthereumBlockPointer>,
+    head_network: Option<&str>,
 ) -> MaybeCached<QueryResponse> {
     // Cache the cache key to not have to calculate it twice - once for lookup
     // and once for insert.
     let mut key: Option<QueryHash> = None;
+    let (cacheable, cache_blocks, head_ttl) = {
+        let settings = QUERY_CACHE_SETTINGS.read().unwrap();
+        // Explained queries return no entities, which must not be cached
+        (
+            settings.caches(&ctx.query.schema.id)
+                && !plans::is_explaining(ctx.query.schema.id.as_str()),
+            settings.blocks,
+            head_network.and_then(|network| settings.head_ttl(network)),
+        )
+    };
 
-    if *CACHE_ALL || CACHED_SUBGRAPH_IDS.contains(&ctx.query.schema.id) {
+    if cacheable {
         if let Some(block_ptr) = block_ptr {
             // JSONB and metadata queries use `BLOCK_NUMBER_MAX`. Ignore this case for two reasons:
             // - Metadata queries are not cacheable.
             // - Caching `BLOCK_NUMBER_MAX` would make this cache think all other blocks are old.
             if block_ptr.number != BLOCK_NUMBER_MAX as u64 {
+                let mut span = Span::child("graphql.cache_lookup");
+
                 // Calculate the hash outside of the lock
                 let cache_key = cache_key(ctx, selection_set, &block_ptr);
 
@@ -296,20 +948,29 @@ pub fn execute_root_selection_set(
 
                 // Iterate from the most recent block looking for a block that matches.
                 if let Some(cache_by_block) = cache.iter().find(|c| c.block == block_ptr) {
-                    if let Some(response) = cache_by_block.cache.get(&cache_key) {
-                        return MaybeCached::Cached(response.cheap_clone());
+                    if let Some(entry) = cache_by_block
+                        .cache
+                        .get(&cache_key)
+                        .filter(|entry| !entry.is_expired())
+                    {
+                        span.set_attribute("cache.hit", true);
+                        return MaybeCached::Cached(entry.response.cheap_clone());
                     }
                 }
 
+                span.set_attribute("cache.hit", false);
                 key = Some(cache_key);
             }
         }
     }
 
     let result = if let Some(key) = key {
-        let cached = QUERY_HERD_CACHE.cached_query(key, || {
+        let (cached, herd) = QUERY_HERD_CACHE.cached_query(key, *QUERY_HERD_MAX_WAIT, || {
             execute_root_selection_set_uncached(ctx, selection_set, root_type)
         });
+        if let Some(metrics) = HERD_METRICS.get() {
+            metrics.observe(herd);
+        }
         MaybeCached::Cached(cached)
     } else {
         let not_cached = execute_root_selection_set_uncached(ctx, selection_set, root_type);
@@ -322,12 +983,28 @@ pub fn execute_root_selection_set(
         // In particular, there is a problem where asking for a block pointer beyond the chain
         // head can cause the legitimate cache to be thrown out.
         if cached.is_ok() {
+            let deployment = &ctx.query.schema.id;
+            let mut entity_types = BTreeSet::new();
+            collect_entity_types(ctx, selection_set, root_type.into(), &mut entity_types);
+            let expires = head_ttl.map(|ttl| Instant::now() + ttl);
+
             let mut cache = QUERY_CACHE.write().unwrap();
 
-            // If there is already a cache by the block of this query, just add it there.
-            if let Some(cache_by_block) = cache.iter_mut().find(|c| c.block == block_ptr) {
-                cache_by_block.cache.insert(key, cached.cheap_clone());
-            } else if *QUERY_CACHE_BLOCKS > 0 {
+            if memory::under_pressure() {
+                // Give memory back by dropping the older half of the
+                // cache, and do not cache anything new for now
+                let keep = cache.len() / 2;
+                cache.truncate(keep);
+            } else if let Some(cache_by_block) = cache.iter_mut().find(|c| c.block == block_ptr) {
+                // If there is already a cache by the block of this query, just add it there.
+                cache_by_block.insert(
+                    key,
+                    cached.cheap_clone(),
+                    deployment,
+                    &entity_types,
+                    expires,
+                );
+            } else if cache_blocks > 0 {
                 // We're creating a new `CacheByBlock` if:
                 // - There are none yet, this is the first query being cached, or
                 // - `block_ptr` is of higher or equal number than the most recent block in the cache.
@@ -339,15 +1016,21 @@ pub fn execute_root_selection_set(
                 };
 
                 if should_insert {
-                    if cache.len() == *QUERY_CACHE_BLOCKS {
-                        // At capacity, so pop the oldest block.
+                    // At capacity, so pop the oldest blocks. The capacity
+                    // might have been reduced since the last insert.
+                    while cache.len() >= cache_blocks {
                         cache.pop_back();
                     }
 
-                    cache.push_front(CacheByBlock {
-                        block: block_ptr,
-                        cache: BTreeMap::from_iter(iter::once((key, cached.cheap_clone()))),
-                    });
+                    let mut cache_by_block = CacheByBlock::new(block_ptr);
+                    cache_by_block.insert(
+                        key,
+                        cached.cheap_clone(),
+                        deployment,
+                        &entity_types,
+                        expires,
+                    );
+                    cache.push_front(cache_by_block);
                 }
             }
         }
@@ -605,19 +1288,24 @@ fn execute_field(
     field_definition: &s::Field,
     fields: Vec<&q::Field>,
 ) -> Result<q::Value, Vec<QueryExecutionError>> {
-    coerce_argument_values(ctx, object_type, field)
-        .and_then(|argument_values| {
-            resolve_field_value(
-                ctx,
-                object_type,
-                field_value,
-                field,
-                field_definition,
-                &field_definition.field_type,
-                &argument_values,
-            )
+    ctx.hooks
+        .around_field(object_type, field, &ctx.query.variables, || {
+            coerce_argument_values(ctx, object_type, field)
+                .and_then(|argument_values| {
+                    resolve_field_value(
+                        ctx,
+                        object_type,
+                        field_value,
+                        field,
+                        field_definition,
+                        &field_definition.field_type,
+                        &argument_values,
+                    )
+                })
+                .and_then(|value| {
+                    complete_value(ctx, field, &field_definition.field_type, &fields, value)
+                })
         })
-        .and_then(|value| complete_value(ctx, field, &field_definition.field_type, &fields, value))
 }
 
 /// Resolves the value of a field.
@@ -957,6 +1645,12 @@ pub fn coerce_argument_values<'a>(
         let value = qast::get_argument_value(&field.arguments, &argument_def.name).cloned();
         match coercion::coerce_input_value(value, &argument_def, &resolver, &ctx.query.variables) {
             Ok(Some(value)) => {
+                if argument_def.name == "where" && ctx.query.api_version.strict_nulls() {
+                    if let Err(e) = check_null_filters(field, &value) {
+                        errors.push(e);
+                        continue;
+                    }
+                }
                 if argument_def.name == "text".to_string() {
                     coerced_values.insert(
                         &argument_def.name,
@@ -977,3 +1671,69 @@ pub fn coerce_argument_values<'a>(
         Err(errors)
     }
 }
+
+/// Check that the `where` filter `filter` of `field` only uses `null` for
+/// equality and `_not`. API versions before `0.0.2` pass other comparisons
+/// with `null` on to the store, which fails them with an opaque error
+fn check_null_filters(field: &q::Field, filter: &q::Value) -> Result<(), QueryExecutionError> {
+    let filter = match filter {
+        q::Value::Object(filter) => filter,
+        _ => return Ok(()),
+    };
+    for (key, value) in filter {
+        if *value != q::Value::Null {
+            continue;
+        }
+        match sast::parse_field_as_filter(key).1 {
+            sast::FilterOp::Equal | sast::FilterOp::Not => (),
+            _ => {
+                return Err(QueryExecutionError::InvalidArgumentError(
+                    field.position,
+                    format!("where.{}", key),
+                    q::Value::Null,
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_head_ttls() {
+        let ttls = parse_head_ttls("mainnet=60, xdai = 5,").unwrap();
+        assert_eq!(Some(&Duration::from_secs(60)), ttls.get("mainnet"));
+        assert_eq!(Some(&Duration::from_secs(5)), ttls.get("xdai"));
+        assert!(parse_head_ttls("mainnet").is_err());
+        assert!(parse_head_ttls("mainnet=1m").is_err());
+        assert!(parse_head_ttls("=5").is_err());
+
+        let settings = QueryCacheSettings {
+            blocks: 1,
+            subgraph_ids: vec![],
+            head_ttls: parse_head_ttls("mainnet=60,*=10").unwrap(),
+        };
+        assert_eq!(Some(Duration::from_secs(60)), settings.head_ttl("mainnet"));
+        assert_eq!(Some(Duration::from_secs(10)), settings.head_ttl("xdai"));
+    }
+
+    #[test]
+    fn restores_values_from_json() {
+        let mut map = BTreeMap::new();
+        map.insert("int".to_owned(), q::Value::Int(7.into()));
+        map.insert("float".to_owned(), q::Value::Float(1.5));
+        map.insert(
+            "list".to_owned(),
+            q::Value::List(vec![q::Value::Null, q::Value::Boolean(true)]),
+        );
+        map.insert("string".to_owned(), q::Value::String("x".to_owned()));
+        let value = q::Value::Object(map);
+        let json = serde_json::to_value(SerializableValue(&value)).unwrap();
+        assert_eq!(Some(value), value_from_json(json));
+
+        assert_eq!(None, value_from_json(serde_json::json!(5_000_000_000i64)));
+    }
+}
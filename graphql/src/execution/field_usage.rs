@@ -0,0 +1,46 @@
+//! Recording which fields of the schema of a deployment queries use, for
+//! the `fieldUsage` query of the index node
+
+use graph::data::graphql::ext::{DirectiveExt, DirectiveFinder, ValueExt};
+use graph::data::query::field_usage;
+
+use super::hooks::{ExecutionHook, HookField};
+
+/// An `ExecutionHook` that records a sample of the fields that are
+/// executed, by deployment, type and field
+pub struct FieldUsageRecorder;
+
+impl FieldUsageRecorder {
+    /// A recorder if `GRAPH_QUERY_FIELD_USAGE_SAMPLE_RATE` turns recording
+    /// field usage on
+    pub fn from_env() -> Option<Self> {
+        if field_usage::is_enabled() {
+            Some(FieldUsageRecorder)
+        } else {
+            None
+        }
+    }
+}
+
+impl ExecutionHook for FieldUsageRecorder {
+    fn before_field(&self, hook_field: &HookField) {
+        if !field_usage::should_sample() {
+            return;
+        }
+
+        // Types that do not belong to a deployment, like the ones for
+        // introspection, are not recorded
+        let object_type = hook_field.object_type;
+        if let Some(deployment) = object_type
+            .find_directive(String::from("subgraphId"))
+            .and_then(|directive| directive.argument("id"))
+            .and_then(ValueExt::as_string)
+        {
+            field_usage::record(
+                deployment,
+                object_type.name.as_str(),
+                hook_field.field.name.as_str(),
+            );
+        }
+    }
+}
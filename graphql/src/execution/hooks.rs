@@ -0,0 +1,247 @@
+//! Custom directives and hooks that run around the execution of fields.
+//! Execution ignores directives other than `@skip` and `@include`, but
+//! directives that are registered in a `DirectiveRegistry` are handed to
+//! the `ExecutionHook`s, together with their arguments, whenever a field
+//! that carries them is executed. That lets hooks, for example, pick a
+//! cache TTL from `@cacheControl(maxAge: 30)` or label metrics with the tag
+//! from `@label(tag: "dashboard")`.
+//!
+//! Hooks only run for fields that are actually executed; responses that
+//! come from the query cache or from an identical query that is already
+//! running do not run them.
+
+use graphql_parser::{query as q, schema as s};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use graph::prelude::QueryExecutionError;
+
+/// The directives that execution itself interprets; they can not be
+/// registered
+const BUILTIN_DIRECTIVES: &[&str] = &["skip", "include"];
+
+/// The custom directives that are passed to hooks, and the arguments they
+/// accept
+#[derive(Clone, Debug, Default)]
+pub struct DirectiveRegistry {
+    directives: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl DirectiveRegistry {
+    /// Pass the directive `name` and its `arguments` to hooks. Panics if
+    /// `name` is one of the built-in directives
+    pub fn register(mut self, name: &str, arguments: &[&str]) -> Self {
+        if BUILTIN_DIRECTIVES.contains(&name) {
+            panic!("the built-in directive `@{}` can not be registered", name);
+        }
+        self.directives.insert(
+            name.to_owned(),
+            arguments.iter().map(|arg| (*arg).to_owned()).collect(),
+        );
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directives.is_empty()
+    }
+
+    /// The registered directives on `field` with the values of their
+    /// registered arguments. Variables are replaced by their values, or by
+    /// `null` if they were not given; other directives and arguments are
+    /// left out
+    pub fn field_directives(
+        &self,
+        field: &q::Field,
+        variables: &HashMap<q::Name, q::Value>,
+    ) -> Vec<FieldDirective> {
+        field
+            .directives
+            .iter()
+            .filter_map(|directive| {
+                let known = self.directives.get(&directive.name)?;
+                let arguments = directive
+                    .arguments
+                    .iter()
+                    .filter(|(name, _)| known.contains(name))
+                    .map(|(name, value)| (name.clone(), substitute_variables(value, variables)))
+                    .collect();
+                Some(FieldDirective {
+                    name: directive.name.clone(),
+                    arguments,
+                })
+            })
+            .collect()
+    }
+}
+
+fn substitute_variables(value: &q::Value, variables: &HashMap<q::Name, q::Value>) -> q::Value {
+    match value {
+        q::Value::Variable(name) => variables.get(name).cloned().unwrap_or(q::Value::Null),
+        q::Value::List(values) => q::Value::List(
+            values
+                .iter()
+                .map(|value| substitute_variables(value, variables))
+                .collect(),
+        ),
+        q::Value::Object(map) => q::Value::Object(
+            map.iter()
+                .map(|(name, value)| (name.clone(), substitute_variables(value, variables)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// A registered directive on a field, as passed to hooks
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldDirective {
+    pub name: String,
+    pub arguments: BTreeMap<String, q::Value>,
+}
+
+impl FieldDirective {
+    pub fn argument(&self, name: &str) -> Option<&q::Value> {
+        self.arguments.get(name)
+    }
+}
+
+/// The field that is being executed
+pub struct HookField<'a> {
+    pub object_type: &'a s::ObjectType,
+    pub field: &'a q::Field,
+    /// The registered directives on the field
+    pub directives: &'a [FieldDirective],
+}
+
+/// Code that runs before and after each field of a query is executed.
+/// Hooks can not change the result; they are called from the threads that
+/// execute queries and should therefore be quick
+pub trait ExecutionHook: Send + Sync {
+    fn before_field(&self, _field: &HookField) {}
+
+    /// Called with the completed value of the field, or the errors that
+    /// executing it caused
+    fn after_field(
+        &self,
+        _field: &HookField,
+        _result: &Result<q::Value, Vec<QueryExecutionError>>,
+    ) {
+    }
+}
+
+/// The hooks for executing queries and the directives they look at
+#[derive(Clone, Default)]
+pub struct ExecutionHooks {
+    pub directives: DirectiveRegistry,
+    pub hooks: Vec<Arc<dyn ExecutionHook>>,
+}
+
+impl ExecutionHooks {
+    pub fn new(directives: DirectiveRegistry) -> Self {
+        ExecutionHooks {
+            directives,
+            hooks: Vec::new(),
+        }
+    }
+
+    pub fn with_hook(mut self, hook: Arc<dyn ExecutionHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Run `execute` for `field`, with the hooks before and after it
+    pub(crate) fn around_field<F>(
+        &self,
+        object_type: &s::ObjectType,
+        field: &q::Field,
+        variables: &HashMap<q::Name, q::Value>,
+        execute: F,
+    ) -> Result<q::Value, Vec<QueryExecutionError>>
+    where
+        F: FnOnce() -> Result<q::Value, Vec<QueryExecutionError>>,
+    {
+        if self.is_empty() {
+            return execute();
+        }
+        let directives = self.directives.field_directives(field, variables);
+        let hook_field = HookField {
+            object_type,
+            field,
+            directives: &directives,
+        };
+        for hook in &self.hooks {
+            hook.before_field(&hook_field);
+        }
+        let result = execute();
+        for hook in &self.hooks {
+            hook.after_field(&hook_field, &result);
+        }
+        result
+    }
+}
+
+impl fmt::Debug for ExecutionHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExecutionHooks")
+            .field("directives", &self.directives)
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(query: &str) -> q::Field {
+        let document = graphql_parser::parse_query(query).unwrap();
+        match &document.definitions[0] {
+            q::Definition::Operation(q::OperationDefinition::SelectionSet(set)) => {
+                match &set.items[0] {
+                    q::Selection::Field(field) => field.clone(),
+                    _ => panic!("expected a field"),
+                }
+            }
+            _ => panic!("expected a selection set"),
+        }
+    }
+
+    #[test]
+    fn passes_registered_directives() {
+        let registry = DirectiveRegistry::default()
+            .register("cacheControl", &["maxAge"])
+            .register("label", &["tag"]);
+        let mut variables = HashMap::new();
+        variables.insert("tag".to_owned(), q::Value::String("dashboard".to_owned()));
+
+        let directives = registry.field_directives(
+            &field(
+                "{ things @cacheControl(maxAge: 30, scope: PRIVATE) @label(tag: $tag) \
+                 @client @skip(if: false) { id } }",
+            ),
+            &variables,
+        );
+        assert_eq!(2, directives.len());
+        assert_eq!("cacheControl", directives[0].name);
+        assert_eq!(
+            Some(&q::Value::Int(30.into())),
+            directives[0].argument("maxAge")
+        );
+        assert_eq!(None, directives[0].argument("scope"));
+        assert_eq!(
+            Some(&q::Value::String("dashboard".to_owned())),
+            directives[1].argument("tag")
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn refuses_builtin_directives() {
+        DirectiveRegistry::default().register("skip", &["if"]);
+    }
+}
@@ -1,10 +1,21 @@
 mod cache;
+/// Metrics for queries that use deprecated fields.
+mod deprecation;
 /// Implementation of the GraphQL execution algorithm.
 mod execution;
+/// Sampling which fields queries use.
+mod field_usage;
+/// Custom directives and hooks around field execution.
+mod hooks;
 mod query;
 /// Common trait for field resolvers used in the execution.
 mod resolver;
 
+pub use self::deprecation::DeprecatedFieldMetrics;
 pub use self::execution::*;
+pub use self::field_usage::FieldUsageRecorder;
+pub use self::hooks::{
+    DirectiveRegistry, ExecutionHook, ExecutionHooks, FieldDirective, HookField,
+};
 pub use self::query::Query;
 pub use self::resolver::{ObjectOrInterface, Resolver};
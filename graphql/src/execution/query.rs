@@ -1,15 +1,21 @@
 use graphql_parser::{query as q, schema as s, Style};
-use std::collections::HashMap;
-use std::sync::Arc;
+use lazy_static::lazy_static;
+use lru_time_cache::LruCache;
+use stable_hash::crypto::SetHasher;
+use stable_hash::prelude::*;
+use stable_hash::utils::stable_hash;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
 
 use graph::data::graphql::ext::TypeExt;
 use graph::data::query::{Query as GraphDataQuery, QueryVariables};
 use graph::data::schema::Schema;
-use graph::prelude::{serde_json, QueryExecutionError};
+use graph::prelude::{serde_json, ApiVersion, QueryExecutionError, SubgraphDeploymentId};
+use graph::util::env::env_var;
 
 use crate::execution::{get_field, get_named_type};
 use crate::introspection::introspection_schema;
-use crate::query::{ast as qast, ext::BlockConstraint, ext::FieldExt};
+use crate::query::{ast as qast, ext::BlockConstraint, ext::FieldExt, shape_hash::shape_hash};
 use crate::schema::ast as sast;
 
 #[derive(Copy, Clone, Debug)]
@@ -25,66 +31,79 @@ enum Kind {
     Subscription,
 }
 
-/// A GraphQL query that has been preprocessed and checked and is ready
-/// for execution. Checking includes validating all query fields and, if
-/// desired, checking the query's complexity
-pub struct Query {
-    /// The schema against which to execute the query
-    pub schema: Arc<Schema>,
-    /// The variables for the query, coerced into proper values
-    pub variables: HashMap<q::Name, q::Value>,
-    /// The root selection set of the query
-    pub selection_set: q::SelectionSet,
-    pub(crate) fragments: HashMap<String, q::FragmentDefinition>,
-    kind: Kind,
+type DocumentHash = <SetHasher as StableHasher>::Out;
 
-    /// Used only for logging; if logging is configured off, these will
-    /// have dummy values
-    pub(crate) query_text: Arc<String>,
-    pub(crate) variables_text: Arc<String>,
-    pub(crate) complexity: u64,
+lazy_static! {
+    /// How many prepared query documents to keep; 0 turns that off
+    static ref QUERY_PLAN_CACHE_SIZE: usize =
+        env_var("GRAPH_QUERY_PLAN_CACHE_SIZE").unwrap_or(1000);
+
+    /// How often a query may alias the same field, and how many aliased
+    /// fields one selection set may have
+    static ref MAX_ALIASES: usize = env_var("GRAPH_GRAPHQL_MAX_ALIASES").unwrap_or(100);
+
+    static ref PLANS: Mutex<LruCache<PlanKey, Arc<Plan>>> =
+        Mutex::new(LruCache::with_capacity((*QUERY_PLAN_CACHE_SIZE).max(1)));
 }
 
-impl Query {
-    /// Process the raw GraphQL query `query` and prepare for executing it.
-    /// The returned `Query` has already been validated and, if `max_complexity`
-    /// is given, also checked whether it is too complex. If validation fails,
-    /// or the query is too complex, errors are returned
-    pub fn new(
-        query: GraphDataQuery,
+/// Everything that can change what preparing a query document produces,
+/// except for the schema, which `Plan` checks
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PlanKey {
+    deployment: SubgraphDeploymentId,
+    document: DocumentHash,
+    operation_name: Option<String>,
+    max_complexity: Option<u64>,
+    max_depth: u8,
+}
+
+/// The parts of a `Query` that only depend on the schema and the query
+/// document, but not on the variables. They are kept so that submitting
+/// the same document again skips splitting and validating it
+struct Plan {
+    schema: Arc<Schema>,
+    variable_definitions: Vec<q::VariableDefinition>,
+    fragments: HashMap<String, q::FragmentDefinition>,
+    selection_set: q::SelectionSet,
+    kind: Kind,
+    query_text: Arc<String>,
+    complexity: u64,
+    shape_hash: u64,
+    used_variables: Arc<BTreeSet<q::Name>>,
+}
+
+impl Plan {
+    fn new(
+        schema: Arc<Schema>,
+        document: q::Document,
+        operation_name: Option<&str>,
         max_complexity: Option<u64>,
         max_depth: u8,
-    ) -> Result<Arc<Self>, Vec<QueryExecutionError>> {
-        let (query_text, variables_text) = if *graph::log::LOG_GQL_TIMING {
-            (
-                query
-                    .document
-                    .format(&Style::default().indent(0))
-                    .replace('\n', " "),
-                serde_json::to_string(&query.variables).unwrap_or_default(),
-            )
+    ) -> Result<Self, Vec<QueryExecutionError>> {
+        let query_text = if *graph::log::LOG_GQL_TIMING {
+            document
+                .format(&Style::default().indent(0))
+                .replace('\n', " ")
         } else {
-            ("(gql logging turned off)".to_owned(), "".to_owned())
+            "(gql logging turned off)".to_owned()
         };
-        let query_text = Arc::new(query_text);
-        let variables_text = Arc::new(variables_text);
+        let shape_hash = shape_hash(&document);
 
-        let mut operation = None;
+        let mut operations = Vec::new();
         let mut fragments = HashMap::new();
-        for defn in query.document.definitions.into_iter() {
+        for defn in document.definitions.into_iter() {
             match defn {
-                q::Definition::Operation(op) => match operation {
-                    None => operation = Some(op),
-                    Some(_) => return Err(vec![QueryExecutionError::OperationNameRequired]),
-                },
+                q::Definition::Operation(op) => operations.push(op),
                 q::Definition::Fragment(frag) => {
                     fragments.insert(frag.name.clone(), frag);
                 }
             }
         }
-        let operation = operation.ok_or(QueryExecutionError::OperationNameRequired)?;
+        let operation = select_operation(operations, operation_name)?;
 
-        let variables = coerce_variables(&query.schema, &operation, query.variables)?;
+        let variable_definitions = qast::get_variable_definitions(&operation)
+            .cloned()
+            .unwrap_or_default();
         let (kind, selection_set) = match operation {
             q::OperationDefinition::Query(q::Query { selection_set, .. }) => {
                 (Kind::Query, selection_set)
@@ -101,21 +120,160 @@ impl Query {
             }
         };
 
-        let mut query = Self {
-            schema: query.schema,
-            variables,
+        let mut used_variables = BTreeSet::new();
+        collect_variables(&selection_set, &mut used_variables);
+        for fragment in fragments.values() {
+            directive_variables(&fragment.directives, &mut used_variables);
+            collect_variables(&fragment.selection_set, &mut used_variables);
+        }
+
+        // Validation does not look at the variables
+        let mut query = Query {
+            schema,
+            variables: HashMap::new(),
             fragments,
             selection_set,
             kind,
-            query_text,
-            variables_text,
+            query_text: Arc::new(query_text),
+            variables_text: Arc::new(String::new()),
             complexity: 0,
+            query_id: String::new(),
+            shape_hash,
+            used_variables: Arc::new(used_variables),
+            api_version: ApiVersion::default(),
         };
-
         query.validate_fields()?;
+        AliasCounter::new(&query.fragments, *MAX_ALIASES)
+            .check(&query.selection_set)
+            .map_err(|e| vec![e])?;
         query.check_complexity(max_complexity, max_depth)?;
 
-        Ok(Arc::new(query))
+        Ok(Plan {
+            schema: query.schema,
+            variable_definitions,
+            fragments: query.fragments,
+            selection_set: query.selection_set,
+            kind: query.kind,
+            query_text: query.query_text,
+            complexity: query.complexity,
+            shape_hash,
+            used_variables: query.used_variables,
+        })
+    }
+
+    /// The plan for `document` against `schema`, either from the cache or
+    /// freshly made
+    fn cached(
+        schema: &Arc<Schema>,
+        document: q::Document,
+        operation_name: Option<&str>,
+        max_complexity: Option<u64>,
+        max_depth: u8,
+    ) -> Result<Arc<Self>, Vec<QueryExecutionError>> {
+        if *QUERY_PLAN_CACHE_SIZE == 0 {
+            return Self::new(
+                schema.clone(),
+                document,
+                operation_name,
+                max_complexity,
+                max_depth,
+            )
+            .map(Arc::new);
+        }
+
+        let key = PlanKey {
+            deployment: schema.id.clone(),
+            document: stable_hash::<SetHasher, _>(&document.to_string()),
+            operation_name: operation_name.map(str::to_owned),
+            max_complexity,
+            max_depth,
+        };
+        if let Some(plan) = PLANS.lock().unwrap().get(&key) {
+            // A redeployed subgraph might have a different schema
+            if Arc::ptr_eq(&plan.schema, schema) {
+                return Ok(plan.clone());
+            }
+        }
+        let plan = Arc::new(Self::new(
+            schema.clone(),
+            document,
+            operation_name,
+            max_complexity,
+            max_depth,
+        )?);
+        PLANS.lock().unwrap().insert(key, plan.clone());
+        Ok(plan)
+    }
+}
+
+/// A GraphQL query that has been preprocessed and checked and is ready
+/// for execution. Checking includes validating all query fields and, if
+/// desired, checking the query's complexity
+pub struct Query {
+    /// The schema against which to execute the query
+    pub schema: Arc<Schema>,
+    /// The variables for the query, coerced into proper values
+    pub variables: HashMap<q::Name, q::Value>,
+    /// The root selection set of the query
+    pub selection_set: q::SelectionSet,
+    pub(crate) fragments: HashMap<String, q::FragmentDefinition>,
+    kind: Kind,
+
+    /// Used only for logging; if logging is configured off, these will
+    /// have dummy values
+    pub(crate) query_text: Arc<String>,
+    pub(crate) variables_text: Arc<String>,
+    pub(crate) complexity: u64,
+    pub(crate) query_id: String,
+    /// The hash of the shape of the query, as in the query audit log
+    pub(crate) shape_hash: u64,
+    /// The names of the variables that the selection set or the fragments
+    /// refer to; others do not change the result of the query
+    pub(crate) used_variables: Arc<BTreeSet<q::Name>>,
+    /// The version of the API semantics to execute the query with
+    pub api_version: ApiVersion,
+}
+
+impl Query {
+    /// Process the raw GraphQL query `query` and prepare for executing it.
+    /// The returned `Query` has already been validated and, if `max_complexity`
+    /// is given, also checked whether it is too complex. If validation fails,
+    /// or the query is too complex, errors are returned
+    pub fn new(
+        query: GraphDataQuery,
+        max_complexity: Option<u64>,
+        max_depth: u8,
+    ) -> Result<Arc<Self>, Vec<QueryExecutionError>> {
+        let variables_text = if *graph::log::LOG_GQL_TIMING {
+            serde_json::to_string(&query.variables).unwrap_or_default()
+        } else {
+            "".to_owned()
+        };
+
+        let plan = Plan::cached(
+            &query.schema,
+            query.document,
+            query.operation_name.as_ref().map(String::as_str),
+            max_complexity,
+            max_depth,
+        )?;
+        let variables =
+            coerce_variables(&plan.schema, &plan.variable_definitions, query.variables)?;
+
+        Ok(Arc::new(Self {
+            schema: query.schema,
+            variables,
+            fragments: plan.fragments.clone(),
+            selection_set: plan.selection_set.clone(),
+            kind: plan.kind,
+            query_text: plan.query_text.clone(),
+            variables_text: Arc::new(variables_text),
+            complexity: plan.complexity,
+            query_id: query.query_id,
+            shape_hash: plan.shape_hash,
+            used_variables: plan.used_variables.clone(),
+            api_version: query.api_version,
+        }))
     }
 
     /// Return the block constraint for the toplevel query field(s) Since,
@@ -164,6 +322,10 @@ impl Query {
             query_text: self.query_text.clone(),
             variables_text: self.variables_text.clone(),
             complexity: self.complexity,
+            query_id: self.query_id.clone(),
+            shape_hash: self.shape_hash,
+            used_variables: self.used_variables.clone(),
+            api_version: self.api_version,
         })
     }
 
@@ -414,19 +576,165 @@ impl Query {
     }
 }
 
-/// Coerces variable values for an operation.
+/// Counts the aliases in a query, so that a query can not get around the
+/// limits on depth and complexity by asking for the same field many times
+/// under different aliases. Fragments are counted everywhere they are
+/// spread, since the fields in them are executed that often
+struct AliasCounter<'a> {
+    fragments: &'a HashMap<String, q::FragmentDefinition>,
+    max_aliases: usize,
+    /// How often each field is aliased in the whole query, by field name
+    per_field: HashMap<&'a str, usize>,
+}
+
+impl<'a> AliasCounter<'a> {
+    fn new(fragments: &'a HashMap<String, q::FragmentDefinition>, max_aliases: usize) -> Self {
+        AliasCounter {
+            fragments,
+            max_aliases,
+            per_field: HashMap::new(),
+        }
+    }
+
+    fn check(&mut self, selection_set: &'a q::SelectionSet) -> Result<(), QueryExecutionError> {
+        self.check_selection_set(selection_set, &mut Vec::new())
+    }
+
+    fn check_selection_set(
+        &mut self,
+        selection_set: &'a q::SelectionSet,
+        spreads: &mut Vec<&'a str>,
+    ) -> Result<(), QueryExecutionError> {
+        let mut aliased = 0;
+        self.count(selection_set, &mut aliased, spreads)
+    }
+
+    /// Count the aliased fields in `selection_set` into `aliased`. Fragments
+    /// in `selection_set` belong to the same selection set, whereas the
+    /// selection sets of fields are counted on their own. `spreads` are
+    /// the fragments that are being counted, to stop at fragments that
+    /// spread themselves
+    fn count(
+        &mut self,
+        selection_set: &'a q::SelectionSet,
+        aliased: &mut usize,
+        spreads: &mut Vec<&'a str>,
+    ) -> Result<(), QueryExecutionError> {
+        for selection in &selection_set.items {
+            match selection {
+                q::Selection::Field(field) => {
+                    if field.alias.is_some() {
+                        *aliased += 1;
+                        if *aliased > self.max_aliases {
+                            return Err(QueryExecutionError::TooManyAliases(
+                                None,
+                                self.max_aliases,
+                            ));
+                        }
+                        let count = self.per_field.entry(field.name.as_str()).or_insert(0);
+                        *count += 1;
+                        if *count > self.max_aliases {
+                            return Err(QueryExecutionError::TooManyAliases(
+                                Some(field.name.clone()),
+                                self.max_aliases,
+                            ));
+                        }
+                    }
+                    self.check_selection_set(&field.selection_set, spreads)?;
+                }
+                q::Selection::FragmentSpread(spread) => {
+                    let name = spread.fragment_name.as_str();
+                    if spreads.contains(&name) {
+                        continue;
+                    }
+                    if let Some(fragment) = self.fragments.get(name) {
+                        spreads.push(name);
+                        self.count(&fragment.selection_set, aliased, spreads)?;
+                        spreads.pop();
+                    }
+                }
+                q::Selection::InlineFragment(fragment) => {
+                    self.count(&fragment.selection_set, aliased, spreads)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Add the names of all variables that `selection_set` refers to in
+/// arguments and directives to `names`
+/// The operation named `name`, or the only operation if there is no name
+fn select_operation(
+    operations: Vec<q::OperationDefinition>,
+    name: Option<&str>,
+) -> Result<q::OperationDefinition, QueryExecutionError> {
+    match name {
+        None if operations.len() == 1 => Ok(operations.into_iter().next().unwrap()),
+        None => Err(QueryExecutionError::OperationNameRequired),
+        Some(name) => operations
+            .into_iter()
+            .find(|op| qast::get_operation_name(op).map_or(false, |n| n == name))
+            .ok_or_else(|| QueryExecutionError::OperationNotFound(name.to_owned())),
+    }
+}
+
+fn collect_variables(selection_set: &q::SelectionSet, names: &mut BTreeSet<q::Name>) {
+    for selection in &selection_set.items {
+        match selection {
+            q::Selection::Field(field) => {
+                for (_, value) in &field.arguments {
+                    value_variables(value, names);
+                }
+                directive_variables(&field.directives, names);
+                collect_variables(&field.selection_set, names);
+            }
+            q::Selection::FragmentSpread(spread) => directive_variables(&spread.directives, names),
+            q::Selection::InlineFragment(fragment) => {
+                directive_variables(&fragment.directives, names);
+                collect_variables(&fragment.selection_set, names);
+            }
+        }
+    }
+}
+
+fn directive_variables(directives: &[q::Directive], names: &mut BTreeSet<q::Name>) {
+    for directive in directives {
+        for (_, value) in &directive.arguments {
+            value_variables(value, names);
+        }
+    }
+}
+
+fn value_variables(value: &q::Value, names: &mut BTreeSet<q::Name>) {
+    match value {
+        q::Value::Variable(name) => {
+            names.insert(name.clone());
+        }
+        q::Value::List(values) => {
+            for value in values {
+                value_variables(value, names);
+            }
+        }
+        q::Value::Object(map) => {
+            for value in map.values() {
+                value_variables(value, names);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Coerces variable values for the variables of an operation.
 pub fn coerce_variables(
     schema: &Schema,
-    operation: &q::OperationDefinition,
+    variable_definitions: &[q::VariableDefinition],
     mut variables: Option<QueryVariables>,
 ) -> Result<HashMap<q::Name, q::Value>, Vec<QueryExecutionError>> {
     let mut coerced_values = HashMap::new();
     let mut errors = vec![];
 
-    for variable_def in qast::get_variable_definitions(operation)
-        .into_iter()
-        .flatten()
-    {
+    for variable_def in variable_definitions {
         // Skip variable if it has an invalid type
         if !sast::is_input_type(&schema.document, &variable_def.var_type) {
             errors.push(QueryExecutionError::InvalidVariableTypeError(
@@ -486,3 +794,149 @@ fn coerce_variable(
         )]
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_prepared_documents() {
+        let schema = Arc::new(
+            Schema::parse(
+                "scalar ID scalar String type Query { thing(id: ID!): String }",
+                SubgraphDeploymentId::new("plancache").unwrap(),
+            )
+            .unwrap(),
+        );
+        let document = graphql_parser::parse_query("query q($id: ID!) { thing(id: $id) }").unwrap();
+        let prepare = |id: &str| {
+            let mut variables = HashMap::new();
+            variables.insert("id".to_owned(), q::Value::String(id.to_owned()));
+            let query = GraphDataQuery::new(
+                schema.clone(),
+                document.clone(),
+                Some(QueryVariables::new(variables)),
+            );
+            Query::new(query, None, 10).unwrap()
+        };
+
+        let first = prepare("1");
+        let second = prepare("2");
+        assert_eq!(
+            Some(&q::Value::String("1".to_owned())),
+            first.variables.get("id")
+        );
+        assert_eq!(
+            Some(&q::Value::String("2".to_owned())),
+            second.variables.get("id")
+        );
+
+        let key = PlanKey {
+            deployment: schema.id.clone(),
+            document: stable_hash::<SetHasher, _>(&document.to_string()),
+            operation_name: None,
+            max_complexity: None,
+            max_depth: 10,
+        };
+        assert!(PLANS.lock().unwrap().get(&key).is_some());
+    }
+
+    #[test]
+    fn selects_operations_by_name() {
+        let schema = Arc::new(
+            Schema::parse(
+                "scalar String type Query { a: String, b: String }",
+                SubgraphDeploymentId::new("operations").unwrap(),
+            )
+            .unwrap(),
+        );
+        let document = graphql_parser::parse_query("query A { a } query B { b }").unwrap();
+        let prepare = |name: Option<&str>| {
+            let query = GraphDataQuery::new(schema.clone(), document.clone(), None)
+                .with_operation_name(name.map(str::to_owned));
+            Query::new(query, None, 10)
+        };
+        let field = |query: Arc<Query>| match &query.selection_set.items[0] {
+            q::Selection::Field(field) => field.name.clone(),
+            _ => panic!("expected a field"),
+        };
+
+        assert_eq!("a", field(prepare(Some("A")).unwrap()));
+        assert_eq!("b", field(prepare(Some("B")).unwrap()));
+        let error = |name: Option<&str>| match prepare(name) {
+            Err(errors) => errors[0].to_string(),
+            Ok(_) => panic!("expected an error"),
+        };
+        assert_eq!("Operation name required", error(None));
+        assert_eq!(
+            QueryExecutionError::OperationNotFound("C".to_owned()).to_string(),
+            error(Some("C"))
+        );
+    }
+
+    #[test]
+    fn rejects_too_many_aliases() {
+        let check = |query: &str, max_aliases: usize| {
+            let document = graphql_parser::parse_query(query).unwrap();
+            let mut fragments = HashMap::new();
+            let mut selection_set = None;
+            for definition in document.definitions {
+                match definition {
+                    q::Definition::Operation(q::OperationDefinition::SelectionSet(set)) => {
+                        selection_set = Some(set)
+                    }
+                    q::Definition::Fragment(fragment) => {
+                        fragments.insert(fragment.name.clone(), fragment);
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            AliasCounter::new(&fragments, max_aliases)
+                .check(&selection_set.unwrap())
+                .map_err(|e| e.to_string())
+        };
+
+        assert!(check("{ a: thing { x: name y: name } b: thing { name } }", 3).is_ok());
+        assert!(check("{ a: thing b: thing c: other }", 2).is_err());
+        // The same field aliased in different selection sets
+        assert_eq!(
+            Err(QueryExecutionError::TooManyAliases(Some("name".to_owned()), 2).to_string()),
+            check(
+                "{ a: thing { x: name } b: thing { y: name other { z: name } } }",
+                2
+            )
+        );
+        // Fragments count everywhere they are spread
+        assert!(check(
+            "{ thing { ...f } other { ...f } } fragment f on Thing { x: name }",
+            1
+        )
+        .is_err());
+        assert!(check("{ thing { ...f } } fragment f on Thing { x: name ...f }", 1).is_ok());
+    }
+
+    #[test]
+    fn collects_used_variables() {
+        let document = graphql_parser::parse_query(
+            "query q($a: ID, $b: Int, $c: Boolean, $d: String, $unused: Int) {
+               things(where: { id_in: [$a] }, first: $b) @include(if: $c) { ...f }
+             }
+             fragment f on Thing { name @skip(if: $d) }",
+        )
+        .unwrap();
+        let mut names = BTreeSet::new();
+        for definition in &document.definitions {
+            match definition {
+                q::Definition::Operation(q::OperationDefinition::Query(query)) => {
+                    collect_variables(&query.selection_set, &mut names)
+                }
+                q::Definition::Fragment(fragment) => {
+                    collect_variables(&fragment.selection_set, &mut names)
+                }
+                _ => (),
+            }
+        }
+        let names: Vec<_> = names.iter().map(String::as_str).collect();
+        assert_eq!(vec!["a", "b", "c", "d"], names);
+    }
+}
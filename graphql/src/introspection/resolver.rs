@@ -1,6 +1,10 @@
 use graphql_parser::{query as q, schema as s, Pos};
+use lazy_static::lazy_static;
+use lru_time_cache::LruCache;
 use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, RwLock};
 
+use graph::data::graphql::ext::DeprecationExt;
 use graph::prelude::*;
 
 use crate::prelude::*;
@@ -8,20 +12,6 @@ use crate::schema::ast as sast;
 
 type TypeObjectsMap = BTreeMap<String, q::Value>;
 
-fn schema_type_objects(schema: &Schema) -> TypeObjectsMap {
-    sast::get_type_definitions(&schema.document).iter().fold(
-        BTreeMap::new(),
-        |mut type_objects, typedef| {
-            let type_name = sast::get_type_name(typedef);
-            if !type_objects.contains_key(type_name) {
-                let type_object = type_definition_object(schema, &mut type_objects, typedef);
-                type_objects.insert(type_name.to_owned(), type_object);
-            }
-            type_objects
-        },
-    )
-}
-
 fn type_object(schema: &Schema, type_objects: &mut TypeObjectsMap, t: &s::Type) -> q::Value {
     match t {
         // We store the name of the named type here to be able to resolve it dynamically later
@@ -98,8 +88,8 @@ fn enum_value(enum_value: &s::EnumValue) -> q::Value {
     object! {
         name: enum_value.name.to_owned(),
         description: enum_value.description.clone(),
-        isDeprecated: false,
-        deprecationReason: q::Value::Null,
+        isDeprecated: enum_value.deprecation_reason().is_some(),
+        deprecationReason: enum_value.deprecation_reason(),
     }
 }
 
@@ -176,8 +166,8 @@ fn field_object(schema: &Schema, type_objects: &mut TypeObjectsMap, field: &s::F
         description: field.description.clone(),
         args: input_values(schema, type_objects, &field.arguments),
         type: type_object(schema, type_objects, &field.field_type),
-        isDeprecated: false,
-        deprecationReason: q::Value::Null,
+        isDeprecated: field.deprecation_reason().is_some(),
+        deprecationReason: field.deprecation_reason(),
     }
 }
 
@@ -294,44 +284,129 @@ fn input_value(
     }
 }
 
+lazy_static! {
+    /// Introspection resolvers by deployment. They keep the types of the
+    /// schema that were turned into values so far, which is too slow to do
+    /// for every query
+    static ref RESOLVERS: Mutex<LruCache<SubgraphDeploymentId, Arc<IntrospectionResolver>>> =
+        Mutex::new(LruCache::with_capacity(100));
+}
+
+/// The type definitions of a schema by name, together with the queryable
+/// objects for the types that were needed so far. Objects are only built
+/// when a query asks for them so that looking up a few types with
+/// `__type(name:)` does not have to build objects for the whole schema
+struct TypeIndex {
+    /// The position of each type definition in the schema document
+    positions: HashMap<String, usize>,
+    objects: RwLock<TypeObjectsMap>,
+}
+
+impl TypeIndex {
+    fn new(schema: &Schema, objects: TypeObjectsMap) -> Self {
+        let mut positions = HashMap::new();
+        for (pos, def) in schema.document.definitions.iter().enumerate() {
+            if let s::Definition::TypeDefinition(typedef) = def {
+                positions
+                    .entry(sast::get_type_name(typedef).to_owned())
+                    .or_insert(pos);
+            }
+        }
+        TypeIndex {
+            positions,
+            objects: RwLock::new(objects),
+        }
+    }
+}
+
 #[derive(Clone)]
-pub struct IntrospectionResolver<'a> {
+pub struct IntrospectionResolver {
     logger: Logger,
-    schema: &'a Schema,
-    type_objects: TypeObjectsMap,
+    schema: Arc<Schema>,
+    types: Arc<TypeIndex>,
     directives: q::Value,
 }
 
-impl<'a> IntrospectionResolver<'a> {
-    pub fn new(logger: &Logger, schema: &'a Schema) -> Self {
+impl IntrospectionResolver {
+    pub fn new(logger: &Logger, schema: &Arc<Schema>) -> Self {
         let logger = logger.new(o!("component" => "IntrospectionResolver"));
 
-        // Generate queryable objects for all types in the schema
-        let mut type_objects = schema_type_objects(schema);
-
-        // Generate queryable objects for all directives in the schema
+        // Generate queryable objects for all directives in the schema; the
+        // objects for types are generated when they are first needed
+        let mut type_objects = TypeObjectsMap::new();
         let directives = schema_directive_objects(schema, &mut type_objects);
 
         IntrospectionResolver {
             logger,
-            schema,
-            type_objects,
+            schema: schema.clone(),
+            types: Arc::new(TypeIndex::new(schema, type_objects)),
             directives,
         }
     }
 
+    /// The resolver for `schema`, shared with all other queries against the
+    /// same schema. A resolver that was built for an earlier schema of the
+    /// same deployment is replaced
+    pub fn cached(logger: &Logger, schema: &Arc<Schema>) -> Arc<Self> {
+        if let Some(resolver) = RESOLVERS.lock().unwrap().get(&schema.id) {
+            if Arc::ptr_eq(&resolver.schema, schema) {
+                return resolver.clone();
+            }
+        }
+        // Build the resolver without holding the lock since that can take
+        // a while for large schemas
+        let resolver = Arc::new(Self::new(logger, schema));
+        RESOLVERS
+            .lock()
+            .unwrap()
+            .insert(schema.id.clone(), resolver.clone());
+        resolver
+    }
+
+    /// The queryable object for the type called `name`, or `None` if the
+    /// schema has no such type
+    fn named_type_object(&self, name: &str) -> Option<q::Value> {
+        if let Some(object) = self.types.objects.read().unwrap().get(name) {
+            return Some(object.clone());
+        }
+        let typedef = match self
+            .types
+            .positions
+            .get(name)
+            .map(|pos| &self.schema.document.definitions[*pos])
+        {
+            Some(s::Definition::TypeDefinition(typedef)) => typedef,
+            _ => return None,
+        };
+        let mut objects = self.types.objects.write().unwrap();
+        Some(type_definition_object(&self.schema, &mut objects, typedef))
+    }
+
+    /// The queryable objects for all types in the schema, ordered by name
+    fn all_type_objects(&self) -> Vec<q::Value> {
+        {
+            let mut objects = self.types.objects.write().unwrap();
+            if objects.len() < self.types.positions.len() {
+                for typedef in sast::get_type_definitions(&self.schema.document) {
+                    type_definition_object(&self.schema, &mut objects, typedef);
+                }
+            }
+        }
+        self.types
+            .objects
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
     fn schema_object(&self) -> q::Value {
         object! {
-            queryType:
-                self.type_objects
-                    .get(&String::from("Query"))
-                    .cloned(),
-            subscriptionType:
-                self.type_objects
-                    .get(&String::from("Subscription"))
-                    .cloned(),
+            queryType: self.named_type_object("Query"),
+            subscriptionType: self.named_type_object("Subscription"),
             mutationType: q::Value::Null,
-            types: self.type_objects.values().cloned().collect::<Vec<_>>(),
+            types: self.all_type_objects(),
             directives: self.directives.clone(),
         }
     }
@@ -341,13 +416,13 @@ impl<'a> IntrospectionResolver<'a> {
             q::Value::String(s) => Some(s),
             _ => None,
         }
-        .and_then(|name| self.type_objects.get(name).cloned())
+        .and_then(|name| self.named_type_object(name))
         .unwrap_or(q::Value::Null)
     }
 }
 
 /// A GraphQL resolver that can resolve entities, enum values, scalar types and interfaces/unions.
-impl<'a> Resolver for IntrospectionResolver<'a> {
+impl Resolver for IntrospectionResolver {
     fn prefetch(
         &self,
         _: &ExecutionContext<Self>,
@@ -362,9 +437,29 @@ impl<'a> Resolver for IntrospectionResolver<'a> {
         field: &q::Field,
         _field_definition: &s::Field,
         _object_type: ObjectOrInterface<'_>,
-        _arguments: &HashMap<&q::Name, q::Value>,
+        arguments: &HashMap<&q::Name, q::Value>,
     ) -> Result<q::Value, QueryExecutionError> {
         match field.name.as_str() {
+            "fields" | "enumValues" => {
+                let include_deprecated = match arguments.get(&String::from("includeDeprecated")) {
+                    Some(q::Value::Boolean(include)) => *include,
+                    _ => false,
+                };
+                match prefetched_objects {
+                    Some(q::Value::List(objects)) if !include_deprecated => Ok(q::Value::List(
+                        objects
+                            .into_iter()
+                            .filter(|object| match object {
+                                q::Value::Object(map) => {
+                                    map.get("isDeprecated") != Some(&q::Value::Boolean(true))
+                                }
+                                _ => true,
+                            })
+                            .collect(),
+                    )),
+                    objects => Ok(objects.unwrap_or(q::Value::Null)),
+                }
+            }
             "possibleTypes" => {
                 let type_names = match prefetched_objects {
                     Some(q::Value::List(type_names)) => Some(type_names),
@@ -380,7 +475,7 @@ impl<'a> Resolver for IntrospectionResolver<'a> {
                                 q::Value::String(ref type_name) => Some(type_name),
                                 _ => None,
                             })
-                            .filter_map(|type_name| self.type_objects.get(type_name).cloned())
+                            .filter_map(|type_name| self.named_type_object(type_name))
                             .collect(),
                     ))
                 } else {
@@ -411,11 +506,9 @@ impl<'a> Resolver for IntrospectionResolver<'a> {
                 self.type_object(name)
             }
             "type" | "ofType" => match prefetched_object {
-                Some(q::Value::String(type_name)) => self
-                    .type_objects
-                    .get(&type_name)
-                    .cloned()
-                    .unwrap_or(q::Value::Null),
+                Some(q::Value::String(type_name)) => {
+                    self.named_type_object(&type_name).unwrap_or(q::Value::Null)
+                }
                 Some(v) => v,
                 None => q::Value::Null,
             },
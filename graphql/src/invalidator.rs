@@ -0,0 +1,184 @@
+//! Remove cached query responses when the blocks they are for are reverted.
+//! For each deployment whose queries are cached, we listen to the changes
+//! to its entities. When a deployment reverts a block, the store sends the
+//! changes that undo that block after the deployment has already moved
+//! back, and we drop the responses for later blocks that might have read
+//! the types of the entities that changed.
+//!
+//! The cached hashes of blocks by number are forgotten for all blocks after
+//! the block that a deployment reverted to on the network of the deployment.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use graph::prelude::{
+    debug, futures03::StreamExt, o, warn, Error, Logger, Store, Stream01CompatExt,
+    SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphDeploymentStore, TypedEntity,
+};
+
+use crate::execution::{caches_deployment, invalidate_query_cache};
+use crate::schema::ast as sast;
+use crate::store::block_cache::{caches_block_hashes, invalidate_block_hashes};
+
+pub struct QueryCacheInvalidator<S> {
+    logger: Logger,
+    store: Arc<S>,
+}
+
+impl<S> QueryCacheInvalidator<S>
+where
+    S: Store + SubgraphDeploymentStore,
+{
+    pub fn new(logger: &Logger, store: Arc<S>) -> Self {
+        QueryCacheInvalidator {
+            logger: logger.new(o!("component" => "QueryCacheInvalidator")),
+            store,
+        }
+    }
+
+    /// Start listening to the entity changes of each deployment whose
+    /// queries are cached once that deployment processes a block, and to
+    /// the block pointers of deployments on networks with cached block
+    /// hashes
+    pub fn start(self) {
+        let mut events = self
+            .store
+            .subscribe(vec![SubgraphDeploymentEntity::subgraph_entity_pair()])
+            .compat();
+
+        graph::spawn(async move {
+            let mut watched = HashSet::new();
+            let mut latest_blocks = HashMap::new();
+            while let Some(Ok(event)) = events.next().await {
+                for change in &event.changes {
+                    if change.entity_type != SubgraphDeploymentEntity::TYPENAME {
+                        continue;
+                    }
+                    let deployment = match SubgraphDeploymentId::new(change.entity_id.clone()) {
+                        Ok(deployment) => deployment,
+                        Err(()) => continue,
+                    };
+                    self.forget_reverted_blocks(&mut latest_blocks, &deployment)
+                        .await;
+                    if watched.contains(&deployment) || !caches_deployment(&deployment) {
+                        continue;
+                    }
+                    match self.store.input_schema(&deployment) {
+                        Ok(schema) => {
+                            let entity_types = sast::get_object_type_definitions(&schema.document)
+                                .into_iter()
+                                .map(|object_type| object_type.name.clone())
+                                .collect();
+                            watch(&self.logger, self.store.clone(), &deployment, entity_types);
+                            watched.insert(deployment);
+                        }
+                        Err(e) => debug!(self.logger, "Failed to get the schema of deployment";
+                                         "subgraph_id" => deployment.as_str(),
+                                         "error" => e.to_string()),
+                    }
+                }
+            }
+            warn!(
+                self.logger,
+                "Stopped invalidating the query cache since the store event stream ended"
+            );
+        });
+    }
+
+    /// Forget the cached hashes of the blocks after the latest block of
+    /// `deployment` if that is lower than the one in `latest_blocks`, i.e.,
+    /// if the deployment reverted blocks since it last changed
+    async fn forget_reverted_blocks(
+        &self,
+        latest_blocks: &mut HashMap<SubgraphDeploymentId, u64>,
+        deployment: &SubgraphDeploymentId,
+    ) {
+        let latest = {
+            let store = self.store.clone();
+            let deployment = deployment.clone();
+            graph::spawn_blocking_async_allow_panic(move || -> Result<_, Error> {
+                let network = match store.network_name(&deployment)? {
+                    Some(network) if caches_block_hashes(&network) => network,
+                    _ => return Ok(None),
+                };
+                let number = store.block_ptr(deployment)?.map_or(0, |ptr| ptr.number);
+                Ok(Some((network, number)))
+            })
+            .await
+        };
+        match latest {
+            Ok(Some((network, number))) => match latest_blocks.insert(deployment.clone(), number) {
+                Some(previous) if number < previous => {
+                    let forgotten = invalidate_block_hashes(&network, number);
+                    if forgotten > 0 {
+                        debug!(self.logger, "Forgot cached hashes of reverted blocks";
+                               "subgraph_id" => deployment.as_str(),
+                               "network" => &network,
+                               "block" => number,
+                               "hashes" => forgotten);
+                    }
+                }
+                _ => {}
+            },
+            // Nothing to forget for networks without cached block hashes
+            Ok(None) => {
+                latest_blocks.remove(deployment);
+            }
+            Err(e) => debug!(self.logger, "Failed to get the block pointer of deployment";
+                             "subgraph_id" => deployment.as_str(),
+                             "error" => e.to_string()),
+        }
+    }
+}
+
+/// Invalidate cached responses for `deployment` whenever it changes
+/// entities of `entity_types` in blocks that it has already moved past.
+/// That only happens when the deployment reverts blocks
+fn watch<S: Store>(
+    logger: &Logger,
+    store: Arc<S>,
+    deployment: &SubgraphDeploymentId,
+    entity_types: Vec<String>,
+) {
+    let logger = logger.new(o!("subgraph_id" => deployment.to_string()));
+    let deployment = deployment.clone();
+    let mut events = store
+        .subscribe(
+            entity_types
+                .into_iter()
+                .map(|entity_type| (deployment.clone(), entity_type))
+                .collect(),
+        )
+        .compat();
+
+    graph::spawn(async move {
+        while let Some(Ok(event)) = events.next().await {
+            let changed: HashSet<_> = event
+                .changes
+                .iter()
+                .filter(|change| change.subgraph_id == deployment)
+                .map(|change| change.entity_type.clone())
+                .collect();
+            let block_ptr = {
+                let store = store.clone();
+                let deployment = deployment.clone();
+                graph::spawn_blocking_async_allow_panic(move || store.block_ptr(deployment)).await
+            };
+            let block = match block_ptr {
+                Ok(Some(block_ptr)) => block_ptr.number,
+                Ok(None) => 0,
+                Err(e) => {
+                    debug!(logger, "Failed to get the block pointer of deployment";
+                           "error" => e.to_string());
+                    continue;
+                }
+            };
+            let removed = invalidate_query_cache(&deployment, block, &changed);
+            if removed > 0 {
+                debug!(logger, "Removed cached responses for reverted blocks";
+                       "block" => block,
+                       "responses" => removed);
+            }
+        }
+    });
+}
@@ -26,9 +26,23 @@ mod store;
 /// The external interface for actually running queries
 mod runner;
 
+/// Running queries for new blocks to warm the query cache
+mod warmer;
+
+/// Removing cached query responses and block hashes for reverted blocks
+mod invalidator;
+
+/// Splitting queries against composite deployments and merging their results
+mod composite;
+
 /// Prelude that exports the most important traits and types.
 pub mod prelude {
-    pub use super::execution::{ExecutionContext, ObjectOrInterface, Query, Resolver};
+    pub use super::execution::{
+        export_herd_metrics, load_query_cache, query_cache_settings, save_query_cache,
+        set_query_cache_settings, DeprecatedFieldMetrics, DirectiveRegistry, ExecutionContext,
+        ExecutionHook, ExecutionHooks, FieldDirective, FieldUsageRecorder, HookField,
+        ObjectOrInterface, Query, QueryCacheSettings, Resolver,
+    };
     pub use super::introspection::{introspection_schema, IntrospectionResolver};
     pub use super::query::{execute_query, ext::BlockConstraint, QueryExecutionOptions};
     pub use super::schema::{api_schema, ast::validate_entity, APISchemaError};
@@ -36,8 +50,13 @@ pub mod prelude {
     pub use super::subscription::{execute_subscription, SubscriptionExecutionOptions};
     pub use super::values::{object_value, IntoValue, MaybeCoercible};
 
+    pub use super::composite::{
+        merge_composite_results, split_composite_query, CompositePart, CompositeResult,
+    };
     pub use super::graphql_parser::{query::Name, schema::ObjectType};
-    pub use super::runner::GraphQlRunner;
+    pub use super::invalidator::QueryCacheInvalidator;
+    pub use super::runner::{set_query_limits, GraphQlRunner, QueryLimits};
+    pub use super::warmer::{read_warm_queries, CacheWarmer, WarmQuery};
 
     pub use crate::object;
 }
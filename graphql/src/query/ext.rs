@@ -36,6 +36,10 @@ pub enum BlockConstraint {
     Hash(H256),
     Number(BlockNumber),
     Latest,
+    /// The latest block, but only after the subgraph has processed the
+    /// chain head block of its network, or after waiting for that for a
+    /// while
+    Head,
 }
 
 impl Default for BlockConstraint {
@@ -72,9 +76,17 @@ impl FieldExt for q::Field {
             if let q::Value::Object(map) = value {
                 let hash = map.get("hash");
                 let number = map.get("number");
-                if map.len() != 1 || (hash.is_none() && number.is_none()) {
+                let latest = map.get("latest");
+                if map.len() != 1 || (hash.is_none() && number.is_none() && latest.is_none()) {
                     return Err(invalid_argument("block", self, value));
                 }
+                if let Some(latest) = latest {
+                    return match latest {
+                        q::Value::Boolean(true) => Ok(BlockConstraint::Head),
+                        q::Value::Boolean(false) => Ok(BlockConstraint::Latest),
+                        _ => Err(invalid_argument("block.latest", self, latest)),
+                    };
+                }
                 match (hash, number) {
                     (Some(hash), _) => TryFromValue::try_from_value(hash)
                         .map_err(|_| invalid_argument("block.hash", self, value))
@@ -1,9 +1,9 @@
 use graph::prelude::{info, o, EthereumBlockPointer, Logger, QueryExecutionError};
+use graph::trace;
 use graphql_parser::query as q;
 use std::collections::BTreeMap;
 use std::sync::{atomic::AtomicBool, Arc};
 use std::time::Instant;
-use uuid::Uuid;
 
 use crate::execution::*;
 use crate::schema::ast as sast;
@@ -33,6 +33,9 @@ where
 
     /// Maximum value for the `first` argument.
     pub max_first: u32,
+
+    /// The hooks to run around each field.
+    pub hooks: Arc<ExecutionHooks>,
 }
 
 /// Executes a query and returns a result.
@@ -45,11 +48,30 @@ pub fn execute_query<R>(
 where
     R: Resolver,
 {
-    let query_id = Uuid::new_v4().to_string();
+    execute_query_with_cache_status(query, selection_set, block_ptr, None, options, &mut false)
+}
+
+/// Like `execute_query`, but also sets `cached` to whether the result came
+/// from the query cache. `head_network` is the network of the deployment
+/// if `block_ptr` is the latest block of the deployment
+pub(crate) fn execute_query_with_cache_status<R>(
+    query: Arc<Query>,
+    selection_set: Option<&q::SelectionSet>,
+    block_ptr: Option<EthereumBlockPointer>,
+    head_network: Option<&str>,
+    options: QueryExecutionOptions<R>,
+    cached: &mut bool,
+) -> Result<BTreeMap<String, q::Value>, Vec<QueryExecutionError>>
+where
+    R: Resolver,
+{
+    *cached = false;
     let query_logger = options.logger.new(o!(
         "subgraph_id" => (*query.schema.id).clone(),
-        "query_id" => query_id
+        "query_id" => query.query_id.clone()
     ));
+    // The SQL for this query is marked with its id
+    let _query = trace::enter_query(&query.query_id, query.shape_hash);
 
     // Create a fresh execution context
     let ctx = ExecutionContext {
@@ -58,6 +80,7 @@ where
         query: query.clone(),
         deadline: options.deadline,
         max_first: options.max_first,
+        hooks: options.hooks,
         cached: AtomicBool::new(true),
     };
 
@@ -76,7 +99,8 @@ where
 
     // Execute top-level `query { ... }` and `{ ... }` expressions.
     let start = Instant::now();
-    let result = execute_root_selection_set(&ctx, selection_set, query_type, block_ptr);
+    let result =
+        execute_root_selection_set(&ctx, selection_set, query_type, block_ptr, head_network);
     if *graph::log::LOG_GQL_TIMING {
         info!(
             query_logger,
@@ -89,5 +113,6 @@ where
             "complexity" => &query.complexity
         );
     }
+    *cached = ctx.cached.load(std::sync::atomic::Ordering::SeqCst);
     result.to_inner()
 }
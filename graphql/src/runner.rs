@@ -1,21 +1,23 @@
 use futures01::future;
 use graphql_parser::query as q;
 use std::collections::{BTreeMap, HashMap};
-use std::env;
-use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
 use crate::prelude::{
-    object, object_value, QueryExecutionOptions, StoreResolver, SubscriptionExecutionOptions,
+    object, object_value, BlockConstraint, ExecutionHooks, QueryExecutionOptions, StoreResolver,
+    SubscriptionExecutionOptions,
 };
-use crate::query::{execute_query, shape_hash::shape_hash};
+use crate::query::{execute_query_with_cache_status, shape_hash::shape_hash};
 use crate::subscription::execute_prepared_subscription;
+use graph::data::query::blocklist;
+use graph::log::audit::{CacheStatus, QueryAuditLog, QueryAuditRecord};
 use graph::prelude::{
     o, EthereumBlockPointer, GraphQlRunner as GraphQlRunnerTrait, Logger, Query,
     QueryExecutionError, QueryResult, QueryResultFuture, Store, StoreError, SubgraphDeploymentId,
     SubgraphDeploymentStore, Subscription, SubscriptionError, SubscriptionResultFuture,
 };
+use graph::util::env::env_var;
 
 use lazy_static::lazy_static;
 
@@ -24,29 +26,47 @@ pub struct GraphQlRunner<S> {
     logger: Logger,
     store: Arc<S>,
     expensive: HashMap<u64, Arc<q::Document>>,
+    audit_log: Option<Arc<QueryAuditLog>>,
+    hooks: Arc<ExecutionHooks>,
+}
+
+/// Limits for GraphQL queries. They are initialized from the environment
+/// and can be changed with `set_query_limits`, e.g., to the settings from
+/// the configuration file
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryLimits {
+    /// `GRAPH_GRAPHQL_QUERY_TIMEOUT`, which is given in seconds
+    pub timeout: Option<Duration>,
+    /// `GRAPH_GRAPHQL_MAX_COMPLEXITY`
+    pub max_complexity: Option<u64>,
+    /// `GRAPH_GRAPHQL_MAX_DEPTH`
+    pub max_depth: u8,
+    /// `GRAPH_GRAPHQL_MAX_FIRST`
+    pub max_first: u32,
+}
+
+impl QueryLimits {
+    pub fn from_env() -> Self {
+        QueryLimits {
+            timeout: env_var("GRAPH_GRAPHQL_QUERY_TIMEOUT").map(Duration::from_secs),
+            max_complexity: env_var("GRAPH_GRAPHQL_MAX_COMPLEXITY"),
+            max_depth: env_var("GRAPH_GRAPHQL_MAX_DEPTH").unwrap_or(u8::max_value()),
+            max_first: env_var("GRAPH_GRAPHQL_MAX_FIRST").unwrap_or(1000),
+        }
+    }
 }
 
 lazy_static! {
-    static ref GRAPHQL_QUERY_TIMEOUT: Option<Duration> = env::var("GRAPH_GRAPHQL_QUERY_TIMEOUT")
-        .ok()
-        .map(|s| Duration::from_secs(
-            u64::from_str(&s)
-                .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_QUERY_TIMEOUT"))
-        ));
-    static ref GRAPHQL_MAX_COMPLEXITY: Option<u64> = env::var("GRAPH_GRAPHQL_MAX_COMPLEXITY")
-        .ok()
-        .map(|s| u64::from_str(&s)
-            .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_MAX_COMPLEXITY")));
-    static ref GRAPHQL_MAX_DEPTH: u8 = env::var("GRAPH_GRAPHQL_MAX_DEPTH")
-        .ok()
-        .map(|s| u8::from_str(&s)
-            .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_MAX_DEPTH")))
-        .unwrap_or(u8::max_value());
-    static ref GRAPHQL_MAX_FIRST: u32 = env::var("GRAPH_GRAPHQL_MAX_FIRST")
-        .ok()
-        .map(|s| u32::from_str(&s)
-            .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_MAX_FIRST")))
-        .unwrap_or(1000);
+    static ref QUERY_LIMITS: RwLock<QueryLimits> = RwLock::new(QueryLimits::from_env());
+}
+
+/// Change the limits for queries that start after this call
+pub fn set_query_limits(limits: QueryLimits) {
+    *QUERY_LIMITS.write().unwrap() = limits;
+}
+
+fn query_limits() -> QueryLimits {
+    QUERY_LIMITS.read().unwrap().clone()
 }
 
 impl<S> GraphQlRunner<S>
@@ -63,6 +83,25 @@ where
             logger: logger.new(o!("component" => "GraphQlRunner")),
             store,
             expensive,
+            audit_log: None,
+            hooks: Arc::new(ExecutionHooks::default()),
+        }
+    }
+
+    /// Record every query that this runner runs in `audit_log`
+    pub fn with_audit_log(self, audit_log: Arc<QueryAuditLog>) -> Self {
+        GraphQlRunner {
+            audit_log: Some(audit_log),
+            ..self
+        }
+    }
+
+    /// Run `hooks` around the fields of every query and subscription that
+    /// this runner executes
+    pub fn with_hooks(self, hooks: ExecutionHooks) -> Self {
+        GraphQlRunner {
+            hooks: Arc::new(hooks),
+            ..self
         }
     }
 
@@ -116,28 +155,59 @@ where
         max_depth: Option<u8>,
         max_first: Option<u32>,
     ) -> Result<QueryResult, Vec<QueryExecutionError>> {
-        let max_depth = max_depth.unwrap_or(*GRAPHQL_MAX_DEPTH);
+        self.execute_with_cache_status(query, max_complexity, max_depth, max_first, &mut false)
+    }
+
+    /// Like `execute`, but also sets `cached` to whether the whole result
+    /// came from the query cache
+    fn execute_with_cache_status(
+        &self,
+        query: Query,
+        max_complexity: Option<u64>,
+        max_depth: Option<u8>,
+        max_first: Option<u32>,
+        cached: &mut bool,
+    ) -> Result<QueryResult, Vec<QueryExecutionError>> {
+        *cached = false;
+        let limits = query_limits();
+        let max_depth = max_depth.unwrap_or(limits.max_depth);
         let query = crate::execution::Query::new(query, max_complexity, max_depth)?;
         let mut values = BTreeMap::new();
         let mut errors = Vec::new();
+        let mut all_cached = true;
+        let network = self
+            .store
+            .network_name(&query.schema.id)
+            .map_err(|e| vec![QueryExecutionError::StoreError(e.into())])?;
         for (bc, selection_set) in query.block_constraint()? {
+            // Only responses for the latest block expire from the cache
+            let head_network = match bc {
+                BlockConstraint::Latest | BlockConstraint::Head => network.as_deref(),
+                BlockConstraint::Number(_) | BlockConstraint::Hash(_) => None,
+            };
             let (resolver, block_ptr) =
                 StoreResolver::at_block(&self.logger, self.store.clone(), bc, &query.schema.id)?;
-            match execute_query(
+            let mut block_cached = false;
+            match execute_query_with_cache_status(
                 query.clone(),
                 Some(&selection_set),
                 Some(block_ptr),
+                head_network,
                 QueryExecutionOptions {
                     logger: self.logger.clone(),
                     resolver,
-                    deadline: GRAPHQL_QUERY_TIMEOUT.map(|t| Instant::now() + t),
-                    max_first: max_first.unwrap_or(*GRAPHQL_MAX_FIRST),
+                    deadline: limits.timeout.map(|t| Instant::now() + t),
+                    max_first: max_first.unwrap_or(limits.max_first),
+                    hooks: self.hooks.clone(),
                 },
+                &mut block_cached,
             ) {
                 Err(errs) => errors.extend(errs),
                 Ok(mut vals) => values.append(&mut vals),
             }
+            all_cached &= block_cached;
         }
+        *cached = all_cached;
         if !errors.is_empty() {
             Err(errors)
         } else {
@@ -146,8 +216,14 @@ where
     }
 
     pub fn check_too_expensive(&self, query: &Query) -> Result<(), Vec<QueryExecutionError>> {
-        if self.expensive.contains_key(&shape_hash(&query.document)) {
+        let hash = shape_hash(&query.document);
+        if self.expensive.contains_key(&hash) {
             Err(vec![QueryExecutionError::TooExpensive])
+        } else if let Some(reason) = blocklist::check(hash) {
+            Err(vec![QueryExecutionError::Blocked(
+                blocklist::format_hash(hash),
+                reason,
+            )])
         } else {
             Ok(())
         }
@@ -159,11 +235,12 @@ where
     S: Store + SubgraphDeploymentStore,
 {
     fn run_query(&self, query: Query) -> QueryResultFuture {
+        let limits = query_limits();
         self.run_query_with_complexity(
             query,
-            *GRAPHQL_MAX_COMPLEXITY,
-            Some(*GRAPHQL_MAX_DEPTH),
-            Some(*GRAPHQL_MAX_FIRST),
+            limits.max_complexity,
+            Some(limits.max_depth),
+            Some(limits.max_first),
         )
     }
 
@@ -174,10 +251,32 @@ where
         max_depth: Option<u8>,
         max_first: Option<u32>,
     ) -> QueryResultFuture {
-        let result = self
+        let audit = self
+            .audit_log
+            .as_ref()
+            .map(|_| QueryAuditRecord::start(&query, shape_hash(&query.document)));
+        let mut cached = false;
+        let mut result = self
             .check_too_expensive(&query)
-            .and_then(|_| self.execute(query, max_complexity, max_depth, max_first))
+            .and_then(|_| {
+                self.execute_with_cache_status(
+                    query,
+                    max_complexity,
+                    max_depth,
+                    max_first,
+                    &mut cached,
+                )
+            })
             .unwrap_or_else(|e| QueryResult::from(e));
+        result.cached = cached;
+        if let (Some(audit_log), Some(audit)) = (&self.audit_log, audit) {
+            let cache = if cached {
+                CacheStatus::Hit
+            } else {
+                CacheStatus::Miss
+            };
+            audit_log.record(audit.finish(&result, cache));
+        }
         Box::new(future::ok(result))
     }
 
@@ -187,10 +286,12 @@ where
             return Box::new(future::result(Err(err)));
         }
 
+        let subscription_interval = subscription.interval.unwrap_or_default();
+        let limits = query_limits();
         let query = match crate::execution::Query::new(
             subscription.query,
-            *GRAPHQL_MAX_COMPLEXITY,
-            *GRAPHQL_MAX_DEPTH,
+            limits.max_complexity,
+            limits.max_depth,
         ) {
             Ok(query) => query,
             Err(e) => return Box::new(future::err(e.into())),
@@ -200,11 +301,13 @@ where
             query,
             SubscriptionExecutionOptions {
                 logger: self.logger.clone(),
-                resolver: StoreResolver::new(&self.logger, self.store.clone()),
-                timeout: GRAPHQL_QUERY_TIMEOUT.clone(),
-                max_complexity: *GRAPHQL_MAX_COMPLEXITY,
-                max_depth: *GRAPHQL_MAX_DEPTH,
-                max_first: *GRAPHQL_MAX_FIRST,
+                resolver: StoreResolver::new(&self.logger, self.store.clone())
+                    .with_subscription_interval(subscription_interval),
+                timeout: limits.timeout,
+                max_complexity: limits.max_complexity,
+                max_depth: limits.max_depth,
+                max_first: limits.max_first,
+                hooks: self.hooks.clone(),
             },
         );
 
@@ -4,7 +4,10 @@ use inflector::Inflector;
 
 use crate::schema::ast;
 
-use graph::data::graphql::ext::{DirectiveExt, DocumentExt, ValueExt};
+use graph::data::graphql::ext::{
+    DirectiveExt, DocumentExt, TypeExt, ValueExt, DEFAULT_DEPRECATION_REASON,
+};
+use graph::data::store::GEO_POINT_SCALAR;
 use graph::prelude::*;
 
 #[derive(Fail, Debug)]
@@ -17,6 +20,21 @@ pub enum APISchemaError {
 
 const BLOCK_HEIGHT: &str = "Block_height";
 
+/// The type of the value of `<field>_within` filters on `GeoPoint` fields
+pub(crate) const GEO_POINT_WITHIN: &str = "GeoPoint_within";
+
+/// The suffix of the `Query` fields that group entities
+pub(crate) const GROUP_BY_FIELD_SUFFIX: &str = "_groupBy";
+
+/// The suffix of the types of the groups of entities
+pub(crate) const GROUP_TYPE_SUFFIX: &str = "_group";
+
+/// The `Query` field with the metadata of the deployment
+pub(crate) const META_FIELD: &str = "_meta";
+
+/// The type of the `_meta` field
+pub(crate) const META_TYPE: &str = "_Meta_";
+
 /// Derives a full-fledged GraphQL API schema from an input schema.
 ///
 /// The input schema should only have type/enum/interface/union definitions
@@ -34,6 +52,8 @@ pub fn api_schema(input_schema: &Document) -> Result<Document, APISchemaError> {
     add_builtin_scalar_types(&mut schema)?;
     add_order_direction_enum(&mut schema);
     add_block_height_type(&mut schema);
+    add_geo_point_within_type(&mut schema);
+    add_meta_type(&mut schema);
     add_types_for_object_types(&mut schema, &object_types)?;
     add_types_for_interface_types(&mut schema, &interface_types)?;
     add_field_arguments(&mut schema, &input_schema)?;
@@ -52,6 +72,7 @@ fn add_builtin_scalar_types(schema: &mut Document) -> Result<(), APISchemaError>
         "String",
         "Bytes",
         "BigInt",
+        GEO_POINT_SCALAR,
     ]
     .iter()
     {
@@ -112,9 +133,28 @@ fn add_directives(schema: &mut Document) {
         locations: vec![DirectiveLocation::Object],
     });
 
+    let deprecated = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: "deprecated".to_owned(),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "reason".to_owned(),
+            value_type: Type::NamedType("String".to_owned()),
+            default_value: Some(Value::String(DEFAULT_DEPRECATION_REASON.to_owned())),
+            directives: vec![],
+        }],
+        locations: vec![
+            DirectiveLocation::FieldDefinition,
+            DirectiveLocation::EnumValue,
+        ],
+    });
+
     schema.definitions.push(entity);
     schema.definitions.push(derived_from);
     schema.definitions.push(subgraph_id);
+    schema.definitions.push(deprecated);
 }
 
 /// Adds a global `OrderDirection` type to the schema.
@@ -163,12 +203,107 @@ fn add_block_height_type(schema: &mut Document) {
                 default_value: None,
                 directives: vec![],
             },
+            InputValue {
+                position: Pos::default(),
+                description: None,
+                name: "latest".to_owned(),
+                value_type: Type::NamedType("Boolean".to_owned()),
+                default_value: None,
+                directives: vec![],
+            },
+        ],
+    });
+    let def = Definition::TypeDefinition(typedef);
+    schema.definitions.push(def);
+}
+
+/// Adds a global `GeoPoint_within` type to the schema. Filters of the form
+/// `<field>_within: { of: "<lat>,<lng>", radius: <meters> }` select the
+/// entities whose `GeoPoint` field is at most `radius` meters from `of`
+fn add_geo_point_within_type(schema: &mut Document) {
+    let typedef = TypeDefinition::InputObject(InputObjectType {
+        position: Pos::default(),
+        description: None,
+        name: GEO_POINT_WITHIN.to_string(),
+        directives: vec![],
+        fields: vec![
+            InputValue {
+                position: Pos::default(),
+                description: None,
+                name: "of".to_owned(),
+                value_type: Type::NonNullType(Box::new(Type::NamedType(
+                    GEO_POINT_SCALAR.to_owned(),
+                ))),
+                default_value: None,
+                directives: vec![],
+            },
+            InputValue {
+                position: Pos::default(),
+                description: Some("The distance from `of` in meters".to_owned()),
+                name: "radius".to_owned(),
+                value_type: Type::NonNullType(Box::new(Type::NamedType("BigDecimal".to_owned()))),
+                default_value: None,
+                directives: vec![],
+            },
+        ],
+    });
+    let def = Definition::TypeDefinition(typedef);
+    schema.definitions.push(def);
+}
+
+/// Adds the global `_Meta_` type, the type of the `_meta` field, to the
+/// schema
+fn add_meta_type(schema: &mut Document) {
+    let field = |name: &str, description: &str, field_type: Type| Field {
+        position: Pos::default(),
+        description: Some(description.to_owned()),
+        name: name.to_owned(),
+        arguments: vec![],
+        field_type,
+        directives: vec![],
+    };
+    let int = || Type::NamedType("Int".to_owned());
+    let typedef = TypeDefinition::Object(ObjectType {
+        position: Pos::default(),
+        description: Some("The blocks that the data of the subgraph covers".to_owned()),
+        name: META_TYPE.to_owned(),
+        implements_interfaces: vec![],
+        directives: vec![],
+        fields: vec![
+            field(
+                "startBlock",
+                "The lowest start block of the data sources of the subgraph",
+                Type::NonNullType(Box::new(int())),
+            ),
+            field(
+                "earliestBlock",
+                "The first block that the subgraph processed",
+                Type::NonNullType(Box::new(int())),
+            ),
+            field(
+                "lastHealthyBlock",
+                "The latest block that the subgraph processed without errors, \
+                 or `null` if it has not processed any",
+                int(),
+            ),
         ],
     });
     let def = Definition::TypeDefinition(typedef);
     schema.definitions.push(def);
 }
 
+/// The `_meta` field of the `Query` type
+fn meta_query_field() -> Field {
+    Field {
+        position: Pos::default(),
+        description: Some("Metadata about the deployment of the subgraph".to_owned()),
+        name: META_FIELD.to_owned(),
+        arguments: vec![],
+        field_type: Type::NonNullType(Box::new(Type::NamedType(META_TYPE.to_owned()))),
+        directives: vec![],
+    }
+}
+
 fn add_types_for_object_types(
     schema: &mut Document,
     object_types: &Vec<&ObjectType>,
@@ -176,6 +311,7 @@ fn add_types_for_object_types(
     for object_type in object_types {
         add_order_by_type(schema, &object_type.name, &object_type.fields)?;
         add_filter_type(schema, &object_type.name, &object_type.fields)?;
+        add_group_types(schema, &object_type.name, &object_type.fields)?;
     }
     Ok(())
 }
@@ -207,8 +343,10 @@ fn add_order_by_type(
                 description: None,
                 name: type_name,
                 directives: vec![],
+                // Points have no order, so entities can not be sorted by them
                 values: fields
                     .iter()
+                    .filter(|field| field.field_type.get_base_type() != GEO_POINT_SCALAR)
                     .map(|field| &field.name)
                     .map(|name| EnumValue {
                         position: Pos::default(),
@@ -260,6 +398,184 @@ fn add_filter_type(
     Ok(())
 }
 
+/// The fields of the `<type_name>_group` type: the fields that entities can
+/// be grouped by, with the name of their type in a group, and the
+/// aggregates, with the name of their type and whether they are non-null
+struct GroupFields {
+    keys: Vec<(Name, Name)>,
+    aggregates: Vec<(Name, Name, bool)>,
+}
+
+impl GroupFields {
+    fn new(schema: &Document, fields: &[Field]) -> Self {
+        let mut keys = vec![];
+        let mut aggregates = vec![("count".to_owned(), "Int".to_owned(), true)];
+        for field in fields {
+            let type_name = match groupable_field_type(schema, field) {
+                Some(type_name) => type_name,
+                None => continue,
+            };
+            aggregates.push((
+                format!("{}_countDistinct", field.name),
+                "Int".to_owned(),
+                true,
+            ));
+            match type_name.as_str() {
+                "Int" | "BigInt" | "BigDecimal" => {
+                    let sum_type = match type_name.as_str() {
+                        "Int" => "BigInt".to_owned(),
+                        _ => type_name.clone(),
+                    };
+                    aggregates.push((format!("{}_sum", field.name), sum_type, false));
+                    aggregates.push((format!("{}_min", field.name), type_name.clone(), false));
+                    aggregates.push((format!("{}_max", field.name), type_name.clone(), false));
+                    aggregates.push((
+                        format!("{}_avg", field.name),
+                        "BigDecimal".to_owned(),
+                        false,
+                    ));
+                }
+                _ => {}
+            }
+            keys.push((field.name.clone(), type_name));
+        }
+        // A field whose name is also the name of an aggregate, e.g., a
+        // field `count`, can not be grouped by
+        keys.retain(|(name, _)| !aggregates.iter().any(|(agg, _, _)| agg == name));
+        GroupFields { keys, aggregates }
+    }
+
+    fn names(&self) -> impl Iterator<Item = &Name> {
+        self.keys
+            .iter()
+            .map(|(name, _)| name)
+            .chain(self.aggregates.iter().map(|(name, _, _)| name))
+    }
+}
+
+/// The name of the type that `field` has in a group if entities can be
+/// grouped by it. References to other entities become their `ID`
+fn groupable_field_type(schema: &Document, field: &Field) -> Option<Name> {
+    let name = match &field.field_type {
+        Type::NamedType(name) => name,
+        Type::NonNullType(inner) => match inner.as_ref() {
+            Type::NamedType(name) => name,
+            _ => return None,
+        },
+        Type::ListType(_) => return None,
+    };
+    match ast::get_named_type(schema, name)? {
+        TypeDefinition::Scalar(_) if name == GEO_POINT_SCALAR => None,
+        TypeDefinition::Scalar(_) | TypeDefinition::Enum(_) => Some(name.clone()),
+        TypeDefinition::Object(_) | TypeDefinition::Interface(_)
+            if ast::get_derived_from_directive(field).is_none() =>
+        {
+            Some("ID".to_owned())
+        }
+        _ => None,
+    }
+}
+
+fn enum_type(name: String, values: Vec<&Name>) -> Definition {
+    Definition::TypeDefinition(TypeDefinition::Enum(EnumType {
+        position: Pos::default(),
+        description: None,
+        name,
+        directives: vec![],
+        values: values
+            .into_iter()
+            .map(|name| EnumValue {
+                position: Pos::default(),
+                description: None,
+                name: name.to_owned(),
+                directives: vec![],
+            })
+            .collect(),
+    }))
+}
+
+/// Adds the `<type_name>_groupBy` and `<type_name>_groupOrderBy` enum
+/// types, the `<type_name>_having` input type and the `<type_name>_group`
+/// object type for grouping the entities of an object type
+fn add_group_types(
+    schema: &mut Document,
+    type_name: &Name,
+    fields: &[Field],
+) -> Result<(), APISchemaError> {
+    let group_fields = GroupFields::new(schema, fields);
+
+    let group_by_name = format!("{}_groupBy", type_name);
+    let order_by_name = format!("{}_groupOrderBy", type_name);
+    let having_name = format!("{}_having", type_name);
+    let group_name = format!("{}{}", type_name, GROUP_TYPE_SUFFIX);
+    for name in &[&group_by_name, &order_by_name, &having_name, &group_name] {
+        if ast::get_named_type(schema, name).is_some() {
+            return Err(APISchemaError::TypeExists(name.to_string()));
+        }
+    }
+
+    let group_by = enum_type(
+        group_by_name,
+        group_fields.keys.iter().map(|(name, _)| name).collect(),
+    );
+    let order_by = enum_type(order_by_name, group_fields.names().collect());
+
+    let having = Definition::TypeDefinition(TypeDefinition::InputObject(InputObjectType {
+        position: Pos::default(),
+        description: None,
+        name: having_name,
+        directives: vec![],
+        fields: group_fields
+            .aggregates
+            .iter()
+            .flat_map(|(name, type_name, _)| {
+                vec!["", "gt", "lt", "gte", "lte"]
+                    .into_iter()
+                    .map(move |op| input_value(name, op, Type::NamedType(type_name.clone())))
+            })
+            .collect(),
+    }));
+
+    let keys = group_fields
+        .keys
+        .iter()
+        .map(|(name, type_name)| (name, Type::NamedType(type_name.clone())));
+    let aggregates = group_fields
+        .aggregates
+        .iter()
+        .map(|(name, type_name, non_null)| {
+            let ty = Type::NamedType(type_name.clone());
+            if *non_null {
+                (name, Type::NonNullType(Box::new(ty)))
+            } else {
+                (name, ty)
+            }
+        });
+    let group = Definition::TypeDefinition(TypeDefinition::Object(ObjectType {
+        position: Pos::default(),
+        description: None,
+        name: group_name,
+        implements_interfaces: vec![],
+        directives: vec![],
+        fields: keys
+            .chain(aggregates)
+            .map(|(name, field_type)| Field {
+                position: Pos::default(),
+                description: None,
+                name: name.to_owned(),
+                arguments: vec![],
+                field_type,
+                directives: vec![],
+            })
+            .collect(),
+    }));
+
+    schema
+        .definitions
+        .extend(vec![group_by, order_by, having, group]);
+    Ok(())
+}
+
 /// Generates `*_filter` input values for the given set of fields.
 fn field_input_values(
     schema: &Document,
@@ -327,6 +643,7 @@ fn field_scalar_filter_input_values(
         "Boolean" => vec!["", "not", "in", "not_in"],
         "Bytes" => vec!["", "not", "in", "not_in", "contains", "not_contains"],
         "BigDecimal" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
+        GEO_POINT_SCALAR => vec!["", "not", "within"],
         "ID" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
         "Int" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
         "List" => vec!["", "not", "in", "not_in", "contains", "not_contains"],
@@ -353,6 +670,7 @@ fn field_scalar_filter_input_values(
         let field_type = Type::NamedType(field_type.name.to_owned());
         let value_type = match filter_type {
             "in" | "not_in" => Type::ListType(Box::new(Type::NonNullType(Box::new(field_type)))),
+            "within" => Type::NamedType(GEO_POINT_WITHIN.to_owned()),
             _ => field_type,
         };
         input_value(&field.name, filter_type, value_type)
@@ -402,6 +720,8 @@ fn field_list_filter_input_values(
                     Type::NamedType("String".into())
                 }
             }
+            // Lists of points can not be compared with other lists
+            TypeDefinition::Scalar(ref t) if t.name == GEO_POINT_SCALAR => return None,
             TypeDefinition::Scalar(ref t) => Type::NamedType(t.name.to_owned()),
             TypeDefinition::Enum(ref t) => Type::NamedType(t.name.to_owned()),
             TypeDefinition::InputObject(_) | TypeDefinition::Union(_) => return None,
@@ -457,6 +777,11 @@ fn add_query_type(
         .map(|t| &t.name)
         .chain(interface_types.iter().map(|t| &t.name))
         .flat_map(|name| query_fields_for_type(schema, name))
+        .chain(
+            object_types
+                .iter()
+                .map(|t| group_query_field(schema, &t.name)),
+        )
         .collect::<Vec<Field>>();
     let mut fulltext_fields = schema
         .get_fulltext_directives()
@@ -464,6 +789,7 @@ fn add_query_type(
         .filter_map(|fulltext| query_field_for_fulltext(fulltext))
         .collect();
     fields.append(&mut fulltext_fields);
+    fields.push(meta_query_field());
 
     let typedef = TypeDefinition::Object(ObjectType {
         position: Pos::default(),
@@ -577,9 +903,11 @@ fn block_argument() -> InputValue {
         position: Pos::default(),
         description: Some(
             "The block at which the query should be executed. \
-             Can either be an `{ number: Int }` containing the block number \
-             or a `{ hash: Bytes }` value containing a block hash. Defaults \
-             to the latest block when omitted."
+             Can either be an `{ number: Int }` containing the block number, \
+             a `{ hash: Bytes }` value containing a block hash, or \
+             `{ latest: true }` to wait briefly for the subgraph to process \
+             the chain head block first. Defaults to the latest block when \
+             omitted."
                 .to_owned(),
         ),
         name: "block".to_string(),
@@ -627,6 +955,51 @@ fn query_fields_for_type(schema: &Document, type_name: &Name) -> Vec<Field> {
     ]
 }
 
+/// Generates the `Query` field that groups the entities of an object type,
+/// e.g. `users_groupBy`
+fn group_query_field(schema: &Document, type_name: &Name) -> Field {
+    let input_objects = ast::get_input_object_definitions(schema);
+    let mut arguments = collection_arguments_for_named_type(&input_objects, type_name);
+    // Groups are distinct by definition
+    arguments.retain(|argument| argument.name != "distinctOn");
+    for argument in arguments.iter_mut() {
+        if argument.name == "orderBy" {
+            argument.value_type = Type::NamedType(format!("{}_groupOrderBy", type_name));
+        }
+    }
+    arguments.push(input_value(
+        &"by".to_string(),
+        "",
+        Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+            Box::new(Type::NamedType(format!("{}_groupBy", type_name))),
+        ))))),
+    ));
+    arguments.push(input_value(
+        &"having".to_string(),
+        "",
+        Type::NamedType(format!("{}_having", type_name)),
+    ));
+    arguments.push(block_argument());
+
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: format!(
+            "{}{}",
+            type_name.to_plural().to_camel_case(),
+            GROUP_BY_FIELD_SUFFIX
+        ),
+        arguments,
+        field_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+            Box::new(Type::NamedType(format!(
+                "{}{}",
+                type_name, GROUP_TYPE_SUFFIX
+            ))),
+        ))))),
+        directives: vec![],
+    }
+}
+
 /// Generates arguments for collection queries of a named type (e.g. User).
 fn collection_arguments_for_named_type(
     input_objects: &[InputObjectType],
@@ -654,6 +1027,11 @@ fn collection_arguments_for_named_type(
             "",
             Type::NamedType("OrderDirection".to_string()),
         ),
+        input_value(
+            &"distinctOn".to_string(),
+            "",
+            Type::NamedType(format!("{}_orderBy", type_name)),
+        ),
     ];
 
     // Not all types have filter types, see comment in `add_filter_type`.
@@ -799,6 +1177,38 @@ mod tests {
             .expect("Root Query type is missing in API schema");
     }
 
+    #[test]
+    fn api_schema_contains_meta_field() {
+        let input_schema =
+            parse_schema("type User { id: ID! }").expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let meta = match ast::get_named_type(&schema, &"_Meta_".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("_Meta_ type is missing in derived API schema"),
+        };
+        let fields: Vec<&Name> = meta.fields.iter().map(|field| &field.name).collect();
+        assert_eq!(
+            fields,
+            [
+                &"startBlock".to_string(),
+                &"earliestBlock".to_string(),
+                &"lastHealthyBlock".to_string()
+            ]
+        );
+
+        let query_type = match ast::get_named_type(&schema, &"Query".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("Query type is missing in derived API schema"),
+        };
+        let field = ast::get_field(query_type, &"_meta".to_string())
+            .expect("\"_meta\" field is missing on Query type");
+        assert_eq!(
+            field.field_type,
+            Type::NonNullType(Box::new(Type::NamedType("_Meta_".to_string())))
+        );
+    }
+
     #[test]
     fn api_schema_contains_field_order_by_enum() {
         let input_schema = parse_schema("type User { id: ID!, name: String! }")
@@ -910,6 +1320,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn api_schema_contains_geo_point_filters() {
+        let input_schema =
+            parse_schema("type Shop { id: ID!, location: GeoPoint!, branches: [GeoPoint!] }")
+                .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let filter_type = match ast::get_named_type(&schema, &"Shop_filter".to_string()) {
+            Some(TypeDefinition::InputObject(t)) => t,
+            _ => panic!("Shop_filter type is missing in derived API schema"),
+        };
+        let within = filter_type
+            .fields
+            .iter()
+            .find(|field| field.name == "location_within")
+            .expect("location_within filter is missing");
+        assert_eq!(
+            Type::NamedType("GeoPoint_within".to_owned()),
+            within.value_type
+        );
+        assert!(filter_type
+            .fields
+            .iter()
+            .all(|field| !field.name.starts_with("branches")));
+        match ast::get_named_type(&schema, &"GeoPoint_within".to_string()) {
+            Some(TypeDefinition::InputObject(t)) => {
+                let fields: Vec<_> = t.fields.iter().map(|field| field.name.as_str()).collect();
+                assert_eq!(vec!["of", "radius"], fields);
+            }
+            _ => panic!("GeoPoint_within type is missing in derived API schema"),
+        }
+
+        // Points can not be sorted
+        let order_by = match ast::get_named_type(&schema, &"Shop_orderBy".to_string()) {
+            Some(TypeDefinition::Enum(t)) => t,
+            _ => panic!("Shop_orderBy type is missing in derived API schema"),
+        };
+        let values: Vec<&Name> = order_by.values.iter().map(|value| &value.name).collect();
+        assert_eq!(values, [&"id".to_string()]);
+    }
+
     #[test]
     fn api_schema_contains_object_fields_on_query_type() {
         let input_schema = parse_schema(
@@ -965,6 +1416,7 @@ mod tests {
                 "first",
                 "orderBy",
                 "orderDirection",
+                "distinctOn",
                 "where",
                 "block"
             ]
@@ -1056,6 +1508,7 @@ mod tests {
                 "first",
                 "orderBy",
                 "orderDirection",
+                "distinctOn",
                 "where",
                 "block"
             ]
@@ -1101,4 +1554,97 @@ type Gravatar @entity {
         }
         .expect("\"metadata\" field is missing on Query type");
     }
+
+    #[test]
+    fn api_schema_contains_group_types_and_query_field() {
+        let input_schema = parse_schema(
+            r#"
+            enum Kind { Mint, Burn }
+            type Token @entity { id: ID!, transfers: [Transfer!]! @derivedFrom(field: "token") }
+            type Transfer @entity {
+              id: ID!, token: Token!, amount: BigInt!, kind: Kind, tags: [String!]
+            }
+            "#,
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let group_by = match ast::get_named_type(&schema, &"Transfer_groupBy".to_string()) {
+            Some(TypeDefinition::Enum(t)) => t,
+            _ => panic!("Transfer_groupBy enum is missing in derived API schema"),
+        };
+        let values: Vec<&str> = group_by.values.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(values, ["id", "token", "amount", "kind"]);
+
+        let group = match ast::get_named_type(&schema, &"Transfer_group".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("Transfer_group type is missing in derived API schema"),
+        };
+        let field_type = |name: &str| {
+            ast::get_field(group, &name.to_string())
+                .map(|field| field.field_type.clone())
+                .unwrap_or_else(|| panic!("Transfer_group.{} is missing", name))
+        };
+        assert_eq!(field_type("token"), Type::NamedType("ID".to_string()));
+        assert_eq!(
+            field_type("count"),
+            Type::NonNullType(Box::new(Type::NamedType("Int".to_string())))
+        );
+        assert_eq!(
+            field_type("amount_sum"),
+            Type::NamedType("BigInt".to_string())
+        );
+        assert_eq!(
+            field_type("amount_avg"),
+            Type::NamedType("BigDecimal".to_string())
+        );
+        assert!(ast::get_field(group, &"kind_sum".to_string()).is_none());
+        assert!(ast::get_field(group, &"tags".to_string()).is_none());
+
+        let having = match ast::get_named_type(&schema, &"Transfer_having".to_string()) {
+            Some(TypeDefinition::InputObject(t)) => t,
+            _ => panic!("Transfer_having input type is missing in derived API schema"),
+        };
+        assert!(having.fields.iter().any(|f| f.name == "amount_sum_gte"));
+
+        match ast::get_named_type(&schema, &"Token_groupBy".to_string()) {
+            Some(TypeDefinition::Enum(t)) => {
+                let values: Vec<&str> = t.values.iter().map(|v| v.name.as_str()).collect();
+                assert_eq!(values, ["id"]);
+            }
+            _ => panic!("Token_groupBy enum is missing in derived API schema"),
+        }
+
+        let query_type = ast::get_named_type(&schema, &"Query".to_string())
+            .expect("Query type is missing in derived API schema");
+        let groups_field = match query_type {
+            TypeDefinition::Object(t) => ast::get_field(t, &"transfers_groupBy".to_string()),
+            _ => None,
+        }
+        .expect("\"transfers_groupBy\" field is missing on Query type");
+        assert_eq!(
+            groups_field.field_type,
+            Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+                Box::new(Type::NamedType("Transfer_group".to_string()))
+            )))))
+        );
+        let arguments: Vec<&str> = groups_field
+            .arguments
+            .iter()
+            .map(|input_value| input_value.name.as_str())
+            .collect();
+        assert_eq!(
+            arguments,
+            [
+                "skip",
+                "first",
+                "orderBy",
+                "orderDirection",
+                "where",
+                "by",
+                "having",
+                "block"
+            ]
+        );
+    }
 }
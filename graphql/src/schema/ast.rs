@@ -23,6 +23,7 @@ pub(crate) enum FilterOp {
     NotStartsWith,
     EndsWith,
     NotEndsWith,
+    Within,
     Equal,
 }
 
@@ -42,6 +43,7 @@ pub(crate) fn parse_field_as_filter(key: &Name) -> (Name, FilterOp) {
         k if k.ends_with("_not_ends_with") => ("_not_ends_with", FilterOp::NotEndsWith),
         k if k.ends_with("_starts_with") => ("_starts_with", FilterOp::StartsWith),
         k if k.ends_with("_ends_with") => ("_ends_with", FilterOp::EndsWith),
+        k if k.ends_with("_within") => ("_within", FilterOp::Within),
         _ => ("", FilterOp::Equal),
     };
 
@@ -467,6 +469,9 @@ fn is_assignable(value: &store::Value, scalar_type: &ValueType, is_list: bool) -
         | (store::Value::Bytes(_), ValueType::Bytes)
         | (store::Value::Int(_), ValueType::Int)
         | (store::Value::Null, _) => true,
+        (store::Value::String(s), ValueType::GeoPoint) => {
+            store::scalar::GeoPoint::from_str(s).is_ok()
+        }
         (store::Value::List(values), _) if is_list => values
             .iter()
             .all(|value| is_assignable(value, scalar_type, false)),
@@ -0,0 +1,122 @@
+//! The hashes of blocks by number for each network, so that queries with
+//! `block: { number }` do not have to look the hash up in the store every
+//! time. Hashes are forgotten when a deployment reverts blocks of their
+//! network, since the blocks that replace the reverted ones have different
+//! hashes
+
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use graph::prelude::web3::types::H256;
+use graph::util::env::env_var;
+
+lazy_static! {
+    /// How many block hashes to cache for each network
+    static ref BLOCK_CACHE_SIZE: usize = env_var("GRAPH_QUERY_BLOCK_CACHE_SIZE").unwrap_or(10000);
+
+    static ref BLOCK_CACHE: RwLock<HashMap<String, BTreeMap<u64, H256>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// The cached hash of the block with `number` on `network`
+pub(crate) fn cached_block_hash(network: &str, number: u64) -> Option<H256> {
+    BLOCK_CACHE
+        .read()
+        .unwrap()
+        .get(network)
+        .and_then(|hashes| hashes.get(&number))
+        .cloned()
+}
+
+/// Remember that the block with `number` on `network` has `hash`. When the
+/// cache for the network is full, the hash of its lowest block is dropped
+pub(crate) fn cache_block_hash(network: &str, number: u64, hash: H256) {
+    insert(
+        &mut BLOCK_CACHE.write().unwrap(),
+        *BLOCK_CACHE_SIZE,
+        network,
+        number,
+        hash,
+    )
+}
+
+/// Whether any block hashes of `network` are cached
+pub(crate) fn caches_block_hashes(network: &str) -> bool {
+    BLOCK_CACHE
+        .read()
+        .unwrap()
+        .get(network)
+        .map_or(false, |hashes| !hashes.is_empty())
+}
+
+/// Forget the hashes of the blocks after `number` on `network`, and return
+/// how many were forgotten. This is called when a deployment on `network`
+/// reverted to block `number`
+pub(crate) fn invalidate_block_hashes(network: &str, number: u64) -> usize {
+    BLOCK_CACHE
+        .write()
+        .unwrap()
+        .get_mut(network)
+        .map_or(0, |hashes| hashes.split_off(&(number + 1)).len())
+}
+
+fn insert(
+    cache: &mut HashMap<String, BTreeMap<u64, H256>>,
+    size: usize,
+    network: &str,
+    number: u64,
+    hash: H256,
+) {
+    if size == 0 {
+        return;
+    }
+    let hashes = cache.entry(network.to_owned()).or_default();
+    hashes.insert(number, hash);
+    while hashes.len() > size {
+        let lowest = *hashes.keys().next().unwrap();
+        hashes.remove(&lowest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_lowest_blocks_when_full() {
+        let mut cache = HashMap::new();
+        for number in 1..=3 {
+            insert(
+                &mut cache,
+                2,
+                "mainnet",
+                number,
+                H256::from_low_u64_be(number),
+            );
+        }
+        insert(&mut cache, 2, "ropsten", 1, H256::from_low_u64_be(1));
+        insert(&mut cache, 0, "kovan", 1, H256::from_low_u64_be(1));
+
+        let mainnet: Vec<_> = cache["mainnet"].keys().cloned().collect();
+        assert_eq!(vec![2, 3], mainnet);
+        assert_eq!(1, cache["ropsten"].len());
+        assert!(!cache.contains_key("kovan"));
+    }
+
+    #[test]
+    fn invalidates_blocks_after_revert() {
+        let network = "block-cache-invalidation-test";
+        for number in 1..=3 {
+            cache_block_hash(network, number, H256::from_low_u64_be(number));
+        }
+        assert!(caches_block_hashes(network));
+        assert_eq!(2, invalidate_block_hashes(network, 1));
+        assert_eq!(
+            Some(H256::from_low_u64_be(1)),
+            cached_block_hash(network, 1)
+        );
+        assert_eq!(None, cached_block_hash(network, 2));
+        assert_eq!(0, invalidate_block_hashes("unknown", 1));
+    }
+}
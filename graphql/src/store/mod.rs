@@ -1,6 +1,7 @@
+pub(crate) mod block_cache;
 mod prefetch;
 mod query;
 mod resolver;
 
-pub use self::query::{build_query, parse_subgraph_id};
+pub use self::query::{build_group_query, build_query, parse_subgraph_id};
 pub use self::resolver::StoreResolver;
@@ -10,15 +10,18 @@ use std::time::Instant;
 
 use graph::data::graphql::ext::ObjectTypeExt;
 use graph::prelude::{
-    BlockNumber, ChildMultiplicity, EntityCollection, EntityFilter, EntityLink, EntityOrder,
-    EntityWindow, Logger, ParentLink, QueryExecutionError, Schema, Store, Value as StoreValue,
-    WindowAttribute,
+    BlockNumber, ChildMultiplicity, EntityAggregate, EntityCollection, EntityFilter, EntityLink,
+    EntityOrder, EntityRange, EntityWindow, Logger, ParentLink, QueryExecutionError, Schema, Store,
+    StoreError, Value as StoreValue, WindowAttribute,
 };
+use graph::trace::Span;
 
 use crate::execution::{ExecutionContext, ObjectOrInterface, Resolver};
 use crate::query::ast as qast;
+use crate::schema::api::{GROUP_BY_FIELD_SUFFIX, GROUP_TYPE_SUFFIX, META_FIELD, META_TYPE};
 use crate::schema::ast as sast;
-use crate::store::{build_query, StoreResolver};
+use crate::store::query::parse_subgraph_id;
+use crate::store::{build_group_query, build_query, StoreResolver};
 
 lazy_static! {
     static ref ARG_FIRST: String = String::from("first");
@@ -482,6 +485,9 @@ pub fn run(
     ctx: &ExecutionContext<impl Resolver>,
     selection_set: &q::SelectionSet,
 ) -> Result<q::Value, Vec<QueryExecutionError>> {
+    // The queries for each level of the result become children of this span
+    let span = Span::child("graphql.prefetch");
+    let _entered = span.enter();
     execute_root_selection_set(resolver, ctx, selection_set).map(|nodes| {
         let map = BTreeMap::default();
         q::Value::Object(nodes.into_iter().fold(map, |mut map, node| {
@@ -563,6 +569,124 @@ fn object_or_interface_by_name<'a>(
     }
 }
 
+/// Coalesces the lookups of single entities by `id` among the root fields
+/// of a query, e.g., `{ a: token(id: "1") { .. } b: token(id: "2") { .. } }`,
+/// into one query per entity type
+#[derive(Default)]
+struct EntityLoader {
+    /// The entity type and id that each batched root field looks up, by
+    /// response key
+    lookups: HashMap<String, (String, String)>,
+    /// The entities that were found, by entity type and id
+    entities: HashMap<(String, String), Node>,
+}
+
+impl EntityLoader {
+    fn load<'a>(
+        resolver: &StoreResolver<impl Store>,
+        ctx: &'a ExecutionContext<impl Resolver>,
+        object_type: &ObjectOrInterface,
+        grouped_field_set: &HashMap<&'a String, HashMap<TypeCondition, Vec<&'a q::Field>>>,
+    ) -> Result<Self, QueryExecutionError> {
+        let mut loader = EntityLoader::default();
+        let query_type = match object_type {
+            ObjectOrInterface::Object(query_type) => *query_type,
+            ObjectOrInterface::Interface(_) => return Ok(loader),
+        };
+
+        // The response keys and ids of the lookups, by entity type
+        let mut requested: BTreeMap<&str, (ObjectOrInterface, Vec<(&String, String)>)> =
+            BTreeMap::new();
+        for (response_key, type_map) in grouped_field_set {
+            let fields = match type_map.values().next() {
+                Some(fields) if type_map.len() == 1 => fields,
+                _ => continue,
+            };
+            let field = match query_type.field(&fields[0].name) {
+                Some(field) if !sast::is_list_or_non_null_list_field(field) => field,
+                _ => continue,
+            };
+            let child_type = match object_or_interface_from_type(
+                &ctx.query.schema.document,
+                &field.field_type,
+            ) {
+                Some(child_type) => child_type,
+                None => continue,
+            };
+            let id = match crate::execution::coerce_argument_values(ctx, query_type, &fields[0]) {
+                Ok(mut arguments) => match arguments.remove(&*ARG_ID) {
+                    Some(q::Value::String(id)) => id,
+                    _ => continue,
+                },
+                // Executing the field reports the error
+                Err(_) => continue,
+            };
+            requested
+                .entry(child_type.name())
+                .or_insert_with(|| (child_type, vec![]))
+                .1
+                .push((response_key, id));
+        }
+
+        for (type_name, (child_type, lookups)) in requested {
+            // A single lookup is executed like any other field
+            if lookups.len() < 2 {
+                continue;
+            }
+            let ids = lookups
+                .iter()
+                .map(|(_, id)| id.clone())
+                .collect::<HashSet<_>>();
+
+            let mut span = Span::child("graphql.prefetch.load");
+            span.set_attribute("entity_type", type_name);
+            span.set_attribute("ids", ids.len());
+            let mut query = build_query(
+                child_type,
+                resolver.block,
+                &HashMap::new(),
+                ctx.query.schema.types_for_interface(),
+                ctx.max_first,
+            )?;
+            query.logger = Some(ctx.logger.clone());
+            query.order = EntityOrder::Unordered;
+            // The filter already limits the query to at most one entity
+            // per id and implementing type
+            query.range = EntityRange {
+                first: None,
+                skip: 0,
+            };
+            query.filter = Some(EntityFilter::In(
+                ARG_ID.to_owned(),
+                ids.into_iter().map(StoreValue::from).collect(),
+            ));
+            for entity in resolver.store.find_query_values(query)? {
+                let node = Node::from(entity);
+                if let Ok(id) = node.id() {
+                    loader
+                        .entities
+                        .entry((type_name.to_owned(), id))
+                        .or_insert(node);
+                }
+            }
+            for (response_key, id) in lookups {
+                loader
+                    .lookups
+                    .insert(response_key.clone(), (type_name.to_owned(), id));
+            }
+        }
+        Ok(loader)
+    }
+
+    /// The entity that the root field with `response_key` looks up, if the
+    /// lookup was batched
+    fn get(&self, response_key: &str) -> Option<Vec<Node>> {
+        self.lookups
+            .get(response_key)
+            .map(|key| self.entities.get(key).cloned().into_iter().collect())
+    }
+}
+
 fn execute_selection_set<'a>(
     resolver: &StoreResolver<impl Store>,
     ctx: &'a ExecutionContext<impl Resolver>,
@@ -575,6 +699,12 @@ fn execute_selection_set<'a>(
     // Group fields with the same response key, so we can execute them together
     let grouped_field_set = collect_fields(ctx, object_type, selection_sets, None);
 
+    let loader = if is_root_node(&parents) {
+        EntityLoader::load(resolver, ctx, object_type, &grouped_field_set).map_err(|e| vec![e])?
+    } else {
+        EntityLoader::default()
+    };
+
     // Process all field groups in order
     for (response_key, type_map) in grouped_field_set {
         match ctx.deadline {
@@ -595,6 +725,36 @@ fn execute_selection_set<'a>(
                 .expect("collect_fields does not create type conditions for nonexistent types");
 
             if let Some(ref field) = concrete_type.field(&fields[0].name) {
+                if is_root_node(&parents) && field.name == META_FIELD {
+                    match execute_meta_field(resolver, &concrete_type) {
+                        Ok(meta) => Join::perform(&mut parents, vec![meta], response_key),
+                        Err(e) => errors.push(e),
+                    }
+                    continue;
+                }
+
+                let grouped = if is_root_node(&parents) {
+                    grouped_entity_type(&ctx.query.schema.document, field)
+                } else {
+                    None
+                };
+                if let (ObjectOrInterface::Object(query_type), Some((entity_type, group_type))) =
+                    (&concrete_type, grouped)
+                {
+                    match execute_group_field(
+                        resolver,
+                        ctx,
+                        query_type,
+                        entity_type,
+                        group_type,
+                        &fields,
+                    ) {
+                        Ok(groups) => Join::perform(&mut parents, groups, response_key),
+                        Err(mut e) => errors.append(&mut e),
+                    }
+                    continue;
+                }
+
                 let child_type =
                     object_or_interface_from_type(&ctx.query.schema.document, &field.field_type)
                         .expect("we only collect fields that are objects or interfaces");
@@ -606,15 +766,19 @@ fn execute_selection_set<'a>(
                     &field.name,
                 );
 
-                match execute_field(
-                    resolver,
-                    &ctx,
-                    &concrete_type,
-                    &parents,
-                    &join,
-                    &fields[0],
-                    field,
-                ) {
+                let children = match loader.get(response_key) {
+                    Some(children) => Ok(children),
+                    None => execute_field(
+                        resolver,
+                        &ctx,
+                        &concrete_type,
+                        &parents,
+                        &join,
+                        &fields[0],
+                        field,
+                    ),
+                };
+                match children {
                     Ok(children) => {
                         let child_object_type = object_or_interface_from_type(
                             &ctx.query.schema.document,
@@ -658,6 +822,131 @@ fn execute_selection_set<'a>(
     }
 }
 
+/// The entity type whose entities the root field `field` groups, if it is
+/// one of the `<entities>_groupBy` fields, together with the type of the
+/// groups
+fn grouped_entity_type<'a>(
+    schema: &'a s::Document,
+    field: &s::Field,
+) -> Option<(&'a s::ObjectType, &'a s::ObjectType)> {
+    if !field.name.ends_with(GROUP_BY_FIELD_SUFFIX) {
+        return None;
+    }
+    let group_name = sast::get_field_name(&field.field_type);
+    if !group_name.ends_with(GROUP_TYPE_SUFFIX) {
+        return None;
+    }
+    let entity_name = &group_name[..group_name.len() - GROUP_TYPE_SUFFIX.len()];
+    match (
+        sast::get_named_type(schema, &entity_name.to_owned()),
+        sast::get_named_type(schema, &group_name),
+    ) {
+        (Some(s::TypeDefinition::Object(entity)), Some(s::TypeDefinition::Object(group))) => {
+            Some((entity, group))
+        }
+        _ => None,
+    }
+}
+
+/// Look up the blocks of the deployment that `query_type` belongs to for
+/// the `_meta` field. All fields of `_Meta_` are scalars, so the node has
+/// no children
+fn execute_meta_field(
+    resolver: &StoreResolver<impl Store>,
+    query_type: &ObjectOrInterface,
+) -> Result<Node, QueryExecutionError> {
+    let subgraph_id = parse_subgraph_id(*query_type)?;
+    let blocks = resolver
+        .store
+        .deployment_blocks(&subgraph_id)
+        .map_err(StoreError::from)?;
+
+    let int = |number: u64| q::Value::Int(q::Number::from(number as i32));
+    let mut meta = BTreeMap::new();
+    meta.insert(
+        "__typename".to_owned(),
+        q::Value::String(META_TYPE.to_owned()),
+    );
+    meta.insert("startBlock".to_owned(), int(blocks.start_block));
+    meta.insert("earliestBlock".to_owned(), int(blocks.earliest_block));
+    meta.insert(
+        "lastHealthyBlock".to_owned(),
+        blocks.last_healthy_block.map_or(q::Value::Null, int),
+    );
+    Ok(Node::from(meta))
+}
+
+/// Collect the names of the fields that `selection_set` selects, including
+/// those in fragments
+fn selected_field_names(
+    ctx: &ExecutionContext<impl Resolver>,
+    selection_set: &q::SelectionSet,
+    names: &mut HashSet<String>,
+) {
+    for selection in &selection_set.items {
+        match selection {
+            q::Selection::Field(field) => {
+                names.insert(field.name.clone());
+            }
+            q::Selection::FragmentSpread(spread) => {
+                if let Some(fragment) = ctx.query.get_fragment(&spread.fragment_name) {
+                    selected_field_names(ctx, &fragment.selection_set, names);
+                }
+            }
+            q::Selection::InlineFragment(fragment) => {
+                selected_field_names(ctx, &fragment.selection_set, names)
+            }
+        }
+    }
+}
+
+/// Query the groups for a `<entities>_groupBy` field. The aggregates that
+/// are computed are the ones that `fields` select
+fn execute_group_field(
+    resolver: &StoreResolver<impl Store>,
+    ctx: &ExecutionContext<impl Resolver>,
+    query_type: &s::ObjectType,
+    entity_type: &s::ObjectType,
+    group_type: &s::ObjectType,
+    fields: &[&q::Field],
+) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
+    let arguments = crate::execution::coerce_argument_values(ctx, query_type, fields[0])?;
+
+    let mut names = HashSet::new();
+    for field in fields {
+        selected_field_names(ctx, &field.selection_set, &mut names);
+    }
+    let mut names: Vec<_> = names.into_iter().collect();
+    names.sort();
+    // Aggregates are only generated for fields of the entity type; other
+    // names are the attributes that the entities are grouped by
+    let aggregates = names
+        .iter()
+        .filter_map(|name| EntityAggregate::from_name(name))
+        .filter(|aggregate| {
+            aggregate
+                .attribute()
+                .map_or(true, |attr| entity_type.field(attr).is_some())
+        })
+        .collect();
+
+    let query = build_group_query(
+        entity_type,
+        group_type,
+        resolver.block,
+        &arguments,
+        aggregates,
+        ctx.max_first,
+    )
+    .map_err(|e| vec![e])?;
+    let _span = Span::child("graphql.prefetch.groups");
+    resolver
+        .store
+        .find_groups(query)
+        .map(|groups| groups.into_iter().map(Node::from).collect())
+        .map_err(|e| vec![e])
+}
+
 /// Collects fields of a selection set. The resulting map indicates for each
 /// response key from which types to fetch what fields to express the effect
 /// of fragment spreads
@@ -858,6 +1147,9 @@ fn fetch<S: Store>(
     block: BlockNumber,
     max_first: u32,
 ) -> Result<Vec<Node>, QueryExecutionError> {
+    let mut span = Span::child("graphql.prefetch.fetch");
+    span.set_attribute("entity_type", join.child_type.name());
+    span.set_attribute("parents", parents.len());
     let mut query = build_query(
         join.child_type,
         block,
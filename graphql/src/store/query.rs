@@ -1,7 +1,9 @@
 use graphql_parser::{query as q, query::Name, schema as s, schema::ObjectType};
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::mem::discriminant;
+use std::str::FromStr;
 
+use graph::data::store::scalar::GeoPoint;
 use graph::prelude::*;
 
 use crate::execution::ObjectOrInterface;
@@ -36,10 +38,18 @@ pub fn build_query<'a>(
     if let Some(filter) = build_filter(entity, arguments)? {
         query = query.filter(filter);
     }
-    let order = match (
-        build_order_by(entity, arguments)?,
-        build_order_direction(arguments)?,
-    ) {
+    let distinct_on = build_distinct_on(entity, arguments)?;
+    let order_by = match (&distinct_on, build_order_by(entity, arguments)?) {
+        (Some((distinct_attr, _)), Some((order_attr, _))) if distinct_attr != &order_attr => {
+            return Err(QueryExecutionError::DistinctOnRequiresOrderBy(
+                distinct_attr.clone(),
+                order_attr,
+            ));
+        }
+        (Some(distinct_on), None) => Some(distinct_on.clone()),
+        (_, order_by) => order_by,
+    };
+    let order = match (order_by, build_order_direction(arguments)?) {
         (Some((attr, value_type)), OrderDirection::Ascending) => {
             EntityOrder::Ascending(attr, value_type)
         }
@@ -48,10 +58,105 @@ pub fn build_query<'a>(
         }
         (None, _) => EntityOrder::Default,
     };
-    query = query.order(order);
+    query = query.order(order).distinct(distinct_on.is_some());
+    Ok(query)
+}
+
+/// Builds an `EntityGroupQuery` for grouping the entities of `entity` from
+/// the GraphQL arguments of its `<entities>_groupBy` field. The groups have
+/// type `group_type`, and `aggregates` are the aggregates the query selects
+pub fn build_group_query(
+    entity: &ObjectType,
+    group_type: &ObjectType,
+    block: BlockNumber,
+    arguments: &HashMap<&q::Name, q::Value>,
+    aggregates: Vec<EntityAggregate>,
+    max_first: u32,
+) -> Result<EntityGroupQuery, QueryExecutionError> {
+    let group_by: Vec<_> = match arguments.get(&"by".to_string()) {
+        Some(q::Value::List(values)) => values
+            .iter()
+            .filter_map(|value| match value {
+                q::Value::Enum(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    let order = match arguments.get(&"orderBy".to_string()) {
+        Some(q::Value::Enum(name)) => {
+            let key = if group_by.contains(name) {
+                GroupSortKey::Attribute(name.clone())
+            } else {
+                EntityAggregate::from_name(name)
+                    .map(GroupSortKey::Aggregate)
+                    .ok_or_else(|| {
+                        QueryExecutionError::OrderByNotSupportedError(
+                            group_type.name.clone(),
+                            name.clone(),
+                        )
+                    })?
+            };
+            match build_order_direction(arguments)? {
+                OrderDirection::Ascending => GroupOrder::Ascending(key),
+                OrderDirection::Descending => GroupOrder::Descending(key),
+            }
+        }
+        _ => GroupOrder::Default,
+    };
+
+    let mut query = EntityGroupQuery::new(parse_subgraph_id(entity)?, block, entity.name.clone())
+        .group_by(group_by)
+        .aggregates(aggregates)
+        .order(order)
+        .range(build_range(arguments, max_first)?);
+    if let Some(filter) = build_filter(entity.into(), arguments)? {
+        query = query.filter(filter);
+    }
+    if let Some(having) = build_having(group_type, arguments)? {
+        query = query.having(having);
+    }
     Ok(query)
 }
 
+/// Parses the `having` argument of a `<entities>_groupBy` field into an
+/// `AggregateFilter`, if present
+fn build_having(
+    group_type: &ObjectType,
+    arguments: &HashMap<&q::Name, q::Value>,
+) -> Result<Option<AggregateFilter>, QueryExecutionError> {
+    let object = match arguments.get(&"having".to_string()) {
+        Some(q::Value::Object(object)) => object,
+        Some(q::Value::Null) | None => return Ok(None),
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    object
+        .iter()
+        .map(|(key, value)| {
+            let (name, op) = match key.rfind('_') {
+                Some(pos) if ["gt", "lt", "gte", "lte"].contains(&&key[pos + 1..]) => {
+                    (&key[..pos], &key[pos + 1..])
+                }
+                _ => (key.as_str(), ""),
+            };
+            let error =
+                || QueryExecutionError::EntityFieldError(group_type.name.clone(), key.clone());
+            let field = sast::get_field(group_type, &name.to_owned()).ok_or_else(error)?;
+            let aggregate = EntityAggregate::from_name(name).ok_or_else(error)?;
+            let value = Value::from_query_value(value, &field.field_type)?;
+            Ok(match op {
+                "gt" => AggregateFilter::GreaterThan(aggregate, value),
+                "lt" => AggregateFilter::LessThan(aggregate, value),
+                "gte" => AggregateFilter::GreaterOrEqual(aggregate, value),
+                "lte" => AggregateFilter::LessOrEqual(aggregate, value),
+                _ => AggregateFilter::Equal(aggregate, value),
+            })
+        })
+        .collect::<Result<Vec<_>, QueryExecutionError>>()
+        .map(|filters| Some(AggregateFilter::And(filters)))
+}
+
 /// Parses GraphQL arguments into a EntityRange, if present.
 fn build_range(
     arguments: &HashMap<&q::Name, q::Value>,
@@ -154,6 +259,10 @@ fn build_filter_from_object(
                     )
                 })?;
 
+                if let Within = op {
+                    return build_within_filter(field_name, value);
+                }
+
                 let ty = &field.field_type;
                 let store_value = Value::from_query_value(value, &ty)?;
 
@@ -171,6 +280,7 @@ fn build_filter_from_object(
                     NotStartsWith => EntityFilter::NotStartsWith(field_name, store_value),
                     EndsWith => EntityFilter::EndsWith(field_name, store_value),
                     NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
+                    Within => unreachable!("`within` filters were handled above"),
                     Equal => EntityFilter::Equal(field_name, store_value),
                 })
             })
@@ -178,6 +288,39 @@ fn build_filter_from_object(
     })))
 }
 
+/// Parses the value of a `<field>_within` filter, an object with the point
+/// `of` and the `radius` around it in meters
+fn build_within_filter(
+    field_name: String,
+    value: &q::Value,
+) -> Result<EntityFilter, QueryExecutionError> {
+    let object = match value {
+        q::Value::Object(object) => object,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    let of = match object.get("of") {
+        Some(q::Value::String(s)) => GeoPoint::from_str(s)?,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    let radius = match object.get("radius") {
+        Some(q::Value::String(s)) => f64::from_str(s)
+            .ok()
+            .filter(|radius| radius.is_finite() && *radius >= 0.0)
+            .ok_or_else(|| {
+                QueryExecutionError::ValueParseError(
+                    "radius".to_owned(),
+                    format!("`{}` is not a distance in meters", s),
+                )
+            })?,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    Ok(EntityFilter::Within(
+        field_name,
+        Value::String(of.to_string()),
+        radius,
+    ))
+}
+
 /// Parses a list of GraphQL values into a vector of entity field values.
 fn list_values(value: Value, filter_type: &str) -> Result<Vec<Value>, QueryExecutionError> {
     match value {
@@ -212,19 +355,7 @@ fn build_order_by(
     arguments: &HashMap<&q::Name, q::Value>,
 ) -> Result<Option<(String, ValueType)>, QueryExecutionError> {
     match arguments.get(&"orderBy".to_string()) {
-        Some(q::Value::Enum(name)) => {
-            let field = sast::get_field(entity, &name).ok_or_else(|| {
-                QueryExecutionError::EntityFieldError(entity.name().to_owned(), name.clone())
-            })?;
-            sast::get_field_value_type(&field.field_type)
-                .map(|value_type| Some((name.to_owned(), value_type)))
-                .map_err(|_| {
-                    QueryExecutionError::OrderByNotSupportedError(
-                        entity.name().to_owned(),
-                        name.clone(),
-                    )
-                })
-        }
+        Some(q::Value::Enum(name)) => sort_attribute(entity, name).map(Some),
         _ => match arguments.get(&"text".to_string()) {
             Some(q::Value::Object(filter)) => build_fulltext_order_by_from_object(filter),
             None => Ok(None),
@@ -233,6 +364,34 @@ fn build_order_by(
     }
 }
 
+/// Parses the `distinctOn` argument into the field whose values should be
+/// distinct, if present. The query must be ordered by that field
+fn build_distinct_on(
+    entity: ObjectOrInterface,
+    arguments: &HashMap<&q::Name, q::Value>,
+) -> Result<Option<(String, ValueType)>, QueryExecutionError> {
+    match arguments.get(&"distinctOn".to_string()) {
+        Some(q::Value::Enum(name)) => sort_attribute(entity, name).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// The field `name` of `entity` together with its value type, if entities
+/// can be sorted by it
+fn sort_attribute(
+    entity: ObjectOrInterface,
+    name: &str,
+) -> Result<(String, ValueType), QueryExecutionError> {
+    let field = sast::get_field(entity, &name.to_owned()).ok_or_else(|| {
+        QueryExecutionError::EntityFieldError(entity.name().to_owned(), name.to_owned())
+    })?;
+    sast::get_field_value_type(&field.field_type)
+        .map(|value_type| (name.to_owned(), value_type))
+        .map_err(|_| {
+            QueryExecutionError::OrderByNotSupportedError(entity.name().to_owned(), name.to_owned())
+        })
+}
+
 fn build_fulltext_order_by_from_object(
     object: &BTreeMap<q::Name, q::Value>,
 ) -> Result<Option<(String, ValueType)>, QueryExecutionError> {
@@ -288,6 +447,11 @@ pub fn parse_subgraph_id<'a>(
 }
 
 /// Recursively collects entities involved in a query field as `(subgraph ID, name)` tuples.
+/// Collect the entity types that the query `field` of `object_type`
+/// touches, so that a subscription for it only runs again when one of them
+/// changes. Fields of an interface type touch every entity type that
+/// implements the interface. Fragment spreads must have been replaced with
+/// inline fragments before, since the fragments are not known here
 pub fn collect_entities_from_query_field(
     schema: &s::Document,
     object_type: &s::ObjectType,
@@ -298,43 +462,81 @@ pub fn collect_entities_from_query_field(
 
     // List of objects/fields to visit next
     let mut queue = VecDeque::new();
-    queue.push_back((object_type, field));
-
-    while let Some((object_type, field)) = queue.pop_front() {
-        // Check if the field exists on the object type
-        if let Some(field_type) = sast::get_field(object_type, &field.name) {
-            // Check if the field type corresponds to a type definition (in a valid schema,
-            // this should always be the case)
-            if let Some(type_definition) = sast::get_type_definition_from_field(schema, field_type)
-            {
-                // If the field's type definition is an object type, extract that type
-                if let s::TypeDefinition::Object(object_type) = type_definition {
-                    // Only collect whether the field's type has an @entity directive
-                    if sast::get_object_type_directive(object_type, String::from("entity"))
-                        .is_some()
-                    {
-                        // Obtain the subgraph ID from the object type
-                        if let Ok(subgraph_id) = parse_subgraph_id(object_type) {
-                            // Add the (subgraph_id, entity_name) tuple to the result set
-                            entities.insert((subgraph_id, object_type.name.to_owned()));
-                        }
-                    }
+    queue.push_back((ObjectOrInterface::from(object_type), field));
+
+    while let Some((parent_type, field)) = queue.pop_front() {
+        // Check if the field exists on the parent type, and if the field type
+        // corresponds to a type definition (in a valid schema, this should
+        // always be the case)
+        let field_type = match sast::get_field(parent_type, &field.name)
+            .and_then(|field_type| sast::get_type_definition_from_field(schema, field_type))
+        {
+            Some(s::TypeDefinition::Object(object_type)) => ObjectOrInterface::from(object_type),
+            Some(s::TypeDefinition::Interface(interface)) => ObjectOrInterface::from(interface),
+            _ => continue,
+        };
 
-                    // If the query field has a non-empty selection set, this means we
-                    // need to recursively process it
-                    for selection in field.selection_set.items.iter() {
-                        if let q::Selection::Field(sub_field) = selection {
-                            queue.push_back((&object_type, sub_field))
-                        }
+        match field_type {
+            ObjectOrInterface::Object(object_type) => {
+                collect_entity(object_type, &mut entities);
+            }
+            ObjectOrInterface::Interface(interface) => {
+                for object_type in sast::get_object_type_definitions(schema) {
+                    if object_type.implements_interfaces.contains(&interface.name) {
+                        collect_entity(object_type, &mut entities);
                     }
                 }
             }
         }
+
+        // If the query field has a non-empty selection set, this means we
+        // need to recursively process it
+        collect_sub_fields(schema, field_type, &field.selection_set, &mut queue);
     }
 
     entities.into_iter().collect()
 }
 
+/// Add `object_type` to `entities` if it is the type of an entity
+fn collect_entity(
+    object_type: &s::ObjectType,
+    entities: &mut HashSet<(SubgraphDeploymentId, String)>,
+) {
+    if sast::get_object_type_directive(object_type, String::from("entity")).is_none() {
+        return;
+    }
+    if let Ok(subgraph_id) = parse_subgraph_id(object_type) {
+        entities.insert((subgraph_id, object_type.name.to_owned()));
+    }
+}
+
+/// Queue the fields of `selection_set`, including those in inline
+/// fragments, with the type they are selected on
+fn collect_sub_fields<'a, 'b>(
+    schema: &'a s::Document,
+    parent_type: ObjectOrInterface<'a>,
+    selection_set: &'b q::SelectionSet,
+    queue: &mut VecDeque<(ObjectOrInterface<'a>, &'b q::Field)>,
+) {
+    for selection in selection_set.items.iter() {
+        match selection {
+            q::Selection::Field(sub_field) => queue.push_back((parent_type, sub_field)),
+            q::Selection::InlineFragment(fragment) => {
+                let fragment_type = match &fragment.type_condition {
+                    Some(q::TypeCondition::On(name)) => match sast::get_named_type(schema, name) {
+                        Some(s::TypeDefinition::Object(object_type)) => object_type.into(),
+                        Some(s::TypeDefinition::Interface(interface)) => interface.into(),
+                        _ => continue,
+                    },
+                    None => parent_type,
+                };
+                collect_sub_fields(schema, fragment_type, &fragment.selection_set, queue);
+            }
+            q::Selection::FragmentSpread(_) => {}
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use graphql_parser::{
@@ -532,6 +734,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn build_query_orders_by_distinct_on() {
+        let order_by = "orderBy".to_string();
+        let distinct_on = "distinctOn".to_string();
+        let build = |args: &HashMap<&String, q::Value>| {
+            build_query(
+                &default_object(),
+                BLOCK_NUMBER_MAX,
+                args,
+                &BTreeMap::new(),
+                std::u32::MAX,
+            )
+        };
+
+        let query = build(&default_arguments()).unwrap();
+        assert!(!query.distinct);
+
+        // Without `orderBy`, the query is ordered by `distinctOn`
+        let mut args = default_arguments();
+        args.insert(&distinct_on, q::Value::Enum("name".to_string()));
+        let query = build(&args).unwrap();
+        assert!(query.distinct);
+        assert_eq!(
+            EntityOrder::Ascending("name".to_string(), ValueType::String),
+            query.order
+        );
+
+        args.insert(&order_by, q::Value::Enum("name".to_string()));
+        assert!(build(&args).unwrap().distinct);
+
+        args.insert(&order_by, q::Value::Enum("email".to_string()));
+        match build(&args) {
+            Err(QueryExecutionError::DistinctOnRequiresOrderBy(distinct_on, order_by)) => {
+                assert_eq!("name", distinct_on);
+                assert_eq!("email", order_by);
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
     #[test]
     fn build_query_parses_order_direction_from_enum_values_correctly() {
         let order_by = "orderBy".to_string();
@@ -712,4 +954,85 @@ mod tests {
             )]))
         )
     }
+
+    #[test]
+    fn build_query_yields_within_filters() {
+        let whre = "where".to_string();
+        let within = |of: &str, radius: &str| {
+            let mut args = default_arguments();
+            args.insert(
+                &whre,
+                q::Value::Object(BTreeMap::from_iter(vec![(
+                    "location_within".to_string(),
+                    q::Value::Object(BTreeMap::from_iter(vec![
+                        ("of".to_string(), q::Value::String(of.to_string())),
+                        ("radius".to_string(), q::Value::String(radius.to_string())),
+                    ])),
+                )])),
+            );
+            build_query(
+                &ObjectType {
+                    fields: vec![field("location", Type::NamedType("GeoPoint".to_owned()))],
+                    ..default_object()
+                },
+                BLOCK_NUMBER_MAX,
+                &args,
+                &BTreeMap::new(),
+                std::u32::MAX,
+            )
+            .map(|query| query.filter)
+        };
+
+        assert_eq!(
+            within("52.52, 13.405", "1500.5").unwrap(),
+            Some(EntityFilter::And(vec![EntityFilter::Within(
+                "location".to_string(),
+                Value::String("52.52,13.405".to_string()),
+                1500.5,
+            )]))
+        );
+        assert!(within("52.52", "1500").is_err());
+        assert!(within("52.52,13.405", "-1").is_err());
+    }
+
+    #[test]
+    fn collect_entities_follows_interfaces_and_fragments() {
+        const ID: &str = "@subgraphId(id: \"QmZ5dsusHwD1PEbx6L4dLCWkDsk1BLhrx9mPsGyPvTxPCM\")";
+        let schema = graphql_parser::parse_schema(&format!(
+            "type Subscription {id} {{ named: [Named!]!, owners: [Owner!]! }}
+             interface Named @entity {id} {{ id: ID!, name: String }}
+             type Thing implements Named @entity {id} {{ id: ID!, name: String, owner: Owner }}
+             type Other implements Named @entity {id} {{ id: ID!, name: String }}
+             type Owner @entity {id} {{ id: ID! }}
+             type Unused @entity {id} {{ id: ID! }}",
+            id = ID
+        ))
+        .unwrap();
+        let query = graphql_parser::parse_query(
+            "subscription { named { id ... on Thing { owner { id } } } }",
+        )
+        .unwrap();
+        let field = match &query.definitions[0] {
+            q::Definition::Operation(q::OperationDefinition::Subscription(subscription)) => {
+                match &subscription.selection_set.items[0] {
+                    q::Selection::Field(field) => field,
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        };
+        let subscription_type =
+            match crate::schema::ast::get_named_type(&schema, &"Subscription".to_owned()) {
+                Some(s::TypeDefinition::Object(object_type)) => object_type,
+                _ => unreachable!(),
+            };
+
+        let mut entities: Vec<_> =
+            super::collect_entities_from_query_field(&schema, subscription_type, field)
+                .into_iter()
+                .map(|(_, entity_type)| entity_type)
+                .collect();
+        entities.sort();
+        assert_eq!(vec!["Other", "Owner", "Thing"], entities);
+    }
 }
@@ -1,22 +1,41 @@
 use graphql_parser::{query as q, schema as s};
+use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::result;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use graph::components::store::*;
 use graph::prelude::*;
+use graph::util::env::env_var;
 
 use crate::prelude::*;
 use crate::query::ext::BlockConstraint;
 use crate::schema::ast as sast;
 
+use crate::store::block_cache::{cache_block_hash, cached_block_hash};
 use crate::store::query::{collect_entities_from_query_field, parse_subgraph_id};
 
+/// How often we check whether a subgraph has processed the chain head
+/// block while a query with `block: { latest: true }` waits for that
+const HEAD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+lazy_static! {
+    /// How long a query with `block: { latest: true }` waits at most for the
+    /// subgraph to process the chain head block of its network
+    static ref MAX_HEAD_WAIT: Duration =
+        Duration::from_millis(env_var("GRAPH_QUERY_MAX_HEAD_WAIT").unwrap_or(1000));
+}
+
 /// A resolver that fetches entities from a `Store`.
 pub struct StoreResolver<S> {
     logger: Logger,
     pub(crate) store: Arc<S>,
     pub(crate) block: BlockNumber,
+    /// The shortest time between two events of subscription streams once
+    /// the subgraph is synced
+    subscription_interval: Duration,
 }
 
 impl<S> Clone for StoreResolver<S>
@@ -28,6 +47,7 @@ where
             logger: self.logger.clone(),
             store: self.store.clone(),
             block: self.block.clone(),
+            subscription_interval: self.subscription_interval,
         }
     }
 }
@@ -45,6 +65,17 @@ where
             logger: logger.new(o!("component" => "StoreResolver")),
             store,
             block: BLOCK_NUMBER_MAX,
+            subscription_interval: *SUBSCRIPTION_MIN_INTERVAL,
+        }
+    }
+
+    /// Report changes to subscriptions at most every `interval` once the
+    /// subgraph is synced. Intervals shorter than the
+    /// `GRAPH_SUBSCRIPTION_MIN_INTERVAL` of the node are raised to that
+    pub fn with_subscription_interval(self, interval: Duration) -> Self {
+        StoreResolver {
+            subscription_interval: interval.max(*SUBSCRIPTION_MIN_INTERVAL),
+            ..self
         }
     }
 
@@ -64,6 +95,7 @@ where
             logger: logger.new(o!("component" => "StoreResolver")),
             store,
             block: block_ptr.number as i32,
+            subscription_interval: *SUBSCRIPTION_MIN_INTERVAL,
         };
         Ok((resolver, block_ptr))
     }
@@ -80,6 +112,9 @@ where
         {
             // Relational storage (most subgraphs); block constraints fully
             // supported
+            if bc == BlockConstraint::Head {
+                Self::wait_for_head(store, subgraph)?;
+            }
             match bc {
                 BlockConstraint::Number(number) => store
                     .block_ptr(subgraph.clone())
@@ -87,6 +122,7 @@ where
                     .and_then(|ptr| {
                         let ptr =
                             ptr.expect("we should have already checked that the subgraph exists");
+                        Self::check_history_available(store, subgraph, number)?;
                         if ptr.number < number as u64 {
                             Err(QueryExecutionError::ValueParseError(
                                 "block.number".to_owned(),
@@ -96,14 +132,15 @@ where
                                     subgraph, ptr.number, number
                                 ),
                             ))
+                        } else if ptr.number == number as u64 {
+                            Ok(ptr)
                         } else {
-                            // We don't have a way here to look the block hash up from
-                            // the database, and even if we did, there is no guarantee
-                            // that we have the block in our cache. We therefore
-                            // always return an all zeroes hash when users specify
-                            // a block number
+                            // Blocks that are not in the store get an all
+                            // zeroes hash since there is no guarantee that
+                            // we can look them up
+                            let hash = Self::block_hash(store, subgraph, number)?;
                             Ok(EthereumBlockPointer::from((
-                                web3::types::H256::zero(),
+                                hash.unwrap_or_else(web3::types::H256::zero),
                                 number as u64,
                             )))
                         }
@@ -119,9 +156,12 @@ where
                                     "no block with that hash found".to_owned(),
                                 )
                             })
-                            .map(|number| EthereumBlockPointer::from((hash, number as u64)))
+                            .and_then(|number| {
+                                Self::check_history_available(store, subgraph, number)?;
+                                Ok(EthereumBlockPointer::from((hash, number as u64)))
+                            })
                     }),
-                BlockConstraint::Latest => store
+                BlockConstraint::Latest | BlockConstraint::Head => store
                     .block_ptr(subgraph.clone())
                     .map_err(|e| StoreError::from(e).into())
                     .and_then(|ptr| {
@@ -132,7 +172,7 @@ where
             }
         } else {
             // JSONB storage or subgraph metadata; only allow BlockConstraint::Latest
-            if matches!(bc, BlockConstraint::Latest) {
+            if matches!(bc, BlockConstraint::Latest | BlockConstraint::Head) {
                 Ok(EthereumBlockPointer::from((
                     web3::types::H256::zero(),
                     BLOCK_NUMBER_MAX as u64,
@@ -147,6 +187,81 @@ where
             }
         }
     }
+
+    /// Fail with `HistoryNotAvailable` if `subgraph` does not retain the
+    /// history for the block with `number` anymore
+    fn check_history_available(
+        store: &S,
+        subgraph: &SubgraphDeploymentId,
+        number: BlockNumber,
+    ) -> Result<(), QueryExecutionError> {
+        let earliest = store
+            .earliest_retained_block(subgraph)
+            .map_err(StoreError::from)?;
+        if number < earliest {
+            Err(QueryExecutionError::HistoryNotAvailable { earliest })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The hash of the block with `number` on the network of `subgraph`,
+    /// from the block cache if possible
+    fn block_hash(
+        store: &S,
+        subgraph: &SubgraphDeploymentId,
+        number: BlockNumber,
+    ) -> Result<Option<web3::types::H256>, QueryExecutionError> {
+        let network = store.network_name(subgraph).map_err(StoreError::from)?;
+        if let Some(hash) = network
+            .as_ref()
+            .and_then(|network| cached_block_hash(network, number as u64))
+        {
+            return Ok(Some(hash));
+        }
+        let hash = store
+            .block_hash(subgraph, number)
+            .map_err(StoreError::from)?;
+        if let (Some(network), Some(hash)) = (&network, hash) {
+            cache_block_hash(network, number as u64, hash);
+        }
+        Ok(hash)
+    }
+
+    /// Wait until `subgraph` has processed the block that is the chain head
+    /// of its network now, but for no longer than `GRAPH_QUERY_MAX_HEAD_WAIT`.
+    /// Subgraphs that are not synced are not waited for since they will
+    /// not get to the chain head soon
+    fn wait_for_head(
+        store: &S,
+        subgraph: &SubgraphDeploymentId,
+    ) -> Result<(), QueryExecutionError> {
+        let head = match store
+            .network_head_block_number(subgraph)
+            .map_err(StoreError::from)?
+        {
+            Some(head) => head,
+            None => return Ok(()),
+        };
+        if !store
+            .is_deployment_synced(subgraph.clone())
+            .map_err(StoreError::from)?
+        {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + *MAX_HEAD_WAIT;
+        loop {
+            let processed = store
+                .block_ptr(subgraph.clone())
+                .map_err(StoreError::from)?
+                .map_or(0, |ptr| ptr.number);
+            if processed >= head || Instant::now() + HEAD_POLL_INTERVAL > deadline {
+                return Ok(());
+            }
+            thread::sleep(HEAD_POLL_INTERVAL);
+        }
+    }
 }
 
 impl<S> Resolver for StoreResolver<S>
@@ -234,11 +349,12 @@ where
 
         // Subscribe to the store and return the entity change stream
         let deployment_id = parse_subgraph_id(object_type)?;
-        Ok(self.store.subscribe(entities).throttle_while_syncing(
+        Ok(self.store.subscribe(entities).throttle(
             &self.logger,
             self.store.clone(),
             deployment_id,
             *SUBSCRIPTION_THROTTLE_INTERVAL,
+            self.subscription_interval,
         ))
     }
 }
@@ -51,6 +51,9 @@ where
 
     /// Maximum value for the `first` argument.
     pub max_first: u32,
+
+    /// The hooks to run around each field.
+    pub hooks: Arc<ExecutionHooks>,
 }
 
 pub fn execute_subscription<R>(
@@ -82,6 +85,7 @@ where
         query: query.clone(),
         deadline: None,
         max_first: options.max_first,
+        hooks: options.hooks,
         cached: AtomicBool::new(true),
     };
 
@@ -136,11 +140,63 @@ fn resolve_field_stream(
     field: &q::Field,
     _argument_values: HashMap<&q::Name, q::Value>,
 ) -> Result<StoreEventStreamBox, SubscriptionError> {
+    // The resolver only sees the field, and needs the fields in fragments
+    // to know which entity types the subscription depends on
+    let field = q::Field {
+        selection_set: inline_fragment_spreads(&ctx.query, &field.selection_set, &mut vec![]),
+        ..field.clone()
+    };
     ctx.resolver
-        .resolve_field_stream(&ctx.query.schema.document, object_type, field)
+        .resolve_field_stream(&ctx.query.schema.document, object_type, &field)
         .map_err(SubscriptionError::from)
 }
 
+/// Replace the fragment spreads in `selection_set` with inline fragments
+/// that contain the selections of the fragments. Fragments in `visited`
+/// are not expanded again
+fn inline_fragment_spreads<'a>(
+    query: &'a crate::execution::Query,
+    selection_set: &q::SelectionSet,
+    visited: &mut Vec<&'a q::Name>,
+) -> q::SelectionSet {
+    let items = selection_set
+        .items
+        .iter()
+        .filter_map(|selection| match selection {
+            q::Selection::Field(field) => Some(q::Selection::Field(q::Field {
+                selection_set: inline_fragment_spreads(query, &field.selection_set, visited),
+                ..field.clone()
+            })),
+            q::Selection::InlineFragment(fragment) => {
+                Some(q::Selection::InlineFragment(q::InlineFragment {
+                    selection_set: inline_fragment_spreads(query, &fragment.selection_set, visited),
+                    ..fragment.clone()
+                }))
+            }
+            q::Selection::FragmentSpread(spread) => {
+                let fragment = query.get_fragment(&spread.fragment_name)?;
+                if visited.contains(&&fragment.name) {
+                    return None;
+                }
+                visited.push(&fragment.name);
+                let selection_set =
+                    inline_fragment_spreads(query, &fragment.selection_set, visited);
+                visited.pop();
+                Some(q::Selection::InlineFragment(q::InlineFragment {
+                    position: spread.position,
+                    type_condition: Some(fragment.type_condition.clone()),
+                    directives: spread.directives.clone(),
+                    selection_set,
+                }))
+            }
+        })
+        .collect();
+    q::SelectionSet {
+        span: selection_set.span,
+        items,
+    }
+}
+
 fn map_source_to_response_stream(
     ctx: &ExecutionContext<impl Resolver + 'static>,
     source_stream: StoreEventStreamBox,
@@ -150,6 +206,7 @@ fn map_source_to_response_stream(
     let resolver = ctx.resolver.clone();
     let query = ctx.query.cheap_clone();
     let max_first = ctx.max_first;
+    let hooks = ctx.hooks.clone();
 
     // Create a stream with a single empty event. By chaining this in front
     // of the real events, we trick the subscription into executing its query
@@ -175,6 +232,7 @@ fn map_source_to_response_stream(
                     event,
                     timeout,
                     max_first,
+                    hooks.clone(),
                 )
                 .boxed(),
             }),
@@ -188,6 +246,7 @@ async fn execute_subscription_event(
     event: Arc<StoreEvent>,
     timeout: Option<Duration>,
     max_first: u32,
+    hooks: Arc<ExecutionHooks>,
 ) -> QueryResult {
     debug!(logger, "Execute subscription event"; "event" => format!("{:?}", event));
 
@@ -198,6 +257,7 @@ async fn execute_subscription_event(
         query,
         deadline: timeout.map(|t| Instant::now() + t),
         max_first,
+        hooks,
         cached: AtomicBool::new(true),
     };
 
@@ -210,7 +270,13 @@ async fn execute_subscription_event(
     // once, from flooding the blocking thread pool and the DB connection pool.
     let _permit = SUBSCRIPTION_QUERY_SEMAPHORE.acquire();
     let result = graph::spawn_blocking_allow_panic(async move {
-        execute_root_selection_set(&ctx, &ctx.query.selection_set, &subscription_type, None)
+        execute_root_selection_set(
+            &ctx,
+            &ctx.query.selection_set,
+            &subscription_type,
+            None,
+            None,
+        )
     })
     .await
     // Performance: Taking the low road here for expediency. Ideally
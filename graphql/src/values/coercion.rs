@@ -48,10 +48,12 @@ impl MaybeCoercible<ScalarType> for Value {
                 Ok(Value::String(n.as_i64().ok_or(Value::Int(n))?.to_string()))
             }
             ("Bytes", v @ Value::String(_)) => Ok(v),
+            ("GeoPoint", v @ Value::String(_)) => Ok(v),
             ("BigInt", v @ Value::String(_)) => Ok(v),
             ("BigInt", Value::Int(n)) => {
                 Ok(Value::String(n.as_i64().ok_or(Value::Int(n))?.to_string()))
             }
+            ("JSONObject", v @ Value::Object(_)) => Ok(v),
             (_, v) => Err(v),
         }
     }
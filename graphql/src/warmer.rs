@@ -0,0 +1,260 @@
+//! Warm the query cache for new blocks. When a deployment has processed a
+//! block, the queries that are configured for it are run right away so
+//! that their results are in the query cache for that block before clients
+//! send the same queries. This only helps for deployments whose queries
+//! are cached, i.e., that are listed in `GRAPH_CACHED_SUBGRAPH_IDS`.
+
+use graphql_parser::query as q;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use graph::prelude::{
+    debug, format_err, futures03::StreamExt, info, o, serde_json, warn, Error, Future,
+    GraphQlRunner as _, Logger, Query, QueryVariables, Store, Stream01CompatExt,
+    SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphDeploymentStore, TypedEntity,
+};
+
+use crate::runner::GraphQlRunner;
+
+/// A query to run whenever `deployment` has processed a new block
+#[derive(Clone, Debug)]
+pub struct WarmQuery {
+    pub deployment: SubgraphDeploymentId,
+    pub document: q::Document,
+    pub variables: Option<QueryVariables>,
+    pub operation_name: Option<String>,
+}
+
+/// Read the queries to warm the cache with from `path`. The file has one
+/// JSON object per line, of the form `{"deployment": "Qm..", "query":
+/// "{ .. }", "variables": { .. }, "operationName": ".."}`, where
+/// `variables` and `operationName` are optional. Queries are only served
+/// from the cache if they have the same selections and variables as the
+/// query that was cached. Empty lines and lines starting with `#` are
+/// ignored
+pub fn read_warm_queries(path: &Path) -> Result<Vec<WarmQuery>, Error> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format_err!("failed to read {}: {}", path.display(), e))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(number, line)| {
+            parse_warm_query(line).map_err(|e| {
+                format_err!(
+                    "invalid query on line {} of {}: {}",
+                    number + 1,
+                    path.display(),
+                    e
+                )
+            })
+        })
+        .collect()
+}
+
+fn parse_warm_query(line: &str) -> Result<WarmQuery, Error> {
+    let mut value: serde_json::Value = serde_json::from_str(line)?;
+    let deployment = value
+        .get("deployment")
+        .and_then(|deployment| deployment.as_str())
+        .ok_or_else(|| format_err!("`deployment` must be a string"))?;
+    let deployment = SubgraphDeploymentId::new(deployment)
+        .map_err(|()| format_err!("`{}` is not a valid deployment id", deployment))?;
+    let document = value
+        .get("query")
+        .and_then(|query| query.as_str())
+        .ok_or_else(|| format_err!("`query` must be a string"))
+        .and_then(|query| graphql_parser::parse_query(query).map_err(|e| format_err!("{}", e)))?;
+    let variables = match value.get_mut("variables").map(serde_json::Value::take) {
+        None | Some(serde_json::Value::Null) => None,
+        Some(variables) => Some(serde_json::from_value(variables)?),
+    };
+    let operation_name = match value.get("operationName") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(serde_json::Value::String(name)) => Some(name.clone()),
+        Some(_) => return Err(format_err!("`operationName` must be a string")),
+    };
+    Ok(WarmQuery {
+        deployment,
+        document,
+        variables,
+        operation_name,
+    })
+}
+
+/// Runs the `WarmQuery`s for a deployment whenever the deployment has
+/// processed a block
+pub struct CacheWarmer<S> {
+    logger: Logger,
+    store: Arc<S>,
+    runner: Arc<GraphQlRunner<S>>,
+    queries: HashMap<SubgraphDeploymentId, Arc<Vec<WarmQuery>>>,
+}
+
+impl<S> CacheWarmer<S>
+where
+    S: Store + SubgraphDeploymentStore,
+{
+    pub fn new(
+        logger: &Logger,
+        store: Arc<S>,
+        runner: Arc<GraphQlRunner<S>>,
+        queries: Vec<WarmQuery>,
+    ) -> Self {
+        let mut by_deployment: HashMap<_, Vec<_>> = HashMap::new();
+        for query in queries {
+            by_deployment
+                .entry(query.deployment.clone())
+                .or_default()
+                .push(query);
+        }
+        CacheWarmer {
+            logger: logger.new(o!("component" => "CacheWarmer")),
+            store,
+            runner,
+            queries: by_deployment
+                .into_iter()
+                .map(|(deployment, queries)| (deployment, Arc::new(queries)))
+                .collect(),
+        }
+    }
+
+    /// Listen for changes to the block pointers of deployments and run
+    /// their queries. A new block that arrives while the queries for the
+    /// previous block are still running is skipped
+    pub fn start(self) {
+        if self.queries.is_empty() {
+            return;
+        }
+        info!(self.logger, "Warming the query cache";
+              "deployments" => self.queries.len(),
+              "queries" => self.queries.values().map(|queries| queries.len()).sum::<usize>());
+
+        let busy: HashMap<_, _> = self
+            .queries
+            .keys()
+            .map(|deployment| (deployment.clone(), Arc::new(AtomicBool::new(false))))
+            .collect();
+        let mut events = self
+            .store
+            .subscribe(vec![SubgraphDeploymentEntity::subgraph_entity_pair()])
+            .compat();
+
+        graph::spawn(async move {
+            while let Some(Ok(event)) = events.next().await {
+                for change in &event.changes {
+                    if change.entity_type != SubgraphDeploymentEntity::TYPENAME {
+                        continue;
+                    }
+                    let (deployment, queries) = match self
+                        .queries
+                        .iter()
+                        .find(|(deployment, _)| deployment.as_str() == change.entity_id)
+                    {
+                        Some((deployment, queries)) => (deployment.clone(), queries.clone()),
+                        None => continue,
+                    };
+                    let busy = busy[&deployment].clone();
+                    if busy.swap(true, Ordering::SeqCst) {
+                        continue;
+                    }
+                    let logger = self.logger.clone();
+                    let store = self.store.clone();
+                    let runner = self.runner.clone();
+                    graph::spawn_blocking_allow_panic(async move {
+                        warm(
+                            &logger,
+                            store.as_ref(),
+                            runner.as_ref(),
+                            &deployment,
+                            &queries,
+                        );
+                        busy.store(false, Ordering::SeqCst);
+                    });
+                }
+            }
+            warn!(
+                self.logger,
+                "Stopped warming the query cache since the store event stream ended"
+            );
+        });
+    }
+}
+
+fn warm<S: Store + SubgraphDeploymentStore>(
+    logger: &Logger,
+    store: &S,
+    runner: &GraphQlRunner<S>,
+    deployment: &SubgraphDeploymentId,
+    queries: &[WarmQuery],
+) {
+    let schema = match store.api_schema(deployment) {
+        Ok(schema) => schema,
+        Err(e) => {
+            debug!(logger, "Failed to get the schema of deployment";
+                   "subgraph_id" => deployment.as_str(),
+                   "error" => e.to_string());
+            return;
+        }
+    };
+
+    let start = Instant::now();
+    let mut failed = 0;
+    for query in queries {
+        let query = Query::new(
+            schema.clone(),
+            query.document.clone(),
+            query.variables.clone(),
+        )
+        .with_operation_name(query.operation_name.clone())
+        .with_client(String::from("cache-warmer"));
+        match runner.run_query(query).wait() {
+            Ok(result) if result.errors.is_none() => {}
+            _ => failed += 1,
+        }
+    }
+    debug!(logger, "Warmed the query cache";
+           "subgraph_id" => deployment.as_str(),
+           "queries" => queries.len(),
+           "failed" => failed,
+           "time_ms" => start.elapsed().as_millis());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_warm_queries() {
+        let query = parse_warm_query(
+            r#"{"deployment": "QmDeployment", "query": "{ tokens(first: $first) { id } }",
+                "variables": { "first": 10 }}"#,
+        )
+        .unwrap();
+        assert_eq!("QmDeployment", query.deployment.as_str());
+        assert_eq!(
+            Some(&q::Value::Int(10.into())),
+            query.variables.as_ref().and_then(|vars| vars.get("first"))
+        );
+
+        let query =
+            parse_warm_query(r#"{"deployment": "QmDeployment", "query": "{ tokens { id } }"}"#)
+                .unwrap();
+        assert!(query.variables.is_none());
+        assert!(query.operation_name.is_none());
+
+        let query = parse_warm_query(
+            r#"{"deployment": "QmDeployment", "query": "query a { a } query b { b }",
+                "operationName": "b"}"#,
+        )
+        .unwrap();
+        assert_eq!(Some("b".to_owned()), query.operation_name);
+
+        assert!(parse_warm_query(r#"{"deployment": "Qm-invalid", "query": "{ a }"}"#).is_err());
+        assert!(parse_warm_query(r#"{"deployment": "QmDeployment", "query": "{"}"#).is_err());
+        assert!(parse_warm_query(r#"{"query": "{ a }"}"#).is_err());
+    }
+}
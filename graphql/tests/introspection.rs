@@ -3,14 +3,15 @@ extern crate pretty_assertions;
 
 use graphql_parser::{query as q, schema as s};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use graph::prelude::{
     o, slog, Logger, Query, QueryExecutionError, QueryResult, Schema, SubgraphDeploymentId,
 };
 use graph_graphql::prelude::{
-    api_schema, execute_query, object, object_value, ExecutionContext, ObjectOrInterface,
-    Query as PreparedQuery, QueryExecutionOptions, Resolver,
+    api_schema, execute_query, object, object_value, DirectiveRegistry, ExecutionContext,
+    ExecutionHook, ExecutionHooks, HookField, ObjectOrInterface, Query as PreparedQuery,
+    QueryExecutionOptions, Resolver,
 };
 
 /// Mock resolver used in tests that don't need a resolver.
@@ -544,10 +545,19 @@ fn expected_mock_schema_introspection() -> q::Value {
 }
 
 /// Execute an introspection query.
-fn introspection_query(schema: Schema, query: &str) -> QueryResult {
+fn introspection_query(schema: impl Into<Arc<Schema>>, query: &str) -> QueryResult {
+    introspection_query_with_hooks(schema, query, ExecutionHooks::default())
+}
+
+/// Execute an introspection query and run `hooks` around its fields.
+fn introspection_query_with_hooks(
+    schema: impl Into<Arc<Schema>>,
+    query: &str,
+    hooks: ExecutionHooks,
+) -> QueryResult {
     // Create the query
     let query = Query::new(
-        Arc::new(schema),
+        schema.into(),
         graphql_parser::parse_query(query).unwrap(),
         None,
     );
@@ -558,6 +568,7 @@ fn introspection_query(schema: Schema, query: &str) -> QueryResult {
         resolver: MockResolver,
         deadline: None,
         max_first: std::u32::MAX,
+        hooks: Arc::new(hooks),
     };
 
     let result = PreparedQuery::new(query, None, 100)
@@ -1255,3 +1266,198 @@ fn introspection_possible_types() {
         )])
     )
 }
+
+#[test]
+fn introspection_responses_are_cached_per_schema() {
+    let query = "query { __type(name: \"Query\") { fields { name } } }";
+    let field_names = |schema: &Arc<Schema>| -> Vec<q::Value> {
+        match introspection_query(schema.clone(), query).data.unwrap() {
+            q::Value::Object(mut map) => match map.remove("__type") {
+                Some(q::Value::Object(mut map)) => match map.remove("fields") {
+                    Some(q::Value::List(fields)) => fields,
+                    _ => panic!("expected a list of fields"),
+                },
+                _ => panic!("expected a type"),
+            },
+            _ => panic!("expected an object"),
+        }
+    };
+
+    let schema = Arc::new(mock_schema());
+    assert_eq!(3, field_names(&schema).len());
+    assert_eq!(3, field_names(&schema).len());
+
+    // The same deployment with a different schema does not get the
+    // responses for the old schema
+    let changed = Arc::new(
+        Schema::parse(
+            "scalar ID type Query @entity { id: ID! }",
+            SubgraphDeploymentId::new("mockschema").unwrap(),
+        )
+        .unwrap(),
+    );
+    assert_eq!(1, field_names(&changed).len());
+}
+
+#[test]
+fn runs_hooks_around_fields() {
+    #[derive(Default)]
+    struct Labels(Mutex<Vec<(String, Option<q::Value>, bool)>>);
+
+    impl ExecutionHook for Labels {
+        fn after_field(
+            &self,
+            field: &HookField,
+            result: &Result<q::Value, Vec<QueryExecutionError>>,
+        ) {
+            let tag = field
+                .directives
+                .iter()
+                .find(|directive| directive.name == "label")
+                .and_then(|directive| directive.argument("tag").cloned());
+            self.0
+                .lock()
+                .unwrap()
+                .push((field.field.name.clone(), tag, result.is_ok()));
+        }
+    }
+
+    let labels = Arc::new(Labels::default());
+    let hooks = ExecutionHooks::new(DirectiveRegistry::default().register("label", &["tag"]))
+        .with_hook(labels.clone());
+    let schema = Schema::parse(
+        "scalar ID type Query @entity { id: ID! }",
+        SubgraphDeploymentId::new("hookschema").unwrap(),
+    )
+    .unwrap();
+    let result = introspection_query_with_hooks(
+        schema,
+        "query { __type(name: \"Query\") @label(tag: \"types\") { name @other } }",
+        hooks,
+    );
+    assert!(result.errors.is_none());
+
+    let labels = labels.0.lock().unwrap();
+    assert_eq!(
+        vec![
+            ("name".to_owned(), None, true),
+            (
+                "__type".to_owned(),
+                Some(q::Value::String("types".to_owned())),
+                true
+            ),
+        ],
+        *labels
+    );
+}
+
+#[test]
+fn introspection_reports_deprecated_fields() {
+    let mut schema = Schema::parse(
+        "type Thing @entity {
+           id: ID!
+           total: Int!
+           count: Int! @deprecated(reason: \"Use `total`\")
+         }",
+        SubgraphDeploymentId::new("deprecatedschema").unwrap(),
+    )
+    .unwrap();
+    schema.document = api_schema(&schema.document).unwrap();
+    let schema = Arc::new(schema);
+
+    let fields = |include_deprecated: bool| -> q::Value {
+        let query = format!(
+            "query {{ __type(name: \"Thing\") {{ \
+               fields(includeDeprecated: {}) {{ name isDeprecated deprecationReason }} \
+             }} }}",
+            include_deprecated
+        );
+        match introspection_query(schema.clone(), &query).data.unwrap() {
+            q::Value::Object(mut map) => match map.remove("__type") {
+                Some(q::Value::Object(mut map)) => map.remove("fields").unwrap(),
+                _ => panic!("expected a type"),
+            },
+            _ => panic!("expected an object"),
+        }
+    };
+    let field = |name: &str, reason: Option<&str>| {
+        object_value(vec![
+            ("name", q::Value::String(name.to_owned())),
+            ("isDeprecated", q::Value::Boolean(reason.is_some())),
+            (
+                "deprecationReason",
+                reason.map_or(q::Value::Null, |reason| q::Value::String(reason.to_owned())),
+            ),
+        ])
+    };
+
+    assert_eq!(
+        q::Value::List(vec![field("id", None), field("total", None)]),
+        fields(false)
+    );
+    assert_eq!(
+        q::Value::List(vec![
+            field("id", None),
+            field("total", None),
+            field("count", Some("Use `total`")),
+        ]),
+        fields(true)
+    );
+}
+
+#[test]
+fn type_lookups_match_full_introspection() {
+    let schema = |id: &str| {
+        let mut schema =
+            Schema::parse(COMPLEX_SCHEMA, SubgraphDeploymentId::new(id).unwrap()).unwrap();
+        schema.document = api_schema(&schema.document).unwrap();
+        Arc::new(schema)
+    };
+    let selection = "name kind fields { name type { name kind ofType { name kind } } } \
+                     interfaces { name } possibleTypes { name }";
+
+    // Look up single types against a schema that was never fully
+    // introspected, and compare with what a full introspection reports
+    let lookups = schema("typelookups");
+    let full = schema("typelookupsfull");
+    let types = match introspection_query(
+        full,
+        &format!("query {{ __schema {{ types {{ {} }} }} }}", selection),
+    )
+    .data
+    .unwrap()
+    {
+        q::Value::Object(mut map) => match map.remove("__schema") {
+            Some(q::Value::Object(mut map)) => match map.remove("types") {
+                Some(q::Value::List(types)) => types,
+                _ => panic!("expected a list of types"),
+            },
+            _ => panic!("expected a schema"),
+        },
+        _ => panic!("expected an object"),
+    };
+
+    for name in &["RegEntry", "Meme", "Query"] {
+        let expected = types
+            .iter()
+            .find(|t| match t {
+                q::Value::Object(map) => {
+                    map.get("name") == Some(&q::Value::String(name.to_string()))
+                }
+                _ => false,
+            })
+            .cloned()
+            .unwrap();
+        let query = format!("query {{ __type(name: \"{}\") {{ {} }} }}", name, selection);
+        assert_eq!(
+            object_value(vec![("__type", expected)]),
+            introspection_query(lookups.clone(), &query).data.unwrap()
+        );
+    }
+
+    let response = introspection_query(lookups, "query { __type(name: \"Nope\") { name } }");
+    assert_eq!(
+        object_value(vec![("__type", q::Value::Null)]),
+        response.data.unwrap()
+    );
+}
@@ -3,15 +3,15 @@ extern crate pretty_assertions;
 
 use graphql_parser::{query as q, Pos};
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::iter::FromIterator;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use graph::prelude::{
     futures03::stream::StreamExt, futures03::FutureExt, futures03::TryFutureExt, o, slog, tokio,
-    Entity, EntityKey, EntityOperation, EthereumBlockPointer, FutureExtension, Logger, Query,
-    QueryError, QueryExecutionError, QueryResult, QueryVariables, Schema, Store,
+    ApiVersion, Entity, EntityKey, EntityOperation, EthereumBlockPointer, FutureExtension, Logger,
+    Query, QueryError, QueryExecutionError, QueryResult, QueryVariables, Schema, Store,
     SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphDeploymentStore, SubgraphManifest,
     Subscription, SubscriptionError, Value,
 };
@@ -93,6 +93,8 @@ fn insert_test_entities(store: &impl Store, id: SubgraphDeploymentId) {
         schema: schema.clone(),
         data_sources: vec![],
         graft: None,
+        features: BTreeSet::new(),
+        api_version: ApiVersion::default(),
         templates: vec![],
     };
 
@@ -783,11 +785,19 @@ async fn query_complexity_subscriptions() {
         max_complexity,
         max_depth: 100,
         max_first: std::u32::MAX,
+        hooks: Arc::new(ExecutionHooks::default()),
     };
 
     // This query is exactly at the maximum complexity.
     // FIXME: Not collecting the stream because that will hang the test.
-    let _ignore_stream = execute_subscription(Subscription { query }, options).unwrap();
+    let _ignore_stream = execute_subscription(
+        Subscription {
+            query,
+            interval: None,
+        },
+        options,
+    )
+    .unwrap();
 
     let query = Query::new(
         Arc::new(api_test_schema()),
@@ -820,10 +830,17 @@ async fn query_complexity_subscriptions() {
         max_complexity,
         max_depth: 100,
         max_first: std::u32::MAX,
+        hooks: Arc::new(ExecutionHooks::default()),
     };
 
     // The extra introspection causes the complexity to go over.
-    let result = execute_subscription(Subscription { query }, options);
+    let result = execute_subscription(
+        Subscription {
+            query,
+            interval: None,
+        },
+        options,
+    );
     match result {
         Err(SubscriptionError::GraphQLError(e)) => match e[0] {
             QueryExecutionError::TooComplex(1_010_200, _) => (), // Expected
@@ -1090,6 +1107,83 @@ fn can_filter_by_relationship_fields() {
     );
 }
 
+#[test]
+fn can_look_up_several_entities_by_id() {
+    let result = execute_query_document(
+        graphql_parser::parse_query(
+            "
+        query {
+            a: musician(id: \"m1\") { id name mainBand { id } }
+            b: musician(id: \"m2\") { name }
+            c: musician(id: \"m1\") { name }
+            d: musician(id: \"m99\") { name }
+        }
+        ",
+        )
+        .expect("invalid test query"),
+    );
+
+    assert!(
+        result.errors.is_none(),
+        format!("Unexpected errors return for query: {:#?}", result.errors)
+    );
+    assert_eq!(
+        result.data,
+        Some(object_value(vec![
+            (
+                "a",
+                object_value(vec![
+                    ("id", q::Value::String(String::from("m1"))),
+                    ("name", q::Value::String(String::from("John"))),
+                    (
+                        "mainBand",
+                        object_value(vec![("id", q::Value::String(String::from("b1")))])
+                    )
+                ])
+            ),
+            (
+                "b",
+                object_value(vec![("name", q::Value::String(String::from("Lisa")))])
+            ),
+            (
+                "c",
+                object_value(vec![("name", q::Value::String(String::from("John")))])
+            ),
+            ("d", q::Value::Null)
+        ]))
+    );
+}
+
+#[test]
+fn can_query_meta() {
+    let result = execute_query_document(
+        graphql_parser::parse_query(
+            "
+        query {
+            _meta { startBlock earliestBlock lastHealthyBlock }
+        }
+        ",
+        )
+        .expect("invalid test query"),
+    );
+
+    assert!(
+        result.errors.is_none(),
+        format!("Unexpected errors return for query: {:#?}", result.errors)
+    );
+    assert_eq!(
+        result.data,
+        Some(object_value(vec![(
+            "_meta",
+            object_value(vec![
+                ("startBlock", q::Value::Int(q::Number::from(0))),
+                ("earliestBlock", q::Value::Int(q::Number::from(0))),
+                ("lastHealthyBlock", q::Value::Int(q::Number::from(1))),
+            ])
+        )]))
+    );
+}
+
 #[test]
 fn cannot_filter_by_derved_relationship_fields() {
     let result = execute_query_document(
@@ -1122,6 +1216,36 @@ fn cannot_filter_by_derved_relationship_fields() {
     };
 }
 
+#[test]
+fn strict_api_version_rejects_null_comparisons() {
+    let query = "query { musicians(where: { name_gt: null }) { id } }";
+
+    // The store can not compare with `null` either
+    let result = execute_query_document(graphql_parser::parse_query(query).unwrap());
+    match &result.errors.expect("null comparisons fail")[0] {
+        QueryError::ExecutionError(QueryExecutionError::InvalidArgumentError(..)) => {
+            panic!("only strict API versions check null comparisons")
+        }
+        _ => (),
+    };
+
+    let runner = GraphQlRunner::new(&*LOGGER, STORE.clone(), &vec![]);
+    let query = Query::new(
+        Arc::new(api_test_schema()),
+        graphql_parser::parse_query(query).unwrap(),
+        None,
+    )
+    .with_api_version(ApiVersion::V0_0_2);
+    let result = return_err!(runner.execute(query, None, None, None));
+    match &result.errors.expect("null comparisons are rejected")[0] {
+        QueryError::ExecutionError(QueryExecutionError::InvalidArgumentError(_, s, v)) => {
+            assert_eq!(s, "where.name_gt");
+            assert_eq!(v, &q::Value::Null);
+        }
+        e => panic!(format!("expected InvalidArgumentError, got {}", e)),
+    };
+}
+
 #[tokio::test]
 async fn subscription_gets_result_even_without_events() {
     let logger = Logger::root(slog::Discard, o!());
@@ -1147,11 +1271,19 @@ async fn subscription_gets_result_even_without_events() {
         max_complexity: None,
         max_depth: 100,
         max_first: std::u32::MAX,
+        hooks: Arc::new(ExecutionHooks::default()),
     };
 
     // Execute the subscription and expect at least one result to be
     // available in the result stream
-    let stream = execute_subscription(Subscription { query }, options).unwrap();
+    let stream = execute_subscription(
+        Subscription {
+            query,
+            interval: None,
+        },
+        options,
+    )
+    .unwrap();
     let results: Vec<_> = stream
         .take(1)
         .collect()
@@ -1330,3 +1462,47 @@ fn block_extension() {
         assert_eq!(None, result.extensions);
     }
 }
+
+#[test]
+fn mixed_introspection_and_data_queries() {
+    let query =
+        "query { musicians(first: 2, orderBy: id) { id } __type(name: \"Musician\") { name } }";
+
+    // Run the query twice so that the second run gets both parts from the
+    // caches, and with only the data fields so that the data part is
+    // shared between both
+    for query in &[
+        query,
+        query,
+        "query { musicians(first: 2, orderBy: id) { id } }",
+    ] {
+        let result = execute_query_document(graphql_parser::parse_query(query).unwrap());
+        assert!(
+            result.errors.is_none(),
+            "unexpected error: {:?}",
+            result.errors
+        );
+        let data = match result.data.unwrap() {
+            q::Value::Object(map) => map,
+            _ => panic!("expected an object"),
+        };
+        assert_eq!(
+            Some(&q::Value::List(vec![
+                object_value(vec![("id", q::Value::String(String::from("m1")))]),
+                object_value(vec![("id", q::Value::String(String::from("m2")))]),
+            ])),
+            data.get("musicians")
+        );
+        if query.contains("__type") {
+            assert_eq!(
+                Some(&object_value(vec![(
+                    "name",
+                    q::Value::String(String::from("Musician"))
+                )])),
+                data.get("__type")
+            );
+        } else {
+            assert_eq!(None, data.get("__type"));
+        }
+    }
+}
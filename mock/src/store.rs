@@ -6,7 +6,7 @@ use graph::components::store::*;
 use graph::data::subgraph::schema::*;
 use graph::prelude::*;
 use graph_graphql::prelude::api_schema;
-use web3::types::{Address, H256};
+use web3::types::{Address, H2048, H256};
 
 mock! {
     pub Store {
@@ -21,6 +21,17 @@ mock! {
         fn uses_relational_schema(&self, subgraph_id: &SubgraphDeploymentId) -> Result<bool, Error>;
 
         fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error>;
+
+        fn network_head_block_number(
+            &self,
+            subgraph_id: &SubgraphDeploymentId,
+        ) -> Result<Option<u64>, Error>;
+
+        fn block_hash(
+            &self,
+            subgraph_id: &SubgraphDeploymentId,
+            number: BlockNumber,
+        ) -> Result<Option<H256>, Error>;
     }
 
     trait ChainStore: Send + Sync + 'static {
@@ -42,6 +53,8 @@ mock! {
 
         fn blocks(&self, hashes: Vec<H256>) -> Result<Vec<LightEthereumBlock>, Error>;
 
+        fn block_data(&self, hash: H256) -> Result<Option<serde_json::Value>, Error>;
+
         fn ancestor_block(
             &self,
             block_ptr: EthereumBlockPointer,
@@ -53,6 +66,10 @@ mock! {
         fn block_hashes_by_block_number(&self, number: u64) -> Result<Vec<H256>, Error>;
 
         fn confirm_block_hash(&self, number: u64, hash: &H256) -> Result<usize, Error>;
+
+        fn slowest_synced_block(&self) -> Result<Option<BlockNumber>, Error>;
+
+        fn logs_blooms(&self, from: u64, to: u64) -> Result<Vec<(u64, H2048)>, Error>;
     }
 }
 
@@ -107,6 +124,22 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn find_groups(
+        &self,
+        _: EntityGroupQuery,
+    ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    fn changes_in_block(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _entity_types: &[String],
+        _block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        unimplemented!()
+    }
+
     fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError> {
         unimplemented!()
     }
@@ -136,6 +169,23 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn set_publication(
+        &self,
+        _subgraph: &SubgraphDeploymentId,
+        _publish: bool,
+        _replica_identity: Option<ReplicaIdentity>,
+    ) -> Result<Option<String>, StoreError> {
+        unimplemented!()
+    }
+
+    fn rewind(
+        &self,
+        _subgraph: &SubgraphDeploymentId,
+        _block_ptr: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
     fn revert_block_operations(
         &self,
         _subgraph_id: SubgraphDeploymentId,
@@ -182,6 +232,21 @@ impl Store for MockStore {
     ) -> Result<Option<BlockNumber>, StoreError> {
         unimplemented!()
     }
+
+    fn record_sync_sample(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block_number: u64,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn sync_samples(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<SyncSample>, StoreError> {
+        unimplemented!()
+    }
 }
 
 pub fn mock_store_with_users_subgraph() -> (Arc<MockStore>, SubgraphDeploymentId) {
@@ -0,0 +1,361 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use prometheus::Registry;
+use std::process::exit;
+use std::sync::Arc;
+
+use graph::log::logger;
+use graph::prelude::{format_err, Error, Logger};
+use graph_core::MetricsRegistry;
+use graph_node::config::Config;
+use graph_node::manager::commands;
+use graph_store_postgres::command_support::PooledPgConnection;
+use graph_store_postgres::connection_pool::create_connection_pool;
+
+fn deployment_arg() -> Arg<'static, 'static> {
+    Arg::with_name("deployment")
+        .required(true)
+        .help("a subgraph name, deployment id, or database schema (sgdNNN)")
+}
+
+fn block_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("block-hash")
+            .required(true)
+            .help("the hash of the block"),
+        Arg::with_name("block-number")
+            .required(true)
+            .help("the number of the block"),
+    ]
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("graphman")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Operator tool for maintaining a graph-node installation")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("postgres-url")
+                .long("postgres-url")
+                .value_name("URL")
+                .env("POSTGRES_URL")
+                .required_unless("config")
+                .conflicts_with("config")
+                .help("Location of the Postgres database used for storing entities"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .short("c")
+                .value_name("FILE")
+                .env("GRAPH_NODE_CONFIG")
+                .help("the name of the configuration file"),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .help("Enable debug logging"),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Show information about deployments")
+                .arg(deployment_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("unassign")
+                .about("Stop indexing a deployment by removing its assignment")
+                .arg(deployment_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("reassign")
+                .about("Assign a deployment to a different index node")
+                .arg(deployment_arg())
+                .arg(
+                    Arg::with_name("node")
+                        .required(true)
+                        .help("the id of the node to assign the deployment to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("place")
+                .about("Check deployments against the placement rules in the configuration file")
+                .arg(
+                    Arg::with_name("deployment")
+                        .help("only check this deployment instead of all current and pending ones"),
+                )
+                .arg(
+                    Arg::with_name("apply")
+                        .long("apply")
+                        .help("reassign deployments that are not on a node the rules allow"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rewind")
+                .about("Rewind an unassigned deployment to a given block")
+                .arg(deployment_arg())
+                .args(&block_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("unused")
+                .about("Manage deployments that are not used by any subgraph")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("record")
+                        .about("Record all deployments that are currently not in use"),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List recorded unused deployments")
+                        .arg(
+                            Arg::with_name("all")
+                                .long("all")
+                                .help("also list deployments that have been removed"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove recorded unused deployments and all their data")
+                        .arg(
+                            Arg::with_name("deployment")
+                                .long("deployment")
+                                .value_name("ID")
+                                .help("only remove this deployment"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Show the sizes of the tables of a deployment")
+                .arg(deployment_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("index")
+                .about("Manage indexes on the tables of a deployment")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("Create an index on attributes of an entity type")
+                        .arg(deployment_arg())
+                        .arg(
+                            Arg::with_name("entity")
+                                .required(true)
+                                .help("the entity type"),
+                        )
+                        .arg(
+                            Arg::with_name("field")
+                                .required(true)
+                                .multiple(true)
+                                .help("the attributes to index"),
+                        )
+                        .arg(
+                            Arg::with_name("method")
+                                .long("method")
+                                .value_name("METHOD")
+                                .default_value("btree")
+                                .help("the index method, e.g., btree or gist"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the indexes on the table for an entity type")
+                        .arg(deployment_arg())
+                        .arg(
+                            Arg::with_name("entity")
+                                .required(true)
+                                .help("the entity type"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("copy")
+                .about("Copy the data of a deployment into a new, unassigned deployment")
+                .arg(
+                    Arg::with_name("src")
+                        .required(true)
+                        .help("the deployment to copy from"),
+                )
+                .arg(
+                    Arg::with_name("dst")
+                        .required(true)
+                        .help("the deployment to copy into"),
+                )
+                .args(&block_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("dump")
+                .about("Write a deployment with its metadata and all its data to a file")
+                .arg(deployment_arg())
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("the file to write"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Create a deployment from a file written by `dump`")
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("the file to read"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("chain")
+                .about("Inspect the chains the store knows about and move their caches")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("List all chains"))
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Write the cached blocks and contract calls of a chain to a file")
+                        .arg(Arg::with_name("chain").required(true).help("the chain"))
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .help("the file to write"),
+                        )
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .value_name("NUMBER")
+                                .help("the first block to export"),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .long("to")
+                                .value_name("NUMBER")
+                                .help("the last block to export"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Add the blocks and contract calls from a file written by `export`")
+                        .arg(Arg::with_name("chain").required(true).help("the chain"))
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .help("the file to read"),
+                        ),
+                ),
+        )
+}
+
+/// Get the value of a required argument
+fn value(matches: &ArgMatches, name: &str) -> String {
+    matches.value_of(name).unwrap().to_string()
+}
+
+fn run(
+    logger: &Logger,
+    conn: &PooledPgConnection,
+    config: Option<&Config>,
+    matches: &ArgMatches,
+) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("info", Some(m)) => commands::info::run(conn, &value(m, "deployment")),
+        ("unassign", Some(m)) => commands::assign::unassign(conn, &value(m, "deployment")),
+        ("reassign", Some(m)) => {
+            commands::assign::reassign(conn, &value(m, "deployment"), &value(m, "node"))
+        }
+        ("place", Some(m)) => {
+            let config = config.ok_or_else(|| {
+                format_err!("the placement rules have to be given with `--config`")
+            })?;
+            commands::place::run(
+                conn,
+                &config.deployment,
+                m.value_of("deployment"),
+                m.is_present("apply"),
+            )
+        }
+        ("rewind", Some(m)) => commands::rewind::run(
+            conn,
+            &value(m, "deployment"),
+            &value(m, "block-hash"),
+            &value(m, "block-number"),
+        ),
+        ("unused", Some(m)) => match m.subcommand() {
+            ("record", Some(_)) => commands::unused::record(conn),
+            ("list", Some(m)) => commands::unused::list(conn, m.is_present("all")),
+            ("remove", Some(m)) => commands::unused::remove(conn, m.value_of("deployment")),
+            _ => unreachable!("clap requires a subcommand"),
+        },
+        ("stats", Some(m)) => commands::stats::run(conn, &value(m, "deployment")),
+        ("index", Some(m)) => match m.subcommand() {
+            ("create", Some(m)) => commands::index::create(
+                conn,
+                &value(m, "deployment"),
+                &value(m, "entity"),
+                m.values_of("field")
+                    .unwrap()
+                    .map(|field| field.to_string())
+                    .collect(),
+                &value(m, "method"),
+            ),
+            ("list", Some(m)) => {
+                commands::index::list(conn, &value(m, "deployment"), &value(m, "entity"))
+            }
+            _ => unreachable!("clap requires a subcommand"),
+        },
+        ("copy", Some(m)) => commands::copy::run(
+            logger,
+            conn,
+            &value(m, "src"),
+            &value(m, "dst"),
+            &value(m, "block-hash"),
+            &value(m, "block-number"),
+        ),
+        ("dump", Some(m)) => commands::dump::dump(conn, &value(m, "deployment"), &value(m, "file")),
+        ("restore", Some(m)) => commands::dump::restore(conn, &value(m, "file")),
+        ("chain", Some(m)) => match m.subcommand() {
+            ("list", Some(_)) => commands::chain::list(conn),
+            ("export", Some(m)) => commands::chain::export(
+                conn,
+                &value(m, "chain"),
+                &value(m, "file"),
+                m.value_of("from"),
+                m.value_of("to"),
+            ),
+            ("import", Some(m)) => {
+                commands::chain::import(conn, &value(m, "chain"), &value(m, "file"))
+            }
+            _ => unreachable!("clap requires a subcommand"),
+        },
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+fn main() {
+    let matches = app().get_matches();
+
+    let logger = logger(matches.is_present("debug"));
+    let config = matches
+        .value_of("config")
+        .map(|path| match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("graphman: invalid configuration file {}: {}", path, e);
+                exit(1);
+            }
+        });
+    let postgres_url = match &config {
+        Some(config) => config.primary_store().connection.clone(),
+        None => matches.value_of("postgres-url").unwrap().to_string(),
+    };
+    let registry = Arc::new(MetricsRegistry::new(
+        logger.clone(),
+        Arc::new(Registry::new()),
+    ));
+    let pool = create_connection_pool(postgres_url, 1, &logger, registry);
+    let conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("graphman: failed to connect to the database: {}", e);
+            exit(1);
+        }
+    };
+
+    if let Err(e) = run(&logger, &conn, config.as_ref(), &matches) {
+        eprintln!("graphman: {}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,218 @@
+//! `graph-node check` validates the whole setup of a node without starting
+//! it: it connects to every store, Ethereum provider and IPFS node, and
+//! compares the networks that the providers are on with each other and
+//! with the ones in the database. The result is a report that is printed
+//! as JSON so that deployment tooling can act on it.
+use ipfs_api::IpfsClient;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use graph::prelude::{
+    futures03::compat::Future01CompatExt, serde_json, tokio, EthereumAdapter,
+    EthereumNetworkIdentifier, Logger, Serialize,
+};
+use graph_store_postgres::check_database;
+
+/// How long to wait for a provider or IPFS node to answer
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The outcome of checking one component
+#[derive(Debug, Serialize)]
+pub struct Check {
+    /// What was checked, e.g. `store/primary` or `provider/mainnet/infura`
+    pub component: String,
+    pub ok: bool,
+    /// What the check found out if it succeeded, or why it failed
+    pub message: String,
+}
+
+impl Check {
+    pub fn ok(component: String, message: String) -> Self {
+        Check {
+            component,
+            ok: true,
+            message,
+        }
+    }
+
+    pub fn failed(component: String, message: String) -> Self {
+        Check {
+            component,
+            ok: false,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Whether all checks succeeded
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn new(checks: Vec<Check>) -> Self {
+        Report {
+            ok: checks.iter().all(|check| check.ok),
+            checks,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a report can always be serialized")
+    }
+}
+
+/// Check the store shard `name` at `url`. Also returns the networks that
+/// the shard knows about
+pub fn check_store(name: &str, url: &str) -> (Check, Vec<(String, EthereumNetworkIdentifier)>) {
+    let component = format!("store/{}", name);
+    match check_database(name, url) {
+        Ok(check) if check.problems.is_empty() => (
+            Check::ok(
+                component,
+                format!(
+                    "Postgres server version {}, {} pending migrations",
+                    check.server_version,
+                    check.migrations.pending.len()
+                ),
+            ),
+            check.networks,
+        ),
+        Ok(check) => (
+            Check::failed(component, check.problems.join("; ")),
+            check.networks,
+        ),
+        Err(e) => (
+            Check::failed(component, format!("failed to connect: {}", e)),
+            vec![],
+        ),
+    }
+}
+
+/// Ask the provider `label` for `network` which network it is on
+pub async fn check_provider(
+    logger: &Logger,
+    network: &str,
+    label: &str,
+    adapter: &dyn EthereumAdapter,
+) -> (Check, Option<EthereumNetworkIdentifier>) {
+    let component = format!("provider/{}/{}", network, label);
+    match tokio::time::timeout(CHECK_TIMEOUT, adapter.net_identifiers(logger).compat()).await {
+        Ok(Ok(ident)) => (Check::ok(component, describe(&ident)), Some(ident)),
+        Ok(Err(e)) => (Check::failed(component, e.to_string()), None),
+        Err(_) => (
+            Check::failed(component, "the provider did not answer in time".to_owned()),
+            None,
+        ),
+    }
+}
+
+/// Check that the IPFS node at `address` answers; `name` identifies it in
+/// the report without revealing credentials that the address may contain
+pub async fn check_ipfs(name: String, address: &str) -> Check {
+    let component = format!("ipfs/{}", name);
+    let client = match IpfsClient::new_from_uri(address) {
+        Ok(client) => client,
+        Err(e) => return Check::failed(component, format!("invalid address: {}", e)),
+    };
+    match tokio::time::timeout(CHECK_TIMEOUT, client.version()).await {
+        Ok(Ok(version)) => Check::ok(component, format!("IPFS version {}", version.version)),
+        Ok(Err(e)) => Check::failed(component, e.to_string()),
+        Err(_) => Check::failed(component, "the IPFS node did not answer in time".to_owned()),
+    }
+}
+
+/// Compare the networks that the providers in `probed`, given as network,
+/// provider label and what the provider reported, are on with each other
+/// and with the networks in the database in `stored`
+pub fn check_networks(
+    stored: &[(String, EthereumNetworkIdentifier)],
+    probed: &[(String, String, EthereumNetworkIdentifier)],
+) -> Vec<Check> {
+    let mut by_network: BTreeMap<&str, Vec<(&str, &EthereumNetworkIdentifier)>> = BTreeMap::new();
+    for (network, label, ident) in probed {
+        by_network
+            .entry(network.as_str())
+            .or_default()
+            .push((label.as_str(), ident));
+    }
+
+    by_network
+        .into_iter()
+        .map(|(network, providers)| {
+            let component = format!("network/{}", network);
+            let (first_label, first) = providers[0];
+            if let Some((label, ident)) = providers.iter().find(|(_, ident)| *ident != first) {
+                return Check::failed(
+                    component,
+                    format!(
+                        "providers disagree: `{}` reports {} but `{}` reports {}",
+                        first_label,
+                        describe(first),
+                        label,
+                        describe(ident)
+                    ),
+                );
+            }
+            match stored.iter().find(|(name, _)| name == network) {
+                Some((_, ident)) if ident != first => Check::failed(
+                    component,
+                    format!(
+                        "the database has {} but the providers report {}",
+                        describe(ident),
+                        describe(first)
+                    ),
+                ),
+                Some(_) => Check::ok(component, describe(first)),
+                None => Check::ok(
+                    component,
+                    format!("{}; not in the database yet", describe(first)),
+                ),
+            }
+        })
+        .collect()
+}
+
+fn describe(ident: &EthereumNetworkIdentifier) -> String {
+    format!(
+        "net_version {} and genesis block {:#x}",
+        ident.net_version, ident.genesis_block_hash
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::web3::types::H256;
+
+    fn ident(net_version: &str, genesis: u64) -> EthereumNetworkIdentifier {
+        EthereumNetworkIdentifier {
+            net_version: net_version.to_owned(),
+            genesis_block_hash: H256::from_low_u64_be(genesis),
+        }
+    }
+
+    #[test]
+    fn compares_networks_of_providers_and_database() {
+        let stored = vec![("mainnet".to_owned(), ident("1", 1))];
+        let probed = vec![
+            ("mainnet".to_owned(), "a".to_owned(), ident("1", 1)),
+            ("mainnet".to_owned(), "b".to_owned(), ident("1", 1)),
+            ("ropsten".to_owned(), "a".to_owned(), ident("3", 3)),
+        ];
+        let checks = check_networks(&stored, &probed);
+        assert!(checks.iter().all(|check| check.ok));
+        assert_eq!("network/ropsten", checks[1].component);
+
+        let probed = vec![
+            ("mainnet".to_owned(), "a".to_owned(), ident("1", 1)),
+            ("mainnet".to_owned(), "b".to_owned(), ident("1", 2)),
+        ];
+        assert!(!check_networks(&stored, &probed)[0].ok);
+
+        let probed = vec![("mainnet".to_owned(), "a".to_owned(), ident("1", 2))];
+        assert!(!check_networks(&stored, &probed)[0].ok);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod check;
+pub mod config;
+pub mod manager;
+pub mod replay;
@@ -1,4 +1,4 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use git_testament::{git_testament, render_testament};
 use ipfs_api::IpfsClient;
 use lazy_static::lazy_static;
@@ -12,29 +12,52 @@ use std::time::Duration;
 use tokio::sync::mpsc;
 
 use graph::components::forward;
-use graph::log::logger;
+use graph::components::migration;
+use graph::data::query::quota::{self, Quotas};
+use graph::log::audit::QueryAuditLog;
+use graph::log::logger_with_format;
 use graph::prelude::{
-    EthereumAdapter as EthereumAdapterTrait, IndexNodeServer as _, JsonRpcServer as _, *,
+    EthereumAdapter as EthereumAdapterTrait, HealthServer as _, IndexNodeServer as _,
+    JsonRpcServer as _, *,
 };
+use graph::util::env::env_var;
 use graph::util::security::SafeDisplay;
+use graph::util::shutdown::Shutdown;
+use graph::util::tls::TlsConfig;
 use graph_chain_arweave::adapter::ArweaveAdapter;
-use graph_chain_ethereum::{network_indexer, BlockIngestor, BlockStreamBuilder, Transport};
+use graph_chain_ethereum::{
+    network_indexer, BlockIngestor, BlockIngestorsCheck, BlockStreamBuilder,
+    FixtureEthereumAdapter, ProviderCheck, ReloadableTransport, Transport,
+};
 use graph_core::{
     three_box::ThreeBoxAdapter, LinkResolver, MetricsRegistry,
     SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
     SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
-use graph_graphql::prelude::GraphQlRunner;
+use graph_graphql::prelude::{
+    export_herd_metrics, load_query_cache, query_cache_settings, read_warm_queries,
+    save_query_cache, set_query_cache_settings, set_query_limits, CacheWarmer,
+    DeprecatedFieldMetrics, ExecutionHooks, FieldUsageRecorder, GraphQlRunner,
+    QueryCacheInvalidator,
+};
+use graph_node::check::{self, Check, Report};
+use graph_node::config::{
+    watch as watch_config, Chain as ConfigChain, Config, Transport as ConfigTransport,
+    PRIMARY_SHARD,
+};
+use graph_node::replay::Replay;
 use graph_runtime_wasm::RuntimeHostBuilder as WASMRuntimeHostBuilder;
 use graph_server_http::GraphQLServer as GraphQLQueryServer;
 use graph_server_index_node::IndexNodeServer;
 use graph_server_json_rpc::JsonRpcServer;
-use graph_server_metrics::PrometheusMetricsServer;
+use graph_server_metrics::{HealthServer, PrometheusMetricsServer};
 use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
-use graph_store_postgres::connection_pool::create_connection_pool;
+use graph_store_postgres::connection_pool::{create_connection_pool, PoolHealthCheck};
 use graph_store_postgres::{
-    ChainHeadUpdateListener as PostgresChainHeadUpdateListener, Store as DieselStore, StoreConfig,
-    SubscriptionManager,
+    migrate, network_identifiers, ChainHeadUpdateListener as PostgresChainHeadUpdateListener,
+    DeploymentFiles as PostgresDeploymentFiles, IpfsCache as PostgresIpfsCache,
+    QuotaStore as PostgresQuotaStore, RetirementPolicy, ShardMigrations, Store as DieselStore,
+    StoreConfig, SubscriptionManager,
 };
 use graphql_parser::query as q;
 
@@ -52,8 +75,37 @@ lazy_static! {
         .map(|s| u64::from_str(&s)
              .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_ANCESTOR_COUNT")))
         .unwrap_or(50);
+
+    // How often this node records in the store that it is alive, in seconds
+    static ref HEARTBEAT_INTERVAL: Duration =
+        Duration::from_secs(env_var("GRAPH_NODE_HEARTBEAT_INTERVAL").unwrap_or(10));
+
+    // How often query quotas are synced with the store, in seconds
+    static ref QUOTA_SYNC_INTERVAL: Duration =
+        Duration::from_secs(env_var("GRAPH_QUERY_QUOTA_SYNC_INTERVAL").unwrap_or(30));
+
+    // How long to wait for running queries and blocks when shutting down,
+    // in seconds
+    static ref SHUTDOWN_TIMEOUT: Duration =
+        Duration::from_secs(env_var("GRAPH_SHUTDOWN_TIMEOUT").unwrap_or(30));
+
+    // How long, in seconds, a node can go without a heartbeat before its
+    // deployments are moved to other nodes. Failover is off unless this
+    // is set
+    static ref FAILOVER_TIMEOUT: Option<Duration> =
+        env_var("GRAPH_NODE_FAILOVER_TIMEOUT").map(Duration::from_secs);
+
+    // How many megabytes of IPFS files to keep in the database. Files are
+    // only kept in memory unless this is set
+    static ref IPFS_DB_CACHE_SIZE: Option<u64> = env_var("GRAPH_IPFS_DB_CACHE_SIZE");
 }
 
+/// How often to check the configuration file for changes
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often to check for subgraph versions that should be retired
+const RETIREMENT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 git_testament!(TESTAMENT);
 
 #[derive(Debug, Clone)]
@@ -109,10 +161,61 @@ async fn main() {
                 .value_name("[NAME:]IPFS_HASH")
                 .help("name and IPFS hash of the subgraph manifest"),
         )
+        .arg(
+            Arg::with_name("config")
+                .takes_value(true)
+                .long("config")
+                .short("c")
+                .value_name("FILE")
+                .env("GRAPH_NODE_CONFIG")
+                .help("the name of the configuration file"),
+        )
+        .arg(
+            Arg::with_name("check-config")
+                .long("check-config")
+                .requires("config")
+                .help("validate the configuration file and exit"),
+        )
+        .arg(
+            Arg::with_name("query-only")
+                .long("query-only")
+                .conflicts_with_all(&["subgraph", "network-subgraphs"])
+                .help(
+                    "only serve queries; do not connect to Ethereum or IPFS, \
+                     index subgraphs, or accept deployments",
+                ),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .takes_value(true)
+                .long("replay")
+                .number_of_values(4)
+                .value_names(&["DEPLOYMENT", "SCRATCH", "FROM", "TO"])
+                .conflicts_with_all(&["subgraph", "network-subgraphs", "query-only"])
+                .help(
+                    "replay blocks FROM to TO of DEPLOYMENT from cached inputs into the new \
+                     deployment SCRATCH, print how the entity changes differ, and exit",
+                ),
+        )
+        .arg(
+            Arg::with_name("migrate")
+                .long("migrate")
+                .conflicts_with_all(&["subgraph", "network-subgraphs", "query-only", "replay"])
+                .help(
+                    "run the pending database migrations of every store and exit; see \
+                     GRAPH_STORE_MIGRATIONS for how other nodes handle pending migrations",
+                ),
+        )
+        .arg(
+            Arg::with_name("disable-graphiql")
+                .long("disable-graphiql")
+                .help("do not serve GraphiQL on the GraphQL HTTP server, e.g., in production"),
+        )
         .arg(
             Arg::with_name("postgres-url")
                 .takes_value(true)
-                .required(true)
+                .required_unless("config")
+                .conflicts_with("config")
                 .long("postgres-url")
                 .value_name("URL")
                 .help("Location of the Postgres database used for storing entities"),
@@ -122,8 +225,15 @@ async fn main() {
                 .takes_value(true)
                 .multiple(true)
                 .min_values(0)
-                .required_unless_one(&["ethereum-ws", "ethereum-ipc"])
-                .conflicts_with_all(&["ethereum-ws", "ethereum-ipc"])
+                .required_unless_one(&[
+                    "ethereum-ws",
+                    "ethereum-ipc",
+                    "config",
+                    "query-only",
+                    "replay",
+                    "migrate",
+                ])
+                .conflicts_with_all(&["ethereum-ws", "ethereum-ipc", "config", "query-only"])
                 .long("ethereum-rpc")
                 .value_name("NETWORK_NAME:URL")
                 .help(
@@ -136,8 +246,15 @@ async fn main() {
                 .takes_value(true)
                 .multiple(true)
                 .min_values(0)
-                .required_unless_one(&["ethereum-rpc", "ethereum-ipc"])
-                .conflicts_with_all(&["ethereum-rpc", "ethereum-ipc"])
+                .required_unless_one(&[
+                    "ethereum-rpc",
+                    "ethereum-ipc",
+                    "config",
+                    "query-only",
+                    "replay",
+                    "migrate",
+                ])
+                .conflicts_with_all(&["ethereum-rpc", "ethereum-ipc", "config", "query-only"])
                 .long("ethereum-ws")
                 .value_name("NETWORK_NAME:URL")
                 .help(
@@ -150,8 +267,15 @@ async fn main() {
                 .takes_value(true)
                 .multiple(true)
                 .min_values(0)
-                .required_unless_one(&["ethereum-rpc", "ethereum-ws"])
-                .conflicts_with_all(&["ethereum-rpc", "ethereum-ws"])
+                .required_unless_one(&[
+                    "ethereum-rpc",
+                    "ethereum-ws",
+                    "config",
+                    "query-only",
+                    "replay",
+                    "migrate",
+                ])
+                .conflicts_with_all(&["ethereum-rpc", "ethereum-ws", "config", "query-only"])
                 .long("ethereum-ipc")
                 .value_name("NETWORK_NAME:FILE")
                 .help(
@@ -162,7 +286,7 @@ async fn main() {
         .arg(
             Arg::with_name("ipfs")
                 .takes_value(true)
-                .required(true)
+                .required_unless_one(&["query-only", "migrate"])
                 .long("ipfs")
                 .multiple(true)
                 .value_name("HOST:PORT")
@@ -203,6 +327,32 @@ async fn main() {
                 .value_name("PORT")
                 .help("Port for the Prometheus metrics server"),
         )
+        .arg(
+            Arg::with_name("health-port")
+                .default_value("8050")
+                .long("health-port")
+                .value_name("PORT")
+                .help("Port for the /healthz and /readyz probes"),
+        )
+        .arg(
+            Arg::with_name("tls-cert-file")
+                .long("tls-cert-file")
+                .value_name("FILE")
+                .env("GRAPH_TLS_CERT_FILE")
+                .requires("tls-key-file")
+                .help(
+                    "PEM file with the certificate chain that the GraphQL, WebSocket, \
+                     index node and admin servers use for TLS",
+                ),
+        )
+        .arg(
+            Arg::with_name("tls-key-file")
+                .long("tls-key-file")
+                .value_name("FILE")
+                .env("GRAPH_TLS_KEY_FILE")
+                .requires("tls-cert-file")
+                .help("PEM file with the private key for --tls-cert-file"),
+        )
         .arg(
             Arg::with_name("node-id")
                 .default_value("default")
@@ -216,6 +366,15 @@ async fn main() {
                 .long("debug")
                 .help("Enable debug logging"),
         )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .env("GRAPH_LOG_FORMAT")
+                .help("Write logs as human readable text or as one JSON object per line"),
+        )
         .arg(
             Arg::with_name("elasticsearch-url")
                 .long("elasticsearch-url")
@@ -289,10 +448,15 @@ async fn main() {
                 .value_name("URL")
                 .help("HTTP endpoint for 3box profiles"),
         )
+        .subcommand(SubCommand::with_name("check").about(
+            "connect to every store, Ethereum provider and IPFS node, check that they \
+             can be used, print a report as JSON and exit without starting the node",
+        ))
         .get_matches();
 
     // Set up logger
-    let logger = logger(matches.is_present("debug"));
+    let log_format = matches.value_of("log-format").unwrap().parse().unwrap();
+    let logger = logger_with_format(matches.is_present("debug"), log_format);
 
     // Log version information
     info!(
@@ -301,15 +465,81 @@ async fn main() {
         render_testament!(TESTAMENT)
     );
 
-    // Safe to unwrap because a value is required by CLI
-    let postgres_url = matches.value_of("postgres-url").unwrap().to_string();
+    // Load and validate the configuration file if there is one
+    let config = matches
+        .value_of("config")
+        .map(|path| match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        });
+    if matches.is_present("check-config") {
+        println!("Successfully validated configuration");
+        std::process::exit(0);
+    }
+    if let Some(config) = &config {
+        set_query_cache_settings(config.query.cache_settings());
+        set_query_limits(config.query.limits());
+    }
+
+    // Safe to unwrap because a value is required by CLI unless there is
+    // a configuration file
+    let postgres_url = match &config {
+        Some(config) => config.primary_store().connection.clone(),
+        None => matches.value_of("postgres-url").unwrap().to_string(),
+    };
+
+    // Every store, by name and connection string
+    let shards: Vec<(String, String)> = match &config {
+        Some(config) => config
+            .store
+            .iter()
+            .map(|(name, shard)| (name.clone(), shard.connection.clone()))
+            .collect(),
+        None => vec![(PRIMARY_SHARD.to_owned(), postgres_url.clone())],
+    };
+    migration::install(Arc::new(ShardMigrations::new(shards.clone())));
+
+    if matches.is_present("migrate") {
+        for (name, url) in &shards {
+            info!(logger, "Migrating store"; "shard" => name);
+            match tokio::task::block_in_place(|| migrate(&logger, name, url)) {
+                Ok(status) => info!(
+                    logger,
+                    "Store is up to date";
+                    "shard" => name,
+                    "version" => status.version.unwrap_or_default(),
+                ),
+                Err(e) => {
+                    eprintln!("Failed to migrate store `{}`: {}", name, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        std::process::exit(0);
+    }
 
     let node_id = NodeId::new(matches.value_of("node-id").unwrap())
         .expect("Node ID must contain only a-z, A-Z, 0-9, and '_'");
 
+    // Query nodes only serve queries from the data that index nodes write
+    let query_only = matches.is_present("query-only");
+
     // Obtain subgraph related command-line arguments
     let subgraph = matches.value_of("subgraph").map(|s| s.to_owned());
 
+    // Replaying blocks only reads cached inputs and does not talk to
+    // Ethereum, just like query nodes
+    let replay = matches.values_of("replay").map(|values| {
+        Replay::parse(&values.collect::<Vec<_>>()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    });
+    let offline = query_only || replay.is_some();
+
     // Obtain the Ethereum parameters
     let ethereum_rpc = matches.values_of("ethereum-rpc");
     let ethereum_ipc = matches.values_of("ethereum-ipc");
@@ -356,6 +586,31 @@ async fn main() {
         .parse()
         .expect("invalid metrics port");
 
+    // Obtain health server port
+    let health_port = matches
+        .value_of("health-port")
+        .unwrap()
+        .parse()
+        .expect("invalid health port");
+
+    // Serve everything except metrics over TLS if a certificate is given.
+    // The certificate is read again on SIGHUP so that it can be renewed
+    // without restarting the node
+    let tls = match (
+        matches.value_of("tls-cert-file"),
+        matches.value_of("tls-key-file"),
+    ) {
+        (Some(cert), Some(key)) => {
+            let tls = Arc::new(TlsConfig::load(cert, key).expect("failed to load TLS certificate"));
+            graph::spawn(reload_tls_on_hangup(
+                logger.new(o!("component" => "TlsReloader")),
+                tls.clone(),
+            ));
+            Some(tls)
+        }
+        _ => None,
+    };
+
     // Obtain DISABLE_BLOCK_INGESTOR setting
     let disable_block_ingestor: bool = matches
         .value_of("disable-block-ingestor")
@@ -363,12 +618,23 @@ async fn main() {
         .parse()
         .expect("invalid --disable-block-ingestor/DISABLE_BLOCK_INGESTOR value");
 
+    // If the configuration file names the node that ingests blocks, all
+    // other nodes leave block ingestion to it
+    let disable_block_ingestor = disable_block_ingestor
+        || config
+            .as_ref()
+            .and_then(|config| config.chains.ingestor.as_ref())
+            .map_or(false, |ingestor| ingestor != node_id.as_str());
+
     // Obtain STORE_CONNECTION_POOL_SIZE setting
-    let store_conn_pool_size: u32 = matches
-        .value_of("store-connection-pool-size")
-        .unwrap()
-        .parse()
-        .expect("invalid --store-connection-pool-size/STORE_CONNECTION_POOL_SIZE value");
+    let store_conn_pool_size: u32 = match &config {
+        Some(config) => config.primary_store().pool_size,
+        None => matches
+            .value_of("store-connection-pool-size")
+            .unwrap()
+            .parse()
+            .expect("invalid --store-connection-pool-size/STORE_CONNECTION_POOL_SIZE value"),
+    };
 
     // Minimum of two connections needed for the pool in order for the Store to bootstrap
     if store_conn_pool_size <= 1 {
@@ -385,10 +651,12 @@ async fn main() {
 
     info!(logger, "Starting up");
 
-    // Parse the IPFS URL from the `--ipfs` command line argument
+    // Parse the IPFS URL from the `--ipfs` command line argument; the
+    // argument is required unless `--query-only` is given
     let ipfs_addresses: Vec<_> = matches
         .values_of("ipfs")
-        .expect("At least one IPFS node is required")
+        .into_iter()
+        .flatten()
         .map(|uri| {
             if uri.starts_with("http://") || uri.starts_with("https://") {
                 String::from(uri)
@@ -398,6 +666,12 @@ async fn main() {
         })
         .collect();
 
+    if matches.subcommand_matches("check").is_some() {
+        let report = run_check(&logger, &matches, config.as_ref(), &shards, &ipfs_addresses).await;
+        println!("{}", report.to_json());
+        std::process::exit(if report.ok { 0 } else { 1 });
+    }
+
     // Optionally, identify the Elasticsearch logging configuration
     let elastic_config =
         matches
@@ -411,6 +685,10 @@ async fn main() {
     // Create a component and subgraph logger factory
     let logger_factory = LoggerFactory::new(logger.clone(), elastic_config);
 
+    // Export traces if a collector is configured
+    graph::trace::init(&logger);
+    graph::log::error_reporting::init(&logger, node_id.as_str());
+
     // Try to create IPFS clients for each URL
     let ipfs_clients: Vec<_> = ipfs_addresses
         .into_iter()
@@ -434,74 +712,106 @@ async fn main() {
                 }
             };
 
-            // Test the IPFS client by getting the version from the IPFS daemon
-            let ipfs_test = ipfs_client.clone();
-            let ipfs_ok_logger = logger.clone();
-            let ipfs_err_logger = logger.clone();
-            let ipfs_address_for_ok = ipfs_address.clone();
-            let ipfs_address_for_err = ipfs_address.clone();
-            graph::spawn(async move {
-                ipfs_test
-                    .version()
-                    .map_err(move |e| {
-                        error!(
-                            ipfs_err_logger,
-                            "Is there an IPFS node running at \"{}\"?",
-                            SafeDisplay(ipfs_address_for_err),
-                        );
-                        panic!("Failed to connect to IPFS: {}", e);
-                    })
-                    .map_ok(move |_| {
-                        info!(
-                            ipfs_ok_logger,
-                            "Successfully connected to IPFS node at: {}",
-                            SafeDisplay(ipfs_address_for_ok)
-                        );
-                    })
-                    .await
-            });
-
             ipfs_client
         })
         .collect();
 
-    // Convert the client into a link resolver
-    let link_resolver = Arc::new(LinkResolver::from(ipfs_clients));
-
     // Set up Prometheus registry
     let prometheus_registry = Arc::new(Registry::new());
     let metrics_registry = Arc::new(MetricsRegistry::new(
         logger.clone(),
         prometheus_registry.clone(),
     ));
+    graph::util::memory::init(&logger, metrics_registry.clone());
+    export_herd_metrics(metrics_registry.clone());
     let mut metrics_server =
         PrometheusMetricsServer::new(&logger_factory, prometheus_registry.clone());
 
+    // Answer `/healthz` while the node is still connecting to Postgres and
+    // Ethereum; `/readyz` fails until the stores and providers are set up
+    let mut health_server = HealthServer::new(&logger_factory);
+    graph::spawn(
+        health_server
+            .serve(health_port)
+            .expect("Failed to start health server")
+            .compat(),
+    );
+
     // Ethereum clients
-    let eth_adapters = [
-        (ConnectionType::RPC, ethereum_rpc),
-        (ConnectionType::IPC, ethereum_ipc),
-        (ConnectionType::WS, ethereum_ws),
-    ]
-    .iter()
-    .cloned()
-    .filter(|(_, values)| values.is_some())
-    .fold(HashMap::new(), |adapters, (connection_type, values)| {
-        match parse_ethereum_networks_and_nodes(
-            logger.clone(),
-            values.unwrap(),
-            connection_type,
-            metrics_registry.clone(),
-        ) {
-            Ok(adapter) => adapters.into_iter().chain(adapter).collect(),
-            Err(e) => {
-                panic!(
-                    "Failed to parse Ethereum networks and create Ethereum adapters: {}",
-                    e
-                );
-            }
+    let (eth_adapters, transports) = match &config {
+        _ if offline => (HashMap::new(), HashMap::new()),
+        Some(config) => {
+            create_ethereum_adapters_from_config(&logger, config, metrics_registry.clone())
         }
-    });
+        None => {
+            let adapters = [
+                (ConnectionType::RPC, ethereum_rpc),
+                (ConnectionType::IPC, ethereum_ipc),
+                (ConnectionType::WS, ethereum_ws),
+            ]
+            .iter()
+            .cloned()
+            .filter(|(_, values)| values.is_some())
+            .fold(HashMap::new(), |adapters, (connection_type, values)| {
+                match parse_ethereum_networks_and_nodes(
+                    logger.clone(),
+                    values.unwrap(),
+                    connection_type,
+                    metrics_registry.clone(),
+                ) {
+                    Ok(adapter) => adapters.into_iter().chain(adapter).collect(),
+                    Err(e) => {
+                        panic!(
+                            "Failed to parse Ethereum networks and create Ethereum adapters: {}",
+                            e
+                        );
+                    }
+                }
+            });
+            (adapters, HashMap::new())
+        }
+    };
+
+    // Placement rules are only read at startup; changing them requires a
+    // restart
+    let placer = config
+        .as_ref()
+        .map(|config| Arc::new(config.deployment.clone()) as Arc<dyn DeploymentPlacer>);
+    let retirement = config
+        .as_ref()
+        .map(|config| config.retirement.policy())
+        .filter(|policy| policy.is_active());
+    let admin_auth = config
+        .as_ref()
+        .map(|config| config.admin.auth())
+        .unwrap_or_default();
+    let cors = config
+        .as_ref()
+        .map(|config| config.cors.config())
+        .unwrap_or_default();
+    let priority_classes = config
+        .as_ref()
+        .map(|config| config.priority.classes())
+        .unwrap_or_default();
+    let composites = config
+        .as_ref()
+        .map(|config| config.composites())
+        .unwrap_or_default();
+
+    // Watch the configuration file and apply changes to providers and
+    // caches while the node is running
+    if let (Some(path), Some(config)) = (matches.value_of("config"), config) {
+        let reload_logger = logger.new(o!("component" => "ConfigReloader"));
+        let mut current = config;
+        watch_config(
+            reload_logger.clone(),
+            path.to_string(),
+            CONFIG_POLL_INTERVAL,
+            move |new| {
+                current = reload_config(&reload_logger, &current, &new, &transports);
+            },
+        );
+    }
 
     // Set up Store
     info!(
@@ -525,6 +835,38 @@ async fn main() {
         &logger,
         connection_pool_registry,
     );
+    let replay_pool = postgres_conn_pool.clone();
+
+    // Convert the clients into a link resolver that checks which of them are
+    // reachable, and keeps files in the database if that is configured.
+    // Subgraphs deployed without IPFS keep their files in the database, too
+    let deployment_files = Arc::new(PostgresDeploymentFiles::new(postgres_conn_pool.clone()));
+    let link_resolver =
+        LinkResolver::from(ipfs_clients).with_deployment_files(deployment_files.clone());
+    let link_resolver = match *IPFS_DB_CACHE_SIZE {
+        Some(size) => {
+            info!(logger, "Caching IPFS files in the database"; "size_mb" => size);
+            link_resolver.with_persistent_cache(Arc::new(PostgresIpfsCache::new(
+                postgres_conn_pool.clone(),
+                size * 1024 * 1024,
+            )))
+        }
+        None => link_resolver,
+    };
+    link_resolver.start_health_checks(&logger);
+    let ipfs_health = link_resolver.health();
+    let link_resolver = Arc::new(link_resolver);
+
+    // Enforce the monthly quotas of API tokens that operators set through
+    // the admin server
+    let quotas = Arc::new(Quotas::from_env(Arc::new(PostgresQuotaStore::new(
+        postgres_conn_pool.clone(),
+    ))));
+    quota::install(quotas.clone());
+    spawn_quota_sync(logger.clone(), quotas);
+
+    let health_logger = logger.clone();
+    let health_pool = postgres_conn_pool.clone();
 
     let chain_head_update_listener = Arc::new(PostgresChainHeadUpdateListener::new(
         &logger,
@@ -539,279 +881,438 @@ async fn main() {
 
     let expensive_queries = read_expensive_queries().unwrap();
 
+    // Queries and subgraph writers use this to finish what they are doing
+    // when the node is asked to stop
+    let shutdown = Shutdown::new();
+    let shutdown_for_stores = shutdown.clone();
+    let shutdown_logger = logger.clone();
+
+    // Query nodes and replays do not talk to Ethereum and set up stores
+    // for the networks that index nodes have already put into the database
+    let net_identifiers: futures::stream::BoxStream<
+        'static,
+        Result<(String, EthereumNetworkIdentifier), Error>,
+    > = if offline {
+        let conn = postgres_conn_pool
+            .get()
+            .expect("failed to connect to Postgres");
+        let identifiers =
+            network_identifiers(&conn).expect("failed to read networks from Postgres");
+        if identifiers.is_empty() {
+            panic!("Query nodes need an index node to have added at least one network");
+        }
+        Box::pin(futures::stream::iter(identifiers.into_iter().map(Ok)))
+    } else {
+        Box::pin(futures::stream::FuturesOrdered::from_iter(
+            stores_eth_adapters
+                .into_iter()
+                .map(|(network_name, eth_adapter)| {
+                    info!(
+                        logger, "Connecting to Ethereum...";
+                        "network" => &network_name,
+                    );
+                    eth_adapter
+                        .net_identifiers(&logger)
+                        .map(|network_identifier| (network_name, network_identifier))
+                        .compat()
+                }),
+        ))
+    };
+
     graph::spawn(
-        futures::stream::FuturesOrdered::from_iter(stores_eth_adapters.into_iter().map(
-            |(network_name, eth_adapter)| {
+        net_identifiers
+            .compat()
+            .map_err(move |e| {
+                error!(stores_error_logger, "Was a valid Ethereum node provided?");
+                panic!("Failed to connect to Ethereum node: {}", e);
+            })
+            .map(move |(network_name, network_identifier)| {
                 info!(
-                    logger, "Connecting to Ethereum...";
+                    stores_logger,
+                    "Connected to Ethereum";
                     "network" => &network_name,
+                    "network_version" => &network_identifier.net_version,
                 );
-                eth_adapter
-                    .net_identifiers(&logger)
-                    .map(|network_identifier| (network_name, network_identifier))
-                    .compat()
-            },
-        ))
-        .compat()
-        .map_err(move |e| {
-            error!(stores_error_logger, "Was a valid Ethereum node provided?");
-            panic!("Failed to connect to Ethereum node: {}", e);
-        })
-        .map(move |(network_name, network_identifier)| {
-            info!(
-                stores_logger,
-                "Connected to Ethereum";
-                "network" => &network_name,
-                "network_version" => &network_identifier.net_version,
-            );
-            (
-                network_name.to_string(),
-                Arc::new(DieselStore::new(
-                    StoreConfig {
-                        postgres_url: postgres_url.clone(),
-                        network_name: network_name.to_string(),
-                    },
-                    &stores_logger,
-                    network_identifier,
-                    chain_head_update_listener.clone(),
-                    subscriptions.clone(),
-                    postgres_conn_pool.clone(),
-                    stores_metrics_registry.clone(),
-                )),
-            )
-        })
-        .collect()
-        .map(|stores| HashMap::from_iter(stores.into_iter()))
-        .and_then(move |stores| {
-            let generic_store = stores.values().next().expect("error creating stores");
-
-            let graphql_runner = Arc::new(GraphQlRunner::new(
-                &logger,
-                generic_store.clone(),
-                &expensive_queries,
-            ));
-            let mut graphql_server = GraphQLQueryServer::new(
-                &logger_factory,
-                graphql_metrics_registry,
-                graphql_runner.clone(),
-                generic_store.clone(),
-                node_id.clone(),
-            );
-            let subscription_server = GraphQLSubscriptionServer::new(
-                &logger,
-                graphql_runner.clone(),
-                generic_store.clone(),
-            );
-
-            let mut index_node_server = IndexNodeServer::new(
-                &logger_factory,
-                graphql_runner.clone(),
-                generic_store.clone(),
-                node_id.clone(),
-            );
+                (
+                    network_name.to_string(),
+                    Arc::new(DieselStore::new(
+                        StoreConfig {
+                            postgres_url: postgres_url.clone(),
+                            network_name: network_name.to_string(),
+                        },
+                        &stores_logger,
+                        network_identifier,
+                        chain_head_update_listener.clone(),
+                        subscriptions.clone(),
+                        postgres_conn_pool.clone(),
+                        stores_metrics_registry.clone(),
+                    )),
+                )
+            })
+            .collect()
+            .map(|stores| HashMap::from_iter(stores.into_iter()))
+            .and_then(move |stores| {
+                let generic_store = stores.values().next().expect("error creating stores");
+
+                // The components that `/readyz` reports on
+                let mut health_checks: Vec<Arc<dyn HealthCheck>> =
+                    vec![Arc::new(PoolHealthCheck::new(health_pool))];
+                health_checks.push(Arc::new(ipfs_health));
+                health_checks.extend(eth_adapters.iter().map(|(network_name, eth_adapter)| {
+                    Arc::new(ProviderCheck::new(
+                        &health_logger,
+                        network_name.clone(),
+                        eth_adapter.clone(),
+                    )) as Arc<dyn HealthCheck>
+                }));
 
-            // Spawn Ethereum network indexers for all networks that are to be indexed
-            if let Some(network_subgraphs) = matches.values_of("network-subgraphs") {
-                network_subgraphs
-                    .into_iter()
-                    .filter(|network_subgraph| network_subgraph.starts_with("ethereum/"))
-                    .for_each(|network_subgraph| {
-                        let network_name = network_subgraph.replace("ethereum/", "");
-                        let mut indexer = network_indexer::NetworkIndexer::new(
+                let mut graphql_runner =
+                    GraphQlRunner::new(&logger, generic_store.clone(), &expensive_queries);
+                if let Some(audit_log) = QueryAuditLog::from_env(&logger) {
+                    graphql_runner = graphql_runner.with_audit_log(Arc::new(audit_log));
+                }
+                let mut hooks = ExecutionHooks::default();
+                if env::var_os("GRAPH_QUERY_DEPRECATED_FIELD_METRICS").is_some() {
+                    let hook = DeprecatedFieldMetrics::new(metrics_registry.clone());
+                    hooks = hooks.with_hook(Arc::new(hook));
+                }
+                if let Some(recorder) = FieldUsageRecorder::from_env() {
+                    hooks = hooks.with_hook(Arc::new(recorder));
+                }
+                if !hooks.hooks.is_empty() {
+                    graphql_runner = graphql_runner.with_hooks(hooks);
+                }
+                let graphql_runner = Arc::new(graphql_runner);
+
+                if let Some(replay) = replay {
+                    let logger = logger.clone();
+                    let conn = replay_pool.get().expect("failed to connect to Postgres");
+                    let store = generic_store.clone();
+                    let stores = stores.clone();
+                    let link_resolver = link_resolver.clone();
+                    let graphql_runner = graphql_runner.clone();
+                    let node_id = node_id.clone();
+                    let metrics_registry = metrics_registry.clone();
+                    let arweave_adapter = arweave_adapter.clone();
+                    let three_box_adapter = three_box_adapter.clone();
+                    graph::spawn_blocking(async move {
+                        let result = graph_node::replay::run(
                             &logger,
-                            eth_adapters
-                                .get(&network_name)
-                                .expect("adapter for network")
-                                .clone(),
-                            stores
-                                .get(&network_name)
-                                .expect("store for network")
-                                .clone(),
-                            metrics_registry.clone(),
-                            format!("network/{}", network_subgraph).into(),
-                            None,
-                        );
-                        graph::spawn(
-                            indexer
-                                .take_event_stream()
-                                .unwrap()
-                                .for_each(|_| {
-                                    // For now we simply ignore these events; we may later use them
-                                    // to drive subgraph indexing
-                                    Ok(())
-                                })
-                                .compat(),
-                        );
-                    })
-            };
+                            conn,
+                            store,
+                            stores,
+                            &link_resolver,
+                            graphql_runner,
+                            node_id,
+                            *REORG_THRESHOLD,
+                            metrics_registry,
+                            arweave_adapter,
+                            three_box_adapter,
+                            replay,
+                        )
+                        .await;
+                        match result {
+                            Ok(0) => std::process::exit(0),
+                            Ok(_) => std::process::exit(1),
+                            Err(e) => {
+                                eprintln!("Replay failed: {}", e);
+                                std::process::exit(2);
+                            }
+                        }
+                    });
+                    return future::ok(());
+                }
+                if let Ok(path) = env::var("GRAPH_QUERY_CACHE_FILE") {
+                    restore_query_cache(&logger, generic_store.as_ref(), Path::new(&path));
+                }
+                QueryCacheInvalidator::new(&logger, generic_store.clone()).start();
+                if let Ok(path) = env::var("GRAPH_QUERY_WARMING_FILE") {
+                    let queries =
+                        read_warm_queries(Path::new(&path)).unwrap_or_else(|e| panic!("{}", e));
+                    CacheWarmer::new(
+                        &logger,
+                        generic_store.clone(),
+                        graphql_runner.clone(),
+                        queries,
+                    )
+                    .start();
+                }
+                let mut graphql_server = GraphQLQueryServer::new(
+                    &logger_factory,
+                    graphql_metrics_registry,
+                    graphql_runner.clone(),
+                    generic_store.clone(),
+                    node_id.clone(),
+                )
+                .with_shutdown(shutdown_for_stores.clone())
+                .with_cors(cors.clone())
+                .with_priority_classes(priority_classes.clone())
+                .with_composites(composites.clone())
+                .with_graphiql(!matches.is_present("disable-graphiql"));
+                let mut subscription_server = GraphQLSubscriptionServer::new(
+                    &logger,
+                    graphql_runner.clone(),
+                    generic_store.clone(),
+                );
 
-            if !disable_block_ingestor {
-                // BlockIngestor must be configured to keep at least REORG_THRESHOLD ancestors,
-                // otherwise BlockStream will not work properly.
-                // BlockStream expects the blocks after the reorg threshold to be present in the
-                // database.
-                assert!(*ANCESTOR_COUNT >= *REORG_THRESHOLD);
+                let mut index_node_server = IndexNodeServer::new(
+                    &logger_factory,
+                    graphql_runner.clone(),
+                    generic_store.clone(),
+                    stores.clone(),
+                    link_resolver.clone(),
+                    node_id.clone(),
+                );
 
-                info!(logger, "Starting block ingestors");
+                if let Some(tls) = &tls {
+                    graphql_server = graphql_server.with_tls(tls.clone());
+                    subscription_server = subscription_server.with_tls(tls.clone());
+                    index_node_server = index_node_server.with_tls(tls.clone());
+                }
 
-                // Create Ethereum block ingestors and spawn a thread to run each
-                eth_adapters.iter().for_each(|(network_name, eth_adapter)| {
+                if query_only {
                     info!(
                         logger,
-                        "Starting block ingestor for network";
-                        "network_name" => &network_name
+                        "Running as a query node; indexing and the admin server are disabled"
                     );
+                } else {
+                    // Spawn Ethereum network indexers for all networks that are to be indexed
+                    if let Some(network_subgraphs) = matches.values_of("network-subgraphs") {
+                        network_subgraphs
+                            .into_iter()
+                            .filter(|network_subgraph| network_subgraph.starts_with("ethereum/"))
+                            .for_each(|network_subgraph| {
+                                let network_name = network_subgraph.replace("ethereum/", "");
+                                let mut indexer = network_indexer::NetworkIndexer::new(
+                                    &logger,
+                                    eth_adapters
+                                        .get(&network_name)
+                                        .expect("adapter for network")
+                                        .clone(),
+                                    stores
+                                        .get(&network_name)
+                                        .expect("store for network")
+                                        .clone(),
+                                    metrics_registry.clone(),
+                                    format!("network/{}", network_subgraph).into(),
+                                    None,
+                                );
+                                graph::spawn(
+                                    indexer
+                                        .take_event_stream()
+                                        .unwrap()
+                                        .for_each(|_| {
+                                            // For now we simply ignore these events; we may later use them
+                                            // to drive subgraph indexing
+                                            Ok(())
+                                        })
+                                        .compat(),
+                                );
+                            })
+                    };
+
+                    if !disable_block_ingestor {
+                        // BlockIngestor must be configured to keep at least REORG_THRESHOLD ancestors,
+                        // otherwise BlockStream will not work properly.
+                        // BlockStream expects the blocks after the reorg threshold to be present in the
+                        // database.
+                        assert!(*ANCESTOR_COUNT >= *REORG_THRESHOLD);
+
+                        info!(logger, "Starting block ingestors");
+
+                        // Create Ethereum block ingestors and spawn a thread to run each
+                        let mut ingestors = vec![];
+                        eth_adapters.iter().for_each(|(network_name, eth_adapter)| {
+                            info!(
+                                logger,
+                                "Starting block ingestor for network";
+                                "network_name" => &network_name
+                            );
+
+                            let block_ingestor = BlockIngestor::new(
+                                stores.get(network_name).expect("network with name").clone(),
+                                eth_adapter.clone(),
+                                *ANCESTOR_COUNT,
+                                network_name.to_string(),
+                                &logger_factory,
+                                block_polling_interval,
+                                metrics_registry.clone(),
+                            )
+                            .expect("failed to create Ethereum block ingestor");
+                            ingestors.push(block_ingestor.health());
+
+                            // Run the Ethereum block ingestor in the background
+                            graph::spawn(block_ingestor.into_polling_stream());
+                        });
+                        if !ingestors.is_empty() {
+                            health_checks.push(Arc::new(BlockIngestorsCheck::new(ingestors)));
+                        }
+                    }
 
-                    let block_ingestor = BlockIngestor::new(
-                        stores.get(network_name).expect("network with name").clone(),
-                        eth_adapter.clone(),
-                        *ANCESTOR_COUNT,
-                        network_name.to_string(),
+                    let block_stream_builder = BlockStreamBuilder::new(
+                        generic_store.clone(),
+                        stores.clone(),
+                        eth_adapters.clone(),
+                        node_id.clone(),
+                        *REORG_THRESHOLD,
+                        metrics_registry.clone(),
+                    );
+                    let runtime_host_builder = WASMRuntimeHostBuilder::new(
+                        eth_adapters.clone(),
+                        link_resolver.clone(),
+                        stores.clone(),
+                        arweave_adapter,
+                        three_box_adapter,
+                    );
+
+                    let subgraph_instance_manager = SubgraphInstanceManager::new(
                         &logger_factory,
-                        block_polling_interval,
-                    )
-                    .expect("failed to create Ethereum block ingestor");
+                        stores.clone(),
+                        eth_adapters.clone(),
+                        runtime_host_builder,
+                        block_stream_builder,
+                        metrics_registry.clone(),
+                        graphql_runner.cheap_clone(),
+                        shutdown_for_stores.clone(),
+                    );
 
-                    // Run the Ethereum block ingestor in the background
-                    graph::spawn(block_ingestor.into_polling_stream());
-                });
-            }
+                    // Create IPFS-based subgraph provider
+                    let mut subgraph_provider = IpfsSubgraphAssignmentProvider::new(
+                        &logger_factory,
+                        link_resolver.clone(),
+                        generic_store.clone(),
+                        graphql_runner.clone(),
+                    );
 
-            let block_stream_builder = BlockStreamBuilder::new(
-                generic_store.clone(),
-                stores.clone(),
-                eth_adapters.clone(),
-                node_id.clone(),
-                *REORG_THRESHOLD,
-                metrics_registry.clone(),
-            );
-            let runtime_host_builder = WASMRuntimeHostBuilder::new(
-                eth_adapters.clone(),
-                link_resolver.clone(),
-                stores.clone(),
-                arweave_adapter,
-                three_box_adapter,
-            );
+                    // Forward subgraph events from the subgraph provider to the subgraph instance manager
+                    graph::spawn(
+                        forward(&mut subgraph_provider, &subgraph_instance_manager)
+                            .unwrap()
+                            .compat(),
+                    );
 
-            let subgraph_instance_manager = SubgraphInstanceManager::new(
-                &logger_factory,
-                stores.clone(),
-                eth_adapters.clone(),
-                runtime_host_builder,
-                block_stream_builder,
-                metrics_registry.clone(),
-                graphql_runner.cheap_clone(),
-            );
+                    // Check version switching mode environment variable. The
+                    // `EXPERIMENTAL_` name is still accepted for compatibility
+                    let version_switching_mode = SubgraphVersionSwitchingMode::parse(
+                        env::var_os("GRAPH_SUBGRAPH_VERSION_SWITCHING_MODE")
+                            .or_else(|| env::var_os("EXPERIMENTAL_SUBGRAPH_VERSION_SWITCHING_MODE"))
+                            .unwrap_or_else(|| "instant".into())
+                            .to_str()
+                            .expect("invalid version switching mode"),
+                    );
 
-            // Create IPFS-based subgraph provider
-            let mut subgraph_provider = IpfsSubgraphAssignmentProvider::new(
-                &logger_factory,
-                link_resolver.clone(),
-                generic_store.clone(),
-                graphql_runner.clone(),
-            );
+                    // Create named subgraph provider for resolving subgraph name->ID mappings
+                    let subgraph_registrar = IpfsSubgraphRegistrar::new(
+                        &logger_factory,
+                        link_resolver,
+                        Arc::new(subgraph_provider),
+                        generic_store.clone(),
+                        stores,
+                        eth_adapters.clone(),
+                        node_id.clone(),
+                        version_switching_mode,
+                    )
+                    .with_deployment_files(deployment_files.clone());
+                    spawn_heartbeat(
+                        logger.clone(),
+                        generic_store.clone(),
+                        node_id.clone(),
+                        placer.clone(),
+                    );
+                    if let Some(policy) = retirement {
+                        spawn_retirement(logger.clone(), generic_store.clone(), policy);
+                    }
+                    let subgraph_registrar = Arc::new(match placer {
+                        Some(placer) => subgraph_registrar.with_placer(placer),
+                        None => subgraph_registrar,
+                    });
+                    graph::spawn(
+                        subgraph_registrar
+                            .start()
+                            .map_err(|e| panic!("failed to initialize subgraph provider {}", e))
+                            .compat(),
+                    );
 
-            // Forward subgraph events from the subgraph provider to the subgraph instance manager
-            graph::spawn(
-                forward(&mut subgraph_provider, &subgraph_instance_manager)
-                    .unwrap()
-                    .compat(),
-            );
+                    // Start admin JSON-RPC server.
+                    let json_rpc_server = JsonRpcServer::serve(
+                        json_rpc_port,
+                        http_port,
+                        ws_port,
+                        subgraph_registrar.clone(),
+                        admin_auth,
+                        tls.clone(),
+                        logger.clone(),
+                    )
+                    .expect("failed to start JSON-RPC admin server");
 
-            // Check version switching mode environment variable
-            let version_switching_mode = SubgraphVersionSwitchingMode::parse(
-                env::var_os("EXPERIMENTAL_SUBGRAPH_VERSION_SWITCHING_MODE")
-                    .unwrap_or_else(|| "instant".into())
-                    .to_str()
-                    .expect("invalid version switching mode"),
-            );
+                    // Let the server run forever.
+                    std::mem::forget(json_rpc_server);
 
-            // Create named subgraph provider for resolving subgraph name->ID mappings
-            let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
-                &logger_factory,
-                link_resolver,
-                Arc::new(subgraph_provider),
-                generic_store.clone(),
-                stores,
-                eth_adapters.clone(),
-                node_id.clone(),
-                version_switching_mode,
-            ));
-            graph::spawn(
-                subgraph_registrar
-                    .start()
-                    .map_err(|e| panic!("failed to initialize subgraph provider {}", e))
-                    .compat(),
-            );
+                    // Add the CLI subgraph with a REST request to the admin server.
+                    if let Some(subgraph) = subgraph {
+                        let (name, hash) = if subgraph.contains(':') {
+                            let mut split = subgraph.split(':');
+                            (split.next().unwrap(), split.next().unwrap().to_owned())
+                        } else {
+                            ("cli", subgraph)
+                        };
 
-            // Start admin JSON-RPC server.
-            let json_rpc_server = JsonRpcServer::serve(
-                json_rpc_port,
-                http_port,
-                ws_port,
-                subgraph_registrar.clone(),
-                node_id.clone(),
-                logger.clone(),
-            )
-            .expect("failed to start JSON-RPC admin server");
-
-            // Let the server run forever.
-            std::mem::forget(json_rpc_server);
-
-            // Add the CLI subgraph with a REST request to the admin server.
-            if let Some(subgraph) = subgraph {
-                let (name, hash) = if subgraph.contains(':') {
-                    let mut split = subgraph.split(':');
-                    (split.next().unwrap(), split.next().unwrap().to_owned())
-                } else {
-                    ("cli", subgraph)
-                };
+                        let name = SubgraphName::new(name)
+                            .expect("Subgraph name must contain only a-z, A-Z, 0-9, '-' and '_'");
+                        let subgraph_id = SubgraphDeploymentId::new(hash)
+                            .expect("Subgraph hash must be a valid IPFS hash");
 
-                let name = SubgraphName::new(name)
-                    .expect("Subgraph name must contain only a-z, A-Z, 0-9, '-' and '_'");
-                let subgraph_id = SubgraphDeploymentId::new(hash)
-                    .expect("Subgraph hash must be a valid IPFS hash");
+                        graph::spawn(
+                            async move {
+                                subgraph_registrar.create_subgraph(name.clone()).await?;
+                                subgraph_registrar
+                                    .create_subgraph_version(
+                                        name,
+                                        subgraph_id,
+                                        Some(node_id),
+                                        None,
+                                        false,
+                                    )
+                                    .await
+                            }
+                            .map_err(|e| {
+                                panic!("Failed to deploy subgraph from `--subgraph` flag: {}", e)
+                            }),
+                        );
+                    }
+                }
 
+                // Serve GraphQL queries over HTTP
                 graph::spawn(
-                    async move {
-                        subgraph_registrar.create_subgraph(name.clone()).await?;
-                        subgraph_registrar
-                            .create_subgraph_version(name, subgraph_id, node_id)
-                            .await
-                    }
-                    .map_err(|e| panic!("Failed to deploy subgraph from `--subgraph` flag: {}", e)),
+                    graphql_server
+                        .serve(http_port, ws_port)
+                        .expect("Failed to start GraphQL query server")
+                        .compat(),
                 );
-            }
 
-            // Serve GraphQL queries over HTTP
-            graph::spawn(
-                graphql_server
-                    .serve(http_port, ws_port)
-                    .expect("Failed to start GraphQL query server")
-                    .compat(),
-            );
+                // Serve GraphQL subscriptions over WebSockets
+                graph::spawn(subscription_server.serve(ws_port));
 
-            // Serve GraphQL subscriptions over WebSockets
-            graph::spawn(subscription_server.serve(ws_port));
+                // Run the index node server
+                graph::spawn(
+                    index_node_server
+                        .serve(index_node_port)
+                        .expect("Failed to start index node server")
+                        .compat(),
+                );
 
-            // Run the index node server
-            graph::spawn(
-                index_node_server
-                    .serve(index_node_port)
-                    .expect("Failed to start index node server")
-                    .compat(),
-            );
+                graph::spawn(
+                    metrics_server
+                        .serve(metrics_port)
+                        .expect("Failed to start metrics server")
+                        .compat(),
+                );
 
-            graph::spawn(
-                metrics_server
-                    .serve(metrics_port)
-                    .expect("Failed to start metrics server")
-                    .compat(),
-            );
+                health_server.set_checks(health_checks);
 
-            future::ok(())
-        })
-        .compat(),
+                future::ok(())
+            })
+            .compat(),
     );
 
     // Periodically check for contention in the tokio threadpool. First spawn a
@@ -845,7 +1346,212 @@ async fn main() {
         }
     });
 
-    futures::future::pending::<()>().await;
+    termination_requested().await;
+    info!(
+        shutdown_logger,
+        "Shutting down; waiting for running queries and blocks to finish";
+        "timeout_s" => SHUTDOWN_TIMEOUT.as_secs(),
+    );
+    shutdown.trigger();
+    if shutdown.wait_idle(*SHUTDOWN_TIMEOUT).await {
+        info!(shutdown_logger, "Shutdown complete");
+    } else {
+        warn!(
+            shutdown_logger,
+            "Shutdown timed out; exiting with work still running"
+        );
+    }
+    if let Ok(path) = env::var("GRAPH_QUERY_CACHE_FILE") {
+        match save_query_cache(Path::new(&path)) {
+            Ok(responses) => info!(shutdown_logger, "Saved the query cache";
+                                   "path" => &path,
+                                   "responses" => responses),
+            Err(e) => warn!(shutdown_logger, "Failed to save the query cache";
+                            "error" => e.to_string()),
+        }
+    }
+    std::process::exit(0);
+}
+
+/// Fill the query cache with the responses that were saved when the node
+/// last shut down. Responses are kept if their deployment is still at the
+/// block they are for, or has not moved so far past it that the block would
+/// already have been evicted from the cache. Responses for blocks that were
+/// reorged away are never served since lookups compare block hashes
+fn restore_query_cache<S: Store>(logger: &Logger, store: &S, path: &Path) {
+    if !path.exists() {
+        return;
+    }
+    let blocks = query_cache_settings().blocks as u64;
+    let keep = |deployment: &SubgraphDeploymentId, block: &EthereumBlockPointer| {
+        let head = store.block_ptr(deployment.clone());
+        match head {
+            Ok(Some(head)) if head == *block => true,
+            Ok(Some(head)) => head.number > block.number && head.number - block.number < blocks,
+            _ => false,
+        }
+    };
+    match load_query_cache(path, keep) {
+        Ok(responses) => info!(logger, "Loaded the query cache";
+                               "path" => path.display().to_string(),
+                               "responses" => responses),
+        Err(e) => warn!(logger, "Failed to load the query cache"; "error" => e.to_string()),
+    }
+}
+
+/// Wait until the process receives SIGTERM or SIGINT
+async fn termination_requested() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("failed to install SIGTERM handler");
+    futures::future::select(
+        Box::pin(terminate.recv()),
+        Box::pin(tokio::signal::ctrl_c()),
+    )
+    .await;
+}
+
+/// Read the TLS certificate and key again whenever the process receives
+/// SIGHUP
+async fn reload_tls_on_hangup(logger: Logger, tls: Arc<TlsConfig>) {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("failed to install SIGHUP handler");
+    while let Some(()) = hangup.recv().await {
+        match tls.reload() {
+            Ok(()) => info!(logger, "Reloaded TLS certificate"),
+            Err(e) => warn!(logger, "Failed to reload TLS certificate, keeping the old one";
+                            "error" => e.to_string()),
+        }
+    }
+}
+
+/// Periodically retire superseded subgraph versions according to `policy`
+fn spawn_retirement(logger: Logger, store: Arc<DieselStore>, policy: RetirementPolicy) {
+    let logger = logger.new(o!("component" => "VersionRetirement"));
+    std::thread::spawn(move || loop {
+        match store.retire_versions(&policy) {
+            Ok(retired) if retired.is_empty() => (),
+            Ok(retired) => {
+                info!(logger, "Retired superseded deployments"; "count" => retired.len())
+            }
+            Err(e) => warn!(logger, "Failed to retire superseded deployments";
+                            "error" => e.to_string()),
+        }
+        std::thread::sleep(RETIREMENT_INTERVAL);
+    });
+}
+
+fn spawn_quota_sync(logger: Logger, quotas: Arc<Quotas>) {
+    let logger = logger.new(o!("component" => "QueryQuotas"));
+    std::thread::spawn(move || loop {
+        if let Err(e) = quotas.sync() {
+            warn!(logger, "Failed to sync query quotas"; "error" => e.to_string());
+        }
+        std::thread::sleep(*QUOTA_SYNC_INTERVAL);
+    });
+}
+
+/// Check the stores, providers and IPFS nodes that the node would use for
+/// `graph-node check`. Unlike at startup, every provider of a chain is
+/// checked, not just the first one
+async fn run_check(
+    logger: &Logger,
+    matches: &ArgMatches<'_>,
+    config: Option<&Config>,
+    shards: &[(String, String)],
+    ipfs_addresses: &[String],
+) -> Report {
+    let mut checks = vec![];
+
+    let mut stored = vec![];
+    for (name, url) in shards {
+        let (check, networks) = tokio::task::block_in_place(|| check::check_store(name, url));
+        checks.push(check);
+        stored.extend(networks);
+    }
+
+    let registry = Arc::new(MetricsRegistry::new(
+        logger.clone(),
+        Arc::new(Registry::new()),
+    ));
+    let eth_rpc_metrics = Arc::new(ProviderEthRpcMetrics::new(registry.clone()));
+    let mut providers: Vec<(String, String, Arc<dyn EthereumAdapterTrait>)> = vec![];
+    match config {
+        Some(config) => {
+            for (name, chain) in &config.chains.chains {
+                for provider in &chain.provider {
+                    let connection_type = match provider.transport {
+                        ConfigTransport::Rpc => ConnectionType::RPC,
+                        ConfigTransport::Ws => ConnectionType::WS,
+                        ConfigTransport::Ipc => ConnectionType::IPC,
+                        ConfigTransport::Fixture => {
+                            match FixtureEthereumAdapter::load(&provider.url) {
+                                Ok(adapter) => providers.push((
+                                    name.clone(),
+                                    provider.label.clone(),
+                                    Arc::new(adapter) as Arc<dyn EthereumAdapterTrait>,
+                                )),
+                                Err(e) => checks.push(Check::failed(
+                                    format!("provider/{}/{}", name, provider.label),
+                                    format!("failed to load the fixture: {}", e),
+                                )),
+                            }
+                            continue;
+                        }
+                    };
+                    let adapter = create_ethereum_adapter(
+                        logger,
+                        name,
+                        &provider.url,
+                        connection_type,
+                        eth_rpc_metrics.clone(),
+                    );
+                    providers.push((name.clone(), provider.label.clone(), adapter));
+                }
+            }
+        }
+        None => {
+            for (connection_type, arg) in &[
+                (ConnectionType::RPC, "ethereum-rpc"),
+                (ConnectionType::WS, "ethereum-ws"),
+                (ConnectionType::IPC, "ethereum-ipc"),
+            ] {
+                let values = match matches.values_of(arg) {
+                    Some(values) => values,
+                    None => continue,
+                };
+                match parse_ethereum_networks_and_nodes(
+                    logger.clone(),
+                    values,
+                    connection_type.clone(),
+                    registry.clone(),
+                ) {
+                    Ok(adapters) => providers.extend(
+                        adapters
+                            .into_iter()
+                            .map(|(name, adapter)| (name, arg.to_string(), adapter)),
+                    ),
+                    Err(e) => checks.push(Check::failed(arg.to_string(), e.to_string())),
+                }
+            }
+        }
+    }
+
+    let mut probed = vec![];
+    for (network, label, adapter) in &providers {
+        let (check, ident) = check::check_provider(logger, network, label, adapter.as_ref()).await;
+        checks.push(check);
+        if let Some(ident) = ident {
+            probed.push((network.clone(), label.clone(), ident));
+        }
+    }
+    checks.extend(check::check_networks(&stored, &probed));
+
+    for address in ipfs_addresses {
+        let name = SafeDisplay(address).to_string();
+        checks.push(check::check_ipfs(name, address).await);
+    }
+
+    Report::new(checks)
 }
 
 /// Parses an Ethereum connection string and returns the network name and Ethereum adapter.
@@ -888,31 +1594,208 @@ fn parse_ethereum_networks_and_nodes(
                     return Err(format_err!("Ethereum node URL cannot be an empty string"));
                 }
 
-                info!(
-                    logger,
-                    "Creating transport";
-                    "network" => &name,
-                    "url" => &loc,
-                );
-
-                let (transport_event_loop, transport) = match connection_type {
-                    ConnectionType::RPC => Transport::new_rpc(loc),
-                    ConnectionType::IPC => Transport::new_ipc(loc),
-                    ConnectionType::WS => Transport::new_ws(loc),
-                };
-
-                // If we drop the event loop the transport will stop working.
-                // For now it's fine to just leak it.
-                std::mem::forget(transport_event_loop);
-
                 Ok((
                     name.to_string(),
-                    Arc::new(graph_chain_ethereum::EthereumAdapter::new(
-                        transport,
+                    create_ethereum_adapter(
+                        &logger,
+                        name,
+                        loc,
+                        connection_type.clone(),
                         eth_rpc_metrics.clone(),
-                    )) as Arc<dyn EthereumAdapter>,
+                    ),
                 ))
             }
         })
         .collect()
 }
+
+/// Periodically record that this node is alive. If failover is turned on,
+/// also move deployments away from nodes that have stopped sending
+/// heartbeats, honoring the placement rules from `placer`
+fn spawn_heartbeat(
+    logger: Logger,
+    store: Arc<DieselStore>,
+    node_id: NodeId,
+    placer: Option<Arc<dyn DeploymentPlacer>>,
+) {
+    let logger = logger.new(o!("component" => "Heartbeat"));
+    std::thread::spawn(move || loop {
+        if let Err(e) = store.heartbeat(&node_id) {
+            warn!(logger, "Failed to record heartbeat"; "error" => e.to_string());
+        }
+        if let Some(timeout) = *FAILOVER_TIMEOUT {
+            match store.fail_over(timeout, placer.as_ref().map(|placer| placer.as_ref())) {
+                Ok(0) => (),
+                Ok(moved) => info!(logger, "Moved deployments from dead nodes"; "count" => moved),
+                Err(e) => warn!(logger, "Failed to move deployments from dead nodes";
+                                "error" => e.to_string()),
+            }
+        }
+        std::thread::sleep(*HEARTBEAT_INTERVAL);
+    });
+}
+
+/// Creates the Ethereum adapters for the chains in the configuration file.
+/// The node can only use one provider per network; if more than one is
+/// configured, the first one is used. The transports of the adapters are
+/// returned, too, so that they can be replaced when the configuration
+/// file changes; chains that are served from a fixture have no transport
+fn create_ethereum_adapters_from_config(
+    logger: &Logger,
+    config: &Config,
+    registry: Arc<MetricsRegistry>,
+) -> (
+    HashMap<String, Arc<dyn EthereumAdapterTrait>>,
+    HashMap<String, ReloadableTransport>,
+) {
+    let eth_rpc_metrics = Arc::new(ProviderEthRpcMetrics::new(registry));
+    let mut adapters = HashMap::new();
+    let mut transports = HashMap::new();
+    for (name, chain) in &config.chains.chains {
+        if chain.provider.len() > 1 {
+            warn!(
+                logger,
+                "Only the first provider for a network is used";
+                "network" => name,
+                "provider" => &chain.provider[0].label,
+            );
+        }
+        if chain.uses_fixture() {
+            let dir = &chain.provider[0].url;
+            let adapter = FixtureEthereumAdapter::load(dir).unwrap_or_else(|e| {
+                panic!("Failed to load the fixture for network `{}`: {}", name, e)
+            });
+            info!(logger, "Serving blocks from fixture"; "network" => name, "fixture" => dir);
+            adapters.insert(
+                name.clone(),
+                Arc::new(adapter) as Arc<dyn EthereumAdapterTrait>,
+            );
+            continue;
+        }
+        let transport = ReloadableTransport::new(provider_transport(logger, name, chain));
+        transport.set_rate_limit(chain.provider[0].rate_limit);
+        adapters.insert(
+            name.clone(),
+            Arc::new(graph_chain_ethereum::EthereumAdapter::new(
+                transport.clone(),
+                eth_rpc_metrics.clone(),
+            )) as Arc<dyn EthereumAdapterTrait>,
+        );
+        transports.insert(name.clone(), transport);
+    }
+    (adapters, transports)
+}
+
+/// Create the transport for the provider that is used for `chain`
+fn provider_transport(logger: &Logger, name: &str, chain: &ConfigChain) -> Transport {
+    // Validation ensures that there is at least one provider
+    let provider = &chain.provider[0];
+    let connection_type = match provider.transport {
+        ConfigTransport::Rpc => ConnectionType::RPC,
+        ConfigTransport::Ws => ConnectionType::WS,
+        ConfigTransport::Ipc => ConnectionType::IPC,
+        ConfigTransport::Fixture => unreachable!("fixtures do not use a transport"),
+    };
+    create_transport(logger, name, &provider.url, connection_type)
+}
+
+/// Apply the changes between `old` and `new` that can be made while the
+/// node is running, and warn about the ones that require a restart. Returns
+/// the configuration that is now in effect
+fn reload_config(
+    logger: &Logger,
+    old: &Config,
+    new: &Config,
+    transports: &HashMap<String, ReloadableTransport>,
+) -> Config {
+    for setting in old.requires_restart(new) {
+        warn!(
+            logger,
+            "Changed configuration requires a restart to take effect";
+            "setting" => setting,
+        );
+    }
+
+    let applied = old.reloaded(new);
+    for (name, chain) in &applied.chains.chains {
+        let (old_provider, provider) = (&old.chains.chains[name].provider[0], &chain.provider[0]);
+        let transport = match transports.get(name) {
+            Some(transport) => transport,
+            // Chains served from a fixture have no transport
+            None => continue,
+        };
+        if !old_provider.same_connection(provider) {
+            info!(
+                logger,
+                "Switching to new provider";
+                "network" => name,
+                "provider" => &provider.label,
+            );
+            transport.replace(provider_transport(logger, name, chain));
+        }
+        if old_provider.rate_limit != provider.rate_limit {
+            let rate_limit = provider
+                .rate_limit
+                .map_or("none".to_string(), |rate| rate.to_string());
+            info!(
+                logger,
+                "Changing provider rate limit";
+                "network" => name,
+                "provider" => &provider.label,
+                "rate_limit" => rate_limit,
+            );
+            transport.set_rate_limit(provider.rate_limit);
+        }
+    }
+
+    if old.query != applied.query {
+        let settings = applied.query.cache_settings();
+        info!(
+            logger,
+            "Changing query cache settings";
+            "blocks" => settings.blocks,
+            "subgraph_ids" => settings.subgraph_ids.join(","),
+        );
+        set_query_cache_settings(settings);
+    }
+    applied
+}
+
+fn create_ethereum_adapter(
+    logger: &Logger,
+    name: &str,
+    loc: &str,
+    connection_type: ConnectionType,
+    eth_rpc_metrics: Arc<ProviderEthRpcMetrics>,
+) -> Arc<dyn EthereumAdapterTrait> {
+    Arc::new(graph_chain_ethereum::EthereumAdapter::new(
+        create_transport(logger, name, loc, connection_type),
+        eth_rpc_metrics,
+    ))
+}
+
+fn create_transport(
+    logger: &Logger,
+    name: &str,
+    loc: &str,
+    connection_type: ConnectionType,
+) -> Transport {
+    info!(
+        logger,
+        "Creating transport";
+        "network" => &name,
+        "url" => &loc,
+    );
+
+    let (transport_event_loop, transport) = match connection_type {
+        ConnectionType::RPC => Transport::new_rpc(loc),
+        ConnectionType::IPC => Transport::new_ipc(loc),
+        ConnectionType::WS => Transport::new_ws(loc),
+    };
+
+    // If we drop the event loop the transport will stop working.
+    // For now it's fine to just leak it.
+    std::mem::forget(transport_event_loop);
+
+    transport
+}
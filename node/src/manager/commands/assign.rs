@@ -0,0 +1,27 @@
+use graph::prelude::{format_err, Error, NodeId};
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection};
+
+pub fn unassign(conn: &PooledPgConnection, search: &str) -> Result<(), Error> {
+    let id = cs::locate(conn, search)?;
+    if cs::unassign(conn, &id)? {
+        println!("unassigned {}", id);
+    } else {
+        println!("{} was not assigned to any node", id);
+    }
+    Ok(())
+}
+
+pub fn reassign(conn: &PooledPgConnection, search: &str, node: &str) -> Result<(), Error> {
+    let node = NodeId::new(node).map_err(|()| format_err!("invalid node id `{}`", node))?;
+    let id = cs::locate(conn, search)?;
+    match cs::assigned_node(conn, &id)? {
+        Some(current) if current == node => {
+            println!("{} is already assigned to {}", id, node);
+            return Ok(());
+        }
+        Some(current) => println!("reassigning {} from {} to {}", id, current, node),
+        None => println!("assigning {} to {}", id, node),
+    }
+    cs::reassign(conn, &id, &node)?;
+    Ok(())
+}
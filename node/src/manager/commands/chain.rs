@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+use graph::prelude::{format_err, Error};
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection};
+
+pub fn list(conn: &PooledPgConnection) -> Result<(), Error> {
+    let chains = cs::chains(conn)?;
+    println!(
+        "{:<20} | {:<12} | {:>10} | {}",
+        "name", "net version", "head", "genesis block"
+    );
+    println!("{:-<20}-+-{:-<12}-+-{:->10}-+-{:-<66}", "", "", "", "");
+    for chain in chains {
+        println!(
+            "{:<20} | {:<12} | {:>10} | {}",
+            chain.name,
+            chain.net_version.unwrap_or_default(),
+            chain
+                .head_block_number
+                .map(|number| number.to_string())
+                .unwrap_or_default(),
+            chain.genesis_block_hash.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+fn block_number(number: Option<&str>) -> Result<Option<i64>, Error> {
+    number
+        .map(|number| {
+            number
+                .parse::<i64>()
+                .map_err(|e| format_err!("invalid block number `{}`: {}", number, e))
+        })
+        .transpose()
+}
+
+pub fn export(
+    conn: &PooledPgConnection,
+    chain: &str,
+    path: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<(), Error> {
+    let (from, to) = (block_number(from)?, block_number(to)?);
+    let file = File::create(path).map_err(|e| format_err!("failed to create `{}`: {}", path, e))?;
+    let mut out = BufWriter::new(file);
+    let (blocks, calls) = cs::export_chain(conn, chain, from, to, &mut out)?;
+    out.flush()
+        .map_err(|e| format_err!("failed to write `{}`: {}", path, e))?;
+    println!(
+        "exported {} blocks and {} contract calls of {} to {}",
+        blocks, calls, chain, path
+    );
+    Ok(())
+}
+
+pub fn import(conn: &PooledPgConnection, chain: &str, path: &str) -> Result<(), Error> {
+    let file = File::open(path).map_err(|e| format_err!("failed to open `{}`: {}", path, e))?;
+    let (blocks, calls) = cs::import_chain(conn, chain, &mut BufReader::new(file))?;
+    println!(
+        "imported {} new blocks and {} new contract calls into {} from {}",
+        blocks, calls, chain, path
+    );
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use graph::prelude::{Error, Logger};
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection};
+
+use crate::manager::block_ptr;
+
+pub fn run(
+    logger: &Logger,
+    conn: &PooledPgConnection,
+    src: &str,
+    dst: &str,
+    block_hash: &str,
+    block_number: &str,
+) -> Result<(), Error> {
+    let block = block_ptr(block_hash, block_number)?;
+    let src = cs::locate(conn, src)?;
+    let dst = cs::locate(conn, dst)?;
+    cs::copy(logger, conn, &src, &dst, block)?;
+    println!("copied {} into {} as of block {}", src, dst, block);
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+use graph::prelude::{format_err, Error};
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection};
+
+pub fn dump(conn: &PooledPgConnection, search: &str, path: &str) -> Result<(), Error> {
+    let id = cs::locate(conn, search)?;
+    let file = File::create(path).map_err(|e| format_err!("failed to create `{}`: {}", path, e))?;
+    let mut out = BufWriter::new(file);
+    let count = cs::dump(conn, &id, &mut out)?;
+    out.flush()
+        .map_err(|e| format_err!("failed to write `{}`: {}", path, e))?;
+    println!("dumped {} rows of {} to {}", count, id, path);
+    Ok(())
+}
+
+pub fn restore(conn: &PooledPgConnection, path: &str) -> Result<(), Error> {
+    let file = File::open(path).map_err(|e| format_err!("failed to open `{}`: {}", path, e))?;
+    let id = cs::restore(conn, &mut BufReader::new(file))?;
+    println!(
+        "restored {} from {}; it is not assigned to any node",
+        id, path
+    );
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use graph::prelude::Error;
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection};
+
+pub fn create(
+    conn: &PooledPgConnection,
+    search: &str,
+    entity: &str,
+    fields: Vec<String>,
+    method: &str,
+) -> Result<(), Error> {
+    let id = cs::locate(conn, search)?;
+    println!(
+        "creating index on {}({}) in {}",
+        entity,
+        fields.join(", "),
+        id
+    );
+    let name = cs::create_index(conn, &id, entity, &fields, method)?;
+    println!("created index {}", name);
+    Ok(())
+}
+
+pub fn list(conn: &PooledPgConnection, search: &str, entity: &str) -> Result<(), Error> {
+    let id = cs::locate(conn, search)?;
+    for index in cs::list_indexes(conn, &id, entity)? {
+        println!("{}\n    {}", index.name, index.definition);
+    }
+    Ok(())
+}
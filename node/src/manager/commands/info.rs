@@ -0,0 +1,35 @@
+use graph::prelude::Error;
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection};
+
+fn opt<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub fn run(conn: &PooledPgConnection, search: &str) -> Result<(), Error> {
+    let infos = cs::deployments(conn, search)?;
+    if infos.is_empty() {
+        println!("No deployments match `{}`", search);
+        return Ok(());
+    }
+    for info in infos {
+        println!("{:-^76}", "");
+        println!("{:<16} | {}", "name", opt(&info.name));
+        println!("{:<16} | {}", "status", info.status);
+        println!("{:<16} | {}", "deployment", info.deployment);
+        println!("{:<16} | {}", "namespace", info.namespace);
+        println!("{:<16} | {}", "network", opt(&info.network));
+        println!("{:<16} | {}", "node_id", opt(&info.node_id));
+        println!("{:<16} | {}", "health", opt(&info.health));
+        println!("{:<16} | {}", "synced", opt(&info.synced));
+        println!("{:<16} | {}", "latest block", opt(&info.latest_block));
+        println!(
+            "{:<16} | {}",
+            "chain head block",
+            opt(&info.chain_head_block)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,10 @@
+pub mod assign;
+pub mod chain;
+pub mod copy;
+pub mod dump;
+pub mod index;
+pub mod info;
+pub mod place;
+pub mod rewind;
+pub mod stats;
+pub mod unused;
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use graph::prelude::{format_err, DeploymentPlacer, Error, NodeId, SubgraphDeploymentId};
+use graph_store_postgres::command_support::{self as cs, DeploymentInfo, PooledPgConnection};
+
+use crate::config::Deployment;
+
+/// Check the placement of deployments against the placement rules in
+/// `rules` and print where each of them should be. If `search` is given,
+/// only consider the deployments it matches, otherwise consider all current
+/// and pending deployments. With `apply`, reassign deployments that are on
+/// a node the rules do not allow, choosing the allowed node with the fewest
+/// assignments
+pub fn run(
+    conn: &PooledPgConnection,
+    rules: &Deployment,
+    search: Option<&str>,
+    apply: bool,
+) -> Result<(), Error> {
+    let infos = match search {
+        Some(search) => cs::deployments(conn, search)?,
+        None => cs::active_deployments(conn)?,
+    };
+    let mut counts = cs::assignment_counts(conn)?;
+
+    for info in infos {
+        let (name, network) = match (&info.name, &info.network) {
+            (Some(name), Some(network)) => (name, network),
+            _ => continue,
+        };
+        let current = info.node_id.as_ref().map(String::as_str).unwrap_or("-");
+        let nodes = match rules
+            .place(name, network)
+            .map_err(|e| format_err!("{}", e))?
+        {
+            Some(nodes) => nodes,
+            None => {
+                println!(
+                    "{:<40} {} {:<10} no rule applies",
+                    name, info.deployment, current
+                );
+                continue;
+            }
+        };
+        let allowed = nodes
+            .iter()
+            .map(|node| node.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        if nodes.iter().any(|node| node.as_str() == current) {
+            println!("{:<40} {} {:<10} ok", name, info.deployment, current);
+        } else if apply {
+            let node = least_assigned(&nodes, &counts);
+            reassign(conn, &info, &node)?;
+            *counts.entry(node.to_string()).or_insert(0) += 1;
+            if let Some(count) = info.node_id.as_ref().and_then(|node| counts.get_mut(node)) {
+                *count -= 1;
+            }
+            println!(
+                "{:<40} {} {:<10} reassigned to {}",
+                name, info.deployment, current, node
+            );
+        } else {
+            println!(
+                "{:<40} {} {:<10} should be on one of {}",
+                name, info.deployment, current, allowed
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Return the node from `nodes` with the fewest assignments, preferring
+/// nodes that come earlier in the list when there is a tie
+fn least_assigned(nodes: &[NodeId], counts: &HashMap<String, i64>) -> NodeId {
+    nodes
+        .iter()
+        .min_by_key(|node| counts.get(node.as_str()).cloned().unwrap_or(0))
+        .cloned()
+        .expect("placement rules produce at least one node")
+}
+
+fn reassign(conn: &PooledPgConnection, info: &DeploymentInfo, node: &NodeId) -> Result<(), Error> {
+    let id = SubgraphDeploymentId::new(info.deployment.clone())
+        .map_err(|()| format_err!("`{}` is not a valid deployment id", info.deployment))?;
+    cs::reassign(conn, &id, node)?;
+    Ok(())
+}
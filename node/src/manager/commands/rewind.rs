@@ -0,0 +1,17 @@
+use graph::prelude::Error;
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection};
+
+use crate::manager::block_ptr;
+
+pub fn run(
+    conn: &PooledPgConnection,
+    search: &str,
+    block_hash: &str,
+    block_number: &str,
+) -> Result<(), Error> {
+    let block = block_ptr(block_hash, block_number)?;
+    let id = cs::locate(conn, search)?;
+    cs::rewind(conn, &id, block)?;
+    println!("rewound {} to block {}", id, block);
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use graph::prelude::Error;
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection};
+
+pub fn run(conn: &PooledPgConnection, search: &str) -> Result<(), Error> {
+    let id = cs::locate(conn, search)?;
+    let stats = cs::stats(conn, &id)?;
+
+    println!("{:<40} | {:>12} | {:>14}", "table", "rows (est.)", "bytes");
+    println!("{:-<40}-+-{:->12}-+-{:->14}", "", "", "");
+    for table in &stats {
+        println!(
+            "{:<40} | {:>12} | {:>14}",
+            table.table, table.rows, table.bytes
+        );
+    }
+    println!("{:-<40}-+-{:->12}-+-{:->14}", "", "", "");
+    println!(
+        "{:<40} | {:>12} | {:>14}",
+        "total",
+        stats.iter().map(|table| table.rows).sum::<i64>(),
+        stats.iter().map(|table| table.bytes).sum::<i64>()
+    );
+    Ok(())
+}
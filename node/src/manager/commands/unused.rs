@@ -0,0 +1,66 @@
+use graph::prelude::{format_err, Error, SubgraphDeploymentId};
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection, UnusedDeployment};
+
+fn print(deployments: &[UnusedDeployment]) {
+    println!(
+        "{:<46} | {:<8} | {:>12} | {}",
+        "deployment", "schema", "entities", "unused since"
+    );
+    println!("{:-<46}-+-{:-<8}-+-{:->12}-+-{:-<26}", "", "", "", "");
+    for deployment in deployments {
+        println!(
+            "{:<46} | {:<8} | {:>12} | {}{}",
+            deployment.deployment,
+            deployment.namespace,
+            deployment
+                .entity_count
+                .map(|count| count.to_string())
+                .unwrap_or_default(),
+            deployment.unused_at,
+            deployment
+                .removed_at
+                .as_ref()
+                .map(|at| format!(" (removed {})", at))
+                .unwrap_or_default()
+        );
+    }
+}
+
+pub fn record(conn: &PooledPgConnection) -> Result<(), Error> {
+    let recorded = cs::record_unused(conn)?;
+    println!("Recorded {} unused deployments", recorded.len());
+    if !recorded.is_empty() {
+        print(&recorded);
+    }
+    Ok(())
+}
+
+pub fn list(conn: &PooledPgConnection, all: bool) -> Result<(), Error> {
+    print(&cs::list_unused(conn, all)?);
+    Ok(())
+}
+
+/// Remove the deployment `deployment` if it is given, or all deployments
+/// that have been recorded as unused and not been removed yet
+pub fn remove(conn: &PooledPgConnection, deployment: Option<&str>) -> Result<(), Error> {
+    let unused = cs::list_unused(conn, false)?;
+    let ids = match deployment {
+        Some(deployment) => {
+            if !unused.iter().any(|unused| unused.deployment == deployment) {
+                return Err(format_err!(
+                    "deployment `{}` is not recorded as unused; run `graphman unused record` first",
+                    deployment
+                ));
+            }
+            vec![deployment.to_string()]
+        }
+        None => unused.into_iter().map(|unused| unused.deployment).collect(),
+    };
+    for id in ids {
+        let id = SubgraphDeploymentId::new(id.clone())
+            .map_err(|()| format_err!("`{}` is not a valid deployment id", id))?;
+        println!("removing {}", id);
+        cs::remove_unused(conn, &id)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,19 @@
+//! The implementation of `graphman`, a tool for operators of a
+//! `graph-node` installation to perform routine maintenance without
+//! having to write SQL by hand
+use graph::prelude::{format_err, web3::types::H256, Error, EthereumBlockPointer};
+
+pub mod commands;
+
+/// Build a block pointer from a block hash and number given on the
+/// command line
+pub fn block_ptr(hash: &str, number: &str) -> Result<EthereumBlockPointer, Error> {
+    let hash = hash
+        .trim_start_matches("0x")
+        .parse::<H256>()
+        .map_err(|e| format_err!("invalid block hash `{}`: {}", hash, e))?;
+    let number = number
+        .parse::<u64>()
+        .map_err(|e| format_err!("invalid block number `{}`: {}", number, e))?;
+    Ok(EthereumBlockPointer::from((hash, number)))
+}
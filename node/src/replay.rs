@@ -0,0 +1,191 @@
+//! Replay a range of blocks that a deployment has already processed from
+//! cached inputs into a scratch deployment and compare the entity changes
+//! of each block with the original; see `graph_core::replay`
+use std::collections::HashMap;
+
+use graph::components::arweave::ArweaveAdapter;
+use graph::components::three_box::ThreeBoxAdapter;
+use graph::data::graphql::ext::DocumentExt;
+use graph::data::schema::SCHEMA_TYPE_NAME;
+use graph::prelude::{DataSourceLoader as _, *};
+use graph_chain_ethereum::BlockStreamBuilder;
+use graph_core::replay::{self, CachedEthereumAdapter};
+use graph_core::{DataSourceLoader, LinkResolver, SubgraphInstanceManager};
+use graph_runtime_wasm::RuntimeHostBuilder;
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection};
+
+/// What to replay, as given on the command line
+pub struct Replay {
+    /// The deployment whose blocks are replayed
+    pub deployment: String,
+    /// The id of the deployment that the replay writes to; it must not
+    /// exist yet
+    pub scratch: SubgraphDeploymentId,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl Replay {
+    /// Parse the values of `--replay DEPLOYMENT SCRATCH FROM TO`
+    pub fn parse(values: &[&str]) -> Result<Self, Error> {
+        let number = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|e| format_err!("invalid block number `{}`: {}", value, e))
+        };
+        match values {
+            [deployment, scratch, from, to] => Ok(Replay {
+                deployment: deployment.to_string(),
+                scratch: SubgraphDeploymentId::new(*scratch)
+                    .map_err(|()| format_err!("invalid deployment id `{}`", scratch))?,
+                from: number(*from)?,
+                to: number(*to)?,
+            }),
+            _ => Err(format_err!(
+                "--replay needs a deployment, a scratch deployment, and a block range"
+            )),
+        }
+    }
+}
+
+/// Replay the blocks of `replay` and print how the entity changes of the
+/// replay differ from the original ones. Returns the number of changes
+/// that differ
+pub async fn run<S, Q>(
+    logger: &Logger,
+    conn: PooledPgConnection,
+    store: Arc<S>,
+    stores: HashMap<String, Arc<S>>,
+    link_resolver: &LinkResolver,
+    graphql_runner: Arc<Q>,
+    node_id: NodeId,
+    reorg_threshold: u64,
+    registry: Arc<impl MetricsRegistry>,
+    arweave_adapter: Arc<dyn ArweaveAdapter>,
+    three_box_adapter: Arc<dyn ThreeBoxAdapter>,
+    replay: Replay,
+) -> Result<usize, Error>
+where
+    S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
+    Q: GraphQlRunner,
+{
+    let original = cs::locate(&conn, &replay.deployment)?;
+    let scratch = replay.scratch.clone();
+    if store.is_deployed(&scratch)? {
+        return Err(format_err!(
+            "the scratch deployment `{}` already exists; use a new id for each replay",
+            scratch
+        ));
+    }
+    let head = store
+        .block_ptr(original.clone())?
+        .ok_or_else(|| format_err!("deployment `{}` has not processed any blocks", original))?;
+
+    // Only the inputs of handlers have to come from the caches; the files
+    // of the deployment itself are read the usual way
+    let manifest = SubgraphManifest::resolve(original.to_ipfs_link(), link_resolver, logger)
+        .await
+        .map_err(|e| format_err!("failed to resolve the manifest of `{}`: {}", original, e))?;
+    let network_name = manifest.network_name();
+    let chain_store = stores
+        .get(&network_name)
+        .cloned()
+        .ok_or_else(|| format_err!("network `{}` is not known", network_name))?;
+    let blocks = replay::cached_blocks(&*chain_store, head, replay.from, replay.to)?;
+
+    // Create the scratch deployment with the data of the original as of
+    // the block before the replay
+    let mut scratch_manifest = manifest.clone();
+    scratch_manifest.id = scratch.clone();
+    scratch_manifest.schema.set_subgraph_id(scratch.clone());
+    let ops =
+        SubgraphDeploymentEntity::new(&scratch_manifest, false, None).create_operations(&scratch);
+    store.create_subgraph_deployment(&scratch_manifest.schema, ops)?;
+    if replay.from > 0 {
+        let parent = EthereumBlockPointer::from((blocks[0].block.parent_hash, replay.from - 1));
+        cs::copy(logger, &conn, &original, &scratch, parent)?;
+    }
+    info!(logger, "Created scratch deployment for the replay";
+          "deployment" => original.as_str(), "scratch" => scratch.as_str());
+
+    let link_resolver = Arc::new(link_resolver.clone().cache_only());
+    let loader = DataSourceLoader::new(store.clone(), link_resolver.clone(), graphql_runner);
+    let data_sources = loader
+        .load_dynamic_data_sources(scratch.clone(), logger.clone())
+        .await?;
+    scratch_manifest.data_sources.extend(data_sources);
+
+    let eth_adapter: Arc<dyn EthereumAdapter> =
+        Arc::new(CachedEthereumAdapter::new(chain_store.clone()));
+    let mut eth_adapters = HashMap::new();
+    eth_adapters.insert(network_name, eth_adapter.clone());
+    let host_builder = RuntimeHostBuilder::new(
+        eth_adapters.clone(),
+        link_resolver,
+        stores.clone(),
+        arweave_adapter,
+        three_box_adapter,
+    );
+    let stream_builder = BlockStreamBuilder::new(
+        store.clone(),
+        stores,
+        eth_adapters,
+        node_id,
+        reorg_threshold,
+        registry.clone(),
+    );
+    SubgraphInstanceManager::replay_blocks(
+        logger.clone(),
+        host_builder,
+        stream_builder,
+        store.clone(),
+        eth_adapter,
+        scratch_manifest,
+        registry,
+        blocks,
+    )
+    .await?;
+
+    let entity_types: Vec<_> = manifest
+        .schema
+        .document
+        .get_object_type_definitions()
+        .into_iter()
+        .filter(|object_type| object_type.name != SCHEMA_TYPE_NAME)
+        .map(|object_type| object_type.name.clone())
+        .collect();
+    let mut differences = 0;
+    for number in replay.from..=replay.to {
+        let block = number as BlockNumber;
+        let diffs = replay::diff_changes(
+            store.changes_in_block(&original, &entity_types, block)?,
+            store.changes_in_block(&scratch, &entity_types, block)?,
+        );
+        for diff in &diffs {
+            println!("block {}: {}", number, diff);
+        }
+        differences += diffs.len();
+    }
+    println!(
+        "replayed blocks {} to {} of {} into {}: {} differences",
+        replay.from, replay.to, original, scratch, differences
+    );
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_replays() {
+        let replay = Replay::parse(&["sgd42", "QmScratch", "10", "20"]).unwrap();
+        assert_eq!("sgd42", replay.deployment);
+        assert_eq!("QmScratch", replay.scratch.as_str());
+        assert_eq!((10, 20), (replay.from, replay.to));
+
+        assert!(Replay::parse(&["sgd42", "Qm-Scratch", "10", "20"]).is_err());
+        assert!(Replay::parse(&["sgd42", "QmScratch", "ten", "20"]).is_err());
+        assert!(Replay::parse(&["sgd42", "QmScratch", "10"]).is_err());
+    }
+}
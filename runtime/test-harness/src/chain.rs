@@ -0,0 +1,316 @@
+use std::sync::Mutex;
+
+use ethabi::{Event, ParamType, Token};
+use graph::mock::MockEthereumAdapter;
+use graph::prelude::*;
+use tiny_keccak::keccak256;
+use web3::types::{Address, Bytes, Log, H256, U256};
+
+struct MockedCall {
+    address: Address,
+    function: String,
+    args: Vec<Token>,
+    result: Result<Vec<Token>, String>,
+}
+
+/// The results of the contract calls that mappings make. Calls whose
+/// result is not mocked revert
+#[derive(Clone, Default)]
+pub struct MockCalls(Arc<Mutex<Vec<MockedCall>>>);
+
+impl MockCalls {
+    /// Let calls of `function` on `address` with `args` return `result`,
+    /// or revert with the reason in `result`
+    pub fn insert(
+        &self,
+        address: Address,
+        function: &str,
+        args: Vec<Token>,
+        result: Result<Vec<Token>, String>,
+    ) {
+        let mut calls = self.0.lock().unwrap();
+        calls.retain(|call| {
+            call.address != address || call.function != function || call.args != args
+        });
+        calls.push(MockedCall {
+            address,
+            function: function.to_owned(),
+            args,
+            result,
+        });
+    }
+
+    fn result(&self, call: &EthereumContractCall) -> Result<Vec<Token>, EthereumContractCallError> {
+        let calls = self.0.lock().unwrap();
+        let mocked = calls.iter().find(|mocked| {
+            mocked.address == call.address
+                && mocked.function == call.function.name
+                && mocked.args == call.args
+        });
+        match mocked {
+            Some(mocked) => mocked
+                .result
+                .clone()
+                .map_err(EthereumContractCallError::Revert),
+            None => Err(EthereumContractCallError::Revert(format!(
+                "no result is mocked for the call of `{}` on {:?} with {:?}",
+                call.function.name, call.address, call.args
+            ))),
+        }
+    }
+
+    /// An Ethereum adapter that answers contract calls with the mocked
+    /// results. The mappings can not use any other part of the adapter
+    pub fn adapter(&self) -> MockEthereumAdapter {
+        let calls = self.clone();
+        let mut adapter = MockEthereumAdapter::default();
+        adapter
+            .expect_contract_call()
+            .returning(move |_, call, _| Box::new(future::result(calls.result(&call))));
+        adapter
+    }
+}
+
+/// The topic for an indexed event parameter. Values of dynamic types are
+/// hashed, like Solidity does
+fn indexed_topic(token: Token) -> H256 {
+    match token {
+        Token::String(s) => H256::from(keccak256(s.as_bytes())),
+        Token::Bytes(bytes) => H256::from(keccak256(&bytes)),
+        token @ Token::Array(_) | token @ Token::FixedArray(_) | token @ Token::Tuple(_) => {
+            H256::from(keccak256(&ethabi::encode(&[token])))
+        }
+        token => {
+            let mut topic = [0u8; 32];
+            topic.copy_from_slice(&ethabi::encode(&[token]));
+            H256::from(topic)
+        }
+    }
+}
+
+/// Encode `params` as the log of `event` with the topic `topic0` that
+/// `address` emits
+pub fn event_log(
+    address: Address,
+    topic0: H256,
+    event: &Event,
+    params: Vec<Token>,
+) -> Result<Log, Error> {
+    if params.len() != event.inputs.len() {
+        return Err(format_err!(
+            "event `{}` has {} parameters, but {} were given",
+            event.name,
+            event.inputs.len(),
+            params.len()
+        ));
+    }
+    let mut topics = vec![topic0];
+    let mut data = vec![];
+    for (input, param) in event.inputs.iter().zip(params) {
+        if !param.type_check(&input.kind) {
+            return Err(format_err!(
+                "parameter `{}` of event `{}` is a {}, but {:?} was given",
+                input.name,
+                event.name,
+                input.kind,
+                param
+            ));
+        }
+        if input.indexed {
+            topics.push(indexed_topic(param));
+        } else {
+            data.push(param);
+        }
+    }
+    Ok(Log {
+        address,
+        topics,
+        data: Bytes(ethabi::encode(&data)),
+        block_hash: None,
+        block_number: None,
+        transaction_hash: None,
+        transaction_index: None,
+        log_index: None,
+        transaction_log_index: None,
+        log_type: None,
+        removed: None,
+    })
+}
+
+fn json_string(value: &serde_json::Value) -> Result<String, Error> {
+    match value {
+        serde_json::Value::String(s) => Ok(s.clone()),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        _ => Err(format_err!("expected a string or a number, not {}", value)),
+    }
+}
+
+fn json_bytes(value: &serde_json::Value) -> Result<Vec<u8>, Error> {
+    let s = json_string(value)?;
+    hex::decode(s.trim_start_matches("0x"))
+        .map_err(|e| format_err!("invalid hex string `{}`: {}", s, e))
+}
+
+/// Convert the JSON array `value` to tokens of the types `kinds`
+pub fn tokens_from_json(
+    kinds: &[&ParamType],
+    value: &serde_json::Value,
+) -> Result<Vec<Token>, Error> {
+    let values = value
+        .as_array()
+        .ok_or_else(|| format_err!("expected an array, not {}", value))?;
+    if kinds.len() != values.len() {
+        return Err(format_err!(
+            "expected {} values, not {}",
+            kinds.len(),
+            values.len()
+        ));
+    }
+    kinds
+        .iter()
+        .zip(values)
+        .map(|(kind, value)| token_from_json(kind, value))
+        .collect()
+}
+
+/// Convert `value` to a token of type `kind`. Numbers are JSON numbers or
+/// decimal strings, addresses and bytes hex strings, and arrays and
+/// tuples JSON arrays
+pub fn token_from_json(kind: &ParamType, value: &serde_json::Value) -> Result<Token, Error> {
+    match kind {
+        ParamType::Address => {
+            let s = json_string(value)?;
+            s.trim_start_matches("0x")
+                .parse::<Address>()
+                .map(Token::Address)
+                .map_err(|e| format_err!("invalid address `{}`: {}", s, e))
+        }
+        ParamType::Bytes => json_bytes(value).map(Token::Bytes),
+        ParamType::FixedBytes(size) => {
+            let bytes = json_bytes(value)?;
+            if bytes.len() != *size {
+                return Err(format_err!("expected {} bytes, not {}", size, bytes.len()));
+            }
+            Ok(Token::FixedBytes(bytes))
+        }
+        ParamType::Int(_) => {
+            let s = json_string(value)?;
+            let negative = s.starts_with('-');
+            let digits = s.trim_start_matches('-');
+            let n = U256::from_dec_str(digits)
+                .map_err(|e| format_err!("invalid integer `{}`: {:?}", s, e))?;
+            // Negative numbers are in two's complement
+            Ok(Token::Int(match negative {
+                true => (!n).overflowing_add(U256::one()).0,
+                false => n,
+            }))
+        }
+        ParamType::Uint(_) => {
+            let s = json_string(value)?;
+            U256::from_dec_str(&s)
+                .map(Token::Uint)
+                .map_err(|e| format_err!("invalid unsigned integer `{}`: {:?}", s, e))
+        }
+        ParamType::Bool => value
+            .as_bool()
+            .map(Token::Bool)
+            .ok_or_else(|| format_err!("expected a boolean, not {}", value)),
+        ParamType::String => value
+            .as_str()
+            .map(|s| Token::String(s.to_owned()))
+            .ok_or_else(|| format_err!("expected a string, not {}", value)),
+        ParamType::Array(inner) => {
+            let values = value
+                .as_array()
+                .ok_or_else(|| format_err!("expected an array, not {}", value))?;
+            values
+                .iter()
+                .map(|value| token_from_json(inner, value))
+                .collect::<Result<_, _>>()
+                .map(Token::Array)
+        }
+        ParamType::FixedArray(inner, size) => {
+            tokens_from_json(&vec![inner.as_ref(); *size], value).map(Token::FixedArray)
+        }
+        ParamType::Tuple(components) => {
+            let kinds: Vec<_> = components.iter().map(|kind| kind.as_ref()).collect();
+            tokens_from_json(&kinds, value).map(Token::Tuple)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethabi::EventParam;
+    use graph::prelude::serde_json::json;
+
+    #[test]
+    fn converts_json_to_tokens() {
+        let address = "0x0000000000000000000000000000000000000001";
+        assert_eq!(
+            Token::Address(Address::from_low_u64_be(1)),
+            token_from_json(&ParamType::Address, &json!(address)).unwrap()
+        );
+        assert_eq!(
+            Token::Uint(U256::from(42)),
+            token_from_json(&ParamType::Uint(256), &json!(42)).unwrap()
+        );
+        assert_eq!(
+            Token::Int(U256::max_value()),
+            token_from_json(&ParamType::Int(256), &json!("-1")).unwrap()
+        );
+        assert_eq!(
+            Token::Array(vec![Token::Bool(true), Token::Bool(false)]),
+            token_from_json(
+                &ParamType::Array(Box::new(ParamType::Bool)),
+                &json!([true, false])
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            Token::Tuple(vec![
+                Token::String("a".to_owned()),
+                Token::Bytes(vec![1, 2])
+            ]),
+            token_from_json(
+                &ParamType::Tuple(vec![
+                    Box::new(ParamType::String),
+                    Box::new(ParamType::Bytes)
+                ]),
+                &json!(["a", "0x0102"])
+            )
+            .unwrap()
+        );
+        assert!(token_from_json(&ParamType::FixedBytes(2), &json!("0x01")).is_err());
+        assert!(token_from_json(&ParamType::Uint(256), &json!(true)).is_err());
+    }
+
+    #[test]
+    fn encodes_event_logs() {
+        let event = Event {
+            name: "Transfer".to_owned(),
+            inputs: vec![
+                EventParam {
+                    name: "to".to_owned(),
+                    kind: ParamType::Address,
+                    indexed: true,
+                },
+                EventParam {
+                    name: "value".to_owned(),
+                    kind: ParamType::Uint(256),
+                    indexed: false,
+                },
+            ],
+            anonymous: false,
+        };
+        let to = Address::from_low_u64_be(7);
+        let params = vec![Token::Address(to), Token::Uint(U256::from(5))];
+        let log = event_log(to, H256::zero(), &event, params).unwrap();
+        assert_eq!(vec![H256::zero(), H256::from(to)], log.topics);
+        assert_eq!(ethabi::encode(&[Token::Uint(U256::from(5))]), log.data.0);
+
+        let params = vec![Token::Uint(U256::from(5)), Token::Uint(U256::from(5))];
+        assert!(event_log(to, H256::zero(), &event, params).is_err());
+    }
+}
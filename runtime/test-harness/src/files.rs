@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use graph::prelude::*;
+
+/// In-memory files that stand in for IPFS. They hold the files of the
+/// subgraph under test, and the files that tests add for `ipfs.cat` and
+/// `ipfs.map`
+#[derive(Default)]
+pub struct Files(RwLock<HashMap<String, Vec<u8>>>);
+
+impl DeploymentFiles for Files {
+    fn get(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.0.read().unwrap().get(id).cloned())
+    }
+
+    fn insert(&self, id: &str, data: &[u8]) -> Result<(), Error> {
+        self.0.write().unwrap().insert(id.to_owned(), data.to_vec());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LinkResolver for Files {
+    fn with_timeout(self, _timeout: Duration) -> Self {
+        self
+    }
+
+    fn with_retries(self) -> Self {
+        self
+    }
+
+    async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        let path = link.link.trim_start_matches("/ipfs/");
+        DeploymentFiles::get(self, path)?
+            .ok_or_else(|| format_err!("file `{}` does not exist", path))
+    }
+
+    async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        let data = self.cat(logger, link).await?;
+        let values = String::from_utf8(data)?
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .map(|value| JsonStreamValue {
+                        value,
+                        line: index + 1,
+                    })
+                    .map_err(|e| format_err!("{}: {}", link.link, e))
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::pin(futures03::stream::iter(values)))
+    }
+}
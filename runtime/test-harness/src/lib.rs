@@ -0,0 +1,368 @@
+//! A harness for unit tests of subgraph mappings. It runs the mappings of
+//! a subgraph in the runtime host of `graph-node`, but against an in-memory
+//! store and a mock chain, so that tests can feed events and blocks to the
+//! handlers and check the entities that they write without a database,
+//! IPFS or an Ethereum node.
+//!
+//! Tests need a Tokio runtime since mappings run on their own threads:
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn creates_gravatars() {
+//!     let mut harness = TestHarness::load("build/subgraph.yaml").await.unwrap();
+//!     let event = harness
+//!         .event(contract, "NewGravatar(uint256,address,string,string)", params)
+//!         .unwrap();
+//!     harness.handle_block(vec![event]).await.unwrap();
+//!     assert!(harness.entity("Gravatar", "0x1").is_some());
+//! }
+//! ```
+
+mod chain;
+mod files;
+mod store;
+
+use std::collections::HashMap;
+
+use ethabi::{Event, Token};
+use graph::bytes::Bytes;
+use graph::components::arweave::ArweaveAdapter;
+use graph::components::three_box::ThreeBoxAdapter;
+use graph::prelude::{SubgraphInstance as _, *};
+use graph::util::ethereum::contract_event_with_signature;
+use graph::util::lfu_cache::LfuCache;
+use graph_core::SubgraphInstance;
+use graph_mock::MockMetricsRegistry;
+use graph_runtime_wasm::RuntimeHostBuilder;
+use tiny_keccak::keccak256;
+use web3::types::{Address, Log, Transaction, H256, U256};
+
+pub use self::chain::{event_log, token_from_json, tokens_from_json, MockCalls};
+pub use self::files::Files;
+pub use self::store::MemoryStore;
+
+type Instance = SubgraphInstance<RuntimeHostBuilder<MemoryStore>>;
+
+/// Arweave and 3Box are not available to mappings under test
+struct Unavailable;
+
+#[async_trait]
+impl ArweaveAdapter for Unavailable {
+    async fn tx_data(&self, tx_id: &str) -> Result<Bytes, Error> {
+        Err(format_err!(
+            "Arweave is not available in tests, can not read `{}`",
+            tx_id
+        ))
+    }
+}
+
+#[async_trait]
+impl ThreeBoxAdapter for Unavailable {
+    async fn profile(
+        &self,
+        address: &str,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, Error> {
+        Err(format_err!(
+            "3Box is not available in tests, can not read the profile of `{}`",
+            address
+        ))
+    }
+}
+
+/// Runs the mappings of one subgraph. Each harness has its own store, files
+/// and mocked calls, and processes the blocks it is given one after the
+/// other, starting at the lowest start block of the data sources
+pub struct TestHarness {
+    logger: Logger,
+    manifest: SubgraphManifest,
+    store: Arc<MemoryStore>,
+    files: Arc<Files>,
+    calls: MockCalls,
+    instance: Instance,
+    templates: Arc<Vec<DataSourceTemplate>>,
+    host_metrics: Arc<HostMetrics>,
+    start_block: u64,
+    block_ptr: Option<EthereumBlockPointer>,
+}
+
+impl TestHarness {
+    /// Load the subgraph at `location` the way `graph-node --subgraph`
+    /// loads one from a directory or URL, e.g., the `build` directory that
+    /// `graph build` writes
+    pub async fn load(location: &str) -> Result<Self, Error> {
+        let logger = graph::log::logger(false);
+        let files = Arc::new(Files::default());
+        let id = import_subgraph(&logger, &*files, location).await?;
+        let link = Link::from(format!("/ipfs/{}", id));
+        let manifest = SubgraphManifest::resolve(link, &*files, &logger)
+            .await
+            .map_err(|e| format_err!("failed to resolve `{}`: {}", location, e))?;
+        Self::new(logger, manifest, files)
+    }
+
+    /// Run the mappings of `manifest`; its links are resolved with `files`
+    pub fn new(
+        logger: Logger,
+        manifest: SubgraphManifest,
+        files: Arc<Files>,
+    ) -> Result<Self, Error> {
+        let network = manifest.network_name();
+        let store = Arc::new(MemoryStore::new(
+            manifest.id.clone(),
+            network.clone(),
+            manifest.schema.clone(),
+        ));
+        let calls = MockCalls::default();
+        let eth_adapter: Arc<dyn EthereumAdapter> = Arc::new(calls.adapter());
+        let mut eth_adapters = HashMap::new();
+        eth_adapters.insert(network.clone(), eth_adapter);
+        let mut stores = HashMap::new();
+        stores.insert(network, store.clone());
+        let host_builder = RuntimeHostBuilder::new(
+            eth_adapters,
+            files.clone(),
+            stores,
+            Arc::new(Unavailable),
+            Arc::new(Unavailable),
+        );
+
+        let registry = Arc::new(MockMetricsRegistry::new());
+        let stopwatch =
+            StopwatchMetrics::new(logger.clone(), manifest.id.clone(), registry.clone());
+        let host_metrics = Arc::new(HostMetrics::new(
+            registry,
+            manifest.id.to_string(),
+            stopwatch,
+        ));
+        let templates = Arc::new(manifest.templates.clone());
+        let start_block = manifest
+            .data_sources
+            .iter()
+            .map(|data_source| data_source.source.start_block)
+            .min()
+            .unwrap_or(0);
+        let instance = Instance::from_manifest(
+            &logger,
+            manifest.clone(),
+            host_builder,
+            host_metrics.clone(),
+        )?;
+
+        Ok(TestHarness {
+            logger,
+            manifest,
+            store,
+            files,
+            calls,
+            instance,
+            templates,
+            host_metrics,
+            start_block,
+            block_ptr: None,
+        })
+    }
+
+    /// The entity of type `entity_type` with the given `id`
+    pub fn entity(&self, entity_type: &str, id: &str) -> Option<Entity> {
+        self.store.entity(entity_type, id)
+    }
+
+    /// All entities of type `entity_type`, ordered by id
+    pub fn entities(&self, entity_type: &str) -> Vec<Entity> {
+        self.store.entities(entity_type)
+    }
+
+    /// Store `entity` before the mappings run
+    pub fn set_entity(&self, entity_type: &str, entity: Entity) -> Result<(), Error> {
+        self.store.set(entity_type, entity)
+    }
+
+    /// Make `data` available to `ipfs.cat` and `ipfs.map` as the file with
+    /// the hash `hash`
+    pub fn add_file(&self, hash: &str, data: &[u8]) -> Result<(), Error> {
+        DeploymentFiles::insert(&*self.files, hash, data)
+    }
+
+    /// Let calls of `function` on `address` with `args` return `result`
+    pub fn mock_call(
+        &self,
+        address: Address,
+        function: &str,
+        args: Vec<Token>,
+        result: Vec<Token>,
+    ) {
+        self.calls.insert(address, function, args, Ok(result))
+    }
+
+    /// Let calls of `function` on `address` with `args` revert
+    pub fn mock_revert(&self, address: Address, function: &str, args: Vec<Token>, reason: &str) {
+        self.calls
+            .insert(address, function, args, Err(reason.to_owned()))
+    }
+
+    /// The topic and the ABI of the event that a handler in the manifest
+    /// declares with `signature`
+    fn event_abi(&self, signature: &str) -> Result<(H256, &Event), Error> {
+        let mappings = self
+            .manifest
+            .data_sources
+            .iter()
+            .map(|data_source| (&data_source.source.abi, &data_source.mapping))
+            .chain(
+                self.templates
+                    .iter()
+                    .map(|template| (&template.source.abi, &template.mapping)),
+            );
+        for (abi, mapping) in mappings {
+            let handler = match mapping
+                .event_handlers
+                .iter()
+                .find(|handler| handler.event == signature)
+            {
+                Some(handler) => handler,
+                None => continue,
+            };
+            let contract = &mapping
+                .abis
+                .iter()
+                .find(|mapping_abi| &mapping_abi.name == abi)
+                .ok_or_else(|| format_err!("ABI `{}` is not in the manifest", abi))?
+                .contract;
+            let event = contract_event_with_signature(contract, signature)
+                .ok_or_else(|| format_err!("event `{}` is not in ABI `{}`", signature, abi))?;
+            return Ok((handler.topic0(), event));
+        }
+        Err(format_err!("no handler for event `{}`", signature))
+    }
+
+    /// The log of the event with `params` that `address` emits. The
+    /// `signature` is the one of a handler in the manifest, e.g.,
+    /// `Transfer(indexed address,indexed address,uint256)`
+    pub fn event(
+        &self,
+        address: Address,
+        signature: &str,
+        params: Vec<Token>,
+    ) -> Result<Log, Error> {
+        let (topic0, event) = self.event_abi(signature)?;
+        event_log(address, topic0, event, params)
+    }
+
+    /// Like `event`, but with the parameters as a JSON array; see
+    /// `token_from_json`
+    pub fn event_from_json(
+        &self,
+        address: Address,
+        signature: &str,
+        params: &serde_json::Value,
+    ) -> Result<Log, Error> {
+        let (topic0, event) = self.event_abi(signature)?;
+        let kinds: Vec<_> = event.inputs.iter().map(|input| &input.kind).collect();
+        let params = tokens_from_json(&kinds, params)
+            .map_err(|e| format_err!("invalid parameters for event `{}`: {}", signature, e))?;
+        event_log(address, topic0, event, params)
+    }
+
+    /// Process the next block, in which the `events` are emitted in this
+    /// order, each in its own transaction, and then run the block handlers.
+    /// Returns the pointer to the block
+    pub async fn handle_block(&mut self, events: Vec<Log>) -> Result<EthereumBlockPointer, Error> {
+        let number = self
+            .block_ptr
+            .map_or(self.start_block, |ptr| ptr.number + 1);
+        let hash = H256::from(keccak256(&number.to_be_bytes()));
+        let mut transactions = vec![];
+        let mut triggers = vec![];
+        for (index, mut log) in events.into_iter().enumerate() {
+            let index = index as u64;
+            let transaction_hash =
+                H256::from(keccak256(&[&hash.0[..], &index.to_be_bytes()[..]].concat()));
+            log.block_hash = Some(hash);
+            log.block_number = Some(number.into());
+            log.transaction_hash = Some(transaction_hash);
+            log.transaction_index = Some(index.into());
+            log.log_index = Some(U256::from(index));
+            log.transaction_log_index = Some(U256::zero());
+            transactions.push(Transaction {
+                hash: transaction_hash,
+                block_hash: Some(hash),
+                block_number: Some(number.into()),
+                transaction_index: Some(index.into()),
+                to: Some(log.address),
+                ..Default::default()
+            });
+            triggers.push(EthereumTrigger::Log(log));
+        }
+        triggers.push(EthereumTrigger::Block(
+            EthereumBlockPointer { hash, number },
+            EthereumBlockTriggerType::Every,
+        ));
+        let block = LightEthereumBlock {
+            hash: Some(hash),
+            parent_hash: self.block_ptr.map_or_else(H256::zero, |ptr| ptr.hash),
+            number: Some(number.into()),
+            timestamp: U256::from(number),
+            transactions,
+            ..Default::default()
+        };
+        self.process_triggers(block, triggers).await
+    }
+
+    /// Process `block` with exactly the `triggers`, e.g., to run call
+    /// handlers. The block has to follow the last block that the harness
+    /// processed
+    pub async fn process_triggers(
+        &mut self,
+        block: LightEthereumBlock,
+        triggers: Vec<EthereumTrigger>,
+    ) -> Result<EthereumBlockPointer, Error> {
+        let block = Arc::new(block);
+        let block_ptr = EthereumBlockPointer::from(block.as_ref());
+        let mut state = BlockState::new(self.store.clone(), LfuCache::new());
+        state.data_source_count = self.instance.data_source_count();
+        for trigger in &triggers {
+            state = self
+                .instance
+                .process_trigger(&self.logger, &block, trigger.clone(), state, None)
+                .await
+                .map_err(|e| format_err!("failed to process block {}: {:#}", block_ptr, e))?;
+        }
+
+        // Like `graph-node`, let the data sources that handlers create
+        // process the triggers of the block they were created in
+        while !state.created_data_sources.is_empty() {
+            let mut hosts = vec![];
+            for info in state.created_data_sources.drain(..).collect::<Vec<_>>() {
+                let data_source = DataSource::try_from(info)
+                    .map_err(|e| format_err!("failed to create data source: {:#}", e))?;
+                let host = self
+                    .instance
+                    .add_dynamic_data_source(
+                        &self.logger,
+                        data_source,
+                        self.templates.clone(),
+                        self.host_metrics.clone(),
+                    )
+                    .map_err(|e| format_err!("failed to create data source: {:#}", e))?;
+                hosts.push(host);
+            }
+            for trigger in &triggers {
+                state = Instance::process_trigger_in_runtime_hosts(
+                    &self.logger,
+                    &hosts,
+                    &block,
+                    trigger.clone(),
+                    state,
+                    None,
+                )
+                .await
+                .map_err(|e| format_err!("failed to process block {}: {:#}", block_ptr, e))?;
+            }
+        }
+
+        let mods = state.entity_cache.as_modifications(&*self.store)?;
+        self.store.apply(mods.modifications);
+        self.block_ptr = Some(block_ptr);
+        Ok(block_ptr)
+    }
+}
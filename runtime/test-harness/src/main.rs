@@ -0,0 +1,153 @@
+use clap::{App, Arg};
+use std::collections::BTreeMap;
+use std::process::exit;
+
+use graph::prelude::*;
+use graph_test_harness::TestHarness;
+use web3::types::Address;
+
+/// A test in a JSON file: the blocks to process, each a list of events,
+/// and the entities to expect afterwards. An expected entity of `null`
+/// must not exist, otherwise the entity must have the listed attributes
+#[derive(Deserialize)]
+struct Test {
+    #[serde(default)]
+    blocks: Vec<Vec<TestEvent>>,
+    #[serde(default)]
+    expect: BTreeMap<String, BTreeMap<String, Option<BTreeMap<String, serde_json::Value>>>>,
+}
+
+#[derive(Deserialize)]
+struct TestEvent {
+    address: String,
+    event: String,
+    #[serde(default)]
+    params: serde_json::Value,
+}
+
+/// Whether the attribute `actual` is what the test expects
+fn attribute_matches(actual: &Value, expected: &serde_json::Value) -> bool {
+    match (actual, expected) {
+        (Value::Null, serde_json::Value::Null) => true,
+        (Value::Null, _) | (_, serde_json::Value::Null) => false,
+        (Value::List(values), serde_json::Value::Array(expected)) => {
+            values.len() == expected.len()
+                && values
+                    .iter()
+                    .zip(expected)
+                    .all(|(value, expected)| attribute_matches(value, expected))
+        }
+        (actual, serde_json::Value::String(expected)) => &actual.to_string() == expected,
+        (actual, expected) => actual.to_string() == expected.to_string(),
+    }
+}
+
+/// Run the test in `path` against the subgraph at `subgraph` and return
+/// how the entities differ from the expected ones
+async fn run(subgraph: &str, path: &str) -> Result<Vec<String>, Error> {
+    let test: Test = serde_json::from_slice(&std::fs::read(path)?)?;
+    let mut harness = TestHarness::load(subgraph).await?;
+    for events in test.blocks {
+        let mut logs = vec![];
+        for event in events {
+            let address = event
+                .address
+                .trim_start_matches("0x")
+                .parse::<Address>()
+                .map_err(|e| format_err!("invalid address `{}`: {}", event.address, e))?;
+            logs.push(harness.event_from_json(address, &event.event, &event.params)?);
+        }
+        harness.handle_block(logs).await?;
+    }
+
+    let mut failures = vec![];
+    for (entity_type, entities) in test.expect {
+        for (id, expected) in entities {
+            let actual = harness.entity(&entity_type, &id);
+            match (actual, expected) {
+                (None, None) => {}
+                (Some(_), None) => failures.push(format!("{}({}) exists", entity_type, id)),
+                (None, Some(_)) => failures.push(format!("{}({}) is missing", entity_type, id)),
+                (Some(actual), Some(expected)) => {
+                    for (attribute, expected) in expected {
+                        let value = actual.get(&attribute).cloned().unwrap_or(Value::Null);
+                        if !attribute_matches(&value, &expected) {
+                            failures.push(format!(
+                                "{}({}).{} is {}, not {}",
+                                entity_type, id, attribute, value, expected
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(failures)
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new("graph-test-harness")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Runs the mappings of a subgraph on the events in test files")
+        .arg(
+            Arg::with_name("subgraph")
+                .required(true)
+                .help("the subgraph manifest, or the directory that contains subgraph.yaml"),
+        )
+        .arg(
+            Arg::with_name("test")
+                .required(true)
+                .multiple(true)
+                .help("a JSON file with the blocks to process and the entities to expect"),
+        )
+        .get_matches();
+
+    let subgraph = matches.value_of("subgraph").unwrap();
+    let mut failed = false;
+    for path in matches.values_of("test").unwrap() {
+        match run(subgraph, path).await {
+            Ok(failures) if failures.is_empty() => println!("{}: ok", path),
+            Ok(failures) => {
+                failed = true;
+                println!("{}: failed", path);
+                for failure in failures {
+                    println!("    {}", failure);
+                }
+            }
+            Err(e) => {
+                failed = true;
+                println!("{}: error: {}", path, e);
+            }
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::serde_json::json;
+
+    #[test]
+    fn matches_expected_attributes() {
+        assert!(attribute_matches(
+            &Value::String("a".to_owned()),
+            &json!("a")
+        ));
+        assert!(attribute_matches(&Value::Int(5), &json!(5)));
+        assert!(attribute_matches(
+            &Value::BigInt(BigInt::from(5)),
+            &json!("5")
+        ));
+        assert!(attribute_matches(&Value::Bool(true), &json!(true)));
+        assert!(attribute_matches(&Value::Null, &json!(null)));
+        let list = Value::List(vec![Value::Int(1), Value::Int(2)]);
+        assert!(attribute_matches(&list, &json!([1, 2])));
+        assert!(!attribute_matches(&list, &json!([1])));
+        assert!(!attribute_matches(&Value::Int(5), &json!(6)));
+        assert!(!attribute_matches(&Value::Null, &json!("null")));
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use graph::prelude::*;
+use web3::types::{Address, H256};
+
+/// An in-memory store for the entities of the subgraph under test. It
+/// implements just enough of the `Store` traits for the runtime host;
+/// everything else is unimplemented
+pub struct MemoryStore {
+    subgraph_id: SubgraphDeploymentId,
+    network: String,
+    schema: Arc<Schema>,
+    entities: RwLock<BTreeMap<EntityKey, Entity>>,
+}
+
+impl MemoryStore {
+    pub fn new(subgraph_id: SubgraphDeploymentId, network: String, schema: Schema) -> Self {
+        MemoryStore {
+            subgraph_id,
+            network,
+            schema: Arc::new(schema),
+            entities: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    fn key(&self, entity_type: &str, id: &str) -> EntityKey {
+        EntityKey {
+            subgraph_id: self.subgraph_id.clone(),
+            entity_type: entity_type.to_owned(),
+            entity_id: id.to_owned(),
+        }
+    }
+
+    /// The entity of type `entity_type` with the given `id`
+    pub fn entity(&self, entity_type: &str, id: &str) -> Option<Entity> {
+        let key = self.key(entity_type, id);
+        self.entities.read().unwrap().get(&key).cloned()
+    }
+
+    /// All entities of type `entity_type`, ordered by id
+    pub fn entities(&self, entity_type: &str) -> Vec<Entity> {
+        self.entities
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.entity_type == entity_type)
+            .map(|(_, entity)| entity.clone())
+            .collect()
+    }
+
+    /// Store `entity`, e.g., to set up the state that the mappings expect
+    /// before they run
+    pub fn set(&self, entity_type: &str, entity: Entity) -> Result<(), Error> {
+        let id = entity.id()?;
+        let key = self.key(entity_type, &id);
+        self.entities.write().unwrap().insert(key, entity);
+        Ok(())
+    }
+
+    /// Apply the changes that the mappings made in a block
+    pub fn apply(&self, mods: Vec<EntityModification>) {
+        let mut entities = self.entities.write().unwrap();
+        for modification in mods {
+            match modification {
+                EntityModification::Insert { key, data }
+                | EntityModification::Overwrite { key, data } => {
+                    entities.insert(key, data);
+                }
+                EntityModification::Remove { key } => {
+                    entities.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+impl Store for MemoryStore {
+    fn block_ptr(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+    ) -> Result<Option<EthereumBlockPointer>, Error> {
+        unimplemented!()
+    }
+
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        Ok(self.entities.read().unwrap().get(&key).cloned())
+    }
+
+    fn get_many(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        ids_for_type: BTreeMap<&str, Vec<&str>>,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        Ok(ids_for_type
+            .into_iter()
+            .map(|(entity_type, ids)| {
+                let entities = ids
+                    .into_iter()
+                    .filter_map(|id| self.entity(entity_type, id))
+                    .collect();
+                (entity_type.to_owned(), entities)
+            })
+            .collect())
+    }
+
+    fn supports_proof_of_indexing<'a>(
+        &'a self,
+        _subgraph_id: &'a SubgraphDeploymentId,
+    ) -> DynTryFuture<'a, bool> {
+        unimplemented!()
+    }
+
+    fn get_proof_of_indexing<'a>(
+        &'a self,
+        _subgraph_id: &'a SubgraphDeploymentId,
+        _indexer: &'a Option<Address>,
+        _block_hash: H256,
+    ) -> DynTryFuture<'a, Option<[u8; 32]>> {
+        unimplemented!()
+    }
+
+    fn find(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    fn find_query_values(
+        &self,
+        _: EntityQuery,
+    ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    fn find_one(&self, _query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    fn find_groups(
+        &self,
+        _: EntityGroupQuery,
+    ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    fn changes_in_block(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _entity_types: &[String],
+        _block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        unimplemented!()
+    }
+
+    fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError> {
+        Ok(None)
+    }
+
+    fn transact_block_operations(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        _block_ptr_to: EthereumBlockPointer,
+        _mods: Vec<EntityModification>,
+        _stopwatch: StopwatchMetrics,
+    ) -> Result<bool, StoreError> {
+        unimplemented!()
+    }
+
+    fn apply_metadata_operations(
+        &self,
+        _operations: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn build_entity_attribute_indexes(
+        &self,
+        _subgraph: &SubgraphDeploymentId,
+        _indexes: Vec<AttributeIndexDefinition>,
+    ) -> Result<(), SubgraphAssignmentProviderError> {
+        unimplemented!()
+    }
+
+    fn set_publication(
+        &self,
+        _subgraph: &SubgraphDeploymentId,
+        _publish: bool,
+        _replica_identity: Option<ReplicaIdentity>,
+    ) -> Result<Option<String>, StoreError> {
+        unimplemented!()
+    }
+
+    fn rewind(
+        &self,
+        _subgraph: &SubgraphDeploymentId,
+        _block_ptr: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn revert_block_operations(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        _block_ptr_from: EthereumBlockPointer,
+        _block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn subscribe(&self, _entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+        unimplemented!()
+    }
+
+    fn create_subgraph_deployment(
+        &self,
+        _schema: &Schema,
+        _ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn start_subgraph_deployment(
+        &self,
+        _logger: &Logger,
+        _subgraph_id: &SubgraphDeploymentId,
+        _ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn migrate_subgraph_deployment(
+        &self,
+        _logger: &Logger,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block_ptr: &EthereumBlockPointer,
+    ) {
+        unimplemented!()
+    }
+
+    fn block_number(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block_hash: H256,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        unimplemented!()
+    }
+
+    fn record_sync_sample(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block_number: u64,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn sync_samples(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<SyncSample>, StoreError> {
+        unimplemented!()
+    }
+}
+
+impl SubgraphDeploymentStore for MemoryStore {
+    fn input_schema(&self, _subgraph_id: &SubgraphDeploymentId) -> Result<Arc<Schema>, Error> {
+        Ok(self.schema.clone())
+    }
+
+    fn api_schema(&self, _subgraph_id: &SubgraphDeploymentId) -> Result<Arc<Schema>, Error> {
+        unimplemented!()
+    }
+
+    fn uses_relational_schema(&self, _subgraph_id: &SubgraphDeploymentId) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn network_name(&self, _subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error> {
+        Ok(Some(self.network.clone()))
+    }
+
+    fn network_head_block_number(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<u64>, Error> {
+        Ok(None)
+    }
+
+    fn block_hash(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _number: BlockNumber,
+    ) -> Result<Option<H256>, Error> {
+        Ok(None)
+    }
+}
+
+/// Mocked contract calls do not go through the call cache, so nothing is
+/// ever cached
+impl EthereumCallCache for MemoryStore {
+    fn get_call(
+        &self,
+        _contract_address: ethabi::Address,
+        _encoded_call: &[u8],
+        _block: EthereumBlockPointer,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        Ok(None)
+    }
+
+    fn set_call(
+        &self,
+        _contract_address: ethabi::Address,
+        _encoded_call: &[u8],
+        _block: EthereumBlockPointer,
+        _return_value: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn get_calls_in_block(
+        &self,
+        _block: EthereumBlockPointer,
+    ) -> Result<Vec<CachedEthereumCall>, Error> {
+        Ok(vec![])
+    }
+}
@@ -15,12 +15,13 @@ use tiny_keccak::keccak256;
 use graph::components::arweave::ArweaveAdapter;
 use graph::components::ethereum::*;
 use graph::components::store::Store;
-use graph::components::subgraph::SharedProofOfIndexing;
+use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
 use graph::components::three_box::ThreeBoxAdapter;
 use graph::data::subgraph::{Mapping, Source};
 use graph::prelude::{
     RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
 };
+use graph::trace::Span;
 use graph::util;
 use web3::types::{Log, Transaction};
 
@@ -36,6 +37,7 @@ struct RuntimeHostConfig {
     data_source_name: String,
     data_source_context: Option<DataSourceContext>,
     contract: Source,
+    source_subgraph: Option<SubgraphDeploymentId>,
     templates: Arc<Vec<DataSourceTemplate>>,
 }
 
@@ -134,6 +136,8 @@ where
             )
         })?;
 
+        let source_subgraph = data_source.source_subgraph().cloned();
+
         // Detect whether the subgraph uses templates in data sources, which are
         // deprecated, or the top-level templates field.
         let templates = match top_level_templates.is_empty() {
@@ -153,6 +157,7 @@ where
                 data_source_name: data_source.name,
                 data_source_context: data_source.context,
                 contract: data_source.source,
+                source_subgraph,
                 templates,
             },
             mapping_request_sender,
@@ -167,10 +172,13 @@ where
 pub struct RuntimeHost {
     data_source_name: String,
     data_source_contract: Source,
-    data_source_contract_abi: MappingABI,
+    /// The ABI of the contract; data sources of kind `subgraph` have none
+    data_source_contract_abi: Option<MappingABI>,
     data_source_event_handlers: Vec<MappingEventHandler>,
     data_source_call_handlers: Vec<MappingCallHandler>,
     data_source_block_handlers: Vec<MappingBlockHandler>,
+    data_source_subgraph: Option<SubgraphDeploymentId>,
+    data_source_entity_handlers: Vec<MappingEntityHandler>,
     mapping_request_sender: Sender<MappingRequest>,
     host_exports: Arc<HostExports>,
     metrics: Arc<HostMetrics>,
@@ -197,19 +205,24 @@ impl RuntimeHost {
             ));
         }
 
-        let data_source_contract_abi = config
-            .mapping
-            .abis
-            .iter()
-            .find(|abi| abi.name == config.contract.abi)
-            .ok_or_else(|| {
-                format_err!(
-                    "No ABI entry found for the main contract of data source \"{}\": {}",
-                    &config.data_source_name,
-                    config.contract.abi,
-                )
-            })?
-            .clone();
+        let data_source_contract_abi = match config.source_subgraph {
+            Some(_) => None,
+            None => Some(
+                config
+                    .mapping
+                    .abis
+                    .iter()
+                    .find(|abi| abi.name == config.contract.abi)
+                    .ok_or_else(|| {
+                        format_err!(
+                            "No ABI entry found for the main contract of data source \"{}\": {}",
+                            &config.data_source_name,
+                            config.contract.abi,
+                        )
+                    })?
+                    .clone(),
+            ),
+        };
 
         let data_source_name = config.data_source_name;
 
@@ -239,12 +252,23 @@ impl RuntimeHost {
             data_source_event_handlers: config.mapping.event_handlers,
             data_source_call_handlers: config.mapping.call_handlers,
             data_source_block_handlers: config.mapping.block_handlers,
+            data_source_subgraph: config.source_subgraph,
+            data_source_entity_handlers: config.mapping.entity_handlers,
             mapping_request_sender,
             host_exports,
             metrics,
         })
     }
 
+    fn contract_abi(&self) -> Result<&MappingABI, anyhow::Error> {
+        self.data_source_contract_abi.as_ref().with_context(|| {
+            format_err!(
+                "Data source \"{}\" has no contract ABI",
+                self.data_source_name
+            )
+        })
+    }
+
     fn matches_call_address(&self, call: &EthereumCall) -> bool {
         // The runtime host matches the contract address of the `EthereumCall`
         // if the data source contains the same contract address or
@@ -383,7 +407,7 @@ impl RuntimeHost {
         &self,
         logger: &Logger,
         extra: OwnedKV<T>,
-        state: BlockState,
+        mut state: BlockState,
         handler: &str,
         trigger: MappingTrigger,
         block: &Arc<LightEthereumBlock>,
@@ -398,6 +422,15 @@ impl RuntimeHost {
             "data_source" => &self.data_source_name,
         );
 
+        // Store access from the mapping is traced as part of the handler
+        let block_trace = state.trace;
+        let mut span = Span::child_of(block_trace.as_ref(), "subgraph.handler");
+        span.set_attribute("handler", handler);
+        span.set_attribute("data_source", &self.data_source_name);
+        span.set_attribute("trigger_type", trigger_type);
+        state.trace = span.context();
+        state.write_audit.enter(&self.data_source_name, handler);
+
         let (result_sender, result_receiver) = channel();
         let start_time = Instant::now();
         let metrics = self.metrics.clone();
@@ -444,7 +477,15 @@ impl RuntimeHost {
                 .as_millis(),
         );
 
+        if let Err(e) = &result {
+            span.set_error(e);
+        }
         result
+            .map(|mut state| {
+                state.trace = block_trace;
+                state
+            })
+            .map_err(|e| MappingError::new(&self.data_source_name, handler, e).into())
     }
 }
 
@@ -471,6 +512,20 @@ impl RuntimeHostTrait for RuntimeHost {
             && self.data_source_contract.start_block <= block_number
     }
 
+    fn matches_entity_change(
+        &self,
+        source: &SubgraphDeploymentId,
+        entity_type: &str,
+        block_number: u64,
+    ) -> bool {
+        self.data_source_subgraph.as_ref() == Some(source)
+            && self
+                .data_source_entity_handlers
+                .iter()
+                .any(|handler| handler.entity == entity_type)
+            && self.data_source_contract.start_block <= block_number
+    }
+
     async fn process_call(
         &self,
         logger: &Logger,
@@ -484,8 +539,9 @@ impl RuntimeHostTrait for RuntimeHost {
         let call_handler = self.handler_for_call(&call)?;
 
         // Identify the function ABI in the contract
+        let contract_abi = self.contract_abi()?;
         let function_abi = util::ethereum::contract_function_with_signature(
-            &self.data_source_contract_abi.contract,
+            &contract_abi.contract,
             call_handler.function.as_str(),
         )
         .with_context(|| {
@@ -493,7 +549,7 @@ impl RuntimeHostTrait for RuntimeHost {
                 "Function with the signature \"{}\" not found in \
                     contract \"{}\" of data source \"{}\"",
                 call_handler.function,
-                self.data_source_contract_abi.name,
+                contract_abi.name,
                 self.data_source_name
             )
         })?;
@@ -603,8 +659,9 @@ impl RuntimeHostTrait for RuntimeHost {
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, anyhow::Error> {
         let data_source_name = &self.data_source_name;
-        let abi_name = &self.data_source_contract_abi.name;
-        let contract = &self.data_source_contract_abi.contract;
+        let contract_abi = self.contract_abi()?;
+        let abi_name = &contract_abi.name;
+        let contract = &contract_abi.contract;
 
         // If there are no matching handlers, fail processing the event
         let potential_handlers = self.handlers_for_log(&log)?;
@@ -706,4 +763,57 @@ impl RuntimeHostTrait for RuntimeHost {
         )
         .await
     }
+
+    async fn process_entity_change(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        change: &EntityOperation,
+        mut state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, anyhow::Error> {
+        let (key, data) = match change {
+            EntityOperation::Set { key, data } => (key, Some(data)),
+            EntityOperation::Remove { key } => (key, None),
+        };
+        let handlers = self
+            .data_source_entity_handlers
+            .iter()
+            .filter(|handler| handler.entity == key.entity_type);
+        for handler in handlers {
+            let (handler_name, trigger) = match (data, &handler.remove_handler) {
+                (Some(entity), _) => (
+                    &handler.handler,
+                    MappingTrigger::Entity {
+                        entity: entity.clone(),
+                        handler: handler.handler.clone(),
+                    },
+                ),
+                (None, Some(remove_handler)) => (
+                    remove_handler,
+                    MappingTrigger::EntityRemoval {
+                        id: key.entity_id.clone(),
+                        handler: remove_handler.clone(),
+                    },
+                ),
+                (None, None) => continue,
+            };
+            state = self
+                .send_mapping_request(
+                    logger,
+                    o! {
+                        "source" => key.subgraph_id.to_string(),
+                        "entity_type" => &key.entity_type,
+                        "entity_id" => &key.entity_id,
+                    },
+                    state,
+                    handler_name,
+                    trigger,
+                    block,
+                    proof_of_indexing.cheap_clone(),
+                )
+                .await?;
+        }
+        Ok(state)
+    }
 }
@@ -9,6 +9,7 @@ use graph::components::three_box::ThreeBoxAdapter;
 use graph::data::store;
 use graph::prelude::serde_json;
 use graph::prelude::{slog::b, slog::record_static, *};
+use graph::util::env::env_var;
 use semver::Version;
 use std::collections::HashMap;
 use std::ops::Deref;
@@ -20,6 +21,17 @@ use graph_graphql::prelude::validate_entity;
 
 use crate::module::{WasmInstance, WasmInstanceContext};
 
+lazy_static! {
+    /// How many data sources, including those in the manifest, a
+    /// deployment may have
+    static ref MAX_DATA_SOURCES: Option<usize> = env_var("GRAPH_SUBGRAPH_MAX_DATA_SOURCES");
+
+    /// How many data sources the handlers of a deployment may create in
+    /// one block
+    static ref MAX_DATA_SOURCES_PER_BLOCK: Option<usize> =
+        env_var("GRAPH_SUBGRAPH_MAX_DATA_SOURCES_PER_BLOCK");
+}
+
 pub(crate) struct HostExports {
     subgraph_id: SubgraphDeploymentId,
     pub(crate) api_version: Version,
@@ -540,6 +552,30 @@ impl HostExports {
             })?
             .clone();
 
+        // The limits fail the handler, so that a mapping that creates too
+        // many data sources fails the same way on every node
+        if let Some(max) = *MAX_DATA_SOURCES_PER_BLOCK {
+            if state.data_sources_created >= max {
+                anyhow::bail!(
+                    "Failed to create data source from template `{}`: \
+                     limit of {} data sources created per block exceeded",
+                    name,
+                    max
+                );
+            }
+        }
+        if let Some(max) = *MAX_DATA_SOURCES {
+            if state.data_source_count + state.data_sources_created >= max {
+                anyhow::bail!(
+                    "Failed to create data source from template `{}`: \
+                     limit of {} data sources per subgraph exceeded",
+                    name,
+                    max
+                );
+            }
+        }
+        state.data_sources_created += 1;
+
         // Remember that we need to create this data source
         state.created_data_sources.push(DataSourceTemplateInfo {
             data_source: self.data_source_name.clone(),
@@ -47,6 +47,9 @@ pub fn spawn_module(
                         result_sender,
                     } = request;
 
+                    // Store access of the handler is part of its span
+                    let _entered = graph::trace::enter(ctx.state.trace);
+
                     // Start the WASM module runtime.
                     let section = host_metrics.stopwatch.start_section("module_init");
                     let module = WasmInstance::from_valid_module_with_ctx(
@@ -86,6 +89,12 @@ pub fn spawn_module(
                         MappingTrigger::Block { handler } => {
                             module.handle_ethereum_block(handler.handler.as_str())
                         }
+                        MappingTrigger::Entity { entity, handler } => {
+                            module.handle_entity(handler.as_str(), entity)
+                        }
+                        MappingTrigger::EntityRemoval { id, handler } => {
+                            module.handle_entity_removal(handler.as_str(), &id)
+                        }
                     };
                     section.end();
 
@@ -125,6 +134,17 @@ pub(crate) enum MappingTrigger {
     Block {
         handler: MappingBlockHandler,
     },
+    /// An entity that the source deployment of the data source created or
+    /// updated, and the handler for it
+    Entity {
+        entity: Entity,
+        handler: String,
+    },
+    /// The id of an entity that the source deployment removed
+    EntityRemoval {
+        id: String,
+        handler: String,
+    },
 }
 
 type MappingResponse = (
@@ -150,11 +170,16 @@ pub(crate) struct MappingContext {
 
 impl MappingContext {
     pub fn derive_with_empty_block_state(&self) -> Self {
+        let mut state = BlockState::new(self.state.entity_cache.store.clone(), Default::default());
+        // Data sources that callbacks create count towards the limits of
+        // the block
+        state.data_source_count = self.state.data_source_count;
+        state.data_sources_created = self.state.data_sources_created;
         MappingContext {
             logger: self.logger.clone(),
             host_exports: self.host_exports.clone(),
             block: self.block.clone(),
-            state: BlockState::new(self.state.entity_cache.store.clone(), Default::default()),
+            state,
             proof_of_indexing: self.proof_of_indexing.cheap_clone(),
         }
     }
@@ -12,8 +12,10 @@ use crate::host_exports;
 use crate::mapping::MappingContext;
 use ethabi::LogParam;
 use graph::components::ethereum::*;
+use graph::components::subgraph::NonDeterministicError;
 use graph::data::store;
 use graph::prelude::*;
+use graph::util::memory::{MemoryUsage, Subsystem};
 use web3::types::{Log, Transaction, U256};
 
 use crate::asc_abi::asc_ptr::*;
@@ -44,6 +46,9 @@ pub(crate) struct WasmInstance {
     // Also this is the only strong reference, so the instance will be dropped once this is dropped.
     // The weak references are circulary held by instance itself through host exports.
     instance_ctx: Rc<RefCell<Option<WasmInstanceContext>>>,
+
+    // Accounts for the memory of the instance
+    usage: MemoryUsage,
 }
 
 impl Drop for WasmInstance {
@@ -171,6 +176,30 @@ impl WasmInstance {
         Ok(self.take_ctx().ctx.state)
     }
 
+    pub(crate) fn handle_entity(
+        mut self,
+        handler_name: &str,
+        entity: Entity,
+    ) -> Result<BlockState, anyhow::Error> {
+        let arg: AscPtr<AscEntity> = self.asc_new(&entity);
+
+        self.invoke_handler(handler_name, arg)?;
+
+        Ok(self.take_ctx().ctx.state)
+    }
+
+    pub(crate) fn handle_entity_removal(
+        mut self,
+        handler_name: &str,
+        id: &str,
+    ) -> Result<BlockState, anyhow::Error> {
+        let arg: AscPtr<AscString> = self.asc_new(id);
+
+        self.invoke_handler(handler_name, arg)?;
+
+        Ok(self.take_ctx().ctx.state)
+    }
+
     pub(crate) fn take_ctx(&mut self) -> WasmInstanceContext {
         self.instance_ctx.borrow_mut().take().unwrap()
     }
@@ -195,18 +224,33 @@ impl WasmInstance {
             .get_func(handler)
             .with_context(|| format!("function {} not found", handler))?;
 
-        func.get1()?(arg.wasm_ptr()).map_err(|e| {
+        let result = func.get1()?(arg.wasm_ptr());
+
+        // The mapping may have grown the memory
+        let memory_size = self.instance_ctx().memory.data_size();
+        self.usage.set(memory_size as u64);
+
+        result.map_err(|e| {
             if e.to_string().contains(TRAP_TIMEOUT) {
-                anyhow::Error::context(
+                NonDeterministicError(anyhow::Error::context(
                     e.into(),
                     format!(
                         "Handler '{}' hit the timeout of '{}' seconds",
                         handler,
                         self.instance_ctx().timeout.unwrap().as_secs()
                     ),
-                )
+                ))
+                .into()
             } else {
-                anyhow::Error::context(e.into(), format!("Failed to invoke handler '{}'", handler))
+                let e = anyhow::Error::context(
+                    e.into(),
+                    format!("Failed to invoke handler '{}'", handler),
+                );
+                if self.instance_ctx().nondeterministic_failure {
+                    NonDeterministicError(e).into()
+                } else {
+                    e
+                }
             }
         })
     }
@@ -238,6 +282,10 @@ pub(crate) struct WasmInstanceContext {
 
     // Number of free bytes starting from `arena_start_ptr`.
     arena_free_size: i32,
+
+    // Set when a host function failed for a reason that has nothing to do
+    // with the mapping, like a failed Ethereum call or store error
+    nondeterministic_failure: bool,
 }
 
 impl WasmInstance {
@@ -477,9 +525,11 @@ impl WasmInstance {
             )?);
         }
 
+        let memory_size = shared_ctx.borrow().as_ref().unwrap().memory.data_size();
         Ok(WasmInstance {
             instance,
             instance_ctx: shared_ctx,
+            usage: MemoryUsage::with_bytes(Subsystem::WasmInstances, memory_size as u64),
         })
     }
 }
@@ -561,6 +611,7 @@ impl WasmInstanceContext {
             timeout_stopwatch,
             arena_free_size: 0,
             arena_start_ptr: 0,
+            nondeterministic_failure: false,
         })
     }
 
@@ -652,6 +703,7 @@ impl WasmInstanceContext {
             id,
             data,
         )?;
+        self.ctx.state.write_audit.entity_set();
         Ok(())
     }
 
@@ -666,6 +718,7 @@ impl WasmInstanceContext {
             entity,
             id,
         );
+        self.ctx.state.write_audit.entity_remove();
     }
 
     /// function store.get(entity: string, id: string): Entity | null
@@ -677,10 +730,14 @@ impl WasmInstanceContext {
         let start = Instant::now();
         let entity_ptr = self.asc_get(entity_ptr);
         let id_ptr = self.asc_get(id_ptr);
-        let entity_option =
-            self.ctx
-                .host_exports
-                .store_get(&mut self.ctx.state, entity_ptr, id_ptr)?;
+        let entity_option = self
+            .ctx
+            .host_exports
+            .store_get(&mut self.ctx.state, entity_ptr, id_ptr)
+            .map_err(|e| {
+                self.nondeterministic_failure = true;
+                e
+            })?;
 
         let ret = Ok(match entity_option {
             Some(entity) => {
@@ -704,10 +761,15 @@ impl WasmInstanceContext {
         &mut self,
         call: UnresolvedContractCall,
     ) -> Result<AscEnumArray<EthereumValueKind>, Trap> {
-        let result =
-            self.ctx
-                .host_exports
-                .ethereum_call(&self.ctx.logger, &self.ctx.block, call)?;
+        let result = self
+            .ctx
+            .host_exports
+            .ethereum_call(&self.ctx.logger, &self.ctx.block, call)
+            .map_err(|e| {
+                self.nondeterministic_failure = true;
+                e
+            })?;
+        self.ctx.state.write_audit.eth_call();
         Ok(match result {
             Some(tokens) => self.asc_new(tokens.as_slice()),
             None => AscPtr::null(),
@@ -835,7 +897,11 @@ impl WasmInstanceContext {
             &*callback,
             user_data,
             flags,
-        )?;
+        )
+        .map_err(|e| {
+            self.nondeterministic_failure = true;
+            e
+        })?;
 
         debug!(
             &self.ctx.logger,
@@ -845,6 +911,8 @@ impl WasmInstanceContext {
             "n_calls" => output_states.len(),
             "time" => format!("{}ms", start_time.elapsed().as_millis())
         );
+        // Every callback started with the data sources created so far
+        let created_before = self.ctx.state.data_sources_created;
         for output_state in output_states {
             self.ctx
                 .state
@@ -855,6 +923,8 @@ impl WasmInstanceContext {
                 .state
                 .created_data_sources
                 .extend(output_state.created_data_sources);
+            self.ctx.state.data_sources_created +=
+                output_state.data_sources_created - created_before;
         }
 
         Ok(())
@@ -84,6 +84,7 @@ fn mock_data_source(path: &str) -> DataSource {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
             abi: String::from("123123"),
             start_block: 0,
+            subgraph: None,
         },
         mapping: Mapping {
             kind: String::from("ethereum/events"),
@@ -94,6 +95,7 @@ fn mock_data_source(path: &str) -> DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            entity_handlers: vec![],
             link: Link {
                 link: "link".to_owned(),
             },
@@ -115,6 +117,7 @@ fn mock_data_source(path: &str) -> DataSource {
                 event_handlers: vec![],
                 call_handlers: vec![],
                 block_handlers: vec![],
+                entity_handlers: vec![],
                 link: Link {
                     link: "link".to_owned(),
                 },
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use graph::prelude::{SubgraphDeploymentId, SubgraphName};
+
+/// The deployment that answers the queries for one namespace of a
+/// composite deployment
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompositeSource {
+    /// Whatever deployment is the current version of the subgraph
+    Name(SubgraphName),
+    Deployment(SubgraphDeploymentId),
+}
+
+impl CompositeSource {
+    /// Parse `s` as a deployment id if it starts with `Qm`, and as a
+    /// subgraph name otherwise
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s.starts_with("Qm") {
+            SubgraphDeploymentId::new(s)
+                .map(CompositeSource::Deployment)
+                .map_err(|()| format!("`{}` is not a valid deployment id", s))
+        } else {
+            SubgraphName::new(s)
+                .map(CompositeSource::Name)
+                .map_err(|()| format!("`{}` is not a valid subgraph name", s))
+        }
+    }
+}
+
+/// The composite deployments that are served under
+/// `/subgraphs/composite/<name>`, each with the sources of its namespaces
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Composites {
+    composites: BTreeMap<String, BTreeMap<String, CompositeSource>>,
+}
+
+impl Composites {
+    pub fn new(composites: BTreeMap<String, BTreeMap<String, CompositeSource>>) -> Self {
+        Composites { composites }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&BTreeMap<String, CompositeSource>> {
+        self.composites.get(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.composites.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sources() {
+        assert_eq!(
+            Ok(CompositeSource::Name(
+                SubgraphName::new("uniswap/uniswap-v2").unwrap()
+            )),
+            CompositeSource::parse("uniswap/uniswap-v2")
+        );
+        assert_eq!(
+            Ok(CompositeSource::Deployment(
+                SubgraphDeploymentId::new("QmDeployment").unwrap()
+            )),
+            CompositeSource::parse("QmDeployment")
+        );
+        assert!(CompositeSource::parse("Qm-invalid").is_err());
+        assert!(CompositeSource::parse("not a name").is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use brotli::enc::BrotliEncoderParams;
+use flate2::write::GzEncoder;
+use http::header::HeaderValue;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use graph::bytes::Bytes;
+use graph::prelude::tiny_keccak::keccak256;
+use graph::util::env::env_var;
+use graph::util::lfu_cache::{CacheWeight, LfuCache};
+use graph::util::memory::{self, MemoryUsage, Subsystem};
+
+/// Responses smaller than this are sent as they are since compressing them
+/// saves less than it costs
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
+/// The content codings the server can produce, in order of preference
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Pick the encoding for a response to a request with the given
+    /// `Accept-Encoding` header. Among the encodings with the highest
+    /// quality, brotli is preferred
+    pub fn negotiate(accept_encoding: Option<&HeaderValue>) -> Option<Encoding> {
+        let accept_encoding = accept_encoding?.to_str().ok()?;
+        let mut best: Option<(Encoding, f32)> = None;
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let coding = parts.next().unwrap_or("");
+            let quality = parts
+                .find(|param| param.starts_with("q="))
+                .map(|param| &param[2..])
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+                .unwrap_or(0.0);
+            if quality <= 0.0 {
+                continue;
+            }
+            let candidates: &[Encoding] = match coding.to_ascii_lowercase().as_str() {
+                "br" => &[Encoding::Brotli],
+                "gzip" | "x-gzip" => &[Encoding::Gzip],
+                "*" => &[Encoding::Brotli, Encoding::Gzip],
+                _ => &[],
+            };
+            for candidate in candidates {
+                best = match best {
+                    Some((encoding, q))
+                        if q > quality || (q == quality && encoding <= *candidate) =>
+                    {
+                        Some((encoding, q))
+                    }
+                    _ => Some((*candidate, quality)),
+                };
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut out = Vec::with_capacity(data.len() / 4);
+                let params = BrotliEncoderParams {
+                    // The default of 11 is too slow to do on every request
+                    quality: 5,
+                    ..BrotliEncoderParams::default()
+                };
+                brotli::BrotliCompress(&mut &data[..], &mut out, &params)?;
+                Ok(out)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(
+                    Vec::with_capacity(data.len() / 4),
+                    flate2::Compression::default(),
+                );
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Compressed(Bytes);
+
+impl CacheWeight for Compressed {
+    fn weight(&self) -> u64 {
+        self.0.len() as u64
+    }
+}
+
+/// Compresses response bodies. If a cache size is set, the compressed
+/// bytes of responses are kept, keyed by a hash of the uncompressed body,
+/// so that responses to hot queries only need to be compressed once
+pub struct Compressor {
+    enabled: bool,
+    /// The maximum number of compressed bytes to keep
+    cache_size: u64,
+    cache: Mutex<LfuCache<([u8; 32], Encoding), Compressed>>,
+    usage: Mutex<MemoryUsage>,
+}
+
+impl Compressor {
+    pub fn new(enabled: bool, cache_size: u64) -> Self {
+        Compressor {
+            enabled,
+            cache_size,
+            cache: Mutex::new(LfuCache::new()),
+            usage: Mutex::new(MemoryUsage::new(Subsystem::ResponseCache)),
+        }
+    }
+
+    /// Read the settings from `GRAPH_GRAPHQL_COMPRESSION`, which turns
+    /// compression off if set to `false`, and from
+    /// `GRAPH_GRAPHQL_COMPRESSION_CACHE_SIZE`
+    pub fn from_env() -> Self {
+        let enabled = env_var("GRAPH_GRAPHQL_COMPRESSION").unwrap_or(true);
+        let cache_size = env_var("GRAPH_GRAPHQL_COMPRESSION_CACHE_SIZE").unwrap_or(0);
+        Self::new(enabled, cache_size)
+    }
+
+    /// The encoding to use for a response to a request that accepts
+    /// `accept_encoding`, or `None` if the response should not be compressed
+    pub fn encoding(&self, accept_encoding: Option<&HeaderValue>) -> Option<Encoding> {
+        if self.enabled {
+            Encoding::negotiate(accept_encoding)
+        } else {
+            None
+        }
+    }
+
+    /// Compress `body`. Returns `None` if `body` is too small to be worth
+    /// compressing
+    pub fn compress(&self, encoding: Encoding, body: &Bytes) -> io::Result<Option<Bytes>> {
+        if body.len() < MIN_COMPRESSED_SIZE {
+            return Ok(None);
+        }
+        if self.cache_size == 0 {
+            return encoding.compress(body).map(|data| Some(Bytes::from(data)));
+        }
+
+        let key = (keccak256(body), encoding);
+        if let Some(compressed) = self.cache.lock().unwrap().get(&key) {
+            return Ok(Some(compressed.0.clone()));
+        }
+        // Compress without holding the lock
+        let compressed = Bytes::from(encoding.compress(body)?);
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(key, Compressed(compressed.clone()));
+        // Under memory pressure, keep only a fraction of the usual cache
+        if memory::under_pressure() {
+            cache.evict(self.cache_size / 4);
+        } else {
+            cache.evict(self.cache_size);
+        }
+        self.usage.lock().unwrap().set(cache.weight());
+        Ok(Some(compressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn negotiate(accept_encoding: &'static str) -> Option<Encoding> {
+        Encoding::negotiate(Some(&HeaderValue::from_static(accept_encoding)))
+    }
+
+    #[test]
+    fn negotiates_encodings() {
+        assert_eq!(None, Encoding::negotiate(None));
+        assert_eq!(None, negotiate("identity"));
+        assert_eq!(Some(Encoding::Gzip), negotiate("gzip"));
+        assert_eq!(Some(Encoding::Brotli), negotiate("gzip, deflate, br"));
+        assert_eq!(Some(Encoding::Gzip), negotiate("br;q=0.5, gzip"));
+        assert_eq!(Some(Encoding::Brotli), negotiate("*"));
+        assert_eq!(None, negotiate("br;q=0, gzip;q=0"));
+    }
+
+    #[test]
+    fn compresses_and_caches_large_bodies() {
+        let compressor = Compressor::new(true, 1 << 20);
+        assert_eq!(
+            None,
+            compressor
+                .compress(Encoding::Gzip, &Bytes::from_static(b"{}"))
+                .unwrap()
+        );
+
+        let body = Bytes::from(format!("{{\"data\":\"{}\"}}", "x".repeat(10_000)));
+        let compressed = compressor.compress(Encoding::Gzip, &body).unwrap().unwrap();
+        assert!(compressed.len() < body.len());
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(&body[..], &decompressed[..]);
+
+        assert_eq!(1, compressor.cache.lock().unwrap().len());
+        let again = compressor.compress(Encoding::Gzip, &body).unwrap().unwrap();
+        assert_eq!(compressed, again);
+        assert_eq!(1, compressor.cache.lock().unwrap().len());
+    }
+}
@@ -0,0 +1,136 @@
+use http::header::{self, HeaderMap, HeaderValue};
+use std::collections::HashMap;
+
+use graph::prelude::SubgraphDeploymentId;
+
+/// Which browser origins may query the server, and what they may send. The
+/// default allows everything, which is what the server always did
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorsPolicy {
+    /// Origins like `https://example.com`; `*` allows all origins
+    pub allowed_origins: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// How many seconds browsers may cache the answer to a preflight request
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        CorsPolicy {
+            allowed_origins: vec!["*".to_owned()],
+            allowed_headers: vec!["Content-Type".to_owned(), "User-Agent".to_owned()],
+            allowed_methods: vec!["GET".to_owned(), "OPTIONS".to_owned(), "POST".to_owned()],
+            max_age: None,
+        }
+    }
+}
+
+impl CorsPolicy {
+    fn allow_origin(&self, origin: Option<&HeaderValue>) -> Option<HeaderValue> {
+        if self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            return Some(HeaderValue::from_static("*"));
+        }
+        let origin = origin?;
+        let value = origin.to_str().ok()?;
+        self.allowed_origins
+            .iter()
+            .find(|allowed| allowed.eq_ignore_ascii_case(value))
+            .map(|_| origin.clone())
+    }
+
+    /// Add the CORS headers for a request from `origin` to `headers`.
+    /// Responses to preflight requests also say which headers and methods
+    /// are allowed. If `origin` is not allowed, browsers will not let the
+    /// page that made the request see the response
+    pub fn apply(&self, origin: Option<&HeaderValue>, preflight: bool, headers: &mut HeaderMap) {
+        let allow_origin = self.allow_origin(origin);
+        let echoes_origin = allow_origin.as_ref().map_or(true, |value| value != "*");
+        if echoes_origin {
+            // The response differs depending on the origin and must not be
+            // served from caches to other origins
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+        let allow_origin = match allow_origin {
+            Some(value) => value,
+            None => return,
+        };
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+
+        if preflight {
+            let list = |items: &[String]| HeaderValue::from_str(&items.join(", ")).ok();
+            if let Some(value) = list(&self.allowed_headers) {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, value);
+            }
+            if let Some(value) = list(&self.allowed_methods) {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, value);
+            }
+            if let Some(max_age) = self.max_age {
+                headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+            }
+        }
+    }
+}
+
+/// The CORS policy of a server, with overrides for individual deployments
+#[derive(Clone, Debug, Default)]
+pub struct CorsConfig {
+    pub default: CorsPolicy,
+    pub deployments: HashMap<SubgraphDeploymentId, CorsPolicy>,
+}
+
+impl CorsConfig {
+    /// Whether the policy depends on the deployment that is queried
+    pub fn has_overrides(&self) -> bool {
+        !self.deployments.is_empty()
+    }
+
+    pub fn policy(&self, deployment: Option<&SubgraphDeploymentId>) -> &CorsPolicy {
+        deployment
+            .and_then(|deployment| self.deployments.get(deployment))
+            .unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_everything_by_default() {
+        let mut headers = HeaderMap::new();
+        CorsPolicy::default().apply(None, true, &mut headers);
+        assert_eq!("*", headers[header::ACCESS_CONTROL_ALLOW_ORIGIN]);
+        assert_eq!(
+            "Content-Type, User-Agent",
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+        );
+        assert_eq!(
+            "GET, OPTIONS, POST",
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+        );
+        assert!(headers.get(header::VARY).is_none());
+    }
+
+    #[test]
+    fn only_allows_listed_origins() {
+        let policy = CorsPolicy {
+            allowed_origins: vec!["https://example.com".to_owned()],
+            max_age: Some(600),
+            ..CorsPolicy::default()
+        };
+
+        let mut headers = HeaderMap::new();
+        let origin = HeaderValue::from_static("https://example.com");
+        policy.apply(Some(&origin), true, &mut headers);
+        assert_eq!(origin, headers[header::ACCESS_CONTROL_ALLOW_ORIGIN]);
+        assert_eq!("600", headers[header::ACCESS_CONTROL_MAX_AGE]);
+        assert_eq!("Origin", headers[header::VARY]);
+
+        let mut headers = HeaderMap::new();
+        let origin = HeaderValue::from_static("https://evil.example.com");
+        policy.apply(Some(&origin), false, &mut headers);
+        assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert_eq!("Origin", headers[header::VARY]);
+    }
+}
@@ -8,11 +8,23 @@ extern crate http;
 extern crate hyper;
 extern crate serde;
 
+mod composite;
+mod compression;
+mod cors;
+mod load_shed;
+mod priority;
+mod rate_limit;
 mod request;
 mod response;
 mod server;
 mod service;
 
+pub use self::composite::{CompositeSource, Composites};
+pub use self::compression::{Compressor, Encoding};
+pub use self::cors::{CorsConfig, CorsPolicy};
+pub use self::load_shed::{LoadLimits, LoadShedder};
+pub use self::priority::{PriorityClass, Scheduler, DEFAULT_CLASS, PRIORITY_HEADER};
+pub use self::rate_limit::{RateLimiter, RateLimits};
 pub use self::request::GraphQLRequest;
 pub use self::response::GraphQLResponse;
 pub use self::server::GraphQLServer;
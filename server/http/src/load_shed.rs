@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::prelude::tokio::{self, sync::Semaphore};
+use graph::prelude::{Gauge, MetricsRegistry};
+use graph::util::env::env_var;
+
+/// How many queries can run at the same time, and how many can wait for
+/// one of them to finish. Queries beyond that are refused right away
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadLimits {
+    /// No limit if `None`
+    pub max_concurrent: Option<usize>,
+    pub max_waiting: usize,
+    /// How long a query can wait before it is refused
+    pub max_wait: Duration,
+}
+
+impl Default for LoadLimits {
+    fn default() -> Self {
+        LoadLimits {
+            max_concurrent: None,
+            max_waiting: 0,
+            max_wait: Duration::from_secs(5),
+        }
+    }
+}
+
+impl LoadLimits {
+    /// Read the limits from `GRAPH_GRAPHQL_MAX_CONCURRENT_QUERIES`,
+    /// `GRAPH_GRAPHQL_QUERY_QUEUE_SIZE`, which defaults to the number of
+    /// concurrent queries, and `GRAPH_GRAPHQL_QUERY_QUEUE_TIMEOUT` in
+    /// milliseconds
+    pub fn from_env() -> Self {
+        let max_concurrent = env_var("GRAPH_GRAPHQL_MAX_CONCURRENT_QUERIES");
+        LoadLimits {
+            max_concurrent,
+            max_waiting: env_var("GRAPH_GRAPHQL_QUERY_QUEUE_SIZE")
+                .unwrap_or_else(|| max_concurrent.unwrap_or(0)),
+            max_wait: env_var("GRAPH_GRAPHQL_QUERY_QUEUE_TIMEOUT")
+                .map(Duration::from_millis)
+                .unwrap_or(LoadLimits::default().max_wait),
+        }
+    }
+}
+
+/// Why a query was refused
+#[derive(Debug, PartialEq)]
+pub enum Overload {
+    /// All queue slots were taken
+    QueueFull,
+    /// The query waited too long
+    Timeout,
+}
+
+impl Overload {
+    /// The label for this rejection in metrics
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Overload::QueueFull => "queue_full",
+            Overload::Timeout => "timeout",
+        }
+    }
+}
+
+/// Caps the number of queries of one priority class that a server runs at
+/// the same time
+pub struct LoadShedder {
+    class: String,
+    limits: LoadLimits,
+    semaphore: Semaphore,
+    running: AtomicUsize,
+    waiting: AtomicUsize,
+    running_gauge: Box<Gauge>,
+    waiting_gauge: Box<Gauge>,
+}
+
+/// A query that may run. It holds on to its slot until it is dropped
+pub struct Slot {
+    shedder: Arc<LoadShedder>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.shedder.semaphore.add_permits(1);
+        let running = self.shedder.running.fetch_sub(1, Ordering::SeqCst) - 1;
+        self.shedder.running_gauge.set(running as f64);
+    }
+}
+
+impl LoadShedder {
+    pub fn new(limits: LoadLimits, class: &str, registry: &dyn MetricsRegistry) -> Self {
+        let gauge = |name: &str, help: &str| {
+            let mut labels = HashMap::new();
+            labels.insert("class".to_owned(), class.to_owned());
+            registry
+                .new_gauge(name.to_owned(), help.to_owned(), labels)
+                .unwrap_or_else(|_| panic!("failed to create `{}` gauge", name))
+        };
+        LoadShedder {
+            class: class.to_owned(),
+            semaphore: Semaphore::new(limits.max_concurrent.unwrap_or(0)),
+            limits,
+            running: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+            running_gauge: gauge(
+                "query_concurrency_running",
+                "GraphQL queries that are running",
+            ),
+            waiting_gauge: gauge(
+                "query_concurrency_waiting",
+                "GraphQL queries that are waiting for a running query to finish",
+            ),
+        }
+    }
+
+    /// The priority class whose queries this shedder limits
+    pub fn class(&self) -> &str {
+        &self.class
+    }
+
+    /// The number of seconds to put into the `Retry-After` header of
+    /// refused queries
+    pub fn retry_after(&self) -> u64 {
+        (self.limits.max_wait.as_secs_f64().ceil() as u64).max(1)
+    }
+
+    /// Wait until the query can run. Returns `Ok(None)` if there is no
+    /// limit on concurrent queries
+    pub async fn acquire(self: &Arc<Self>) -> Result<Option<Slot>, Overload> {
+        if self.limits.max_concurrent.is_none() {
+            return Ok(None);
+        }
+
+        match self.semaphore.try_acquire() {
+            Ok(permit) => permit.forget(),
+            Err(_) => {
+                // Reserve a place in the queue
+                let waiting = self.waiting.fetch_add(1, Ordering::SeqCst);
+                if waiting >= self.limits.max_waiting {
+                    self.waiting.fetch_sub(1, Ordering::SeqCst);
+                    return Err(Overload::QueueFull);
+                }
+                self.waiting_gauge.set((waiting + 1) as f64);
+                let acquired =
+                    tokio::time::timeout(self.limits.max_wait, self.semaphore.acquire()).await;
+                let waiting = self.waiting.fetch_sub(1, Ordering::SeqCst) - 1;
+                self.waiting_gauge.set(waiting as f64);
+                match acquired {
+                    Ok(permit) => permit.forget(),
+                    Err(_) => return Err(Overload::Timeout),
+                }
+            }
+        }
+
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.running_gauge.set(running as f64);
+        Ok(Some(Slot {
+            shedder: self.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_mock::MockMetricsRegistry;
+
+    #[tokio::test]
+    async fn sheds_queries_beyond_the_queue() {
+        let limits = LoadLimits {
+            max_concurrent: Some(1),
+            max_waiting: 1,
+            max_wait: Duration::from_millis(10),
+        };
+        let shedder = Arc::new(LoadShedder::new(
+            limits,
+            "default",
+            &MockMetricsRegistry::new(),
+        ));
+
+        let first = shedder.acquire().await.unwrap();
+        assert!(first.is_some());
+        // The second query waits in the queue and times out
+        assert_eq!(Some(Overload::Timeout), shedder.acquire().await.err());
+
+        drop(first);
+        let second = shedder.acquire().await.unwrap();
+        assert!(second.is_some());
+        assert_eq!(1, shedder.running.load(Ordering::SeqCst));
+        assert_eq!(0, shedder.waiting.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn does_not_limit_by_default() {
+        let shedder = Arc::new(LoadShedder::new(
+            LoadLimits::default(),
+            "default",
+            &MockMetricsRegistry::new(),
+        ));
+        for _ in 0..10 {
+            assert!(shedder.acquire().await.unwrap().is_none());
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use http::header::HeaderMap;
+use std::sync::Arc;
+
+use crate::load_shed::{LoadLimits, LoadShedder};
+use graph::prelude::MetricsRegistry;
+use graph::util::security::constant_time_eq;
+
+/// The header that clients on trusted networks can use to pick a priority
+/// class without a token
+pub const PRIORITY_HEADER: &str = "X-Graph-Priority";
+
+/// The name of the class for queries that do not belong to any other class
+pub const DEFAULT_CLASS: &str = "default";
+
+/// A class of queries, like those from monitoring or the indexer agent,
+/// that has its own concurrency budget so that it is not starved by other
+/// traffic. Queries belong to the class if they send one of its bearer
+/// `tokens`, or if they send its `header` value in `X-Graph-Priority`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriorityClass {
+    pub name: String,
+    pub limits: LoadLimits,
+    pub tokens: Vec<String>,
+    pub header: Option<String>,
+}
+
+impl PriorityClass {
+    fn matches(&self, token: Option<&str>, header: Option<&str>) -> bool {
+        // Look at every token so that the time this takes does not reveal
+        // how much of a token was right
+        let token_matches = token.map_or(false, |token| {
+            self.tokens
+                .iter()
+                .filter(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+                .count()
+                > 0
+        });
+        let header_matches = match (&self.header, header) {
+            (Some(ours), Some(theirs)) => ours.eq_ignore_ascii_case(theirs),
+            _ => false,
+        };
+        token_matches || header_matches
+    }
+}
+
+/// Assigns queries to priority classes, each with its own `LoadShedder`
+pub struct Scheduler {
+    default: Arc<LoadShedder>,
+    classes: Vec<(PriorityClass, Arc<LoadShedder>)>,
+}
+
+impl Scheduler {
+    pub fn new(
+        default: LoadLimits,
+        classes: Vec<PriorityClass>,
+        registry: &dyn MetricsRegistry,
+    ) -> Self {
+        let classes = classes
+            .into_iter()
+            .map(|class| {
+                let shedder = Arc::new(LoadShedder::new(
+                    class.limits.clone(),
+                    &class.name,
+                    registry,
+                ));
+                (class, shedder)
+            })
+            .collect();
+        Scheduler {
+            default: Arc::new(LoadShedder::new(default, DEFAULT_CLASS, registry)),
+            classes,
+        }
+    }
+
+    /// The load shedder for a query with the given bearer `token` and
+    /// headers. The first class that matches wins
+    pub fn shedder(&self, token: Option<&str>, headers: &HeaderMap) -> &Arc<LoadShedder> {
+        let header = headers
+            .get(PRIORITY_HEADER)
+            .and_then(|value| value.to_str().ok());
+        self.classes
+            .iter()
+            .find(|(class, _)| class.matches(token, header))
+            .map(|(_, shedder)| shedder)
+            .unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_mock::MockMetricsRegistry;
+    use http::header::HeaderValue;
+
+    #[test]
+    fn picks_class_by_token_or_header() {
+        let internal = PriorityClass {
+            name: "internal".to_owned(),
+            limits: LoadLimits {
+                max_concurrent: Some(5),
+                ..LoadLimits::default()
+            },
+            tokens: vec!["secret".to_owned()],
+            header: Some("internal".to_owned()),
+        };
+        let scheduler = Scheduler::new(
+            LoadLimits::default(),
+            vec![internal],
+            &MockMetricsRegistry::new(),
+        );
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(DEFAULT_CLASS, scheduler.shedder(None, &headers).class());
+        assert_eq!(
+            DEFAULT_CLASS,
+            scheduler.shedder(Some("other"), &headers).class()
+        );
+        assert_eq!(
+            "internal",
+            scheduler.shedder(Some("secret"), &headers).class()
+        );
+
+        headers.insert(PRIORITY_HEADER, HeaderValue::from_static("Internal"));
+        assert_eq!("internal", scheduler.shedder(None, &headers).class());
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use graph::util::env::env_var;
+
+/// How often clients that are idle are forgotten
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Limits that apply to each client separately. A client is identified by
+/// the API token in its `Authorization` header if the token is known, and
+/// by its IP address otherwise
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RateLimits {
+    /// How many requests per second a client can make on average
+    pub rate: Option<f64>,
+    /// How many requests a client can make in a burst; defaults to `rate`
+    pub burst: Option<f64>,
+    /// How many requests of a client can run at the same time
+    pub max_concurrent: Option<usize>,
+}
+
+impl RateLimits {
+    /// Read the limits from `GRAPH_GRAPHQL_RATE_LIMIT`,
+    /// `GRAPH_GRAPHQL_RATE_LIMIT_BURST`, and
+    /// `GRAPH_GRAPHQL_MAX_CONCURRENT_PER_CLIENT`. Limits that are not set
+    /// are not enforced
+    pub fn from_env() -> Self {
+        RateLimits {
+            rate: env_var("GRAPH_GRAPHQL_RATE_LIMIT"),
+            burst: env_var("GRAPH_GRAPHQL_RATE_LIMIT_BURST"),
+            max_concurrent: env_var("GRAPH_GRAPHQL_MAX_CONCURRENT_PER_CLIENT"),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.rate.is_some() || self.max_concurrent.is_some()
+    }
+
+    fn burst(&self) -> f64 {
+        self.burst.or(self.rate).unwrap_or(0.0).max(1.0)
+    }
+}
+
+/// Why a request was refused
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+    /// The client made too many requests; it can try again after the
+    /// given time
+    Rate(Duration),
+    /// The client has too many requests running
+    Concurrency,
+    /// The API token of the client used up its monthly quota; it gets a
+    /// new one after the given time
+    Quota(Duration),
+}
+
+impl Rejection {
+    /// The label for this rejection in metrics
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Rejection::Rate(_) => "rate",
+            Rejection::Concurrency => "concurrency",
+            Rejection::Quota(_) => "quota",
+        }
+    }
+
+    /// The body of the response to the refused request
+    pub fn message(&self) -> &'static str {
+        match self {
+            Rejection::Rate(_) | Rejection::Concurrency => "Too many requests",
+            Rejection::Quota(_) => "Monthly query quota exceeded",
+        }
+    }
+
+    /// The number of seconds to put into a `Retry-After` header
+    pub fn retry_after(&self) -> u64 {
+        match self {
+            Rejection::Rate(wait) | Rejection::Quota(wait) => {
+                (wait.as_secs_f64().ceil() as u64).max(1)
+            }
+            Rejection::Concurrency => 1,
+        }
+    }
+}
+
+struct Client {
+    /// The requests the client can still make right now; refilled at
+    /// `rate` up to `burst`
+    tokens: f64,
+    refilled: Instant,
+    in_flight: usize,
+}
+
+impl Client {
+    fn refill(&mut self, limits: &RateLimits, now: Instant) {
+        if let Some(rate) = limits.rate {
+            let elapsed = now.duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate).min(limits.burst());
+        }
+        self.refilled = now;
+    }
+
+    fn is_idle(&self, limits: &RateLimits) -> bool {
+        self.in_flight == 0 && self.tokens >= limits.burst()
+    }
+}
+
+struct Clients {
+    by_key: HashMap<String, Client>,
+    /// When idle clients were last forgotten
+    swept: Instant,
+}
+
+impl Clients {
+    /// Forget clients that are idle if the last sweep was more than
+    /// `SWEEP_INTERVAL` ago, so that checking a request does not have to
+    /// look at all clients
+    fn sweep(&mut self, limits: &RateLimits, now: Instant) {
+        if now.duration_since(self.swept) < SWEEP_INTERVAL {
+            return;
+        }
+        self.by_key.retain(|_, client| {
+            client.refill(limits, now);
+            !client.is_idle(limits)
+        });
+        self.swept = now;
+    }
+}
+
+/// Enforces `RateLimits` for all clients of a server
+pub struct RateLimiter {
+    limits: RateLimits,
+    clients: Mutex<Clients>,
+}
+
+/// A request that the rate limiter let through. It counts against the
+/// concurrency limit of its client until it is dropped
+pub struct Permit {
+    limiter: Arc<RateLimiter>,
+    key: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut clients = self.limiter.clients.lock().unwrap();
+        if let Some(client) = clients.by_key.get_mut(&self.key) {
+            client.in_flight -= 1;
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            limits,
+            clients: Mutex::new(Clients {
+                by_key: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+
+    /// Let a request of the client `key` through, or say why it has to be
+    /// refused. Returns `Ok(None)` if no limits are configured
+    pub fn acquire(self: &Arc<Self>, key: &str) -> Result<Option<Permit>, Rejection> {
+        self.acquire_at(key, Instant::now())
+    }
+
+    fn acquire_at(self: &Arc<Self>, key: &str, now: Instant) -> Result<Option<Permit>, Rejection> {
+        if !self.limits.is_active() {
+            return Ok(None);
+        }
+        let limits = &self.limits;
+        let mut clients = self.clients.lock().unwrap();
+        clients.sweep(limits, now);
+
+        let client = clients
+            .by_key
+            .entry(key.to_owned())
+            .or_insert_with(|| Client {
+                tokens: limits.burst(),
+                refilled: now,
+                in_flight: 0,
+            });
+        client.refill(limits, now);
+
+        if let Some(max) = limits.max_concurrent {
+            if client.in_flight >= max {
+                return Err(Rejection::Concurrency);
+            }
+        }
+        if let Some(rate) = limits.rate {
+            if client.tokens < 1.0 {
+                let wait = (1.0 - client.tokens) / rate;
+                return Err(Rejection::Rate(Duration::from_secs_f64(wait)));
+            }
+            client.tokens -= 1.0;
+        }
+        client.in_flight += 1;
+        Ok(Some(Permit {
+            limiter: self.clone(),
+            key: key.to_owned(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_rate_and_concurrency_per_client() {
+        let limiter = Arc::new(RateLimiter::new(RateLimits {
+            rate: Some(0.001),
+            burst: Some(2.0),
+            max_concurrent: Some(1),
+        }));
+
+        let first = limiter.acquire("ip:10.0.0.1").unwrap();
+        assert!(first.is_some());
+        assert_eq!(
+            Some(Rejection::Concurrency),
+            limiter.acquire("ip:10.0.0.1").err()
+        );
+        // Other clients are not affected
+        assert!(limiter.acquire("ip:10.0.0.2").is_ok());
+
+        drop(first);
+        assert!(limiter.acquire("ip:10.0.0.1").is_ok());
+        match limiter.acquire("ip:10.0.0.1") {
+            Err(rejection @ Rejection::Rate(_)) => assert!(rejection.retry_after() > 1),
+            _ => panic!("expected the third request to exceed the rate"),
+        }
+    }
+
+    #[test]
+    fn forgets_idle_clients_periodically() {
+        let limiter = Arc::new(RateLimiter::new(RateLimits {
+            rate: Some(1.0),
+            burst: Some(1.0),
+            max_concurrent: Some(1),
+        }));
+        let start = Instant::now();
+        let tracked = || limiter.clients.lock().unwrap().by_key.len();
+
+        drop(limiter.acquire_at("ip:10.0.0.1", start).unwrap());
+        let busy = limiter.acquire_at("ip:10.0.0.2", start).unwrap();
+        assert_eq!(2, tracked());
+
+        // Idle clients are kept until the next sweep
+        let later = start + Duration::from_secs(2);
+        drop(limiter.acquire_at("ip:10.0.0.3", later).unwrap());
+        assert_eq!(3, tracked());
+
+        // Clients with requests in flight are not forgotten
+        let next_sweep = start + SWEEP_INTERVAL + Duration::from_secs(2);
+        assert!(limiter.acquire_at("ip:10.0.0.4", next_sweep).is_ok());
+        assert_eq!(2, tracked());
+        drop(busy);
+    }
+
+    #[test]
+    fn no_limits_by_default() {
+        let limiter = Arc::new(RateLimiter::new(RateLimits::default()));
+        for _ in 0..100 {
+            assert!(limiter.acquire("ip:10.0.0.1").unwrap().is_none());
+        }
+    }
+}
@@ -1,10 +1,15 @@
 use graph::prelude::serde_json;
 use graphql_parser;
+use graphql_parser::query as q;
 use hyper::body::Bytes;
 
 use graph::components::server::query::GraphQLServerError;
 use graph::prelude::*;
 
+/// How many operations a batched request may contain unless
+/// `GRAPH_GRAPHQL_MAX_BATCH_SIZE` says otherwise
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 10;
+
 /// Future for a query parsed from an HTTP request.
 pub struct GraphQLRequest {
     body: Bytes,
@@ -23,51 +28,117 @@ impl Future for GraphQLRequest {
     type Error = GraphQLServerError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        // Fail if no schema is available
-        let schema = self.schema.clone();
-
         // Parse request body as JSON
         let json: serde_json::Value = serde_json::from_slice(&self.body)
             .map_err(|e| GraphQLServerError::ClientError(format!("{}", e)))?;
 
-        // Ensure the JSON data is an object
-        let obj = json.as_object().ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from("Request data is not an object"))
-        })?;
-
-        // Ensure the JSON data has a "query" field
-        let query_value = obj.get("query").ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from(
-                "The \"query\" field missing in request data",
-            ))
-        })?;
-
-        // Ensure the "query" field is a string
-        let query_string = query_value.as_str().ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from("The\"query\" field is not a string"))
-        })?;
-
-        // Parse the "query" field of the JSON body
-        let document = graphql_parser::parse_query(query_string)
-            .map_err(|e| GraphQLServerError::from(QueryError::from(e)))?;
-
-        // Parse the "variables" field of the JSON body, if present
-        let variables = match obj.get("variables") {
-            None | Some(serde_json::Value::Null) => Ok(None),
-            Some(variables @ serde_json::Value::Object(_)) => {
-                serde_json::from_value(variables.clone())
-                    .map_err(|e| GraphQLServerError::ClientError(e.to_string()))
-                    .map(Some)
-            }
-            _ => Err(GraphQLServerError::ClientError(
-                "Invalid query variables provided".to_string(),
-            )),
-        }?;
-
-        Ok(Async::Ready(Query::new(schema, document, variables)))
+        parse_operation(&json, self.schema.clone()).map(Async::Ready)
     }
 }
 
+/// Whether `body` is a batch of operations, i.e., a JSON array, rather
+/// than a single operation
+pub fn is_batch(body: &[u8]) -> bool {
+    body.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[')
+}
+
+/// Parse the operations in a batched request `body`. Each operation is
+/// parsed on its own, so that one broken operation only fails its own
+/// entry in the response. The whole batch fails if it is not a non-empty
+/// array of at most `max_size` operations
+pub fn parse_batch(
+    body: &[u8],
+    schema: Arc<Schema>,
+    max_size: usize,
+) -> Result<Vec<Result<Query, GraphQLServerError>>, GraphQLServerError> {
+    let json: serde_json::Value = serde_json::from_slice(body)
+        .map_err(|e| GraphQLServerError::ClientError(format!("{}", e)))?;
+    let operations = json.as_array().ok_or_else(|| {
+        GraphQLServerError::ClientError(String::from("Request data is not an array"))
+    })?;
+    if operations.is_empty() {
+        return Err(GraphQLServerError::ClientError(String::from(
+            "The batch does not contain any operations",
+        )));
+    }
+    if operations.len() > max_size {
+        return Err(GraphQLServerError::ClientError(format!(
+            "The batch contains {} operations, but at most {} are allowed",
+            operations.len(),
+            max_size
+        )));
+    }
+    Ok(operations
+        .iter()
+        .map(|operation| parse_operation(operation, schema.clone()))
+        .collect())
+}
+
+/// Parse a single operation, i.e., an object with a `query` and optional
+/// `variables` and `operationName`
+fn parse_operation(
+    json: &serde_json::Value,
+    schema: Arc<Schema>,
+) -> Result<Query, GraphQLServerError> {
+    let (document, variables, operation_name) = parse_operation_parts(json)?;
+    Ok(Query::new(schema, document, variables).with_operation_name(operation_name))
+}
+
+/// Parse the query document, variables and operation name of a request
+/// `body` that is not a batch, without tying them to a schema
+pub fn parse_request_parts(
+    body: &[u8],
+) -> Result<(q::Document, Option<QueryVariables>, Option<String>), GraphQLServerError> {
+    let json: serde_json::Value = serde_json::from_slice(body)
+        .map_err(|e| GraphQLServerError::ClientError(format!("{}", e)))?;
+    parse_operation_parts(&json)
+}
+
+fn parse_operation_parts(
+    json: &serde_json::Value,
+) -> Result<(q::Document, Option<QueryVariables>, Option<String>), GraphQLServerError> {
+    // Ensure the JSON data is an object
+    let obj = json.as_object().ok_or_else(|| {
+        GraphQLServerError::ClientError(String::from("Request data is not an object"))
+    })?;
+
+    // Ensure the JSON data has a "query" field
+    let query_value = obj.get("query").ok_or_else(|| {
+        GraphQLServerError::ClientError(String::from("The \"query\" field missing in request data"))
+    })?;
+
+    // Ensure the "query" field is a string
+    let query_string = query_value.as_str().ok_or_else(|| {
+        GraphQLServerError::ClientError(String::from("The\"query\" field is not a string"))
+    })?;
+
+    // Parse the "query" field of the JSON body
+    let document = graphql_parser::parse_query(query_string)
+        .map_err(|e| GraphQLServerError::from(QueryError::from(e)))?;
+
+    // Parse the "variables" field of the JSON body, if present
+    let variables = match obj.get("variables") {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(variables @ serde_json::Value::Object(_)) => serde_json::from_value(variables.clone())
+            .map_err(|e| GraphQLServerError::ClientError(e.to_string()))
+            .map(Some),
+        _ => Err(GraphQLServerError::ClientError(
+            "Invalid query variables provided".to_string(),
+        )),
+    }?;
+
+    // Parse the "operationName" field of the JSON body, if present
+    let operation_name = match obj.get("operationName") {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::String(name)) => Ok(Some(name.clone())),
+        _ => Err(GraphQLServerError::ClientError(
+            "The \"operationName\" field is not a string".to_string(),
+        )),
+    }?;
+
+    Ok((document, variables, operation_name))
+}
+
 #[cfg(test)]
 mod tests {
     use graphql_parser;
@@ -77,7 +148,7 @@ mod tests {
 
     use graph::prelude::*;
 
-    use super::GraphQLRequest;
+    use super::{is_batch, parse_batch, GraphQLRequest};
 
     const EXAMPLE_SCHEMA: &'static str = "type Query @entity { users: [User!] }";
 
@@ -210,4 +281,46 @@ mod tests {
         assert_eq!(query.document, expected_query);
         assert_eq!(query.variables, Some(expected_variables));
     }
+
+    #[test]
+    fn parses_operation_names() {
+        let schema =
+            Schema::parse(EXAMPLE_SCHEMA, SubgraphDeploymentId::new("test").unwrap()).unwrap();
+        let request = GraphQLRequest::new(
+            hyper::body::Bytes::from(
+                "{\"query\": \"query a { a } query b { b }\", \"operationName\": \"b\"}",
+            ),
+            Arc::new(schema.clone()),
+        );
+        let query = request.wait().expect("Should accept an operation name");
+        assert_eq!(Some("b".to_owned()), query.operation_name);
+
+        let request = GraphQLRequest::new(
+            hyper::body::Bytes::from("{\"query\": \"{ a }\", \"operationName\": 1}"),
+            Arc::new(schema),
+        );
+        request
+            .wait()
+            .expect_err("Should reject a non-string operation name");
+    }
+
+    #[test]
+    fn parses_batches() {
+        let schema = Arc::new(
+            Schema::parse(EXAMPLE_SCHEMA, SubgraphDeploymentId::new("test").unwrap()).unwrap(),
+        );
+        let body = " [{\"query\": \"{ user { name } }\"}, {\"query\": 5}]";
+        assert!(is_batch(body.as_bytes()));
+        assert!(!is_batch(b"{\"query\": \"{ user { name } }\"}"));
+
+        let operations = parse_batch(body.as_bytes(), schema.clone(), 2)
+            .expect("Should accept a batch of two operations");
+        assert_eq!(2, operations.len());
+        assert!(operations[0].is_ok());
+        assert!(operations[1].is_err());
+
+        parse_batch(body.as_bytes(), schema.clone(), 1)
+            .expect_err("Should reject batches that are too large");
+        parse_batch(b"[]", schema, 2).expect_err("Should reject empty batches");
+    }
 }
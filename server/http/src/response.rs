@@ -59,9 +59,39 @@ impl Future for GraphQLResponse {
             serde_json::to_string(self).expect("Failed to serialize GraphQL response to JSON");
         let response = Response::builder()
             .status(status_code)
-            .header("Access-Control-Allow-Origin", "*")
-            .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
-            .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
+            .header("Content-Type", "application/json")
+            .body(Body::from(json))
+            .unwrap();
+        Ok(Async::Ready(response))
+    }
+}
+
+/// Future for HTTP responses to batched GraphQL requests. The response is
+/// an array with the result of each operation, in the order of the
+/// operations in the request
+pub struct GraphQLBatchResponse {
+    responses: Vec<GraphQLResponse>,
+}
+
+impl GraphQLBatchResponse {
+    pub fn new(results: Vec<Result<QueryResult, GraphQLServerError>>) -> Self {
+        GraphQLBatchResponse {
+            responses: results.into_iter().map(GraphQLResponse::new).collect(),
+        }
+    }
+}
+
+impl Future for GraphQLBatchResponse {
+    type Item = Response<Body>;
+    type Error = GraphQLServerError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // Errors of individual operations are reported in their entry, so
+        // that clients can match results to operations
+        let json = serde_json::to_string(&self.responses)
+            .expect("Failed to serialize GraphQL response to JSON");
+        let response = Response::builder()
+            .status(StatusCode::OK)
             .header("Content-Type", "application/json")
             .body(Body::from(json))
             .unwrap();
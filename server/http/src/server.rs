@@ -1,21 +1,34 @@
+use std::io;
 use std::net::{Ipv4Addr, SocketAddrV4};
 
 use hyper;
+use hyper::server::accept;
 use hyper::service::make_service_fn;
 use hyper::Server;
 
+use crate::composite::Composites;
+use crate::compression::Compressor;
+use crate::cors::CorsConfig;
+use crate::load_shed::LoadLimits;
+use crate::priority::{PriorityClass, Scheduler};
+use crate::rate_limit::{RateLimiter, RateLimits};
+use crate::request::DEFAULT_MAX_BATCH_SIZE;
 use crate::service::{GraphQLService, GraphQLServiceMetrics};
 use graph::prelude::{GraphQLServer as GraphQLServerTrait, *};
+use graph::util::env::env_var;
+use graph::util::http_limits::{HttpLimits, LimitedStream};
+use graph::util::shutdown::Shutdown;
+use graph::util::tls::{self, MaybeTlsStream, TlsConfig};
 
 /// Errors that may occur when starting the server.
 #[derive(Debug, Fail)]
 pub enum GraphQLServeError {
     #[fail(display = "Bind error: {}", _0)]
-    BindError(hyper::Error),
+    BindError(io::Error),
 }
 
-impl From<hyper::Error> for GraphQLServeError {
-    fn from(err: hyper::Error) -> Self {
+impl From<io::Error> for GraphQLServeError {
+    fn from(err: io::Error) -> Self {
         GraphQLServeError::BindError(err)
     }
 }
@@ -27,6 +40,17 @@ pub struct GraphQLServer<Q, S> {
     graphql_runner: Arc<Q>,
     store: Arc<S>,
     node_id: NodeId,
+    shutdown: Shutdown,
+    rate_limiter: Arc<RateLimiter>,
+    tls: Option<Arc<TlsConfig>>,
+    cors: Arc<CorsConfig>,
+    compressor: Arc<Compressor>,
+    registry: Arc<dyn MetricsRegistry>,
+    priority_classes: Vec<PriorityClass>,
+    limits: HttpLimits,
+    max_batch_size: usize,
+    graphiql: bool,
+    composites: Arc<Composites>,
 }
 
 impl<Q, S> GraphQLServer<Q, S> {
@@ -47,12 +71,70 @@ impl<Q, S> GraphQLServer<Q, S> {
             }),
         );
         let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry.clone()));
+        let registry = metrics_registry as Arc<dyn MetricsRegistry>;
         GraphQLServer {
             logger,
             metrics,
             graphql_runner,
             store,
             node_id,
+            shutdown: Shutdown::new(),
+            rate_limiter: Arc::new(RateLimiter::new(RateLimits::from_env())),
+            tls: None,
+            cors: Arc::new(CorsConfig::default()),
+            compressor: Arc::new(Compressor::from_env()),
+            registry,
+            priority_classes: vec![],
+            limits: HttpLimits::from_env(),
+            max_batch_size: env_var("GRAPH_GRAPHQL_MAX_BATCH_SIZE")
+                .unwrap_or(DEFAULT_MAX_BATCH_SIZE),
+            graphiql: true,
+            composites: Arc::new(Composites::default()),
+        }
+    }
+
+    /// Stop accepting connections once `shutdown` has been triggered, and
+    /// make `shutdown` wait for queries that are still running
+    pub fn with_shutdown(self, shutdown: Shutdown) -> Self {
+        GraphQLServer { shutdown, ..self }
+    }
+
+    /// Answer requests from browsers according to `cors`; by default, all
+    /// origins are allowed
+    pub fn with_cors(self, cors: CorsConfig) -> Self {
+        GraphQLServer {
+            cors: Arc::new(cors),
+            ..self
+        }
+    }
+
+    /// Give the queries of each of `classes` their own concurrency budget.
+    /// All other queries use the limits from the environment
+    pub fn with_priority_classes(self, priority_classes: Vec<PriorityClass>) -> Self {
+        GraphQLServer {
+            priority_classes,
+            ..self
+        }
+    }
+
+    /// Serve GraphiQL to browsers that visit a query URL; on by default
+    pub fn with_graphiql(self, graphiql: bool) -> Self {
+        GraphQLServer { graphiql, ..self }
+    }
+
+    /// Serve `composites` under `/subgraphs/composite/<name>`
+    pub fn with_composites(self, composites: Composites) -> Self {
+        GraphQLServer {
+            composites: Arc::new(composites),
+            ..self
+        }
+    }
+
+    /// Only accept connections that use TLS with the certificate in `tls`
+    pub fn with_tls(self, tls: Arc<TlsConfig>) -> Self {
+        GraphQLServer {
+            tls: Some(tls),
+            ..self
         }
     }
 }
@@ -71,9 +153,10 @@ where
     ) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>, Self::ServeError> {
         let logger = self.logger.clone();
 
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
         info!(
             logger,
-            "Starting GraphQL HTTP server at: http://localhost:{}", port
+            "Starting GraphQL HTTP server at: {}://localhost:{}", scheme, port
         );
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
@@ -85,22 +168,59 @@ where
         let metrics = self.metrics.clone();
         let store = self.store.clone();
         let node_id = self.node_id.clone();
-        let new_service = make_service_fn(move |_| {
-            futures03::future::ok::<_, Error>(GraphQLService::new(
-                logger_for_service.clone(),
-                metrics.clone(),
-                graphql_runner.clone(),
-                store.clone(),
-                ws_port,
-                node_id.clone(),
-            ))
+        let shutdown = self.shutdown.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let cors = self.cors.clone();
+        let compressor = self.compressor.clone();
+        let scheduler = Arc::new(Scheduler::new(
+            LoadLimits::from_env(),
+            self.priority_classes.clone(),
+            self.registry.as_ref(),
+        ));
+        let max_body_size = self.limits.max_body_size;
+        let max_batch_size = self.max_batch_size;
+        let graphiql = self.graphiql;
+        let composites = self.composites.clone();
+        let new_service = make_service_fn(move |conn: &LimitedStream<MaybeTlsStream>| {
+            // The address is only missing if the client has already
+            // disconnected; such requests can all share one bucket
+            let ip = conn
+                .get_ref()
+                .remote_addr()
+                .map(|addr| addr.ip())
+                .unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+            futures03::future::ok::<_, Error>(
+                GraphQLService::new(
+                    logger_for_service.clone(),
+                    metrics.clone(),
+                    graphql_runner.clone(),
+                    store.clone(),
+                    ws_port,
+                    node_id.clone(),
+                )
+                .with_shutdown(shutdown.clone())
+                .with_rate_limiter(rate_limiter.clone(), ip)
+                .with_cors(cors.clone())
+                .with_compressor(compressor.clone())
+                .with_scheduler(scheduler.clone())
+                .with_max_body_size(max_body_size)
+                .with_max_batch_size(max_batch_size)
+                .with_graphiql(graphiql)
+                .with_composites(composites.clone()),
+            )
         });
 
         // Create a task to run the server and handle HTTP requests
-        let task = Server::try_bind(&addr.into())?
+        let listener = tls::bind(addr.into())?;
+        let limits = self.limits.clone();
+        let incoming = tls::incoming(self.logger.clone(), listener, self.tls.clone())
+            .map_ok(move |conn| LimitedStream::new(conn, limits.clone()));
+        let task = Server::builder(accept::from_stream(incoming))
             .serve(new_service)
+            .with_graceful_shutdown(self.shutdown.triggered())
             .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
 
-        Ok(Box::new(task.compat()))
+        // The shutdown signal is not `Unpin`, so the task needs to be pinned
+        Ok(Box::new(Box::pin(task).compat()))
     }
 }
@@ -1,6 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::fmt;
+use std::net::IpAddr;
 use std::ops::Deref;
 use std::pin::Pin;
 use std::task::Context;
@@ -8,18 +9,46 @@ use std::task::Poll;
 use std::time::Instant;
 
 use graph::components::server::query::GraphQLServerError;
+use graph::data::query::quota::{self, QuotaCheck};
 use graph::data::subgraph::schema::{SubgraphEntity, SUBGRAPHS_ID};
+use graph::log::audit::client_identity;
 use graph::prelude::*;
+use graph::trace::{self, Span, SpanKind, TraceContext};
+use graph::util::env::env_var;
+use graph::util::http_limits::HttpLimits;
+use graph::util::lfu_cache::CacheWeight;
+use graph::util::memory::{MemoryUsage, Subsystem};
+use graph::util::security;
+use graph::util::shutdown::Shutdown;
+use graph_graphql::prelude::{merge_composite_results, split_composite_query, CompositeResult};
 use http::header;
+use hyper::body::{Bytes, HttpBody};
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
 
-use crate::request::GraphQLRequest;
-use crate::response::GraphQLResponse;
+use crate::composite::{CompositeSource, Composites};
+use crate::compression::{Compressor, Encoding};
+use crate::cors::CorsConfig;
+use crate::load_shed::{LoadShedder, Slot};
+use crate::priority::Scheduler;
+use crate::rate_limit::{Permit, RateLimiter, RateLimits, Rejection};
+use crate::request::{
+    is_batch, parse_batch, parse_request_parts, GraphQLRequest, DEFAULT_MAX_BATCH_SIZE,
+};
+use crate::response::{GraphQLBatchResponse, GraphQLResponse};
 
 pub struct GraphQLServiceMetrics {
     query_execution_time: Box<HistogramVec>,
     failed_query_execution_time: Box<HistogramVec>,
+    rate_limited: Box<CounterVec>,
+    load_shed: Box<CounterVec>,
+    query_count: Box<CounterVec>,
+    query_errors: Box<CounterVec>,
+    query_cache_hits: Box<CounterVec>,
+    query_duration: Box<HistogramVec>,
+    response_bytes: Box<CounterVec>,
+    /// Whether to label query metrics with the client's API token
+    by_token: bool,
 }
 
 impl fmt::Debug for GraphQLServiceMetrics {
@@ -50,12 +79,137 @@ impl GraphQLServiceMetrics {
             )
             .expect("failed to create `subgraph_failed_query_execution_time` histogram");
 
+        let rate_limited = registry
+            .new_counter_vec(
+                format!("query_rate_limited"),
+                String::from("Requests that were refused because of client rate limits"),
+                HashMap::new(),
+                vec![String::from("reason")],
+            )
+            .expect("failed to create `query_rate_limited` counter");
+
+        let load_shed = registry
+            .new_counter_vec(
+                format!("query_load_shed"),
+                String::from("Queries that were refused because the server was overloaded"),
+                HashMap::new(),
+                vec![String::from("class"), String::from("reason")],
+            )
+            .expect("failed to create `query_load_shed` counter");
+
+        // Metrics for charging deployments, and optionally API tokens,
+        // for the queries they cause
+        let labels = vec![String::from("subgraph_deployment"), String::from("token")];
+        let query_count = registry
+            .new_counter_vec(
+                format!("subgraph_query_count"),
+                String::from("Queries that were run"),
+                HashMap::new(),
+                labels.clone(),
+            )
+            .expect("failed to create `subgraph_query_count` counter");
+        let query_errors = registry
+            .new_counter_vec(
+                format!("subgraph_query_errors"),
+                String::from("Queries that failed or whose result contains errors"),
+                HashMap::new(),
+                labels.clone(),
+            )
+            .expect("failed to create `subgraph_query_errors` counter");
+        let query_cache_hits = registry
+            .new_counter_vec(
+                format!("subgraph_query_cache_hits"),
+                String::from("Queries whose result came from the query cache"),
+                HashMap::new(),
+                labels.clone(),
+            )
+            .expect("failed to create `subgraph_query_cache_hits` counter");
+        let query_duration = registry
+            .new_histogram_vec(
+                format!("subgraph_query_duration_seconds"),
+                String::from("How long it took to run queries"),
+                HashMap::new(),
+                labels.clone(),
+                vec![0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0],
+            )
+            .expect("failed to create `subgraph_query_duration_seconds` histogram");
+        let response_bytes = registry
+            .new_counter_vec(
+                format!("subgraph_query_response_bytes"),
+                String::from("Bytes in the uncompressed bodies of query responses"),
+                HashMap::new(),
+                labels,
+            )
+            .expect("failed to create `subgraph_query_response_bytes` counter");
+        let by_token = env_var("GRAPH_QUERY_METRICS_BY_TOKEN").unwrap_or(false);
+
         Self {
             query_execution_time,
             failed_query_execution_time,
+            rate_limited,
+            load_shed,
+            query_count,
+            query_errors,
+            query_cache_hits,
+            query_duration,
+            response_bytes,
+            by_token,
         }
     }
 
+    /// The value of the `token` label for a request from `client`, as
+    /// identified by `client_identity`. The label is empty unless
+    /// `GRAPH_QUERY_METRICS_BY_TOKEN` is set and the client sent a token
+    pub fn token_label(&self, client: &str) -> String {
+        if self.by_token && client.starts_with("token:") {
+            client.to_owned()
+        } else {
+            String::new()
+        }
+    }
+
+    /// Account for one query against `deployment` and `token`
+    pub fn observe_query(
+        &self,
+        deployment: &str,
+        token: &str,
+        duration: f64,
+        result: &Result<QueryResult, GraphQLServerError>,
+    ) {
+        let labels = [deployment, token];
+        self.query_count.with_label_values(&labels).inc();
+        self.query_duration
+            .with_label_values(&labels)
+            .observe(duration);
+        match result {
+            Ok(result) => {
+                if result.errors.is_some() {
+                    self.query_errors.with_label_values(&labels).inc();
+                }
+                if result.cached {
+                    self.query_cache_hits.with_label_values(&labels).inc();
+                }
+            }
+            Err(_) => self.query_errors.with_label_values(&labels).inc(),
+        }
+    }
+
+    /// Account for a response of `bytes` bytes to queries against
+    /// `deployment` and `token`
+    pub fn observe_response_bytes(&self, deployment: &str, token: &str, bytes: u64) {
+        self.response_bytes
+            .with_label_values(&[deployment, token])
+            .inc_by(bytes as f64);
+    }
+
+    pub fn observe_rate_limited(&self, reason: &str) {
+        self.rate_limited.with_label_values(&[reason]).inc();
+    }
+
+    pub fn observe_load_shed(&self, class: &str, reason: &str) {
+        self.load_shed.with_label_values(&[class, reason]).inc();
+    }
+
     pub fn observe_query_execution_time(&self, duration: f64, deployment_id: String) {
         self.query_execution_time
             .with_label_values(vec![deployment_id.as_ref()].as_slice())
@@ -74,6 +228,18 @@ pub type GraphQLServiceResult = Result<Response<Body>, GraphQLServerError>;
 pub type GraphQLServiceResponse =
     Pin<Box<dyn std::future::Future<Output = GraphQLServiceResult> + Send>>;
 
+/// Who sent a batched request, and what the operations of the batch count
+/// against
+struct BatchClient {
+    /// The identity that queries are run and logged with
+    identity: String,
+    token: Option<String>,
+    /// The key of the client for rate limiting
+    key: String,
+    /// The shedder of the client's priority class if there is a scheduler
+    shedder: Option<Arc<LoadShedder>>,
+}
+
 /// A Hyper Service that serves GraphQL over a POST / endpoint.
 #[derive(Debug)]
 pub struct GraphQLService<Q, S> {
@@ -83,6 +249,17 @@ pub struct GraphQLService<Q, S> {
     store: Arc<S>,
     ws_port: u16,
     node_id: NodeId,
+    shutdown: Shutdown,
+    rate_limiter: Arc<RateLimiter>,
+    /// The address of the client on the other end of the connection
+    remote_addr: Option<IpAddr>,
+    cors: Arc<CorsConfig>,
+    compressor: Arc<Compressor>,
+    scheduler: Option<Arc<Scheduler>>,
+    max_body_size: usize,
+    max_batch_size: usize,
+    graphiql: bool,
+    composites: Arc<Composites>,
 }
 
 impl<Q, S> Clone for GraphQLService<Q, S> {
@@ -94,6 +271,16 @@ impl<Q, S> Clone for GraphQLService<Q, S> {
             store: self.store.clone(),
             ws_port: self.ws_port,
             node_id: self.node_id.clone(),
+            shutdown: self.shutdown.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            remote_addr: self.remote_addr,
+            cors: self.cors.clone(),
+            compressor: self.compressor.clone(),
+            scheduler: self.scheduler.clone(),
+            max_body_size: self.max_body_size,
+            max_batch_size: self.max_batch_size,
+            graphiql: self.graphiql,
+            composites: self.composites.clone(),
         }
     }
 }
@@ -119,7 +306,225 @@ where
             store,
             ws_port,
             node_id,
+            shutdown: Shutdown::new(),
+            rate_limiter: Arc::new(RateLimiter::new(RateLimits::default())),
+            remote_addr: None,
+            cors: Arc::new(CorsConfig::default()),
+            compressor: Arc::new(Compressor::new(false, 0)),
+            scheduler: None,
+            max_body_size: HttpLimits::default().max_body_size,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            graphiql: true,
+            composites: Arc::new(Composites::default()),
+        }
+    }
+
+    /// Refuse new requests once `shutdown` has been triggered, and make
+    /// `shutdown` wait for requests that are already running
+    pub fn with_shutdown(self, shutdown: Shutdown) -> Self {
+        GraphQLService { shutdown, ..self }
+    }
+
+    /// Enforce the per-client limits of `rate_limiter` on queries. The
+    /// limiter should be shared by all services of a server
+    pub fn with_rate_limiter(self, rate_limiter: Arc<RateLimiter>, remote_addr: IpAddr) -> Self {
+        GraphQLService {
+            rate_limiter,
+            remote_addr: Some(remote_addr),
+            ..self
+        }
+    }
+
+    /// Answer requests from browsers according to `cors` instead of
+    /// allowing all origins
+    pub fn with_cors(self, cors: Arc<CorsConfig>) -> Self {
+        GraphQLService { cors, ..self }
+    }
+
+    /// Compress query responses for clients that accept that. The
+    /// compressor should be shared by all services of a server so that
+    /// its cache is shared, too
+    pub fn with_compressor(self, compressor: Arc<Compressor>) -> Self {
+        GraphQLService { compressor, ..self }
+    }
+
+    /// Limit how many queries of each priority class run at the same time
+    /// across all services that share `scheduler`
+    pub fn with_scheduler(self, scheduler: Arc<Scheduler>) -> Self {
+        GraphQLService {
+            scheduler: Some(scheduler),
+            ..self
+        }
+    }
+
+    /// Refuse requests whose body is larger than `max_body_size` bytes
+    pub fn with_max_body_size(self, max_body_size: usize) -> Self {
+        GraphQLService {
+            max_body_size,
+            ..self
+        }
+    }
+
+    /// Refuse batched requests with more than `max_batch_size` operations
+    pub fn with_max_batch_size(self, max_batch_size: usize) -> Self {
+        GraphQLService {
+            max_batch_size,
+            ..self
+        }
+    }
+
+    /// Whether to serve GraphiQL to browsers; production deployments may
+    /// want to turn it off
+    pub fn with_graphiql(self, graphiql: bool) -> Self {
+        GraphQLService { graphiql, ..self }
+    }
+
+    /// Serve `composites` under `/subgraphs/composite/<name>`
+    pub fn with_composites(self, composites: Arc<Composites>) -> Self {
+        GraphQLService { composites, ..self }
+    }
+
+    /// Compress the body of `response` with `encoding` if it is a JSON
+    /// response that is large enough for that to be worthwhile
+    async fn compress(&self, response: Response<Body>, encoding: Encoding) -> Response<Body> {
+        let is_json = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map_or(false, |value| value == "application/json");
+        if !is_json || response.headers().contains_key(header::CONTENT_ENCODING) {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        parts.headers.append(
+            header::VARY,
+            header::HeaderValue::from_static("Accept-Encoding"),
+        );
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(e) => {
+                error!(self.logger, "Failed to read response body"; "error" => e.to_string());
+                return Response::builder()
+                    .status(500)
+                    .header("Content-Type", "text/plain")
+                    .body(Body::from("Internal server error"))
+                    .unwrap();
+            }
+        };
+        let compressor = self.compressor.clone();
+        match tokio::task::block_in_place(|| compressor.compress(encoding, &body)) {
+            Ok(Some(compressed)) => {
+                parts.headers.insert(
+                    header::CONTENT_ENCODING,
+                    header::HeaderValue::from_static(encoding.as_str()),
+                );
+                parts.headers.remove(header::CONTENT_LENGTH);
+                Response::from_parts(parts, Body::from(compressed))
+            }
+            Ok(None) => Response::from_parts(parts, Body::from(body)),
+            Err(e) => {
+                warn!(self.logger, "Failed to compress response"; "error" => e.to_string());
+                Response::from_parts(parts, Body::from(body))
+            }
+        }
+    }
+
+    /// The deployment whose CORS policy applies to `req`. Names are only
+    /// resolved if some deployment has its own policy
+    fn cors_deployment(&self, req: &Request<Body>) -> Option<SubgraphDeploymentId> {
+        if !self.cors.has_overrides() {
+            return None;
+        }
+        let mut segments = req.uri().path().split('/').skip(1).collect::<Vec<_>>();
+        if segments.last() == Some(&"graphql") {
+            segments.pop();
+        }
+        if segments.starts_with(&["subgraphs", "name"]) {
+            if let Some((name, selector)) = SubgraphVersionSelector::from_path(&segments[2..]) {
+                return self
+                    .store
+                    .resolve_subgraph_version(SubgraphName::new(name).ok()?, selector)
+                    .ok()
+                    .flatten();
+            }
         }
+        let name = match segments.as_slice() {
+            ["subgraphs", "id", id] => return SubgraphDeploymentId::new(*id).ok(),
+            ["subgraphs", "name", name] => name.to_string(),
+            ["subgraphs", "name", part1, part2] => format!("{}/{}", part1, part2),
+            ["subgraphs", "network", part1, part2] => format!("network/{}/{}", part1, part2),
+            _ => return None,
+        };
+        let selector = version_selector(req.uri().query()).ok()?;
+        let name = SubgraphName::new(name).ok()?;
+        self.store
+            .resolve_subgraph_version(name, selector)
+            .ok()
+            .flatten()
+    }
+
+    /// The key that identifies the client making `req` for rate limiting:
+    /// the API token if it is one that has a quota, and the client's
+    /// address otherwise. Keying by any token would let a client get a
+    /// fresh rate limit for every made up token it sends
+    fn client_key(&self, req: &Request<Body>) -> String {
+        let known_token = bearer_token(req)
+            .map(|token| client_identity(Some(token), None))
+            .filter(|client| {
+                quota::installed()
+                    .map(|quotas| quotas.has_quota(client))
+                    .unwrap_or(false)
+            });
+        known_token.unwrap_or_else(|| client_identity(None, self.remote_addr))
+    }
+
+    /// Let a query from `key` through the rate limits and the quota of its
+    /// API token. Queries of tokens that are over their quota, but within
+    /// the grace threshold, run with a warning for the response
+    fn admit(
+        &self,
+        token: Option<&str>,
+        key: &str,
+    ) -> Result<(Option<Permit>, Option<String>), Rejection> {
+        let permit = self.rate_limiter.acquire(key)?;
+        let (quotas, token) = match (quota::installed(), token) {
+            (Some(quotas), Some(token)) => (quotas, token),
+            _ => return Ok((permit, None)),
+        };
+        match quotas.acquire(&client_identity(Some(token), None)) {
+            QuotaCheck::Unlimited | QuotaCheck::Within => Ok((permit, None)),
+            QuotaCheck::Grace { limit, used } => {
+                let warning = format!(
+                    "299 - \"Monthly query quota of {} exceeded with {} queries\"",
+                    limit, used
+                );
+                Ok((permit, Some(warning)))
+            }
+            QuotaCheck::Exceeded { .. } => Err(Rejection::Quota(quotas.retry_after())),
+        }
+    }
+
+    /// Let an operation of a batch through the rate limits, the quota and
+    /// the scheduler like a request with a single query. The slot is
+    /// `None` if there is no limit on concurrent queries
+    async fn admit_operation(
+        &self,
+        client: &BatchClient,
+    ) -> Result<(Option<Permit>, Option<Slot>), GraphQLServerError> {
+        let admitted = self.admit(client.token.as_deref(), &client.key);
+        let (permit, _) = admitted.map_err(|rejection| {
+            self.metrics.observe_rate_limited(rejection.reason());
+            GraphQLServerError::ClientError(rejection.message().to_owned())
+        })?;
+        let slot = match &client.shedder {
+            Some(shedder) => shedder.acquire().await.map_err(|overload| {
+                self.metrics
+                    .observe_load_shed(shedder.class(), overload.reason());
+                GraphQLServerError::InternalError("Service unavailable (overloaded)".to_owned())
+            })?,
+            None => None,
+        };
+        Ok((permit, slot))
     }
 
     fn graphiql_html(&self) -> String {
@@ -184,14 +589,32 @@ where
     }
 
     fn handle_graphiql(&self) -> GraphQLServiceResponse {
+        if !self.graphiql {
+            return self.handle_not_found();
+        }
         self.serve_dynamic_file(self.graphiql_html())
     }
 
+    /// Handles a `GET` of the query URL `path`: browsers get GraphiQL
+    /// right away, everybody else is sent to the GraphiQL URL
+    fn handle_query_url_get(self, path: &str, html: bool) -> GraphQLServiceResponse {
+        if html || !self.graphiql {
+            return self.handle_graphiql();
+        }
+        let dest = format!("{}/graphql", path.trim_end_matches('/'));
+        self.handle_temp_redirect(dest).boxed()
+    }
+
     async fn handle_graphql_query_by_name(
         self,
         subgraph_name: String,
+        selector: Option<SubgraphVersionSelector>,
         request: Request<Body>,
     ) -> GraphQLServiceResult {
+        let selector = match selector {
+            Some(selector) => selector,
+            None => version_selector(request.uri().query())?,
+        };
         let subgraph_id = SubgraphName::new(subgraph_name.as_str())
             .map_err(|()| {
                 GraphQLServerError::ClientError(format!(
@@ -201,7 +624,7 @@ where
             })
             .and_then(|subgraph_name| {
                 self.store
-                    .resolve_subgraph_name_to_id(subgraph_name)
+                    .resolve_subgraph_version(subgraph_name, selector.clone())
                     .map_err(|e| {
                         GraphQLServerError::InternalError(format!(
                             "Error resolving subgraph name: {}",
@@ -210,13 +633,23 @@ where
                     })
             })
             .and_then(|subgraph_id_opt| {
-                subgraph_id_opt.ok_or(GraphQLServerError::ClientError(
-                    "Subgraph name not found".to_owned(),
-                ))
+                subgraph_id_opt.ok_or_else(|| match selector {
+                    SubgraphVersionSelector::Current => {
+                        GraphQLServerError::ClientError("Subgraph name not found".to_owned())
+                    }
+                    SubgraphVersionSelector::Pending => GraphQLServerError::ClientError(
+                        "Subgraph name not found or subgraph has no pending version".to_owned(),
+                    ),
+                    SubgraphVersionSelector::Label(label) => {
+                        GraphQLServerError::ClientError(format!(
+                            "Subgraph name not found or subgraph has no version labelled {:?}",
+                            label
+                        ))
+                    }
+                })
             })?;
 
-        self.handle_graphql_query(subgraph_id, request.into_body())
-            .await
+        self.handle_graphql_query(subgraph_id, request).await
     }
 
     fn handle_graphql_query_by_id(
@@ -226,16 +659,30 @@ where
     ) -> GraphQLServiceResponse {
         match SubgraphDeploymentId::new(id) {
             Err(()) => self.handle_not_found(),
-            Ok(id) => self.handle_graphql_query(id, request.into_body()).boxed(),
+            Ok(id) => self.handle_graphql_query(id, request).boxed(),
         }
     }
 
     async fn handle_graphql_query(
         self,
         id: SubgraphDeploymentId,
-        request_body: Body,
+        request: Request<Body>,
     ) -> GraphQLServiceResult {
-        let service = self.clone();
+        let trace = request.extensions().get::<TraceContext>().cloned();
+        let client = client_identity(bearer_token(&request), self.remote_addr);
+        let api_version = ApiVersion::from_url_query(request.uri().query())
+            .map_err(|e| GraphQLServerError::ClientError(e.to_string()))?;
+        let batch_client = BatchClient {
+            identity: client.clone(),
+            token: bearer_token(&request).map(str::to_owned),
+            key: self.client_key(&request),
+            shedder: self.scheduler.as_ref().map(|scheduler| {
+                scheduler
+                    .shedder(bearer_token(&request), request.headers())
+                    .clone()
+            }),
+        };
+        let request_body = request.into_body();
         let logger = self.logger.clone();
         let service_metrics = self.metrics.clone();
         let sd_id = id.clone();
@@ -260,49 +707,289 @@ where
             }
         };
 
+        // Queries use the version of the deployment unless the URL asks
+        // for a different one
+        let api_version = match api_version {
+            Some(api_version) => api_version,
+            None => self
+                .store
+                .api_version(&id)
+                .map_err(|e| GraphQLServerError::InternalError(e.to_string()))?,
+        };
+
+        let token = self.metrics.token_label(&client);
         let start = Instant::now();
-        hyper::body::to_bytes(request_body)
-            .map_err(|_| GraphQLServerError::from("Failed to read request body"))
-            .and_then(move |body| GraphQLRequest::new(body, schema).compat())
-            .and_then(move |query| {
-                // Run the query using the query runner
-                tokio::task::block_in_place(|| {
-                    service
-                        .graphql_runner
-                        .run_query(query)
-                        .map_err(|e| GraphQLServerError::from(e))
-                        .compat()
-                })
+        let body = read_body(request_body, self.max_body_size).await;
+        if let Ok(body) = &body {
+            if is_batch(body) {
+                let operations = {
+                    let _span = Span::child_of(trace.as_ref(), "graphql.parse");
+                    match parse_batch(body, schema, self.max_batch_size) {
+                        Ok(operations) => operations,
+                        Err(e) => return GraphQLResponse::new(Err(e)).compat().await,
+                    }
+                };
+                return self
+                    .handle_graphql_batch(id, operations, api_version, start, trace, batch_client)
+                    .await;
+            }
+        }
+
+        let query = body.and_then(|body| {
+            let _span = Span::child_of(trace.as_ref(), "graphql.parse");
+            GraphQLRequest::new(body, schema).wait()
+        });
+        let query_id = query.as_ref().ok().map(|query| query.query_id.clone());
+        let result = query.and_then(|query| {
+            // Run the query using the query runner
+            tokio::task::block_in_place(|| {
+                // Spans for cache lookups and SQL statements become
+                // children of this span
+                let mut span = Span::child_of(trace.as_ref(), "graphql.execute");
+                span.set_attribute("graphql.query_id", &query.query_id);
+                let _entered = span.enter();
+                self.graphql_runner
+                    .run_query(query.with_client(client).with_api_version(api_version))
+                    .wait()
+                    .map_err(GraphQLServerError::from)
             })
-            .then(move |result| {
-                service_metrics.observe_query_execution_time(
-                    start.elapsed().as_secs_f64(),
-                    sd_id.deref().to_string(),
-                );
-                let elapsed = start.elapsed().as_millis();
-                if let Err(e) = &result {
-                    error!(
-                        logger,
-                        "GraphQL query failed";
-                        "subgraph_deployment" => sd_id.deref(),
-                        "error" => e.to_string(),
-                        "query_time_ms" => elapsed,
-                        "code" => LogCode::GraphQlQueryFailure,
-                    )
+        });
+
+        service_metrics
+            .observe_query_execution_time(start.elapsed().as_secs_f64(), sd_id.deref().to_string());
+        service_metrics.observe_query(
+            sd_id.deref(),
+            &token,
+            start.elapsed().as_secs_f64(),
+            &result,
+        );
+        let elapsed = start.elapsed().as_millis();
+        if let Err(e) = &result {
+            error!(
+                logger,
+                "GraphQL query failed";
+                "subgraph_deployment" => sd_id.deref(),
+                "query_id" => query_id,
+                "error" => e.to_string(),
+                "query_time_ms" => elapsed,
+                "code" => LogCode::GraphQlQueryFailure,
+            )
+        }
+        // The result is accounted for until it has been serialized
+        let _usage = MemoryUsage::with_bytes(
+            Subsystem::InFlightQueries,
+            result.as_ref().map_or(0, |result| result.weight()),
+        );
+        let response = GraphQLResponse::new(result).compat().await;
+        self.observe_response(sd_id.deref(), &token, response)
+    }
+
+    /// Runs the operations of a batched request concurrently. The response
+    /// lists their results in the order of the operations. Each operation
+    /// counts as one query for the rate and concurrency limits; the first
+    /// one runs with the permits of the request itself, and the others
+    /// have to be admitted separately
+    async fn handle_graphql_batch(
+        self,
+        id: SubgraphDeploymentId,
+        operations: Vec<Result<Query, GraphQLServerError>>,
+        api_version: ApiVersion,
+        start: Instant,
+        trace: Option<TraceContext>,
+        client: BatchClient,
+    ) -> GraphQLServiceResult {
+        let token = self.metrics.token_label(&client.identity);
+
+        let service = &self;
+        let client = &client;
+        let operations = operations.into_iter().enumerate();
+        let results = futures03::future::join_all(operations.map(|(i, operation)| {
+            let graphql_runner = self.graphql_runner.clone();
+            async move {
+                let query = operation
+                    .map_err(|e| (None, e))?
+                    .with_client(client.identity.clone())
+                    .with_api_version(api_version);
+                let query_id = query.query_id.clone();
+                let _admitted = if i > 0 {
+                    let admitted = service.admit_operation(client).await;
+                    Some(admitted.map_err(|e| (Some(query_id.clone()), e))?)
+                } else {
+                    None
+                };
+                tokio::task::spawn_blocking(move || {
+                    let mut span = Span::child_of(trace.as_ref(), "graphql.execute");
+                    span.set_attribute("graphql.query_id", &query.query_id);
+                    let _entered = span.enter();
+                    graphql_runner.run_query(query).wait()
+                })
+                .await
+                .map_err(|e| GraphQLServerError::InternalError(format!("Query panicked: {}", e)))
+                .and_then(|result| result.map_err(GraphQLServerError::from))
+                .map_err(|e| (Some(query_id), e))
+            }
+        }))
+        .await;
+
+        self.metrics
+            .observe_query_execution_time(start.elapsed().as_secs_f64(), id.deref().to_string());
+        let elapsed = start.elapsed().as_millis();
+        for (query_id, e) in results.iter().filter_map(|result| result.as_ref().err()) {
+            error!(
+                self.logger,
+                "GraphQL query failed";
+                "subgraph_deployment" => id.deref(),
+                "query_id" => query_id,
+                "error" => e.to_string(),
+                "query_time_ms" => elapsed,
+                "batch_size" => results.len(),
+                "code" => LogCode::GraphQlQueryFailure,
+            )
+        }
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|result| result.map_err(|(_, e)| e))
+            .collect();
+        // Each operation counts as one query, and took as long as the
+        // whole batch
+        for result in &results {
+            self.metrics
+                .observe_query(id.deref(), &token, start.elapsed().as_secs_f64(), result);
+        }
+        let _usage = MemoryUsage::with_bytes(
+            Subsystem::InFlightQueries,
+            results
+                .iter()
+                .filter_map(|result| result.as_ref().ok())
+                .map(|result| result.weight())
+                .sum(),
+        );
+        let response = GraphQLBatchResponse::new(results).compat().await;
+        self.observe_response(id.deref(), &token, response)
+    }
+
+    /// Runs a query against the composite deployment `name`. The query for
+    /// each namespace runs against the deployment of that namespace, and
+    /// the response combines their results. Metrics for such queries are
+    /// labelled with `composite/<name>`
+    async fn handle_composite_query(
+        self,
+        name: String,
+        request: Request<Body>,
+    ) -> GraphQLServiceResult {
+        let sources = match self.composites.get(&name) {
+            Some(sources) => sources.clone(),
+            None => return self.handle_not_found().await,
+        };
+        let client = client_identity(bearer_token(&request), self.remote_addr);
+        let label = format!("composite/{}", name);
+        let token = self.metrics.token_label(&client);
+        let start = Instant::now();
+
+        let body = read_body(request.into_body(), self.max_body_size).await?;
+        let (document, variables, operation_name) = parse_request_parts(&body)?;
+        let namespaces: Vec<_> = sources.keys().map(String::as_str).collect();
+        let parts = match split_composite_query(
+            &document,
+            operation_name.as_deref(),
+            variables.as_ref(),
+            &namespaces,
+        ) {
+            Ok(parts) => parts,
+            Err(e) => {
+                return GraphQLResponse::new(Ok(QueryResult::from(e)))
+                    .compat()
+                    .await
+            }
+        };
+
+        let mut deployments = BTreeMap::new();
+        for (namespace, source) in &sources {
+            let deployment = match source {
+                CompositeSource::Deployment(id) => id.clone(),
+                CompositeSource::Name(subgraph_name) => self
+                    .store
+                    .resolve_subgraph_name_to_id(subgraph_name.clone())
+                    .map_err(|e| GraphQLServerError::InternalError(e.to_string()))?
+                    .ok_or_else(|| {
+                        GraphQLServerError::ClientError(format!(
+                            "Subgraph {} for namespace `{}` not found",
+                            subgraph_name, namespace
+                        ))
+                    })?,
+            };
+            deployments.insert(namespace.as_str(), deployment);
+        }
+
+        let results = futures03::future::join_all(parts.into_iter().map(|part| {
+            let graphql_runner = self.graphql_runner.clone();
+            let store = self.store.clone();
+            let deployment = deployments[part.namespace.as_str()].clone();
+            let variables = variables.clone();
+            let client = client.clone();
+            async move {
+                let result = tokio::task::spawn_blocking({
+                    let deployment = deployment.clone();
+                    let document = part.document.clone();
+                    move || {
+                        let schema = store.api_schema(&deployment).map_err(|e| {
+                            QueryError::from(QueryExecutionError::StoreError(e.into()))
+                        })?;
+                        let query = Query::new(schema, document, variables).with_client(client);
+                        let result = graphql_runner.run_query(query).wait();
+                        let block = store.block_ptr(deployment).ok().flatten();
+                        result.map(|result| (result, block))
+                    }
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    Err(QueryError::from(QueryExecutionError::Panic(e.to_string())))
+                });
+                let (result, block) = match result {
+                    Ok((result, block)) => (Ok(result), block),
+                    Err(e) => (Err(e), None),
+                };
+                CompositeResult {
+                    part,
+                    deployment,
+                    block,
+                    result,
                 }
-                GraphQLResponse::new(result).compat()
-            })
-            .await
+            }
+        }))
+        .await;
+
+        let result = Ok(merge_composite_results(results));
+        self.metrics
+            .observe_query(&label, &token, start.elapsed().as_secs_f64(), &result);
+        let response = GraphQLResponse::new(result).compat().await;
+        self.observe_response(&label, &token, response)
     }
 
-    // Handles OPTIONS requests
+    /// Account for the size of the body of `response` to queries against
+    /// `deployment`
+    fn observe_response(
+        &self,
+        deployment: &str,
+        token: &str,
+        response: GraphQLServiceResult,
+    ) -> GraphQLServiceResult {
+        if let Some(bytes) = response
+            .as_ref()
+            .ok()
+            .and_then(|response| HttpBody::size_hint(response.body()).exact())
+        {
+            self.metrics
+                .observe_response_bytes(deployment, token, bytes);
+        }
+        response
+    }
+
+    // Handles OPTIONS requests; the CORS headers are added in `call`
     fn handle_graphql_options(&self, _request: Request<Body>) -> GraphQLServiceResponse {
         async {
             Ok(Response::builder()
                 .status(200)
-                .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
-                .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
                 .body(Body::from(""))
                 .unwrap())
         }
@@ -335,6 +1022,7 @@ where
 
     fn handle_call(self, req: Request<Body>) -> GraphQLServiceResponse {
         let method = req.method().clone();
+        let html = accepts_html(&req);
 
         let path = req.uri().path().to_owned();
         let path_segments = {
@@ -346,12 +1034,38 @@ where
             segments.collect::<Vec<_>>()
         };
 
+        // `/subgraphs/name/<name>/pending` and
+        // `/subgraphs/name/<name>/version/<label>`, with or without a
+        // trailing `/graphql` for GraphiQL
+        let versioned = if path_segments.starts_with(&["subgraphs", "name"]) {
+            let rest = &path_segments[2..];
+            match rest.split_last() {
+                Some((&"graphql", init)) => {
+                    SubgraphVersionSelector::from_path(init).map(|target| (true, target))
+                }
+                _ => SubgraphVersionSelector::from_path(rest).map(|target| (false, target)),
+            }
+        } else {
+            None
+        };
+        if let Some((graphiql, (subgraph_name, selector))) = versioned {
+            return match method {
+                Method::GET if graphiql => self.handle_graphiql(),
+                Method::GET => self.handle_query_url_get(&path, html),
+                Method::POST if !graphiql => self
+                    .handle_graphql_query_by_name(subgraph_name, Some(selector), req)
+                    .boxed(),
+                Method::OPTIONS if !graphiql => self.handle_graphql_options(req),
+                _ => self.handle_not_found(),
+            };
+        }
+
         match (method, path_segments.as_slice()) {
             (Method::GET, [""]) => self.index().boxed(),
-            (Method::GET, ["graphiql.css"]) => {
+            (Method::GET, ["graphiql.css"]) if self.graphiql => {
                 self.serve_file(include_str!("../assets/graphiql.css"))
             }
-            (Method::GET, ["graphiql.min.js"]) => {
+            (Method::GET, ["graphiql.min.js"]) if self.graphiql => {
                 self.serve_file(include_str!("../assets/graphiql.min.js"))
             }
 
@@ -361,31 +1075,28 @@ where
             | (Method::GET, &["subgraphs", "network", _, _, "graphql"])
             | (Method::GET, &["subgraphs", "graphql"]) => self.handle_graphiql(),
 
-            (Method::GET, path @ ["subgraphs", "id", _])
-            | (Method::GET, path @ ["subgraphs", "name", _])
-            | (Method::GET, path @ ["subgraphs", "name", _, _])
-            | (Method::GET, path @ ["subgraphs", "network", _, _])
-            | (Method::GET, path @ ["subgraphs"]) => {
-                let dest = format!("/{}/graphql", path.join("/"));
-                self.handle_temp_redirect(dest).boxed()
-            }
+            (Method::GET, ["subgraphs", "id", _])
+            | (Method::GET, ["subgraphs", "name", _])
+            | (Method::GET, ["subgraphs", "name", _, _])
+            | (Method::GET, ["subgraphs", "network", _, _])
+            | (Method::GET, ["subgraphs"]) => self.handle_query_url_get(&path, html),
 
             (Method::POST, &["subgraphs", "id", subgraph_id]) => {
                 self.handle_graphql_query_by_id(subgraph_id.to_owned(), req)
             }
             (Method::OPTIONS, ["subgraphs", "id", _]) => self.handle_graphql_options(req),
             (Method::POST, &["subgraphs", "name", subgraph_name]) => self
-                .handle_graphql_query_by_name(subgraph_name.to_owned(), req)
+                .handle_graphql_query_by_name(subgraph_name.to_owned(), None, req)
                 .boxed(),
             (Method::POST, ["subgraphs", "name", subgraph_name_part1, subgraph_name_part2]) => {
                 let subgraph_name = format!("{}/{}", subgraph_name_part1, subgraph_name_part2);
-                self.handle_graphql_query_by_name(subgraph_name, req)
+                self.handle_graphql_query_by_name(subgraph_name, None, req)
                     .boxed()
             }
             (Method::POST, ["subgraphs", "network", subgraph_name_part1, subgraph_name_part2]) => {
                 let subgraph_name =
                     format!("network/{}/{}", subgraph_name_part1, subgraph_name_part2);
-                self.handle_graphql_query_by_name(subgraph_name, req)
+                self.handle_graphql_query_by_name(subgraph_name, None, req)
                     .boxed()
             }
 
@@ -393,6 +1104,11 @@ where
             | (Method::OPTIONS, ["subgraphs", "name", _, _])
             | (Method::OPTIONS, ["subgraphs", "network", _, _]) => self.handle_graphql_options(req),
 
+            (Method::POST, &["subgraphs", "composite", name]) => {
+                self.handle_composite_query(name.to_owned(), req).boxed()
+            }
+            (Method::OPTIONS, ["subgraphs", "composite", _]) => self.handle_graphql_options(req),
+
             // `/subgraphs` acts as an alias to `/subgraphs/id/SUBGRAPHS_ID`
             (Method::POST, &["subgraphs"]) => {
                 self.handle_graphql_query_by_id(SUBGRAPHS_ID.to_string(), req)
@@ -404,6 +1120,54 @@ where
     }
 }
 
+/// Read `body`, failing if it is larger than `max_size` bytes. Requests
+/// that say how large their body is are refused before it is read
+async fn read_body(mut body: Body, max_size: usize) -> Result<Bytes, GraphQLServerError> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|_| GraphQLServerError::from("Failed to read request body"))?;
+        if data.len() + chunk.len() > max_size {
+            return Err(GraphQLServerError::ClientError(format!(
+                "request body is larger than {} bytes",
+                max_size
+            )));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(data))
+}
+
+/// Whether the `Content-Length` of `req` is larger than `max_size`
+fn body_too_large(req: &Request<Body>, max_size: usize) -> bool {
+    req.headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .map_or(false, |len| len > max_size as u64)
+}
+
+/// Whether `req` comes from a browser that wants to see a page
+fn accepts_html(req: &Request<Body>) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("text/html"))
+}
+
+/// The bearer token in the `Authorization` header of `req`, if any
+fn bearer_token(req: &Request<Body>) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(security::bearer_token)
+}
+
+fn version_selector(query: Option<&str>) -> Result<SubgraphVersionSelector, GraphQLServerError> {
+    SubgraphVersionSelector::from_url_query(query)
+        .map_err(|e| GraphQLServerError::ClientError(e.to_string()))
+}
+
 impl<Q, S> Service<Request<Body>> for GraphQLService<Q, S>
 where
     Q: GraphQlRunner,
@@ -420,49 +1184,174 @@ where
     fn call(&mut self, req: Request<Body>) -> Self::Future {
         let logger = self.logger.clone();
         let service = self.clone();
+        let origin = req.headers().get(header::ORIGIN).cloned();
+        let preflight = req.method() == Method::OPTIONS;
+        let encoding = service
+            .compressor
+            .encoding(req.headers().get(header::ACCEPT_ENCODING));
+
+        // Only queries count against the rate limits
+        let is_query = req.method() == Method::POST;
+        if body_too_large(&req, service.max_body_size) {
+            let mut response = Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .header("Content-Type", "text/plain")
+                .body(Body::from(format!(
+                    "Request body is larger than {} bytes",
+                    service.max_body_size
+                )))
+                .unwrap();
+            service
+                .cors
+                .default
+                .apply(origin.as_ref(), false, response.headers_mut());
+            return Box::pin(futures03::future::ok(response));
+        }
+        let (permit, quota_warning) = if is_query {
+            let key = service.client_key(&req);
+            match service.admit(bearer_token(&req), &key) {
+                Ok(admitted) => admitted,
+                Err(rejection) => {
+                    debug!(logger, "Refusing request because of rate limits";
+                           "client" => &key, "reason" => rejection.reason());
+                    service.metrics.observe_rate_limited(rejection.reason());
+                    let mut response = Response::builder()
+                        .status(StatusCode::TOO_MANY_REQUESTS)
+                        .header("Content-Type", "text/plain")
+                        .header(header::RETRY_AFTER, rejection.retry_after())
+                        .body(Body::from(rejection.message()))
+                        .unwrap();
+                    // Let browsers see why the request failed without
+                    // looking up the deployment for a refused request
+                    service
+                        .cors
+                        .default
+                        .apply(origin.as_ref(), false, response.headers_mut());
+                    return Box::pin(futures03::future::ok(response));
+                }
+            }
+        } else {
+            (None, None)
+        };
 
         // Returning Err here will prevent the client from receiving any response.
         // Instead, we generate a Response with an error code and return Ok
         Box::pin(async move {
+            let mut req = req;
+            let mut span = Span::root(
+                "http.request",
+                SpanKind::Server,
+                req.headers()
+                    .get(trace::TRACEPARENT_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(TraceContext::from_traceparent),
+            );
+            span.set_attribute("http.method", req.method());
+            span.set_attribute("http.target", req.uri().path());
+            if let Some(context) = span.context() {
+                req.extensions_mut().insert(context);
+            }
+            let _permit = permit;
+            let _work = match service.shutdown.start_work() {
+                Some(work) => work,
+                None => {
+                    return Ok(Response::builder()
+                        .status(503)
+                        .header("Content-Type", "text/plain")
+                        .body(Body::from("Service unavailable (shutting down)"))
+                        .unwrap())
+                }
+            };
+            let shedder = match (is_query, &service.scheduler) {
+                (true, Some(scheduler)) => {
+                    Some(scheduler.shedder(bearer_token(&req), req.headers()).clone())
+                }
+                _ => None,
+            };
+            let _slot = match shedder {
+                Some(shedder) => match shedder.acquire().await {
+                    Ok(slot) => slot,
+                    Err(overload) => {
+                        debug!(logger, "Refusing query because the server is overloaded";
+                               "class" => shedder.class(), "reason" => overload.reason());
+                        service
+                            .metrics
+                            .observe_load_shed(shedder.class(), overload.reason());
+                        let mut response = Response::builder()
+                            .status(StatusCode::SERVICE_UNAVAILABLE)
+                            .header("Content-Type", "text/plain")
+                            .header(header::RETRY_AFTER, shedder.retry_after())
+                            .body(Body::from("Service unavailable (overloaded)"))
+                            .unwrap();
+                        service
+                            .cors
+                            .default
+                            .apply(origin.as_ref(), false, response.headers_mut());
+                        return Ok(response);
+                    }
+                },
+                None => None,
+            };
+            let deployment = service.cors_deployment(&req);
+            let cors = service.cors.clone();
+            let compressing_service = service.clone();
             let result = service.handle_call(req).await;
-            match result {
-                Ok(response) => Ok(response),
+            let mut response = match result {
+                Ok(response) => response,
                 Err(err @ GraphQLServerError::Canceled(_)) => {
                     error!(logger, "GraphQLService call failed: {}", err);
 
-                    Ok(Response::builder()
+                    Response::builder()
                         .status(500)
                         .header("Content-Type", "text/plain")
                         .body(Body::from("Internal server error (operation canceled)"))
-                        .unwrap())
+                        .unwrap()
                 }
                 Err(err @ GraphQLServerError::ClientError(_)) => {
                     debug!(logger, "GraphQLService call failed: {}", err);
 
-                    Ok(Response::builder()
+                    Response::builder()
                         .status(400)
                         .header("Content-Type", "text/plain")
                         .body(Body::from(format!("Invalid request: {}", err)))
-                        .unwrap())
+                        .unwrap()
                 }
                 Err(err @ GraphQLServerError::QueryError(_)) => {
                     error!(logger, "GraphQLService call failed: {}", err);
 
-                    Ok(Response::builder()
+                    Response::builder()
                         .status(500)
                         .header("Content-Type", "text/plain")
                         .body(Body::from(format!("Query error: {}", err)))
-                        .unwrap())
+                        .unwrap()
                 }
                 Err(err @ GraphQLServerError::InternalError(_)) => {
                     error!(logger, "GraphQLService call failed: {}", err);
 
-                    Ok(Response::builder()
+                    Response::builder()
                         .status(500)
                         .header("Content-Type", "text/plain")
                         .body(Body::from(format!("Internal server error: {}", err)))
-                        .unwrap())
+                        .unwrap()
                 }
+            };
+            cors.policy(deployment.as_ref()).apply(
+                origin.as_ref(),
+                preflight,
+                response.headers_mut(),
+            );
+            if let Some(warning) = quota_warning {
+                response
+                    .headers_mut()
+                    .insert(header::WARNING, warning.parse().unwrap());
+            }
+            span.set_attribute("http.status_code", response.status().as_u16());
+            if response.status().is_server_error() {
+                span.set_error(response.status());
+            }
+            match encoding {
+                Some(encoding) => Ok(compressing_service.compress(response, encoding).await),
+                None => Ok(response),
             }
         })
     }
@@ -479,8 +1368,10 @@ mod tests {
     use graph_mock::{mock_store_with_users_subgraph, MockMetricsRegistry};
     use graphql_parser::query as q;
 
+    use crate::rate_limit::{RateLimiter, RateLimits};
     use crate::test_utils;
 
+    use super::version_selector;
     use super::GraphQLService;
     use super::GraphQLServiceMetrics;
 
@@ -515,6 +1406,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn version_selector_defaults_to_current() {
+        assert_eq!(
+            version_selector(None).unwrap(),
+            SubgraphVersionSelector::Current
+        );
+        assert_eq!(
+            version_selector(Some("foo=bar")).unwrap(),
+            SubgraphVersionSelector::Current
+        );
+        assert_eq!(
+            version_selector(Some("foo=bar&version=pending")).unwrap(),
+            SubgraphVersionSelector::Pending
+        );
+        assert!(version_selector(Some("version=latest")).is_err());
+    }
+
+    #[test]
+    fn version_selector_from_path_suffix() {
+        use SubgraphVersionSelector::*;
+
+        let from_path = SubgraphVersionSelector::from_path;
+        assert_eq!(from_path(&["a"]), None);
+        assert_eq!(from_path(&["a", "b"]), None);
+        assert_eq!(from_path(&["a", "version"]), None);
+        assert_eq!(
+            from_path(&["a", "pending"]),
+            Some(("a".to_owned(), Pending))
+        );
+        assert_eq!(
+            from_path(&["a", "b", "pending"]),
+            Some(("a/b".to_owned(), Pending))
+        );
+        assert_eq!(
+            from_path(&["a", "version", "v1.0"]),
+            Some(("a".to_owned(), Label("v1.0".to_owned())))
+        );
+        assert_eq!(
+            from_path(&["a", "b", "version", "v1.0"]),
+            Some(("a/b".to_owned(), Label("v1.0".to_owned())))
+        );
+        assert_eq!(from_path(&["a", "b", "c", "pending"]), None);
+        assert_eq!(from_path(&["a", "b", "c", "version", "v1"]), None);
+    }
+
     #[test]
     fn posting_invalid_query_yields_error_response() {
         let logger = Logger::root(slog::Discard, o!());
@@ -554,6 +1490,72 @@ mod tests {
         );
     }
 
+    #[test]
+    fn refuses_large_bodies() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let (store, subgraph_id) = mock_store_with_users_subgraph();
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+
+        let node_id = NodeId::new("test").unwrap();
+        let mut service =
+            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id)
+                .with_max_body_size(16);
+        let uri = format!("http://localhost:8000/subgraphs/id/{}", subgraph_id);
+        let body = "{\"query\": \"{ name }\"}";
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(&uri)
+            .header(http::header::CONTENT_LENGTH, body.len())
+            .body(Body::from(body))
+            .unwrap();
+        let response =
+            futures03::executor::block_on(service.call(request)).expect("Should return a response");
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+
+        // Without a `Content-Length`, the body is cut off while reading it
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(&uri)
+            .body(Body::from(body))
+            .unwrap();
+        let response =
+            futures03::executor::block_on(service.call(request)).expect("Should return a response");
+        test_utils::assert_error_response(response, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn serves_graphiql_to_browsers() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let (store, subgraph_id) = mock_store_with_users_subgraph();
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+
+        let node_id = NodeId::new("test").unwrap();
+        let service = GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id);
+        let uri = format!("http://localhost:8000/subgraphs/id/{}", subgraph_id);
+        let get = |mut service: GraphQLService<_, _>, accept: &str| {
+            let request = Request::builder()
+                .method(Method::GET)
+                .uri(&uri)
+                .header(http::header::ACCEPT, accept)
+                .body(Body::from(""))
+                .unwrap();
+            futures03::executor::block_on(service.call(request))
+                .expect("Should return a response")
+                .status()
+        };
+
+        assert_eq!(StatusCode::OK, get(service.clone(), "text/html,*/*"));
+        assert_eq!(StatusCode::FOUND, get(service.clone(), "*/*"));
+        let service = service.with_graphiql(false);
+        assert_eq!(StatusCode::NOT_FOUND, get(service.clone(), "text/html"));
+        assert_eq!(StatusCode::NOT_FOUND, get(service, "*/*"));
+    }
+
     #[tokio::test(threaded_scheduler)]
     async fn posting_valid_queries_yields_result_response() {
         let logger = Logger::root(slog::Discard, o!());
@@ -590,4 +1592,86 @@ mod tests {
             .expect("Query result field \"name\" is not a string");
         assert_eq!(name, "Jordi".to_string());
     }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn runs_batches_in_order() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let (store, subgraph_id) = mock_store_with_users_subgraph();
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+
+        let node_id = NodeId::new("test").unwrap();
+        let mut service =
+            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id);
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!(
+                "http://localhost:8000/subgraphs/id/{}",
+                subgraph_id
+            ))
+            .body(Body::from(
+                "[{\"query\": \"{ name }\"}, {\"query\": 5}, {\"query\": \"{ name }\"}]",
+            ))
+            .unwrap();
+
+        let response = tokio::spawn(service.call(request))
+            .await
+            .unwrap()
+            .expect("Should return a response");
+        assert_eq!(StatusCode::OK, response.status());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let results = json.as_array().expect("Batch response must be an array");
+        assert_eq!(3, results.len());
+        assert_eq!(results[0]["data"]["name"], "Jordi");
+        assert!(results[1]["errors"].is_array());
+        assert_eq!(results[2]["data"]["name"], "Jordi");
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn rate_limits_each_operation_of_a_batch() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let (store, subgraph_id) = mock_store_with_users_subgraph();
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimits {
+            rate: Some(0.001),
+            burst: Some(2.0),
+            max_concurrent: None,
+        }));
+
+        let node_id = NodeId::new("test").unwrap();
+        let mut service =
+            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id)
+                .with_rate_limiter(rate_limiter, "10.0.0.1".parse().unwrap());
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!(
+                "http://localhost:8000/subgraphs/id/{}",
+                subgraph_id
+            ))
+            .body(Body::from(
+                "[{\"query\": \"{ name }\"}, {\"query\": \"{ name }\"}, {\"query\": \"{ name }\"}]",
+            ))
+            .unwrap();
+
+        let response = tokio::spawn(service.call(request))
+            .await
+            .unwrap()
+            .expect("Should return a response");
+        assert_eq!(StatusCode::OK, response.status());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let results = json.as_array().expect("Batch response must be an array");
+        // The burst covers the request and its second operation, but not
+        // the third one
+        assert_eq!(3, results.len());
+        assert_eq!(results[0]["data"]["name"], "Jordi");
+        assert_eq!(results[1]["data"]["name"], "Jordi");
+        assert!(results[2]["errors"].is_array());
+    }
 }
@@ -0,0 +1,24 @@
+//! Make the git commit that graph-node is built from available as
+//! `GRAPH_NODE_COMMIT` for the `version` query. Builds outside a git
+//! checkout, e.g. in Docker, can set `GRAPH_NODE_COMMIT` themselves.
+
+use std::env;
+use std::process::Command;
+
+fn main() {
+    let commit = env::var("GRAPH_NODE_COMMIT").ok().or_else(|| {
+        Command::new("git")
+            .args(&["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|commit| commit.trim().to_owned())
+    });
+    println!(
+        "cargo:rustc-env=GRAPH_NODE_COMMIT={}",
+        commit.unwrap_or_else(|| String::from("unknown"))
+    );
+    println!("cargo:rerun-if-env-changed=GRAPH_NODE_COMMIT");
+    println!("cargo:rerun-if-changed=../../.git/HEAD");
+}
@@ -0,0 +1,266 @@
+use graph::data::subgraph::schema::{
+    EthereumContractAbiEntity, SubgraphManifestEntity, TypedEntity,
+};
+use graph::prelude::*;
+
+/// The files that a subgraph was deployed from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Artifact {
+    /// The manifest, as it was uploaded to IPFS
+    Manifest,
+    /// The GraphQL schema supplied by the user
+    Schema,
+    /// The ABI with this name from the manifest
+    Abi(String),
+}
+
+impl Artifact {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Artifact::Manifest => "text/yaml",
+            Artifact::Schema => "application/graphql",
+            Artifact::Abi(_) => "application/json",
+        }
+    }
+}
+
+/// What artifacts are read from; this is the store, except in tests
+pub trait ArtifactStore {
+    fn is_deployed(&self, id: &SubgraphDeploymentId) -> Result<bool, Error>;
+    fn input_schema(&self, id: &SubgraphDeploymentId) -> Result<Arc<Schema>, Error>;
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError>;
+}
+
+impl<S: Store + SubgraphDeploymentStore> ArtifactStore for S {
+    fn is_deployed(&self, id: &SubgraphDeploymentId) -> Result<bool, Error> {
+        Store::is_deployed(self, id)
+    }
+
+    fn input_schema(&self, id: &SubgraphDeploymentId) -> Result<Arc<Schema>, Error> {
+        SubgraphDeploymentStore::input_schema(self, id)
+    }
+
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        Store::find(self, query)
+    }
+}
+
+/// Read `artifact` of the deployment `id`. Returns `None` if the
+/// deployment does not exist or has no such ABI. The schema comes from the
+/// store; the manifest and ABIs are fetched through `link_resolver` from
+/// the links that the store recorded when the subgraph was deployed
+pub async fn fetch<S: ArtifactStore + ?Sized>(
+    logger: &Logger,
+    store: &S,
+    link_resolver: &dyn LinkResolver,
+    id: &SubgraphDeploymentId,
+    artifact: &Artifact,
+) -> Result<Option<Vec<u8>>, Error> {
+    if !store.is_deployed(id)? {
+        return Ok(None);
+    }
+
+    let link = match artifact {
+        Artifact::Schema => {
+            let schema = store.input_schema(id)?;
+            return Ok(Some(schema.document.to_string().into_bytes()));
+        }
+        Artifact::Manifest => id.to_ipfs_link(),
+        Artifact::Abi(name) => match abi_link(store, id, name)? {
+            Some(link) => link,
+            None => return Ok(None),
+        },
+    };
+    link_resolver.cat(logger, &link).await.map(Some)
+}
+
+/// The link to the ABI called `name` in any data source or template of the
+/// deployment `id`. All manifest entities of a deployment have ids that
+/// start with the id of its `SubgraphManifest` entity
+fn abi_link<S: ArtifactStore + ?Sized>(
+    store: &S,
+    id: &SubgraphDeploymentId,
+    name: &str,
+) -> Result<Option<Link>, Error> {
+    let prefix = format!("{}-", SubgraphManifestEntity::id(id));
+    let abis = store
+        .find(
+            EthereumContractAbiEntity::query()
+                .filter(EntityFilter::And(vec![
+                    EntityFilter::StartsWith("id".to_owned(), prefix.into()),
+                    EntityFilter::new_equal("name", name),
+                ]))
+                .first(1),
+        )
+        .map_err(QueryError::from)?;
+    abis.into_iter()
+        .next()
+        .map(|abi| match abi.get("file") {
+            Some(Value::String(file)) => Ok(Link::from(file.to_owned())),
+            _ => Err(format_err!("EthereumContractAbi entity has no `file`")),
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    const ABI: &str = r#"[{"type":"event","name":"Transfer"}]"#;
+
+    /// A store that only knows the deployment `QmArtifacts` with one ABI
+    struct TestStore {
+        id: SubgraphDeploymentId,
+        abis: Vec<Entity>,
+    }
+
+    impl TestStore {
+        fn new() -> Self {
+            let id = SubgraphDeploymentId::new("QmArtifacts").unwrap();
+            let mut abi = Entity::new();
+            abi.set(
+                "id",
+                format!(
+                    "{}-data-source-0-mapping-abi-0",
+                    SubgraphManifestEntity::id(&id)
+                ),
+            );
+            abi.set("name", "Token");
+            abi.set("file", "/ipfs/QmTokenAbi");
+            TestStore {
+                id,
+                abis: vec![abi],
+            }
+        }
+    }
+
+    impl ArtifactStore for TestStore {
+        fn is_deployed(&self, id: &SubgraphDeploymentId) -> Result<bool, Error> {
+            Ok(id == &self.id)
+        }
+
+        fn input_schema(&self, _: &SubgraphDeploymentId) -> Result<Arc<Schema>, Error> {
+            unimplemented!()
+        }
+
+        fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+            let filters = match query.filter {
+                Some(EntityFilter::And(filters)) => filters,
+                filter => panic!("unexpected ABI filter {:?}", filter),
+            };
+            let (prefix, name) = match filters.as_slice() {
+                [EntityFilter::StartsWith(id, prefix), EntityFilter::Equal(attr, name)]
+                    if id == "id" && attr == "name" =>
+                {
+                    (prefix.clone(), name.clone())
+                }
+                _ => panic!("unexpected ABI filter {:?}", filters),
+            };
+            Ok(self
+                .abis
+                .iter()
+                .filter(|abi| match (abi.get("id"), abi.get("name")) {
+                    (Some(Value::String(id)), Some(abi_name)) => {
+                        id.starts_with(prefix.as_str().unwrap()) && abi_name == &name
+                    }
+                    _ => false,
+                })
+                .cloned()
+                .collect())
+        }
+    }
+
+    /// A link resolver that remembers which links were requested
+    #[derive(Default)]
+    struct TestResolver {
+        files: HashMap<String, Vec<u8>>,
+        requested: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl LinkResolver for TestResolver {
+        fn with_timeout(self, _: Duration) -> Self {
+            self
+        }
+
+        fn with_retries(self) -> Self {
+            self
+        }
+
+        async fn cat(&self, _: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+            self.requested.lock().unwrap().push(link.link.clone());
+            self.files
+                .get(&link.link)
+                .cloned()
+                .ok_or_else(|| format_err!("no file at {}", link.link))
+        }
+
+        async fn json_stream(&self, _: &Logger, _: &Link) -> Result<JsonValueStream, Error> {
+            unimplemented!()
+        }
+    }
+
+    fn fetch_artifact(
+        store: &TestStore,
+        resolver: &TestResolver,
+        id: &str,
+        artifact: Artifact,
+    ) -> Option<Vec<u8>> {
+        let logger = Logger::root(slog::Discard, o!());
+        let id = SubgraphDeploymentId::new(id).unwrap();
+        futures::executor::block_on(fetch(&logger, store, resolver, &id, &artifact)).unwrap()
+    }
+
+    #[test]
+    fn reads_abis_from_their_links() {
+        let store = TestStore::new();
+        let mut resolver = TestResolver::default();
+        resolver
+            .files
+            .insert("/ipfs/QmTokenAbi".to_owned(), ABI.as_bytes().to_vec());
+
+        assert_eq!(
+            Some(ABI.as_bytes().to_vec()),
+            fetch_artifact(
+                &store,
+                &resolver,
+                "QmArtifacts",
+                Artifact::Abi("Token".to_owned())
+            )
+        );
+        assert_eq!(
+            None,
+            fetch_artifact(
+                &store,
+                &resolver,
+                "QmArtifacts",
+                Artifact::Abi("Other".to_owned())
+            )
+        );
+        assert_eq!(
+            vec!["/ipfs/QmTokenAbi".to_owned()],
+            *resolver.requested.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_deployments_have_no_artifacts() {
+        let store = TestStore::new();
+        let resolver = TestResolver::default();
+
+        for artifact in vec![
+            Artifact::Manifest,
+            Artifact::Schema,
+            Artifact::Abi("Token".to_owned()),
+        ] {
+            assert_eq!(
+                None,
+                fetch_artifact(&store, &resolver, "QmUnknown", artifact)
+            );
+        }
+        assert!(resolver.requested.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+//! Endpoints of the index node server that are only open to clients that
+//! send the token from an environment variable as `Authorization: Bearer
+//! <token>`. An endpoint is disabled if its variable is not set
+
+use http::header;
+use hyper::{Body, Request};
+use std::env;
+
+use graph::prelude::*;
+use graph::util::security::{self, constant_time_eq};
+
+lazy_static! {
+    static ref EXPORT_TOKEN: Option<String> = token("GRAPH_INDEX_NODE_EXPORT_TOKEN");
+    static ref EXPLAIN_TOKEN: Option<String> = token("GRAPH_INDEX_NODE_EXPLAIN_TOKEN");
+}
+
+fn token(var: &str) -> Option<String> {
+    env::var(var).ok().filter(|token| !token.is_empty())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Restricted {
+    /// Exporting entities as CSV
+    Export,
+    /// Explaining the SQL for GraphQL queries
+    Explain,
+}
+
+impl Restricted {
+    fn token(self) -> Option<&'static str> {
+        match self {
+            Restricted::Export => EXPORT_TOKEN.as_deref(),
+            Restricted::Explain => EXPLAIN_TOKEN.as_deref(),
+        }
+    }
+
+    /// Whether the endpoint is enabled at all
+    pub fn is_enabled(self) -> bool {
+        self.token().is_some()
+    }
+
+    /// Whether a request with the bearer token `token` may use the endpoint
+    pub fn is_authorized(self, token: Option<&str>) -> bool {
+        match (self.token(), token) {
+            (Some(expected), Some(token)) => {
+                constant_time_eq(expected.as_bytes(), token.as_bytes())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The token in the `Authorization: Bearer <token>` header of `req`
+pub fn bearer_token(req: &Request<Body>) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(security::bearer_token)
+}
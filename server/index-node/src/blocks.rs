@@ -0,0 +1,91 @@
+use graph::prelude::web3::types::H256;
+use graph::prelude::*;
+
+/// Which blocks of a chain to look up in its chain store
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockLookup {
+    /// The block the chain store considers the head of the chain
+    Head,
+    /// All blocks with this number. There can be more than one if blocks
+    /// that were reorged away are still in the store
+    Number(u64),
+    /// The block with this hash
+    Hash(H256),
+}
+
+impl BlockLookup {
+    /// The lookup for the path segments after `/chains/<name>/`, i.e.,
+    /// `head`, `blocks/number/<number>`, or `blocks/hash/<hash>`
+    pub fn parse(segments: &[&str]) -> Option<Self> {
+        match segments {
+            ["head"] => Some(BlockLookup::Head),
+            ["blocks", "number", number] => number.parse().ok().map(BlockLookup::Number),
+            ["blocks", "hash", hash] => hash
+                .trim_start_matches("0x")
+                .parse()
+                .ok()
+                .map(BlockLookup::Hash),
+            _ => None,
+        }
+    }
+}
+
+fn block_json(block: &LightEthereumBlock) -> serde_json::Value {
+    serde_json::json!({
+        "hash": block.hash,
+        "number": block.number.map(|number| number.as_u64()),
+        "parentHash": block.parent_hash,
+    })
+}
+
+/// Look up blocks in `chain_store`. The head is returned as a block
+/// pointer, a block by hash as an object with its hash, number and parent
+/// hash, and the blocks with a number as a list of such objects. Returns
+/// `None` if the chain store does not have the head or the block
+pub fn lookup<C: ChainStore>(
+    chain_store: &C,
+    lookup: &BlockLookup,
+) -> Result<Option<serde_json::Value>, Error> {
+    match lookup {
+        BlockLookup::Head => Ok(chain_store.chain_head_ptr()?.map(|head| {
+            serde_json::json!({
+                "hash": head.hash,
+                "number": head.number,
+            })
+        })),
+        BlockLookup::Number(number) => {
+            let hashes = chain_store.block_hashes_by_block_number(*number)?;
+            let mut blocks = chain_store.blocks(hashes)?;
+            blocks.sort_by_key(|block| block.hash);
+            Ok(Some(serde_json::Value::Array(
+                blocks.iter().map(block_json).collect(),
+            )))
+        }
+        BlockLookup::Hash(hash) => Ok(chain_store.blocks(vec![*hash])?.first().map(block_json)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lookups() {
+        assert_eq!(Some(BlockLookup::Head), BlockLookup::parse(&["head"]));
+        assert_eq!(
+            Some(BlockLookup::Number(10)),
+            BlockLookup::parse(&["blocks", "number", "10"])
+        );
+        assert_eq!(
+            Some(BlockLookup::Hash(H256::from_low_u64_be(1))),
+            BlockLookup::parse(&[
+                "blocks",
+                "hash",
+                "0x0000000000000000000000000000000000000000000000000000000000000001"
+            ])
+        );
+        assert_eq!(None, BlockLookup::parse(&["blocks", "number", "-1"]));
+        assert_eq!(None, BlockLookup::parse(&["blocks", "hash", "0x01"]));
+        assert_eq!(None, BlockLookup::parse(&["blocks"]));
+    }
+}
@@ -0,0 +1,69 @@
+//! Show developers the SQL that a GraphQL query against a deployment leads
+//! to, together with the plan Postgres would use for it, without running
+//! the SQL
+
+use graph::data::query::plans::{self, SqlExplanation};
+use graph::prelude::*;
+
+/// Run `query` with the store explaining its SQL statements instead of
+/// running them. Since those statements return no entities, only the
+/// statements for the toplevel fields of the query, and for nested fields
+/// that do not depend on the entities of their parent, are explained
+pub fn explain<Q: GraphQlRunner>(graphql_runner: &Q, query: Query) -> serde_json::Value {
+    let deployment = query.schema.id.clone();
+    // The statements are only explained while `explain` runs, and
+    // therefore we wait for the result right here
+    let (result, statements) =
+        plans::explain(&deployment, || graphql_runner.run_query(query).wait());
+    let errors = match result {
+        Ok(result) => result.errors.unwrap_or_default(),
+        Err(e) => vec![e],
+    };
+    explanation_json(statements, errors)
+}
+
+/// The JSON for the response to an explain request
+fn explanation_json(statements: Vec<SqlExplanation>, errors: Vec<QueryError>) -> serde_json::Value {
+    let statements: Vec<_> = statements
+        .into_iter()
+        .map(|statement| {
+            serde_json::json!({
+                "sql": statement.sql,
+                "plan": statement.plan,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "statements": statements,
+        "errors": errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_explanations() {
+        let statement = SqlExplanation {
+            sql: "select 1".to_owned(),
+            plan: "Result  (cost=0.00..0.01 rows=1 width=4)".to_owned(),
+        };
+        assert_eq!(
+            serde_json::json!({
+                "statements": [{
+                    "sql": "select 1",
+                    "plan": "Result  (cost=0.00..0.01 rows=1 width=4)",
+                }],
+                "errors": [],
+            }),
+            explanation_json(vec![statement], vec![])
+        );
+
+        let json = explanation_json(vec![], vec![QueryError::from(QueryExecutionError::Timeout)]);
+        assert_eq!(
+            Some(1),
+            json["errors"].as_array().map(|errors| errors.len())
+        );
+    }
+}
@@ -0,0 +1,382 @@
+//! Bulk exports of the entities of one type of a deployment as CSV, for
+//! analysts who want all the data rather than paginating through it with
+//! GraphQL. The export reads the entities from the store a page at a time,
+//! ordered by id, and streams each page to the client as soon as it has
+//! been read
+
+use graphql_parser::{query as q, schema as s};
+use std::collections::HashMap;
+
+use graph::prelude::*;
+use graph::url::form_urlencoded;
+use graph_graphql::prelude::build_query;
+
+/// How many entities we read from the store at a time
+const EXPORT_PAGE_SIZE: i32 = 1000;
+
+/// The error type of the stream of an export, which is what `hyper` wants
+/// for streaming bodies
+pub type ExportStreamError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+        }
+    }
+}
+
+/// What to export, from the URL query of an export request
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportRequest {
+    pub format: ExportFormat,
+    /// The attributes to export, in this order; all attributes that are
+    /// stored if this is `None`
+    pub columns: Option<Vec<String>>,
+    /// Export the entities as of this block rather than the latest block
+    pub block: Option<BlockNumber>,
+    /// The `where` argument of a GraphQL query for the entities
+    pub filter: Option<q::Value>,
+}
+
+impl ExportRequest {
+    /// Parse the URL query `format=csv&columns=id,name&block=<N>&where=<filter>`.
+    /// All parameters are optional; `where` uses the syntax of GraphQL
+    /// `where` arguments, e.g. `{ name_starts_with: "a", count_gt: 3 }`
+    pub fn from_url_query(query: Option<&str>) -> Result<Self, Error> {
+        let mut request = ExportRequest {
+            format: ExportFormat::Csv,
+            columns: None,
+            block: None,
+            filter: None,
+        };
+        for (key, value) in form_urlencoded::parse(query.unwrap_or("").as_bytes()) {
+            match key.as_ref() {
+                "format" => {
+                    request.format = match value.as_ref() {
+                        "csv" => ExportFormat::Csv,
+                        "parquet" => {
+                            return Err(format_err!(
+                                "Parquet exports are not supported, use `format=csv`"
+                            ))
+                        }
+                        _ => return Err(format_err!("Invalid export format {:?}", value)),
+                    }
+                }
+                "columns" => {
+                    request.columns = Some(
+                        value
+                            .split(',')
+                            .map(|column| column.trim().to_owned())
+                            .filter(|column| !column.is_empty())
+                            .collect(),
+                    )
+                }
+                "block" => {
+                    request.block = Some(
+                        value
+                            .parse::<BlockNumber>()
+                            .ok()
+                            .filter(|block| *block >= 0)
+                            .ok_or_else(|| format_err!("Invalid block number {:?}", value))?,
+                    )
+                }
+                "where" => request.filter = Some(parse_filter(&value)?),
+                _ => return Err(format_err!("Unknown export parameter `{}`", key)),
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// Parse `filter` as the value of a GraphQL `where` argument
+fn parse_filter(filter: &str) -> Result<q::Value, Error> {
+    let document = graphql_parser::parse_query(&format!("{{ export(where: {}) }}", filter))
+        .map_err(|e| format_err!("Invalid filter {:?}: {}", filter, e))?;
+    let value = match document.definitions.as_slice() {
+        [q::Definition::Operation(q::OperationDefinition::SelectionSet(selection_set))] => {
+            match selection_set.items.as_slice() {
+                [q::Selection::Field(field)] => field.arguments.first().map(|(_, value)| value),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    match value {
+        Some(value @ q::Value::Object(_)) => Ok(value.clone()),
+        _ => Err(format_err!(
+            "Invalid filter {:?}, it must be an object like `{{ name: \"x\" }}`",
+            filter
+        )),
+    }
+}
+
+/// An export of the entities of one type
+#[derive(Clone, Debug)]
+pub struct Export {
+    /// The query for the first page of entities
+    query: EntityQuery,
+    columns: Vec<String>,
+}
+
+impl Export {
+    /// Prepare the export of the entities of type `entity_type` from the
+    /// deployment with `schema`. Returns `None` if the schema has no such
+    /// type. The filter of `request` is turned into a store filter just
+    /// like the `where` argument of GraphQL queries
+    pub fn new(
+        schema: &Schema,
+        entity_type: &str,
+        request: ExportRequest,
+    ) -> Result<Option<Self>, Error> {
+        let object_type =
+            match schema
+                .document
+                .definitions
+                .iter()
+                .find_map(|definition| match definition {
+                    s::Definition::TypeDefinition(s::TypeDefinition::Object(object_type))
+                        if object_type.name == entity_type =>
+                    {
+                        Some(object_type)
+                    }
+                    _ => None,
+                }) {
+                Some(object_type) => object_type,
+                None => return Ok(None),
+            };
+
+        // Derived fields are not stored with the entity
+        let stored: Vec<&str> = object_type
+            .fields
+            .iter()
+            .filter(|field| {
+                !field
+                    .directives
+                    .iter()
+                    .any(|directive| directive.name == "derivedFrom")
+            })
+            .map(|field| field.name.as_str())
+            .collect();
+        let columns = match request.columns {
+            Some(columns) => {
+                if let Some(column) = columns
+                    .iter()
+                    .find(|column| !stored.contains(&column.as_str()))
+                {
+                    return Err(format_err!(
+                        "`{}` is not an attribute of `{}` that can be exported",
+                        column,
+                        entity_type
+                    ));
+                }
+                columns
+            }
+            None => stored.iter().map(|column| column.to_string()).collect(),
+        };
+
+        let (where_arg, order_by, first) =
+            ("where".to_owned(), "orderBy".to_owned(), "first".to_owned());
+        let mut arguments = HashMap::new();
+        if let Some(filter) = request.filter {
+            arguments.insert(&where_arg, filter);
+        }
+        arguments.insert(&order_by, q::Value::Enum("id".to_owned()));
+        arguments.insert(&first, q::Value::Int(EXPORT_PAGE_SIZE.into()));
+        let query = build_query(
+            object_type,
+            request.block.unwrap_or(BLOCK_NUMBER_MAX),
+            &arguments,
+            &schema.types_for_interface,
+            EXPORT_PAGE_SIZE as u32,
+        )?;
+
+        Ok(Some(Export { query, columns }))
+    }
+
+    fn header(&self) -> String {
+        csv_row(self.columns.iter().map(|column| csv_field(column)))
+    }
+
+    /// The CSV rows for the page of entities that follows the entity with
+    /// id `after`, and the id of the last entity if there might be more
+    fn page<S: Store>(
+        &self,
+        store: &S,
+        after: Option<&str>,
+    ) -> Result<(String, Option<String>), QueryExecutionError> {
+        let mut query = self.query.clone();
+        if let Some(after) = after {
+            let after = EntityFilter::GreaterThan("id".to_owned(), Value::String(after.to_owned()));
+            query.filter = Some(match query.filter {
+                Some(filter) => EntityFilter::And(vec![filter, after]),
+                None => after,
+            });
+        }
+
+        let entities = store.find(query)?;
+        let rows = entities
+            .iter()
+            .map(|entity| {
+                csv_row(
+                    self.columns
+                        .iter()
+                        .map(|column| csv_value(entity.get(column))),
+                )
+            })
+            .collect();
+        let last = if entities.len() == EXPORT_PAGE_SIZE as usize {
+            entities.last().and_then(|entity| entity.id().ok())
+        } else {
+            None
+        };
+        Ok((rows, last))
+    }
+
+    /// Stream the export as CSV, starting with a header row with the names
+    /// of the columns. Each page is read from `store` when the client is
+    /// ready for more data; if reading fails, the stream ends with an error
+    pub fn csv_stream<S: Store>(
+        self,
+        store: Arc<S>,
+    ) -> impl futures03::Stream<Item = Result<String, ExportStreamError>> + Send + 'static {
+        let header = self.header();
+        // The state is `None` once we have read the last page
+        let pages = futures03::stream::unfold(Some(None), move |after: Option<Option<String>>| {
+            let page = after.map(|after| {
+                tokio::task::block_in_place(|| self.page(store.as_ref(), after.as_deref()))
+            });
+            async move {
+                match page {
+                    None => None,
+                    Some(Ok((rows, last))) => Some((Ok(rows), last.map(Some))),
+                    Some(Err(e)) => Some((Err(ExportStreamError::from(e.to_string())), None)),
+                }
+            }
+        });
+        futures03::stream::once(futures03::future::ready(Ok(header))).chain(pages)
+    }
+}
+
+/// Quote `field` if it contains characters that are special in CSV
+fn csv_field(field: &str) -> String {
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// The CSV for the value of an attribute; missing and null values are
+/// empty, bytes are hex strings, and lists look like `[a, b]`
+fn csv_value(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(value) => csv_field(&value.to_string()),
+    }
+}
+
+fn csv_row(fields: impl Iterator<Item = String>) -> String {
+    let mut row = fields.collect::<Vec<_>>().join(",");
+    row.push('\n');
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_requests() {
+        assert_eq!(
+            ExportRequest {
+                format: ExportFormat::Csv,
+                columns: None,
+                block: None,
+                filter: None,
+            },
+            ExportRequest::from_url_query(None).unwrap()
+        );
+
+        let request = ExportRequest::from_url_query(Some(
+            "format=csv&columns=id,%20name&block=10&where=%7Bname_starts_with%3A%22a%22%7D",
+        ))
+        .unwrap();
+        assert_eq!(
+            Some(vec!["id".to_owned(), "name".to_owned()]),
+            request.columns
+        );
+        assert_eq!(Some(10), request.block);
+        assert!(match request.filter {
+            Some(q::Value::Object(object)) =>
+                object.get("name_starts_with") == Some(&q::Value::String("a".to_owned())),
+            _ => false,
+        });
+
+        assert!(ExportRequest::from_url_query(Some("format=parquet")).is_err());
+        assert!(ExportRequest::from_url_query(Some("block=-1")).is_err());
+        assert!(ExportRequest::from_url_query(Some("where=name")).is_err());
+        assert!(ExportRequest::from_url_query(Some("where={a:1})%20b(c:{d:1}")).is_err());
+        assert!(ExportRequest::from_url_query(Some("limit=10")).is_err());
+    }
+
+    #[test]
+    fn prepares_exports() {
+        let schema = Schema::parse(
+            "type Thing @entity { id: ID!, name: String!, count: Int, \
+             parts: [Part!]! @derivedFrom(field: \"thing\") } \
+             type Part @entity { id: ID!, thing: Thing! }",
+            SubgraphDeploymentId::new("QmExport").unwrap(),
+        )
+        .unwrap();
+        let request = |query: &str| ExportRequest::from_url_query(Some(query)).unwrap();
+
+        let export = Export::new(&schema, "Thing", request("block=5&where={count_gt:3}"))
+            .unwrap()
+            .unwrap();
+        assert_eq!("id,name,count\n", export.header());
+        assert_eq!(5, export.query.block);
+        assert_eq!(Some(EXPORT_PAGE_SIZE as u32), export.query.range.first);
+        assert_eq!(
+            Some(EntityFilter::And(vec![EntityFilter::GreaterThan(
+                "count".to_owned(),
+                Value::Int(3)
+            )])),
+            export.query.filter
+        );
+
+        let export = Export::new(&schema, "Thing", request("columns=count,id"))
+            .unwrap()
+            .unwrap();
+        assert_eq!("count,id\n", export.header());
+
+        assert!(Export::new(&schema, "Other", request(""))
+            .unwrap()
+            .is_none());
+        assert!(Export::new(&schema, "Thing", request("columns=parts")).is_err());
+        assert!(Export::new(&schema, "Thing", request("where={size:3}")).is_err());
+    }
+
+    #[test]
+    fn writes_csv() {
+        assert_eq!("plain", csv_field("plain"));
+        assert_eq!("\"a,b\"", csv_field("a,b"));
+        assert_eq!("\"say \"\"hi\"\"\"", csv_field("say \"hi\""));
+        assert_eq!("\"two\nlines\"", csv_field("two\nlines"));
+        assert_eq!("", csv_value(None));
+        assert_eq!("", csv_value(Some(&Value::Null)));
+        assert_eq!(
+            "\"[1, 2]\"",
+            csv_value(Some(&Value::List(vec![Value::Int(1), Value::Int(2)])))
+        );
+        assert_eq!(
+            "a,,c\n",
+            csv_row(vec!["a".to_owned(), String::new(), "c".to_owned()].into_iter())
+        );
+    }
+}
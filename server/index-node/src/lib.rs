@@ -1,3 +1,8 @@
+mod artifacts;
+mod auth;
+mod blocks;
+mod explain;
+mod export;
 mod request;
 mod resolver;
 mod response;
@@ -5,6 +10,7 @@ mod schema;
 mod server;
 mod service;
 
+pub use self::artifacts::Artifact;
 pub use self::request::IndexNodeRequest;
 pub use self::response::IndexNodeResponse;
 pub use self::server::IndexNodeServer;
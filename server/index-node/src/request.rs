@@ -65,7 +65,18 @@ impl Future for IndexNodeRequest {
             )),
         }?;
 
-        Ok(Async::Ready(Query::new(schema, document, variables)))
+        // Parse the "operationName" field of the JSON body, if present
+        let operation_name = match obj.get("operationName") {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(serde_json::Value::String(name)) => Ok(Some(name.clone())),
+            _ => Err(GraphQLServerError::ClientError(
+                "The \"operationName\" field is not a string".to_string(),
+            )),
+        }?;
+
+        Ok(Async::Ready(
+            Query::new(schema, document, variables).with_operation_name(operation_name),
+        ))
     }
 }
 
@@ -1,17 +1,27 @@
 use graphql_parser::{query as q, schema as s};
 use std::collections::HashMap;
 
+use graph::components::subgraph::write_audit;
 use graph::data::graphql::{TryFromValue, ValueList, ValueMap};
+use graph::data::query::{blocklist, field_usage, plans};
 use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth, SUBGRAPHS_ID};
+use graph::data::subgraph::SubgraphFeature;
 use graph::prelude::*;
+use graph::util::env::env_var;
 use graph_graphql::prelude::{object, ExecutionContext, IntoValue, ObjectOrInterface, Resolver};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use web3::types::{Address, H256};
 
+/// The sync speed of a deployment is measured over this many seconds
+const SYNC_SPEED_WINDOW: u64 = 600;
+
 static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
     fragment deploymentStatus on SubgraphDeploymentDetail {
         id
         synced
+        maintenance
         health
         fatalError {
             subgraphId
@@ -19,6 +29,9 @@ static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
             blockNumber
             blockHash
             handler
+            dataSource
+            deterministic
+            proofOfIndexing
         }
         nonFatalErrors(first: 1000, orderBy: blockNumber) {
             subgraphId
@@ -26,6 +39,9 @@ static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
             blockNumber
             blockHash
             handler
+            dataSource
+            deterministic
+            proofOfIndexing
         }
         ethereumHeadBlockNumber
         ethereumHeadBlockHash
@@ -34,6 +50,7 @@ static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
         latestEthereumBlockHash
         latestEthereumBlockNumber
         manifest {
+            features
             dataSources(first: 1) {
                 network
             }
@@ -41,11 +58,48 @@ static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
     }
   "#;
 
+/// The default and the maximum value of `first` in `dynamicDataSources`
+const DYNAMIC_DATA_SOURCES_FIRST: u64 = 100;
+const DYNAMIC_DATA_SOURCES_MAX_FIRST: u64 = 1000;
+
+lazy_static! {
+    /// How many deployments a single `publicProofsOfIndexing` query may
+    /// ask for
+    static ref MAX_PUBLIC_POIS: usize =
+        env_var("GRAPH_INDEX_NODE_MAX_PUBLIC_POIS").unwrap_or(10);
+
+    /// How many `publicProofsOfIndexing` queries the node answers per
+    /// second, across all clients
+    static ref PUBLIC_POI_RATE_LIMIT: f64 =
+        env_var("GRAPH_INDEX_NODE_PUBLIC_POI_RATE_LIMIT").unwrap_or(10.0);
+
+    /// The queries that can still be answered right now, and when they
+    /// were last refilled
+    static ref PUBLIC_POI_TOKENS: Mutex<(f64, Instant)> =
+        Mutex::new((*PUBLIC_POI_RATE_LIMIT, Instant::now()));
+}
+
+/// Take one of the tokens that `PUBLIC_POI_RATE_LIMIT` refills every
+/// second; `false` if there are none left
+fn take_public_poi_token() -> bool {
+    let mut tokens = PUBLIC_POI_TOKENS.lock().unwrap();
+    let now = Instant::now();
+    let refill = now.duration_since(tokens.1).as_secs_f64() * *PUBLIC_POI_RATE_LIMIT;
+    *tokens = ((tokens.0 + refill).min(PUBLIC_POI_RATE_LIMIT.max(1.0)), now);
+    if tokens.0 >= 1.0 {
+        tokens.0 -= 1.0;
+        true
+    } else {
+        false
+    }
+}
+
 /// Resolver for the index node GraphQL API.
-pub struct IndexNodeResolver<R, S> {
+pub struct IndexNodeResolver<R, S, C> {
     logger: Logger,
     graphql_runner: Arc<R>,
     store: Arc<S>,
+    chain_stores: Arc<HashMap<String, Arc<C>>>,
 }
 
 /// The ID of a subgraph deployment assignment.
@@ -135,6 +189,12 @@ struct IndexingStatusWithoutNode {
 
     /// Indexing status on different chains involved in the subgraph's data sources.
     chains: Vec<ChainIndexingStatus>,
+
+    /// The features that the subgraph declares in its manifest.
+    features: Vec<String>,
+
+    /// Whether indexing is paused for maintenance.
+    maintenance: bool,
 }
 
 #[derive(Debug)]
@@ -151,8 +211,17 @@ struct IndexingStatus {
     /// Indexing status on different chains involved in the subgraph's data sources.
     chains: Vec<ChainIndexingStatus>,
 
+    /// The features that the subgraph declares in its manifest.
+    features: Vec<String>,
+
+    /// Whether indexing is paused for maintenance.
+    maintenance: bool,
+
     /// ID of the Graph Node that the subgraph is indexed by.
     node: String,
+
+    /// The blocks that the subgraph had processed recently, oldest first.
+    sync_samples: Vec<SyncSample>,
 }
 
 impl IndexingStatusWithoutNode {
@@ -165,7 +234,10 @@ impl IndexingStatusWithoutNode {
             fatal_error: self.fatal_error,
             non_fatal_errors: self.non_fatal_errors,
             chains: self.chains,
+            features: self.features,
+            maintenance: self.maintenance,
             node,
+            sync_samples: vec![],
         }
     }
 
@@ -211,6 +283,10 @@ impl TryFromValue for IndexingStatusWithoutNode {
                 earliest_block: Self::block_from_value(value, "earliestEthereumBlock")?,
                 latest_block: Self::block_from_value(value, "latestEthereumBlock")?,
             })],
+            features: value
+                .get_required::<q::Value>("manifest")?
+                .get_required("features")?,
+            maintenance: value.get_required("maintenance")?,
         })
     }
 }
@@ -225,6 +301,9 @@ impl From<IndexingStatus> for q::Value {
             node,
             non_fatal_errors,
             synced,
+            features,
+            maintenance,
+            sync_samples,
         } = status;
 
         fn subgraph_error_to_value(subgraph_error: SubgraphError) -> q::Value {
@@ -233,6 +312,9 @@ impl From<IndexingStatus> for q::Value {
                 message,
                 block_ptr,
                 handler,
+                data_source,
+                deterministic,
+                proof_of_indexing,
             } = subgraph_error;
 
             object! {
@@ -240,6 +322,10 @@ impl From<IndexingStatus> for q::Value {
                 subgraphId: subgraph_id.to_string(),
                 message: message,
                 handler: handler,
+                dataSource: data_source,
+                deterministic: deterministic,
+                proofOfIndexing: proof_of_indexing
+                    .map(|poi| q::Value::from(Value::Bytes(poi.as_ref().into()))),
                 block: object! {
                     __typename: "Block",
                     number: block_ptr.map(|x| x.number),
@@ -254,6 +340,39 @@ impl From<IndexingStatus> for q::Value {
             .collect();
         let fatal_error_val = fatal_error.map_or(q::Value::Null, subgraph_error_to_value);
 
+        let blocks_per_second = sync_speed(&sync_samples, now());
+        let estimated_seconds_to_sync = if synced {
+            Some(0)
+        } else {
+            chains.iter().find_map(|chain| match chain {
+                ChainIndexingStatus::Ethereum(chain) => {
+                    let head = chain.chain_head_block.as_ref()?.0.number;
+                    let latest = chain
+                        .latest_block
+                        .as_ref()
+                        .map_or(0, |block| block.0.number);
+                    let speed = blocks_per_second.filter(|speed| *speed > 0.0)?;
+                    Some((head.saturating_sub(latest) as f64 / speed).ceil() as u64)
+                }
+            })
+        };
+        let sync_samples: Vec<q::Value> = sync_samples
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| {
+                let blocks_per_second = match i {
+                    0 => None,
+                    _ => speed_between(&sync_samples[i - 1], sample),
+                };
+                object! {
+                    __typename: "SyncSample",
+                    timestamp: format!("{}", sample.timestamp),
+                    blockNumber: format!("{}", sample.block_number),
+                    blocksPerSecond: blocks_per_second,
+                }
+            })
+            .collect();
+
         object! {
             __typename: "SubgraphIndexingStatus",
             subgraph: subgraph,
@@ -263,10 +382,43 @@ impl From<IndexingStatus> for q::Value {
             nonFatalErrors: non_fatal_errors,
             chains: chains.into_iter().map(q::Value::from).collect::<Vec<_>>(),
             node: node,
+            features: features,
+            maintenance: maintenance,
+            blocksPerSecond: blocks_per_second,
+            estimatedSecondsToSync: estimated_seconds_to_sync.map(|secs| format!("{}", secs)),
+            syncSamples: sync_samples,
         }
     }
 }
 
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// How many blocks per second were processed between `from` and `to`
+fn speed_between(from: &SyncSample, to: &SyncSample) -> Option<f64> {
+    if to.timestamp <= from.timestamp {
+        return None;
+    }
+    let blocks = to.block_number.saturating_sub(from.block_number);
+    Some(blocks as f64 / (to.timestamp - from.timestamp) as f64)
+}
+
+/// How many blocks per second the subgraph processed over the last
+/// `SYNC_SPEED_WINDOW` seconds before `now`, according to `samples`
+fn sync_speed(samples: &[SyncSample], now: u64) -> Option<f64> {
+    let mut recent = samples
+        .iter()
+        .filter(|sample| sample.timestamp + SYNC_SPEED_WINDOW >= now);
+    let first = recent.next()?;
+    let last = recent.last()?;
+    speed_between(first, last)
+}
+
 struct IndexingStatuses(Vec<IndexingStatus>);
 
 impl From<q::Value> for IndexingStatuses {
@@ -297,23 +449,50 @@ impl From<q::Value> for IndexingStatuses {
     }
 }
 
+impl IndexingStatuses {
+    /// Add the sync samples from `store` to the statuses; statuses whose
+    /// samples can not be loaded get no estimate
+    fn with_sync_samples(mut self, logger: &Logger, store: &impl Store) -> Self {
+        for status in &mut self.0 {
+            let id = match SubgraphDeploymentId::new(status.subgraph.clone()) {
+                Ok(id) => id,
+                Err(()) => continue,
+            };
+            match store.sync_samples(&id) {
+                Ok(samples) => status.sync_samples = samples,
+                Err(e) => warn!(logger, "Failed to load sync samples";
+                                "subgraph" => &status.subgraph,
+                                "error" => e.to_string()),
+            }
+        }
+        self
+    }
+}
+
 impl From<IndexingStatuses> for q::Value {
     fn from(statuses: IndexingStatuses) -> Self {
         q::Value::List(statuses.0.into_iter().map(q::Value::from).collect())
     }
 }
 
-impl<R, S> IndexNodeResolver<R, S>
+impl<R, S, C> IndexNodeResolver<R, S, C>
 where
     R: GraphQlRunner,
     S: Store + SubgraphDeploymentStore,
+    C: ChainStore + EthereumCallCache,
 {
-    pub fn new(logger: &Logger, graphql_runner: Arc<R>, store: Arc<S>) -> Self {
+    pub fn new(
+        logger: &Logger,
+        graphql_runner: Arc<R>,
+        store: Arc<S>,
+        chain_stores: Arc<HashMap<String, Arc<C>>>,
+    ) -> Self {
         let logger = logger.new(o!("component" => "IndexNodeResolver"));
         Self {
             logger,
             graphql_runner,
             store,
+            chain_stores,
         }
     }
 
@@ -393,7 +572,9 @@ where
             }
         };
 
-        Ok(IndexingStatuses::from(data).into())
+        Ok(IndexingStatuses::from(data)
+            .with_sync_samples(&self.logger, &*self.store)
+            .into())
     }
 
     fn resolve_indexing_statuses_for_subgraph_name(
@@ -508,7 +689,9 @@ where
                     .expect("missing deployment assignments"),
         };
 
-        Ok(IndexingStatuses::from(transformed_data).into())
+        Ok(IndexingStatuses::from(transformed_data)
+            .with_sync_samples(&self.logger, &*self.store)
+            .into())
     }
 
     fn resolve_proof_of_indexing(
@@ -549,6 +732,443 @@ where
         Ok(poi)
     }
 
+    fn resolve_public_proofs_of_indexing(
+        &self,
+        argument_values: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let deployments = argument_values
+            .get_required::<Vec<String>>("deployments")
+            .expect("Valid deployments required");
+        let block_number = argument_values
+            .get_required::<u64>("blockNumber")
+            .expect("Valid blockNumber required");
+
+        if deployments.len() > *MAX_PUBLIC_POIS {
+            return Err(QueryExecutionError::LimitExceeded(format!(
+                "publicProofsOfIndexing can ask for at most {} deployments at once, but {} \
+                 were requested",
+                *MAX_PUBLIC_POIS,
+                deployments.len()
+            )));
+        }
+        if !take_public_poi_token() {
+            return Err(QueryExecutionError::LimitExceeded(
+                "too many publicProofsOfIndexing queries, try again later".to_owned(),
+            ));
+        }
+
+        let results = deployments
+            .into_iter()
+            .map(|deployment| {
+                let (block, poi) = match SubgraphDeploymentId::new(deployment.clone()) {
+                    Ok(id) => self.public_proof_of_indexing(&id, block_number),
+                    Err(()) => (None, None),
+                };
+                object! {
+                    __typename: "PublicProofOfIndexingResult",
+                    deployment: deployment,
+                    block: block.map(EthereumBlock),
+                    proofOfIndexing: poi.map(|poi| format!("0x{}", hex::encode(&poi))),
+                }
+            })
+            .collect();
+        Ok(q::Value::List(results))
+    }
+
+    /// The block with `block_number` on the network of `deployment` and the
+    /// proof of indexing of the deployment at that block without an
+    /// indexer. Errors are logged and lead to `None`
+    fn public_proof_of_indexing(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        block_number: u64,
+    ) -> (Option<EthereumBlockPointer>, Option<[u8; 32]>) {
+        let block = self.store.network_name(deployment).and_then(|network| {
+            let chain_store = match network.and_then(|n| self.chain_stores.get(&n)) {
+                Some(chain_store) => chain_store,
+                None => return Ok(None),
+            };
+            // Blocks that were reorged away may still be in the store;
+            // with several candidates, it is not clear which one the
+            // deployment processed
+            let mut hashes = chain_store.block_hashes_by_block_number(block_number)?;
+            Ok(match hashes.len() {
+                1 => hashes.pop().map(|hash| EthereumBlockPointer {
+                    hash,
+                    number: block_number,
+                }),
+                _ => None,
+            })
+        });
+        let block = match block {
+            Ok(Some(block)) => block,
+            Ok(None) => return (None, None),
+            Err(e) => {
+                error!(
+                    self.logger,
+                    "Failed to look up block for public proof of indexing";
+                    "subgraph" => deployment,
+                    "error" => format!("{:?}", e)
+                );
+                return (None, None);
+            }
+        };
+
+        let poi_fut = self
+            .store
+            .get_proof_of_indexing(deployment, &None, block.hash);
+        match futures::executor::block_on(poi_fut) {
+            Ok(poi) => (Some(block), poi),
+            Err(e) => {
+                error!(
+                    self.logger,
+                    "Failed to query public proof of indexing";
+                    "subgraph" => deployment,
+                    "error" => format!("{:?}", e)
+                );
+                (Some(block), None)
+            }
+        }
+    }
+
+    /// The chain store of the network that `subgraph` indexes, and the
+    /// block `blockHash` from the arguments
+    fn chain_store_and_block(
+        &self,
+        argument_values: &HashMap<&q::Name, q::Value>,
+    ) -> Result<Option<(Arc<C>, H256)>, Error> {
+        let deployment_id = argument_values
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .expect("Valid subgraphId required");
+        let block_hash = argument_values
+            .get_required::<H256>("blockHash")
+            .expect("Valid blockHash required");
+
+        let network = match self.store.network_name(&deployment_id)? {
+            Some(network) => network,
+            None => return Ok(None),
+        };
+        Ok(self
+            .chain_stores
+            .get(&network)
+            .map(|chain_store| (chain_store.clone(), block_hash)))
+    }
+
+    fn resolve_block_data(
+        &self,
+        argument_values: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let block_data = self
+            .chain_store_and_block(argument_values)
+            .and_then(|store_and_block| match store_and_block {
+                Some((chain_store, block_hash)) => chain_store.block_data(block_hash),
+                None => Ok(None),
+            });
+        match block_data {
+            Ok(Some(data)) => Ok(json_to_value(data)),
+            Ok(None) => Ok(q::Value::Null),
+            Err(e) => {
+                error!(
+                    self.logger,
+                    "Failed to query block data";
+                    "error" => format!("{:?}", e)
+                );
+                Ok(q::Value::Null)
+            }
+        }
+    }
+
+    fn resolve_query_plans(
+        &self,
+        argument_values: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let query_hash = argument_values
+            .get_optional::<String>("queryHash")
+            .expect("Invalid queryHash");
+
+        let plans = match query_hash {
+            Some(query_hash) => {
+                let hash = blocklist::parse_hash(&query_hash).map_err(|e| {
+                    QueryExecutionError::ValueParseError("queryHash".to_owned(), e.to_string())
+                })?;
+                plans::get(hash)
+                    .map(|plan| (hash, plan))
+                    .into_iter()
+                    .collect()
+            }
+            None => plans::list(),
+        };
+        Ok(q::Value::List(
+            plans
+                .into_iter()
+                .map(|(hash, plan)| {
+                    object! {
+                        __typename: "QueryPlan",
+                        queryHash: blocklist::format_hash(hash),
+                        subgraph: plan.subgraph,
+                        sql: plan.sql,
+                        plan: plan.plan,
+                        durationMs: format!("{}", plan.duration.as_millis()),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_field_usage(
+        &self,
+        argument_values: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let deployment_id = argument_values
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .expect("Valid subgraph required");
+
+        Ok(q::Value::List(
+            field_usage::usage(deployment_id.as_str())
+                .into_iter()
+                .map(|usage| {
+                    object! {
+                        __typename: "FieldUsage",
+                        typeName: usage.type_name,
+                        field: usage.field,
+                        count: format!("{}", usage.count),
+                        lastUsed: format!("{}", usage.last_used),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_write_audit(
+        &self,
+        argument_values: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let deployment_id = argument_values
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .expect("Valid subgraph required");
+        let block_number = argument_values
+            .get_optional::<BigInt>("blockNumber")
+            .expect("Invalid blockNumber")
+            .map(|number| number.to_u64());
+
+        Ok(q::Value::List(
+            write_audit::blocks(&deployment_id)
+                .into_iter()
+                .filter(|audit| block_number.map_or(true, |number| audit.block.number == number))
+                .map(|audit| {
+                    let handlers: Vec<q::Value> = audit
+                        .handlers
+                        .into_iter()
+                        .map(|handler| {
+                            object! {
+                                __typename: "HandlerWriteAudit",
+                                dataSource: handler.data_source,
+                                handler: handler.handler,
+                                invocations: format!("{}", handler.invocations),
+                                entitySets: format!("{}", handler.entity_sets),
+                                entityRemoves: format!("{}", handler.entity_removes),
+                                ethCalls: format!("{}", handler.eth_calls),
+                            }
+                        })
+                        .collect();
+                    object! {
+                        __typename: "BlockWriteAudit",
+                        block: object! {
+                            __typename: "Block",
+                            hash: audit.block.hash_hex(),
+                            number: format!("{}", audit.block.number),
+                        },
+                        handlers: handlers,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_cached_ethereum_calls(
+        &self,
+        argument_values: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let calls = self
+            .chain_store_and_block(argument_values)
+            .and_then(|store_and_block| match store_and_block {
+                Some((chain_store, block_hash)) => {
+                    match chain_store.blocks(vec![block_hash])?.pop() {
+                        Some(block) => chain_store
+                            .get_calls_in_block(EthereumBlockPointer::from(&block))
+                            .map(Some),
+                        None => Ok(None),
+                    }
+                }
+                None => Ok(None),
+            });
+        match calls {
+            Ok(Some(calls)) => Ok(q::Value::List(
+                calls
+                    .into_iter()
+                    .map(|call| {
+                        object! {
+                            __typename: "CachedEthereumCall",
+                            idHash: format!("0x{}", hex::encode(&call.blake3_id)),
+                            block: EthereumBlock(call.block_ptr),
+                            contractAddress: format!("0x{}", hex::encode(call.contract_address)),
+                            returnValue: format!("0x{}", hex::encode(&call.return_value)),
+                        }
+                    })
+                    .collect(),
+            )),
+            Ok(None) => Ok(q::Value::Null),
+            Err(e) => {
+                error!(
+                    self.logger,
+                    "Failed to query cached Ethereum calls";
+                    "error" => format!("{:?}", e)
+                );
+                Ok(q::Value::Null)
+            }
+        }
+    }
+
+    fn resolve_dynamic_data_sources(
+        &self,
+        argument_values: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let deployment_id = argument_values
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .expect("Valid subgraph required");
+        let first = argument_values
+            .get_optional::<u64>("first")
+            .expect("Invalid first")
+            .unwrap_or(DYNAMIC_DATA_SOURCES_FIRST);
+        let skip = argument_values
+            .get_optional::<u64>("skip")
+            .expect("Invalid skip")
+            .unwrap_or(0);
+        let mut invalid = vec![];
+        if first < 1 || first > DYNAMIC_DATA_SOURCES_MAX_FIRST {
+            invalid.push("first");
+        }
+        // Negative values wrap around when they are read as `u64`
+        if skip > std::i32::MAX as u64 {
+            invalid.push("skip");
+        }
+        if !invalid.is_empty() {
+            return Err(QueryExecutionError::RangeArgumentsError(
+                invalid,
+                DYNAMIC_DATA_SOURCES_MAX_FIRST as u32,
+            ));
+        }
+
+        let query = Query::new(
+            self.store
+                .api_schema(&SUBGRAPHS_ID)
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?,
+            q::parse_query(
+                r#"
+                query dynamicDataSources($id: ID!, $first: Int!, $skip: Int!) {
+                  subgraphDeployment(id: $id) {
+                    dynamicDataSources(orderBy: ethereumBlockNumber, first: $first, skip: $skip) {
+                      name
+                      network
+                      context
+                      ethereumBlockHash
+                      ethereumBlockNumber
+                      source { address }
+                    }
+                  }
+                }
+                "#,
+            )
+            .unwrap(),
+            Some(QueryVariables::new(HashMap::from_iter(
+                vec![
+                    ("id".into(), q::Value::String(deployment_id.to_string())),
+                    ("first".into(), q::Value::Int((first as i32).into())),
+                    ("skip".into(), q::Value::Int((skip as i32).into())),
+                ]
+                .into_iter(),
+            ))),
+        );
+
+        let result = self
+            .graphql_runner
+            .run_query_with_complexity(query, None, None, Some(std::u32::MAX))
+            .wait()
+            .expect("error querying dynamic data sources");
+
+        let data_sources = match result.data {
+            Some(data) => data
+                .get_optional::<q::Value>("subgraphDeployment")
+                .expect("invalid subgraphDeployment")
+                .and_then(|deployment| {
+                    deployment
+                        .get_optional::<q::Value>("dynamicDataSources")
+                        .expect("invalid dynamicDataSources")
+                })
+                .map(|data_sources| {
+                    data_sources
+                        .get_values::<q::Value>()
+                        .expect("invalid dynamic data sources")
+                })
+                .unwrap_or_default(),
+            None => {
+                error!(
+                    self.logger,
+                    "Failed to query dynamic data sources";
+                    "subgraph" => deployment_id.to_string(),
+                    "errors" => format!("{:?}", result.errors)
+                );
+                return Ok(q::Value::List(vec![]));
+            }
+        };
+
+        Ok(q::Value::List(
+            data_sources
+                .into_iter()
+                .map(|data_source| {
+                    let field = |name: &str| {
+                        data_source
+                            .get_optional::<q::Value>(name)
+                            .expect("invalid dynamic data source")
+                            .unwrap_or(q::Value::Null)
+                    };
+                    let address = field("source")
+                        .get_optional::<q::Value>("address")
+                        .expect("invalid dynamic data source source")
+                        .unwrap_or(q::Value::Null);
+                    object! {
+                        __typename: "DynamicDataSource",
+                        name: field("name"),
+                        address: address,
+                        network: field("network"),
+                        createdAtBlock: object! {
+                            __typename: "Block",
+                            hash: field("ethereumBlockHash"),
+                            number: field("ethereumBlockNumber"),
+                        },
+                        context: field("context"),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_version(&self) -> Result<q::Value, QueryExecutionError> {
+        Ok(object! {
+            __typename: "Version",
+            version: env!("CARGO_PKG_VERSION"),
+            commit: env!("GRAPH_NODE_COMMIT"),
+            apiVersions: ApiVersion::ALL
+                .iter()
+                .map(ApiVersion::as_str)
+                .collect::<Vec<_>>(),
+            supportedFeatures: SubgraphFeature::ALL
+                .iter()
+                .map(SubgraphFeature::as_str)
+                .collect::<Vec<_>>(),
+        })
+    }
+
     fn resolve_indexing_statuses_for_version(
         &self,
         arguments: &HashMap<&q::Name, q::Value>,
@@ -668,6 +1288,7 @@ where
         );
 
         Ok(IndexingStatuses::from(transformed_data)
+            .with_sync_samples(&self.logger, &*self.store)
             .0
             .into_iter()
             .next()
@@ -676,24 +1297,49 @@ where
     }
 }
 
-impl<R, S> Clone for IndexNodeResolver<R, S>
+/// Convert the JSON `value` into an equivalent GraphQL value; numbers that
+/// do not fit into a GraphQL `Int` become floats
+fn json_to_value(value: serde_json::Value) -> q::Value {
+    match value {
+        serde_json::Value::Null => q::Value::Null,
+        serde_json::Value::Bool(b) => q::Value::Boolean(b),
+        serde_json::Value::Number(n) => match n.as_i64().and_then(|i| i32::try_from(i).ok()) {
+            Some(i) => q::Value::Int(q::Number::from(i)),
+            None => q::Value::Float(n.as_f64().unwrap_or(std::f64::NAN)),
+        },
+        serde_json::Value::String(s) => q::Value::String(s),
+        serde_json::Value::Array(values) => {
+            q::Value::List(values.into_iter().map(json_to_value).collect())
+        }
+        serde_json::Value::Object(map) => q::Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, json_to_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+impl<R, S, C> Clone for IndexNodeResolver<R, S, C>
 where
     R: GraphQlRunner,
     S: Store + SubgraphDeploymentStore,
+    C: ChainStore + EthereumCallCache,
 {
     fn clone(&self) -> Self {
         Self {
             logger: self.logger.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            chain_stores: self.chain_stores.clone(),
         }
     }
 }
 
-impl<R, S> Resolver for IndexNodeResolver<R, S>
+impl<R, S, C> Resolver for IndexNodeResolver<R, S, C>
 where
     R: GraphQlRunner,
     S: Store + SubgraphDeploymentStore,
+    C: ChainStore + EthereumCallCache,
 {
     fn prefetch(
         &self,
@@ -720,6 +1366,13 @@ where
             return self.resolve_proof_of_indexing(argument_values);
         }
 
+        if &parent_object_type.name == "Query"
+            && &field.name == "blockData"
+            && &scalar_type.name == "JSONObject"
+        {
+            return self.resolve_block_data(argument_values);
+        }
+
         // Fallback to the same as is in the default trait implementation. There
         // is no way to call back into the default implementation for the trait.
         // So, note that this is duplicated.
@@ -746,6 +1399,30 @@ where
                 self.resolve_indexing_statuses_for_subgraph_name(arguments)
             }
 
+            // The top-level `publicProofsOfIndexing` field
+            (None, "PublicProofOfIndexingResult", "publicProofsOfIndexing") => {
+                self.resolve_public_proofs_of_indexing(arguments)
+            }
+
+            // The top-level `cachedEthereumCalls` field
+            (None, "CachedEthereumCall", "cachedEthereumCalls") => {
+                self.resolve_cached_ethereum_calls(arguments)
+            }
+
+            // The top-level `queryPlans` field
+            (None, "QueryPlan", "queryPlans") => self.resolve_query_plans(arguments),
+
+            // The top-level `fieldUsage` field
+            (None, "FieldUsage", "fieldUsage") => self.resolve_field_usage(arguments),
+
+            // The top-level `writeAudit` field
+            (None, "BlockWriteAudit", "writeAudit") => self.resolve_write_audit(arguments),
+
+            // The top-level `dynamicDataSources` field
+            (None, "DynamicDataSource", "dynamicDataSources") => {
+                self.resolve_dynamic_data_sources(arguments)
+            }
+
             // Resolve fields of `Object` values (e.g. the `chains` field of `ChainIndexingStatus`)
             (value, _, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
@@ -770,8 +1447,54 @@ where
                 self.resolve_indexing_statuses_for_version(arguments, false)
             }
 
+            // The top-level `version` field
+            (None, "version") => self.resolve_version(),
+
             // Resolve fields of `Object` values (e.g. the `latestBlock` field of `EthereumBlock`)
             (value, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_json_to_values() {
+        let json = serde_json::json!({
+            "number": "0x10",
+            "size": 1234,
+            "difficulty": 123456789012u64,
+            "uncles": [],
+            "sealFields": null,
+        });
+        let value = json_to_value(json);
+        let expected = object! {
+            number: "0x10",
+            size: q::Value::Int(q::Number::from(1234)),
+            difficulty: q::Value::Float(123456789012.0),
+            uncles: q::Value::List(vec![]),
+            sealFields: q::Value::Null,
+        };
+        assert_eq!(expected, value);
+    }
+
+    #[test]
+    fn measures_sync_speed_over_recent_samples() {
+        let sample = |timestamp, block_number| SyncSample {
+            timestamp,
+            block_number,
+        };
+        let samples = vec![
+            sample(1000, 100),
+            sample(2000, 1100),
+            sample(2060, 1400),
+            sample(2120, 1700),
+        ];
+        assert_eq!(Some(5.0), sync_speed(&samples, 2120));
+        assert_eq!(Some(1.0), speed_between(&samples[0], &samples[1]));
+        assert_eq!(None, sync_speed(&samples[..1], 1000));
+        assert_eq!(None, sync_speed(&samples, 5000));
+    }
+}
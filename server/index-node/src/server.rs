@@ -1,9 +1,13 @@
 use hyper;
+use hyper::server::accept;
 use hyper::service::make_service_fn;
 use hyper::Server;
+use std::collections::HashMap;
+use std::io;
 use std::net::{Ipv4Addr, SocketAddrV4};
 
 use graph::prelude::{IndexNodeServer as IndexNodeServerTrait, *};
+use graph::util::tls::{self, TlsConfig};
 
 use crate::service::IndexNodeService;
 
@@ -11,29 +15,35 @@ use crate::service::IndexNodeService;
 #[derive(Debug, Fail)]
 pub enum IndexNodeServeError {
     #[fail(display = "Bind error: {}", _0)]
-    BindError(hyper::Error),
+    BindError(io::Error),
 }
 
-impl From<hyper::Error> for IndexNodeServeError {
-    fn from(err: hyper::Error) -> Self {
+impl From<io::Error> for IndexNodeServeError {
+    fn from(err: io::Error) -> Self {
         IndexNodeServeError::BindError(err)
     }
 }
 
 /// A GraphQL server based on Hyper.
-pub struct IndexNodeServer<Q, S> {
+pub struct IndexNodeServer<Q, S, C> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    /// The chain store of each network, by network name
+    chain_stores: Arc<HashMap<String, Arc<C>>>,
+    link_resolver: Arc<dyn LinkResolver>,
     node_id: NodeId,
+    tls: Option<Arc<TlsConfig>>,
 }
 
-impl<Q, S> IndexNodeServer<Q, S> {
+impl<Q, S, C> IndexNodeServer<Q, S, C> {
     /// Creates a new GraphQL server.
     pub fn new(
         logger_factory: &LoggerFactory,
         graphql_runner: Arc<Q>,
         store: Arc<S>,
+        chain_stores: HashMap<String, Arc<C>>,
+        link_resolver: Arc<dyn LinkResolver>,
         node_id: NodeId,
     ) -> Self {
         let logger = logger_factory.component_logger(
@@ -49,15 +59,27 @@ impl<Q, S> IndexNodeServer<Q, S> {
             logger,
             graphql_runner,
             store,
+            chain_stores: Arc::new(chain_stores),
+            link_resolver,
             node_id,
+            tls: None,
+        }
+    }
+
+    /// Only accept connections that use TLS with the certificate in `tls`
+    pub fn with_tls(self, tls: Arc<TlsConfig>) -> Self {
+        IndexNodeServer {
+            tls: Some(tls),
+            ..self
         }
     }
 }
 
-impl<Q, S> IndexNodeServerTrait for IndexNodeServer<Q, S>
+impl<Q, S, C> IndexNodeServerTrait for IndexNodeServer<Q, S, C>
 where
     Q: GraphQlRunner,
     S: SubgraphDeploymentStore + Store,
+    C: ChainStore + EthereumCallCache,
 {
     type ServeError = IndexNodeServeError;
 
@@ -67,9 +89,10 @@ where
     ) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>, Self::ServeError> {
         let logger = self.logger.clone();
 
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
         info!(
             logger,
-            "Starting index node server at: http://localhost:{}", port
+            "Starting index node server at: {}://localhost:{}", scheme, port
         );
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
@@ -79,18 +102,24 @@ where
         let logger_for_service = self.logger.clone();
         let graphql_runner = self.graphql_runner.clone();
         let store = self.store.clone();
+        let chain_stores = self.chain_stores.clone();
+        let link_resolver = self.link_resolver.clone();
         let node_id = self.node_id.clone();
         let new_service = make_service_fn(move |_| {
             futures03::future::ok::<_, Error>(IndexNodeService::new(
                 logger_for_service.clone(),
                 graphql_runner.clone(),
                 store.clone(),
+                chain_stores.clone(),
+                link_resolver.clone(),
                 node_id.clone(),
             ))
         });
 
         // Create a task to run the server and handle HTTP requests
-        let task = Server::try_bind(&addr.into())?
+        let listener = tls::bind(addr.into())?;
+        let incoming = tls::incoming(self.logger.clone(), listener, self.tls.clone());
+        let task = Server::builder(accept::from_stream(incoming))
             .serve(new_service)
             .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
 
@@ -1,14 +1,22 @@
 use http::header;
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
+use std::collections::HashMap;
 use std::task::Context;
 use std::task::Poll;
 use std::time::Instant;
 
 use graph::components::server::query::GraphQLServerError;
 use graph::prelude::*;
-use graph_graphql::prelude::{execute_query, Query as PreparedQuery, QueryExecutionOptions};
-
+use graph_graphql::prelude::{
+    execute_query, ExecutionHooks, Query as PreparedQuery, QueryExecutionOptions,
+};
+
+use crate::artifacts::{self, Artifact};
+use crate::auth::{bearer_token, Restricted};
+use crate::blocks::{self, BlockLookup};
+use crate::explain;
+use crate::export::{Export, ExportRequest};
 use crate::request::IndexNodeRequest;
 use crate::resolver::IndexNodeResolver;
 use crate::response::IndexNodeResponse;
@@ -18,36 +26,49 @@ use crate::schema::SCHEMA;
 pub type IndexNodeServiceResponse = DynTryFuture<'static, Response<Body>, GraphQLServerError>;
 
 /// A Hyper Service that serves GraphQL over a POST / endpoint.
-#[derive(Debug)]
-pub struct IndexNodeService<Q, S> {
+pub struct IndexNodeService<Q, S, C> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    chain_stores: Arc<HashMap<String, Arc<C>>>,
+    link_resolver: Arc<dyn LinkResolver>,
     node_id: NodeId,
 }
 
-impl<Q, S> Clone for IndexNodeService<Q, S> {
+impl<Q, S, C> Clone for IndexNodeService<Q, S, C> {
     fn clone(&self) -> Self {
         Self {
             logger: self.logger.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            chain_stores: self.chain_stores.clone(),
+            link_resolver: self.link_resolver.clone(),
             node_id: self.node_id.clone(),
         }
     }
 }
 
-impl<Q, S> IndexNodeService<Q, S>
+impl<Q, S, C> IndexNodeService<Q, S, C>
 where
     Q: GraphQlRunner,
     S: SubgraphDeploymentStore + Store,
+    C: ChainStore + EthereumCallCache,
 {
     /// Creates a new GraphQL service.
-    pub fn new(logger: Logger, graphql_runner: Arc<Q>, store: Arc<S>, node_id: NodeId) -> Self {
+    pub fn new(
+        logger: Logger,
+        graphql_runner: Arc<Q>,
+        store: Arc<S>,
+        chain_stores: Arc<HashMap<String, Arc<C>>>,
+        link_resolver: Arc<dyn LinkResolver>,
+        node_id: NodeId,
+    ) -> Self {
         IndexNodeService {
             logger,
             graphql_runner,
             store,
+            chain_stores,
+            link_resolver,
             node_id,
         }
     }
@@ -95,6 +116,7 @@ where
     fn handle_graphql_query(&self, request_body: Body) -> IndexNodeServiceResponse {
         let logger = self.logger.clone();
         let store = self.store.clone();
+        let chain_stores = self.chain_stores.clone();
         let result_logger = self.logger.clone();
         let graphql_runner = self.graphql_runner.clone();
 
@@ -114,9 +136,15 @@ where
                 tokio::task::block_in_place(|| {
                     let options = QueryExecutionOptions {
                         logger: logger.clone(),
-                        resolver: IndexNodeResolver::new(&logger, graphql_runner, store),
+                        resolver: IndexNodeResolver::new(
+                            &logger,
+                            graphql_runner,
+                            store,
+                            chain_stores,
+                        ),
                         deadline: None,
                         max_first: std::u32::MAX,
+                        hooks: Arc::new(ExecutionHooks::default()),
                     };
                     let result = PreparedQuery::new(query, None, 100)
                         .and_then(|query| execute_query(query, None, None, options));
@@ -140,6 +168,197 @@ where
             .boxed()
     }
 
+    /// Serves `artifact` of the deployment `id`
+    fn handle_artifact(&self, id: &str, artifact: Artifact) -> IndexNodeServiceResponse {
+        let id = match SubgraphDeploymentId::new(id) {
+            Ok(id) => id,
+            Err(()) => return self.handle_not_found(),
+        };
+        let service = self.clone();
+
+        async move {
+            let contents = artifacts::fetch(
+                &service.logger,
+                service.store.as_ref(),
+                service.link_resolver.as_ref(),
+                &id,
+                &artifact,
+            )
+            .await
+            .map_err(|e| {
+                GraphQLServerError::InternalError(format!(
+                    "Failed to read {:?} of subgraph {}: {}",
+                    artifact, id, e
+                ))
+            })?;
+            match contents {
+                Some(contents) => Ok(Response::builder()
+                    .status(200)
+                    .header(header::CONTENT_TYPE, artifact.content_type())
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(contents))
+                    .unwrap()),
+                None => service.handle_not_found().await,
+            }
+        }
+        .boxed()
+    }
+
+    /// Serves the blocks that `segments` select from the chain store of
+    /// `chain`, as JSON
+    fn handle_blocks(&self, chain: &str, segments: &[&str]) -> IndexNodeServiceResponse {
+        let (chain_store, lookup) =
+            match (self.chain_stores.get(chain), BlockLookup::parse(segments)) {
+                (Some(chain_store), Some(lookup)) => (chain_store.clone(), lookup),
+                _ => return self.handle_not_found(),
+            };
+        let service = self.clone();
+        let chain = chain.to_owned();
+
+        async move {
+            let blocks = blocks::lookup(chain_store.as_ref(), &lookup).map_err(|e| {
+                GraphQLServerError::InternalError(format!(
+                    "Failed to look up {:?} of chain {}: {}",
+                    lookup, chain, e
+                ))
+            })?;
+            match blocks {
+                Some(blocks) => Ok(Response::builder()
+                    .status(200)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(blocks.to_string()))
+                    .unwrap()),
+                None => service.handle_not_found().await,
+            }
+        }
+        .boxed()
+    }
+
+    /// Answers with `404` if `endpoint` is disabled, and with `401` if `req`
+    /// does not have its token. Returns `None` if `req` may use `endpoint`
+    fn check_access(
+        &self,
+        endpoint: Restricted,
+        req: &Request<Body>,
+    ) -> Option<IndexNodeServiceResponse> {
+        if !endpoint.is_enabled() {
+            return Some(self.handle_not_found());
+        }
+        if !endpoint.is_authorized(bearer_token(req)) {
+            return Some(Box::pin(futures03::future::ok(
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(header::WWW_AUTHENTICATE, "Bearer")
+                    .body(Body::from("Unauthorized"))
+                    .unwrap(),
+            )));
+        }
+        None
+    }
+
+    /// Explains the SQL for the GraphQL query in the body of `req` against
+    /// the deployment `id` to clients that present the explain token
+    fn handle_explain(&self, req: Request<Body>, id: &str) -> IndexNodeServiceResponse {
+        if let Some(response) = self.check_access(Restricted::Explain, &req) {
+            return response;
+        }
+        let id = match SubgraphDeploymentId::new(id) {
+            Ok(id) => id,
+            Err(()) => return self.handle_not_found(),
+        };
+        let service = self.clone();
+
+        async move {
+            let schema = match service.store.is_deployed(&id) {
+                Ok(true) => service.store.api_schema(&id),
+                Ok(false) => return service.handle_not_found().await,
+                Err(e) => Err(e),
+            }
+            .map_err(|e| {
+                GraphQLServerError::InternalError(format!(
+                    "Failed to read the schema of subgraph {}: {}",
+                    id, e
+                ))
+            })?;
+            let body = hyper::body::to_bytes(req.into_body())
+                .await
+                .map_err(|_| GraphQLServerError::from("Failed to read request body"))?;
+            let query = IndexNodeRequest::new(body, schema).compat().await?;
+
+            let explanation = tokio::task::block_in_place(|| {
+                explain::explain(service.graphql_runner.as_ref(), query)
+            });
+            Ok(Response::builder()
+                .status(200)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(explanation.to_string()))
+                .unwrap())
+        }
+        .boxed()
+    }
+
+    /// Streams the entities of type `entity_type` of the deployment `id` to
+    /// clients that present the export token. Answers with `404` if exports
+    /// are disabled
+    fn handle_export(
+        &self,
+        req: &Request<Body>,
+        id: &str,
+        entity_type: &str,
+    ) -> IndexNodeServiceResponse {
+        if let Some(response) = self.check_access(Restricted::Export, req) {
+            return response;
+        }
+        let id = match SubgraphDeploymentId::new(id) {
+            Ok(id) => id,
+            Err(()) => return self.handle_not_found(),
+        };
+        let request = match ExportRequest::from_url_query(req.uri().query()) {
+            Ok(request) => request,
+            Err(e) => {
+                return Box::pin(futures03::future::err(GraphQLServerError::ClientError(
+                    e.to_string(),
+                )))
+            }
+        };
+        let service = self.clone();
+        let entity_type = entity_type.to_owned();
+
+        async move {
+            let schema = match service.store.is_deployed(&id) {
+                Ok(true) => service.store.input_schema(&id),
+                Ok(false) => return service.handle_not_found().await,
+                Err(e) => Err(e),
+            }
+            .map_err(|e| {
+                GraphQLServerError::InternalError(format!(
+                    "Failed to read the schema of subgraph {}: {}",
+                    id, e
+                ))
+            })?;
+            let format = request.format;
+            let export = match Export::new(&schema, &entity_type, request)
+                .map_err(|e| GraphQLServerError::ClientError(e.to_string()))?
+            {
+                Some(export) => export,
+                None => return service.handle_not_found().await,
+            };
+
+            debug!(
+                service.logger, "Exporting entities";
+                "subgraph_id" => id.to_string(),
+                "entity_type" => &entity_type,
+            );
+            Ok(Response::builder()
+                .status(200)
+                .header(header::CONTENT_TYPE, format.content_type())
+                .body(Body::wrap_stream(export.csv_stream(service.store.clone())))
+                .unwrap())
+        }
+        .boxed()
+    }
+
     // Handles OPTIONS requests
     fn handle_graphql_options(&self, _request: Request<Body>) -> IndexNodeServiceResponse {
         Box::pin(async {
@@ -209,15 +428,33 @@ where
             (Method::POST, ["graphql"]) => self.handle_graphql_query(req.into_body()),
             (Method::OPTIONS, ["graphql"]) => self.handle_graphql_options(req),
 
+            (Method::GET, ["subgraphs", "id", id, "manifest"]) => {
+                self.handle_artifact(id, Artifact::Manifest)
+            }
+            (Method::GET, ["subgraphs", "id", id, "schema"]) => {
+                self.handle_artifact(id, Artifact::Schema)
+            }
+            (Method::GET, ["subgraphs", "id", id, "abis", name]) => {
+                self.handle_artifact(id, Artifact::Abi(name.to_string()))
+            }
+            (Method::POST, ["subgraphs", "id", id, "explain"]) => self.handle_explain(req, id),
+            (Method::GET, ["subgraphs", "id", id, "export", entity_type]) => {
+                self.handle_export(&req, id, entity_type)
+            }
+            (Method::GET, segments) if segments.len() > 2 && segments[0] == "chains" => {
+                self.handle_blocks(segments[1], &segments[2..])
+            }
+
             _ => self.handle_not_found(),
         }
     }
 }
 
-impl<Q, S> Service<Request<Body>> for IndexNodeService<Q, S>
+impl<Q, S, C> Service<Request<Body>> for IndexNodeService<Q, S, C>
 where
     Q: GraphQlRunner,
     S: SubgraphDeploymentStore + Store,
+    C: ChainStore + EthereumCallCache,
 {
     type Response = Response<Body>;
     type Error = GraphQLServerError;
@@ -3,12 +3,19 @@ extern crate jsonrpc_http_server;
 extern crate lazy_static;
 extern crate serde;
 
+use graph::components::migration;
+use graph::data::query::blocklist;
+use graph::data::query::quota::{self, Quotas, TokenQuota};
 use graph::prelude::futures03::channel::{mpsc, oneshot};
 use graph::prelude::futures03::SinkExt;
 use graph::prelude::serde_json;
 use graph::prelude::{JsonRpcServer as JsonRpcServerTrait, *};
+use graph::util::http_limits::HttpLimits;
+use graph::util::security;
+use graph::util::tls::{self, TlsConfig};
 use jsonrpc_http_server::{
-    jsonrpc_core::{self, Compatibility, IoHandler, Params, Value},
+    hyper,
+    jsonrpc_core::{self, Compatibility, MetaIoHandler, Metadata, Params, Value},
     RestApi, Server, ServerBuilder,
 };
 use lazy_static::lazy_static;
@@ -29,6 +36,37 @@ const JSON_RPC_DEPLOY_ERROR: i64 = 0;
 const JSON_RPC_REMOVE_ERROR: i64 = 1;
 const JSON_RPC_CREATE_ERROR: i64 = 2;
 const JSON_RPC_REASSIGN_ERROR: i64 = 3;
+const JSON_RPC_UNAUTHORIZED_ERROR: i64 = 4;
+const JSON_RPC_QUERY_BLOCK_ERROR: i64 = 5;
+const JSON_RPC_MAINTENANCE_ERROR: i64 = 6;
+const JSON_RPC_PUBLISH_ERROR: i64 = 7;
+const JSON_RPC_QUOTA_ERROR: i64 = 8;
+const JSON_RPC_STORE_ERROR: i64 = 9;
+const JSON_RPC_REWIND_ERROR: i64 = 10;
+
+/// Where the methods that use the store send the work they need to do
+type TaskSender = mpsc::Sender<Box<dyn std::future::Future<Output = ()> + Send + Unpin>>;
+
+/// Information about a request that is not part of the JSON-RPC call
+#[derive(Clone, Debug, Default)]
+struct RequestMeta {
+    /// The bearer token from the `Authorization` header
+    token: Option<String>,
+}
+
+impl Metadata for RequestMeta {}
+
+impl RequestMeta {
+    fn from_request(request: &hyper::Request<hyper::Body>) -> Self {
+        let token = request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(security::bearer_token)
+            .map(str::to_owned);
+        RequestMeta { token }
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct SubgraphCreateParams {
@@ -38,8 +76,16 @@ struct SubgraphCreateParams {
 #[derive(Debug, Deserialize)]
 struct SubgraphDeployParams {
     name: SubgraphName,
-    ipfs_hash: SubgraphDeploymentId,
+    ipfs_hash: Option<SubgraphDeploymentId>,
+    /// The path or HTTP(S) URL of a manifest, or of a directory with a
+    /// `subgraph.yaml`, to deploy from instead of IPFS
+    manifest: Option<String>,
     node_id: Option<NodeId>,
+    version_label: Option<String>,
+    /// Allow grafting onto a longer chain of grafts than
+    /// `GRAPH_MAX_GRAFT_DEPTH`
+    #[serde(default)]
+    ignore_graft_depth: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -53,15 +99,93 @@ struct SubgraphReassignParams {
     node_id: NodeId,
 }
 
+#[derive(Debug, Deserialize)]
+struct SubgraphMaintenanceParams {
+    ipfs_hash: SubgraphDeploymentId,
+    maintenance: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphPublishParams {
+    ipfs_hash: SubgraphDeploymentId,
+    /// Drop the publication instead of creating it if this is `false`
+    #[serde(default = "default_publish")]
+    publish: bool,
+    /// Change the replica identity of the tables to `default` or `full`
+    replica_identity: Option<ReplicaIdentity>,
+}
+
+fn default_publish() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphRewindParams {
+    ipfs_hash: SubgraphDeploymentId,
+    block_hash: web3::types::H256,
+    block_number: BlockNumber,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryBlockParams {
+    query_hash: String,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryUnblockParams {
+    query_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuotaSetParams {
+    /// The API token itself; only its hash is stored
+    token: String,
+    name: String,
+    monthly_limit: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuotaRemoveParams {
+    name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct QuotaUsageParams {
+    /// The month as `YYYY-MM`; defaults to the current month
+    month: Option<String>,
+}
+
 pub struct JsonRpcServer<R> {
     registrar: Arc<R>,
     http_port: u16,
     ws_port: u16,
-    node_id: NodeId,
+    auth: AdminAuth,
     logger: Logger,
 }
 
 impl<R: SubgraphRegistrar> JsonRpcServer<R> {
+    /// Check that the request described by `meta` may call `method`, which
+    /// needs `scope`
+    fn authorize(
+        &self,
+        method: &str,
+        meta: &RequestMeta,
+        scope: AdminScope,
+    ) -> Result<(), jsonrpc_core::Error> {
+        self.auth
+            .check(meta.token.as_ref().map(String::as_str), scope)
+            .map_err(|message| {
+                warn!(self.logger, "Refusing unauthorized request";
+                      "method" => method, "error" => &message);
+                jsonrpc_core::Error {
+                    code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_UNAUTHORIZED_ERROR),
+                    message: format!("unauthorized: {}", message),
+                    data: None,
+                }
+            })
+    }
+
     /// Handler for the `subgraph_create` endpoint.
     async fn create_handler(
         &self,
@@ -90,14 +214,54 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
     ) -> Result<Value, jsonrpc_core::Error> {
         info!(&self.logger, "Received subgraph_deploy request"; "params" => format!("{:?}", params));
 
-        let node_id = params.node_id.clone().unwrap_or(self.node_id.clone());
-        let routes = subgraph_routes(&params.name, self.http_port, self.ws_port);
+        let hash = match (params.ipfs_hash.clone(), params.manifest.clone()) {
+            (Some(hash), None) => hash,
+            (None, Some(manifest)) => match self.registrar.import_subgraph(&manifest).await {
+                Ok(hash) => hash,
+                Err(e) => {
+                    return Err(json_rpc_error(
+                        &self.logger,
+                        "subgraph_deploy",
+                        e,
+                        JSON_RPC_DEPLOY_ERROR,
+                        params,
+                    ))
+                }
+            },
+            _ => {
+                return Err(jsonrpc_core::Error {
+                    code: jsonrpc_core::ErrorCode::InvalidParams,
+                    message: "exactly one of `ipfs_hash` and `manifest` must be given".to_owned(),
+                    data: None,
+                })
+            }
+        };
+
+        let mut routes = subgraph_routes(
+            &params.name,
+            params.version_label.as_deref(),
+            self.http_port,
+            self.ws_port,
+        );
+        routes["deployment"] = Value::from(hash.to_string());
         match self
             .registrar
-            .create_subgraph_version(params.name.clone(), params.ipfs_hash.clone(), node_id)
+            .create_subgraph_version(
+                params.name.clone(),
+                hash,
+                params.node_id.clone(),
+                params.version_label.clone(),
+                params.ignore_graft_depth,
+            )
             .await
         {
-            Ok(_) => Ok(routes),
+            Ok(warnings) => {
+                routes["warnings"] = warnings
+                    .iter()
+                    .map(|warning| Value::from(warning.to_string()))
+                    .collect();
+                Ok(routes)
+            }
             Err(e) => Err(json_rpc_error(
                 &self.logger,
                 "subgraph_deploy",
@@ -151,136 +315,570 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
             )),
         }
     }
-}
 
-impl<R> JsonRpcServerTrait<R> for JsonRpcServer<R>
-where
-    R: SubgraphRegistrar,
-{
-    type Server = Server;
+    /// Handler for the `subgraph_maintenance` endpoint.
+    async fn maintenance_handler(
+        &self,
+        params: SubgraphMaintenanceParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        let logger = self.logger.clone();
 
-    fn serve(
-        port: u16,
-        http_port: u16,
-        ws_port: u16,
-        registrar: Arc<R>,
-        node_id: NodeId,
-        logger: Logger,
-    ) -> Result<Self::Server, io::Error> {
-        let logger = logger.new(o!("component" => "JsonRpcServer"));
+        info!(logger, "Received subgraph_maintenance request"; "params" => format!("{:?}", params));
 
-        info!(
-            logger,
-            "Starting JSON-RPC admin server at: http://localhost:{}", port
-        );
+        match self
+            .registrar
+            .set_maintenance(params.ipfs_hash.clone(), params.maintenance)
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &logger,
+                "subgraph_maintenance",
+                e,
+                JSON_RPC_MAINTENANCE_ERROR,
+                params,
+            )),
+        }
+    }
 
-        let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
+    /// Handler for the `subgraph_publish` endpoint.
+    async fn publish_handler(
+        &self,
+        params: SubgraphPublishParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        let logger = self.logger.clone();
 
-        let mut handler = IoHandler::with_compatibility(Compatibility::Both);
+        info!(logger, "Received subgraph_publish request"; "params" => format!("{:?}", params));
 
-        let arc_self = Arc::new(JsonRpcServer {
-            registrar,
-            http_port,
-            ws_port,
-            node_id,
-            logger,
-        });
+        match self
+            .registrar
+            .set_publication(
+                params.ipfs_hash.clone(),
+                params.publish,
+                params.replica_identity,
+            )
+            .await
+        {
+            Ok(publication) => Ok(serde_json::json!({ "publication": publication })),
+            Err(e) => Err(json_rpc_error(
+                &logger,
+                "subgraph_publish",
+                e,
+                JSON_RPC_PUBLISH_ERROR,
+                params,
+            )),
+        }
+    }
 
-        let (task_sender, task_receiver) =
-            mpsc::channel::<Box<dyn std::future::Future<Output = ()> + Send + Unpin>>(100);
-        graph::spawn(task_receiver.for_each(|f| {
-            async {
-                // Blocking due to store interactions. Won't be blocking after #905.
-                graph::spawn_blocking(f);
-            }
-        }));
-
-        // This is a hack required because the json-rpc crate is not updated to tokio 0.2.
-        // We should watch the `jsonrpsee` crate and switch to that once it's ready.
-        async fn tokio02_spawn<I: Send + 'static, ER: Send + 'static>(
-            mut task_sink: mpsc::Sender<Box<dyn std::future::Future<Output = ()> + Send + Unpin>>,
-            future: impl std::future::Future<Output = Result<I, ER>> + Send + Unpin + 'static,
-        ) -> Result<I, ER>
-        where
-            I: Debug,
-            ER: Debug,
+    /// Handler for the `subgraph_rewind` endpoint.
+    async fn rewind_handler(
+        &self,
+        params: SubgraphRewindParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        let logger = self.logger.clone();
+
+        info!(logger, "Received subgraph_rewind request"; "params" => format!("{:?}", params));
+
+        if params.block_number < 0 {
+            return Err(jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::InvalidParams,
+                message: "`block_number` must not be negative".to_owned(),
+                data: None,
+            });
+        }
+        let block_ptr = EthereumBlockPointer::from((params.block_hash, params.block_number as u64));
+        match self
+            .registrar
+            .rewind_subgraph(params.ipfs_hash.clone(), block_ptr)
+            .await
         {
-            let (return_sender, return_receiver) = oneshot::channel();
-            task_sink
-                .send(Box::new(future.map(move |res| {
-                    return_sender.send(res).expect("`return_receiver` dropped");
-                })))
-                .await
-                .expect("task receiver dropped");
-            return_receiver.await.expect("`return_sender` dropped")
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &logger,
+                "subgraph_rewind",
+                e,
+                JSON_RPC_REWIND_ERROR,
+                params,
+            )),
         }
+    }
+
+    /// Handler for the `query_block` endpoint.
+    fn query_block_handler(&self, params: QueryBlockParams) -> Result<Value, jsonrpc_core::Error> {
+        let hash = parse_query_hash(&params.query_hash)?;
+        warn!(self.logger, "Blocking queries";
+              "query_hash" => &params.query_hash,
+              "reason" => params.reason.as_deref().unwrap_or(""));
+        blocklist::block(hash, params.reason);
+        Ok(Value::Null)
+    }
+
+    /// Handler for the `query_unblock` endpoint.
+    fn query_unblock_handler(
+        &self,
+        params: QueryUnblockParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        let hash = parse_query_hash(&params.query_hash)?;
+        if !blocklist::unblock(hash) {
+            return Err(jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_QUERY_BLOCK_ERROR),
+                message: format!("queries with hash `{}` are not blocked", params.query_hash),
+                data: None,
+            });
+        }
+        info!(self.logger, "Unblocking queries"; "query_hash" => &params.query_hash);
+        Ok(Value::Null)
+    }
 
-        let me = arc_self.clone();
+    /// Handler for the `query_blocklist` endpoint.
+    fn query_blocklist_handler(&self) -> Result<Value, jsonrpc_core::Error> {
+        Ok(Value::Array(
+            blocklist::list()
+                .into_iter()
+                .map(|(hash, reason)| {
+                    serde_json::json!({
+                        "query_hash": blocklist::format_hash(hash),
+                        "reason": reason,
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    /// Handler for the `quota_set` endpoint.
+    async fn quota_set_handler(
+        &self,
+        params: QuotaSetParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        let quota = TokenQuota::new(&params.token, params.name, params.monthly_limit);
+        info!(self.logger, "Setting query quota";
+              "name" => &quota.name,
+              "token" => &quota.token,
+              "monthly_limit" => quota.monthly_limit);
+        installed_quotas()?
+            .set_quota(quota)
+            .map_err(|e| quota_error(&self.logger, "quota_set", e))?;
+        Ok(Value::Null)
+    }
+
+    /// Handler for the `quota_remove` endpoint.
+    async fn quota_remove_handler(
+        &self,
+        params: QuotaRemoveParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        let removed = installed_quotas()?
+            .remove_quota(&params.name)
+            .map_err(|e| quota_error(&self.logger, "quota_remove", e))?;
+        if !removed {
+            return Err(jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_QUOTA_ERROR),
+                message: format!("there is no quota for a token called `{}`", params.name),
+                data: None,
+            });
+        }
+        info!(self.logger, "Removed query quota"; "name" => &params.name);
+        Ok(Value::Null)
+    }
+
+    /// Handler for the `quota_usage` endpoint.
+    async fn quota_usage_handler(
+        &self,
+        params: QuotaUsageParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        let usage = installed_quotas()?
+            .usage(params.month.as_deref())
+            .map_err(|e| quota_error(&self.logger, "quota_usage", e))?;
+        Ok(Value::Array(
+            usage
+                .into_iter()
+                .map(|usage| {
+                    serde_json::json!({
+                        "name": usage.quota.name,
+                        "token": usage.quota.token,
+                        "monthly_limit": usage.quota.monthly_limit,
+                        "month": usage.month,
+                        "used": usage.used,
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    /// Handler for the `store_migrations` endpoint.
+    async fn store_migrations_handler(&self) -> Result<Value, jsonrpc_core::Error> {
+        let source = migration::installed().ok_or_else(|| jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_STORE_ERROR),
+            message: "migration status is not available on this node".to_owned(),
+            data: None,
+        })?;
+        let statuses = tokio::task::block_in_place(|| source.migration_status()).map_err(|e| {
+            error!(self.logger, "store_migrations failed"; "error" => e.to_string());
+            jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_STORE_ERROR),
+                message: "internal error".to_owned(),
+                data: None,
+            }
+        })?;
+        Ok(serde_json::to_value(statuses).expect("invalid migration status"))
+    }
+
+    /// Create the handler for all methods of the admin server. Methods
+    /// that use the store send their work to `task_sender`
+    fn io_handler(self: Arc<Self>, task_sender: TaskSender) -> MetaIoHandler<RequestMeta> {
+        let mut handler = MetaIoHandler::<RequestMeta>::with_compatibility(Compatibility::Both);
+
+        let me = self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_create",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_create", &meta, AdminScope::Deploy)?;
+                        let params = params.parse()?;
+                        me.create_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_create", move |params: Params| {
+
+        handler.add_method_with_meta(
+            "subgraph_deploy",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_deploy", &meta, AdminScope::Deploy)?;
+                        let params = params.parse()?;
+                        me.deploy_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_remove",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_remove", &meta, AdminScope::Remove)?;
+                        let params = params.parse()?;
+                        me.remove_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_reassign",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_reassign", &meta, AdminScope::Assign)?;
+                        let params = params.parse()?;
+                        me.reassign_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_maintenance",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_maintenance", &meta, AdminScope::Assign)?;
+                        let params = params.parse()?;
+                        me.maintenance_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_publish",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_publish", &meta, AdminScope::Assign)?;
+                        let params = params.parse()?;
+                        me.publish_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_rewind",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_rewind", &meta, AdminScope::Rewind)?;
+                        let params = params.parse()?;
+                        me.rewind_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = self.clone();
+        handler.add_method_with_meta("query_block", move |params: Params, meta: RequestMeta| {
+            future::result(
+                me.authorize("query_block", &meta, AdminScope::Queries)
+                    .and_then(|_| params.parse())
+                    .and_then(|params| me.query_block_handler(params)),
+            )
+        });
+
+        let me = self.clone();
+        handler.add_method_with_meta("query_unblock", move |params: Params, meta: RequestMeta| {
+            future::result(
+                me.authorize("query_unblock", &meta, AdminScope::Queries)
+                    .and_then(|_| params.parse())
+                    .and_then(|params| me.query_unblock_handler(params)),
+            )
+        });
+
+        let me = self.clone();
+        handler.add_method_with_meta("query_blocklist", move |_: Params, meta: RequestMeta| {
+            future::result(
+                me.authorize("query_blocklist", &meta, AdminScope::Queries)
+                    .and_then(|_| me.query_blocklist_handler()),
+            )
+        });
+
+        let me = self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta("quota_set", move |params: Params, meta: RequestMeta| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
+                    me.authorize("quota_set", &meta, AdminScope::Quotas)?;
                     let params = params.parse()?;
-                    me.create_handler(params).await
+                    me.quota_set_handler(params).await
                 }
                 .boxed(),
             ))
             .compat()
         });
 
-        let me = arc_self.clone();
+        let me = self.clone();
         let sender = task_sender.clone();
-
-        handler.add_method("subgraph_deploy", move |params: Params| {
+        handler.add_method_with_meta("quota_remove", move |params: Params, meta: RequestMeta| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
+                    me.authorize("quota_remove", &meta, AdminScope::Quotas)?;
                     let params = params.parse()?;
-                    me.deploy_handler(params).await
+                    me.quota_remove_handler(params).await
                 }
                 .boxed(),
             ))
             .compat()
         });
 
-        let me = arc_self.clone();
+        let me = self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_remove", move |params: Params| {
+        handler.add_method_with_meta("quota_usage", move |params: Params, meta: RequestMeta| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
-                    let params = params.parse()?;
-                    me.remove_handler(params).await
+                    me.authorize("quota_usage", &meta, AdminScope::Quotas)?;
+                    let params = match params {
+                        Params::None => QuotaUsageParams::default(),
+                        params => params.parse()?,
+                    };
+                    me.quota_usage_handler(params).await
                 }
                 .boxed(),
             ))
             .compat()
         });
 
-        let me = arc_self.clone();
+        let me = self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_reassign", move |params: Params| {
+        handler.add_method_with_meta("store_migrations", move |_: Params, meta: RequestMeta| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
-                    let params = params.parse()?;
-                    me.reassign_handler(params).await
+                    me.authorize("store_migrations", &meta, AdminScope::Store)?;
+                    me.store_migrations_handler().await
                 }
                 .boxed(),
             ))
             .compat()
         });
 
-        ServerBuilder::new(handler)
+        handler
+    }
+}
+
+/// Run the work that methods send to the returned sender
+fn spawn_tasks() -> TaskSender {
+    let (task_sender, task_receiver) =
+        mpsc::channel::<Box<dyn std::future::Future<Output = ()> + Send + Unpin>>(100);
+    graph::spawn(task_receiver.for_each(|f| {
+        async {
+            // Blocking due to store interactions. Won't be blocking after #905.
+            graph::spawn_blocking(f);
+        }
+    }));
+    task_sender
+}
+
+// This is a hack required because the json-rpc crate is not updated to tokio 0.2.
+// We should watch the `jsonrpsee` crate and switch to that once it's ready.
+async fn tokio02_spawn<I: Send + 'static, ER: Send + 'static>(
+    mut task_sink: TaskSender,
+    future: impl std::future::Future<Output = Result<I, ER>> + Send + Unpin + 'static,
+) -> Result<I, ER>
+where
+    I: Debug,
+    ER: Debug,
+{
+    let (return_sender, return_receiver) = oneshot::channel();
+    task_sink
+        .send(Box::new(future.map(move |res| {
+            return_sender.send(res).expect("`return_receiver` dropped");
+        })))
+        .await
+        .expect("task receiver dropped");
+    return_receiver.await.expect("`return_sender` dropped")
+}
+
+fn installed_quotas() -> Result<Arc<Quotas>, jsonrpc_core::Error> {
+    quota::installed().ok_or_else(|| jsonrpc_core::Error {
+        code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_QUOTA_ERROR),
+        message: "query quotas are not enabled on this node".to_owned(),
+        data: None,
+    })
+}
+
+fn quota_error(logger: &Logger, operation: &str, e: Error) -> jsonrpc_core::Error {
+    error!(logger, "{} failed", operation; "error" => e.to_string());
+    jsonrpc_core::Error {
+        code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_QUOTA_ERROR),
+        message: "internal error".to_owned(),
+        data: None,
+    }
+}
+
+fn parse_query_hash(hash: &str) -> Result<u64, jsonrpc_core::Error> {
+    blocklist::parse_hash(hash).map_err(|e| jsonrpc_core::Error {
+        code: jsonrpc_core::ErrorCode::InvalidParams,
+        message: e.to_string(),
+        data: None,
+    })
+}
+
+impl<R> JsonRpcServerTrait<R> for JsonRpcServer<R>
+where
+    R: SubgraphRegistrar,
+{
+    type Server = Server;
+
+    fn serve(
+        port: u16,
+        http_port: u16,
+        ws_port: u16,
+        registrar: Arc<R>,
+        auth: AdminAuth,
+        tls: Option<Arc<TlsConfig>>,
+        logger: Logger,
+    ) -> Result<Self::Server, io::Error> {
+        let logger = logger.new(o!("component" => "JsonRpcServer"));
+
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        info!(
+            logger,
+            "Starting JSON-RPC admin server at: {}://localhost:{}", scheme, port
+        );
+
+        // The server can neither do TLS nor protect itself against slow
+        // clients. It only listens on a local port, and connections to
+        // `port` are forwarded to it
+        let public_addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
+        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+        let limits = HttpLimits::from_env();
+
+        if !auth.is_enabled() {
+            warn!(
+                logger,
+                "The JSON-RPC admin server does not require authentication; \
+                 configure admin tokens to change that"
+            );
+        }
+
+        let proxy_logger = logger.clone();
+        let arc_self = Arc::new(JsonRpcServer {
+            registrar,
+            http_port,
+            ws_port,
+            auth,
+            logger,
+        });
+        let handler = arc_self.io_handler(spawn_tasks());
+
+        let server = ServerBuilder::with_meta_extractor(handler, RequestMeta::from_request)
             // Enable REST API:
             // POST /<method>/<param1>/<param2>
             .rest_api(RestApi::Secure)
-            .start_http(&addr.into())
+            .max_request_body_size(limits.max_body_size)
+            .start_http(&addr.into())?;
+
+        let listener = tls::bind(public_addr.into())?;
+        graph::spawn(tls::proxy(
+            proxy_logger,
+            listener,
+            tls,
+            limits,
+            *server.address(),
+        ));
+        Ok(server)
     }
 }
 
@@ -301,10 +899,24 @@ fn json_rpc_error(
         e.to_string()
     };
 
+    // List the problems with an invalid manifest individually, so that
+    // deployers can show all of them
+    let data = match &e {
+        SubgraphRegistrarError::ManifestValidationError(errors) => {
+            let errors: Vec<_> = errors
+                .iter()
+                .flat_map(|error| error.messages())
+                .map(Value::from)
+                .collect();
+            Some(serde_json::json!({ "errors": errors }))
+        }
+        _ => None,
+    };
+
     jsonrpc_core::Error {
         code: jsonrpc_core::ErrorCode::ServerError(code),
         message,
-        data: None,
+        data,
     }
 }
 
@@ -320,7 +932,12 @@ pub fn parse_response(response: Value) -> Result<(), jsonrpc_core::Error> {
     }
 }
 
-fn subgraph_routes(name: &SubgraphName, http_port: u16, ws_port: u16) -> Value {
+fn subgraph_routes(
+    name: &SubgraphName,
+    version_label: Option<&str>,
+    http_port: u16,
+    ws_port: u16,
+) -> Value {
     let http_base_url = EXTERNAL_HTTP_BASE_URL
         .clone()
         .unwrap_or_else(|| format!(":{}", http_port));
@@ -341,5 +958,128 @@ fn subgraph_routes(name: &SubgraphName, http_port: u16, ws_port: u16) -> Value {
         "subscriptions",
         format!("{}/subgraphs/name/{}", ws_base_url, name),
     );
+    if let Some(label) = version_label {
+        map.insert(
+            "versionQueries",
+            format!(
+                "{}/subgraphs/name/{}/version/{}",
+                http_base_url, name, label
+            ),
+        );
+    }
     jsonrpc_core::to_value(map).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A registrar for requests that must be refused before they get to it
+    struct RefusingRegistrar;
+
+    #[async_trait]
+    impl SubgraphRegistrar for RefusingRegistrar {
+        async fn create_subgraph(
+            &self,
+            _name: SubgraphName,
+        ) -> Result<CreateSubgraphResult, SubgraphRegistrarError> {
+            unreachable!()
+        }
+
+        async fn create_subgraph_version(
+            &self,
+            _name: SubgraphName,
+            _hash: SubgraphDeploymentId,
+            _assignment_node_id: Option<NodeId>,
+            _version_label: Option<String>,
+            _ignore_graft_depth: bool,
+        ) -> Result<Vec<SubgraphManifestValidationWarning>, SubgraphRegistrarError> {
+            unreachable!()
+        }
+
+        async fn import_subgraph(
+            &self,
+            _location: &str,
+        ) -> Result<SubgraphDeploymentId, SubgraphRegistrarError> {
+            unreachable!()
+        }
+
+        async fn remove_subgraph(&self, _name: SubgraphName) -> Result<(), SubgraphRegistrarError> {
+            unreachable!()
+        }
+
+        async fn reassign_subgraph(
+            &self,
+            _hash: SubgraphDeploymentId,
+            _node_id: NodeId,
+        ) -> Result<(), SubgraphRegistrarError> {
+            unreachable!()
+        }
+
+        async fn set_maintenance(
+            &self,
+            _hash: SubgraphDeploymentId,
+            _maintenance: bool,
+        ) -> Result<(), SubgraphRegistrarError> {
+            unreachable!()
+        }
+
+        async fn set_publication(
+            &self,
+            _hash: SubgraphDeploymentId,
+            _publish: bool,
+            _replica_identity: Option<ReplicaIdentity>,
+        ) -> Result<Option<String>, SubgraphRegistrarError> {
+            unreachable!()
+        }
+
+        async fn rewind_subgraph(
+            &self,
+            _hash: SubgraphDeploymentId,
+            _block_ptr: EthereumBlockPointer,
+        ) -> Result<(), SubgraphRegistrarError> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn refuses_rewind_without_scope() {
+        let auth = AdminAuth::default().with_token(
+            "operator".to_owned(),
+            vec![AdminScope::Assign, AdminScope::Remove],
+        );
+        let server = Arc::new(JsonRpcServer {
+            registrar: Arc::new(RefusingRegistrar),
+            http_port: 8000,
+            ws_port: 8001,
+            auth,
+            logger: Logger::root(slog::Discard, o!()),
+        });
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "subgraph_rewind",
+            "params": {
+                "ipfs_hash": "QmRewind",
+                "block_hash": format!("0x{}", "00".repeat(32)),
+                "block_number": 1,
+            },
+        })
+        .to_string();
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(async move {
+            let handler = server.io_handler(spawn_tasks());
+            let meta = RequestMeta {
+                token: Some("operator".to_owned()),
+            };
+            handler.handle_request(&request, meta).compat().await
+        });
+
+        let response: Value = serde_json::from_str(&response.unwrap().unwrap()).unwrap();
+        assert_eq!(
+            Value::from(JSON_RPC_UNAUTHORIZED_ERROR),
+            response["error"]["code"]
+        );
+    }
+}
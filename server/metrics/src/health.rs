@@ -0,0 +1,226 @@
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+
+use graph::prelude::futures03::future::join_all;
+use graph::prelude::{HealthServer as HealthServerTrait, *};
+
+/// Components that do not answer within this time are reported as unhealthy
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Errors that may occur when starting the server.
+#[derive(Debug, Fail)]
+pub enum HealthServeError {
+    #[fail(display = "Bind error: {}", _0)]
+    BindError(hyper::Error),
+}
+
+impl From<hyper::Error> for HealthServeError {
+    fn from(err: hyper::Error) -> Self {
+        HealthServeError::BindError(err)
+    }
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    components: Vec<ComponentHealth>,
+}
+
+/// The components that `/readyz` reports on. They are only known once the
+/// node is connected to its stores and providers; until then, the node is
+/// not ready
+type Checks = Arc<RwLock<Option<Vec<Arc<dyn HealthCheck>>>>>;
+
+/// Serves `/healthz`, which succeeds as long as the process can answer
+/// requests, and `/readyz`, which succeeds if all components are healthy.
+/// Both respond with JSON; `/readyz` lists the state of every component.
+/// The server can be started before the components are set up with
+/// `set_checks`
+pub struct HealthServer {
+    logger: Logger,
+    checks: Checks,
+}
+
+impl HealthServer {
+    pub fn new(logger_factory: &LoggerFactory) -> Self {
+        HealthServer {
+            logger: logger_factory.component_logger("HealthServer", None),
+            checks: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Make `/readyz` report on `checks` from now on
+    pub fn set_checks(&self, checks: Vec<Arc<dyn HealthCheck>>) {
+        *self.checks.write().unwrap() = Some(checks);
+    }
+}
+
+async fn check_component(check: Arc<dyn HealthCheck>) -> ComponentHealth {
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, check.check()).await {
+        Ok(result) => result,
+        Err(_) => Err(format_err!(
+            "no answer within {} seconds",
+            CHECK_TIMEOUT.as_secs()
+        )),
+    };
+    ComponentHealth {
+        component: check.component(),
+        healthy: result.is_ok(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+/// The component that is reported while the node is still starting
+fn starting() -> Readiness {
+    Readiness {
+        ready: false,
+        components: vec![ComponentHealth {
+            component: "startup".to_owned(),
+            healthy: false,
+            error: Some("the node is connecting to its stores and providers".to_owned()),
+        }],
+    }
+}
+
+async fn readiness(checks: &[Arc<dyn HealthCheck>]) -> Readiness {
+    let components = join_all(checks.iter().cloned().map(check_component)).await;
+    Readiness {
+        ready: components.iter().all(|component| component.healthy),
+        components,
+    }
+}
+
+fn json_response(status: StatusCode, body: &impl serde::Serialize) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(body).unwrap()))
+        .unwrap()
+}
+
+async fn handle(checks: Checks, req: Request<Body>) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/healthz") => {
+            json_response(StatusCode::OK, &serde_json::json!({ "alive": true }))
+        }
+        (&Method::GET, "/readyz") => {
+            let checks = checks.read().unwrap().clone();
+            let readiness = match checks {
+                Some(checks) => readiness(&checks).await,
+                None => starting(),
+            };
+            let status = if readiness.ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            json_response(status, &readiness)
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found"))
+            .unwrap(),
+    }
+}
+
+impl HealthServerTrait for HealthServer {
+    type ServeError = HealthServeError;
+
+    fn serve(
+        &mut self,
+        port: u16,
+    ) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>, Self::ServeError> {
+        let logger = self.logger.clone();
+
+        info!(
+            logger,
+            "Starting health server at: http://localhost:{}", port,
+        );
+
+        let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
+
+        let checks = self.checks.clone();
+        let new_service = make_service_fn(move |_| {
+            let checks = checks.clone();
+            async move {
+                Ok::<_, Error>(service_fn(move |req| {
+                    handle(checks.clone(), req).map(Ok::<_, Error>)
+                }))
+            }
+        });
+
+        let task = Server::try_bind(&addr.into())?
+            .serve(new_service)
+            .map_err(move |e| error!(logger, "Health server error"; "error" => format!("{}", e)));
+
+        Ok(Box::new(task.compat()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str, bool);
+
+    #[async_trait]
+    impl HealthCheck for Fixed {
+        fn component(&self) -> String {
+            self.0.to_owned()
+        }
+
+        async fn check(&self) -> Result<(), Error> {
+            if self.1 {
+                Ok(())
+            } else {
+                Err(format_err!("{} is down", self.0))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn is_not_ready_while_starting() {
+        let get = |path| Request::get(path).body(Body::empty()).unwrap();
+        let checks: Checks = Arc::new(RwLock::new(None));
+
+        let response = handle(checks.clone(), get("/healthz")).await;
+        assert_eq!(StatusCode::OK, response.status());
+        let response = handle(checks.clone(), get("/readyz")).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+
+        *checks.write().unwrap() =
+            Some(vec![Arc::new(Fixed("store", true)) as Arc<dyn HealthCheck>]);
+        let response = handle(checks, get("/readyz")).await;
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[tokio::test]
+    async fn reports_every_component() {
+        let store: Arc<dyn HealthCheck> = Arc::new(Fixed("store", true));
+        let provider: Arc<dyn HealthCheck> = Arc::new(Fixed("provider/mainnet", false));
+
+        assert!(readiness(&[store.clone()]).await.ready);
+
+        let report = readiness(&[store, provider]).await;
+        assert!(!report.ready);
+        assert_eq!(
+            vec![
+                ComponentHealth {
+                    component: "store".to_owned(),
+                    healthy: true,
+                    error: None,
+                },
+                ComponentHealth {
+                    component: "provider/mainnet".to_owned(),
+                    healthy: false,
+                    error: Some("provider/mainnet is down".to_owned()),
+                },
+            ],
+            report.components
+        );
+    }
+}
@@ -8,6 +8,10 @@ use prometheus::{Encoder, Registry, TextEncoder};
 
 use graph::prelude::{MetricsServer as MetricsServerTrait, *};
 
+mod health;
+
+pub use self::health::{HealthServeError, HealthServer};
+
 /// Errors that may occur when starting the server.
 #[derive(Debug, Fail)]
 pub enum PrometheusMetricsServeError {
@@ -2,9 +2,10 @@ use futures::future::IntoFuture;
 use futures::sync::mpsc;
 use futures03::stream::SplitStream;
 use graphql_parser::parse_query;
+use graphql_parser::query as q;
 use http::StatusCode;
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::str::FromStr;
 use tokio::prelude::{AsyncRead, AsyncWrite};
@@ -14,6 +15,13 @@ use uuid::Uuid;
 
 use graph::prelude::serde_json;
 use graph::prelude::*;
+use graph::util::env::env_var;
+
+use crate::replay::ReplayBuffer;
+
+/// Operation ids that clients want to resume must be at least this long,
+/// so that the ids of different clients do not collide
+const MIN_RESUMABLE_ID_LENGTH: usize = 16;
 
 lazy_static! {
     static ref MAX_OPERATIONS_PER_CONNECTION: Option<usize> =
@@ -22,6 +30,13 @@ lazy_static! {
             .map(|s| usize::from_str(&s).unwrap_or_else(|_| panic!(
                 "failed to parse env var GRAPH_GRAPHQL_MAX_OPERATIONS_PER_CONNECTION"
             )));
+
+    /// How often to send keepalive messages to clients, in seconds; `0`
+    /// turns them off
+    static ref KEEPALIVE_INTERVAL: Option<Duration> =
+        Some(env_var::<u64>("GRAPH_GRAPHQL_WS_KEEPALIVE_INTERVAL").unwrap_or(30))
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -30,6 +45,22 @@ struct StartPayload {
     query: String,
     variables: Option<serde_json::Value>,
     operation_name: Option<String>,
+    extensions: Option<StartExtensions>,
+}
+
+/// Options for a subscription that are not part of the GraphQL query
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StartExtensions {
+    /// The shortest time between two results, in milliseconds
+    min_interval: Option<u64>,
+    /// An id for the operation that is unique across connections, e.g. a
+    /// UUID. Results of operations with such an id say which block they
+    /// are for, and can be replayed when the client resubscribes
+    operation_id: Option<String>,
+    /// When resubscribing, the last block the client saw results for;
+    /// results that were produced after it are sent again first
+    since_block: Option<BlockNumber>,
 }
 
 /// GraphQL/WebSocket message received from a client.
@@ -58,9 +89,19 @@ impl IncomingMessage {
 #[serde(tag = "type", rename_all = "snake_case")]
 enum OutgoingMessage {
     ConnectionAck,
-    Error { id: String, payload: String },
-    Data { id: String, payload: QueryResult },
-    Complete { id: String },
+    #[serde(rename = "ka")]
+    ConnectionKeepAlive,
+    Error {
+        id: String,
+        payload: String,
+    },
+    Data {
+        id: String,
+        payload: QueryResult,
+    },
+    Complete {
+        id: String,
+    },
 }
 
 impl OutgoingMessage {
@@ -101,6 +142,65 @@ fn send_error_string(
         .map_err(|_| WsError::Http(StatusCode::INTERNAL_SERVER_ERROR))
 }
 
+/// Send keepalive messages to the client until the connection is closed,
+/// so that the client and proxies do not consider an idle connection dead
+fn spawn_keepalive(sink: mpsc::UnboundedSender<WsMessage>) {
+    let interval = match *KEEPALIVE_INTERVAL {
+        Some(interval) => interval,
+        None => return,
+    };
+    graph::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            if send_message(&sink, OutgoingMessage::ConnectionKeepAlive).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// What resuming operations needs: the results that can be replayed, and
+/// the store to find out which block a result is for
+pub(crate) struct Resumption<St> {
+    store: Arc<St>,
+    replay: Arc<ReplayBuffer>,
+}
+
+impl<St: Store> Resumption<St> {
+    pub fn new(store: Arc<St>, replay: Arc<ReplayBuffer>) -> Self {
+        Resumption { store, replay }
+    }
+
+    /// Add the block that `deployment` is at to the extensions of
+    /// `result`, and keep it for replaying
+    fn record(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        operation_id: &str,
+        query: &str,
+        mut result: QueryResult,
+    ) -> QueryResult {
+        let block = tokio::task::block_in_place(|| self.store.block_ptr(deployment.clone()))
+            .ok()
+            .flatten()
+            .map(|ptr| ptr.number as BlockNumber);
+        if let Some(block) = block {
+            let mut extensions = match result.extensions.take() {
+                Some(q::Value::Object(extensions)) => extensions,
+                _ => BTreeMap::new(),
+            };
+            let mut block_value = BTreeMap::new();
+            block_value.insert("number".to_owned(), q::Value::Int(block.into()));
+            extensions.insert("block".to_owned(), q::Value::Object(block_value));
+            result.extensions = Some(q::Value::Object(extensions));
+            self.replay
+                .record(deployment, operation_id, query, block, &result);
+        }
+        result
+    }
+}
+
 /// Responsible for recording operation ids and stopping them.
 /// On drop, cancels all operations.
 struct Operations {
@@ -159,18 +259,20 @@ impl Drop for Operations {
 }
 
 /// A WebSocket connection implementing the GraphQL over WebSocket protocol.
-pub struct GraphQlConnection<Q, S> {
+pub struct GraphQlConnection<Q, S, St> {
     id: String,
     logger: Logger,
     graphql_runner: Arc<Q>,
     stream: WebSocketStream<S>,
     schema: Arc<Schema>,
+    resumption: Arc<Resumption<St>>,
 }
 
-impl<Q, S> GraphQlConnection<Q, S>
+impl<Q, S, St> GraphQlConnection<Q, S, St>
 where
     Q: GraphQlRunner,
     S: AsyncRead + AsyncWrite + Send + 'static + Unpin,
+    St: Store,
 {
     /// Creates a new GraphQL subscription service.
     pub(crate) fn new(
@@ -178,6 +280,7 @@ where
         schema: Arc<Schema>,
         stream: WebSocketStream<S>,
         graphql_runner: Arc<Q>,
+        resumption: Arc<Resumption<St>>,
     ) -> Self {
         GraphQlConnection {
             id: Uuid::new_v4().to_string(),
@@ -185,6 +288,7 @@ where
             graphql_runner,
             stream,
             schema,
+            resumption,
         }
     }
 
@@ -195,8 +299,10 @@ where
         connection_id: String,
         schema: Arc<Schema>,
         graphql_runner: Arc<Q>,
+        resumption: Arc<Resumption<St>>,
     ) -> Result<(), WsError> {
         let mut operations = Operations::new(msg_sink.clone());
+        let mut keepalive = false;
 
         // Process incoming messages as long as the WebSocket is open
         while let Some(ws_msg) = ws_stream.try_next().await? {
@@ -215,7 +321,14 @@ where
 
             match msg {
                 // Always accept connection init requests
-                ConnectionInit { payload: _ } => send_message(&msg_sink, ConnectionAck),
+                ConnectionInit { payload: _ } => {
+                    send_message(&msg_sink, ConnectionAck)?;
+                    if !keepalive {
+                        keepalive = true;
+                        spawn_keepalive(msg_sink.clone());
+                    }
+                    Ok(())
+                }
 
                 // When receiving a connection termination request
                 ConnectionTerminate => {
@@ -253,6 +366,35 @@ where
                         }
                     }
 
+                    let StartExtensions {
+                        min_interval,
+                        operation_id,
+                        since_block,
+                    } = payload.extensions.unwrap_or_default();
+                    if let Some(operation_id) = &operation_id {
+                        if operation_id.len() < MIN_RESUMABLE_ID_LENGTH {
+                            return send_error_string(
+                                &msg_sink,
+                                id.clone(),
+                                format!(
+                                    "The operationId must have at least {} characters",
+                                    MIN_RESUMABLE_ID_LENGTH
+                                ),
+                            );
+                        }
+                    }
+                    // Resumed operations only get the results of the same
+                    // query with the same variables
+                    let resumed_query = format!(
+                        "{}\n{}",
+                        payload.query,
+                        payload
+                            .variables
+                            .as_ref()
+                            .map(|variables| variables.to_string())
+                            .unwrap_or_default()
+                    );
+
                     // Parse the GraphQL query document; respond with a GQL_ERROR if
                     // the query is invalid
                     let query = match parse_query(&payload.query) {
@@ -292,13 +434,37 @@ where
 
                     // Construct a subscription
                     let subscription = Subscription {
-                        query: Query::new(schema.clone(), query, variables),
+                        query: Query::new(schema.clone(), query, variables)
+                            .with_operation_name(payload.operation_name),
+                        interval: min_interval.map(Duration::from_millis),
                     };
 
                     debug!(logger, "Start operation";
                            "connection" => &connection_id,
                            "id" => &id);
 
+                    // Send the results that the client missed since it
+                    // last saw results of the operation
+                    if let (Some(operation_id), Some(since_block)) = (&operation_id, since_block) {
+                        let missed = resumption.replay.since(
+                            &schema.id,
+                            operation_id,
+                            &resumed_query,
+                            since_block,
+                        );
+                        debug!(logger, "Resume operation";
+                               "connection" => &connection_id,
+                               "id" => &id,
+                               "since_block" => since_block,
+                               "replayed" => missed.len());
+                        for result in missed {
+                            send_message(
+                                &msg_sink,
+                                OutgoingMessage::from_query_result(id.clone(), result),
+                            )?;
+                        }
+                    }
+
                     // Execute the GraphQL subscription
                     let graphql_runner = graphql_runner.clone();
                     let error_sink = msg_sink.clone();
@@ -307,6 +473,8 @@ where
                     let err_id = id.clone();
                     let err_connection_id = connection_id.clone();
                     let err_logger = logger.clone();
+                    let result_resumption = resumption.clone();
+                    let deployment = schema.id.clone();
                     let run_subscription = graphql_runner
                         .run_subscription(subscription)
                         .map_err(move |e| {
@@ -329,6 +497,15 @@ where
                             // Send results back to the client as GQL_DATA
                             result_stream
                                 .map(move |result| {
+                                    let result = match &operation_id {
+                                        Some(operation_id) => result_resumption.record(
+                                            &deployment,
+                                            operation_id,
+                                            &resumed_query,
+                                            result,
+                                        ),
+                                        None => result,
+                                    };
                                     OutgoingMessage::from_query_result(result_id.clone(), result)
                                 })
                                 .map(WsMessage::from)
@@ -359,10 +536,11 @@ where
     }
 }
 
-impl<Q, S> IntoFuture for GraphQlConnection<Q, S>
+impl<Q, S, St> IntoFuture for GraphQlConnection<Q, S, St>
 where
     Q: GraphQlRunner,
     S: AsyncRead + AsyncWrite + Send + 'static + Unpin,
+    St: Store,
 {
     type Future = Box<dyn Future<Item = Self::Item, Error = Self::Error> + Send>;
     type Item = ();
@@ -385,6 +563,7 @@ where
             self.id.clone(),
             self.schema.clone(),
             self.graphql_runner.clone(),
+            self.resumption.clone(),
         );
 
         // Send outgoing messages asynchronously
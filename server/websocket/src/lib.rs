@@ -1,4 +1,5 @@
 mod connection;
+mod replay;
 mod server;
 
 pub use self::server::SubscriptionServer;
@@ -0,0 +1,179 @@
+//! Recent results of subscriptions that clients gave an `operationId`, so
+//! that a client that lost its connection can resubscribe and get the
+//! results that were produced since the last block it saw. The buffer is
+//! bounded in how many results it keeps per operation and how long it
+//! keeps them after they were produced; older results are not replayed.
+
+use graphql_parser::query as q;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use graph::prelude::{BlockNumber, QueryResult, SubgraphDeploymentId};
+use graph::util::env::env_var;
+
+/// Once this many operations are buffered, the ones that were updated
+/// longest ago are forgotten
+const MAX_OPERATIONS: usize = 10_000;
+
+lazy_static! {
+    /// How many results to keep for each operation
+    static ref REPLAY_RESULTS: usize = env_var("GRAPH_GRAPHQL_WS_REPLAY_RESULTS").unwrap_or(10);
+
+    /// How long to keep results, in seconds
+    static ref REPLAY_TTL: Duration =
+        Duration::from_secs(env_var("GRAPH_GRAPHQL_WS_REPLAY_TTL").unwrap_or(300));
+}
+
+/// A result that can be replayed
+struct Replayable {
+    block: BlockNumber,
+    produced: Instant,
+    data: Option<q::Value>,
+    extensions: Option<q::Value>,
+}
+
+struct Operation {
+    /// The query and variables of the operation; a client that resumes
+    /// the operation with a different query gets no results
+    query: String,
+    results: VecDeque<Replayable>,
+    updated: Instant,
+}
+
+/// The results of resumable operations of all connections of a server
+pub(crate) struct ReplayBuffer {
+    max_results: usize,
+    ttl: Duration,
+    operations: Mutex<HashMap<String, Operation>>,
+}
+
+impl ReplayBuffer {
+    pub fn new(max_results: usize, ttl: Duration) -> Self {
+        ReplayBuffer {
+            max_results,
+            ttl,
+            operations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A buffer with the limits from `GRAPH_GRAPHQL_WS_REPLAY_RESULTS` and
+    /// `GRAPH_GRAPHQL_WS_REPLAY_TTL`
+    pub fn from_env() -> Self {
+        ReplayBuffer::new(*REPLAY_RESULTS, *REPLAY_TTL)
+    }
+
+    fn key(deployment: &SubgraphDeploymentId, operation_id: &str) -> String {
+        format!("{}/{}", deployment, operation_id)
+    }
+
+    /// Remember that `result` was produced for `operation_id` once the
+    /// deployment had reached `block`. Results with errors are not kept
+    pub fn record(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        operation_id: &str,
+        query: &str,
+        block: BlockNumber,
+        result: &QueryResult,
+    ) {
+        if self.max_results == 0 || result.errors.is_some() {
+            return;
+        }
+        let now = Instant::now();
+        let ttl = self.ttl;
+        let mut operations = self.operations.lock().unwrap();
+
+        if operations.len() >= MAX_OPERATIONS {
+            operations.retain(|_, operation| now.duration_since(operation.updated) < ttl);
+        }
+        if operations.len() >= MAX_OPERATIONS {
+            if let Some(oldest) = operations
+                .iter()
+                .min_by_key(|(_, operation)| operation.updated)
+                .map(|(key, _)| key.clone())
+            {
+                operations.remove(&oldest);
+            }
+        }
+
+        let operation = operations
+            .entry(Self::key(deployment, operation_id))
+            .or_insert_with(|| Operation {
+                query: query.to_owned(),
+                results: VecDeque::new(),
+                updated: now,
+            });
+        if operation.query != query {
+            operation.query = query.to_owned();
+            operation.results.clear();
+        }
+        operation.updated = now;
+        operation.results.push_back(Replayable {
+            block,
+            produced: now,
+            data: result.data.clone(),
+            extensions: result.extensions.clone(),
+        });
+        while operation.results.len() > self.max_results {
+            operation.results.pop_front();
+        }
+    }
+
+    /// The results of `operation_id` that were produced after the
+    /// deployment had passed `block`, oldest first
+    pub fn since(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        operation_id: &str,
+        query: &str,
+        block: BlockNumber,
+    ) -> Vec<QueryResult> {
+        let now = Instant::now();
+        let operations = self.operations.lock().unwrap();
+        let operation = match operations.get(&Self::key(deployment, operation_id)) {
+            Some(operation) if operation.query == query => operation,
+            _ => return vec![],
+        };
+        operation
+            .results
+            .iter()
+            .filter(|result| result.block > block && now.duration_since(result.produced) < self.ttl)
+            .map(|result| {
+                let mut replayed = QueryResult::new(result.data.clone());
+                replayed.extensions = result.extensions.clone();
+                replayed
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(n: i32) -> QueryResult {
+        QueryResult::new(Some(q::Value::Int(n.into())))
+    }
+
+    #[test]
+    fn replays_recent_results_of_the_same_query() {
+        let deployment = SubgraphDeploymentId::new("replay").unwrap();
+        let buffer = ReplayBuffer::new(2, Duration::from_secs(60));
+        for block in 1..=3 {
+            buffer.record(&deployment, "op", "query", block, &result(block));
+        }
+
+        let replayed = buffer.since(&deployment, "op", "query", 1);
+        let data: Vec<_> = replayed.into_iter().map(|result| result.data).collect();
+        // Only the last two results are kept
+        assert_eq!(
+            vec![Some(q::Value::Int(2.into())), Some(q::Value::Int(3.into()))],
+            data
+        );
+        assert_eq!(1, buffer.since(&deployment, "op", "query", 2).len());
+        assert!(buffer.since(&deployment, "op", "other query", 0).is_empty());
+        assert!(buffer.since(&deployment, "other", "query", 0).is_empty());
+    }
+}
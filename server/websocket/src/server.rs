@@ -1,19 +1,22 @@
 use graph::data::subgraph::schema::SUBGRAPHS_ID;
 use graph::prelude::{SubscriptionServer as SubscriptionServerTrait, *};
+use graph::util::tls::{self, TlsConfig};
 use http::{HeaderValue, Response, StatusCode};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Mutex;
-use tokio::net::TcpListener;
 use tokio_tungstenite::accept_hdr_async;
 use tokio_tungstenite::tungstenite::handshake::server::Request;
 
-use crate::connection::GraphQlConnection;
+use crate::connection::{GraphQlConnection, Resumption};
+use crate::replay::ReplayBuffer;
 
 /// A GraphQL subscription server based on Hyper / Websockets.
 pub struct SubscriptionServer<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    tls: Option<Arc<TlsConfig>>,
+    replay: Arc<ReplayBuffer>,
 }
 
 impl<Q, S> SubscriptionServer<Q, S>
@@ -26,13 +29,25 @@ where
             logger: logger.new(o!("component" => "SubscriptionServer")),
             graphql_runner,
             store,
+            tls: None,
+            replay: Arc::new(ReplayBuffer::from_env()),
         }
     }
 
-    fn subgraph_id_from_url_path(
+    /// Only accept connections that use TLS with the certificate in `tls`
+    pub fn with_tls(self, tls: Arc<TlsConfig>) -> Self {
+        SubscriptionServer {
+            tls: Some(tls),
+            ..self
+        }
+    }
+
+    fn subgraph_id_from_url(
         store: Arc<S>,
         path: &str,
+        query: Option<&str>,
     ) -> Result<Option<SubgraphDeploymentId>, Error> {
+        let selector = SubgraphVersionSelector::from_url_query(query)?;
         let path_segments = {
             let mut segments = path.split("/");
 
@@ -45,6 +60,18 @@ where
             segments.collect::<Vec<_>>()
         };
 
+        // A `pending` or `version/<label>` suffix takes precedence over
+        // the `version` query parameter
+        if path_segments.starts_with(&["subgraphs", "name"]) {
+            if let Some((name, selector)) = SubgraphVersionSelector::from_path(&path_segments[2..])
+            {
+                return match SubgraphName::new(name) {
+                    Err(()) => Ok(None),
+                    Ok(name) => store.resolve_subgraph_version(name, selector),
+                };
+            }
+        }
+
         match path_segments.as_slice() {
             &["subgraphs"] => Ok(Some(SUBGRAPHS_ID.clone())),
             &["subgraphs", "id", subgraph_id] => Ok(SubgraphDeploymentId::new(subgraph_id).ok()),
@@ -53,7 +80,7 @@ where
 
                 match SubgraphName::new(subgraph_name) {
                     Err(()) => Ok(None),
-                    Ok(subgraph_name) => store.resolve_subgraph_name_to_id(subgraph_name),
+                    Ok(subgraph_name) => store.resolve_subgraph_version(subgraph_name, selector),
                 }
             }
             &["subgraphs", "network", _, _] => {
@@ -61,7 +88,7 @@ where
 
                 match SubgraphName::new(subgraph_name) {
                     Err(()) => Ok(None),
-                    Ok(subgraph_name) => store.resolve_subgraph_name_to_id(subgraph_name),
+                    Ok(subgraph_name) => store.resolve_subgraph_version(subgraph_name, selector),
                 }
             }
             _ => Ok(None),
@@ -76,17 +103,16 @@ where
     S: SubgraphDeploymentStore + Store,
 {
     async fn serve(self, port: u16) {
+        let scheme = if self.tls.is_some() { "wss" } else { "ws" };
         info!(
             self.logger,
-            "Starting GraphQL WebSocket server at: ws://localhost:{}", port
+            "Starting GraphQL WebSocket server at: {}://localhost:{}", scheme, port
         );
 
         let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
-        let mut socket = TcpListener::bind(&addr)
-            .await
-            .expect("Failed to bind WebSocket port");
+        let socket = tls::bind(addr).expect("Failed to bind WebSocket port");
 
-        let mut incoming = socket.incoming();
+        let mut incoming = tls::incoming(self.logger.clone(), socket, self.tls.clone());
         while let Some(stream_res) = incoming.next().await {
             let stream = match stream_res {
                 Ok(stream) => stream,
@@ -100,20 +126,22 @@ where
             let graphql_runner = self.graphql_runner.clone();
             let store = self.store.clone();
             let store2 = self.store.clone();
+            let resumption = Arc::new(Resumption::new(self.store.clone(), self.replay.clone()));
 
             // Subgraph that the request is resolved to (if any)
             let subgraph_id = Arc::new(Mutex::new(None));
             let accept_subgraph_id = subgraph_id.clone();
 
             accept_hdr_async(stream, move |request: &Request, mut response: Response<()>| {
-                // Try to obtain the subgraph ID or name from the URL path.
+                // Try to obtain the subgraph ID or name from the URL.
                 // Return a 404 if the URL path contains no name/ID segment.
                 let path = request.uri().path();
-                let subgraph_id = Self::subgraph_id_from_url_path(store.clone(), path.as_ref())
+                let query = request.uri().query();
+                let subgraph_id = Self::subgraph_id_from_url(store.clone(), path.as_ref(), query)
                     .map_err(|e| {
                         error!(
                             logger,
-                            "Error resolving subgraph ID from URL path";
+                            "Error resolving subgraph ID from URL";
                             "error" => e.to_string()
                         );
 
@@ -163,6 +191,7 @@ where
                             schema,
                             ws_stream,
                             graphql_runner.clone(),
+                            resumption,
                         );
 
                         graph::spawn_allow_panic(service.into_future().compat());
@@ -0,0 +1,34 @@
+//! List the versions of our migrations in `MIGRATIONS`, so that the store
+//! can tell which of them a database is missing. Diesel embeds the
+//! migrations themselves, but does not expose which ones it embedded.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let mut versions = fs::read_dir("migrations")
+        .expect("failed to read the migrations directory")
+        .map(|entry| entry.expect("failed to read the migrations directory"))
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Diesel's version of a migration is the part of its name before
+            // the first `_` without dashes
+            name.split('_').next().unwrap().replace('-', "")
+        })
+        .collect::<Vec<_>>();
+    versions.sort();
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("migrations.rs");
+    fs::write(
+        out,
+        format!(
+            "/// The versions of all migrations, oldest first\n\
+             const MIGRATIONS: &[&str] = &{:?};\n",
+            versions
+        ),
+    )
+    .expect("failed to write the list of migrations");
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -202,6 +202,7 @@ fn print_diesel_tables(layout: &Layout) {
             ColumnType::Bytes | ColumnType::BytesId => "Binary",
             ColumnType::Int => "Integer",
             ColumnType::String | ColumnType::Enum(_) | ColumnType::TSVector(_) => "Text",
+            ColumnType::GeoPoint => "Point",
         }
         .to_owned();
 
@@ -221,6 +222,7 @@ fn print_diesel_tables(layout: &Layout) {
             ColumnType::Bytes | ColumnType::BytesId => "Vec<u8>",
             ColumnType::Int => "i32",
             ColumnType::String | ColumnType::Enum(_) | ColumnType::TSVector(_) => "String",
+            ColumnType::GeoPoint => "(f64, f64)",
         }
         .to_owned();
 
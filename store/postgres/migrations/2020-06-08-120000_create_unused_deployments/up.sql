@@ -0,0 +1,10 @@
+-- Deployments that are not used by any subgraph version anymore and are
+-- candidates for removal. Rows are added by 'graphman unused record' and
+-- marked as removed by 'graphman unused remove'
+create table if not exists unused_deployments (
+  deployment   text primary key,
+  namespace    text not null,
+  entity_count int8,
+  unused_at    timestamptz not null default now(),
+  removed_at   timestamptz
+);
@@ -0,0 +1 @@
+drop table if exists node_heartbeats;
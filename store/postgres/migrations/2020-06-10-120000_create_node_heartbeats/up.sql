@@ -0,0 +1,6 @@
+-- The last time each index node reported that it is alive. Deployments
+-- assigned to nodes whose heartbeat is too old are moved to other nodes
+create table if not exists node_heartbeats (
+  node_id   text primary key,
+  last_seen timestamptz not null default now()
+);
@@ -0,0 +1,2 @@
+alter table subgraphs.subgraph_version
+    drop column if exists label;
@@ -0,0 +1,4 @@
+-- An optional label that a deployment gives a version of a subgraph so
+-- that queries can be routed to it by name
+alter table subgraphs.subgraph_version
+    add column if not exists label text;
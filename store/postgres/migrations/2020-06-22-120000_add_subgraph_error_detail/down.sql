@@ -0,0 +1,6 @@
+alter table subgraphs.subgraph_error
+    drop column if exists data_source;
+alter table subgraphs.subgraph_error
+    drop column if exists deterministic;
+alter table subgraphs.subgraph_error
+    drop column if exists proof_of_indexing;
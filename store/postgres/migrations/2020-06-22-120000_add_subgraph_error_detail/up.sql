@@ -0,0 +1,7 @@
+-- Details about the errors of deployments for indexers that act on them
+alter table subgraphs.subgraph_error
+    add column if not exists data_source text;
+alter table subgraphs.subgraph_error
+    add column if not exists deterministic boolean not null default false;
+alter table subgraphs.subgraph_error
+    add column if not exists proof_of_indexing bytea;
@@ -0,0 +1,10 @@
+-- Files fetched from IPFS, shared by all nodes that use this database
+create table if not exists public.ipfs_cache(
+    path        text primary key,
+    data        bytea not null,
+    size        int8 not null,
+    last_access timestamptz not null default now()
+);
+
+create index if not exists ipfs_cache_last_access
+    on public.ipfs_cache(last_access);
@@ -0,0 +1 @@
+drop table if exists subgraphs.subgraph_deployment_file;
@@ -0,0 +1,6 @@
+-- The manifests and other files of subgraphs that were deployed from a
+-- local directory or an HTTP URL instead of IPFS
+create table if not exists subgraphs.subgraph_deployment_file(
+    id   text primary key,
+    data bytea not null
+);
@@ -0,0 +1,3 @@
+-- The features of the node that each deployment declares it uses
+alter table subgraphs.subgraph_manifest
+    add column if not exists features text[] not null default '{}';
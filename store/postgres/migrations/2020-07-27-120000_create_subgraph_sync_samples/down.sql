@@ -0,0 +1 @@
+drop table if exists subgraph_sync_samples;
@@ -0,0 +1,11 @@
+-- The block that each deployment had reached at points in time, recorded
+-- by the node that indexes it, to estimate how long syncing will take
+create table if not exists subgraph_sync_samples (
+  deployment   text        not null,
+  recorded_at  timestamptz not null default now(),
+  block_number int8        not null,
+  primary key(deployment, recorded_at)
+);
+
+create index if not exists subgraph_sync_samples_recorded_at
+    on subgraph_sync_samples(recorded_at);
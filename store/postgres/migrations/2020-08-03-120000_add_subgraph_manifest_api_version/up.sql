@@ -0,0 +1,3 @@
+-- The version of the GraphQL API semantics that each deployment uses
+alter table subgraphs.subgraph_manifest
+    add column if not exists api_version text not null default '0.0.1';
@@ -0,0 +1,2 @@
+drop table if exists public.api_token_usage;
+drop table if exists public.api_token_quotas;
@@ -0,0 +1,15 @@
+-- Monthly query quotas for API tokens, shared by all query nodes. Tokens
+-- are identified by their hash, never by the token itself
+create table if not exists public.api_token_quotas(
+    token         text primary key,
+    name          text not null unique,
+    monthly_limit int8 not null
+);
+
+-- The number of queries that each token made in a month (`YYYY-MM`)
+create table if not exists public.api_token_usage(
+    token   text not null,
+    month   text not null,
+    queries int8 not null,
+    primary key(token, month)
+);
@@ -0,0 +1 @@
+-- No good reason to drop the earthdistance and cube extensions again
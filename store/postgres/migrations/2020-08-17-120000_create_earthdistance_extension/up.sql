@@ -0,0 +1,5 @@
+-- The `<@>` operator that `GeoPoint` filters use to compute the distance
+-- between points comes from `earthdistance`, which needs `cube`
+-- This requires superuser privileges
+create extension if not exists cube;
+create extension if not exists earthdistance;
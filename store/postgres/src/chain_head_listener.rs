@@ -1,22 +1,98 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
 use tokio::sync::watch;
-use web3::types::H256;
 
 use crate::notification_listener::{NotificationListener, SafeChannelName};
 use graph::prelude::serde_json;
 use graph::prelude::{ChainHeadUpdateListener as ChainHeadUpdateListenerTrait, *};
 use graph_chain_ethereum::BlockIngestorMetrics;
 
+/// The channel through which the chain head updates of one network are
+/// broadcast to all block streams of that network in this process
+struct Watcher {
+    sender: watch::Sender<u64>,
+    receiver: watch::Receiver<u64>,
+}
+
+impl Watcher {
+    fn new() -> Self {
+        // Block number 0 means that there has not been an update yet
+        let (sender, receiver) = watch::channel(0);
+        Watcher { sender, receiver }
+    }
+}
+
+/// Metrics about how chain head updates are fanned out to block streams
+struct FanoutMetrics {
+    subscribers: Box<GaugeVec>,
+    skipped: Box<CounterVec>,
+}
+
+impl FanoutMetrics {
+    fn new(registry: Arc<dyn MetricsRegistry>) -> Self {
+        let subscribers = registry
+            .new_gauge_vec(
+                String::from("chain_head_update_subscribers"),
+                String::from("The number of block streams that follow the head of a network"),
+                HashMap::new(),
+                vec![String::from("network")],
+            )
+            .expect("failed to create `chain_head_update_subscribers` gauge");
+        let skipped = registry
+            .new_counter_vec(
+                String::from("chain_head_update_skipped_blocks"),
+                String::from(
+                    "The number of chain head blocks that block streams did not see an update \
+                     for because they were busy; they still process these blocks",
+                ),
+                HashMap::new(),
+                vec![String::from("network")],
+            )
+            .expect("failed to create `chain_head_update_skipped_blocks` counter");
+        FanoutMetrics {
+            subscribers,
+            skipped,
+        }
+    }
+}
+
+/// Keeps the subscriber count of a network up to date for as long as the
+/// subscription it belongs to exists
+struct Subscriber {
+    network_name: String,
+    metrics: Arc<FanoutMetrics>,
+}
+
+impl Subscriber {
+    fn new(network_name: String, metrics: Arc<FanoutMetrics>) -> Self {
+        metrics
+            .subscribers
+            .with_label_values(&[network_name.as_str()])
+            .inc();
+        Subscriber {
+            network_name,
+            metrics,
+        }
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.metrics
+            .subscribers
+            .with_label_values(&[self.network_name.as_str()])
+            .dec();
+    }
+}
+
 pub struct ChainHeadUpdateListener {
-    /// A receiver that gets all chain head updates for all networks. We
-    /// filter notifications to the desired network in `subscribe`. Using
-    /// a `watch::Receiver` here has the downside that the notification for
-    /// one network can make the notification for another network disappear
-    /// if events aren't processed fast enough. If that happens, the update
-    /// for the preempted network will happen on the next block. Since even
-    /// the fastest network generates new blocks a few seconds apart, the
-    /// risk for collisions, and in particular sustained collisions is
-    /// very low
-    update_receiver: watch::Receiver<ChainHeadUpdate>,
+    /// One channel per network that gets the number of the latest chain
+    /// head block. Since a `watch` channel only keeps the latest value,
+    /// block streams that are busy processing blocks see fewer updates,
+    /// but the block ingestor never waits for them and, since block streams
+    /// look up the chain head in the store, they do not miss any blocks
+    watchers: Arc<RwLock<HashMap<String, Watcher>>>,
+    metrics: Arc<FanoutMetrics>,
     _listener: NotificationListener,
 }
 
@@ -24,6 +100,7 @@ impl ChainHeadUpdateListener {
     pub fn new(logger: &Logger, registry: Arc<dyn MetricsRegistry>, postgres_url: String) -> Self {
         let logger = logger.new(o!("component" => "ChainHeadUpdateListener"));
         let ingestor_metrics = Arc::new(BlockIngestorMetrics::new(registry.clone()));
+        let metrics = Arc::new(FanoutMetrics::new(registry));
 
         // Create a Postgres notification listener for chain head updates
         let mut listener = NotificationListener::new(
@@ -32,16 +109,12 @@ impl ChainHeadUpdateListener {
             SafeChannelName::i_promise_this_is_safe("chain_head_updates"),
         );
 
-        let none_update = ChainHeadUpdate {
-            network_name: "none".to_owned(),
-            head_block_hash: H256::zero(),
-            head_block_number: 0,
-        };
-        let (update_sender, update_receiver) = watch::channel(none_update);
-        Self::listen(logger, ingestor_metrics, &mut listener, update_sender);
+        let watchers = Arc::new(RwLock::new(HashMap::new()));
+        Self::listen(logger, ingestor_metrics, &mut listener, watchers.clone());
 
         ChainHeadUpdateListener {
-            update_receiver,
+            watchers,
+            metrics,
 
             // We keep the listener around to tie its stream's lifetime to
             // that of the chain head update listener and prevent it from
@@ -54,7 +127,7 @@ impl ChainHeadUpdateListener {
         logger: Logger,
         metrics: Arc<BlockIngestorMetrics>,
         listener: &mut NotificationListener,
-        update_sender: watch::Sender<ChainHeadUpdate>,
+        watchers: Arc<RwLock<HashMap<String, Watcher>>>,
     ) {
         let logger = logger.clone();
 
@@ -91,7 +164,18 @@ impl ChainHeadUpdateListener {
                         "head_block_number" => &update.head_block_number,
                     );
 
-                    futures03::future::ready(update_sender.broadcast(update).map_err(|_| ()))
+                    let mut watchers = watchers.write().unwrap();
+                    let watcher = watchers
+                        .entry(update.network_name)
+                        .or_insert_with(Watcher::new);
+                    // Sending only fails if there are no receivers, but the
+                    // watcher always holds on to one
+                    futures03::future::ready(
+                        watcher
+                            .sender
+                            .broadcast(update.head_block_number)
+                            .map_err(|_| ()),
+                    )
                 }),
         );
 
@@ -102,16 +186,35 @@ impl ChainHeadUpdateListener {
 
 impl ChainHeadUpdateListenerTrait for ChainHeadUpdateListener {
     fn subscribe(&self, network_name: String) -> ChainHeadUpdateStream {
-        let f = move |update: ChainHeadUpdate| {
-            if update.network_name == network_name {
-                futures03::future::ready(Some(()))
-            } else {
-                futures03::future::ready(None)
+        let receiver = self
+            .watchers
+            .write()
+            .unwrap()
+            .entry(network_name.clone())
+            .or_insert_with(Watcher::new)
+            .receiver
+            .clone();
+        let subscriber = Subscriber::new(network_name, self.metrics.clone());
+        let mut last_seen = None;
+
+        let f = move |number: u64| {
+            if number == 0 {
+                return futures03::future::ready(None);
+            }
+            if let Some(last_seen) = last_seen {
+                if number > last_seen + 1 {
+                    subscriber
+                        .metrics
+                        .skipped
+                        .with_label_values(&[subscriber.network_name.as_str()])
+                        .inc_by((number - last_seen - 1) as f64);
+                }
             }
+            last_seen = Some(number);
+            futures03::future::ready(Some(()))
         };
         Box::new(
-            self.update_receiver
-                .clone()
+            receiver
                 .filter_map(f)
                 .map(Result::<_, ()>::Ok)
                 .boxed()
@@ -0,0 +1,1143 @@
+//! Operations that support the `graphman` operator tool. They all work
+//! directly on a database connection and bypass the `Store`, so that they
+//! can be run without connecting to an Ethereum node.
+//!
+//! These functions exist so that routine maintenance does not require
+//! hand-written SQL. Apart from the handling of unused deployments, which
+//! the automatic retirement of versions relies on, nothing in here should
+//! be used by `graph-node` itself.
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::sql_types::{Array, BigInt, Binary, Bool, Nullable, Text};
+use diesel::{sql_query, Connection as _, RunQueryDsl};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+
+use graph::data::subgraph::schema::{
+    SubgraphDeploymentAssignmentEntity, SubgraphDeploymentEntity, TypedEntity as _, SUBGRAPHS_ID,
+};
+use graph::prelude::{
+    format_err, serde_json, web3::types::H256, Entity, EthereumBlockPointer, Logger,
+    MetadataOperation, NodeId, StoreError, StoreEvent, SubgraphDeploymentId,
+};
+
+use crate::entities::{self as e, Storage};
+use crate::heartbeat;
+use crate::metadata;
+use crate::relational::{Layout, SqlName, Table};
+
+pub type PooledPgConnection = PooledConnection<ConnectionManager<PgConnection>>;
+
+/// Summary information about a deployment, and how it is used by
+/// subgraph names
+#[derive(QueryableByName, Debug)]
+pub struct DeploymentInfo {
+    #[sql_type = "Nullable<Text>"]
+    pub name: Option<String>,
+    /// One of `current`, `pending`, or `unused`
+    #[sql_type = "Text"]
+    pub status: String,
+    #[sql_type = "Text"]
+    pub deployment: String,
+    /// The name of the database schema, `sgdNNN`
+    #[sql_type = "Text"]
+    pub namespace: String,
+    #[sql_type = "Nullable<Text>"]
+    pub node_id: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub network: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub health: Option<String>,
+    #[sql_type = "Nullable<Bool>"]
+    pub synced: Option<bool>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub latest_block: Option<i64>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub chain_head_block: Option<i64>,
+}
+
+/// The query for `DeploymentInfo`; users need to add a `where` clause
+const DEPLOYMENT_INFO_QUERY: &str = "
+        select s.name,
+               case when s.current_version = v.id then 'current'
+                    when s.pending_version = v.id then 'pending'
+                    else 'unused' end as status,
+               ds.subgraph as deployment,
+               ds.name as namespace,
+               sd.node_id,
+               sd.network,
+               sd.health::text as health,
+               sd.synced,
+               sd.latest_ethereum_block_number::int8 as latest_block,
+               sd.ethereum_head_block_number::int8 as chain_head_block
+          from public.deployment_schemas ds
+          left join subgraphs.subgraph_deployment_detail sd
+            on (sd.id = ds.subgraph and upper_inf(sd.block_range))
+          left join subgraphs.subgraph_version v
+            on (v.deployment = ds.subgraph and upper_inf(v.block_range))
+          left join subgraphs.subgraph s
+            on (s.id = v.subgraph and upper_inf(s.block_range))";
+
+/// Find all deployments that match `search`, which can be a subgraph
+/// name, a deployment id, or the name of a database schema (`sgdNNN`)
+pub fn deployments(conn: &PgConnection, search: &str) -> Result<Vec<DeploymentInfo>, StoreError> {
+    let query = format!(
+        "{}
+         where ds.subgraph != 'subgraphs'
+           and $1 in (s.name, ds.subgraph, ds.name)
+         order by s.name, ds.subgraph",
+        DEPLOYMENT_INFO_QUERY
+    );
+    Ok(sql_query(query).bind::<Text, _>(search).load(conn)?)
+}
+
+/// Find all deployments that are the current or pending version of some
+/// subgraph
+pub fn active_deployments(conn: &PgConnection) -> Result<Vec<DeploymentInfo>, StoreError> {
+    let query = format!(
+        "{}
+         where ds.subgraph != 'subgraphs'
+           and (s.current_version = v.id or s.pending_version = v.id)
+         order by s.name, ds.subgraph",
+        DEPLOYMENT_INFO_QUERY
+    );
+    Ok(sql_query(query).load(conn)?)
+}
+
+/// Return how many deployments are assigned to each node
+pub fn assignment_counts(conn: &PgConnection) -> Result<HashMap<String, i64>, StoreError> {
+    heartbeat::assignment_counts(conn)
+}
+
+/// Resolve `search` to exactly one deployment. It is an error if the
+/// search matches no deployments, or matches more than one
+pub fn locate(conn: &PgConnection, search: &str) -> Result<SubgraphDeploymentId, StoreError> {
+    let mut ids = deployments(conn, search)?
+        .into_iter()
+        .map(|info| info.deployment)
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    match ids.len() {
+        0 => Err(format_err!("no deployment matches `{}`", search).into()),
+        1 => {
+            let id = ids.pop().unwrap();
+            SubgraphDeploymentId::new(id.clone()).map_err(|()| {
+                StoreError::Unknown(format_err!("`{}` is not a valid deployment id", id))
+            })
+        }
+        _ => Err(format_err!(
+            "`{}` is ambiguous and matches deployments {}",
+            search,
+            ids.join(", ")
+        )
+        .into()),
+    }
+}
+
+fn entity_conn<'a>(
+    conn: &'a PooledPgConnection,
+    subgraph: &SubgraphDeploymentId,
+) -> Result<e::Connection<'a>, StoreError> {
+    let storage = Arc::new(Storage::new(conn, subgraph)?);
+    let metadata = Arc::new(Storage::new(conn, &*SUBGRAPHS_ID)?);
+    Ok(e::Connection::new(conn.into(), storage, metadata))
+}
+
+fn relational_layout<'a>(
+    storage: &'a Storage,
+    id: &SubgraphDeploymentId,
+) -> Result<&'a Layout, StoreError> {
+    match storage {
+        Storage::Relational(layout) => Ok(layout),
+        Storage::Json(_) => Err(format_err!(
+            "deployment `{}` uses JSONB storage which is not supported by this operation",
+            id
+        )
+        .into()),
+    }
+}
+
+fn assignment_key(id: &SubgraphDeploymentId) -> graph::prelude::EntityKey {
+    MetadataOperation::entity_key(
+        SubgraphDeploymentAssignmentEntity::TYPENAME.to_owned(),
+        id.to_string(),
+    )
+}
+
+/// Return the node to which `id` is assigned, if any
+pub fn assigned_node(
+    conn: &PooledPgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<NodeId>, StoreError> {
+    let econn = entity_conn(conn, &*SUBGRAPHS_ID)?;
+    let key = assignment_key(id);
+    let assignment = econn.find_metadata(&key.entity_type, &key.entity_id)?;
+    Ok(assignment
+        .and_then(|entity| entity.get("nodeId").cloned())
+        .and_then(|node| node.as_string())
+        .and_then(|node| NodeId::new(node).ok()))
+}
+
+/// Remove the assignment of `id` so that no index node indexes it
+/// anymore. Returns `false` if the deployment was not assigned
+pub fn unassign(conn: &PooledPgConnection, id: &SubgraphDeploymentId) -> Result<bool, StoreError> {
+    let econn = entity_conn(conn, &*SUBGRAPHS_ID)?;
+    let key = assignment_key(id);
+    let ops = vec![MetadataOperation::Remove {
+        entity: key.entity_type.clone(),
+        id: key.entity_id.clone(),
+    }];
+    let count = econn.transaction(|| econn.delete(&key, None))?;
+    if count > 0 {
+        let event: StoreEvent = ops.into();
+        econn.transaction(|| econn.send_store_event(&event))?;
+    }
+    Ok(count > 0)
+}
+
+/// Assign `id` to `node`, regardless of whether it is currently assigned
+/// to a different node or not assigned at all
+pub fn reassign(
+    conn: &PooledPgConnection,
+    id: &SubgraphDeploymentId,
+    node: &NodeId,
+) -> Result<(), StoreError> {
+    let econn = entity_conn(conn, &*SUBGRAPHS_ID)?;
+    let key = assignment_key(id);
+    let ops = SubgraphDeploymentAssignmentEntity::new(node.clone()).write_operations(id);
+    econn.transaction(|| -> Result<(), StoreError> {
+        for op in ops.clone() {
+            if let MetadataOperation::Set { data, .. } = op {
+                match econn.find_metadata(&key.entity_type, &key.entity_id)? {
+                    Some(mut entity) => {
+                        entity.merge_remove_null_fields(data);
+                        econn.update(&key, entity, None)?;
+                    }
+                    None => {
+                        let mut entity = Entity::new();
+                        entity.merge_remove_null_fields(data);
+                        econn.insert(&key, entity, None)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    })?;
+    let event: StoreEvent = ops.into();
+    econn.transaction(|| econn.send_store_event(&event))
+}
+
+/// Update the block pointer of `id` to `block` with `econn`, which must be
+/// a connection for `id`, and return the corresponding store event
+fn set_block_ptr(
+    econn: &e::Connection,
+    id: &SubgraphDeploymentId,
+    block: EthereumBlockPointer,
+) -> Result<StoreEvent, StoreError> {
+    let ops = SubgraphDeploymentEntity::update_ethereum_block_pointer_operations(id, block);
+    for op in ops.clone() {
+        if let MetadataOperation::Update { entity, id, data } = op {
+            let key = MetadataOperation::entity_key(entity, id);
+            econn.update_metadata(&key, &data)?;
+        }
+    }
+    Ok(ops.into())
+}
+
+/// Rewind the deployment `id` so that its latest block is `block`. All
+/// entity versions that were created after `block` are deleted. The
+/// deployment must not be assigned to any node while it is rewound, since
+/// the index node would otherwise continue to write to it
+pub fn rewind(
+    conn: &PooledPgConnection,
+    id: &SubgraphDeploymentId,
+    block: EthereumBlockPointer,
+) -> Result<(), StoreError> {
+    if let Some(node) = assigned_node(conn, id)? {
+        return Err(format_err!(
+            "deployment `{}` is assigned to `{}`; unassign it before rewinding",
+            id,
+            node
+        )
+        .into());
+    }
+    if let Some((_, graft_block)) = metadata::deployment_graft(conn, id)? {
+        if graft_block.number > block.number {
+            return Err(format_err!(
+                "can not rewind `{}` to block {} since it was grafted at block {}",
+                id,
+                block.number,
+                graft_block.number
+            )
+            .into());
+        }
+    }
+
+    let econn = entity_conn(conn, id)?;
+    if !econn.uses_relational_schema() {
+        return Err(format_err!(
+            "deployment `{}` uses JSONB storage and can not be rewound",
+            id
+        )
+        .into());
+    }
+    // `revert_block` removes everything including the block passed to
+    // it. We want to keep `block` and therefore revert `block+1`. For
+    // relational storage, only the block number matters
+    let revert_from = EthereumBlockPointer {
+        hash: H256::zero(),
+        number: block.number + 1,
+    };
+    let (event, metadata_event) = econn.transaction(|| -> Result<_, StoreError> {
+        let metadata_event = set_block_ptr(&econn, id, block)?;
+        let (event, count) = econn.revert_block(&revert_from)?;
+        econn.update_entity_count(count)?;
+        Ok((event, metadata_event))
+    })?;
+    econn.transaction(|| {
+        econn.send_store_event(&metadata_event)?;
+        econn.send_store_event(&event)
+    })
+}
+
+/// Copy the data of the deployment `src` as of `block` into the deployment
+/// `dst`. The destination must be a freshly deployed, unassigned
+/// deployment whose schema is compatible with the source; this is the
+/// same copying that grafting performs when a deployment starts
+pub fn copy(
+    logger: &Logger,
+    conn: &PooledPgConnection,
+    src: &SubgraphDeploymentId,
+    dst: &SubgraphDeploymentId,
+    block: EthereumBlockPointer,
+) -> Result<(), StoreError> {
+    if let Some(node) = assigned_node(conn, dst)? {
+        return Err(format_err!(
+            "deployment `{}` is assigned to `{}`; unassign it before copying into it",
+            dst,
+            node
+        )
+        .into());
+    }
+    let dst_storage = Storage::new(conn, dst)?;
+    let src_storage = Storage::new(conn, src)?;
+    let dst_layout = relational_layout(&dst_storage, dst)?;
+    let src_layout = relational_layout(&src_storage, src)?;
+
+    let errors = dst_layout.can_copy_from(src_layout);
+    if !errors.is_empty() {
+        return Err(format_err!(
+            "the schema of `{}` is not compatible with `{}`:\n  {}",
+            dst,
+            src,
+            errors.join("\n  ")
+        )
+        .into());
+    }
+
+    let econn = entity_conn(conn, dst)?;
+    let metadata_storage = Storage::new(conn, &*SUBGRAPHS_ID)?;
+    let metadata_layout = relational_layout(&metadata_storage, &*SUBGRAPHS_ID)?;
+    let event = econn.transaction(|| -> Result<_, StoreError> {
+        dst_layout.copy_from(logger, conn, src_layout, block, metadata_layout)?;
+        set_block_ptr(&econn, dst, block)
+    })?;
+    econn.transaction(|| econn.send_store_event(&event))
+}
+
+/// A deployment that is not used by any subgraph version anymore
+#[derive(QueryableByName, Debug)]
+pub struct UnusedDeployment {
+    #[sql_type = "Text"]
+    pub deployment: String,
+    #[sql_type = "Text"]
+    pub namespace: String,
+    /// When the deployment was recorded as unused, as text
+    #[sql_type = "Text"]
+    pub unused_at: String,
+    #[sql_type = "Nullable<Text>"]
+    pub removed_at: Option<String>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub entity_count: Option<i64>,
+}
+
+/// Record all deployments that are neither referenced by a subgraph
+/// version nor assigned to a node in `unused_deployments` and return the
+/// deployments that were newly recorded
+pub fn record_unused(conn: &PgConnection) -> Result<Vec<UnusedDeployment>, StoreError> {
+    let query = "
+        insert into unused_deployments(deployment, namespace, entity_count)
+        select ds.subgraph, ds.name, sd.entity_count::int8
+          from public.deployment_schemas ds
+          left join subgraphs.subgraph_deployment sd
+            on (sd.id = ds.subgraph and upper_inf(sd.block_range))
+         where ds.subgraph != 'subgraphs'
+           and not exists (select 1 from subgraphs.subgraph_version v
+                            where v.deployment = ds.subgraph
+                              and upper_inf(v.block_range))
+           and not exists (select 1 from subgraphs.subgraph_deployment_assignment a
+                            where a.id = ds.subgraph
+                              and upper_inf(a.block_range))
+        on conflict(deployment) do nothing
+        returning deployment, namespace, unused_at::text,
+                  removed_at::text, entity_count";
+    Ok(sql_query(query).load(conn)?)
+}
+
+/// List deployments recorded as unused. Unless `all` is `true`, only
+/// deployments that have not been removed yet are listed
+pub fn list_unused(conn: &PgConnection, all: bool) -> Result<Vec<UnusedDeployment>, StoreError> {
+    let query = "
+        select deployment, namespace, unused_at::text,
+               removed_at::text, entity_count
+          from unused_deployments
+         where $1 or removed_at is null
+         order by unused_at, deployment";
+    Ok(sql_query(query).bind::<Bool, _>(all).load(conn)?)
+}
+
+/// Delete the data and all metadata for the deployment `id`, which must
+/// have been recorded as unused and must still not be used by any
+/// subgraph version. This can not be undone
+pub fn remove_unused(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+    #[derive(QueryableByName)]
+    struct Id {
+        #[sql_type = "Text"]
+        id: String,
+    }
+
+    let still_unused = "
+        select u.deployment as id
+          from unused_deployments u
+         where u.deployment = $1
+           and u.removed_at is null
+           and not exists (select 1 from subgraphs.subgraph_version v
+                            where v.deployment = u.deployment
+                              and upper_inf(v.block_range))
+           and not exists (select 1 from subgraphs.subgraph_deployment_assignment a
+                            where a.id = u.deployment
+                              and upper_inf(a.block_range))";
+
+    conn.transaction(|| -> Result<(), StoreError> {
+        let unused: Vec<Id> = sql_query(still_unused)
+            .bind::<Text, _>(id.as_str())
+            .load(conn)?;
+        if unused.is_empty() {
+            return Err(format_err!(
+                "deployment `{}` is not recorded as unused or is in use again",
+                id
+            )
+            .into());
+        }
+
+        // Find the tables for all metadata from the layout of the
+        // subgraph of subgraphs. Static metadata has ids that start with
+        // the deployment id, and metadata subordinate to dynamic data
+        // sources has ids that start with the id of the data source
+        let metadata = Storage::new(conn, &*SUBGRAPHS_ID)?;
+        let layout = relational_layout(&metadata, &*SUBGRAPHS_ID)?;
+        let dds = dynamic_data_sources(conn, id)?;
+        for table in layout.tables.values() {
+            // The deployment detail is a view, not a table
+            if table.object == "SubgraphDeploymentDetail" {
+                continue;
+            }
+            let query = format!(
+                "delete from {} t where {}",
+                table.qualified_name,
+                owned_by("$1", "$2")
+            );
+            sql_query(query)
+                .bind::<Text, _>(id.as_str())
+                .bind::<Array<Text>, _>(&dds)
+                .execute(conn)?;
+        }
+
+        e::drop_schema(conn, id)?;
+        sql_query("update unused_deployments set removed_at = now() where deployment = $1")
+            .bind::<Text, _>(id.as_str())
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// The condition that the metadata row `t` belongs to the deployment whose
+/// id is in the bind variable `deployment`, or to one of the dynamic data
+/// sources whose ids are in the array bind variable `dds`. Metadata ids are
+/// the id of their owner, possibly followed by `-` and a suffix like
+/// `manifest`. Comparing the whole id of the owner rather than a prefix of
+/// a fixed length keeps this from depending on how long these ids are
+fn owned_by(deployment: &str, dds: &str) -> String {
+    format!(
+        "(t.id = {d} or left(t.id, length({d}) + 1) = {d} || '-'
+          or exists (select 1 from unnest({dds}::text[]) as dds(id)
+                      where t.id = dds.id
+                         or left(t.id, length(dds.id) + 1) = dds.id || '-'))",
+        d = deployment,
+        dds = dds
+    )
+}
+
+/// Return the ids of the dynamic data sources of `id`
+fn dynamic_data_sources(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Id {
+        #[sql_type = "Text"]
+        id: String,
+    }
+
+    let query = "
+        select id from subgraphs.dynamic_ethereum_contract_data_source
+         where deployment = $1";
+    Ok(sql_query(query)
+        .bind::<Text, _>(id.as_str())
+        .load::<Id>(conn)?
+        .into_iter()
+        .map(|dds| dds.id)
+        .collect())
+}
+
+/// The version of the file format that `dump` writes
+const DUMP_FORMAT_VERSION: u64 = 1;
+
+/// How many rows `dump` reads in one query
+const DUMP_BATCH_SIZE: i64 = 1000;
+
+/// Metadata that is not part of a dump: the names and versions of a
+/// subgraph and the node a deployment is assigned to belong to the
+/// environment the deployment is in, not to the deployment
+const UNDUMPED_METADATA: &[&str] = &[
+    "Subgraph",
+    "SubgraphVersion",
+    "SubgraphDeploymentAssignment",
+    "SubgraphDeploymentDetail",
+];
+
+fn dump_error(e: impl std::fmt::Display) -> StoreError {
+    format_err!("invalid deployment dump: {}", e).into()
+}
+
+/// Write all rows of `table` to `out`, in batches of one JSON object per
+/// line. If `owner` is given, only write the metadata rows that belong to
+/// the deployment or one of its dynamic data sources
+fn dump_table(
+    conn: &PgConnection,
+    out: &mut dyn Write,
+    kind: &str,
+    table: &Table,
+    owner: Option<(&SubgraphDeploymentId, &[String])>,
+) -> Result<usize, StoreError> {
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "BigInt"]
+        vid: i64,
+        #[sql_type = "Text"]
+        row: String,
+    }
+
+    let filter = match owner {
+        Some(_) => format!("and {}", owned_by("$3", "$4")),
+        None => String::new(),
+    };
+    let query = format!(
+        "select vid, (to_jsonb(t) - 'vid')::text as row
+           from {} t
+          where vid > $1 {}
+          order by vid
+          limit $2",
+        table.qualified_name, filter
+    );
+
+    let mut last_vid = -1;
+    let mut count = 0;
+    loop {
+        let batch = sql_query(query.as_str())
+            .bind::<BigInt, _>(last_vid)
+            .bind::<BigInt, _>(DUMP_BATCH_SIZE);
+        let rows: Vec<Row> = match owner {
+            Some((id, dds)) => batch
+                .bind::<Text, _>(id.as_str())
+                .bind::<Array<Text>, _>(dds)
+                .load(conn)?,
+            None => batch.load(conn)?,
+        };
+        let last = match rows.last() {
+            Some(last) => last.vid,
+            None => return Ok(count),
+        };
+        let header = serde_json::json!({ "kind": kind, "table": table.name.as_str() });
+        let header = header.to_string();
+        // Splice the rows, which are already JSON, into the object
+        let line = format!(
+            "{},\"rows\":[{}]}}\n",
+            &header[..header.len() - 1],
+            rows.iter()
+                .map(|row| row.row.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        out.write_all(line.as_bytes()).map_err(dump_error)?;
+        count += rows.len();
+        last_vid = last;
+    }
+}
+
+/// Insert the JSON `rows` into `table`, which lives in the database
+/// schema `schema`
+fn restore_rows(
+    conn: &PgConnection,
+    schema: &str,
+    table: &Table,
+    rows: &serde_json::Value,
+) -> Result<usize, StoreError> {
+    #[derive(QueryableByName)]
+    struct Column {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    let columns = sql_query(
+        "select column_name::text as name
+           from information_schema.columns
+          where table_schema = $1 and table_name = $2 and column_name != 'vid'
+          order by ordinal_position",
+    )
+    .bind::<Text, _>(schema)
+    .bind::<Text, _>(table.name.as_str())
+    .load::<Column>(conn)?
+    .into_iter()
+    .map(|column| format!("\"{}\"", column.name))
+    .collect::<Vec<_>>()
+    .join(", ");
+    let query = format!(
+        "insert into {table}({columns})
+         select {columns} from jsonb_populate_recordset(null::{table}, $1::jsonb)",
+        table = table.qualified_name,
+        columns = columns
+    );
+    Ok(sql_query(query)
+        .bind::<Text, _>(rows.to_string())
+        .execute(conn)?)
+}
+
+/// Write the deployment `id` to `out`: its metadata, including its
+/// dynamic data sources and block pointer, and all versions of all its
+/// entities. The dump is read in one transaction so that it is consistent
+/// even while the deployment is being indexed. Returns the number of rows
+/// that were written
+pub fn dump(
+    conn: &PooledPgConnection,
+    id: &SubgraphDeploymentId,
+    out: &mut dyn Write,
+) -> Result<usize, StoreError> {
+    let storage = Storage::new(conn, id)?;
+    let layout = relational_layout(&storage, id)?;
+    let metadata = Storage::new(conn, &*SUBGRAPHS_ID)?;
+    let metadata_layout = relational_layout(&metadata, &*SUBGRAPHS_ID)?;
+
+    let mut metadata_tables = metadata_layout
+        .tables
+        .values()
+        .filter(|table| !UNDUMPED_METADATA.contains(&table.object.as_str()))
+        .collect::<Vec<_>>();
+    metadata_tables.sort_by(|a, b| a.name.as_str().cmp(b.name.as_str()));
+    let mut data_tables = layout.tables.values().collect::<Vec<_>>();
+    data_tables.sort_by(|a, b| a.name.as_str().cmp(b.name.as_str()));
+
+    conn.transaction(|| -> Result<usize, StoreError> {
+        conn.batch_execute("set transaction isolation level repeatable read, read only")?;
+        let header = serde_json::json!({
+            "kind": "header",
+            "version": DUMP_FORMAT_VERSION,
+            "deployment": id.as_str(),
+        });
+        writeln!(out, "{}", header).map_err(dump_error)?;
+
+        let dds = dynamic_data_sources(conn, id)?;
+        let mut count = 0;
+        for table in metadata_tables {
+            count += dump_table(conn, out, "metadata", table, Some((id, &dds)))?;
+        }
+        for table in data_tables {
+            count += dump_table(conn, out, "data", table, None)?;
+        }
+        Ok(count)
+    })
+}
+
+/// Create a deployment from a dump written by `dump`. The deployment must
+/// not exist yet; it is restored without a subgraph name and without
+/// being assigned to a node. Returns the id of the restored deployment
+pub fn restore(
+    conn: &PooledPgConnection,
+    input: &mut dyn BufRead,
+) -> Result<SubgraphDeploymentId, StoreError> {
+    let mut lines = input.lines();
+    let header: serde_json::Value = match lines.next() {
+        Some(line) => serde_json::from_str(&line.map_err(dump_error)?).map_err(dump_error)?,
+        None => return Err(dump_error("the file is empty")),
+    };
+    if header["kind"] != "header" {
+        return Err(dump_error("the file does not start with a header"));
+    }
+    if header["version"] != DUMP_FORMAT_VERSION {
+        return Err(dump_error(format!(
+            "unsupported format version {}",
+            header["version"]
+        )));
+    }
+    let id = header["deployment"]
+        .as_str()
+        .and_then(|id| SubgraphDeploymentId::new(id).ok())
+        .ok_or_else(|| dump_error("the header has no valid deployment id"))?;
+    if !deployments(conn, id.as_str())?.is_empty() {
+        return Err(format_err!("deployment `{}` already exists", id).into());
+    }
+
+    let metadata = Storage::new(conn, &*SUBGRAPHS_ID)?;
+    let metadata_layout = relational_layout(&metadata, &*SUBGRAPHS_ID)?;
+
+    conn.transaction(|| -> Result<(), StoreError> {
+        // The data tables can only be created once the manifest, which
+        // contains the GraphQL schema, has been restored
+        let create_tables = || -> Result<Layout, StoreError> {
+            #[derive(QueryableByName)]
+            struct Name {
+                #[sql_type = "Text"]
+                name: String,
+            }
+
+            let schema = metadata::subgraph_schema(conn, id.clone())?;
+            let name = sql_query(
+                "insert into deployment_schemas(subgraph, version, state)
+                 values ($1, 'relational', 'ready')
+                 returning name",
+            )
+            .bind::<Text, _>(id.as_str())
+            .get_result::<Name>(conn)?
+            .name;
+            conn.batch_execute(&format!("create schema {}", name))?;
+            Layout::create_relational_schema(conn, &schema, name)
+        };
+
+        let mut layout = None;
+        for line in lines {
+            let line: serde_json::Value =
+                serde_json::from_str(&line.map_err(dump_error)?).map_err(dump_error)?;
+            let name = line["table"]
+                .as_str()
+                .ok_or_else(|| dump_error("a line has no table"))?;
+            let (schema, table) = match line["kind"].as_str() {
+                Some("metadata") => {
+                    let table = metadata_layout
+                        .tables
+                        .values()
+                        .filter(|table| !UNDUMPED_METADATA.contains(&table.object.as_str()))
+                        .find(|table| table.name.as_str() == name)
+                        .ok_or_else(|| dump_error(format!("unknown metadata table `{}`", name)))?;
+                    ("subgraphs", table)
+                }
+                Some("data") => {
+                    if layout.is_none() {
+                        layout = Some(create_tables()?);
+                    }
+                    let layout = layout.as_ref().unwrap();
+                    let table = layout
+                        .tables
+                        .values()
+                        .find(|table| table.name.as_str() == name)
+                        .ok_or_else(|| dump_error(format!("unknown entity table `{}`", name)))?;
+                    (layout.catalog.schema.as_str(), table)
+                }
+                _ => return Err(dump_error(format!("unknown kind of line {}", line["kind"]))),
+            };
+            restore_rows(conn, schema, table, &line["rows"])?;
+        }
+        if layout.is_none() {
+            create_tables()?;
+        }
+        Ok(())
+    })?;
+    Ok(id)
+}
+
+/// Size information for one table of a deployment
+#[derive(QueryableByName, Debug)]
+pub struct TableStats {
+    #[sql_type = "Text"]
+    pub table: String,
+    /// The number of rows as estimated by Postgres' statistics
+    #[sql_type = "BigInt"]
+    pub rows: i64,
+    /// Disk usage of the table including indexes and toast, in bytes
+    #[sql_type = "BigInt"]
+    pub bytes: i64,
+}
+
+/// Return size information for all tables of the deployment `id`
+pub fn stats(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<TableStats>, StoreError> {
+    let namespace = e::find_schema_name(conn, id)?;
+    let query = "
+        select c.relname::text as table,
+               c.reltuples::int8 as rows,
+               pg_total_relation_size(c.oid)::int8 as bytes
+          from pg_class c, pg_namespace n
+         where n.oid = c.relnamespace
+           and n.nspname = $1
+           and c.relkind = 'r'
+         order by c.relname";
+    Ok(sql_query(query).bind::<Text, _>(namespace).load(conn)?)
+}
+
+/// An index on a table of a deployment
+#[derive(QueryableByName, Debug)]
+pub struct IndexInfo {
+    #[sql_type = "Text"]
+    pub name: String,
+    #[sql_type = "Text"]
+    pub definition: String,
+}
+
+/// List the indexes on the table for `entity` in deployment `id`
+pub fn list_indexes(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    entity: &str,
+) -> Result<Vec<IndexInfo>, StoreError> {
+    let storage = Storage::new(conn, id)?;
+    let layout = relational_layout(&storage, id)?;
+    let table = layout.table_for_entity(entity)?;
+    let query = "
+        select indexname::text as name, indexdef::text as definition
+          from pg_indexes
+         where schemaname = $1
+           and tablename = $2
+         order by indexname";
+    Ok(sql_query(query)
+        .bind::<Text, _>(layout.catalog.schema.as_str())
+        .bind::<Text, _>(table.name.as_str())
+        .load(conn)?)
+}
+
+/// Create an index on the attributes `fields` of `entity` in deployment
+/// `id` using the index method `method` (e.g., `btree` or `gist`). The
+/// index is built concurrently so that it does not block indexing or
+/// queries, and the name of the index is returned
+pub fn create_index(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    entity: &str,
+    fields: &[String],
+    method: &str,
+) -> Result<String, StoreError> {
+    SqlName::check_valid_identifier(method, "index method")?;
+    let storage = Storage::new(conn, id)?;
+    let layout = relational_layout(&storage, id)?;
+    let table = layout.table_for_entity(entity)?;
+    let columns = fields
+        .iter()
+        .map(|field| table.column_for_field(field).map(|column| &column.name))
+        .collect::<Result<Vec<_>, _>>()?;
+    let name = format!(
+        "manual_{}_{}",
+        table.name.as_str(),
+        columns
+            .iter()
+            .map(|column| column.as_str())
+            .collect::<Vec<_>>()
+            .join("_")
+    );
+    // Postgres limits identifiers to 63 bytes and would silently
+    // truncate longer names
+    let name: String = name.chars().take(63).collect();
+    let query = format!(
+        "create index concurrently if not exists \"{}\" on {} using {}({})",
+        name,
+        table.qualified_name,
+        method,
+        columns
+            .iter()
+            .map(|column| column.quoted())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    // `create index concurrently` can not run inside a transaction
+    conn.batch_execute(&query)?;
+    Ok(name)
+}
+
+/// A chain that the store knows about
+#[derive(QueryableByName, Debug)]
+pub struct ChainInfo {
+    #[sql_type = "Text"]
+    pub name: String,
+    #[sql_type = "Nullable<Text>"]
+    pub net_version: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub genesis_block_hash: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub head_block_hash: Option<String>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub head_block_number: Option<i64>,
+}
+
+/// List all chains in `ethereum_networks`
+pub fn chains(conn: &PgConnection) -> Result<Vec<ChainInfo>, StoreError> {
+    let query = "
+        select name, net_version, genesis_block_hash,
+               head_block_hash, head_block_number
+          from ethereum_networks
+         order by name";
+    Ok(sql_query(query).load(conn)?)
+}
+
+/// The version of the file format that `export_chain` writes
+const CHAIN_EXPORT_FORMAT_VERSION: u64 = 1;
+
+fn chain_export_error(e: impl std::fmt::Display) -> StoreError {
+    format_err!("invalid chain export: {}", e).into()
+}
+
+/// Splice `rows`, which are JSON objects, into a line of `kind`
+fn chain_export_line(kind: &str, rows: &[String]) -> String {
+    format!("{{\"kind\":\"{}\",\"rows\":[{}]}}\n", kind, rows.join(","))
+}
+
+/// Write the blocks of the chain `name` with numbers from `from` to `to`,
+/// and the cached results of contract calls made at these blocks, to `out`
+/// so that `import_chain` can prime the caches of another installation
+/// with them. Blocks that do not have a number in that range are not
+/// exported. Contract calls are cached for all chains together, and all
+/// calls at these block numbers are exported, whichever chain they were
+/// made on. Returns the number of blocks and calls that were written
+pub fn export_chain(
+    conn: &PgConnection,
+    name: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+    out: &mut dyn Write,
+) -> Result<(usize, usize), StoreError> {
+    #[derive(QueryableByName)]
+    struct Block {
+        #[sql_type = "BigInt"]
+        number: i64,
+        #[sql_type = "Text"]
+        hash: String,
+        #[sql_type = "Text"]
+        row: String,
+    }
+
+    #[derive(QueryableByName)]
+    struct Call {
+        #[sql_type = "Binary"]
+        id: Vec<u8>,
+        #[sql_type = "Text"]
+        row: String,
+    }
+
+    let chain = chains(conn)?
+        .into_iter()
+        .find(|chain| chain.name == name)
+        .ok_or_else(|| format_err!("unknown chain `{}`", name))?;
+    let from = from.unwrap_or(0);
+    let to = to.unwrap_or(i64::max_value());
+
+    conn.transaction(|| -> Result<(usize, usize), StoreError> {
+        conn.batch_execute("set transaction isolation level repeatable read, read only")?;
+        let header = serde_json::json!({
+            "kind": "header",
+            "version": CHAIN_EXPORT_FORMAT_VERSION,
+            "chain": chain.name,
+            "net_version": chain.net_version,
+            "genesis_block_hash": chain.genesis_block_hash,
+        });
+        writeln!(out, "{}", header).map_err(chain_export_error)?;
+
+        let mut blocks = 0;
+        let mut last = (-1, String::new());
+        loop {
+            let rows = sql_query(
+                "select number, hash::text as hash,
+                        jsonb_build_object('hash', hash, 'number', number,
+                                           'parent_hash', parent_hash, 'data', data)::text as row
+                   from ethereum_blocks
+                  where network_name = $1
+                    and number between $2 and $3
+                    and (number, hash) > ($4, $5)
+                  order by number, hash
+                  limit $6",
+            )
+            .bind::<Text, _>(name)
+            .bind::<BigInt, _>(from)
+            .bind::<BigInt, _>(to)
+            .bind::<BigInt, _>(last.0)
+            .bind::<Text, _>(&last.1)
+            .bind::<BigInt, _>(DUMP_BATCH_SIZE)
+            .load::<Block>(conn)?;
+            let next = match rows.last() {
+                Some(block) => (block.number, block.hash.clone()),
+                None => break,
+            };
+            let rows = rows.into_iter().map(|block| block.row).collect::<Vec<_>>();
+            out.write_all(chain_export_line("blocks", &rows).as_bytes())
+                .map_err(chain_export_error)?;
+            blocks += rows.len();
+            last = next;
+        }
+
+        let mut calls = 0;
+        let mut last_id = vec![];
+        loop {
+            let rows = sql_query(
+                "select id,
+                        jsonb_build_object('id', encode(id, 'hex'),
+                                           'return_value', encode(return_value, 'hex'),
+                                           'contract_address', encode(contract_address, 'hex'),
+                                           'block_number', block_number)::text as row
+                   from eth_call_cache
+                  where block_number between $1 and $2
+                    and id > $3
+                  order by id
+                  limit $4",
+            )
+            .bind::<BigInt, _>(from)
+            .bind::<BigInt, _>(to)
+            .bind::<Binary, _>(&last_id)
+            .bind::<BigInt, _>(DUMP_BATCH_SIZE)
+            .load::<Call>(conn)?;
+            let next = match rows.last() {
+                Some(call) => call.id.clone(),
+                None => break,
+            };
+            let rows = rows.into_iter().map(|call| call.row).collect::<Vec<_>>();
+            out.write_all(chain_export_line("calls", &rows).as_bytes())
+                .map_err(chain_export_error)?;
+            calls += rows.len();
+            last_id = next;
+        }
+        Ok((blocks, calls))
+    })
+}
+
+/// Add the blocks and contract calls in a file written by `export_chain`
+/// to the caches of the chain `name`. The chain is created if the store
+/// does not know it yet; otherwise, it must be the same chain as the one
+/// that was exported. Blocks and calls that are already cached are left
+/// alone, so that an import can be repeated. Returns the number of blocks
+/// and calls that were added
+pub fn import_chain(
+    conn: &PgConnection,
+    name: &str,
+    input: &mut dyn BufRead,
+) -> Result<(usize, usize), StoreError> {
+    let mut lines = input.lines();
+    let header: serde_json::Value = match lines.next() {
+        Some(line) => {
+            serde_json::from_str(&line.map_err(chain_export_error)?).map_err(chain_export_error)?
+        }
+        None => return Err(chain_export_error("the file is empty")),
+    };
+    if header["kind"] != "header" {
+        return Err(chain_export_error("the file does not start with a header"));
+    }
+    if header["version"] != CHAIN_EXPORT_FORMAT_VERSION {
+        return Err(chain_export_error(format!(
+            "unsupported format version {}",
+            header["version"]
+        )));
+    }
+    let net_version = header["net_version"].as_str();
+    let genesis_block_hash = header["genesis_block_hash"].as_str();
+
+    match chains(conn)?.into_iter().find(|chain| chain.name == name) {
+        None => {
+            sql_query(
+                "insert into ethereum_networks(name, net_version, genesis_block_hash)
+                 values ($1, $2, $3)",
+            )
+            .bind::<Text, _>(name)
+            .bind::<Nullable<Text>, _>(net_version)
+            .bind::<Nullable<Text>, _>(genesis_block_hash)
+            .execute(conn)?;
+        }
+        Some(chain) => {
+            let differs = |exported: Option<&str>, ours: &Option<String>| match (exported, ours) {
+                (Some(exported), Some(ours)) => exported != ours,
+                _ => false,
+            };
+            if differs(net_version, &chain.net_version)
+                || differs(genesis_block_hash, &chain.genesis_block_hash)
+            {
+                return Err(format_err!(
+                    "the file contains chain `{}` with net_version {} and genesis block {}, \
+                     which is not the same as chain `{}` in the store",
+                    header["chain"],
+                    net_version.unwrap_or("unknown"),
+                    genesis_block_hash.unwrap_or("unknown"),
+                    name
+                )
+                .into());
+            }
+        }
+    }
+
+    let mut blocks = 0;
+    let mut calls = 0;
+    for line in lines {
+        let line: serde_json::Value =
+            serde_json::from_str(&line.map_err(chain_export_error)?).map_err(chain_export_error)?;
+        let rows = line["rows"].to_string();
+        match line["kind"].as_str() {
+            Some("blocks") => {
+                blocks += sql_query(
+                    "insert into ethereum_blocks(hash, number, parent_hash, network_name, data)
+                     select hash, number, parent_hash, $2, data
+                       from jsonb_to_recordset($1::jsonb)
+                            as b(hash varchar, number int8, parent_hash varchar, data jsonb)
+                     on conflict (hash) do nothing",
+                )
+                .bind::<Text, _>(&rows)
+                .bind::<Text, _>(name)
+                .execute(conn)?;
+            }
+            Some("calls") => conn.transaction(|| -> Result<(), StoreError> {
+                calls += sql_query(
+                    "insert into eth_call_cache(id, return_value, contract_address, block_number)
+                     select decode(id, 'hex'), decode(return_value, 'hex'),
+                            decode(contract_address, 'hex'), block_number
+                       from jsonb_to_recordset($1::jsonb)
+                            as c(id text, return_value text, contract_address text,
+                                 block_number int4)
+                     on conflict (id) do nothing",
+                )
+                .bind::<Text, _>(&rows)
+                .execute(conn)?;
+                sql_query(
+                    "insert into eth_call_meta(contract_address, accessed_at)
+                     select distinct decode(contract_address, 'hex'), current_date
+                       from jsonb_to_recordset($1::jsonb) as c(contract_address text)
+                     on conflict (contract_address) do nothing",
+                )
+                .bind::<Text, _>(&rows)
+                .execute(conn)?;
+                Ok(())
+            })?,
+            _ => {
+                return Err(chain_export_error(format!(
+                    "unknown kind of line {}",
+                    line["kind"]
+                )))
+            }
+        }
+    }
+    Ok((blocks, calls))
+}
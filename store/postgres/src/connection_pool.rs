@@ -1,7 +1,9 @@
 use diesel::pg::PgConnection;
 use diesel::r2d2::{self, event as e, ConnectionManager, HandleEvent, Pool};
+use diesel::{sql_query, RunQueryDsl};
 
 use graph::prelude::*;
+use graph::spawn_blocking_async_allow_panic;
 use graph::util::security::SafeDisplay;
 
 use std::collections::HashMap;
@@ -12,6 +14,9 @@ use std::time::Duration;
 // Log connection checkouts that take longer than this many millis
 const CONTENTION_LOG_THRESHOLD: u64 = 100;
 
+// How long the health check waits for a connection
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
 struct ErrorHandler(Logger, Box<Counter>);
 
 impl Debug for ErrorHandler {
@@ -117,3 +122,32 @@ pub fn create_connection_pool(
     );
     pool
 }
+
+/// Healthy if a connection can be checked out of the pool and Postgres
+/// answers a trivial query on it
+pub struct PoolHealthCheck {
+    pool: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl PoolHealthCheck {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        PoolHealthCheck { pool }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for PoolHealthCheck {
+    fn component(&self) -> String {
+        "store".to_owned()
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        let pool = self.pool.clone();
+        spawn_blocking_async_allow_panic(move || {
+            let conn = pool.get_timeout(HEALTH_CHECK_TIMEOUT)?;
+            sql_query("select 1").execute(&conn)?;
+            Ok(())
+        })
+        .await
+    }
+}
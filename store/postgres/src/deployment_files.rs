@@ -0,0 +1,52 @@
+//! The files of subgraphs that were deployed from a local directory or an
+//! HTTP URL, in the `subgraphs.subgraph_deployment_file` table
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_types::{Binary, Text};
+use diesel::{sql_query, RunQueryDsl};
+
+use graph::prelude::{DeploymentFiles as DeploymentFilesTrait, Error};
+
+#[derive(QueryableByName)]
+struct DeploymentFile {
+    #[sql_type = "Binary"]
+    data: Vec<u8>,
+}
+
+pub struct DeploymentFiles {
+    pool: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl DeploymentFiles {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        DeploymentFiles { pool }
+    }
+}
+
+impl DeploymentFilesTrait for DeploymentFiles {
+    fn get(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+        let conn = self.pool.get()?;
+        Ok(
+            sql_query("select data from subgraphs.subgraph_deployment_file where id = $1")
+                .bind::<Text, _>(id)
+                .get_results::<DeploymentFile>(&*conn)?
+                .pop()
+                .map(|file| file.data),
+        )
+    }
+
+    fn insert(&self, id: &str, data: &[u8]) -> Result<(), Error> {
+        let conn = self.pool.get()?;
+        // Files are identified by the hash of their contents, and a file
+        // that already exists has the same contents
+        sql_query(
+            "insert into subgraphs.subgraph_deployment_file(id, data)
+             values ($1, $2)
+             on conflict(id) do nothing",
+        )
+        .bind::<Text, _>(id)
+        .bind::<Binary, _>(data)
+        .execute(&*conn)?;
+        Ok(())
+    }
+}
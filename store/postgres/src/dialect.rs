@@ -0,0 +1,211 @@
+//! The parts of the SQL we generate that depend on the database we talk
+//! to. Everything that the store writes into DDL or into statements and
+//! that is specific to Postgres goes through a `SqlDialect`, so that a
+//! different backend only needs to implement this trait rather than
+//! rewrite how layouts and entity storage generate their SQL.
+//!
+//! See the unit tests at the end of `relational.rs` for the DDL that the
+//! `Postgres` dialect produces.
+use std::fmt::{self, Debug, Write};
+
+use graph::prelude::BLOCK_NUMBER_MAX;
+
+use crate::block_range::BLOCK_RANGE_COLUMN;
+use crate::relational::{Column, ColumnType, VID_COLUMN};
+
+pub trait SqlDialect: Debug + Send + Sync {
+    /// A short name for the dialect, for log messages
+    fn name(&self) -> &str;
+
+    /// The database type used to store values of `column_type`
+    fn sql_type<'a>(&self, column_type: &'a ColumnType) -> &'a str;
+
+    /// Write the statement that creates the enum type `name` in `schema`
+    /// with the given `values`
+    fn write_enum_type(
+        &self,
+        out: &mut String,
+        schema: &str,
+        name: &str,
+        values: &mut dyn Iterator<Item = &String>,
+    ) -> fmt::Result;
+
+    /// Write the definitions of the columns that every table for a
+    /// versioned entity has in addition to its attributes, i.e. the
+    /// synthetic primary key and the block range, together with the
+    /// constraint that keeps the block ranges of the versions of one entity
+    /// from overlapping. This finishes the `create table` statement
+    fn write_versioning_columns(&self, out: &mut String) -> fmt::Result;
+
+    /// Write the statement for the index that speeds up finding the rows
+    /// of `table` in `schema` with a given block range
+    fn write_block_range_index(&self, out: &mut String, schema: &str, table: &str) -> fmt::Result;
+
+    /// The index method to use for the attribute index on `column`
+    fn attribute_index_method(&self, column: &Column) -> &'static str;
+
+    /// Write an `insert` into `table` of `columns` whose values are the
+    /// positional parameters `$1, $2, ..` in the order of `columns`. If a
+    /// row with the same values for the `conflict` columns already exists,
+    /// set the columns in `update` of that row to their new values instead
+    fn write_upsert(
+        &self,
+        out: &mut String,
+        table: &str,
+        columns: &[&str],
+        conflict: &[&str],
+        update: &[&str],
+    ) -> fmt::Result;
+}
+
+/// The dialect of Postgres 9.6 and later
+#[derive(Debug)]
+pub struct Postgres;
+
+impl SqlDialect for Postgres {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    fn sql_type<'a>(&self, column_type: &'a ColumnType) -> &'a str {
+        match column_type {
+            ColumnType::Boolean => "boolean",
+            ColumnType::BigDecimal => "numeric",
+            ColumnType::BigInt => "numeric",
+            ColumnType::Bytes => "bytea",
+            ColumnType::Int => "integer",
+            ColumnType::String => "text",
+            ColumnType::TSVector(_) => "tsvector",
+            ColumnType::Enum(enum_type) => enum_type.name.as_str(),
+            ColumnType::BytesId => "bytea",
+            ColumnType::GeoPoint => "point",
+        }
+    }
+
+    fn write_enum_type(
+        &self,
+        out: &mut String,
+        schema: &str,
+        name: &str,
+        values: &mut dyn Iterator<Item = &String>,
+    ) -> fmt::Result {
+        let mut sep = "";
+        write!(out, "create type {}.\"{}\"\n    as enum (", schema, name)?;
+        for value in values {
+            write!(out, "{}'{}'", sep, value)?;
+            sep = ", "
+        }
+        writeln!(out, ");")
+    }
+
+    fn write_versioning_columns(&self, out: &mut String) -> fmt::Result {
+        write!(
+            out,
+            "\n        {vid}                  bigserial primary key,\
+             \n        {block_range}          int4range not null,
+        exclude using gist   (id with =, {block_range} with &&)\n);\n",
+            vid = VID_COLUMN,
+            block_range = BLOCK_RANGE_COLUMN
+        )
+    }
+
+    fn write_block_range_index(&self, out: &mut String, schema: &str, table: &str) -> fmt::Result {
+        // Add a BRIN index on the block_range bounds to exploit the fact
+        // that block ranges closely correlate with where in a table an
+        // entity appears physically. This index is incredibly efficient for
+        // reverts where we look for very recent blocks, so that this index
+        // is highly selective. See https://github.com/graphprotocol/graph-node/issues/1415#issuecomment-630520713
+        // for details on one experiment.
+        //
+        // We do not index the `block_range` as a whole, but rather the lower
+        // and upper bound separately, since experimentation has shown that
+        // Postgres will not use the index on `block_range` for clauses like
+        // `block_range @> $block` but rather falls back to a full table scan.
+        //
+        // We also make sure that we do not put `NULL` in the index for
+        // the upper bound since nulls can not be compared to anything and
+        // will make the index less effective.
+        //
+        // To make the index usable, queries need to have clauses using
+        // `lower(block_range)` and `coalesce(..)` verbatim.
+        //
+        // We also index `vid` as that correlates with the order in which
+        // entities are stored.
+        write!(out,"create index brin_{table_name}\n    \
+                    on {schema_name}.{table_name}\n \
+                       using brin(lower(block_range), coalesce(upper(block_range), {block_max}), vid);\n",
+            table_name = table,
+            schema_name = schema,
+            block_max = BLOCK_NUMBER_MAX)
+    }
+
+    fn attribute_index_method(&self, column: &Column) -> &'static str {
+        if column.is_list() || column.is_fulltext() {
+            "gin"
+        } else if column.is_geo_point() {
+            "gist"
+        } else {
+            "btree"
+        }
+    }
+
+    fn write_upsert(
+        &self,
+        out: &mut String,
+        table: &str,
+        columns: &[&str],
+        conflict: &[&str],
+        update: &[&str],
+    ) -> fmt::Result {
+        let position = |name: &str| {
+            columns
+                .iter()
+                .position(|column| *column == name)
+                .expect("updated columns are inserted")
+                + 1
+        };
+        writeln!(out, "insert into {}({})", table, columns.join(", "))?;
+        write!(out, "values(")?;
+        for i in 1..=columns.len() {
+            if i > 1 {
+                write!(out, ", ")?;
+            }
+            write!(out, "${}", i)?;
+        }
+        writeln!(out, ")\non conflict({})", conflict.join(", "))?;
+        write!(out, "do update set ")?;
+        for (i, column) in update.iter().enumerate() {
+            if i > 0 {
+                write!(out, ", ")?;
+            }
+            write!(out, "{} = ${}", column, position(column))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn postgres_upsert() {
+        let mut out = String::new();
+        Postgres
+            .write_upsert(
+                &mut out,
+                "sgd1.entities",
+                &["entity", "id", "data", "event_source"],
+                &["entity", "id"],
+                &["data", "event_source"],
+            )
+            .unwrap();
+        assert_eq!(
+            "insert into sgd1.entities(entity, id, data, event_source)\n\
+             values($1, $2, $3, $4)\n\
+             on conflict(entity, id)\n\
+             do update set data = $3, event_source = $4",
+            out
+        );
+    }
+}
@@ -41,21 +41,26 @@ use std::ops::Deref as _;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
+use graph::data::query::plans;
 use graph::data::schema::Schema as SubgraphSchema;
 use graph::data::subgraph::schema::{POI_OBJECT, POI_TABLE, SUBGRAPHS_ID};
 use graph::prelude::{
     debug, format_err, info, serde_json, warn, AttributeIndexDefinition, BlockNumber, Entity,
-    EntityChange, EntityChangeOperation, EntityCollection, EntityFilter, EntityKey,
-    EntityModification, EntityOrder, EntityRange, Error, EthereumBlockPointer, Logger,
-    QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId, ValueType, BLOCK_NUMBER_MAX,
+    EntityChange, EntityChangeOperation, EntityCollection, EntityFilter, EntityGroupQuery,
+    EntityKey, EntityModification, EntityOperation, EntityOrder, EntityRange, Error,
+    EthereumBlockPointer, GaugeVec, Logger, QueryExecutionError, ReplicaIdentity, StoreError,
+    StoreEvent, SubgraphDeploymentId, ValueType, BLOCK_NUMBER_MAX,
 };
 
 use crate::block_range::block_number;
+use crate::dialect::{Postgres, SqlDialect};
 use crate::history_event::HistoryEvent;
+use crate::isolation;
 use crate::jsonb_queries::FilterQuery;
 use crate::metadata;
 use crate::notification_listener::JsonNotification;
 use crate::relational::{Catalog, Layout};
+use crate::vacuum;
 
 lazy_static! {
     // We allow overriding the default storage scheme with the environment
@@ -262,6 +267,13 @@ impl Storage {
             Storage::Relational(layout) => &layout.subgraph,
         }
     }
+
+    fn schema(&self) -> &str {
+        match self {
+            Storage::Json(json) => &json.schema,
+            Storage::Relational(layout) => &layout.catalog.schema,
+        }
+    }
 }
 
 /// Helper struct to support a custom query for entity history
@@ -458,11 +470,21 @@ impl Connection<'_> {
         collection: EntityCollection,
         filter: Option<EntityFilter>,
         order: EntityOrder,
+        distinct: bool,
         range: EntityRange,
         block: BlockNumber,
     ) -> Result<Vec<T>, QueryExecutionError> {
         match &*self.storage {
             Storage::Json(json) => {
+                if plans::is_explaining(&json.subgraph) {
+                    return Err(StoreError::QueryExecutionError(
+                        "This subgraph uses JSONB storage, which does not \
+                         support explaining queries. Redeploy a new version \
+                         of this subgraph to enable this feature."
+                            .to_owned(),
+                    )
+                    .into());
+                }
                 // JSON storage can only query at the latest block
                 if block != BLOCK_NUMBER_MAX {
                     return Err(StoreError::QueryExecutionError(
@@ -473,6 +495,15 @@ impl Connection<'_> {
                     )
                     .into());
                 }
+                if distinct {
+                    return Err(StoreError::QueryExecutionError(
+                        "This subgraph uses JSONB storage, which does not \
+                         support `distinctOn`. Redeploy a new version \
+                         of this subgraph to enable this feature."
+                            .to_owned(),
+                    )
+                    .into());
+                }
                 let order = match order {
                     EntityOrder::Ascending(attr, value_type) => Some((attr, value_type, "asc")),
                     EntityOrder::Descending(attr, value_type) => Some((attr, value_type, "desc")),
@@ -480,8 +511,107 @@ impl Connection<'_> {
                 };
                 json.query(&self.conn, collection, filter, order, range)
             }
+            Storage::Relational(layout) => layout.query(
+                logger, &self.conn, collection, filter, order, distinct, range, block,
+            ),
+        }
+    }
+
+    /// Run the GraphQL query `f` with only the privileges that it needs to
+    /// read the data of the subgraph of this connection; see the
+    /// `isolation` module
+    pub(crate) fn as_reader<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&Self) -> Result<T, E>,
+        E: From<StoreError>,
+    {
+        isolation::as_reader(
+            &self.conn,
+            self.storage.subgraph(),
+            self.storage.schema(),
+            || f(self),
+        )
+    }
+
+    pub(crate) fn find_groups(
+        &self,
+        query: &EntityGroupQuery,
+    ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError> {
+        match &*self.storage {
+            Storage::Json(_) => Err(StoreError::QueryExecutionError(
+                "This subgraph uses JSONB storage, which does not \
+                 support grouping entities. Redeploy a new version of \
+                 this subgraph to enable this feature."
+                    .to_owned(),
+            )
+            .into()),
+            Storage::Relational(layout) => layout.find_groups(&self.conn, query),
+        }
+    }
+
+    pub(crate) fn changes_in_block(
+        &self,
+        entity_types: &[String],
+        block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        match &*self.storage {
+            Storage::Json(_) => Err(StoreError::QueryExecutionError(
+                "This subgraph uses JSONB storage, which does not \
+                 keep track of the blocks in which entities changed. Redeploy \
+                 a new version of this subgraph to enable this feature."
+                    .to_owned(),
+            )),
+            Storage::Relational(layout) => layout.changes_in_block(&self.conn, entity_types, block),
+        }
+    }
+
+    pub(crate) fn set_publication(
+        &self,
+        publish: bool,
+        replica_identity: Option<ReplicaIdentity>,
+    ) -> Result<Option<String>, StoreError> {
+        match &*self.storage {
+            Storage::Json(_) => Err(StoreError::QueryExecutionError(
+                "This subgraph uses JSONB storage, which does not \
+                 support logical replication. Redeploy a new version of \
+                 this subgraph to enable this feature."
+                    .to_owned(),
+            )),
             Storage::Relational(layout) => {
-                layout.query(logger, &self.conn, collection, filter, order, range, block)
+                self.transaction(|| layout.set_publication(&self.conn, publish, replica_identity))
+            }
+        }
+    }
+
+    /// Count `writes`, the number of entity versions written to each entity
+    /// type, against the tables of this deployment and do the maintenance
+    /// that the tables need because of them
+    pub(crate) fn maintain_tables(
+        &self,
+        logger: &Logger,
+        writes: BTreeMap<String, u64>,
+        dead_tuple_ratio: &GaugeVec,
+    ) {
+        let layout = match &*self.storage {
+            Storage::Json(_) => return,
+            Storage::Relational(layout) => layout,
+        };
+        for (entity_type, count) in writes {
+            let table = match layout.table_for_entity(&entity_type) {
+                Ok(table) => table,
+                Err(_) => continue,
+            };
+            let due = vacuum::record_writes(table.qualified_name.as_str(), count);
+            if !due.is_empty() {
+                vacuum::maintain(
+                    logger,
+                    &self.conn,
+                    &layout.subgraph,
+                    &layout.catalog.schema,
+                    table,
+                    due,
+                    dead_tuple_ratio,
+                );
             }
         }
     }
@@ -924,6 +1054,17 @@ fn find_schema(
         .optional()?)
 }
 
+/// Return the name of the database schema for `subgraph`, or an error if
+/// the subgraph does not have one
+pub(crate) fn find_schema_name(
+    conn: &diesel::pg::PgConnection,
+    subgraph: &SubgraphDeploymentId,
+) -> Result<String, StoreError> {
+    find_schema(conn, subgraph)?
+        .map(|schema| schema.name)
+        .ok_or_else(|| StoreError::Unknown(format_err!("unknown subgraph {}", subgraph)))
+}
+
 fn supports_proof_of_indexing(
     conn: &diesel::pg::PgConnection,
     subgraph_id: &SubgraphDeploymentId,
@@ -1046,13 +1187,16 @@ impl JsonStorage {
     ) -> Result<usize, StoreError> {
         let event_source = HistoryEvent::to_event_source_string(&history_event);
 
-        let query = format!(
-            "insert into {}.entities(entity, id, data, event_source)
-                       values($1, $2, $3, $4)
-                     on conflict(entity, id)
-                       do update set data = $3, event_source = $4",
-            self.schema
-        );
+        let mut query = String::new();
+        Postgres
+            .write_upsert(
+                &mut query,
+                &format!("{}.entities", self.schema),
+                &["entity", "id", "data", "event_source"],
+                &["entity", "id"],
+                &["data", "event_source"],
+            )
+            .map_err(|_| StoreError::Unknown(format_err!("failed to generate upsert")))?;
         let query = diesel::sql_query(query)
             .bind::<Text, _>(&key.entity_type)
             .bind::<Text, _>(&key.entity_id)
@@ -1233,7 +1377,8 @@ impl JsonStorage {
             | ValueType::Bytes
             | ValueType::BigDecimal
             | ValueType::Int
-            | ValueType::String => (String::from("btree"), String::from(""), "->>"),
+            | ValueType::String
+            | ValueType::GeoPoint => (String::from("btree"), String::from(""), "->>"),
             ValueType::List => (String::from("gin"), String::from("jsonb_path_ops"), "->"),
         };
         // Cast between the type we store in JSONB for the field and the type
@@ -1482,6 +1627,7 @@ pub fn delete_all_entities_for_test_use_only(
 ) -> Result<(), StoreError> {
     // Delete public entities and related data
     diesel::delete(public::event_meta_data::table).execute(conn)?;
+    conn.batch_execute("delete from public.unused_deployments")?;
     // Delete all subgraph schemas
     for subgraph in public::deployment_schemas::table
         .select(public::deployment_schemas::subgraph)
@@ -1590,9 +1736,11 @@ pub fn create_split_schema(conn: &PgConnection, schema_name: &str) -> Result<(),
 
 /// Drop the schema for `subgraph`. This deletes all data for the subgraph,
 /// and can not be reversed. It does not remove any of the metadata in
-/// `subgraphs.entities` associated with the subgraph
-#[cfg(debug_assertions)]
-fn drop_schema(
+/// `subgraphs.entities` associated with the subgraph. Besides tests, this
+/// is used in production by `command_support::remove_unused`, which
+/// removes unused deployments for `graphman` and the retirement of
+/// versions, and is therefore not limited to debug builds
+pub(crate) fn drop_schema(
     conn: &diesel::pg::PgConnection,
     subgraph: &SubgraphDeploymentId,
 ) -> Result<usize, StoreError> {
@@ -1600,6 +1748,7 @@ fn drop_schema(
     if let Some(schema) = info {
         let query = format!("drop schema if exists {} cascade", schema.name);
         conn.batch_execute(&*query)?;
+        isolation::drop_reader(conn, subgraph, &schema.name)?;
         Ok(diesel::delete(deployment_schemas::table)
             .filter(deployment_schemas::subgraph.eq(schema.subgraph))
             .execute(conn)?)
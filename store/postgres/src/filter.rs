@@ -40,6 +40,17 @@ impl From<UnsupportedFilter> for diesel::result::Error {
 
 type FilterExpression<QS> = Box<dyn BoxableExpression<QS, Pg, SqlType = Bool>>;
 
+/// The value of `attribute` as a number. The attribute name is put into the
+/// SQL text rather than passed as a bind variable so that the expression is
+/// identical to the one in the index that `build_attribute_index` creates
+/// for `BigInt` and `BigDecimal` attributes, which Postgres can then use
+fn numeric_attribute(attribute: &str) -> String {
+    format!(
+        "((c.data->'{}'->>'data')::numeric)",
+        attribute.replace("'", "''")
+    )
+}
+
 trait IntoFilter<QS> {
     fn into_filter(self, attribute: String, op: &str) -> FilterExpression<QS>;
 }
@@ -116,9 +127,7 @@ impl<QS> IntoFilter<QS> for bool {
 impl<QS> IntoFilter<QS> for BigInt {
     fn into_filter(self, attribute: String, op: &str) -> FilterExpression<QS> {
         Box::new(
-            sql("(c.data -> ")
-                .bind::<Text, _>(attribute)
-                .sql("->> 'data')::numeric")
+            sql(&numeric_attribute(&attribute))
                 .sql(op)
                 .bind::<Text, _>(self.to_string())
                 .sql("::numeric"),
@@ -129,9 +138,7 @@ impl<QS> IntoFilter<QS> for BigInt {
 impl<QS> IntoFilter<QS> for BigDecimal {
     fn into_filter(self, attribute: String, op: &str) -> FilterExpression<QS> {
         Box::new(
-            sql("(c.data -> ")
-                .bind::<Text, _>(attribute)
-                .sql("->> 'data')::numeric")
+            sql(&numeric_attribute(&attribute))
                 .sql(op)
                 .bind::<Text, _>(self.to_string())
                 .sql("::numeric"),
@@ -178,6 +185,14 @@ impl<QS> IntoArrayFilter<QS, SqlValue> for Vec<SqlValue> {
                     .bind::<Array<U>, _>(self)
                     .sql(")"),
             ) as FilterExpression<QS>
+        } else if coercion == "::numeric" {
+            Box::new(
+                sql(&numeric_attribute(&attribute))
+                    .sql(op)
+                    .sql("(")
+                    .bind::<Array<U>, _>(self)
+                    .sql(")"),
+            ) as FilterExpression<QS>
         } else {
             Box::new(
                 sql("(c.data -> ")
@@ -437,5 +452,11 @@ where
                 }
             }
         }
+
+        // Distances between points need the relational storage scheme
+        Within(_, value, _) => Err(UnsupportedFilter {
+            filter: "within".to_owned(),
+            value,
+        }),
     }
 }
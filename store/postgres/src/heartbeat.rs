@@ -0,0 +1,123 @@
+//! Heartbeats of index nodes, and the queries needed to move deployments
+//! away from nodes that have stopped sending them
+use diesel::pg::PgConnection;
+use diesel::sql_types::{BigInt, Bool, Nullable, Text};
+use diesel::{sql_query, RunQueryDsl};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use graph::prelude::{NodeId, StoreError};
+
+/// The key of the advisory lock that is held by the node that moves
+/// deployments away from dead nodes. Whoever holds the lock is the leader
+/// for the current round of failover
+const FAILOVER_LOCK: i64 = 1390;
+
+/// A deployment that is assigned to a node whose heartbeat is too old
+#[derive(QueryableByName, Debug)]
+pub(crate) struct Orphan {
+    #[sql_type = "Text"]
+    pub deployment: String,
+    #[sql_type = "Text"]
+    pub node_id: String,
+    /// One of the names of the subgraph, if there are any
+    #[sql_type = "Nullable<Text>"]
+    pub name: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub network: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct Node {
+    #[sql_type = "Text"]
+    node_id: String,
+}
+
+#[derive(QueryableByName)]
+struct AssignmentCount {
+    #[sql_type = "Text"]
+    node_id: String,
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+#[derive(QueryableByName)]
+struct Locked {
+    #[sql_type = "Bool"]
+    locked: bool,
+}
+
+/// Record that `node` is alive right now
+pub(crate) fn record(conn: &PgConnection, node: &NodeId) -> Result<(), StoreError> {
+    sql_query(
+        "insert into node_heartbeats(node_id, last_seen) values ($1, now())
+         on conflict(node_id) do update set last_seen = excluded.last_seen",
+    )
+    .bind::<Text, _>(node.as_str())
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Try to become the leader for failover. This must be called inside a
+/// transaction; the lock is released when the transaction ends
+pub(crate) fn try_lock(conn: &PgConnection) -> Result<bool, StoreError> {
+    Ok(sql_query("select pg_try_advisory_xact_lock($1) as locked")
+        .bind::<BigInt, _>(FAILOVER_LOCK)
+        .get_result::<Locked>(conn)?
+        .locked)
+}
+
+/// Return all nodes that sent a heartbeat within the last `timeout`
+pub(crate) fn live_nodes(
+    conn: &PgConnection,
+    timeout: Duration,
+) -> Result<Vec<NodeId>, StoreError> {
+    Ok(sql_query(
+        "select node_id from node_heartbeats
+          where last_seen > now() - $1 * interval '1 second'
+          order by node_id",
+    )
+    .bind::<BigInt, _>(timeout.as_secs() as i64)
+    .load::<Node>(conn)?
+    .into_iter()
+    .filter_map(|node| NodeId::new(node.node_id).ok())
+    .collect())
+}
+
+/// Return all deployments that are assigned to nodes that have not sent a
+/// heartbeat within the last `timeout`. Deployments assigned to nodes that
+/// never sent a heartbeat are not considered orphaned, since assigning a
+/// deployment to a made-up node is how operators pause indexing
+pub(crate) fn orphans(conn: &PgConnection, timeout: Duration) -> Result<Vec<Orphan>, StoreError> {
+    Ok(sql_query(
+        "select a.id as deployment, a.node_id, min(s.name) as name, min(d.network) as network
+           from subgraphs.subgraph_deployment_assignment a
+           join node_heartbeats h on (h.node_id = a.node_id)
+           left join subgraphs.subgraph_deployment_detail d
+             on (d.id = a.id and upper_inf(d.block_range))
+           left join subgraphs.subgraph_version v
+             on (v.deployment = a.id and upper_inf(v.block_range))
+           left join subgraphs.subgraph s
+             on (s.id = v.subgraph and upper_inf(s.block_range))
+          where upper_inf(a.block_range)
+            and h.last_seen <= now() - $1 * interval '1 second'
+          group by a.id, a.node_id
+          order by a.id",
+    )
+    .bind::<BigInt, _>(timeout.as_secs() as i64)
+    .load(conn)?)
+}
+
+/// Return how many deployments are assigned to each node
+pub(crate) fn assignment_counts(conn: &PgConnection) -> Result<HashMap<String, i64>, StoreError> {
+    Ok(sql_query(
+        "select node_id, count(*) as count
+           from subgraphs.subgraph_deployment_assignment
+          where upper_inf(block_range)
+          group by node_id",
+    )
+    .load::<AssignmentCount>(conn)?
+    .into_iter()
+    .map(|row| (row.node_id, row.count))
+    .collect())
+}
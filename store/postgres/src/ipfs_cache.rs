@@ -0,0 +1,73 @@
+//! A cache of IPFS files in the `ipfs_cache` table, so that files that
+//! mappings and subgraph deployments need do not have to be fetched from
+//! IPFS again after a restart, or by every node
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_types::{BigInt, Binary, Text};
+use diesel::{sql_query, RunQueryDsl};
+
+use graph::prelude::{Error, IpfsCache as IpfsCacheTrait};
+
+#[derive(QueryableByName)]
+struct CachedFile {
+    #[sql_type = "Binary"]
+    data: Vec<u8>,
+}
+
+pub struct IpfsCache {
+    pool: Pool<ConnectionManager<PgConnection>>,
+    /// How many bytes of files to keep
+    max_size: u64,
+}
+
+impl IpfsCache {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>, max_size: u64) -> Self {
+        IpfsCache { pool, max_size }
+    }
+}
+
+impl IpfsCacheTrait for IpfsCache {
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>, Error> {
+        let conn = self.pool.get()?;
+        Ok(sql_query(
+            "update ipfs_cache set last_access = now()
+              where path = $1
+             returning data",
+        )
+        .bind::<Text, _>(path)
+        .get_results::<CachedFile>(&*conn)?
+        .pop()
+        .map(|file| file.data))
+    }
+
+    fn insert(&self, path: &str, data: &[u8]) -> Result<(), Error> {
+        if data.len() as u64 > self.max_size {
+            return Ok(());
+        }
+        let conn = self.pool.get()?;
+        sql_query(
+            "insert into ipfs_cache(path, data, size, last_access)
+             values ($1, $2, $3, now())
+             on conflict(path) do nothing",
+        )
+        .bind::<Text, _>(path)
+        .bind::<Binary, _>(data)
+        .bind::<BigInt, _>(data.len() as i64)
+        .execute(&*conn)?;
+        // Evict the files that were used least recently beyond the size
+        // limit; nodes that fill the cache at the same time may evict a
+        // little more than necessary, which is harmless
+        sql_query(
+            "delete from ipfs_cache
+              where path in (select path
+                               from (select path,
+                                            sum(size) over (order by last_access desc, path)
+                                              as total
+                                       from ipfs_cache) c
+                              where c.total > $1)",
+        )
+        .bind::<BigInt, _>(self.max_size as i64)
+        .execute(&*conn)?;
+        Ok(())
+    }
+}
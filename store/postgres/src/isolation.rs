@@ -0,0 +1,160 @@
+//! Keep GraphQL queries for one deployment from reading the data of any
+//! other deployment, even if the SQL we generate for them has a bug. When
+//! `GRAPH_STORE_ISOLATE_QUERIES` is set, GraphQL queries run as a role
+//! that can only read the schema of their deployment, and with a
+//! `search_path` that only contains that schema and `public`. The store
+//! creates the role of a deployment and grants it access the first time it
+//! runs a query for the deployment, which needs a database user with the
+//! `createrole` privilege. The entity reads of indexing run as that role,
+//! too; writes and everything else run as the database user of the node.
+//! Removing a deployment drops its role.
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::Connection;
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use graph::prelude::{StoreError, SubgraphDeploymentId};
+use graph::util::env::env_var;
+
+lazy_static! {
+    static ref ISOLATE_QUERIES: bool = env_var("GRAPH_STORE_ISOLATE_QUERIES").unwrap_or(false);
+
+    /// The schemas whose reader role this process already set up
+    static ref READERS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// The role that GraphQL queries for `subgraph` run as. Roles are shared
+/// by all databases of a Postgres cluster; naming them after the
+/// deployment rather than its schema keeps deployments in different shards
+/// on the same cluster apart
+fn reader_role(subgraph: &SubgraphDeploymentId) -> String {
+    format!("reader_{}", subgraph)
+}
+
+/// The statements that create the reader role for `subgraph` if it does
+/// not exist yet and let it read all tables in `schema`, including the
+/// ones that migrations add later
+fn create_reader_sql(subgraph: &SubgraphDeploymentId, schema: &str) -> String {
+    format!(
+        "do $$\n\
+         begin\n    \
+             create role \"{role}\" nologin;\n\
+         exception when duplicate_object then\n    \
+             null;\n\
+         end\n\
+         $$;\n\
+         grant \"{role}\" to current_user;\n\
+         grant usage on schema {schema} to \"{role}\";\n\
+         grant select on all tables in schema {schema} to \"{role}\";\n\
+         alter default privileges in schema {schema} grant select on tables to \"{role}\";\n",
+        role = reader_role(subgraph),
+        schema = schema
+    )
+}
+
+/// The statements that revoke everything that was granted to the reader
+/// role for `subgraph` in this database and drop it. The role is kept if
+/// it still has privileges in another database of the cluster
+fn drop_reader_sql(subgraph: &SubgraphDeploymentId) -> String {
+    format!(
+        "do $$\n\
+         begin\n    \
+             drop owned by \"{role}\";\n    \
+             drop role \"{role}\";\n\
+         exception when undefined_object or dependent_objects_still_exist then\n    \
+             null;\n\
+         end\n\
+         $$;\n",
+        role = reader_role(subgraph)
+    )
+}
+
+/// The statements that switch the current transaction to the reader role
+/// for `subgraph`
+fn set_reader_sql(subgraph: &SubgraphDeploymentId, schema: &str) -> String {
+    format!(
+        "set local role \"{}\";\nset local search_path to {}, public;\n",
+        reader_role(subgraph),
+        schema
+    )
+}
+
+fn ensure_reader(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    schema: &str,
+) -> Result<(), StoreError> {
+    if READERS.lock().unwrap().contains(schema) {
+        return Ok(());
+    }
+    conn.batch_execute(&create_reader_sql(subgraph, schema))?;
+    READERS.lock().unwrap().insert(schema.to_owned());
+    Ok(())
+}
+
+/// Drop the reader role of `subgraph`, whose data was in `schema`, when
+/// the deployment is removed
+pub(crate) fn drop_reader(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    schema: &str,
+) -> Result<(), StoreError> {
+    if !*ISOLATE_QUERIES {
+        return Ok(());
+    }
+    conn.batch_execute(&drop_reader_sql(subgraph))?;
+    READERS.lock().unwrap().remove(schema);
+    Ok(())
+}
+
+/// Run the GraphQL query `f` for `subgraph`, whose data is in `schema`, as
+/// the reader role of the deployment if `GRAPH_STORE_ISOLATE_QUERIES` is
+/// set. The role and search path only last for one transaction; `f` must
+/// therefore not be called while a transaction is already open on `conn`
+pub(crate) fn as_reader<T, E, F>(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    schema: &str,
+    f: F,
+) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+    E: From<StoreError>,
+{
+    if !*ISOLATE_QUERIES {
+        return f();
+    }
+    ensure_reader(conn, subgraph, schema)?;
+    conn.transaction::<_, StoreError, _>(|| {
+        conn.batch_execute(&set_reader_sql(subgraph, schema))?;
+        // The transaction only reads, and committing it when `f` fails
+        // does no harm
+        Ok(f())
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reader_sql() {
+        let subgraph = SubgraphDeploymentId::new("QmReader").unwrap();
+        let create = create_reader_sql(&subgraph, "sgd42");
+        assert!(create.contains("create role \"reader_QmReader\" nologin;"));
+        assert!(create.contains("grant \"reader_QmReader\" to current_user;"));
+        assert!(
+            create.contains("grant select on all tables in schema sgd42 to \"reader_QmReader\";")
+        );
+        assert_eq!(
+            "set local role \"reader_QmReader\";\n\
+             set local search_path to sgd42, public;\n",
+            set_reader_sql(&subgraph, "sgd42")
+        );
+        let drop = drop_reader_sql(&subgraph);
+        assert!(drop.contains("drop owned by \"reader_QmReader\";"));
+        assert!(drop.contains("drop role \"reader_QmReader\";"));
+    }
+}
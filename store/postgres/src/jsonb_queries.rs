@@ -48,6 +48,11 @@ impl<'a> FilterQuery<'a> {
                 ValueType::Bytes => "",
                 ValueType::Int => "::bigint",
                 ValueType::String => "",
+                ValueType::GeoPoint => {
+                    return Err(QueryExecutionError::OrderByNotSupportedForType(
+                        "GeoPoint".to_string(),
+                    ));
+                }
                 ValueType::List => {
                     return Err(QueryExecutionError::OrderByNotSupportedForType(
                         "List".to_string(),
@@ -23,21 +23,35 @@ extern crate uuid;
 mod block_range;
 mod catalog;
 mod chain_head_listener;
+pub mod command_support;
 pub mod connection_pool;
 mod db_schema;
+mod deployment_files;
+pub mod dialect;
 mod entities;
 mod filter;
 mod functions;
+mod heartbeat;
 mod history_event;
+mod ipfs_cache;
+mod isolation;
 mod jsonb;
 mod jsonb_queries;
 mod metadata;
+mod migration;
 mod notification_listener;
+mod planner;
+mod quotas;
 pub mod relational;
 mod relational_queries;
+mod retirement;
+mod self_check;
+mod sql_cache;
 mod sql_value;
 pub mod store;
 mod store_events;
+mod sync_samples;
+mod vacuum;
 
 #[cfg(debug_assertions)]
 pub mod db_schema_for_tests {
@@ -53,5 +67,11 @@ pub mod layout_for_tests {
 }
 
 pub use self::chain_head_listener::ChainHeadUpdateListener;
-pub use self::store::{Store, StoreConfig};
+pub use self::deployment_files::DeploymentFiles;
+pub use self::ipfs_cache::IpfsCache;
+pub use self::migration::{migrate, ShardMigrations};
+pub use self::quotas::QuotaStore;
+pub use self::retirement::RetirementPolicy;
+pub use self::self_check::{check_database, DatabaseCheck};
+pub use self::store::{network_identifiers, Store, StoreConfig};
 pub use self::store_events::SubscriptionManager;
@@ -63,6 +63,8 @@ table! {
         schema -> Text,
         data_sources -> Array<Text>,
         templates -> Nullable<Array<Text>>,
+        features -> Array<Text>,
+        api_version -> Text,
         block_range -> Range<Integer>,
     }
 }
@@ -0,0 +1,247 @@
+//! Migrations of the database schema. By default, every node runs the
+//! migrations that its database is missing when it starts. Operators who
+//! would rather migrate as a separate step run `graph-node --migrate` and
+//! set `GRAPH_STORE_MIGRATIONS` to `wait` or `fail` for all other nodes, so
+//! that those wait until the migrations have been run, or refuse to start
+//! while any are pending.
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_types::{Bool, Text};
+use diesel::{sql_query, Connection, RunQueryDsl};
+use lazy_static::lazy_static;
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use graph::components::migration::{MigrationStatus, MigrationStatusSource};
+use graph::prelude::{debug, info, trace, Error, Logger};
+
+include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+/// How often a node that waits for migrations checks whether they are done
+const WAIT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What a node does when it starts and its database has pending migrations
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    /// Run the migrations
+    Run,
+    /// Wait until another node, usually `graph-node --migrate`, ran them
+    Wait,
+    /// Refuse to start
+    Fail,
+}
+
+lazy_static! {
+    static ref MODE: Mode = match env::var("GRAPH_STORE_MIGRATIONS").ok().as_deref() {
+        None | Some("run") => Mode::Run,
+        Some("wait") => Mode::Wait,
+        Some("fail") => Mode::Fail,
+        Some(other) => panic!(
+            "invalid value `{}` for env var GRAPH_STORE_MIGRATIONS, \
+             it must be one of `run`, `wait` or `fail`",
+            other
+        ),
+    };
+}
+
+#[derive(QueryableByName)]
+struct Applied {
+    #[sql_type = "Text"]
+    version: String,
+}
+
+#[derive(QueryableByName)]
+struct Found {
+    #[sql_type = "Bool"]
+    found: bool,
+}
+
+embed_migrations!("./migrations");
+
+/// Run all schema migrations.
+///
+/// When multiple `graph-node` processes start up at the same time, we ensure
+/// that they do not run migrations in parallel by using `blocking_conn` to
+/// serialize them. The `conn` is used to run the actual migration.
+fn initiate_schema(logger: &Logger, conn: &PgConnection, blocking_conn: &PgConnection) {
+    // Collect migration logging output
+    let mut output = vec![];
+
+    // Make sure the locking table exists so we have something
+    // to lock. We intentionally ignore errors here, because they are most
+    // likely caused by us losing a race to create the table against another
+    // graph-node. If this truly is an error, we will trip over it when
+    // we try to lock the table and report it to the user
+    if let Err(e) = blocking_conn.batch_execute(
+        "create table if not exists \
+         __graph_node_global_lock(id int)",
+    ) {
+        debug!(
+            logger,
+            "Creating lock table failed, this is most likely harmless";
+            "error" => format!("{:?}", e)
+        );
+    }
+
+    // blocking_conn holds the lock on the migrations table for the duration
+    // of the migration on conn. Since all nodes execute this code, only one
+    // of them can run this code at the same time. We need to use two
+    // connections for this because diesel will run each migration in its
+    // own txn, which makes it impossible to hold a lock across all of them
+    // on that connection
+    info!(
+        logger,
+        "Waiting for other graph-node instances to finish migrating"
+    );
+    let result = blocking_conn.transaction(|| {
+        diesel::sql_query("lock table __graph_node_global_lock in exclusive mode")
+            .execute(blocking_conn)?;
+        info!(logger, "Running migrations");
+        embedded_migrations::run_with_output(conn, &mut output)
+    });
+    info!(logger, "Migrations finished");
+
+    match result {
+        Ok(_) => info!(logger, "Completed pending Postgres schema migrations"),
+        Err(e) => panic!(
+            "Error setting up Postgres database: \
+             You may need to drop and recreate your database to work with the \
+             latest version of graph-node. Error information: {:?}",
+            e
+        ),
+    };
+    // If there was any migration output, log it now
+    if !output.is_empty() {
+        debug!(
+            logger, "Postgres migration output";
+            "output" => String::from_utf8(output)
+                .unwrap_or_else(|_| String::from("<unreadable>"))
+        );
+        // We take getting output as a signal that a migration was actually
+        // run, which is not easy to tell from the Diesel API, and reset the
+        // query statistics since a schema change makes them not all that
+        // useful. An error here is not serious and can be ignored.
+        let res = conn.batch_execute("select pg_stat_statements_reset()");
+        if let Err(e) = res {
+            trace!(logger, "Failed to reset query statistics ({})", e);
+        }
+    }
+}
+
+/// The versions of the migrations that have been applied to the database,
+/// oldest first
+fn applied(conn: &PgConnection) -> Result<Vec<String>, Error> {
+    // Diesel creates the table when it runs the first migration
+    let found =
+        sql_query("select to_regclass('public.__diesel_schema_migrations') is not null as found")
+            .get_result::<Found>(conn)?
+            .found;
+    if !found {
+        return Ok(vec![]);
+    }
+    Ok(sql_query(
+        "select version::text as version from __diesel_schema_migrations order by version",
+    )
+    .load::<Applied>(conn)?
+    .into_iter()
+    .map(|applied| applied.version)
+    .collect())
+}
+
+/// How far the database of `shard` that `conn` is connected to is migrated
+pub fn migration_status(conn: &PgConnection, shard: &str) -> Result<MigrationStatus, Error> {
+    let applied = applied(conn)?;
+    let pending = MIGRATIONS
+        .iter()
+        .filter(|version| !applied.iter().any(|applied| applied == *version))
+        .map(|version| version.to_string())
+        .collect();
+    Ok(MigrationStatus {
+        shard: shard.to_owned(),
+        version: applied.last().cloned(),
+        pending,
+    })
+}
+
+/// Bring the schema of the database behind `pool` up to date, or make sure
+/// that it is, as `GRAPH_STORE_MIGRATIONS` says
+pub(crate) fn prepare_schema(logger: &Logger, pool: &Pool<ConnectionManager<PgConnection>>) {
+    let pending = || {
+        migration_status(&pool.get().unwrap(), "primary")
+            .expect("failed to check for pending migrations")
+            .pending
+    };
+    match *MODE {
+        Mode::Run => initiate_schema(logger, &pool.get().unwrap(), &pool.get().unwrap()),
+        Mode::Wait => loop {
+            let pending = pending();
+            if pending.is_empty() {
+                break;
+            }
+            info!(
+                logger,
+                "Waiting for the pending migrations to be run, e.g., with `graph-node --migrate`";
+                "pending" => pending.len(),
+            );
+            thread::sleep(WAIT_INTERVAL);
+        },
+        Mode::Fail => {
+            let pending = pending();
+            if !pending.is_empty() {
+                panic!(
+                    "the database has {} pending migrations ({}); \
+                     run `graph-node --migrate` before starting this node",
+                    pending.len(),
+                    pending.join(", ")
+                );
+            }
+        }
+    }
+}
+
+/// Run the pending migrations of the database of `shard` at `url`, for
+/// `graph-node --migrate`
+pub fn migrate(logger: &Logger, shard: &str, url: &str) -> Result<MigrationStatus, Error> {
+    let conn = PgConnection::establish(url)?;
+    let blocking_conn = PgConnection::establish(url)?;
+    initiate_schema(logger, &conn, &blocking_conn);
+    migration_status(&conn, shard)
+}
+
+/// The migration status of the databases of all store shards, for the
+/// admin server
+pub struct ShardMigrations {
+    /// The name and connection string of each shard
+    shards: Vec<(String, String)>,
+}
+
+impl ShardMigrations {
+    pub fn new(shards: Vec<(String, String)>) -> Self {
+        ShardMigrations { shards }
+    }
+}
+
+impl MigrationStatusSource for ShardMigrations {
+    fn migration_status(&self) -> Result<Vec<MigrationStatus>, Error> {
+        self.shards
+            .iter()
+            .map(|(shard, url)| migration_status(&PgConnection::establish(url)?, shard))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_migrations_in_order() {
+        assert!(!MIGRATIONS.is_empty());
+        assert!(MIGRATIONS.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(MIGRATIONS
+            .iter()
+            .all(|version| version.len() == 14 && version.chars().all(|c| c.is_ascii_digit())));
+    }
+}
@@ -0,0 +1,211 @@
+//! Help Postgres plan queries with `block_range @> $block` well. The
+//! planner often misestimates how many rows such a clause matches. We
+//! give it better statistics for new tables, let operators change planner
+//! settings for the queries that are prone to bad plans, and check at
+//! startup that the database has what we need.
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Integer, Text};
+use diesel::{sql_query, Connection, RunQueryDsl};
+use lazy_static::lazy_static;
+use std::env;
+use std::fmt::Write;
+
+use graph::prelude::{info, Logger, StoreError};
+use graph::util::env::env_var;
+
+use crate::block_range::BLOCK_RANGE_COLUMN;
+use crate::relational::{Table, PRIMARY_KEY_COLUMN};
+
+/// The oldest Postgres version we support
+const MIN_SERVER_VERSION: i32 = 90600;
+
+/// The first Postgres version with `create statistics`
+const EXTENDED_STATISTICS_VERSION: i32 = 100000;
+
+/// The extensions our migrations install
+pub(crate) const REQUIRED_EXTENSIONS: &[&str] = &["pg_trgm", "btree_gist"];
+
+lazy_static! {
+    /// The statistics target for the `block_range` column of new tables;
+    /// the Postgres default of 100 is used if this is not set
+    static ref STATISTICS_TARGET: Option<u32> = env_var("GRAPH_BLOCK_RANGE_STATISTICS_TARGET");
+
+    /// Planner settings for queries that fetch the children of a list of
+    /// parents; these combine a lateral join with a `block_range` clause,
+    /// which is where bad plans are most common
+    static ref WINDOW_SETTINGS: Vec<(String, String)> = env::var("GRAPH_SQL_WINDOW_SETTINGS")
+        .ok()
+        .map(|s| parse_settings(&s).unwrap_or_else(|e| panic!("{}", e)))
+        .unwrap_or_default();
+}
+
+/// Parse a comma separated list of `name=value` pairs. Since they are
+/// put into `set local` statements verbatim, names and values must be
+/// plain words or numbers
+fn parse_settings(s: &str) -> Result<Vec<(String, String)>, String> {
+    fn is_plain(word: &str) -> bool {
+        !word.is_empty()
+            && word
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    }
+
+    s.split(',')
+        .filter(|setting| !setting.trim().is_empty())
+        .map(|setting| {
+            let mut parts = setting.splitn(2, '=').map(str::trim);
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if is_plain(name) && is_plain(value) => {
+                    Ok((name.to_owned(), value.to_owned()))
+                }
+                _ => Err(format!(
+                    "invalid setting `{}` in env var GRAPH_SQL_WINDOW_SETTINGS, \
+                     it must have the form `name=value`",
+                    setting
+                )),
+            }
+        })
+        .collect()
+}
+
+#[derive(QueryableByName)]
+struct ServerVersion {
+    #[sql_type = "Integer"]
+    version: i32,
+}
+
+#[derive(QueryableByName)]
+struct Extension {
+    #[sql_type = "Text"]
+    name: String,
+}
+
+/// The version of the Postgres server, e.g., 110005 for 11.5
+pub(crate) fn server_version(conn: &PgConnection) -> Result<i32, StoreError> {
+    Ok(
+        sql_query("select current_setting('server_version_num')::int as version")
+            .get_result::<ServerVersion>(conn)?
+            .version,
+    )
+}
+
+/// Whether the database is too old or lacks extensions that we need
+pub(crate) fn server_problems(conn: &PgConnection) -> Result<Vec<String>, StoreError> {
+    let mut problems = vec![];
+    let version = server_version(conn)?;
+    if version < MIN_SERVER_VERSION {
+        problems.push(format!(
+            "Postgres server version {} is too old, graph-node needs at least version {}",
+            version, MIN_SERVER_VERSION
+        ));
+    }
+
+    let available = sql_query("select name::text as name from pg_available_extensions")
+        .load::<Extension>(conn)?
+        .into_iter()
+        .map(|extension| extension.name)
+        .collect::<Vec<_>>();
+    let missing = REQUIRED_EXTENSIONS
+        .iter()
+        .filter(|name| !available.iter().any(|available| available == *name))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        problems.push(format!(
+            "the Postgres extensions {} are not available in the database, \
+             graph-node needs them. They are part of the `contrib` package of \
+             most Postgres distributions",
+            missing.join(", ")
+        ));
+    }
+    Ok(problems)
+}
+
+/// Make sure that the database is recent enough and that the extensions we
+/// need can be installed. Panics if that is not the case since the node
+/// can not work with such a database
+pub fn check_server(logger: &Logger, conn: &PgConnection) {
+    let problems = server_problems(conn).expect("failed to check the Postgres server");
+    if let Some(problem) = problems.first() {
+        panic!("{}", problem);
+    }
+    let version = server_version(conn).expect("failed to get the Postgres server version");
+    info!(logger, "Postgres server is suitable"; "server_version" => version);
+}
+
+/// The statements that improve the statistics for `table` on a server with
+/// `version`. Extended statistics tell the planner how closely `id` and
+/// `block_range` depend on each other
+pub(crate) fn statistics_ddl(
+    out: &mut String,
+    schema: &str,
+    table: &Table,
+    version: i32,
+) -> std::fmt::Result {
+    if let Some(target) = *STATISTICS_TARGET {
+        writeln!(
+            out,
+            "alter table {}.{} alter column {} set statistics {};",
+            schema,
+            table.name.quoted(),
+            BLOCK_RANGE_COLUMN,
+            target
+        )?;
+    }
+    if version >= EXTENDED_STATISTICS_VERSION {
+        writeln!(
+            out,
+            "create statistics if not exists {schema}.\"stats_{table}\"\n    \
+             (ndistinct, dependencies) on {id}, {block_range} from {schema}.{qtable};",
+            schema = schema,
+            table = table.name,
+            qtable = table.name.quoted(),
+            id = PRIMARY_KEY_COLUMN,
+            block_range = BLOCK_RANGE_COLUMN
+        )?;
+    }
+    Ok(())
+}
+
+/// Run `f`, with the planner settings from `GRAPH_SQL_WINDOW_SETTINGS` if
+/// `is_window` is `true`. The settings only last for one transaction
+pub(crate) fn with_window_settings<T, E, F>(
+    conn: &PgConnection,
+    is_window: bool,
+    f: F,
+) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    if !is_window || WINDOW_SETTINGS.is_empty() {
+        return f();
+    }
+    conn.transaction(|| {
+        let mut sql = String::new();
+        for (name, value) in WINDOW_SETTINGS.iter() {
+            sql.push_str(&format!("set local {} = {};\n", name, value));
+        }
+        conn.batch_execute(&sql)?;
+        f()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_settings;
+
+    #[test]
+    fn parses_settings() {
+        assert_eq!(
+            Ok(vec![
+                ("enable_seqscan".to_owned(), "off".to_owned()),
+                ("random_page_cost".to_owned(), "1.1".to_owned())
+            ]),
+            parse_settings("enable_seqscan=off, random_page_cost = 1.1,")
+        );
+        assert!(parse_settings("enable_seqscan").is_err());
+        assert!(parse_settings("work_mem='1GB'; drop table x").is_err());
+    }
+}
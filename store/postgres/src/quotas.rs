@@ -0,0 +1,120 @@
+//! The quotas of API tokens and how many queries they made, in the
+//! `api_token_quotas` and `api_token_usage` tables
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_types::{BigInt, Text};
+use diesel::{sql_query, Connection, RunQueryDsl};
+use std::collections::HashMap;
+
+use graph::data::query::quota::{QuotaStore as QuotaStoreTrait, TokenQuota};
+use graph::prelude::Error;
+
+#[derive(QueryableByName)]
+struct Quota {
+    #[sql_type = "Text"]
+    token: String,
+    #[sql_type = "Text"]
+    name: String,
+    #[sql_type = "BigInt"]
+    monthly_limit: i64,
+}
+
+#[derive(QueryableByName)]
+struct Usage {
+    #[sql_type = "Text"]
+    token: String,
+    #[sql_type = "BigInt"]
+    queries: i64,
+}
+
+pub struct QuotaStore {
+    pool: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl QuotaStore {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        QuotaStore { pool }
+    }
+}
+
+impl QuotaStoreTrait for QuotaStore {
+    fn quotas(&self) -> Result<Vec<TokenQuota>, Error> {
+        let conn = self.pool.get()?;
+        Ok(
+            sql_query("select token, name, monthly_limit from api_token_quotas order by name")
+                .get_results::<Quota>(&*conn)?
+                .into_iter()
+                .map(|quota| TokenQuota {
+                    token: quota.token,
+                    name: quota.name,
+                    monthly_limit: quota.monthly_limit as u64,
+                })
+                .collect(),
+        )
+    }
+
+    fn set_quota(&self, quota: &TokenQuota) -> Result<(), Error> {
+        let conn = self.pool.get()?;
+        conn.transaction(|| {
+            // Giving a name to a different token moves the name
+            sql_query("delete from api_token_quotas where name = $1 and token <> $2")
+                .bind::<Text, _>(&quota.name)
+                .bind::<Text, _>(&quota.token)
+                .execute(&*conn)?;
+            sql_query(
+                "insert into api_token_quotas(token, name, monthly_limit)
+                 values ($1, $2, $3)
+                 on conflict(token) do update
+                    set name = excluded.name,
+                        monthly_limit = excluded.monthly_limit",
+            )
+            .bind::<Text, _>(&quota.token)
+            .bind::<Text, _>(&quota.name)
+            .bind::<BigInt, _>(quota.monthly_limit as i64)
+            .execute(&*conn)
+        })?;
+        Ok(())
+    }
+
+    fn remove_quota(&self, name: &str) -> Result<bool, Error> {
+        let conn = self.pool.get()?;
+        // The usage of the token is kept, so that its quota can be given
+        // back within the same month
+        let removed = sql_query("delete from api_token_quotas where name = $1")
+            .bind::<Text, _>(name)
+            .execute(&*conn)?;
+        Ok(removed > 0)
+    }
+
+    fn add_usage(&self, month: &str, counts: &[(String, u64)]) -> Result<(), Error> {
+        let conn = self.pool.get()?;
+        conn.transaction(|| {
+            for (token, count) in counts {
+                sql_query(
+                    "insert into api_token_usage(token, month, queries)
+                     values ($1, $2, $3)
+                     on conflict(token, month) do update
+                        set queries = api_token_usage.queries + excluded.queries",
+                )
+                .bind::<Text, _>(token)
+                .bind::<Text, _>(month)
+                .bind::<BigInt, _>(*count as i64)
+                .execute(&*conn)?;
+            }
+            Ok::<_, diesel::result::Error>(())
+        })?;
+        Ok(())
+    }
+
+    fn usage(&self, month: &str) -> Result<HashMap<String, u64>, Error> {
+        let conn = self.pool.get()?;
+        Ok(
+            sql_query("select token, queries from api_token_usage where month = $1")
+                .bind::<Text, _>(month)
+                .get_results::<Usage>(&*conn)?
+                .into_iter()
+                .map(|usage| (usage.token, usage.queries as u64))
+                .collect(),
+        )
+    }
+}
@@ -7,6 +7,8 @@
 //! The pivotal struct in this module is the `Layout` which handles all the
 //! information about mapping a GraphQL schema to database tables
 use diesel::connection::SimpleConnection;
+use diesel::pg::Pg;
+use diesel::query_builder::QueryFragment;
 use diesel::{
     debug_query, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
 };
@@ -21,26 +23,32 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use crate::relational_queries::{
-    self as rq, ClampRangeQuery, ConflictingEntityQuery, DeleteByPrefixQuery,
-    DeleteDynamicDataSourcesQuery, DeleteQuery, EntityData, FilterCollection, FilterQuery,
-    FindManyQuery, FindQuery, InsertQuery, RevertClampQuery, RevertRemoveQuery, UpdateQuery,
+    self as rq, CachedFilterQuery, ChangedEntitiesQuery, ClampRangeQuery, ConflictingEntityQuery,
+    DeleteByPrefixQuery, DeleteDynamicDataSourcesQuery, DeleteQuery, EntityData, ExplainQuery,
+    FilterCollection, FilterQuery, FindManyQuery, FindQuery, GroupData, GroupQuery, InsertQuery,
+    QueryPlanLine, RemovedEntitiesQuery, RevertClampQuery, RevertRemoveQuery, UpdateQuery,
 };
 use graph::data::graphql::ext::{DocumentExt, ObjectTypeExt};
+use graph::data::query::plans::{self, QueryPlan, SqlExplanation};
 use graph::data::schema::{FulltextConfig, FulltextDefinition, Schema, SCHEMA_TYPE_NAME};
 use graph::data::store::BYTES_SCALAR;
 use graph::data::subgraph::schema::{
     DynamicEthereumContractDataSourceEntity, POI_OBJECT, POI_TABLE,
 };
 use graph::prelude::{
-    format_err, info, BlockNumber, Entity, EntityChange, EntityChangeOperation, EntityCollection,
-    EntityFilter, EntityKey, EntityOrder, EntityRange, EthereumBlockPointer, Logger,
-    QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId, Value, ValueType,
-    BLOCK_NUMBER_MAX,
+    format_err, info, warn, BlockNumber, Entity, EntityChange, EntityChangeOperation,
+    EntityCollection, EntityFilter, EntityGroupQuery, EntityKey, EntityOperation, EntityOrder,
+    EntityRange, EthereumBlockPointer, Logger, QueryExecutionError, ReplicaIdentity, StoreError,
+    StoreEvent, SubgraphDeploymentId, Value, ValueType,
 };
+use graph::trace::{self, Span, SpanKind};
 
-use crate::block_range::{BLOCK_RANGE_COLUMN, BLOCK_UNVERSIONED};
+use crate::block_range::BLOCK_UNVERSIONED;
 pub use crate::catalog::Catalog;
+use crate::dialect::{Postgres, SqlDialect};
 use crate::entities::STRING_PREFIX_SIZE;
+use crate::planner;
+use crate::sql_cache;
 
 /// A string we use as a SQL name for a table or column. The important thing
 /// is that SQL names are snake cased. Using this type makes it easier to
@@ -180,6 +188,8 @@ pub struct Layout {
     pub enums: EnumMap,
     /// The query to count all entities
     pub count_query: String,
+    /// The dialect of the SQL that gets generated for this layout
+    pub dialect: Arc<dyn SqlDialect>,
 }
 
 impl Layout {
@@ -246,11 +256,20 @@ impl Layout {
         });
 
         // Map of type name to the type of the ID column for the object_types
-        // and interfaces in the schema
-        let id_types = object_types
-            .iter()
-            .map(|obj_type| IdType::try_from(*obj_type).map(|t| (obj_type.name.to_owned(), t)))
-            .chain(id_types_for_interface)
+        // and interfaces in the schema. References to types imported from
+        // other subgraphs are stored as strings
+        let id_types = schema
+            .imported_type_names()
+            .into_iter()
+            .map(|name| Ok((name, IdType::String)))
+            .chain(
+                object_types
+                    .iter()
+                    .map(|obj_type| {
+                        IdType::try_from(*obj_type).map(|t| (obj_type.name.to_owned(), t))
+                    })
+                    .chain(id_types_for_interface),
+            )
             .collect::<Result<IdTypeMap, _>>()?;
 
         // Construct a Table struct for each ObjectType
@@ -299,9 +318,17 @@ impl Layout {
             tables,
             enums,
             count_query,
+            dialect: Arc::new(Postgres),
         })
     }
 
+    /// Generate SQL for this layout in `dialect` instead of the default
+    /// Postgres dialect
+    pub fn with_dialect(mut self, dialect: Arc<dyn SqlDialect>) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
     fn make_poi_table(catalog: &Catalog, position: usize) -> Table {
         let table_name = SqlName::verbatim(POI_TABLE.to_owned());
         Table {
@@ -348,6 +375,17 @@ impl Layout {
             .as_ddl()
             .map_err(|_| StoreError::Unknown(format_err!("failed to generate DDL for layout")))?;
         conn.batch_execute(&sql)?;
+
+        let version = planner::server_version(conn)?;
+        let mut sql = String::new();
+        for table in layout.tables.values() {
+            planner::statistics_ddl(&mut sql, &layout.catalog.schema, table, version).map_err(
+                |_| StoreError::Unknown(format_err!("failed to generate statistics for layout")),
+            )?;
+        }
+        if !sql.is_empty() {
+            conn.batch_execute(&sql)?;
+        }
         Ok(layout)
     }
 
@@ -444,19 +482,13 @@ impl Layout {
 
         // Output enums first
         for (name, values) in &self.enums {
-            let mut sep = "";
             let name = SqlName::from(name.as_str());
-            write!(
-                out,
-                "create type {}.{}\n    as enum (",
-                self.catalog.schema,
-                name.quoted()
+            self.dialect.write_enum_type(
+                &mut out,
+                &self.catalog.schema,
+                name.as_str(),
+                &mut values.iter(),
             )?;
-            for value in values.iter() {
-                write!(out, "{}'{}'", sep, value)?;
-                sep = ", "
-            }
-            writeln!(out, ");")?;
         }
         // We sort tables here solely because the unit tests rely on
         // 'create table' statements appearing in a fixed order
@@ -493,7 +525,9 @@ impl Layout {
         block: BlockNumber,
     ) -> Result<Option<Entity>, StoreError> {
         let table = self.table_for_entity(entity)?;
-        FindQuery::new(table.as_ref(), id, block)
+        let query = FindQuery::new(table.as_ref(), id, block);
+        let _span = sql_span("sql.find", &query);
+        query
             .get_result::<EntityData>(conn)
             .optional()?
             .map(|entity_data| entity_data.deserialize_with_layout(self))
@@ -516,8 +550,11 @@ impl Layout {
             tables,
             block,
         };
+        let span = sql_span("sql.find_many", &query);
+        let rows = query.load::<EntityData>(conn)?;
+        drop(span);
         let mut entities_for_type: BTreeMap<String, Vec<Entity>> = BTreeMap::new();
-        for data in query.load::<EntityData>(conn)? {
+        for data in rows {
             entities_for_type
                 .entry(data.entity_type())
                 .or_default()
@@ -526,6 +563,51 @@ impl Layout {
         Ok(entities_for_type)
     }
 
+    /// The changes that `block` made to entities of `entity_types`, ordered
+    /// by entity type and id. Entities that were created or updated show
+    /// up with their data as of `block`, and removed entities with just
+    /// their key
+    pub fn changes_in_block(
+        &self,
+        conn: &PgConnection,
+        entity_types: &[String],
+        block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        let mut entity_types: Vec<_> = entity_types.iter().collect();
+        entity_types.sort();
+        entity_types.dedup();
+
+        let mut changes = Vec::new();
+        for entity_type in entity_types {
+            let table = self.table_for_entity(entity_type)?;
+            let key = |entity_id: String| EntityKey {
+                subgraph_id: self.subgraph.clone(),
+                entity_type: entity_type.clone(),
+                entity_id,
+            };
+
+            let query = ChangedEntitiesQuery::new(table.as_ref(), block);
+            let span = sql_span("sql.changes_in_block", &query);
+            let rows = query.load::<EntityData>(conn)?;
+            drop(span);
+            for data in rows {
+                let data: Entity = data.deserialize_with_layout(self)?;
+                changes.push(EntityOperation::Set {
+                    key: key(data.id()?),
+                    data,
+                });
+            }
+
+            let removed = RemovedEntitiesQuery::new(table.as_ref(), block).get_results(conn)?;
+            changes.extend(
+                removed
+                    .into_iter()
+                    .map(|data| EntityOperation::Remove { key: key(data.id) }),
+            );
+        }
+        Ok(changes)
+    }
+
     pub fn insert(
         &self,
         conn: &PgConnection,
@@ -535,6 +617,7 @@ impl Layout {
     ) -> Result<(), StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
         let query = InsertQuery::new(table, key, entity, block)?;
+        let _span = sql_span("sql.insert", &query);
         query.execute(conn)?;
         Ok(())
     }
@@ -547,6 +630,7 @@ impl Layout {
     ) -> Result<(), StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
         let query = InsertQuery::new(table, key, entity, BLOCK_UNVERSIONED)?;
+        let _span = sql_span("sql.insert", &query);
         query.execute(conn)?;
         Ok(())
     }
@@ -571,6 +655,7 @@ impl Layout {
         collection: EntityCollection,
         filter: Option<EntityFilter>,
         order: EntityOrder,
+        distinct: bool,
         range: EntityRange,
         block: BlockNumber,
     ) -> Result<Vec<T>, QueryExecutionError> {
@@ -605,18 +690,48 @@ impl Layout {
         }
 
         let filter_collection = FilterCollection::new(&self, collection, filter.as_ref())?;
-        let query = FilterQuery::new(&filter_collection, filter.as_ref(), order, range, block)?;
+        let query = FilterQuery::new(
+            &filter_collection,
+            filter.as_ref(),
+            order,
+            distinct,
+            range,
+            block,
+        )?
+        .with_query_id(trace::query_id());
         let query_clone = query.clone();
 
+        let is_window = match filter_collection {
+            FilterCollection::All(_) => false,
+            FilterCollection::SingleWindow(_) | FilterCollection::MultiWindow(_, _) => true,
+        };
+
+        if plans::is_explaining(&self.subgraph) {
+            self.explain_only(conn, &query_clone, is_window)?;
+            return Ok(vec![]);
+        }
+
         let start = Instant::now();
-        let values = query.load::<EntityData>(conn).map_err(|e| {
+        let span = sql_span("sql.query", &query_clone);
+        let values = planner::with_window_settings(conn, is_window, || {
+            match sql_cache::sql_for(&self.subgraph, &query) {
+                Some(sql) => CachedFilterQuery::new(sql, &query).load::<EntityData>(conn),
+                None => query.load::<EntityData>(conn),
+            }
+        })
+        .map_err(|e| {
             QueryExecutionError::ResolveEntitiesError(format!(
                 "{}, query = {:?}",
                 e,
                 debug_query(&query_clone).to_string()
             ))
         })?;
-        log_query_timing(logger, &query_clone, start.elapsed(), values.len());
+        drop(span);
+        let elapsed = start.elapsed();
+        log_query_timing(logger, &query_clone, elapsed, values.len());
+        if let Some(hash) = trace::query_hash().filter(|_| plans::should_explain(elapsed)) {
+            self.explain_query(logger, conn, &query_clone, hash, elapsed);
+        }
         values
             .into_iter()
             .map(|entity_data| {
@@ -627,6 +742,164 @@ impl Layout {
             .collect()
     }
 
+    /// Group the entities of `query.entity_type` and compute aggregates
+    /// for each group
+    pub fn find_groups(
+        &self,
+        conn: &PgConnection,
+        query: &EntityGroupQuery,
+    ) -> Result<Vec<BTreeMap<String, q::Value>>, QueryExecutionError> {
+        use graph::prelude::serde_json::Value as j;
+        use rq::FromColumnValue;
+
+        let table = self.table_for_entity(&query.entity_type)?;
+        let group_query = GroupQuery::new(table, query)?;
+        let result_types = group_query.result_types();
+        let group_query_clone = group_query.clone();
+        if plans::is_explaining(&self.subgraph) {
+            self.explain_only(conn, &group_query_clone, false)?;
+            return Ok(vec![]);
+        }
+
+        let span = sql_span("sql.groups", &group_query_clone);
+        let groups = group_query.load::<GroupData>(conn).map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!(
+                "{}, query = {:?}",
+                e,
+                debug_query(&group_query_clone).to_string()
+            ))
+        })?;
+        drop(span);
+        groups
+            .into_iter()
+            .map(|group| match group.data {
+                j::Object(map) => map
+                    .into_iter()
+                    .filter_map(|(key, json)| {
+                        result_types.get(&key).map(|column_type| {
+                            q::Value::from_column_value(column_type, json)
+                                .map(|value| (key, value))
+                                .map_err(QueryExecutionError::from)
+                        })
+                    })
+                    .collect::<Result<BTreeMap<_, _>, _>>(),
+                _ => unreachable!("we use `to_jsonb` in the query and always get an object back"),
+            })
+            .collect()
+    }
+
+    /// Run `query` again with `explain` and record its plan for the shape
+    /// `hash` of the GraphQL query it is for. Failures are only logged
+    fn explain_query(
+        &self,
+        logger: &Logger,
+        conn: &PgConnection,
+        query: &FilterQuery,
+        hash: u64,
+        duration: Duration,
+    ) {
+        // 20kB, as for the query timing log
+        const MAXLEN: usize = 20_480;
+
+        match ExplainQuery::new(query, true).load::<QueryPlanLine>(conn) {
+            Ok(lines) => plans::record(
+                hash,
+                QueryPlan {
+                    subgraph: self.subgraph.to_string(),
+                    sql: debug_query(&query)
+                        .to_string()
+                        .chars()
+                        .take(MAXLEN)
+                        .collect(),
+                    plan: lines
+                        .into_iter()
+                        .map(|line| line.line)
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    duration,
+                },
+            ),
+            Err(e) => warn!(logger, "Failed to explain slow query";
+                            "subgraph" => self.subgraph.to_string(),
+                            "error" => e.to_string()),
+        }
+    }
+
+    /// Explain `query` without running it, and add the explanation to the
+    /// statements that `plans::explain` returns
+    fn explain_only<Q: QueryFragment<Pg>>(
+        &self,
+        conn: &PgConnection,
+        query: &Q,
+        is_window: bool,
+    ) -> Result<(), QueryExecutionError> {
+        let lines = planner::with_window_settings(conn, is_window, || {
+            ExplainQuery::new(query, false).load::<QueryPlanLine>(conn)
+        })
+        .map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!(
+                "failed to explain query: {}, query = {:?}",
+                e,
+                debug_query::<Pg, _>(query).to_string()
+            ))
+        })?;
+        plans::add_explanation(SqlExplanation {
+            sql: debug_query::<Pg, _>(query).to_string(),
+            plan: lines
+                .into_iter()
+                .map(|line| line.line)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        });
+        Ok(())
+    }
+
+    /// The name of the Postgres publication for the tables of this
+    /// deployment
+    pub fn publication_name(&self) -> String {
+        format!("{}_pub", self.catalog.schema)
+    }
+
+    /// Create the publication for all tables of this deployment, or drop it
+    /// if `publish` is `false`. An existing publication is replaced so that
+    /// it covers exactly the tables that the deployment has now. With a
+    /// `replica_identity`, also change the replica identity of all tables.
+    /// This should be run in a transaction
+    pub fn set_publication(
+        &self,
+        conn: &PgConnection,
+        publish: bool,
+        replica_identity: Option<ReplicaIdentity>,
+    ) -> Result<Option<String>, StoreError> {
+        let name = self.publication_name();
+        let mut tables = self
+            .tables
+            .values()
+            .map(|table| table.qualified_name.as_str())
+            .collect::<Vec<_>>();
+        tables.sort();
+
+        let mut sql = format!("drop publication if exists {};\n", name);
+        if publish {
+            sql.push_str(&format!(
+                "create publication {} for table {};\n",
+                name,
+                tables.join(", ")
+            ));
+        }
+        if let Some(replica_identity) = replica_identity {
+            for table in &tables {
+                sql.push_str(&format!(
+                    "alter table {} replica identity {};\n",
+                    table,
+                    replica_identity.as_sql()
+                ));
+            }
+        }
+        conn.batch_execute(&sql)?;
+        Ok(if publish { Some(name) } else { None })
+    }
+
     pub fn update(
         &self,
         conn: &PgConnection,
@@ -635,8 +908,12 @@ impl Layout {
         block: BlockNumber,
     ) -> Result<(), StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
-        ClampRangeQuery::new(table, key, block).execute(conn)?;
+        let clamp = ClampRangeQuery::new(table, key, block);
+        let span = sql_span("sql.clamp", &clamp);
+        clamp.execute(conn)?;
+        drop(span);
         let query = InsertQuery::new(table, key, entity, block)?;
+        let _span = sql_span("sql.insert", &query);
         query.execute(conn)?;
         Ok(())
     }
@@ -649,6 +926,7 @@ impl Layout {
     ) -> Result<usize, StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
         let query = UpdateQuery::new(table, key, entity)?;
+        let _span = sql_span("sql.update", &query);
         Ok(query.execute(conn)?)
     }
 
@@ -667,6 +945,7 @@ impl Layout {
             }
         }
         let query = UpdateQuery::new(table, key, &entity)?;
+        let _span = sql_span("sql.update", &query);
         Ok(query.execute(conn)?)
     }
 
@@ -677,7 +956,9 @@ impl Layout {
         block: BlockNumber,
     ) -> Result<usize, StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
-        Ok(ClampRangeQuery::new(table, key, block).execute(conn)?)
+        let query = ClampRangeQuery::new(table, key, block);
+        let _span = sql_span("sql.delete", &query);
+        Ok(query.execute(conn)?)
     }
 
     pub fn delete_unversioned(
@@ -686,7 +967,9 @@ impl Layout {
         key: &EntityKey,
     ) -> Result<usize, StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
-        Ok(DeleteQuery::new(table, key).execute(conn)?)
+        let query = DeleteQuery::new(table, key);
+        let _span = sql_span("sql.delete", &query);
+        Ok(query.execute(conn)?)
     }
 
     pub fn revert_block(
@@ -855,6 +1138,9 @@ pub enum ColumnType {
     /// A `bytea` in SQL, represented as a ValueType::String; this is
     /// used for `id` columns of type `Bytes`
     BytesId,
+    /// A `point` in SQL with the longitude as `x` and the latitude as `y`,
+    /// which is what the `earthdistance` extension expects
+    GeoPoint,
 }
 
 impl From<IdType> for ColumnType {
@@ -910,24 +1196,17 @@ impl ColumnType {
             ValueType::Bytes => Ok(ColumnType::Bytes),
             ValueType::Int => Ok(ColumnType::Int),
             ValueType::String => Ok(ColumnType::String),
+            ValueType::GeoPoint => Ok(ColumnType::GeoPoint),
             ValueType::List => Err(StoreError::Unknown(format_err!(
                 "can not convert ValueType::List to ColumnType"
             ))),
         }
     }
 
+    /// The Postgres type for this column type. Queries are always run
+    /// against Postgres; DDL uses the type from the layout's dialect
     pub fn sql_type(&self) -> &str {
-        match self {
-            ColumnType::Boolean => "boolean",
-            ColumnType::BigDecimal => "numeric",
-            ColumnType::BigInt => "numeric",
-            ColumnType::Bytes => "bytea",
-            ColumnType::Int => "integer",
-            ColumnType::String => "text",
-            ColumnType::TSVector(_) => "tsvector",
-            ColumnType::Enum(enum_type) => enum_type.name.as_str(),
-            ColumnType::BytesId => "bytea",
-        }
+        Postgres.sql_type(self)
     }
 
     /// Return the `IdType` corresponding to this column type. This can only
@@ -1004,10 +1283,6 @@ impl Column {
         })
     }
 
-    fn sql_type(&self) -> &str {
-        self.column_type.sql_type()
-    }
-
     pub fn is_nullable(&self) -> bool {
         fn is_nullable(field_type: &q::Type) -> bool {
             match field_type {
@@ -1043,6 +1318,10 @@ impl Column {
         named_type(&self.field_type) == "fulltext"
     }
 
+    pub fn is_geo_point(&self) -> bool {
+        self.column_type == ColumnType::GeoPoint
+    }
+
     pub fn is_reference(&self) -> bool {
         self.is_reference
     }
@@ -1091,9 +1370,14 @@ impl Column {
     ///
     /// See the unit tests at the end of this file for the actual DDL that
     /// gets generated
-    fn as_ddl(&self, out: &mut String) -> fmt::Result {
+    fn as_ddl(&self, out: &mut String, dialect: &dyn SqlDialect) -> fmt::Result {
         write!(out, "    ")?;
-        write!(out, "{:20} {}", self.name.quoted(), self.sql_type())?;
+        write!(
+            out,
+            "{:20} {}",
+            self.name.quoted(),
+            dialect.sql_type(&self.column_type)
+        )?;
         if self.is_list() {
             write!(out, "[]")?;
         }
@@ -1222,55 +1506,24 @@ impl Table {
         )?;
         for column in self.columns.iter() {
             write!(out, "    ")?;
-            column.as_ddl(out)?;
+            column.as_ddl(out, layout.dialect.as_ref())?;
             writeln!(out, ",")?;
         }
         // Add block_range column and constraint
-        write!(
-            out,
-            "\n        {vid}                  bigserial primary key,\
-             \n        {block_range}          int4range not null,
-        exclude using gist   (id with =, {block_range} with &&)\n);\n",
-            vid = VID_COLUMN,
-            block_range = BLOCK_RANGE_COLUMN
-        )?;
-
-        // Add a BRIN index on the block_range bounds to exploit the fact
-        // that block ranges closely correlate with where in a table an
-        // entity appears physically. This index is incredibly efficient for
-        // reverts where we look for very recent blocks, so that this index
-        // is highly selective. See https://github.com/graphprotocol/graph-node/issues/1415#issuecomment-630520713
-        // for details on one experiment.
-        //
-        // We do not index the `block_range` as a whole, but rather the lower
-        // and upper bound separately, since experimentation has shown that
-        // Postgres will not use the index on `block_range` for clauses like
-        // `block_range @> $block` but rather falls back to a full table scan.
-        //
-        // We also make sure that we do not put `NULL` in the index for
-        // the upper bound since nulls can not be compared to anything and
-        // will make the index less effective.
-        //
-        // To make the index usable, queries need to have clauses using
-        // `lower(block_range)` and `coalesce(..)` verbatim.
-        //
-        // We also index `vid` as that correlates with the order in which
-        // entities are stored.
-        write!(out,"create index brin_{table_name}\n    \
-                    on {schema_name}.{table_name}\n \
-                       using brin(lower(block_range), coalesce(upper(block_range), {block_max}), vid);\n",
-            table_name = self.name,
-            schema_name = layout.catalog.schema,
-            block_max = BLOCK_NUMBER_MAX)?;
+        layout.dialect.write_versioning_columns(out)?;
+        layout
+            .dialect
+            .write_block_range_index(out, &layout.catalog.schema, self.name.as_str())?;
 
         // Create indexes. Skip columns whose type is an array of enum,
         // since there is no good way to index them with Postgres 9.6.
         // Once we move to Postgres 11, we can enable that
-        // (tracked in graph-node issue #1330)
+        // (tracked in graph-node issue #1330). Arrays of points can not
+        // be indexed at all
         for (i, column) in self
             .columns
             .iter()
-            .filter(|col| !(col.is_list() && col.is_enum()))
+            .filter(|col| !(col.is_list() && (col.is_enum() || col.is_geo_point())))
             .enumerate()
         {
             // Attributes that are plain strings are indexed with a BTree; but
@@ -1283,11 +1536,7 @@ impl Table {
                 column.name.quoted()
             };
 
-            let method = if column.is_list() || column.is_fulltext() {
-                "gin"
-            } else {
-                "btree"
-            };
+            let method = layout.dialect.attribute_index_method(column);
             write!(
                 out,
                 "create index attr_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
@@ -1306,6 +1555,28 @@ impl Table {
 
 /// Return the enclosed named type for a field type, i.e., the type after
 /// stripping List and NonNull.
+/// A span for running `query`, as a child of the current span. The text of
+/// the statement is only rendered if the span is exported
+fn sql_span<Q: QueryFragment<Pg>>(name: &'static str, query: &Q) -> Span {
+    // 20kB, as for the query timing log
+    const MAXLEN: usize = 20_480;
+
+    let mut span = Span::child_of_kind(trace::current().as_ref(), name, SpanKind::Client);
+    if span.is_recording() {
+        let mut text = debug_query::<Pg, _>(query).to_string().replace("\n", " ");
+        if text.len() > MAXLEN {
+            text.truncate(MAXLEN);
+            text.push_str(" ...");
+        }
+        span.set_attribute("db.system", "postgresql");
+        span.set_attribute("db.statement", text);
+        if let Some(query_id) = trace::query_id() {
+            span.set_attribute("graphql.query_id", query_id);
+        }
+    }
+    span
+}
+
 fn named_type(field_type: &q::Type) -> &str {
     match field_type {
         q::Type::NamedType(name) => name.as_str(),
@@ -1366,6 +1637,167 @@ mod tests {
         assert!(table.column(&bad_sql_name).is_none());
     }
 
+    #[test]
+    fn queries_with_the_same_shape_have_the_same_sql() {
+        let layout = test_layout(THING_GQL);
+        let collection = EntityCollection::All(vec!["Scalar".to_owned()]);
+        let shape_and_sql = |filter: EntityFilter, range: EntityRange| {
+            let collection = FilterCollection::new(&layout, collection.clone(), Some(&filter))
+                .expect("the filter is valid");
+            let query = FilterQuery::new(
+                &collection,
+                Some(&filter),
+                EntityOrder::Default,
+                false,
+                range,
+                1,
+            )
+            .expect("the query is valid")
+            .with_query_id(Some("query-1".to_owned()));
+            (query.shape().unwrap(), query.sql().unwrap())
+        };
+        let equal = |value: &str| EntityFilter::new_equal("string", value);
+
+        let (shape, sql) = shape_and_sql(equal("a"), EntityRange::first(10));
+        assert!(!sql.contains("query-1"));
+        assert_eq!(
+            (shape.clone(), sql.clone()),
+            shape_and_sql(equal("b"), EntityRange::first(10))
+        );
+        let long = "x".repeat(STRING_PREFIX_SIZE + 1);
+        let (long_shape, long_sql) = shape_and_sql(equal(&long), EntityRange::first(10));
+        assert_ne!(shape, long_shape);
+        assert_ne!(sql, long_sql);
+        assert_ne!(shape, shape_and_sql(equal("a"), EntityRange::first(20)).0);
+    }
+
+    #[test]
+    fn distinct_queries_select_distinct_on_the_sort_key() {
+        let layout = test_layout(THING_GQL);
+        let collection = FilterCollection::new(
+            &layout,
+            EntityCollection::All(vec!["Scalar".to_owned()]),
+            None,
+        )
+        .expect("the collection is valid");
+        let sql = |order: EntityOrder, distinct: bool| {
+            FilterQuery::new(
+                &collection,
+                None,
+                order,
+                distinct,
+                EntityRange::first(10),
+                1,
+            )
+            .and_then(|query| {
+                query
+                    .sql()
+                    .map_err(|e| QueryExecutionError::ResolveEntitiesError(e.to_string()))
+            })
+        };
+
+        let order = EntityOrder::Ascending("string".to_owned(), ValueType::String);
+        let distinct = sql(order.clone(), true).unwrap();
+        assert!(distinct.contains("select distinct on (c.\"string\") * "));
+        assert!(distinct.contains("order by \"string\" asc"));
+        assert!(!sql(order, false).unwrap().contains("distinct on"));
+        assert!(sql(EntityOrder::Default, true)
+            .unwrap()
+            .contains("select distinct on (c.\"id\") * "));
+        assert!(sql(EntityOrder::Unordered, true).is_err());
+    }
+
+    #[test]
+    fn numeric_filters_compare_as_numeric() {
+        let layout = test_layout(THING_GQL);
+        let collection = FilterCollection::new(
+            &layout,
+            EntityCollection::All(vec!["Scalar".to_owned()]),
+            None,
+        )
+        .expect("the collection is valid");
+        let sql = |filter: EntityFilter| {
+            FilterQuery::new(
+                &collection,
+                Some(&filter),
+                EntityOrder::Default,
+                false,
+                EntityRange::first(10),
+                1,
+            )
+            .and_then(|query| {
+                query
+                    .sql()
+                    .map_err(|e| QueryExecutionError::ResolveEntitiesError(e.to_string()))
+            })
+        };
+
+        let filter = EntityFilter::GreaterThan("bigDecimal".to_owned(), Value::from("1000.5"));
+        assert!(sql(filter)
+            .unwrap()
+            .contains("\"big_decimal\" > $2::numeric"));
+        let filter = EntityFilter::LessThan("bigInt".to_owned(), Value::Int(7));
+        assert!(sql(filter).unwrap().contains("\"big_int\" < $2::numeric"));
+        let filter = EntityFilter::Equal("bigDecimal".to_owned(), Value::from("a lot"));
+        assert!(sql(filter).is_err());
+    }
+
+    #[test]
+    fn geo_point_columns_and_filters() {
+        let layout = test_layout(
+            "type Shop @entity { id: ID!, location: GeoPoint!, branches: [GeoPoint!] }",
+        );
+        let ddl = layout.as_ddl().expect("Failed to generate DDL");
+        assert!(ddl.contains(&format!("{:20} point not null", "\"location\"")));
+        assert!(ddl.contains("using gist(\"location\")"));
+        assert!(!ddl.contains("(\"branches\")"));
+
+        let collection = FilterCollection::new(
+            &layout,
+            EntityCollection::All(vec!["Shop".to_owned()]),
+            None,
+        )
+        .expect("the collection is valid");
+        let sql = |filter: EntityFilter| {
+            FilterQuery::new(
+                &collection,
+                Some(&filter),
+                EntityOrder::Default,
+                false,
+                EntityRange::first(10),
+                1,
+            )
+            .and_then(|query| {
+                query
+                    .sql()
+                    .map_err(|e| QueryExecutionError::ResolveEntitiesError(e.to_string()))
+            })
+            .unwrap()
+        };
+
+        let within = |of: &str, radius: f64| {
+            sql(EntityFilter::Within(
+                "location".to_owned(),
+                Value::from(of),
+                radius,
+            ))
+        };
+        let near = within("52.52,13.405", 1500.0);
+        assert!(near.contains("(\"location\" <@ box($2::point, $3::point) and"));
+        assert!(near.contains("(\"location\" <@> $4::point) <= $5"));
+        // Around the pole and across the antimeridian, there is no box
+        let polar = within("89.99,0", 5000.0);
+        assert!(!polar.contains("box("));
+        assert!(polar.contains("(\"location\" <@> $2::point) <= $3"));
+        assert!(!within("0,179.99", 5000.0).contains("box("));
+
+        let equal = sql(EntityFilter::Not(
+            "location".to_owned(),
+            Value::from("52.52,13.405"),
+        ));
+        assert!(equal.contains("not (\"location\" ~= $2::point)"));
+    }
+
     #[test]
     fn generate_ddl() {
         let layout = test_layout(THING_GQL);
@@ -1,3 +1,4 @@
+use diesel::backend::Backend;
 ///! This module contains the gory details of using Diesel to query
 ///! a database schema that is not known at compile time. The code in this
 ///! module is mostly concerned with constructing SQL queries and some
@@ -5,21 +6,24 @@
 ///!
 ///! Code in this module works very hard to minimize the number of allocations
 ///! that it performs
-use diesel::pg::{Pg, PgConnection};
-use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::deserialize::QueryableByName;
+use diesel::pg::{Pg, PgConnection, PgQueryBuilder};
+use diesel::query_builder::{AstPass, QueryBuilder, QueryFragment, QueryId};
 use diesel::query_dsl::{LoadQuery, RunQueryDsl};
 use diesel::result::{Error as DieselError, QueryResult};
-use diesel::sql_types::{Array, Binary, Bool, Integer, Jsonb, Range, Text};
+use diesel::sql_types::{Array, Binary, Bool, Double, Integer, Jsonb, Range, Text, TypeMetadata};
 use diesel::Connection;
 use std::collections::{BTreeMap, HashSet};
 use std::convert::TryFrom;
 use std::iter::FromIterator;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use graph::data::{schema::FulltextAlgorithm, store::scalar};
 use graph::prelude::{
-    format_err, serde_json, Attribute, BlockNumber, ChildMultiplicity, Entity, EntityCollection,
-    EntityFilter, EntityKey, EntityLink, EntityOrder, EntityRange, EntityWindow, ParentLink,
+    format_err, serde_json, AggregateFilter, Attribute, BlockNumber, ChildMultiplicity, Entity,
+    EntityAggregate, EntityCollection, EntityFilter, EntityGroupQuery, EntityKey, EntityLink,
+    EntityOrder, EntityRange, EntityWindow, GroupOrder, GroupSortKey, ParentLink,
     QueryExecutionError, StoreError, Value,
 };
 
@@ -41,6 +45,36 @@ fn bytes_as_str(id: &str) -> String {
     id.trim_start_matches("\\x").to_owned()
 }
 
+/// The radius of the earth in statute miles that the `<@>` operator of the
+/// `earthdistance` extension uses
+const EARTH_RADIUS_MILES: f64 = 3958.747716;
+const METERS_PER_MILE: f64 = 1609.344;
+
+fn str_as_geo_point(s: &str) -> QueryResult<scalar::GeoPoint> {
+    scalar::GeoPoint::from_str(s).map_err(|e| DieselError::SerializationError(e.to_string().into()))
+}
+
+/// Convert our string representation of a point "lat,lng" to the Postgres
+/// representation "(lng,lat)"
+fn geo_point_as_pg(point: &scalar::GeoPoint) -> String {
+    format!("({},{})", point.lng, point.lat)
+}
+
+/// Convert the Postgres representation of a point "(lng,lat)" to ours
+fn pg_as_geo_point(s: &str) -> Result<String, StoreError> {
+    let mut coords = s
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .splitn(2, ',')
+        .map(|coord| f64::from_str(coord).ok());
+    match (coords.next().flatten(), coords.next().flatten()) {
+        (Some(lng), Some(lat)) => scalar::GeoPoint::new(lat, lng)
+            .map(|point| point.to_string())
+            .map_err(|e| StoreError::Unknown(format_err!("invalid point {}: {}", s, e))),
+        _ => Err(StoreError::Unknown(format_err!("invalid point {}", s))),
+    }
+}
+
 /// Conveniences for handling foreign keys depending on whether we are using
 /// `IdType::Bytes` or `IdType::String` as the primary key
 ///
@@ -257,6 +291,7 @@ pub trait FromColumnValue: Sized {
             }
             (j::String(s), ColumnType::Bytes) => Self::from_bytes(s.trim_start_matches("\\x")),
             (j::String(s), ColumnType::BytesId) => Ok(Self::from_string(bytes_as_str(&s))),
+            (j::String(s), ColumnType::GeoPoint) => pg_as_geo_point(&s).map(Self::from_string),
             (j::String(s), column_type) => Err(StoreError::Unknown(format_err!(
                 "can not convert string {} to {:?}",
                 s,
@@ -421,6 +456,28 @@ impl<'a> QueryFragment<Pg> for QueryValue<'a> {
         out.unsafe_to_cache_prepared();
         let column_type = self.1;
 
+        // Values compared against a numeric column are always passed as
+        // `numeric`, whatever their own type, so that the comparison is
+        // numeric and Postgres can use the index on the column
+        if let ColumnType::BigDecimal | ColumnType::BigInt = column_type {
+            let number = match self.0 {
+                Value::String(s) => Some(
+                    scalar::BigDecimal::from_str(s)
+                        .map_err(|e| DieselError::SerializationError(Box::new(e)))?
+                        .to_string(),
+                ),
+                Value::Int(i) => Some(i.to_string()),
+                Value::BigInt(i) => Some(i.to_string()),
+                Value::BigDecimal(d) => Some(d.to_string()),
+                _ => None,
+            };
+            if let Some(number) = number {
+                out.push_bind_param::<Text, _>(&number)?;
+                out.push_sql("::numeric");
+                return Ok(());
+            }
+        }
+
         match self.0 {
             Value::String(s) => match &column_type {
                 ColumnType::String => out.push_bind_param::<Text, _>(s),
@@ -441,8 +498,13 @@ impl<'a> QueryFragment<Pg> for QueryValue<'a> {
                         .map_err(|e| DieselError::SerializationError(Box::new(e)))?;
                     out.push_bind_param::<Binary, _>(&bytes.as_slice())
                 }
+                ColumnType::GeoPoint => {
+                    out.push_bind_param::<Text, _>(&geo_point_as_pg(&str_as_geo_point(s)?))?;
+                    out.push_sql("::point");
+                    Ok(())
+                }
                 _ => unreachable!(
-                    "only string, enum and tsvector columns have values of type string"
+                    "only string, enum, tsvector, numeric and point columns have values of type string"
                 ),
             },
             Value::Int(i) => out.push_bind_param::<Integer, _>(i),
@@ -488,6 +550,15 @@ impl<'a> QueryFragment<Pg> for QueryValue<'a> {
                         Ok(())
                     }
                     ColumnType::BytesId => out.push_bind_param::<Array<Binary>, _>(&sql_values),
+                    ColumnType::GeoPoint => {
+                        let points = values
+                            .iter()
+                            .map(|v| str_as_geo_point(&v.to_string()).map(|p| geo_point_as_pg(&p)))
+                            .collect::<QueryResult<Vec<_>>>()?;
+                        out.push_bind_param::<Array<Text>, _>(&points)?;
+                        out.push_sql("::point[]");
+                        Ok(())
+                    }
                 }
             }
             Value::Null => {
@@ -704,7 +775,8 @@ impl<'a> QueryFilter<'a> {
             | StartsWith(attr, _)
             | NotStartsWith(attr, _)
             | EndsWith(attr, _)
-            | NotEndsWith(attr, _) => {
+            | NotEndsWith(attr, _)
+            | Within(attr, _, _) => {
                 table.column_for_field(attr)?;
             }
         }
@@ -820,6 +892,16 @@ impl<'a> QueryFilter<'a> {
             out.push_identifier(column.name.as_str())?;
             out.push_sql(Comparison::Match.as_str());
             QueryValue(value, &column.column_type).walk_ast(out)?;
+        } else if column.is_geo_point() && !column.is_list() && value != &Value::Null {
+            // Postgres has no `=` for points, only `~=`
+            if op == Comparison::NotEqual {
+                out.push_sql("not ");
+            }
+            out.push_sql("(");
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(" ~= ");
+            QueryValue(value, &column.column_type).walk_ast(out.reborrow())?;
+            out.push_sql(")");
         } else {
             out.push_identifier(column.name.as_str())?;
 
@@ -924,6 +1006,31 @@ impl<'a> QueryFilter<'a> {
             out.push_sql(" or ");
         }
 
+        if have_non_nulls && column.is_primary_key() {
+            // Lookups of many entities by id are common, and passing all ids
+            // as one array keeps the statement the same no matter how many
+            // ids there are
+            //   id {= any|!= all}($ids)
+            let ids = values
+                .iter()
+                .filter_map(|value| match value {
+                    Value::String(id) => Some(id.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            if ids.len() == values.len() {
+                out.push_identifier(column.name.as_str())?;
+                if negated {
+                    out.push_sql(" != all(");
+                } else {
+                    out.push_sql(" = any(");
+                }
+                column.bind_ids(&ids, &mut out)?;
+                out.push_sql(")");
+                return Ok(());
+            }
+        }
+
         if have_non_nulls {
             if column.is_text()
                 && values.iter().all(|v| match v {
@@ -1002,6 +1109,72 @@ impl<'a> QueryFilter<'a> {
         }
         Ok(())
     }
+
+    /// Generate
+    ///   (attribute <@ box($lower_left, $upper_right)
+    ///    and (attribute <@> $center) <= $miles)
+    /// The first condition makes the gist index on the attribute usable.
+    /// We leave it out when the circle around `center` contains a pole or
+    /// crosses the antimeridian, since no box describes that area then
+    fn within(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        radius: f64,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+        let center = match value {
+            Value::String(s) if column.is_geo_point() && !column.is_list() => str_as_geo_point(s)?,
+            _ => {
+                return Err(UnsupportedFilter {
+                    filter: "within".to_owned(),
+                    value: value.clone(),
+                }
+                .into())
+            }
+        };
+
+        out.push_sql("(");
+        if let Some((lower_left, upper_right)) = bounding_box(&center, radius) {
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(" <@ box(");
+            out.push_bind_param::<Text, _>(&geo_point_as_pg(&lower_left))?;
+            out.push_sql("::point, ");
+            out.push_bind_param::<Text, _>(&geo_point_as_pg(&upper_right))?;
+            out.push_sql("::point) and ");
+        }
+        out.push_sql("(");
+        out.push_identifier(column.name.as_str())?;
+        out.push_sql(" <@> ");
+        out.push_bind_param::<Text, _>(&geo_point_as_pg(&center))?;
+        out.push_sql("::point) <= ");
+        out.push_bind_param::<Double, _>(&(radius / METERS_PER_MILE))?;
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+/// The corners of the smallest box of latitudes and longitudes that
+/// contains all points that are at most `radius` meters from `center`, or
+/// `None` if there is no such box
+fn bounding_box(
+    center: &scalar::GeoPoint,
+    radius: f64,
+) -> Option<(scalar::GeoPoint, scalar::GeoPoint)> {
+    // The angle between the center and the points on the circle
+    let angle = radius / METERS_PER_MILE / EARTH_RADIUS_MILES;
+    let dlat = angle.to_degrees();
+    // The sine of the largest difference in longitude of points on the
+    // circle; if it is not below 1, the circle contains a pole
+    let sin_dlng = angle.sin() / center.lat.to_radians().cos();
+    if angle >= std::f64::consts::FRAC_PI_2 || sin_dlng >= 1.0 {
+        return None;
+    }
+    let dlng = sin_dlng.asin().to_degrees();
+    let lower_left = scalar::GeoPoint::new(center.lat - dlat, center.lng - dlng).ok()?;
+    let upper_right = scalar::GeoPoint::new(center.lat + dlat, center.lng + dlng).ok()?;
+    Some((lower_left, upper_right))
 }
 
 impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
@@ -1038,6 +1211,8 @@ impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
             NotEndsWith(attr, value) => {
                 self.starts_or_ends_with(attr, value, " not like ", false, out)?
             }
+
+            Within(attr, value, radius) => self.within(attr, value, *radius, out)?,
         }
         Ok(())
     }
@@ -1493,25 +1668,46 @@ impl<'a> TableLink<'a> {
 /// sort key and limiting
 #[derive(Copy, Clone)]
 enum ParentLimit<'a> {
-    /// Limit children to a specific parent
-    Outer,
+    /// Limit children to a specific parent; the outer query picks the top
+    /// n from all windows, so each window only needs to produce the first
+    /// `first + skip` children of the parent in sort order
+    Outer(&'a SortKey<'a>, Option<u32>),
     /// Limit children by sorting and picking top n
     Ranked(&'a SortKey<'a>, &'a FilterRange),
 }
 
 impl<'a> ParentLimit<'a> {
+    /// Generate `distinct on (..)` if each parent should only get the first
+    /// child for each value of the sort key
+    fn distinct(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        match self {
+            ParentLimit::Outer(sort_key, _) | ParentLimit::Ranked(sort_key, _) => {
+                sort_key.distinct(out)
+            }
+        }
+    }
+
     fn filter(&self, out: &mut AstPass<Pg>) {
         match self {
-            ParentLimit::Outer => out.push_sql(" and q.id = p.id"),
+            ParentLimit::Outer(_, _) => out.push_sql(" and q.id = p.id"),
             ParentLimit::Ranked(_, _) => (),
         }
     }
 
     fn restrict(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
-        if let ParentLimit::Ranked(sort_key, range) = self {
-            out.push_sql(" ");
-            sort_key.order_by(out)?;
-            range.walk_ast(out.reborrow())?;
+        match self {
+            ParentLimit::Ranked(sort_key, range) => {
+                out.push_sql(" ");
+                sort_key.order_by(out)?;
+                range.walk_ast(out.reborrow())?;
+            }
+            ParentLimit::Outer(sort_key, Some(limit)) => {
+                out.push_sql(" ");
+                sort_key.order_by(out)?;
+                out.push_sql("\n limit ");
+                out.push_sql(&limit.to_string());
+            }
+            ParentLimit::Outer(_, None) => (),
         }
         Ok(())
     }
@@ -1524,7 +1720,7 @@ impl<'a> ParentLimit<'a> {
                 out.push_sql(" limit ");
                 out.push_sql(&(num_parents + 1).to_string());
             }
-            ParentLimit::Outer => {
+            ParentLimit::Outer(_, _) => {
                 // limiting is taken care of in a wrapper around
                 // the query we are currently building
             }
@@ -1594,7 +1790,7 @@ impl<'a> FilterWindow<'a> {
         // Generate
         //      from unnest({parent_ids}) as p(id)
         //           cross join lateral
-        //           (select *
+        //           (select [distinct on (c.{sort_key})] *
         //              from children c
         //             where p.id = any(c.{parent_field})
         //               and .. other conditions on c ..
@@ -1604,7 +1800,9 @@ impl<'a> FilterWindow<'a> {
 
         out.push_sql("\n/* children_type_a */  from unnest(");
         column.bind_ids(&self.ids, out)?;
-        out.push_sql(") as p(id) cross join lateral (select * from ");
+        out.push_sql(") as p(id) cross join lateral (select ");
+        limit.distinct(out)?;
+        out.push_sql("* from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
         BlockRangeContainsClause::new("c.", block).walk_ast(out.reborrow())?;
@@ -1661,7 +1859,7 @@ impl<'a> FilterWindow<'a> {
         // Generate
         //      from unnest({parent_ids}) as p(id)
         //           cross join lateral
-        //           (select *
+        //           (select [distinct on (c.{sort_key})] *
         //              from children c
         //             where p.id = c.{parent_field}
         //               and .. other conditions on c ..
@@ -1671,7 +1869,9 @@ impl<'a> FilterWindow<'a> {
 
         out.push_sql("\n/* children_type_b */  from unnest(");
         column.bind_ids(&self.ids, out)?;
-        out.push_sql(") as p(id) cross join lateral (select * from ");
+        out.push_sql(") as p(id) cross join lateral (select ");
+        limit.distinct(out)?;
+        out.push_sql("* from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
         BlockRangeContainsClause::new("c.", block).walk_ast(out.reborrow())?;
@@ -1724,7 +1924,7 @@ impl<'a> FilterWindow<'a> {
         //      from rows from (unnest({parent_ids}), reduce_dim({child_id_matrix}))
         //                  as p(id, child_ids)
         //           cross join lateral
-        //           (select *
+        //           (select [distinct on (c.{sort_key})] *
         //              from children c
         //             where c.id = any(p.child_ids)
         //               and .. other conditions on c ..
@@ -1738,7 +1938,9 @@ impl<'a> FilterWindow<'a> {
         out.push_sql("), reduce_dim(");
         self.table.primary_key().push_matrix(&child_ids, out)?;
         out.push_sql(")) as p(id, child_ids)");
-        out.push_sql(" cross join lateral (select * from ");
+        out.push_sql(" cross join lateral (select ");
+        limit.distinct(out)?;
+        out.push_sql("* from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
         BlockRangeContainsClause::new("c.", block).walk_ast(out.reborrow())?;
@@ -1815,6 +2017,7 @@ impl<'a> FilterWindow<'a> {
     fn children_uniform(
         &self,
         sort_key: &SortKey,
+        range: &FilterRange,
         block: BlockNumber,
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
@@ -1822,7 +2025,11 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(self.table.object.as_str());
         out.push_sql("' as entity, c.id, c.vid, p.id::text as g$parent_id");
         sort_key.select(&mut out)?;
-        self.children(ParentLimit::Outer, block, out)
+        self.children(
+            ParentLimit::Outer(sort_key, range.union_limit()),
+            block,
+            out,
+        )
     }
 
     /// Collect all the parent id's from all windows
@@ -1914,15 +2121,19 @@ impl<'a> FilterCollection<'a> {
 }
 
 /// Convenience to pass the name of the column to order by around. If `name`
-/// is `None`, the sort key should be ignored
+/// is `None`, the sort key should be ignored. If `distinct` is set, only the
+/// first row for each value of the sort key is returned
 #[derive(Debug, Clone, Copy)]
 pub enum SortKey<'a> {
     None,
-    Id,
+    Id {
+        distinct: bool,
+    },
     Key {
         column: &'a Column,
         value: Option<&'a str>,
         direction: &'static str,
+        distinct: bool,
     },
 }
 
@@ -1931,6 +2142,7 @@ impl<'a> SortKey<'a> {
         order: EntityOrder,
         table: &'a Table,
         filter: Option<&'a EntityFilter>,
+        distinct: bool,
     ) -> Result<Self, QueryExecutionError> {
         const ASC: &str = "asc";
         const DESC: &str = "desc";
@@ -1940,8 +2152,14 @@ impl<'a> SortKey<'a> {
             attribute: String,
             filter: Option<&'a EntityFilter>,
             direction: &'static str,
+            distinct: bool,
         ) -> Result<SortKey<'a>, QueryExecutionError> {
             let column = table.column_for_field(&attribute)?;
+            if column.is_fulltext() && distinct {
+                return Err(QueryExecutionError::NotSupported(
+                    "`distinctOn` can not be used with fulltext search".to_owned(),
+                ));
+            }
             if column.is_fulltext() {
                 match filter {
                     Some(entity_filter) => match entity_filter {
@@ -1952,6 +2170,7 @@ impl<'a> SortKey<'a> {
                                 column,
                                 value: sort_value,
                                 direction,
+                                distinct,
                             })
                         }
                         _ => unreachable!(),
@@ -1963,27 +2182,48 @@ impl<'a> SortKey<'a> {
                     column,
                     value: None,
                     direction,
+                    distinct,
                 })
             }
         }
 
         match order {
-            EntityOrder::Ascending(attr, _) => with_key(table, attr, filter, ASC),
-            EntityOrder::Descending(attr, _) => with_key(table, attr, filter, DESC),
-            EntityOrder::Default => Ok(SortKey::Id),
+            EntityOrder::Ascending(attr, _) => with_key(table, attr, filter, ASC, distinct),
+            EntityOrder::Descending(attr, _) => with_key(table, attr, filter, DESC, distinct),
+            EntityOrder::Default => Ok(SortKey::Id { distinct }),
+            EntityOrder::Unordered if distinct => Err(QueryExecutionError::NotSupported(
+                "distinct entities must be ordered".to_owned(),
+            )),
             EntityOrder::Unordered => Ok(SortKey::None),
         }
     }
 
+    /// Generate
+    ///   distinct on (c.{name})
+    /// if only the first row for each value of the sort key is wanted. The
+    /// `order by` of the query must start with the sort key, which
+    /// `order_by` guarantees
+    fn distinct(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        let name = match self {
+            SortKey::Id { distinct: true } => PRIMARY_KEY_COLUMN,
+            SortKey::Key {
+                column,
+                distinct: true,
+                ..
+            } => column.name.as_str(),
+            _ => return Ok(()),
+        };
+        out.push_sql("distinct on (c.");
+        out.push_identifier(name)?;
+        out.push_sql(") ");
+        Ok(())
+    }
+
     /// Generate selecting the sort key if it is needed
     fn select(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         match self {
-            SortKey::None | SortKey::Id => Ok(()),
-            SortKey::Key {
-                column,
-                value: _,
-                direction: _,
-            } => {
+            SortKey::None | SortKey::Id { .. } => Ok(()),
+            SortKey::Key { column, .. } => {
                 let name = column.name.as_str();
                 if !column.is_primary_key() {
                     out.push_sql(", c.");
@@ -1999,7 +2239,7 @@ impl<'a> SortKey<'a> {
     fn order_by(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         match self {
             SortKey::None => Ok(()),
-            SortKey::Id => {
+            SortKey::Id { .. } => {
                 out.push_sql("order by ");
                 out.push_identifier(PRIMARY_KEY_COLUMN)
             }
@@ -2007,6 +2247,7 @@ impl<'a> SortKey<'a> {
                 column,
                 value,
                 direction,
+                ..
             } => {
                 out.push_sql("order by ");
                 SortKey::sort_expr(column, value, direction, out)
@@ -2019,7 +2260,7 @@ impl<'a> SortKey<'a> {
     fn order_by_parent(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         match self {
             SortKey::None => Ok(()),
-            SortKey::Id => {
+            SortKey::Id { .. } => {
                 out.push_sql("order by g$parent_id, ");
                 out.push_identifier(PRIMARY_KEY_COLUMN)
             }
@@ -2027,6 +2268,7 @@ impl<'a> SortKey<'a> {
                 column,
                 value,
                 direction,
+                ..
             } => {
                 out.push_sql("order by g$parent_id, ");
                 SortKey::sort_expr(column, value, direction, out)
@@ -2083,6 +2325,14 @@ impl<'a> SortKey<'a> {
 #[derive(Debug, Clone)]
 pub struct FilterRange(EntityRange);
 
+impl FilterRange {
+    /// How many rows each part of a `union all` needs to produce so that
+    /// applying this range to the whole union is still correct
+    fn union_limit(&self) -> Option<u32> {
+        self.0.first.map(|first| first.saturating_add(self.0.skip))
+    }
+}
+
 impl QueryFragment<Pg> for FilterRange {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
         let range = &self.0;
@@ -2108,6 +2358,7 @@ pub struct FilterQuery<'a> {
     sort_key: SortKey<'a>,
     range: FilterRange,
     block: BlockNumber,
+    query_id: Option<String>,
 }
 
 impl<'a> FilterQuery<'a> {
@@ -2115,6 +2366,7 @@ impl<'a> FilterQuery<'a> {
         collection: &'a FilterCollection,
         filter: Option<&'a EntityFilter>,
         order: EntityOrder,
+        distinct: bool,
         range: EntityRange,
         block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
@@ -2125,16 +2377,23 @@ impl<'a> FilterQuery<'a> {
         let first_table = collection
             .first_table()
             .expect("an entity query always contains at least one entity type/table");
-        let sort_key = SortKey::new(order, first_table, filter)?;
+        let sort_key = SortKey::new(order, first_table, filter, distinct)?;
 
         Ok(FilterQuery {
             collection,
             sort_key,
             range: FilterRange(range),
             block,
+            query_id: None,
         })
     }
 
+    /// Mark the generated SQL with the id of the GraphQL query it is for,
+    /// so that it can be found in `pg_stat_activity` and the database logs
+    pub fn with_query_id(self, query_id: Option<String>) -> Self {
+        FilterQuery { query_id, ..self }
+    }
+
     /// Generate
     ///     from schema.table c
     ///    where block_range @> $block
@@ -2173,7 +2432,7 @@ impl<'a> FilterQuery<'a> {
     ///
     ///   select '..' as entity, to_jsonb(e.*) as data
     ///     from
-    ///       (select *
+    ///       (select [distinct on (c.{sort_key})] *
     ///          from table c
     ///         where block_range @> $block
     ///           and filter
@@ -2185,7 +2444,9 @@ impl<'a> FilterQuery<'a> {
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
         Self::select_entity_and_data(table, &mut out);
-        out.push_sql(" from (select * ");
+        out.push_sql(" from (select ");
+        self.sort_key.distinct(&mut out)?;
+        out.push_sql("* ");
         self.filtered_rows(table, filter, out.reborrow())?;
         out.push_sql("\n ");
         self.sort_key.order_by(&mut out)?;
@@ -2232,12 +2493,19 @@ impl<'a> FilterQuery<'a> {
         // step, we get matching rows from the underlying tables and convert
         // them to JSONB.
         //
+        // Since we only need the first `n + m` entities from each table,
+        // every branch of the `union all` is ordered and limited by itself,
+        // which lets Postgres use an index on {sort_key} for each table
+        // instead of sorting all matching rows from all tables.
+        //
         // Overall, we generate a query
         //
         // with matches as (
-        //   select '...' as entity, id, vid, {sort_key}
-        //     from {table} c
-        //    where {query_filter}
+        //   (select '...' as entity, id, vid, {sort_key}
+        //      from {table} c
+        //     where {query_filter}
+        //     order by {sort_key}
+        //     limit n + m)
         //    union all
         //    ...
         //    order by {sort_key}
@@ -2255,15 +2523,26 @@ impl<'a> FilterQuery<'a> {
             if i > 0 {
                 out.push_sql("\nunion all\n");
             }
-            // select '..' as entity,
-            //        c.id,
-            //        c.vid,
-            //        c.${sort_key}
-            out.push_sql("select '");
+            // (select '..' as entity,
+            //         c.id,
+            //         c.vid,
+            //         c.${sort_key}
+            //    ...
+            //   order by {sort_key} limit {n + m})
+            out.push_sql("(select ");
+            self.sort_key.distinct(&mut out)?;
+            out.push_sql("'");
             out.push_sql(&table.object);
             out.push_sql("' as entity, c.id, c.vid");
             self.sort_key.select(&mut out)?;
             self.filtered_rows(table, filter, out.reborrow())?;
+            if let Some(limit) = self.range.union_limit() {
+                out.push_sql("\n ");
+                self.sort_key.order_by(&mut out)?;
+                out.push_sql("\n limit ");
+                out.push_sql(&limit.to_string());
+            }
+            out.push_sql(")");
         }
         out.push_sql("\n ");
         self.sort_key.order_by(&mut out)?;
@@ -2311,6 +2590,8 @@ impl<'a> FilterQuery<'a> {
         //       from (select id from unnest({all_parent_ids}) as q(id)) q
         //            cross join lateral
         //            ({window.children_uniform("q")}
+        //             -- each window is sorted and limited to
+        //             -- {first + skip} children per parent
         //             union all
         //             ... range over all windows ...
         //             order by c.{sort_key}
@@ -2333,7 +2614,7 @@ impl<'a> FilterQuery<'a> {
             if i > 0 {
                 out.push_sql("\nunion all\n");
             }
-            window.children_uniform(&self.sort_key, self.block, out.reborrow())?;
+            window.children_uniform(&self.sort_key, &self.range, self.block, out.reborrow())?;
         }
         out.push_sql("\n");
         self.sort_key.order_by(&mut out)?;
@@ -2378,6 +2659,17 @@ impl<'a> QueryFragment<Pg> for FilterQuery<'a> {
             return Ok(());
         }
 
+        if let Some(query_id) = &self.query_id {
+            // Only keep characters that can not end the comment
+            let query_id: String = query_id
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+                .collect();
+            out.push_sql("/* query_id: ");
+            out.push_sql(&query_id);
+            out.push_sql(" */\n");
+        }
+
         // We generate four different kinds of queries, depending on whether
         // we need to window and whether we query just one or multiple entity
         // types/windows; the most complex situation is windowing with multiple
@@ -2419,6 +2711,614 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FilterQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FilterQuery<'a> {}
 
+impl<'a> FilterQuery<'a> {
+    /// A description of everything that the SQL for this query depends on
+    /// apart from the values of bind variables, so that two queries with
+    /// the same shape generate the same SQL. The id of the GraphQL query is
+    /// not part of the shape. Queries that use windows have no shape since
+    /// their SQL depends on the parent ids in ways that do not pay to
+    /// describe
+    pub fn shape(&self) -> Option<String> {
+        let entities = match self.collection {
+            FilterCollection::All(entities) => entities,
+            FilterCollection::SingleWindow(_) | FilterCollection::MultiWindow(_, _) => return None,
+        };
+
+        let mut shape = String::new();
+        for (table, filter) in entities {
+            shape.push_str(table.qualified_name.as_str());
+            shape.push('(');
+            if let Some(filter) = filter {
+                filter_shape(filter.filter, &mut shape);
+            }
+            shape.push(')');
+        }
+        match &self.sort_key {
+            SortKey::None => shape.push_str("|none"),
+            SortKey::Id { distinct } => {
+                shape.push_str("|id");
+                if *distinct {
+                    shape.push_str(" distinct");
+                }
+            }
+            SortKey::Key {
+                column,
+                value,
+                direction,
+                distinct,
+            } => {
+                shape.push('|');
+                shape.push_str(column.name.as_str());
+                shape.push(' ');
+                shape.push_str(direction);
+                if value.is_some() {
+                    shape.push_str(" ranked");
+                }
+                if *distinct {
+                    shape.push_str(" distinct");
+                }
+            }
+        }
+        shape.push_str(&format!("|{:?}|{}", self.range.0.first, self.range.0.skip));
+        Some(shape)
+    }
+
+    /// The SQL for this query without the comment with the id of the
+    /// GraphQL query
+    pub fn sql(&self) -> QueryResult<String> {
+        let mut builder = PgQueryBuilder::default();
+        self.clone().with_query_id(None).to_sql(&mut builder)?;
+        Ok(builder.finish())
+    }
+}
+
+/// Add the shape of `filter` to `shape`; see `FilterQuery::shape`
+fn filter_shape(filter: &EntityFilter, shape: &mut String) {
+    use EntityFilter::*;
+
+    let (op, attr) = match filter {
+        And(filters) | Or(filters) => {
+            shape.push_str(if let And(_) = filter { "and[" } else { "or[" });
+            for filter in filters {
+                filter_shape(filter, shape);
+                shape.push(',');
+            }
+            shape.push(']');
+            return;
+        }
+        Equal(attr, _) => ("=", attr),
+        Not(attr, _) => ("!=", attr),
+        GreaterThan(attr, _) => (">", attr),
+        LessThan(attr, _) => ("<", attr),
+        GreaterOrEqual(attr, _) => (">=", attr),
+        LessOrEqual(attr, _) => ("<=", attr),
+        In(attr, _) => ("in", attr),
+        NotIn(attr, _) => ("!in", attr),
+        Contains(attr, _) => ("contains", attr),
+        NotContains(attr, _) => ("!contains", attr),
+        StartsWith(attr, _) => ("starts", attr),
+        NotStartsWith(attr, _) => ("!starts", attr),
+        EndsWith(attr, _) => ("ends", attr),
+        NotEndsWith(attr, _) => ("!ends", attr),
+        Within(attr, _, _) => ("within", attr),
+    };
+    shape.push_str(attr);
+    shape.push(' ');
+    shape.push_str(op);
+    shape.push(' ');
+    match filter {
+        In(_, values) | NotIn(_, values) => {
+            shape.push('[');
+            for value in values {
+                value_shape(value, shape);
+            }
+            shape.push(']');
+        }
+        Equal(_, value)
+        | Not(_, value)
+        | GreaterThan(_, value)
+        | LessThan(_, value)
+        | GreaterOrEqual(_, value)
+        | LessOrEqual(_, value)
+        | Contains(_, value)
+        | NotContains(_, value)
+        | StartsWith(_, value)
+        | NotStartsWith(_, value)
+        | EndsWith(_, value)
+        | NotEndsWith(_, value) => value_shape(value, shape),
+        Within(_, value, radius) => {
+            value_shape(value, shape);
+            // Whether the query restricts the points to a box depends on
+            // the center and radius
+            let boxed = value
+                .as_str()
+                .and_then(|s| scalar::GeoPoint::from_str(s).ok())
+                .and_then(|center| bounding_box(&center, *radius))
+                .is_some();
+            if boxed {
+                shape.push_str(" box");
+            }
+        }
+        And(_) | Or(_) => unreachable!("handled above"),
+    }
+}
+
+/// Add the kind of `value` to `shape`. Strings that are too long for the
+/// prefix index on text columns lead to different comparisons than short
+/// ones, and are therefore a different kind
+fn value_shape(value: &Value, shape: &mut String) {
+    match value {
+        Value::String(s) if s.len() > STRING_PREFIX_SIZE - 1 => shape.push('S'),
+        Value::String(_) => shape.push('s'),
+        Value::Int(_) => shape.push('i'),
+        Value::BigDecimal(_) => shape.push('d'),
+        Value::Bool(_) => shape.push('b'),
+        Value::Null => shape.push('n'),
+        Value::Bytes(_) => shape.push('x'),
+        Value::BigInt(_) => shape.push('I'),
+        Value::List(values) => {
+            shape.push('[');
+            for value in values {
+                value_shape(value, shape);
+            }
+            shape.push(']');
+        }
+    }
+}
+
+/// A `FilterQuery` that is run with SQL that was generated for an earlier
+/// query of the same shape. Only the bind variables come from the query
+/// itself. Since the SQL is the same every time, the connection prepares
+/// the statement once and reuses it
+#[derive(Debug, Clone)]
+pub struct CachedFilterQuery<'a> {
+    sql: Arc<String>,
+    query: &'a FilterQuery<'a>,
+}
+
+impl<'a> CachedFilterQuery<'a> {
+    /// Run `query` with `sql`, which must be what `query.sql()` returns for
+    /// a query with the same shape
+    pub fn new(sql: Arc<String>, query: &'a FilterQuery<'a>) -> Self {
+        CachedFilterQuery { sql, query }
+    }
+}
+
+impl<'a> QueryFragment<Pg> for CachedFilterQuery<'a> {
+    fn walk_ast(&self, out: AstPass<Pg>) -> QueryResult<()> {
+        self.query.walk_ast(out)
+    }
+
+    fn to_sql(&self, out: &mut <Pg as Backend>::QueryBuilder) -> QueryResult<()> {
+        out.push_sql(&self.sql);
+        Ok(())
+    }
+
+    fn collect_binds(
+        &self,
+        out: &mut <Pg as Backend>::BindCollector,
+        metadata_lookup: &<Pg as TypeMetadata>::MetadataLookup,
+    ) -> QueryResult<()> {
+        self.query.collect_binds(out, metadata_lookup)
+    }
+
+    fn is_safe_to_cache_prepared(&self) -> QueryResult<bool> {
+        Ok(true)
+    }
+}
+
+impl<'a> QueryId for CachedFilterQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, EntityData> for CachedFilterQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<EntityData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for CachedFilterQuery<'a> {}
+
+/// Run a query with `explain`. With `analyze`, the query is run with
+/// `explain (analyze, buffers)` to find out why it is slow; otherwise, it
+/// is only planned and not run
+#[derive(Debug, Clone)]
+pub struct ExplainQuery<'a, Q> {
+    query: &'a Q,
+    analyze: bool,
+}
+
+impl<'a, Q> ExplainQuery<'a, Q> {
+    pub fn new(query: &'a Q, analyze: bool) -> Self {
+        ExplainQuery { query, analyze }
+    }
+}
+
+/// One line of the output of `explain`
+pub struct QueryPlanLine {
+    pub line: String,
+}
+
+impl QueryableByName<Pg> for QueryPlanLine {
+    fn build<R: diesel::row::NamedRow<Pg>>(row: &R) -> diesel::deserialize::Result<Self> {
+        Ok(QueryPlanLine {
+            line: row.get::<Text, _>("QUERY PLAN")?,
+        })
+    }
+}
+
+impl<'a, Q: QueryFragment<Pg>> QueryFragment<Pg> for ExplainQuery<'a, Q> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+        if self.analyze {
+            out.push_sql("explain (analyze, buffers) ");
+        } else {
+            out.push_sql("explain ");
+        }
+        self.query.walk_ast(out)
+    }
+}
+
+impl<'a, Q> QueryId for ExplainQuery<'a, Q> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Q: QueryFragment<Pg>> LoadQuery<PgConnection, QueryPlanLine> for ExplainQuery<'a, Q> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<QueryPlanLine>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Q, Conn> RunQueryDsl<Conn> for ExplainQuery<'a, Q> {}
+
+/// An aggregate of an `EntityGroupQuery` together with the column it is
+/// computed over and the type of its result
+#[derive(Debug, Clone)]
+struct GroupAggregate<'a> {
+    aggregate: &'a EntityAggregate,
+    column: Option<&'a Column>,
+    result_type: ColumnType,
+}
+
+impl<'a> GroupAggregate<'a> {
+    fn new(aggregate: &'a EntityAggregate, table: &'a Table) -> Result<Self, StoreError> {
+        use EntityAggregate::*;
+
+        let column = match aggregate {
+            Count => None,
+            CountDistinct(attr) | Sum(attr) | Min(attr) | Max(attr) | Avg(attr) => {
+                let column = table.column_for_field(attr)?;
+                if column.is_list() || column.is_fulltext() {
+                    return Err(StoreError::QueryExecutionError(format!(
+                        "can not aggregate the attribute `{}` of `{}`",
+                        attr, table.object
+                    )));
+                }
+                Some(column)
+            }
+        };
+        let numeric = column.map_or(false, |column| match column.column_type {
+            ColumnType::Int | ColumnType::BigInt | ColumnType::BigDecimal => true,
+            _ => false,
+        });
+        let result_type = match (aggregate, column) {
+            (Count, _) | (CountDistinct(_), _) => ColumnType::Int,
+            (Sum(_), Some(column)) if numeric => match column.column_type {
+                ColumnType::Int => ColumnType::BigInt,
+                _ => column.column_type.clone(),
+            },
+            (Avg(_), Some(_)) if numeric => ColumnType::BigDecimal,
+            (Min(_), Some(column)) | (Max(_), Some(column)) => column.column_type.clone(),
+            (Sum(attr), _) | (Avg(attr), _) => {
+                return Err(StoreError::QueryExecutionError(format!(
+                    "can not compute `{}` since `{}` of `{}` is not numeric",
+                    aggregate.name(),
+                    attr,
+                    table.object
+                )))
+            }
+            (Min(_), None) | (Max(_), None) => unreachable!("min and max have a column"),
+        };
+        Ok(GroupAggregate {
+            aggregate,
+            column,
+            result_type,
+        })
+    }
+
+    /// Generate the expression that computes the aggregate, e.g.,
+    /// `sum(c."amount")`
+    fn expr(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        use EntityAggregate::*;
+
+        let (func, distinct) = match self.aggregate {
+            Count => {
+                out.push_sql("count(*)");
+                return Ok(());
+            }
+            CountDistinct(_) => ("count(", true),
+            Sum(_) => ("sum(", false),
+            Min(_) => ("min(", false),
+            Max(_) => ("max(", false),
+            Avg(_) => ("avg(", false),
+        };
+        let column = self.column.expect("only `count` has no column");
+        out.push_sql(func);
+        if distinct {
+            out.push_sql("distinct ");
+        }
+        out.push_sql("c.");
+        out.push_identifier(column.name.as_str())?;
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+/// The parallel to `EntityGroupQuery`. Generate
+///
+///   select to_jsonb(g.*) as data
+///     from (select c.group_col as "field", .., count(*) as "count", ..
+///             from schema.table c
+///            where block_range @> $block
+///              and query_filter
+///            group by c.group_col, ..
+///           having aggregate_filter
+///            order by sort_key, "field", ..
+///            limit $first offset $skip) g
+///
+/// The groups come back as JSONB objects keyed by the GraphQL names of
+/// the grouping attributes and the names of the aggregates
+#[derive(Debug, Clone)]
+pub struct GroupQuery<'a> {
+    table: &'a Table,
+    filter: Option<QueryFilter<'a>>,
+    group_by: Vec<&'a Column>,
+    aggregates: Vec<GroupAggregate<'a>>,
+    having: Option<&'a AggregateFilter>,
+    order: &'a GroupOrder,
+    range: FilterRange,
+    block: BlockNumber,
+}
+
+impl<'a> GroupQuery<'a> {
+    pub fn new(table: &'a Table, query: &'a EntityGroupQuery) -> Result<Self, StoreError> {
+        let filter = query
+            .filter
+            .as_ref()
+            .map(|filter| QueryFilter::new(filter, table))
+            .transpose()?;
+        let group_by = query
+            .group_by
+            .iter()
+            .map(|attr| {
+                let column = table.column_for_field(attr)?;
+                if column.is_fulltext() {
+                    return Err(StoreError::QueryExecutionError(format!(
+                        "can not group by the fulltext attribute `{}`",
+                        attr
+                    )));
+                }
+                Ok(column)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Everything that the groups are filtered or sorted by has to be
+        // selected so that `having` and `order by` can refer to it
+        let mut aggregates = query
+            .aggregates
+            .iter()
+            .map(|aggregate| GroupAggregate::new(aggregate, table))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut needed = vec![];
+        if let Some(having) = &query.having {
+            Self::having_aggregates(having, &mut needed);
+        }
+        match &query.order {
+            GroupOrder::Ascending(GroupSortKey::Aggregate(aggregate))
+            | GroupOrder::Descending(GroupSortKey::Aggregate(aggregate)) => needed.push(aggregate),
+            GroupOrder::Ascending(GroupSortKey::Attribute(attr))
+            | GroupOrder::Descending(GroupSortKey::Attribute(attr)) => {
+                if !group_by.iter().any(|column| &column.field == attr) {
+                    return Err(StoreError::QueryExecutionError(format!(
+                        "can only sort groups by `{}` when grouping by it",
+                        attr
+                    )));
+                }
+            }
+            GroupOrder::Default => {}
+        }
+        for aggregate in needed {
+            if !aggregates.iter().any(|agg| agg.aggregate == aggregate) {
+                aggregates.push(GroupAggregate::new(aggregate, table)?);
+            }
+        }
+
+        Ok(GroupQuery {
+            table,
+            filter,
+            group_by,
+            aggregates,
+            having: query.having.as_ref(),
+            order: &query.order,
+            range: FilterRange(query.range.clone()),
+            block: query.block,
+        })
+    }
+
+    fn having_aggregates(having: &'a AggregateFilter, aggregates: &mut Vec<&'a EntityAggregate>) {
+        use AggregateFilter::*;
+
+        match having {
+            And(filters) => {
+                for filter in filters {
+                    Self::having_aggregates(filter, aggregates);
+                }
+            }
+            Equal(aggregate, _)
+            | GreaterThan(aggregate, _)
+            | LessThan(aggregate, _)
+            | GreaterOrEqual(aggregate, _)
+            | LessOrEqual(aggregate, _) => aggregates.push(aggregate),
+        }
+    }
+
+    /// The type of each of the values in the JSONB objects that the query
+    /// returns, by their key
+    pub fn result_types(&self) -> BTreeMap<String, ColumnType> {
+        self.group_by
+            .iter()
+            .map(|column| (column.field.clone(), column.column_type.clone()))
+            .chain(
+                self.aggregates
+                    .iter()
+                    .map(|agg| (agg.aggregate.name(), agg.result_type.clone())),
+            )
+            .collect()
+    }
+
+    fn aggregate(&self, aggregate: &EntityAggregate) -> &GroupAggregate<'a> {
+        self.aggregates
+            .iter()
+            .find(|agg| agg.aggregate == aggregate)
+            .expect("the constructor selects all aggregates that the groups are filtered by")
+    }
+
+    fn having(&self, having: &AggregateFilter, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        use AggregateFilter::*;
+
+        let (aggregate, op, value) = match having {
+            And(filters) => {
+                if filters.is_empty() {
+                    out.push_sql("true");
+                }
+                for (i, filter) in filters.iter().enumerate() {
+                    if i > 0 {
+                        out.push_sql(" and ");
+                    }
+                    out.push_sql("(");
+                    self.having(filter, out)?;
+                    out.push_sql(")");
+                }
+                return Ok(());
+            }
+            Equal(aggregate, value) => (aggregate, " = ", value),
+            GreaterThan(aggregate, value) => (aggregate, " > ", value),
+            LessThan(aggregate, value) => (aggregate, " < ", value),
+            GreaterOrEqual(aggregate, value) => (aggregate, " >= ", value),
+            LessOrEqual(aggregate, value) => (aggregate, " <= ", value),
+        };
+        let aggregate = self.aggregate(aggregate);
+        aggregate.expr(out)?;
+        out.push_sql(op);
+        QueryValue(value, &aggregate.result_type).walk_ast(out.reborrow())
+    }
+
+    /// Generate `order by sort_key, "field", ..` using the names of the
+    /// selected values
+    fn order_by(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        let mut keys = vec![];
+        let sort_key = match self.order {
+            GroupOrder::Ascending(key) => Some((key, " asc")),
+            GroupOrder::Descending(key) => Some((key, " desc")),
+            GroupOrder::Default => None,
+        };
+        if let Some((key, direction)) = sort_key {
+            let name = match key {
+                GroupSortKey::Attribute(attr) => attr.clone(),
+                GroupSortKey::Aggregate(aggregate) => aggregate.name(),
+            };
+            keys.push((name, direction));
+        }
+        for column in &self.group_by {
+            if !keys.iter().any(|(name, _)| name == &column.field) {
+                keys.push((column.field.clone(), " asc"));
+            }
+        }
+        for (i, (name, direction)) in keys.iter().enumerate() {
+            out.push_sql(if i == 0 { "\n order by " } else { ", " });
+            out.push_identifier(name)?;
+            out.push_sql(direction);
+            out.push_sql(" nulls last");
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryFragment<Pg> for GroupQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        out.push_sql("select to_jsonb(g.*) as data\n  from (select ");
+        for (i, column) in self.group_by.iter().enumerate() {
+            if i > 0 {
+                out.push_sql(", ");
+            }
+            out.push_sql("c.");
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(" as ");
+            out.push_identifier(&column.field)?;
+        }
+        for (i, aggregate) in self.aggregates.iter().enumerate() {
+            if i > 0 || !self.group_by.is_empty() {
+                out.push_sql(", ");
+            }
+            aggregate.expr(&mut out)?;
+            out.push_sql(" as ");
+            out.push_identifier(&aggregate.aggregate.name())?;
+        }
+        if self.group_by.is_empty() && self.aggregates.is_empty() {
+            out.push_sql("count(*) as \"count\"");
+        }
+
+        out.push_sql("\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" c\n where ");
+        BlockRangeContainsClause::new("c.", self.block).walk_ast(out.reborrow())?;
+        if let Some(filter) = &self.filter {
+            out.push_sql(" and ");
+            filter.walk_ast(out.reborrow())?;
+        }
+        for (i, column) in self.group_by.iter().enumerate() {
+            out.push_sql(if i == 0 { "\n group by " } else { ", " });
+            out.push_sql("c.");
+            out.push_identifier(column.name.as_str())?;
+        }
+        if let Some(having) = self.having {
+            out.push_sql("\nhaving ");
+            self.having(having, &mut out)?;
+        }
+        self.order_by(&mut out)?;
+        self.range.walk_ast(out.reborrow())?;
+        out.push_sql(") g");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for GroupQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+/// One group returned by a `GroupQuery`
+#[derive(QueryableByName)]
+pub struct GroupData {
+    #[sql_type = "Jsonb"]
+    pub data: serde_json::Value,
+}
+
+impl<'a> LoadQuery<PgConnection, GroupData> for GroupQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<GroupData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for GroupQuery<'a> {}
+
 /// Reduce the upper bound of the current entry's block range to `block` as
 /// long as that does not result in an empty block range
 #[derive(Debug, Clone, Constructor)]
@@ -2594,6 +3494,108 @@ impl<'a> LoadQuery<PgConnection, RevertEntityData> for RevertClampQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for RevertClampQuery<'a> {}
 
+/// A query that finds the versions of entities that were written in
+/// `block`, i.e., the entities that were created or updated in it
+#[derive(Debug, Clone, Constructor)]
+pub struct ChangedEntitiesQuery<'a> {
+    table: &'a Table,
+    block: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for ChangedEntitiesQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select '..' as entity, to_jsonb(e.*) as data
+        //      from schema.table e where lower(e.block_range) = $block
+        //     order by e.id
+        out.push_sql("select ");
+        out.push_bind_param::<Text, _>(&self.table.object)?;
+        out.push_sql(" as entity, to_jsonb(e.*) as data\n");
+        out.push_sql("  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" e\n where lower(e.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") = ");
+        out.push_bind_param::<Integer, _>(&self.block)?;
+        out.push_sql("\n order by e.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)
+    }
+}
+
+impl<'a> QueryId for ChangedEntitiesQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, EntityData> for ChangedEntitiesQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<EntityData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ChangedEntitiesQuery<'a> {}
+
+/// A query that finds the ids of entities that were removed in `block`,
+/// i.e., whose last version ends at `block` without a new version that
+/// starts there
+#[derive(Debug, Clone, Constructor)]
+pub struct RemovedEntitiesQuery<'a> {
+    table: &'a Table,
+    block: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for RemovedEntitiesQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   select e.id::text from table e
+        //    where upper(e.block_range) = $block
+        //      and not exists (select 1 from table n
+        //                       where n.id = e.id
+        //                         and lower(n.block_range) = $block)
+        //    order by e.id
+        out.push_sql("select e.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql("::text as id\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" e\n where upper(e.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") = ");
+        out.push_bind_param::<Integer, _>(&self.block)?;
+        out.push_sql("\n   and not exists (select 1 from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" n where n.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" = e.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" and lower(n.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") = ");
+        out.push_bind_param::<Integer, _>(&self.block)?;
+        out.push_sql(")\n order by e.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)
+    }
+}
+
+impl<'a> QueryId for RemovedEntitiesQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, RevertEntityData> for RemovedEntitiesQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<RevertEntityData>> {
+        conn.query_by_name(&self)
+            .map(|data| RevertEntityData::bytes_as_str(&self.table, data))
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for RemovedEntitiesQuery<'a> {}
+
 #[test]
 fn block_number_max_is_i32_max() {
     // The code in RevertClampQuery::walk_ast embeds i32::MAX
@@ -0,0 +1,105 @@
+//! Queries that find subgraph versions which have been superseded by newer
+//! versions long enough ago that they can be retired
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Array, BigInt, Bool, Nullable, Text};
+use diesel::{sql_query, RunQueryDsl};
+use std::time::Duration;
+
+use graph::prelude::StoreError;
+
+/// The key of the advisory lock that is held by the node that retires
+/// versions, so that only one node at a time does that
+const RETIREMENT_LOCK: i64 = 1394;
+
+/// Which superseded versions to keep. A version that is neither the
+/// current nor the pending version of its subgraph is retired unless it is
+/// one of the `keep_versions` newest versions of its subgraph, or was
+/// created less than `keep_for` ago. A deployment is only unassigned once
+/// all of its versions are retired, and never if it is in `pinned`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RetirementPolicy {
+    pub keep_versions: Option<usize>,
+    pub keep_for: Option<Duration>,
+    /// Delete the version entities of retired versions, and the data of
+    /// deployments that are not used anymore because of that
+    pub remove_data: bool,
+    /// Deployments that are never retired
+    pub pinned: Vec<String>,
+}
+
+impl RetirementPolicy {
+    /// A policy that keeps everything does not need to be enforced
+    pub fn is_active(&self) -> bool {
+        self.keep_versions.is_some() || self.keep_for.is_some()
+    }
+}
+
+/// A subgraph version that the retirement policy does not keep
+#[derive(QueryableByName, Debug)]
+pub(crate) struct RetiredVersion {
+    #[sql_type = "Text"]
+    pub version: String,
+    #[sql_type = "Text"]
+    pub name: String,
+    #[sql_type = "Text"]
+    pub deployment: String,
+    /// Whether no version that is kept uses the deployment
+    #[sql_type = "Bool"]
+    pub deployment_retired: bool,
+    #[sql_type = "Bool"]
+    pub assigned: bool,
+}
+
+#[derive(QueryableByName)]
+struct Locked {
+    #[sql_type = "Bool"]
+    locked: bool,
+}
+
+/// Try to become the node that retires versions. This must be called
+/// inside a transaction; the lock is released when the transaction ends
+pub(crate) fn try_lock(conn: &PgConnection) -> Result<bool, StoreError> {
+    Ok(sql_query("select pg_try_advisory_xact_lock($1) as locked")
+        .bind::<BigInt, _>(RETIREMENT_LOCK)
+        .get_result::<Locked>(conn)?
+        .locked)
+}
+
+/// Return all versions that `policy` does not keep
+pub(crate) fn retired_versions(
+    conn: &PgConnection,
+    policy: &RetirementPolicy,
+) -> Result<Vec<RetiredVersion>, StoreError> {
+    let query = "
+        with versions as (
+          select v.id, v.deployment, v.created_at, s.name,
+                 (v.id = s.current_version or v.id = s.pending_version) as active,
+                 row_number() over (partition by v.subgraph
+                                        order by v.created_at desc, v.id) as rank
+            from subgraphs.subgraph_version v
+            join subgraphs.subgraph s
+              on (s.id = v.subgraph and upper_inf(s.block_range))
+           where upper_inf(v.block_range)
+        ), kept as (
+          select id, deployment
+            from versions
+           where active
+              or deployment = any($3)
+              or coalesce(rank <= $1, false)
+              or coalesce(created_at > extract(epoch from now()) - $2, false)
+        )
+        select r.id as version, r.name, r.deployment,
+               not exists (select 1 from kept k
+                            where k.deployment = r.deployment) as deployment_retired,
+               exists (select 1 from subgraphs.subgraph_deployment_assignment a
+                        where a.id = r.deployment
+                          and upper_inf(a.block_range)) as assigned
+          from versions r
+         where not exists (select 1 from kept k where k.id = r.id)
+         order by r.name, r.created_at";
+    Ok(sql_query(query)
+        .bind::<Nullable<BigInt>, _>(policy.keep_versions.map(|n| n as i64))
+        .bind::<Nullable<BigInt>, _>(policy.keep_for.map(|d| d.as_secs() as i64))
+        .bind::<Array<Text>, _>(&policy.pinned)
+        .load(conn)?)
+}
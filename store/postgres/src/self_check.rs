@@ -0,0 +1,109 @@
+//! Check whether graph-node can use a database, for `graph-node check`.
+//! Unlike the checks when a store is created, these report all problems
+//! they find instead of panicking at the first one.
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Array, Bool, Text};
+use diesel::{sql_query, Connection, RunQueryDsl};
+
+use graph::components::migration::MigrationStatus;
+use graph::prelude::{Error, EthereumNetworkIdentifier};
+
+use crate::migration::migration_status;
+use crate::planner::{self, REQUIRED_EXTENSIONS};
+use crate::store::network_identifiers;
+
+/// The first Postgres version in which users that may create objects in
+/// a database can install trusted extensions like `pg_trgm`
+const TRUSTED_EXTENSIONS_VERSION: i32 = 130000;
+
+#[derive(QueryableByName)]
+struct Privileges {
+    #[sql_type = "Bool"]
+    can_create: bool,
+    #[sql_type = "Bool"]
+    superuser: bool,
+}
+
+#[derive(QueryableByName)]
+struct Extension {
+    #[sql_type = "Text"]
+    name: String,
+}
+
+#[derive(QueryableByName)]
+struct Found {
+    #[sql_type = "Bool"]
+    found: bool,
+}
+
+/// What `check_database` found out about a database
+pub struct DatabaseCheck {
+    /// The version of the Postgres server, e.g., 110005 for 11.5
+    pub server_version: i32,
+    /// Everything that keeps graph-node from using the database
+    pub problems: Vec<String>,
+    pub migrations: MigrationStatus,
+    /// The networks that index nodes have added to the database
+    pub networks: Vec<(String, EthereumNetworkIdentifier)>,
+}
+
+/// Connect to the database of `shard` at `url` and check that the server
+/// is suitable and that the user can do what graph-node needs to do
+pub fn check_database(shard: &str, url: &str) -> Result<DatabaseCheck, Error> {
+    let conn = PgConnection::establish(url)?;
+    let server_version = planner::server_version(&conn)?;
+    let mut problems = planner::server_problems(&conn)?;
+
+    let privileges = sql_query(
+        "select has_database_privilege(current_database(), 'CREATE') as can_create,
+                current_setting('is_superuser') = 'on' as superuser",
+    )
+    .get_result::<Privileges>(&conn)?;
+    if !privileges.can_create {
+        problems.push(
+            "the user can not create schemas in the database, \
+             but graph-node creates one for every deployment"
+                .to_owned(),
+        );
+    }
+
+    let uninstalled = sql_query(
+        "select name::text as name from pg_available_extensions
+          where installed_version is null and name = any($1)",
+    )
+    .bind::<Array<Text>, _>(REQUIRED_EXTENSIONS)
+    .load::<Extension>(&conn)?
+    .into_iter()
+    .map(|extension| extension.name)
+    .collect::<Vec<_>>();
+    if !uninstalled.is_empty()
+        && !privileges.superuser
+        && server_version < TRUSTED_EXTENSIONS_VERSION
+    {
+        problems.push(format!(
+            "the Postgres extensions {} are not installed yet, \
+             and only a superuser can install them",
+            uninstalled.join(", ")
+        ));
+    }
+
+    // The table does not exist before graph-node ran its migrations
+    let has_networks =
+        sql_query("select to_regclass('public.ethereum_networks') is not null as found")
+            .get_result::<Found>(&conn)?
+            .found;
+    let networks = if has_networks {
+        network_identifiers(&conn)?
+    } else {
+        vec![]
+    };
+
+    let migrations = migration_status(&conn, shard)?;
+
+    Ok(DatabaseCheck {
+        server_version,
+        problems,
+        migrations,
+        networks,
+    })
+}
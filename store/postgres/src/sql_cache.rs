@@ -0,0 +1,44 @@
+//! The SQL that the store generated for recent query shapes. The store
+//! resolver runs queries of the same shape, e.g. `things(where: { owner:
+//! $owner }, first: 10)` with different owners, over and over; looking
+//! their SQL up by shape is much cheaper than generating it every time,
+//! and lets connections reuse the statements they prepared for it.
+//!
+//! Only queries that do not use windows are cached; see
+//! `FilterQuery::shape`. `GRAPH_SQL_CACHE_SIZE` sets how many shapes to
+//! keep, and setting it to 0 turns the cache off.
+
+use lazy_static::lazy_static;
+use lru_time_cache::LruCache;
+use std::sync::{Arc, Mutex};
+
+use graph::prelude::SubgraphDeploymentId;
+use graph::util::env::env_var;
+
+use crate::relational_queries::FilterQuery;
+
+lazy_static! {
+    static ref SQL_CACHE_SIZE: usize = env_var("GRAPH_SQL_CACHE_SIZE").unwrap_or(1000);
+    static ref SQL: Mutex<LruCache<(SubgraphDeploymentId, String), Arc<String>>> =
+        Mutex::new(LruCache::with_capacity((*SQL_CACHE_SIZE).max(1)));
+}
+
+/// The SQL for `query` against `deployment`, from the cache if a query of
+/// the same shape ran before. Returns `None` if the cache is turned off or
+/// `query` has no shape, in which case the query has to generate its SQL
+/// itself
+pub(crate) fn sql_for(
+    deployment: &SubgraphDeploymentId,
+    query: &FilterQuery,
+) -> Option<Arc<String>> {
+    if *SQL_CACHE_SIZE == 0 {
+        return None;
+    }
+    let key = (deployment.clone(), query.shape()?);
+    if let Some(sql) = SQL.lock().unwrap().get(&key) {
+        return Some(sql.clone());
+    }
+    let sql = Arc::new(query.sql().ok()?);
+    SQL.lock().unwrap().insert(key, sql.clone());
+    Some(sql)
+}
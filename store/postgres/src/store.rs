@@ -1,4 +1,3 @@
-use diesel::connection::SimpleConnection;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
@@ -6,43 +5,55 @@ use diesel::{insert_into, select, update};
 use futures03::FutureExt as _;
 use graph::prelude::{CancelGuard, CancelHandle, CancelToken, CancelableError};
 use graph::spawn_blocking_async_allow_panic;
+use graphql_parser::schema as s;
 use lazy_static::lazy_static;
 use lru_time_cache::LruCache;
 use std::collections::{BTreeMap, HashMap};
 use std::convert::{TryFrom, TryInto};
 use std::iter::FromIterator;
 use std::ops::Deref;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use tokio::sync::Semaphore;
 
 use graph::components::store::{EntityCollection, Store as StoreTrait};
 use graph::components::subgraph::ProofOfIndexingFinisher;
+use graph::data::schema::SchemaReference;
 use graph::data::subgraph::schema::{
-    SubgraphDeploymentEntity, TypedEntity as _, POI_OBJECT, SUBGRAPHS_ID,
+    SubgraphDeploymentAssignmentEntity, SubgraphDeploymentEntity, SubgraphVersionEntity,
+    TypedEntity as _, POI_OBJECT, SUBGRAPHS_ID,
 };
+use graph::log::error_reporting::{self, ErrorReport};
 use graph::prelude::{
     debug, ethabi, format_err, futures03, info, o, serde_json, tiny_keccak, tokio, trace, warn,
-    web3, AttributeIndexDefinition, BigInt, BlockNumber, ChainHeadUpdateListener as _,
-    ChainHeadUpdateStream, ChainStore, CheapClone, DynTryFuture, Entity, EntityKey,
-    EntityModification, EntityOrder, EntityQuery, EntityRange, Error, EthereumBlock,
-    EthereumBlockPointer, EthereumCallCache, EthereumNetworkIdentifier, Future, LightEthereumBlock,
-    Logger, MetadataOperation, MetricsRegistry, QueryExecutionError, Schema, StopwatchMetrics,
-    StoreError, StoreEvent, StoreEventStreamBox, Stream, SubgraphAssignmentProviderError,
-    SubgraphDeploymentId, SubgraphDeploymentStore, SubgraphEntityPair, TransactionAbortError,
-    Value, BLOCK_NUMBER_MAX,
+    web3, AttributeIndexDefinition, BigInt, BlockNumber, CachedEthereumCall,
+    ChainHeadUpdateListener as _, ChainHeadUpdateStream, ChainStore, CheapClone, DeploymentPlacer,
+    DynTryFuture, Entity, EntityGroupQuery, EntityKey, EntityModification, EntityOperation,
+    EntityOrder, EntityQuery, EntityRange, Error, EthereumBlock, EthereumBlockPointer,
+    EthereumCallCache, EthereumNetworkIdentifier, Future, GaugeVec, LightEthereumBlock, Logger,
+    MetadataOperation, MetricsRegistry, NodeId, QueryExecutionError, ReplicaIdentity, Schema,
+    StopwatchMetrics, StoreError, StoreEvent, StoreEventStreamBox, Stream,
+    SubgraphAssignmentProviderError, SubgraphDeploymentId, SubgraphDeploymentStore,
+    SubgraphEntityPair, SyncSample, TransactionAbortError, Value, BLOCK_NUMBER_MAX,
 };
 
 use graph_graphql::prelude::api_schema;
-use web3::types::{Address, H256};
+use web3::types::{Address, H2048, H256};
 
 use crate::chain_head_listener::ChainHeadUpdateListener;
+use crate::command_support;
 use crate::entities as e;
 use crate::functions::{attempt_chain_head_update, lookup_ancestor_block};
+use crate::heartbeat;
 use crate::history_event::HistoryEvent;
 use crate::metadata;
+use crate::migration;
+use crate::planner;
 use crate::relational_queries::FromEntityData;
+use crate::retirement::{self, RetirementPolicy};
 use crate::store_events::SubscriptionManager;
+use crate::sync_samples;
+use crate::vacuum;
 
 // TODO: Integrate with https://github.com/graphprotocol/graph-node/pull/1522/files
 lazy_static! {
@@ -60,78 +71,6 @@ lazy_static! {
     };
 }
 
-embed_migrations!("./migrations");
-
-/// Run all schema migrations.
-///
-/// When multiple `graph-node` processes start up at the same time, we ensure
-/// that they do not run migrations in parallel by using `blocking_conn` to
-/// serialize them. The `conn` is used to run the actual migration.
-fn initiate_schema(logger: &Logger, conn: &PgConnection, blocking_conn: &PgConnection) {
-    // Collect migration logging output
-    let mut output = vec![];
-
-    // Make sure the locking table exists so we have something
-    // to lock. We intentionally ignore errors here, because they are most
-    // likely caused by us losing a race to create the table against another
-    // graph-node. If this truly is an error, we will trip over it when
-    // we try to lock the table and report it to the user
-    if let Err(e) = blocking_conn.batch_execute(
-        "create table if not exists \
-         __graph_node_global_lock(id int)",
-    ) {
-        debug!(
-            logger,
-            "Creating lock table failed, this is most likely harmless";
-            "error" => format!("{:?}", e)
-        );
-    }
-
-    // blocking_conn holds the lock on the migrations table for the duration
-    // of the migration on conn. Since all nodes execute this code, only one
-    // of them can run this code at the same time. We need to use two
-    // connections for this because diesel will run each migration in its
-    // own txn, which makes it impossible to hold a lock across all of them
-    // on that connection
-    info!(
-        logger,
-        "Waiting for other graph-node instances to finish migrating"
-    );
-    let result = blocking_conn.transaction(|| {
-        diesel::sql_query("lock table __graph_node_global_lock in exclusive mode")
-            .execute(blocking_conn)?;
-        info!(logger, "Running migrations");
-        embedded_migrations::run_with_output(conn, &mut output)
-    });
-    info!(logger, "Migrations finished");
-
-    match result {
-        Ok(_) => info!(logger, "Completed pending Postgres schema migrations"),
-        Err(e) => panic!(
-            "Error setting up Postgres database: \
-             You may need to drop and recreate your database to work with the \
-             latest version of graph-node. Error information: {:?}",
-            e
-        ),
-    };
-    // If there was any migration output, log it now
-    if !output.is_empty() {
-        debug!(
-            logger, "Postgres migration output";
-            "output" => String::from_utf8(output)
-                .unwrap_or_else(|_| String::from("<unreadable>"))
-        );
-        // We take getting output as a signal that a migration was actually
-        // run, which is not easy to tell from the Diesel API, and reset the
-        // query statistics since a schema change makes them not all that
-        // useful. An error here is not serious and can be ignored.
-        let res = conn.batch_execute("select pg_stat_statements_reset()");
-        if let Err(e) = res {
-            trace!(logger, "Failed to reset query statistics ({})", e);
-        }
-    }
-}
-
 /// Configuration for the Diesel/Postgres store.
 pub struct StoreConfig {
     pub postgres_url: String,
@@ -166,12 +105,51 @@ pub struct StoreInner {
     /// A cache of commonly needed data about a subgraph.
     subgraph_cache: Mutex<LruCache<SubgraphDeploymentId, SubgraphInfo>>,
 
+    /// API schemas by the text of the input schema they were derived from,
+    /// so that deployments with the same schema, and deployments that
+    /// fall out of `subgraph_cache`, do not have to derive them again
+    api_schema_cache: Mutex<LruCache<String, s::Document>>,
+
     /// A cache for the storage metadata for subgraphs. The Store just
     /// hosts this because it lives long enough, but it is managed from
     /// the entities module
     pub(crate) storage_cache: e::StorageCache,
 
     registry: Arc<dyn MetricsRegistry>,
+
+    /// The fraction of dead tuples in the tables of deployments, as of the
+    /// last time that the table was checked; see `vacuum`
+    dead_tuple_ratio: Box<GaugeVec>,
+}
+
+/// Return the identifiers of all networks that the store has seen so far.
+/// Nodes that do not connect to Ethereum use this to set up their stores
+pub fn network_identifiers(
+    conn: &PgConnection,
+) -> Result<Vec<(String, EthereumNetworkIdentifier)>, Error> {
+    use crate::db_schema::ethereum_networks::dsl::*;
+
+    ethereum_networks
+        .select((name, net_version, genesis_block_hash))
+        .filter(net_version.is_not_null())
+        .filter(genesis_block_hash.is_not_null())
+        .order(name)
+        .load::<(String, Option<String>, Option<String>)>(conn)?
+        .into_iter()
+        .map(|(network, version, hash)| {
+            let hash = hash.unwrap();
+            let genesis = hash.parse().map_err(|_| {
+                format_err!("invalid genesis block hash `{}` for {}", hash, network)
+            })?;
+            Ok((
+                network,
+                EthereumNetworkIdentifier {
+                    net_version: version.unwrap(),
+                    genesis_block_hash: genesis,
+                },
+            ))
+        })
+        .collect()
 }
 
 /// A Store based on Diesel and Postgres.
@@ -200,8 +178,20 @@ impl Store {
         // Create a store-specific logger
         let logger = logger.new(o!("component" => "Store"));
 
-        // Create the entities table (if necessary)
-        initiate_schema(&logger, &pool.get().unwrap(), &pool.get().unwrap());
+        planner::check_server(&logger, &pool.get().unwrap());
+
+        // Create the entities table (if necessary), or wait for another
+        // node to do that
+        migration::prepare_schema(&logger, &pool);
+
+        let dead_tuple_ratio = registry
+            .new_gauge_vec(
+                String::from("deployment_table_dead_tuple_ratio"),
+                String::from("The fraction of dead tuples in the tables of a deployment"),
+                HashMap::new(),
+                vec![String::from("deployment"), String::from("table")],
+            )
+            .expect("failed to create `deployment_table_dead_tuple_ratio` gauge");
 
         // Create the store
         let store = StoreInner {
@@ -212,8 +202,10 @@ impl Store {
             genesis_block_ptr: (net_identifiers.genesis_block_hash, 0 as u64).into(),
             conn: pool,
             subgraph_cache: Mutex::new(LruCache::with_capacity(100)),
+            api_schema_cache: Mutex::new(LruCache::with_capacity(100)),
             storage_cache: e::make_storage_cache(),
             registry,
+            dead_tuple_ratio,
         };
         let store = Store(Arc::new(store));
 
@@ -327,6 +319,7 @@ impl Store {
             query.collection,
             query.filter,
             query.order,
+            query.distinct,
             query.range,
             query.block,
         )
@@ -762,23 +755,20 @@ impl Store {
         conn: &PgConnection,
         subgraph: &SubgraphDeploymentId,
     ) -> Result<Arc<e::Storage>, StoreError> {
-        if let Some(storage) = self.storage_cache.lock().unwrap().get(subgraph) {
+        if let Some(storage) = lock_cache(&self.storage_cache, "storage cache").get(subgraph) {
             return Ok(storage.clone());
         }
 
         let storage = Arc::new(e::Storage::new(conn, subgraph)?);
         if storage.is_cacheable() {
-            &self
-                .storage_cache
-                .lock()
-                .unwrap()
+            lock_cache(&self.storage_cache, "storage cache")
                 .insert(subgraph.clone(), storage.clone());
         }
         Ok(storage.clone())
     }
 
     fn subgraph_info(&self, subgraph_id: &SubgraphDeploymentId) -> Result<SubgraphInfo, Error> {
-        if let Some(info) = self.subgraph_cache.lock().unwrap().get(&subgraph_id) {
+        if let Some(info) = lock_cache(&self.subgraph_cache, "subgraph cache").get(&subgraph_id) {
             return Ok(info.clone());
         }
         trace!(self.logger, "schema cache miss"; "id" => subgraph_id.to_string());
@@ -796,10 +786,12 @@ impl Store {
         let graft_block =
             metadata::deployment_graft(&conn, &subgraph_id)?.map(|(_, ptr)| ptr.number as i32);
 
-        // Generate an API schema for the subgraph and make sure all types in the
-        // API schema have a @subgraphId directive as well
+        // Generate an API schema for the subgraph and the types it imports
+        // from other subgraphs, and make sure all types in the API schema
+        // have a @subgraphId directive as well
+        let imports = self.imported_schemas(&conn, &input_schema);
         let mut schema = input_schema.clone();
-        schema.document = api_schema(&schema.document)?;
+        schema.document = self.derive_api_schema(&input_schema.document_with_imports(&imports))?;
         schema.add_subgraph_id_directives(subgraph_id.clone());
 
         let info = SubgraphInfo {
@@ -810,12 +802,53 @@ impl Store {
         };
 
         // Insert the schema into the cache.
-        let mut cache = self.subgraph_cache.lock().unwrap();
+        let mut cache = lock_cache(&self.subgraph_cache, "subgraph cache");
         cache.insert(subgraph_id.clone(), info);
 
         Ok(cache.get(&subgraph_id).unwrap().clone())
     }
 
+    /// The input schemas of the subgraphs that `schema` imports types from.
+    /// Subgraphs that can not be found are left out, which leaves their
+    /// types out of the API schema. Imports by name are resolved to the
+    /// current version of the subgraph once, when the API schema is built
+    fn imported_schemas(
+        &self,
+        conn: &PgConnection,
+        schema: &Schema,
+    ) -> HashMap<SchemaReference, Arc<Schema>> {
+        schema
+            .imported_schemas()
+            .into_iter()
+            .filter_map(|schema_ref| {
+                let imported = schema_ref
+                    .deployment(self)
+                    .map_err(Error::from)
+                    .and_then(|id| metadata::subgraph_schema(conn, id).map_err(Error::from));
+                match imported {
+                    Ok(imported) => Some((schema_ref, Arc::new(imported))),
+                    Err(e) => {
+                        debug!(self.logger, "Failed to load imported schema";
+                               "subgraph_id" => schema.id.as_str(),
+                               "import" => schema_ref.to_string(),
+                               "error" => e.to_string());
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn derive_api_schema(&self, input: &s::Document) -> Result<s::Document, Error> {
+        let text = input.to_string();
+        if let Some(api) = lock_cache(&self.api_schema_cache, "API schema cache").get(&text) {
+            return Ok(api.clone());
+        }
+        let api = api_schema(input)?;
+        lock_cache(&self.api_schema_cache, "API schema cache").insert(text, api.clone());
+        Ok(api)
+    }
+
     fn block_ptr_with_conn(
         subgraph_id: &SubgraphDeploymentId,
         conn: &e::Connection,
@@ -853,6 +886,170 @@ impl Store {
             )),
         }
     }
+
+    /// Record that `node` is alive
+    pub fn heartbeat(&self, node: &NodeId) -> Result<(), StoreError> {
+        heartbeat::record(&*self.get_conn()?, node)
+    }
+
+    /// Move deployments that are assigned to nodes that have not sent a
+    /// heartbeat within `timeout` to the live node with the fewest
+    /// assignments. If `placer` has a rule for a deployment, the deployment
+    /// is only moved to a node that the rule allows. Only one node at a time
+    /// does this; if another node holds the failover lock, nothing happens.
+    /// Returns the number of deployments that were moved
+    pub fn fail_over(
+        &self,
+        timeout: Duration,
+        placer: Option<&dyn DeploymentPlacer>,
+    ) -> Result<usize, StoreError> {
+        let conn = self.get_conn()?;
+        let storage = self.storage(&conn, &*SUBGRAPHS_ID)?;
+        let econn = e::Connection::new((&conn).into(), storage.clone(), storage);
+
+        let mut moved = 0;
+        let event = econn.transaction(|| -> Result<Option<StoreEvent>, StoreError> {
+            if !heartbeat::try_lock(&conn)? {
+                return Ok(None);
+            }
+            let orphans = heartbeat::orphans(&conn, timeout)?;
+            if orphans.is_empty() {
+                return Ok(None);
+            }
+            let live = heartbeat::live_nodes(&conn, timeout)?;
+            let mut counts = heartbeat::assignment_counts(&conn)?;
+
+            let mut ops = vec![];
+            for orphan in orphans {
+                let allowed = match (placer, &orphan.name, &orphan.network) {
+                    (Some(placer), Some(name), Some(network)) => placer
+                        .place(name, network)
+                        .map_err(|e| format_err!("failed to place `{}`: {}", name, e))?,
+                    _ => None,
+                };
+                let target = live
+                    .iter()
+                    .filter(|node| match &allowed {
+                        Some(allowed) => allowed.contains(node),
+                        None => true,
+                    })
+                    .min_by_key(|node| counts.get(node.as_str()).cloned().unwrap_or(0))
+                    .cloned();
+                let target = match target {
+                    Some(target) => target,
+                    None => {
+                        warn!(
+                            self.logger,
+                            "No live node to move deployment from dead node to";
+                            "deployment" => &orphan.deployment,
+                            "node_id" => &orphan.node_id,
+                        );
+                        continue;
+                    }
+                };
+                info!(
+                    self.logger,
+                    "Moving deployment from dead node";
+                    "deployment" => &orphan.deployment,
+                    "from" => &orphan.node_id,
+                    "to" => target.as_str(),
+                );
+                let id = SubgraphDeploymentId::new(orphan.deployment.clone())
+                    .map_err(|()| format_err!("invalid deployment id `{}`", orphan.deployment))?;
+                *counts.entry(target.to_string()).or_insert(0) += 1;
+                ops.extend(SubgraphDeploymentAssignmentEntity::new(target).write_operations(&id));
+                moved += 1;
+            }
+            if ops.is_empty() {
+                return Ok(None);
+            }
+            self.apply_metadata_operations_with_conn(&econn, ops)
+                .map(Some)
+        })?;
+
+        // Send the event separately, because NOTIFY uses a global DB lock.
+        if let Some(event) = event {
+            econn.transaction(|| econn.send_store_event(&event))?;
+        }
+        Ok(moved)
+    }
+
+    /// Retire the subgraph versions that `policy` does not keep: unassign
+    /// deployments that none of the remaining versions use, and, if the
+    /// policy says so, remove the retired versions and the data of the
+    /// deployments that are unused after that. Only one node at a time
+    /// does this; if another node holds the retirement lock, nothing
+    /// happens. Returns the deployments that were unassigned
+    pub fn retire_versions(&self, policy: &RetirementPolicy) -> Result<Vec<String>, StoreError> {
+        let conn = self.get_conn()?;
+        let storage = self.storage(&conn, &*SUBGRAPHS_ID)?;
+        let econn = e::Connection::new((&conn).into(), storage.clone(), storage);
+
+        let mut unassigned = vec![];
+        let mut retired = vec![];
+        let event = econn.transaction(|| -> Result<Option<StoreEvent>, StoreError> {
+            if !retirement::try_lock(&conn)? {
+                return Ok(None);
+            }
+            let mut ops = vec![];
+            for version in retirement::retired_versions(&conn, policy)? {
+                if version.deployment_retired {
+                    retired.push(version.deployment.clone());
+                    if version.assigned && !unassigned.contains(&version.deployment) {
+                        info!(
+                            self.logger,
+                            "Retiring superseded deployment";
+                            "subgraph" => &version.name,
+                            "deployment" => &version.deployment,
+                        );
+                        ops.push(MetadataOperation::Remove {
+                            entity: SubgraphDeploymentAssignmentEntity::TYPENAME.to_owned(),
+                            id: version.deployment.clone(),
+                        });
+                        unassigned.push(version.deployment.clone());
+                    }
+                }
+                if policy.remove_data {
+                    ops.push(MetadataOperation::Remove {
+                        entity: SubgraphVersionEntity::TYPENAME.to_owned(),
+                        id: version.version,
+                    });
+                }
+            }
+            if ops.is_empty() {
+                return Ok(None);
+            }
+            self.apply_metadata_operations_with_conn(&econn, ops)
+                .map(Some)
+        })?;
+
+        // Send the event separately, because NOTIFY uses a global DB lock.
+        if let Some(event) = event {
+            econn.transaction(|| econn.send_store_event(&event))?;
+        }
+
+        if policy.remove_data && !retired.is_empty() {
+            // Retired deployments might already have been recorded as unused
+            // earlier, and `record_unused` only returns the ones it records
+            // now; all of them need to be removed
+            command_support::record_unused(&conn)?;
+            for unused in command_support::list_unused(&conn, false)? {
+                if !retired.contains(&unused.deployment) {
+                    continue;
+                }
+                let id = SubgraphDeploymentId::new(unused.deployment.clone())
+                    .map_err(|()| format_err!("invalid deployment id `{}`", unused.deployment))?;
+                command_support::remove_unused(&conn, &id)?;
+                info!(
+                    self.logger,
+                    "Removed data of retired deployment";
+                    "deployment" => &unused.deployment,
+                    "namespace" => &unused.namespace,
+                );
+            }
+        }
+        Ok(unassigned)
+    }
 }
 
 impl StoreTrait for Store {
@@ -942,6 +1139,7 @@ impl StoreTrait for Store {
                                 EntityCollection::All(vec![POI_OBJECT.to_owned()]),
                                 None,
                                 EntityOrder::Default,
+                                false,
                                 EntityRange {
                                     first: None,
                                     skip: 0,
@@ -996,7 +1194,9 @@ impl StoreTrait for Store {
         let conn = self
             .get_entity_conn(&key.subgraph_id)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.get_entity(&conn, &key.subgraph_id, &key.entity_type, &key.entity_id)
+        conn.as_reader(|conn| {
+            self.get_entity(conn, &key.subgraph_id, &key.entity_type, &key.entity_id)
+        })
     }
 
     fn get_many(
@@ -1010,14 +1210,14 @@ impl StoreTrait for Store {
         let conn = self
             .get_entity_conn(subgraph_id)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        conn.find_many(ids_for_type, BLOCK_NUMBER_MAX)
+        conn.as_reader(|conn| conn.find_many(ids_for_type, BLOCK_NUMBER_MAX))
     }
 
     fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
         let conn = self
             .get_entity_conn(&query.subgraph_id)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.execute_query(&conn, query)
+        conn.as_reader(|conn| self.execute_query(conn, query))
     }
 
     fn find_query_values(
@@ -1027,7 +1227,7 @@ impl StoreTrait for Store {
         let conn = self
             .get_entity_conn(&query.subgraph_id)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.execute_query(&conn, query)
+        conn.as_reader(|conn| self.execute_query(conn, query))
     }
 
     fn find_one(&self, mut query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
@@ -1037,13 +1237,35 @@ impl StoreTrait for Store {
             .get_entity_conn(&query.subgraph_id)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
 
-        let mut results = self.execute_query(&conn, query)?;
+        let mut results = conn.as_reader(|conn| self.execute_query(conn, query))?;
         match results.len() {
             0 | 1 => Ok(results.pop()),
             n => panic!("find_one query found {} results", n),
         }
     }
 
+    fn find_groups(
+        &self,
+        query: EntityGroupQuery,
+    ) -> Result<Vec<BTreeMap<String, graphql_parser::query::Value>>, QueryExecutionError> {
+        let conn = self
+            .get_entity_conn(&query.subgraph_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        conn.as_reader(|conn| conn.find_groups(&query))
+    }
+
+    fn changes_in_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: &[String],
+        block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        let conn = self
+            .get_entity_conn(subgraph_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        conn.changes_in_block(entity_types, block)
+    }
+
     fn find_ens_name(&self, hash: &str) -> Result<Option<String>, QueryExecutionError> {
         use crate::db_schema::ens_names as dsl;
 
@@ -1084,6 +1306,7 @@ impl StoreTrait for Store {
         }
 
         let econn = self.get_entity_conn(&subgraph_id)?;
+        let writes = vacuum::count_writes(&subgraph_id, &mods);
 
         let (event, metadata_event, should_migrate) =
             econn.transaction(|| -> Result<_, StoreError> {
@@ -1126,6 +1349,8 @@ impl StoreTrait for Store {
             econn.send_store_event(&event)
         })?;
 
+        econn.maintain_tables(&self.logger, writes, &self.dead_tuple_ratio);
+
         Ok(should_migrate)
     }
 
@@ -1152,6 +1377,25 @@ impl StoreTrait for Store {
         econn.transaction(|| self.build_entity_attribute_indexes_with_conn(&econn, indexes))
     }
 
+    fn set_publication(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+        publish: bool,
+        replica_identity: Option<ReplicaIdentity>,
+    ) -> Result<Option<String>, StoreError> {
+        let econn = self.get_entity_conn(subgraph)?;
+        econn.set_publication(publish, replica_identity)
+    }
+
+    fn rewind(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+        block_ptr: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        command_support::rewind(&conn, subgraph, block_ptr)
+    }
+
     fn revert_block_operations(
         &self,
         subgraph_id: SubgraphDeploymentId,
@@ -1306,6 +1550,21 @@ impl StoreTrait for Store {
             })
             .transpose()
     }
+
+    fn record_sync_sample(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_number: u64,
+    ) -> Result<(), StoreError> {
+        sync_samples::record(&*self.get_conn()?, subgraph_id, block_number)
+    }
+
+    fn sync_samples(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<SyncSample>, StoreError> {
+        sync_samples::load(&*self.get_conn()?, subgraph_id)
+    }
 }
 
 impl SubgraphDeploymentStore for Store {
@@ -1325,6 +1584,63 @@ impl SubgraphDeploymentStore for Store {
     fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error> {
         Ok(self.subgraph_info(subgraph_id)?.network)
     }
+
+    fn network_head_block_number(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<u64>, Error> {
+        use crate::db_schema::ethereum_networks::dsl::*;
+
+        let network = match self.network_name(subgraph_id)? {
+            Some(network) => network,
+            None => return Ok(None),
+        };
+        Ok(ethereum_networks
+            .select(head_block_number)
+            .filter(name.eq(&network))
+            .first::<Option<i64>>(&*self.get_conn()?)
+            .optional()?
+            .and_then(|number| number)
+            .map(|number| number as u64))
+    }
+
+    fn block_hash(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        number: BlockNumber,
+    ) -> Result<Option<H256>, Error> {
+        use crate::db_schema::ethereum_blocks::dsl;
+
+        let network = match self.network_name(subgraph_id)? {
+            Some(network) => network,
+            None => return Ok(None),
+        };
+        let ptr = match self.block_ptr(subgraph_id.clone())? {
+            Some(ptr) if ptr.number >= number as u64 => ptr,
+            _ => return Ok(None),
+        };
+
+        let conn = self.get_conn()?;
+        let hashes = dsl::ethereum_blocks
+            .select(dsl::hash)
+            .filter(dsl::network_name.eq(&network))
+            .filter(dsl::number.eq(number as i64))
+            .get_results::<String>(&conn)?;
+        if let [hash] = hashes.as_slice() {
+            return Ok(Some(hash.parse()?));
+        }
+
+        // Blocks that were reorged away may still be in the store; the
+        // block the subgraph processed is an ancestor of its latest block
+        Ok(select(lookup_ancestor_block(
+            ptr.hash_hex(),
+            (ptr.number - number as u64) as i64,
+        ))
+        .first::<Option<serde_json::Value>>(&conn)?
+        .map(|block| serde_json::from_value::<EthereumBlock>(block))
+        .transpose()?
+        .and_then(|block| block.block.hash))
+    }
 }
 
 impl ChainStore for Store {
@@ -1466,6 +1782,18 @@ impl ChainStore for Store {
             .collect()
     }
 
+    fn block_data(&self, block_hash: H256) -> Result<Option<serde_json::Value>, Error> {
+        use crate::db_schema::ethereum_blocks::dsl::*;
+
+        ethereum_blocks
+            .select(data)
+            .filter(network_name.eq(&self.network_name))
+            .filter(hash.eq(format!("{:x}", block_hash)))
+            .first::<serde_json::Value>(&*self.get_conn()?)
+            .optional()
+            .map_err(Error::from)
+    }
+
     fn ancestor_block(
         &self,
         block_ptr: EthereumBlockPointer,
@@ -1572,6 +1900,70 @@ impl ChainStore for Store {
             .execute(&conn)
             .map_err(Error::from)
     }
+
+    fn slowest_synced_block(&self) -> Result<Option<BlockNumber>, Error> {
+        use diesel::sql_types::{Integer, Nullable, Text};
+
+        #[derive(QueryableByName)]
+        struct MinBlock {
+            #[sql_type = "Nullable<Integer>"]
+            block: Option<i32>,
+        };
+
+        // Same as for `cleanup_cached_blocks`, but only for deployments
+        // that follow the chain head
+        let query = "
+            select min(d.latest_ethereum_block_number)::int as block
+              from subgraphs.subgraph_deployment d,
+                   subgraphs.subgraph_deployment_assignment a
+             where a.id = d.id
+               and d.synced
+               and not d.failed
+               and exists (select 1
+                             from subgraphs.ethereum_contract_data_source ds
+                            where left(ds.id, 46) = d.id
+                              and ds.network = $1)";
+        Ok(diesel::sql_query(query)
+            .bind::<Text, _>(&self.network_name)
+            .get_result::<MinBlock>(&*self.get_conn()?)?
+            .block)
+    }
+
+    fn logs_blooms(&self, from: u64, to: u64) -> Result<Vec<(u64, H2048)>, Error> {
+        use diesel::sql_types::{BigInt, Nullable, Text};
+
+        #[derive(QueryableByName)]
+        struct Bloom {
+            #[sql_type = "BigInt"]
+            number: i64,
+            #[sql_type = "Nullable<Text>"]
+            bloom: Option<String>,
+        };
+
+        let query = "
+            select number, min(data -> 'block' ->> 'logsBloom') as bloom
+              from ethereum_blocks
+             where network_name = $1
+               and number between $2 and $3
+             group by number
+            having count(*) = 1
+             order by number";
+        diesel::sql_query(query)
+            .bind::<Text, _>(&self.network_name)
+            .bind::<BigInt, _>(from as i64)
+            .bind::<BigInt, _>(to as i64)
+            .load::<Bloom>(&*self.get_conn()?)?
+            .into_iter()
+            .filter_map(|row| row.bloom.map(|bloom| (row.number as u64, bloom)))
+            .map(|(number, bloom)| {
+                bloom
+                    .trim_start_matches("0x")
+                    .parse()
+                    .map(|bloom| (number, bloom))
+                    .map_err(Error::from)
+            })
+            .collect()
+    }
 }
 
 impl EthereumCallCache for Store {
@@ -1670,6 +2062,37 @@ impl EthereumCallCache for Store {
                 .map_err(Error::from)
         })
     }
+
+    fn get_calls_in_block(
+        &self,
+        block: EthereumBlockPointer,
+    ) -> Result<Vec<CachedEthereumCall>, Error> {
+        use crate::db_schema::eth_call_cache::dsl;
+
+        let calls = dsl::eth_call_cache
+            .select((dsl::id, dsl::contract_address, dsl::return_value))
+            .filter(dsl::block_number.eq(block.number as i32))
+            .order(dsl::id)
+            .load::<(Vec<u8>, Vec<u8>, Vec<u8>)>(&*self.get_conn()?)?;
+        Ok(calls
+            .into_iter()
+            .map(|(id, contract_address, return_value)| CachedEthereumCall {
+                blake3_id: id,
+                block_ptr: block,
+                contract_address: Address::from_slice(&contract_address),
+                return_value,
+            })
+            .collect())
+    }
+}
+
+/// Lock one of the caches of the store. A poisoned lock means that a thread
+/// panicked while it changed the cache, and is reported before panicking
+fn lock_cache<'a, T>(cache: &'a Mutex<T>, what: &str) -> MutexGuard<'a, T> {
+    cache.lock().unwrap_or_else(|e| {
+        error_reporting::report(ErrorReport::store_poisoned(what));
+        panic!("the lock for the {} is poisoned: {}", what, e)
+    })
 }
 
 /// Deprecated format for the contract call id.
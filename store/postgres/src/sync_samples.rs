@@ -0,0 +1,63 @@
+//! Samples of how far deployments had synced at points in time, from which
+//! the index node estimates how long syncing will take
+use diesel::pg::PgConnection;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{sql_query, RunQueryDsl};
+
+use graph::prelude::{StoreError, SubgraphDeploymentId, SyncSample};
+
+/// How long samples are kept
+const RETENTION_HOURS: i64 = 24;
+
+#[derive(QueryableByName)]
+struct Sample {
+    #[sql_type = "BigInt"]
+    timestamp: i64,
+    #[sql_type = "BigInt"]
+    block_number: i64,
+}
+
+/// Record that `deployment` has processed the block `block_number`, and
+/// forget samples of all deployments that are older than `RETENTION_HOURS`
+pub(crate) fn record(
+    conn: &PgConnection,
+    deployment: &SubgraphDeploymentId,
+    block_number: u64,
+) -> Result<(), StoreError> {
+    sql_query(
+        "insert into subgraph_sync_samples(deployment, recorded_at, block_number)
+         values ($1, now(), $2)
+         on conflict(deployment, recorded_at) do nothing",
+    )
+    .bind::<Text, _>(deployment.as_str())
+    .bind::<BigInt, _>(block_number as i64)
+    .execute(conn)?;
+    sql_query(
+        "delete from subgraph_sync_samples
+          where recorded_at < now() - $1 * interval '1 hour'",
+    )
+    .bind::<BigInt, _>(RETENTION_HOURS)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Return the samples of `deployment`, oldest first
+pub(crate) fn load(
+    conn: &PgConnection,
+    deployment: &SubgraphDeploymentId,
+) -> Result<Vec<SyncSample>, StoreError> {
+    Ok(sql_query(
+        "select extract(epoch from recorded_at)::int8 as timestamp, block_number
+           from subgraph_sync_samples
+          where deployment = $1
+          order by recorded_at",
+    )
+    .bind::<Text, _>(deployment.as_str())
+    .load::<Sample>(conn)?
+    .into_iter()
+    .map(|sample| SyncSample {
+        timestamp: sample.timestamp as u64,
+        block_number: sample.block_number as u64,
+    })
+    .collect())
+}
@@ -0,0 +1,236 @@
+//! Keep the statistics and autovacuum settings of the tables of deployments
+//! in line with how much they change. Postgres' default autovacuum settings
+//! are relative to the size of a table, which lets large tables that many
+//! entity versions are written to accumulate lots of dead tuples before
+//! they are vacuumed. We count the writes to each table, and every so often
+//! look at its statistics and set its autovacuum parameters so that it is
+//! vacuumed after about `GRAPH_AUTOVACUUM_DEAD_TUPLES` dead tuples,
+//! regardless of its size.
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{sql_query, Connection, RunQueryDsl};
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use graph::prelude::{
+    debug, info, EntityModification, GaugeVec, Logger, StoreError, SubgraphDeploymentId,
+};
+use graph::util::env::env_var;
+
+use crate::relational::Table;
+
+/// How many writes to a table we wait for before looking at its
+/// statistics again
+const CHECK_INTERVAL: u64 = 10_000;
+
+/// Postgres' default for `autovacuum_vacuum_scale_factor`; we never make
+/// autovacuum less eager than that
+const DEFAULT_SCALE_FACTOR: f64 = 0.2;
+
+lazy_static! {
+    /// How many dead tuples a table should have at most before autovacuum
+    /// cleans it up; 0 leaves the autovacuum settings of tables alone
+    static ref DEAD_TUPLES: u64 = env_var("GRAPH_AUTOVACUUM_DEAD_TUPLES").unwrap_or(100_000);
+
+    /// Run `analyze` on a table after this many writes to it
+    static ref ANALYZE_AFTER_WRITES: Option<u64> = env_var("GRAPH_ANALYZE_AFTER_WRITES");
+
+    static ref CHURN: Mutex<HashMap<String, Churn>> = Mutex::new(HashMap::new());
+}
+
+/// The writes to a table since this node started writing to it
+#[derive(Debug, Default)]
+struct Churn {
+    since_check: u64,
+    since_analyze: u64,
+    /// The `autovacuum_vacuum_scale_factor` we set for the table
+    scale_factor: Option<f64>,
+}
+
+/// The maintenance a table needs after a batch of writes
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Due {
+    /// Look at the statistics of the table and adjust its autovacuum
+    /// parameters
+    pub check: bool,
+    pub analyze: bool,
+}
+
+impl Due {
+    pub fn is_empty(&self) -> bool {
+        !self.check && !self.analyze
+    }
+}
+
+/// The number of entity versions that `mods` write to each entity type of
+/// `subgraph`
+pub(crate) fn count_writes(
+    subgraph: &SubgraphDeploymentId,
+    mods: &[EntityModification],
+) -> BTreeMap<String, u64> {
+    let mut writes = BTreeMap::new();
+    for key in mods.iter().map(|modification| modification.entity_key()) {
+        if &key.subgraph_id == subgraph {
+            *writes.entry(key.entity_type.clone()).or_default() += 1;
+        }
+    }
+    writes
+}
+
+/// Add `writes` to the writes to the table `qualified_name` and return what
+/// maintenance it needs because of that
+pub(crate) fn record_writes(qualified_name: &str, writes: u64) -> Due {
+    let mut churn = CHURN.lock().unwrap();
+    let churn = churn.entry(qualified_name.to_owned()).or_default();
+    churn.since_check += writes;
+    churn.since_analyze += writes;
+
+    let mut due = Due::default();
+    if churn.since_check >= CHECK_INTERVAL {
+        churn.since_check = 0;
+        due.check = true;
+    }
+    if let Some(limit) = *ANALYZE_AFTER_WRITES {
+        if churn.since_analyze >= limit {
+            churn.since_analyze = 0;
+            due.analyze = true;
+        }
+    }
+    due
+}
+
+/// The `autovacuum_vacuum_scale_factor` that makes autovacuum clean up a
+/// table with `live_tuples` after about `dead_tuples` dead tuples
+fn scale_factor(live_tuples: i64, dead_tuples: u64) -> f64 {
+    let factor = dead_tuples as f64 / live_tuples.max(1) as f64;
+    // Round to three significant digits so that small changes in the size
+    // of the table do not change the factor
+    let scale = 10f64.powi(2 - factor.log10().floor() as i32);
+    ((factor * scale).round() / scale).min(DEFAULT_SCALE_FACTOR)
+}
+
+#[derive(QueryableByName)]
+struct TableStats {
+    #[sql_type = "BigInt"]
+    n_live_tup: i64,
+    #[sql_type = "BigInt"]
+    n_dead_tup: i64,
+}
+
+/// Do the maintenance that is `due` for `table` in `schema`, and set the
+/// dead tuple ratio of the table of `subgraph` in `dead_tuple_ratio`.
+/// Since this only helps Postgres, errors are logged and otherwise ignored
+pub(crate) fn maintain(
+    logger: &Logger,
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    schema: &str,
+    table: &Table,
+    due: Due,
+    dead_tuple_ratio: &GaugeVec,
+) {
+    if let Err(e) = try_maintain(
+        logger,
+        conn,
+        subgraph,
+        schema,
+        table,
+        &due,
+        dead_tuple_ratio,
+    ) {
+        debug!(logger, "Failed to maintain table";
+               "table" => table.qualified_name.as_str(),
+               "error" => e.to_string());
+    }
+}
+
+fn try_maintain(
+    logger: &Logger,
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+    schema: &str,
+    table: &Table,
+    due: &Due,
+    dead_tuple_ratio: &GaugeVec,
+) -> Result<(), StoreError> {
+    let qualified_name = table.qualified_name.as_str();
+
+    if due.check {
+        let stats = sql_query(
+            "select n_live_tup, n_dead_tup
+               from pg_stat_user_tables
+              where schemaname = $1 and relname = $2",
+        )
+        .bind::<Text, _>(schema)
+        .bind::<Text, _>(table.name.as_str())
+        .get_results::<TableStats>(conn)?
+        .pop();
+        if let Some(stats) = stats {
+            let total = (stats.n_live_tup + stats.n_dead_tup).max(1);
+            dead_tuple_ratio
+                .with_label_values(&[subgraph.as_str(), table.name.as_str()])
+                .set(stats.n_dead_tup as f64 / total as f64);
+
+            if *DEAD_TUPLES > 0 {
+                let factor = scale_factor(stats.n_live_tup, *DEAD_TUPLES);
+                let previous = CHURN
+                    .lock()
+                    .unwrap()
+                    .get(qualified_name)
+                    .and_then(|churn| churn.scale_factor);
+                if previous != Some(factor) {
+                    // Changing storage parameters needs a lock that
+                    // conflicts with vacuuming the table; we rather try
+                    // again later than wait for a running vacuum
+                    conn.transaction(|| {
+                        conn.batch_execute(&format!(
+                            "set local lock_timeout = '1s';
+                             alter table {} set (autovacuum_vacuum_scale_factor = {},
+                                                 autovacuum_analyze_scale_factor = {})",
+                            qualified_name,
+                            factor,
+                            factor / 2.0
+                        ))
+                    })?;
+                    if let Some(churn) = CHURN.lock().unwrap().get_mut(qualified_name) {
+                        churn.scale_factor = Some(factor);
+                    }
+                    info!(logger, "Adjusted autovacuum for table";
+                          "table" => qualified_name,
+                          "live_tuples" => stats.n_live_tup,
+                          "scale_factor" => factor);
+                }
+            }
+        }
+    }
+
+    if due.analyze {
+        conn.batch_execute(&format!("analyze {}", qualified_name))?;
+        debug!(logger, "Analyzed table"; "table" => qualified_name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_factors() {
+        assert_eq!(DEFAULT_SCALE_FACTOR, scale_factor(0, 100_000));
+        assert_eq!(DEFAULT_SCALE_FACTOR, scale_factor(200_000, 100_000));
+        assert_eq!(0.1, scale_factor(1_000_000, 100_000));
+        assert_eq!(0.000333, scale_factor(300_000_000, 100_000));
+        assert_eq!(0.000333, scale_factor(300_100_000, 100_000));
+    }
+
+    #[test]
+    fn checks_after_many_writes() {
+        let table = "\"sgd_test\".\"thing\"";
+        assert!(record_writes(table, CHECK_INTERVAL - 1).is_empty());
+        assert!(record_writes(table, 1).check);
+        assert!(!record_writes(table, 1).check);
+    }
+}
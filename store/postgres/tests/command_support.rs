@@ -0,0 +1,227 @@
+use diesel::pg::PgConnection;
+use diesel::*;
+use hex_literal::hex;
+use lazy_static::lazy_static;
+use test_store::*;
+
+use graph::components::store::EntityKey;
+use graph::data::subgraph::schema::{SubgraphEntity, SubgraphVersionEntity};
+use graph::prelude::*;
+use graph_mock::MockMetricsRegistry;
+use graph_store_postgres::command_support::{self as cs, PooledPgConnection};
+use graph_store_postgres::connection_pool::create_connection_pool;
+use graph_store_postgres::{RetirementPolicy, Store as DieselStore};
+use web3::types::H256;
+
+const USER_GQL: &str = "
+type User @entity {
+    id: ID!,
+    name: String
+}
+";
+
+const USER: &str = "User";
+
+macro_rules! block_pointer {
+    ($hash:expr, $number:expr) => {{
+        EthereumBlockPointer::from((H256::from(hex!($hash)), $number as u64))
+    }};
+}
+
+lazy_static! {
+    static ref BLOCKS: Vec<EthereumBlockPointer> = vec![
+        block_pointer!(
+            "bd34884280958002c51d3f7b5f853e6febeba33de0f40d15b0363006533c924f",
+            0
+        ),
+        block_pointer!(
+            "8511fa04b64657581e3f00e14543c1d522d5d7e771b54aa3060b662ade47da13",
+            1
+        ),
+        block_pointer!(
+            "b98fb783b49de5652097a989414c767824dff7e7fd765a63b493772511db81c1",
+            2
+        ),
+    ];
+}
+
+/// Test harness for running database integration tests.
+fn run_test<R, F>(test: F)
+where
+    F: FnOnce(Arc<DieselStore>, PooledPgConnection) -> R + Send + 'static,
+    R: IntoFuture<Item = ()> + Send + 'static,
+    R::Error: Send + Debug,
+    R::Future: Send,
+{
+    let store = STORE.clone();
+
+    // Lock regardless of poisoning. This also forces sequential test execution.
+    let mut runtime = match STORE_RUNTIME.lock() {
+        Ok(guard) => guard,
+        Err(err) => err.into_inner(),
+    };
+
+    runtime
+        .block_on(async {
+            // Reset state before starting
+            remove_test_data(store.clone());
+
+            let pool = create_connection_pool(
+                postgres_test_url(),
+                1,
+                &*LOGGER,
+                Arc::new(MockMetricsRegistry::new()),
+            );
+            let conn = pool.get().expect("Failed to get a connection");
+
+            // Run test
+            test(store, conn).into_future().compat().await
+        })
+        .unwrap_or_else(|e| panic!("Failed to run command support test: {:?}", e));
+}
+
+/// Removes test data from the database behind the store.
+fn remove_test_data(store: Arc<DieselStore>) {
+    let url = postgres_test_url();
+    let conn = PgConnection::establish(url.as_str()).expect("Failed to connect to Postgres");
+    graph_store_postgres::store::delete_all_entities_for_test_use_only(&store, &conn)
+        .expect("Failed to remove entity test data");
+}
+
+fn user(subgraph_id: &SubgraphDeploymentId, id: &str, name: &str) -> EntityOperation {
+    let mut data = Entity::new();
+    data.set("id", id);
+    data.set("name", name);
+    EntityOperation::Set {
+        key: user_key(subgraph_id, id),
+        data,
+    }
+}
+
+fn user_key(subgraph_id: &SubgraphDeploymentId, id: &str) -> EntityKey {
+    EntityKey {
+        subgraph_id: subgraph_id.clone(),
+        entity_type: USER.to_owned(),
+        entity_id: id.to_owned(),
+    }
+}
+
+/// Create the deployment `id` with one user at each of the `BLOCKS`
+fn create_users(store: &Arc<DieselStore>, id: &SubgraphDeploymentId) {
+    create_test_subgraph(id.as_str(), USER_GQL);
+    for (i, block) in BLOCKS.iter().enumerate() {
+        let op = user(id, &i.to_string(), &format!("user {}", i));
+        transact_entity_operations(store, id.clone(), *block, vec![op]).unwrap();
+    }
+}
+
+#[test]
+fn record_and_remove_unused() {
+    run_test(|store, conn| -> Result<(), ()> {
+        let id = SubgraphDeploymentId::new("unused").unwrap();
+        create_users(&store, &id);
+
+        let recorded = cs::record_unused(&conn).unwrap();
+        assert_eq!(
+            vec!["unused"],
+            recorded
+                .iter()
+                .map(|unused| unused.deployment.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(3), recorded[0].entity_count);
+        // Deployments are only recorded once
+        assert!(cs::record_unused(&conn).unwrap().is_empty());
+
+        let other = SubgraphDeploymentId::new("notrecorded").unwrap();
+        create_test_subgraph(other.as_str(), USER_GQL);
+        assert!(cs::remove_unused(&conn, &other).is_err());
+
+        cs::remove_unused(&conn, &id).unwrap();
+        assert!(cs::deployments(&conn, id.as_str()).unwrap().is_empty());
+        assert!(cs::list_unused(&conn, false).unwrap().is_empty());
+        let removed = cs::list_unused(&conn, true).unwrap();
+        assert_eq!(1, removed.len());
+        assert!(removed[0].removed_at.is_some());
+
+        // A removed deployment can not be removed again
+        assert!(cs::remove_unused(&conn, &id).is_err());
+        // Only the removed deployment lost its data
+        assert_eq!(1, cs::deployments(&conn, other.as_str()).unwrap().len());
+        Ok(())
+    })
+}
+
+#[test]
+fn rewind() {
+    run_test(|store, conn| -> Result<(), ()> {
+        if !*USING_RELATIONAL_STORAGE {
+            // Rewinding is only supported for relational storage
+            return Ok(());
+        }
+        let id = SubgraphDeploymentId::new("rewind").unwrap();
+        create_users(&store, &id);
+
+        // Assigned deployments can not be rewound
+        let node = NodeId::new("test").unwrap();
+        cs::reassign(&conn, &id, &node).unwrap();
+        assert_eq!(Some(node), cs::assigned_node(&conn, &id).unwrap());
+        assert!(cs::rewind(&conn, &id, BLOCKS[1]).is_err());
+        assert!(cs::unassign(&conn, &id).unwrap());
+
+        cs::rewind(&conn, &id, BLOCKS[1]).unwrap();
+        assert_eq!(Some(BLOCKS[1]), store.block_ptr(id.clone()).unwrap());
+        assert!(store.get(user_key(&id, "1")).unwrap().is_some());
+        assert_eq!(None, store.get(user_key(&id, "2")).unwrap());
+        Ok(())
+    })
+}
+
+#[test]
+fn retire_recorded_deployment() {
+    run_test(|store, conn| -> Result<(), ()> {
+        let old = SubgraphDeploymentId::new("retired").unwrap();
+        let current = SubgraphDeploymentId::new("current").unwrap();
+        create_users(&store, &old);
+        create_users(&store, &current);
+
+        // Both deployments are recorded as unused before any subgraph
+        // version uses them
+        assert_eq!(2, cs::record_unused(&conn).unwrap().len());
+
+        let name = SubgraphName::new("retire").unwrap();
+        let mut ops =
+            SubgraphEntity::new(name, Some("v2".to_owned()), None, 0).write_operations("retire");
+        ops.extend(
+            SubgraphVersionEntity::new("retire".to_owned(), old.clone(), 1, None)
+                .write_operations("v1"),
+        );
+        ops.extend(
+            SubgraphVersionEntity::new("retire".to_owned(), current.clone(), 2, None)
+                .write_operations("v2"),
+        );
+        store.apply_metadata_operations(ops).unwrap();
+
+        let policy = RetirementPolicy {
+            keep_versions: Some(1),
+            remove_data: true,
+            ..RetirementPolicy::default()
+        };
+        store.retire_versions(&policy).unwrap();
+
+        // The data of the retired deployment is removed even though it was
+        // recorded as unused before it was retired
+        assert!(cs::deployments(&conn, old.as_str()).unwrap().is_empty());
+        assert_eq!(1, cs::deployments(&conn, current.as_str()).unwrap().len());
+        // Deployments that were not retired stay recorded, but keep their data
+        assert_eq!(
+            vec!["current"],
+            cs::list_unused(&conn, false)
+                .unwrap()
+                .iter()
+                .map(|unused| unused.deployment.as_str())
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    })
+}
@@ -2,6 +2,7 @@ use diesel::pg::PgConnection;
 use diesel::*;
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::BTreeSet;
 use std::str::FromStr;
 use test_store::*;
 
@@ -127,6 +128,8 @@ fn insert_test_data(store: Arc<DieselStore>) {
         schema: TEST_SUBGRAPH_SCHEMA.clone(),
         data_sources: vec![],
         graft: None,
+        features: BTreeSet::new(),
+        api_version: ApiVersion::default(),
         templates: vec![],
     };
 
@@ -5,14 +5,15 @@ use diesel::prelude::*;
 use futures::future::IntoFuture;
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::str::FromStr;
 
 use graph::data::store::scalar::{BigDecimal, BigInt, Bytes};
 use graph::prelude::{
-    web3::types::H256, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityQuery,
-    EntityRange, Future01CompatExt, Schema, SubgraphDeploymentId, Value, ValueType,
-    BLOCK_NUMBER_MAX,
+    web3::types::H256, AggregateFilter, Entity, EntityAggregate, EntityCollection, EntityFilter,
+    EntityGroupQuery, EntityKey, EntityOrder, EntityQuery, EntityRange, Future01CompatExt,
+    GroupOrder, GroupSortKey, Schema, SubgraphDeploymentId, Value, ValueType, BLOCK_NUMBER_MAX,
 };
 use graph_store_postgres::layout_for_tests::{Layout, STRING_PREFIX_SIZE};
 
@@ -544,6 +545,7 @@ fn count_scalar_entities(conn: &PgConnection, layout: &Layout) -> usize {
             collection,
             Some(filter),
             EntityOrder::Default,
+            false,
             EntityRange {
                 first: None,
                 skip: 0,
@@ -644,6 +646,7 @@ fn test_find(expected_entity_ids: Vec<&str>, query: EntityQuery) {
                 query.collection,
                 query.filter,
                 query.order,
+                query.distinct,
                 query.range,
                 BLOCK_NUMBER_MAX,
             )
@@ -734,6 +737,15 @@ fn find_interface() {
         vec!["garfield", "pluto"],
         query(vec!["Cat", "Dog"]).unordered(),
     );
+
+    // Each implementation only contributes `first + skip` entities
+    test_find(
+        vec!["pluto"],
+        query(vec!["Cat", "Dog"]).asc("name").range(EntityRange {
+            first: Some(1),
+            skip: 1,
+        }),
+    );
 }
 
 #[test]
@@ -1380,6 +1392,7 @@ fn text_find(expected_entity_ids: Vec<&str>, filter: EntityFilter) {
                 query.collection,
                 query.filter,
                 query.order,
+                query.distinct,
                 query.range,
                 BLOCK_NUMBER_MAX,
             )
@@ -1532,3 +1545,64 @@ fn find_empty_and_or() {
         user_query().filter(EntityFilter::Or(vec![EntityFilter::And(vec![])])),
     )
 }
+
+#[test]
+fn find_groups() {
+    use graphql_parser::query as q;
+
+    run_test(|conn, layout| -> Result<(), ()> {
+        insert_users(conn, layout);
+
+        let group = |coffee: Option<bool>, count: i32, age_sum: &str| {
+            let mut group = BTreeMap::new();
+            if let Some(coffee) = coffee {
+                group.insert("coffee".to_owned(), q::Value::Boolean(coffee));
+            }
+            group.insert("count".to_owned(), q::Value::Int(count.into()));
+            group.insert("age_sum".to_owned(), q::Value::String(age_sum.to_owned()));
+            group
+        };
+
+        let query = EntityGroupQuery::new(
+            THINGS_SUBGRAPH_ID.clone(),
+            BLOCK_NUMBER_MAX,
+            "User".to_owned(),
+        )
+        .group_by(vec!["coffee".to_owned()])
+        .aggregates(vec![
+            EntityAggregate::Count,
+            EntityAggregate::Sum("age".to_owned()),
+        ])
+        .having(AggregateFilter::GreaterOrEqual(
+            EntityAggregate::Count,
+            Value::Int(1),
+        ))
+        .order(GroupOrder::Descending(GroupSortKey::Aggregate(
+            EntityAggregate::Count,
+        )));
+        let groups = layout
+            .find_groups(conn, &query)
+            .expect("layout.find_groups failed to execute query");
+        assert_eq!(
+            vec![group(Some(false), 2, "95"), group(Some(true), 1, "43")],
+            groups
+        );
+
+        // Without grouping attributes, all matching entities form one group
+        let query = query
+            .group_by(vec![])
+            .filter(EntityFilter::GreaterThan("age".to_owned(), Value::Int(30)))
+            .order(GroupOrder::Default);
+        let groups = layout
+            .find_groups(conn, &query)
+            .expect("layout.find_groups failed to execute query");
+        assert_eq!(vec![group(None, 2, "110")], groups);
+
+        // Sorting by an attribute that is not grouped by is an error
+        let query = query.order(GroupOrder::Ascending(GroupSortKey::Attribute(
+            "coffee".to_owned(),
+        )));
+        assert!(layout.find_groups(conn, &query).is_err());
+        Ok(())
+    })
+}
@@ -384,6 +384,15 @@ fn make_thing_tree(conn: &PgConnection, layout: &Layout) -> (Entity, Entity, Ent
 #[test]
 fn query() {
     fn fetch(conn: &PgConnection, layout: &Layout, coll: EntityCollection) -> Vec<String> {
+        fetch_range(conn, layout, coll, EntityRange::first(10))
+    }
+
+    fn fetch_range(
+        conn: &PgConnection,
+        layout: &Layout,
+        coll: EntityCollection,
+        range: EntityRange,
+    ) -> Vec<String> {
         layout
             .query::<Entity>(
                 &*LOGGER,
@@ -391,7 +400,8 @@ fn query() {
                 coll,
                 None,
                 EntityOrder::Default,
-                EntityRange::first(10),
+                false,
+                range,
                 BLOCK_NUMBER_MAX,
             )
             .expect("the query succeeds")
@@ -494,6 +504,30 @@ fn query() {
         let things = fetch(conn, layout, coll);
         assert_eq!(vec![ROOT, ROOT], things);
 
+        // EntityCollection::Window, multiple windows; `first` is applied
+        // to the children of each parent
+        //   things { children(first: 1) { id } }
+        let coll = EntityCollection::Window(vec![
+            EntityWindow {
+                child_type: "Thing".to_owned(),
+                ids: vec![ROOT.to_owned()],
+                link: EntityLink::Direct(
+                    WindowAttribute::Scalar("parent".to_string()),
+                    ChildMultiplicity::Many,
+                ),
+            },
+            EntityWindow {
+                child_type: "Thing".to_owned(),
+                ids: vec![CHILD1.to_owned()],
+                link: EntityLink::Direct(
+                    WindowAttribute::Scalar("parent".to_string()),
+                    ChildMultiplicity::Many,
+                ),
+            },
+        ]);
+        let things = fetch_range(conn, layout, coll, EntityRange::first(1));
+        assert_eq!(vec![GRANDCHILD1, CHILD1], things);
+
         Ok(())
     });
 }
@@ -4,7 +4,7 @@ use graph_mock::MockMetricsRegistry;
 use graphql_parser::schema as s;
 use hex_literal::hex;
 use lazy_static::lazy_static;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::str::FromStr;
 use std::time::Duration;
 use test_store::*;
@@ -160,6 +160,8 @@ fn insert_test_data(store: Arc<DieselStore>) {
         schema: TEST_SUBGRAPH_SCHEMA.clone(),
         data_sources: vec![],
         graft: None,
+        features: BTreeSet::new(),
+        api_version: ApiVersion::default(),
         templates: vec![],
     };
 
@@ -1408,6 +1410,7 @@ fn mock_data_source() -> DataSource {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
             abi: String::from("123123"),
             start_block: 0,
+            subgraph: None,
         },
         mapping: Mapping {
             kind: String::from("ethereum/events"),
@@ -1418,6 +1421,7 @@ fn mock_data_source() -> DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            entity_handlers: vec![],
             link: Link {
                 link: "link".to_owned(),
             },
@@ -1439,6 +1443,7 @@ fn mock_data_source() -> DataSource {
                 event_handlers: vec![],
                 call_handlers: vec![],
                 block_handlers: vec![],
+                entity_handlers: vec![],
                 link: Link {
                     link: "link".to_owned(),
                 },
@@ -1601,6 +1606,8 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
             schema: schema.clone(),
             data_sources: vec![],
             graft: None,
+            features: BTreeSet::new(),
+            api_version: ApiVersion::default(),
             templates: vec![],
         };
 
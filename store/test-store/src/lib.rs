@@ -5,7 +5,7 @@ use crate::tokio::runtime::{Builder, Runtime};
 use graph::log;
 use graph::prelude::{Store as _, *};
 use graph_graphql::prelude::{
-    execute_query, Query as PreparedQuery, QueryExecutionOptions, StoreResolver,
+    execute_query, ExecutionHooks, Query as PreparedQuery, QueryExecutionOptions, StoreResolver,
 };
 use graph_mock::MockMetricsRegistry;
 use graph_store_postgres::connection_pool::create_connection_pool;
@@ -13,6 +13,7 @@ use graph_store_postgres::{ChainHeadUpdateListener, Store, StoreConfig, Subscrip
 use graphql_parser::query as q;
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::BTreeSet;
 use std::env;
 use std::sync::Mutex;
 use std::time::Instant;
@@ -120,6 +121,8 @@ fn create_subgraph(
         schema: schema.clone(),
         data_sources: vec![],
         graft: None,
+        features: BTreeSet::new(),
+        api_version: ApiVersion::default(),
         templates: vec![],
     };
 
@@ -402,6 +405,7 @@ fn execute_subgraph_query_internal(
                 resolver,
                 deadline,
                 max_first: std::u32::MAX,
+                hooks: Arc::new(ExecutionHooks::default()),
             },
         ) {
             Err(errs) => errors.extend(errs),